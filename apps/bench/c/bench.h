@@ -0,0 +1,42 @@
+// Helpers shared by the benchmarks.
+//
+// Each benchmark runs ITERS round trips between two processes or threads and
+// prints one line:
+//
+//     BENCH name=<name> iters=<n> ns=<elapsed> ops_per_sec=<rate>
+//
+// or, if the kernel lacks something it needs:
+//
+//     BENCH name=<name> skipped=<reason>
+#ifndef BENCH_H
+#define BENCH_H
+
+#include <errno.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <time.h>
+
+#define ITERS 10000
+
+static inline uint64_t now_ns(void)
+{
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return (uint64_t)ts.tv_sec * 1000000000ull + ts.tv_nsec;
+}
+
+static inline void report(const char *name, uint64_t iters, uint64_t ns)
+{
+    uint64_t rate = ns ? iters * 1000000000ull / ns : 0;
+    printf("BENCH name=%s iters=%llu ns=%llu ops_per_sec=%llu\n", name,
+           (unsigned long long)iters, (unsigned long long)ns, (unsigned long long)rate);
+}
+
+static inline int skip(const char *name, const char *what)
+{
+    printf("BENCH name=%s skipped=%s:%s\n", name, what, strerror(errno));
+    return 0;
+}
+
+#endif
@@ -0,0 +1,54 @@
+// Ping-pong between two threads sleeping on a futex.
+//
+// Futexes are keyed by address space, so the pair is two threads of one
+// process rather than two processes.
+#include <linux/futex.h>
+#include <pthread.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#include "bench.h"
+
+static volatile int turn;
+
+static void futex_wait(volatile int *addr, int val)
+{
+    syscall(SYS_futex, addr, FUTEX_WAIT_PRIVATE, val, NULL, NULL, 0);
+}
+
+static void futex_wake(volatile int *addr)
+{
+    syscall(SYS_futex, addr, FUTEX_WAKE_PRIVATE, 1, NULL, NULL, 0);
+}
+
+static void *pong(void *arg)
+{
+    (void)arg;
+    for (int i = 0; i < ITERS; i++) {
+        while (turn != 2 * i + 1)
+            futex_wait(&turn, 2 * i);
+        turn = 2 * i + 2;
+        futex_wake(&turn);
+    }
+    return NULL;
+}
+
+int main()
+{
+    pthread_t thread;
+
+    if (pthread_create(&thread, NULL, pong, NULL) != 0)
+        return skip("futex", "pthread_create");
+
+    uint64_t start = now_ns();
+    for (int i = 0; i < ITERS; i++) {
+        turn = 2 * i + 1;
+        futex_wake(&turn);
+        while (turn != 2 * i + 2)
+            futex_wait(&turn, 2 * i + 1);
+    }
+    uint64_t ns = now_ns() - start;
+    pthread_join(thread, NULL);
+    report("futex", ITERS, ns);
+    return 0;
+}
@@ -0,0 +1,36 @@
+// Ping-pong a byte between a parent and a child through two pipes.
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "bench.h"
+
+int main()
+{
+    int ping[2], pong[2];
+    char byte = 0;
+
+    if (pipe(ping) < 0 || pipe(pong) < 0)
+        return skip("pipe", "pipe");
+    pid_t pid = fork();
+    if (pid < 0)
+        return skip("pipe", "fork");
+    if (pid == 0) {
+        for (int i = 0; i < ITERS; i++) {
+            if (read(ping[0], &byte, 1) != 1 || write(pong[1], &byte, 1) != 1)
+                _exit(1);
+        }
+        _exit(0);
+    }
+
+    uint64_t start = now_ns();
+    for (int i = 0; i < ITERS; i++) {
+        if (write(ping[1], &byte, 1) != 1 || read(pong[0], &byte, 1) != 1) {
+            printf("BENCH name=pipe failed=round_trip\n");
+            return 1;
+        }
+    }
+    uint64_t ns = now_ns() - start;
+    waitpid(pid, NULL, 0);
+    report("pipe", ITERS, ns);
+    return 0;
+}
@@ -0,0 +1,46 @@
+// Ping-pong a counter between a parent and a child through a SysV shared
+// memory segment, yielding while waiting for the other side.
+#include <sched.h>
+#include <sys/ipc.h>
+#include <sys/shm.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "bench.h"
+
+int main()
+{
+    int id = shmget(IPC_PRIVATE, 4096, IPC_CREAT | 0600);
+    if (id < 0)
+        return skip("shm", "shmget");
+    volatile int *turn = shmat(id, NULL, 0);
+    if (turn == (void *)-1)
+        return skip("shm", "shmat");
+    // The segment goes away once both sides detach
+    shmctl(id, IPC_RMID, NULL);
+    *turn = 0;
+
+    pid_t pid = fork();
+    if (pid < 0)
+        return skip("shm", "fork");
+    if (pid == 0) {
+        for (int i = 0; i < ITERS; i++) {
+            while (*turn != 2 * i + 1)
+                sched_yield();
+            *turn = 2 * i + 2;
+        }
+        _exit(0);
+    }
+
+    uint64_t start = now_ns();
+    for (int i = 0; i < ITERS; i++) {
+        *turn = 2 * i + 1;
+        while (*turn != 2 * i + 2)
+            sched_yield();
+    }
+    uint64_t ns = now_ns() - start;
+    waitpid(pid, NULL, 0);
+    shmdt((void *)turn);
+    report("shm", ITERS, ns);
+    return 0;
+}
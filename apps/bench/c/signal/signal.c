@@ -0,0 +1,48 @@
+// Ping-pong SIGUSR1 between a parent and a child, each waiting for it in
+// sigsuspend with the signal blocked otherwise.
+#include <signal.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "bench.h"
+
+static void on_usr1(int sig)
+{
+    (void)sig;
+}
+
+int main()
+{
+    struct sigaction sa = {.sa_handler = on_usr1};
+    sigset_t block, wait_mask;
+
+    if (sigaction(SIGUSR1, &sa, NULL) < 0)
+        return skip("signal", "sigaction");
+    sigemptyset(&block);
+    sigaddset(&block, SIGUSR1);
+    if (sigprocmask(SIG_BLOCK, &block, &wait_mask) < 0)
+        return skip("signal", "sigprocmask");
+    sigdelset(&wait_mask, SIGUSR1);
+
+    pid_t parent = getpid();
+    pid_t pid = fork();
+    if (pid < 0)
+        return skip("signal", "fork");
+    if (pid == 0) {
+        for (int i = 0; i < ITERS; i++) {
+            sigsuspend(&wait_mask);
+            kill(parent, SIGUSR1);
+        }
+        _exit(0);
+    }
+
+    uint64_t start = now_ns();
+    for (int i = 0; i < ITERS; i++) {
+        kill(pid, SIGUSR1);
+        sigsuspend(&wait_mask);
+    }
+    uint64_t ns = now_ns() - start;
+    waitpid(pid, NULL, 0);
+    report("signal", ITERS, ns);
+    return 0;
+}
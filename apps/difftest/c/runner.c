@@ -0,0 +1,400 @@
+// Run a script of syscalls and print their results, on starry-next or on
+// Linux, so that scripts/difftest.py can compare the two.
+//
+// A script has one syscall per line, `#` starting a comment:
+//
+//     [var =] [~]name arg...
+//
+// where an argument is one of:
+//
+//     42, -1, 0x10, 0644      a number, octal with a leading 0
+//     O_CREAT|O_RDWR          named constants and numbers, or-ed
+//     $var, $var.1            the result of an earlier syscall, or an element
+//                             of the `ints:` argument it was given
+//     "text"                  a pointer to the string, with C escapes
+//     buf:N                   a pointer to N zeroed bytes, printed after the
+//                             call up to the length it returned, or up to
+//                             the first NUL if it returned 0
+//     ints:N                  a pointer to N zeroed ints, printed after the
+//                             call and stored as $var.0 to $var.<N-1>
+//
+// Every syscall prints one line with the number of the script line:
+//
+//     DIFF <script> <line>: <name> = <result> [<buffer>]
+//
+// where the result is the return value, or `-1 <errno name>`. A `~` before
+// the name prints `ok` for any success instead, for values which differ from
+// run to run, like pids or addresses. The last line is `DIFF <script> done`.
+//
+// Built with SCRIPT_PATH and SCRIPT_NAME, the runner embeds that script and
+// runs it without arguments, which is how testcases are started. Otherwise
+// it runs the script given as its argument.
+#include <ctype.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/prctl.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#ifdef SCRIPT_PATH
+__asm__(".section .rodata\n"
+        ".global embedded_script\n"
+        "embedded_script:\n"
+        ".incbin \"" SCRIPT_PATH "\"\n"
+        ".byte 0\n"
+        ".previous\n");
+extern const char embedded_script[];
+#endif
+
+#define MAX_ARGS 6
+#define MAX_VARS 128
+#define MAX_PRINTED 64
+
+struct named {
+    const char *name;
+    long value;
+};
+
+#define SC(name) { #name, SYS_##name }
+static const struct named syscalls[] = {
+    SC(read), SC(write), SC(openat), SC(close), SC(lseek), SC(pipe2),
+    SC(dup), SC(dup3), SC(fcntl), SC(ioctl), SC(getdents64), SC(getcwd),
+    SC(chdir), SC(mkdirat), SC(unlinkat), SC(symlinkat), SC(linkat),
+    SC(renameat2), SC(readlinkat), SC(faccessat), SC(fchmodat), SC(fchownat),
+    SC(fstat), SC(truncate), SC(ftruncate), SC(umask), SC(mmap), SC(munmap),
+    SC(mprotect), SC(madvise), SC(brk), SC(getpid), SC(getppid), SC(gettid),
+    SC(getuid), SC(geteuid), SC(getgid), SC(getegid), SC(setpgid),
+    SC(getpgid), SC(getsid), SC(setsid), SC(kill), SC(tkill),
+    SC(personality), SC(prctl), SC(sched_yield), SC(uname), SC(memfd_create),
+    SC(getrandom),
+};
+
+#define C(name) { #name, name }
+static const struct named constants[] = {
+    C(AT_FDCWD), C(AT_REMOVEDIR), C(AT_SYMLINK_NOFOLLOW), C(O_RDONLY),
+    C(O_WRONLY), C(O_RDWR), C(O_CREAT), C(O_EXCL), C(O_TRUNC), C(O_APPEND),
+    C(O_NONBLOCK), C(O_DIRECTORY), C(O_NOFOLLOW), C(O_CLOEXEC), C(SEEK_SET),
+    C(SEEK_CUR), C(SEEK_END), C(F_DUPFD), C(F_DUPFD_CLOEXEC), C(F_GETFD),
+    C(F_SETFD), C(F_GETFL), C(F_SETFL), C(FD_CLOEXEC), C(F_OK), C(R_OK),
+    C(W_OK), C(X_OK), C(PROT_NONE), C(PROT_READ), C(PROT_WRITE),
+    C(PROT_EXEC), C(MAP_SHARED), C(MAP_PRIVATE), C(MAP_FIXED),
+    C(MAP_ANONYMOUS), C(MADV_DONTNEED), C(PR_SET_NAME), C(PR_GET_NAME),
+    C(PR_SET_PDEATHSIG), C(PR_GET_PDEATHSIG), C(SIGHUP), C(SIGINT),
+    C(SIGKILL), C(SIGUSR1), C(SIGUSR2), C(SIGPIPE), C(SIGTERM), C(SIGCHLD),
+    C(SIGCONT), C(SIGSTOP), { "NULL", 0 },
+};
+
+#define E(name) { #name, name }
+static const struct named errnos[] = {
+    E(EPERM), E(ENOENT), E(ESRCH), E(EINTR), E(EIO), E(ENXIO), E(E2BIG),
+    E(ENOEXEC), E(EBADF), E(ECHILD), E(EAGAIN), E(ENOMEM), E(EACCES),
+    E(EFAULT), E(EBUSY), E(EEXIST), E(EXDEV), E(ENODEV), E(ENOTDIR),
+    E(EISDIR), E(EINVAL), E(ENFILE), E(EMFILE), E(ENOTTY), E(EFBIG),
+    E(ENOSPC), E(ESPIPE), E(EROFS), E(EMLINK), E(EPIPE), E(ERANGE),
+    E(EDEADLK), E(ENAMETOOLONG), E(ENOSYS), E(ENOTEMPTY), E(ELOOP),
+    E(EOVERFLOW), E(EOPNOTSUPP), E(ETIMEDOUT),
+};
+
+static struct {
+    char name[32];
+    long value;
+} vars[MAX_VARS];
+static int var_count;
+
+static const char *script_name;
+static int line_no;
+
+static void fail(const char *what)
+{
+    printf("DIFF %s %d: error %s\n", script_name, line_no, what);
+    exit(1);
+}
+
+static const struct named *lookup(const struct named *table, size_t len, const char *name)
+{
+    for (size_t i = 0; i < len; i++) {
+        if (strcmp(table[i].name, name) == 0)
+            return &table[i];
+    }
+    return NULL;
+}
+
+static void set_var(const char *name, long value)
+{
+    for (int i = 0; i < var_count; i++) {
+        if (strcmp(vars[i].name, name) == 0) {
+            vars[i].value = value;
+            return;
+        }
+    }
+    if (var_count == MAX_VARS || strlen(name) >= sizeof(vars[0].name))
+        fail("too many variables");
+    strcpy(vars[var_count].name, name);
+    vars[var_count++].value = value;
+}
+
+static long get_var(const char *name)
+{
+    for (int i = 0; i < var_count; i++) {
+        if (strcmp(vars[i].name, name) == 0)
+            return vars[i].value;
+    }
+    fail("unknown variable");
+    return 0;
+}
+
+// Parse `A|B|...` into the or of its parts.
+static long parse_int(char *token)
+{
+    long value = 0;
+    for (char *part = strtok(token, "|"); part; part = strtok(NULL, "|")) {
+        const struct named *constant;
+        char *end;
+        if (part[0] == '$') {
+            value |= get_var(part + 1);
+        } else if ((constant = lookup(constants, sizeof(constants) / sizeof(constants[0]), part))) {
+            value |= constant->value;
+        } else {
+            long n = strtol(part, &end, 0);
+            if (*end)
+                fail("bad number");
+            value |= n;
+        }
+    }
+    return value;
+}
+
+// Decode the string literal starting after the quote at `*p`, moving `*p`
+// past its closing quote.
+static char *parse_string(char **p)
+{
+    char *out = malloc(strlen(*p) + 1), *q = out;
+    char *s = *p + 1;
+    for (; *s && *s != '"'; s++) {
+        if (*s != '\\') {
+            *q++ = *s;
+            continue;
+        }
+        switch (*++s) {
+        case 'n': *q++ = '\n'; break;
+        case 't': *q++ = '\t'; break;
+        case '0': *q++ = '\0'; break;
+        case '\0': fail("unterminated string"); break;
+        default: *q++ = *s; break;
+        }
+    }
+    if (*s != '"')
+        fail("unterminated string");
+    *q = '\0';
+    *p = s + 1;
+    return out;
+}
+
+static void print_bytes(const unsigned char *buf, size_t len)
+{
+    printf(" \"");
+    for (size_t i = 0; i < len && i < MAX_PRINTED; i++) {
+        unsigned char c = buf[i];
+        if (c == '"' || c == '\\')
+            printf("\\%c", c);
+        else if (c == '\n')
+            printf("\\n");
+        else if (c == '\t')
+            printf("\\t");
+        else if (isprint(c))
+            putchar(c);
+        else
+            printf("\\x%02x", c);
+    }
+    printf(len > MAX_PRINTED ? "\"..." : "\"");
+}
+
+static void run_line(char *line)
+{
+    char *var = NULL, *name, *p;
+    long args[MAX_ARGS] = { 0 };
+    // The output arguments, printed after the result
+    unsigned char *buf = NULL;
+    long buf_len = 0;
+    int *ints = NULL;
+    long ints_len = 0;
+    int nargs = 0, hide = 0;
+
+    // Cut the comment, if the `#` is outside of a string
+    int quoted = 0;
+    for (p = line; *p; p++) {
+        if (*p == '\\' && quoted && p[1])
+            p++;
+        else if (*p == '"')
+            quoted = !quoted;
+        else if (*p == '#' && !quoted) {
+            *p = '\0';
+            break;
+        }
+    }
+    p = line;
+    while (isspace((unsigned char)*p))
+        p++;
+    if (!*p)
+        return;
+
+    name = p;
+    while (*p && !isspace((unsigned char)*p))
+        p++;
+    if (*p)
+        *p++ = '\0';
+    while (isspace((unsigned char)*p))
+        p++;
+    if (*p == '=') {
+        var = name;
+        p++;
+        while (isspace((unsigned char)*p))
+            p++;
+        name = p;
+        while (*p && !isspace((unsigned char)*p))
+            p++;
+        if (*p)
+            *p++ = '\0';
+    }
+    if (*name == '~') {
+        hide = 1;
+        name++;
+    }
+    const struct named *sc = lookup(syscalls, sizeof(syscalls) / sizeof(syscalls[0]), name);
+    if (!sc)
+        fail("unknown syscall");
+
+    for (;;) {
+        while (isspace((unsigned char)*p))
+            p++;
+        if (!*p)
+            break;
+        if (nargs == MAX_ARGS)
+            fail("too many arguments");
+        if (*p == '"') {
+            args[nargs++] = (long)parse_string(&p);
+            continue;
+        }
+        char *token = p;
+        while (*p && !isspace((unsigned char)*p))
+            p++;
+        if (*p)
+            *p++ = '\0';
+        if (strncmp(token, "buf:", 4) == 0) {
+            buf_len = parse_int(token + 4);
+            buf = calloc(buf_len + 1, 1);
+            args[nargs++] = (long)buf;
+        } else if (strncmp(token, "ints:", 5) == 0) {
+            ints_len = parse_int(token + 5);
+            ints = calloc(ints_len + 1, sizeof(int));
+            args[nargs++] = (long)ints;
+        } else {
+            args[nargs++] = parse_int(token);
+        }
+    }
+
+    errno = 0;
+    long ret = syscall(sc->value, args[0], args[1], args[2], args[3], args[4], args[5]);
+    int err = errno;
+
+    printf("DIFF %s %d: %s = ", script_name, line_no, name);
+    if (ret == -1 && err) {
+        const struct named *e = NULL;
+        for (size_t i = 0; i < sizeof(errnos) / sizeof(errnos[0]); i++) {
+            if (errnos[i].value == err)
+                e = &errnos[i];
+        }
+        if (e)
+            printf("-1 %s", e->name);
+        else
+            printf("-1 errno%d", err);
+    } else if (hide) {
+        printf("ok");
+    } else {
+        printf("%ld", ret);
+        // The length returned, or the string filled in by those returning 0
+        if (buf && ret > 0)
+            print_bytes(buf, ret < buf_len ? ret : buf_len);
+        else if (buf && ret == 0 && buf[0])
+            print_bytes(buf, strnlen((char *)buf, buf_len));
+        if (ints) {
+            printf(" [");
+            for (long i = 0; i < ints_len; i++)
+                printf(i ? ", %d" : "%d", ints[i]);
+            printf("]");
+        }
+    }
+    printf("\n");
+    fflush(stdout);
+
+    if (var) {
+        char elem[64];
+        set_var(var, ret);
+        for (long i = 0; i < ints_len; i++) {
+            snprintf(elem, sizeof(elem), "%s.%ld", var, i);
+            set_var(elem, ints[i]);
+        }
+    }
+}
+
+#ifndef SCRIPT_PATH
+static char *read_file(const char *path)
+{
+    FILE *f = fopen(path, "r");
+    if (!f) {
+        perror(path);
+        exit(1);
+    }
+    size_t cap = 4096, len = 0, n;
+    char *data = malloc(cap);
+    while ((n = fread(data + len, 1, cap - len - 1, f)) > 0) {
+        len += n;
+        if (len + 1 == cap)
+            data = realloc(data, cap *= 2);
+    }
+    fclose(f);
+    data[len] = '\0';
+    return data;
+}
+#endif
+
+int main(int argc, char **argv)
+{
+    char *script;
+#ifdef SCRIPT_PATH
+    script = strdup(embedded_script);
+    script_name = SCRIPT_NAME;
+    (void)argc;
+    (void)argv;
+#else
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <script>\n", argv[0]);
+        return 1;
+    }
+    script = read_file(argv[1]);
+    // The name of the script, without its directory and extension
+    char *base = strrchr(argv[1], '/');
+    base = strdup(base ? base + 1 : argv[1]);
+    char *ext = strrchr(base, '.');
+    if (ext)
+        *ext = '\0';
+    script_name = base;
+#endif
+
+    char *line = script;
+    while (line && *line) {
+        char *next = strchr(line, '\n');
+        if (next)
+            *next++ = '\0';
+        line_no++;
+        run_line(line);
+        line = next;
+    }
+    printf("DIFF %s done\n", script_name);
+    return 0;
+}
@@ -0,0 +1,143 @@
+#define _GNU_SOURCE
+#include <dirent.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/statfs.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#define TEST_NAME "dirents"
+#include "test.h"
+
+#define DIR_PATH "/tmp/dirents"
+#define FILES 40
+
+#define TMPFS_MAGIC 0x01021994
+#define PROC_SUPER_MAGIC 0x9fa0
+#define PIPEFS_MAGIC 0x50495045
+
+struct dirent64_head {
+    unsigned long long d_ino;
+    long long d_off;
+    unsigned short d_reclen;
+    unsigned char d_type;
+    char d_name[];
+};
+
+static long getdents(int fd, void *buf, size_t len)
+{
+    return syscall(SYS_getdents64, fd, buf, len);
+}
+
+/* Count the files of the directory, reading it with a buffer of `len` bytes,
+ * and keep the offset after the first one in `first_off` */
+static int read_all(int fd, size_t len, int seen[FILES], long long *first_off)
+{
+    char buf[512];
+    long n;
+    int index;
+
+    *first_off = -1;
+    while ((n = getdents(fd, buf, len)) > 0) {
+        for (long pos = 0; pos < n;) {
+            struct dirent64_head *d = (struct dirent64_head *)(buf + pos);
+
+            if (d->d_reclen == 0 || d->d_reclen % 8 != 0)
+                return fail("an entry has the length %u", d->d_reclen);
+            if (sscanf(d->d_name, "f%d", &index) == 1 && index >= 0 && index < FILES) {
+                if (d->d_type != DT_REG || d->d_ino == 0)
+                    return fail("%s has type %u and inode %llu", d->d_name, d->d_type, d->d_ino);
+                seen[index]++;
+                if (*first_off < 0)
+                    *first_off = d->d_off;
+            }
+            pos += d->d_reclen;
+        }
+    }
+    if (n < 0)
+        return fail("getdents64 failed: %s", strerror(errno));
+    return 0;
+}
+
+/* A listing goes on where the previous call stopped, whatever the buffer */
+static int check_getdents(void)
+{
+    int seen[FILES] = {0}, again[FILES] = {0};
+    long long first_off, unused;
+    char buf[16];
+    int fd, total = 0;
+
+    mkdir(DIR_PATH, 0755);
+    for (int i = 0; i < FILES; i++) {
+        char path[64];
+
+        snprintf(path, sizeof(path), DIR_PATH "/f%d", i);
+        close(open(path, O_WRONLY | O_CREAT, 0644));
+    }
+    fd = open(DIR_PATH, O_RDONLY | O_DIRECTORY);
+    if (fd < 0)
+        return fail("cannot open " DIR_PATH);
+    if (getdents(fd, buf, sizeof(buf)) == 0 || errno != EINVAL)
+        return fail("getdents64 took a buffer too small for an entry");
+
+    /* Room for about two entries at a time */
+    if (read_all(fd, 64, seen, &first_off))
+        return 1;
+    for (int i = 0; i < FILES; i++) {
+        if (seen[i] != 1)
+            return fail("f%d was listed %d times", i, seen[i]);
+    }
+    if (getdents(fd, buf, sizeof(buf)) != 0)
+        return fail("getdents64 at the end of the directory did not return 0");
+
+    /* Seeking back to the offset of an entry lists what follows it */
+    if (lseek(fd, first_off, SEEK_SET) < 0)
+        return fail("cannot seek to the offset of an entry");
+    if (read_all(fd, 512, again, &unused))
+        return 1;
+    for (int i = 0; i < FILES; i++)
+        total += again[i];
+    if (total != FILES - 1)
+        return fail("seeking to an entry lists %d files instead of the %d after it", total,
+                    FILES - 1);
+    close(fd);
+    return 0;
+}
+
+static int check_statfs(void)
+{
+    struct statfs st;
+    int fds[2];
+
+    if (statfs(DIR_PATH, &st) < 0)
+        return fail("statfs failed: %s", strerror(errno));
+    if (st.f_type != TMPFS_MAGIC || st.f_bsize == 0 || st.f_bfree > st.f_blocks)
+        return fail("/tmp is of type %#lx, with %lu free blocks of %lu", (unsigned long)st.f_type,
+                    (unsigned long)st.f_bfree, (unsigned long)st.f_blocks);
+    if (statfs("/proc/self", &st) < 0 || st.f_type != PROC_SUPER_MAGIC)
+        return fail("/proc is not of the type of procfs");
+    if (pipe(fds) < 0 || fstatfs(fds[0], &st) < 0 || st.f_type != PIPEFS_MAGIC)
+        return fail("a pipe is not of the type of pipefs");
+    close(fds[0]);
+    close(fds[1]);
+    if (statfs(DIR_PATH "/missing", &st) == 0 || errno != ENOENT)
+        return fail("statfs found a missing file");
+    return 0;
+}
+
+int main(void)
+{
+    if (check_getdents() || check_statfs())
+        return 1;
+    for (int i = 0; i < FILES; i++) {
+        char path[64];
+
+        snprintf(path, sizeof(path), DIR_PATH "/f%d", i);
+        unlink(path);
+    }
+    rmdir(DIR_PATH);
+    return pass();
+}
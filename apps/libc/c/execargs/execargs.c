@@ -0,0 +1,66 @@
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/prctl.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "execargs"
+#include "test.h"
+
+/* Read `path` into `buf`, returning its length */
+static ssize_t read_file(const char *path, char *buf, size_t size)
+{
+    ssize_t len;
+    int fd = open(path, O_RDONLY);
+
+    if (fd < 0)
+        return -1;
+    len = read(fd, buf, size);
+    close(fd);
+    return len;
+}
+
+static int child(void)
+{
+    static const char cmdline[] = "execargs\0child\0two words";
+    char buf[256], name[16] = {0};
+    ssize_t len;
+
+    /* The arguments and environment given to execve */
+    len = read_file("/proc/self/cmdline", buf, sizeof(buf));
+    if (len != sizeof(cmdline) || memcmp(buf, cmdline, len) != 0)
+        return fail("/proc/self/cmdline does not hold the arguments");
+    len = read_file("/proc/self/environ", buf, sizeof(buf));
+    if (len <= 0 || !memmem(buf, len, "EXECARGS=yes", sizeof("EXECARGS=yes")))
+        return fail("/proc/self/environ does not hold the environment");
+
+    /* The name is that of the file run, not argv[0] */
+    if (prctl(PR_GET_NAME, name) < 0 || strcmp(name, "execargs_c") != 0)
+        return fail("the name is not that of the file run");
+    return 0;
+}
+
+int main(int argc, char **argv)
+{
+    char *args[] = {"execargs", "child", "two words", NULL};
+    char *envp[] = {"EXECARGS=yes", NULL};
+    int status;
+    pid_t pid;
+
+    if (argc > 1 && strcmp(argv[1], "child") == 0)
+        return child();
+
+    pid = fork();
+    if (pid == 0) {
+        execve("/proc/self/exe", args, envp);
+        _exit(fail("execve of /proc/self/exe failed"));
+    }
+    if (pid < 0 || waitpid(pid, &status, 0) != pid)
+        return fail("fork failed");
+    if (!WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return 1;
+
+    return pass();
+}
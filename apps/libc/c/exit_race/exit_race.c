@@ -0,0 +1,128 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <pthread.h>
+#include <signal.h>
+#include <stdatomic.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "exit_race"
+#include "test.h"
+
+#define ROUNDS 32
+#define THREADS 8
+
+static atomic_int caught;
+static atomic_int stop;
+static pthread_mutex_t robust;
+
+static void handler(int sig)
+{
+    (void)sig;
+    atomic_fetch_add(&caught, 1);
+}
+
+static void *short_lived(void *arg)
+{
+    (void)arg;
+    return NULL;
+}
+
+/* Keep signalling the process while its threads come and go */
+static void *sender(void *arg)
+{
+    (void)arg;
+    while (!atomic_load(&stop))
+        kill(getpid(), SIGUSR1);
+    return NULL;
+}
+
+/* Exit with the robust mutex held, without the cleanup of pthread_exit */
+static void *die_holding(void *arg)
+{
+    (void)arg;
+    pthread_mutex_lock(&robust);
+    syscall(SYS_exit, 0);
+    return NULL;
+}
+
+/* Threads exit while signals keep coming, and a signal to an exiting thread
+ * goes to another one or is dropped */
+static int check_threads(void)
+{
+    pthread_t send, threads[THREADS];
+
+    if (pthread_create(&send, NULL, sender, NULL) != 0)
+        return fail("pthread_create failed");
+    for (int round = 0; round < ROUNDS; round++) {
+        for (int i = 0; i < THREADS; i++) {
+            if (pthread_create(&threads[i], NULL, short_lived, NULL) != 0)
+                return fail("pthread_create failed");
+        }
+        for (int i = 0; i < THREADS; i++)
+            pthread_join(threads[i], NULL);
+    }
+    atomic_store(&stop, 1);
+    pthread_join(send, NULL);
+    if (atomic_load(&caught) == 0)
+        return fail("no signal caught");
+    return 0;
+}
+
+/* A child signalled while it exits still reports its own exit status */
+static int check_children(void)
+{
+    pid_t pid, reaped;
+    int status;
+
+    for (int round = 0; round < ROUNDS; round++) {
+        pid = fork();
+        if (pid < 0)
+            return fail("fork failed");
+        if (pid == 0)
+            _exit(7);
+        do {
+            kill(pid, SIGUSR1);
+            reaped = waitpid(pid, &status, WNOHANG);
+        } while (reaped == 0);
+        if (reaped != pid)
+            return fail("waitpid failed");
+        if (!WIFEXITED(status) || WEXITSTATUS(status) != 7)
+            return fail("child status %#x instead of exiting with 7", status);
+    }
+    return 0;
+}
+
+/* The robust list is walked before a joiner is woken up */
+static int check_robust(void)
+{
+    pthread_mutexattr_t attr;
+    pthread_t thread;
+    int ret;
+
+    pthread_mutexattr_init(&attr);
+    pthread_mutexattr_setrobust(&attr, PTHREAD_MUTEX_ROBUST);
+    pthread_mutex_init(&robust, &attr);
+    if (pthread_create(&thread, NULL, die_holding, NULL) != 0)
+        return fail("pthread_create failed");
+    pthread_join(thread, NULL);
+    ret = pthread_mutex_trylock(&robust);
+    if (ret != EOWNERDEAD)
+        return fail("trylock after the owner exited returned %d instead of EOWNERDEAD", ret);
+    pthread_mutex_consistent(&robust);
+    pthread_mutex_unlock(&robust);
+    return 0;
+}
+
+int main(void)
+{
+    struct sigaction sa = { .sa_handler = handler };
+
+    if (sigaction(SIGUSR1, &sa, NULL) < 0)
+        return fail("sigaction failed");
+    if (check_threads() || check_children() || check_robust())
+        return 1;
+    return pass();
+}
@@ -0,0 +1,80 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "exit_teardown"
+#include "test.h"
+
+#define ROUNDS 64
+#define CHILD_PAGES 64
+/* Some slack for the kernel heap, which keeps the pages it grew into */
+#define SLACK_KB 256
+
+static long mem_free_kb(void)
+{
+    char line[128];
+    long kb = -1;
+    FILE *f = fopen("/proc/meminfo", "r");
+    if (!f)
+        return -1;
+    while (fgets(line, sizeof(line), f)) {
+        if (sscanf(line, "MemFree: %ld kB", &kb) == 1)
+            break;
+    }
+    fclose(f);
+    return kb;
+}
+
+/* Leave things behind for the exit to clean up: touched memory and a pipe */
+static int child(void)
+{
+    int fds[2];
+    size_t len = CHILD_PAGES * 4096;
+    char *p = mmap(NULL, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED || pipe(fds) < 0)
+        return 1;
+    memset(p, 0x5a, len);
+    write(fds[1], p, 16);
+    return 0;
+}
+
+static int round_trip(const char *self)
+{
+    int status;
+    pid_t pid = fork();
+    if (pid < 0)
+        return -1;
+    if (pid == 0) {
+        execl(self, self, "child", (char *)NULL);
+        _exit(127);
+    }
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return -1;
+    return 0;
+}
+
+int main(int argc, char **argv)
+{
+    long before, after;
+
+    if (argc > 1 && strcmp(argv[1], "child") == 0)
+        return child();
+
+    /* The first round allocates what the kernel keeps for good */
+    if (round_trip(argv[0]) < 0)
+        return fail("fork/exec failed");
+    before = mem_free_kb();
+    for (int i = 0; i < ROUNDS; i++) {
+        if (round_trip(argv[0]) < 0)
+            return fail("round %d failed", i);
+    }
+    after = mem_free_kb();
+    if (before < 0 || after < 0)
+        return fail("no MemFree in /proc/meminfo");
+    if (after + SLACK_KB < before)
+        return fail("leaked %ld kB in %d rounds", before - after, ROUNDS);
+    return pass();
+}
@@ -0,0 +1,135 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/uio.h>
+#include <unistd.h>
+
+#define TEST_NAME "fileio"
+#include "test.h"
+
+#ifndef RENAME_NOREPLACE
+#define RENAME_NOREPLACE (1 << 0)
+#endif
+
+#define DIR_PATH "/tmp/fileio"
+#define FILE_A DIR_PATH "/a"
+#define FILE_B DIR_PATH "/b"
+
+static long file_size(const char *path)
+{
+    struct stat st;
+
+    return stat(path, &st) < 0 ? -1 : st.st_size;
+}
+
+/* Whole reads and writes over several buffers, at the file offset or not */
+static int check_vectored(int fd)
+{
+    char one[4] = {0}, two[8] = {0};
+    struct iovec out[] = {{"head", 4}, {"", 0}, {"-tail", 5}};
+    struct iovec in[] = {{one, 3}, {two, 6}};
+
+    if (writev(fd, out, 3) != 9)
+        return fail("writev did not write all the buffers");
+    if (lseek(fd, 0, SEEK_CUR) != 9)
+        return fail("writev did not move the file offset");
+    if (preadv(fd, in, 2, 0) != 9 || strcmp(one, "hea") != 0 || strcmp(two, "d-tail") != 0)
+        return fail("preadv read \"%s\" and \"%s\"", one, two);
+    if (lseek(fd, 0, SEEK_CUR) != 9)
+        return fail("preadv moved the file offset");
+
+    /* pwritev past the end leaves a hole of zeroes */
+    out[0].iov_base = "XY";
+    out[0].iov_len = 2;
+    if (pwritev(fd, out, 1, 12) != 2 || file_size(FILE_A) != 14)
+        return fail("pwritev did not extend the file");
+    memset(two, 0, sizeof(two));
+    lseek(fd, 7, SEEK_SET);
+    in[0].iov_len = 2;
+    in[1].iov_len = 5;
+    if (readv(fd, in, 2) != 7 || memcmp(one, "il", 2) != 0 || memcmp(two, "\0\0\0XY", 5) != 0)
+        return fail("readv did not read the hole and the data past it");
+    if (readv(fd, in, 2) != 0)
+        return fail("readv at the end of the file did not return 0");
+
+    if (pwritev(fd, out, 1, -1) == 0 || errno != EINVAL)
+        return fail("pwritev took a negative offset");
+    return 0;
+}
+
+static int check_truncate(int fd)
+{
+    int rdonly;
+
+    if (ftruncate(fd, 4) < 0 || file_size(FILE_A) != 4)
+        return fail("ftruncate did not shrink the file");
+    if (truncate(FILE_A, 4096) < 0 || file_size(FILE_A) != 4096)
+        return fail("truncate did not grow the file");
+    if (truncate(FILE_A, -1) == 0 || errno != EINVAL)
+        return fail("truncate took a negative length");
+    if (truncate(DIR_PATH, 0) == 0 || errno != EISDIR)
+        return fail("truncate took a directory");
+    rdonly = open(FILE_A, O_RDONLY);
+    if (ftruncate(rdonly, 0) == 0 || errno != EINVAL)
+        return fail("ftruncate took a file not open for writing");
+    close(rdonly);
+
+    if (fsync(fd) < 0 || fdatasync(fd) < 0 || syncfs(fd) < 0)
+        return fail("syncing the file failed: %s", strerror(errno));
+    if (fsync(-1) == 0 || errno != EBADF)
+        return fail("fsync took a closed fd");
+    return 0;
+}
+
+static int rename2(const char *from, const char *to, unsigned int flags)
+{
+    return syscall(SYS_renameat2, AT_FDCWD, from, AT_FDCWD, to, flags);
+}
+
+static int check_rename(void)
+{
+    struct stat a, b;
+
+    close(open(FILE_B, O_WRONLY | O_CREAT, 0644));
+    if (stat(FILE_A, &a) < 0)
+        return fail("cannot stat " FILE_A);
+    if (rename2(FILE_A, FILE_B, RENAME_NOREPLACE) == 0 || errno != EEXIST)
+        return fail("RENAME_NOREPLACE replaced the target");
+    if (rename2(FILE_A, FILE_B, 1 << 8) == 0 || errno != EINVAL)
+        return fail("renameat2 took an unknown flag");
+
+    /* The file keeps its inode, the target is gone */
+    if (rename(FILE_A, FILE_B) < 0)
+        return fail("rename failed: %s", strerror(errno));
+    if (stat(FILE_B, &b) < 0 || b.st_ino != a.st_ino || b.st_size != 4096)
+        return fail("the renamed file is not the source");
+    if (access(FILE_A, F_OK) == 0)
+        return fail("the source is still there after rename");
+    if (rename2(FILE_B, FILE_A, RENAME_NOREPLACE) < 0 || access(FILE_A, F_OK) < 0)
+        return fail("RENAME_NOREPLACE failed on a free target");
+    if (rename(DIR_PATH, DIR_PATH "/sub") == 0 || errno != EINVAL)
+        return fail("a directory was moved below itself");
+    return 0;
+}
+
+int main(void)
+{
+    int fd;
+
+    mkdir(DIR_PATH, 0755);
+    fd = open(FILE_A, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    if (fd < 0)
+        return fail("cannot create " FILE_A);
+    if (check_vectored(fd) || check_truncate(fd))
+        return 1;
+    close(fd);
+    if (check_rename())
+        return 1;
+    unlink(FILE_A);
+    rmdir(DIR_PATH);
+    return pass();
+}
@@ -0,0 +1,135 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <linux/futex.h>
+#include <pthread.h>
+#include <sched.h>
+#include <stdatomic.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <time.h>
+#include <unistd.h>
+
+#define TEST_NAME "futex"
+#include "test.h"
+
+#ifndef SYS_futex_waitv
+#define SYS_futex_waitv 449
+#endif
+
+#define SIZE_U8 0x00
+#define SIZE_U32 0x02
+#define SIZE_U64 0x03
+
+/* struct futex_waitv, which older headers lack */
+struct waitv {
+    uint64_t val;
+    uint64_t uaddr;
+    uint32_t flags;
+    uint32_t reserved;
+};
+
+static uint32_t word32;
+static uint64_t word64 __attribute__((aligned(8)));
+static atomic_int waiting, done;
+static pid_t waiter_tid;
+
+static long futex_waitv(struct waitv *waiters, unsigned int count, const struct timespec *timeout)
+{
+    return syscall(SYS_futex_waitv, waiters, count, 0, timeout, CLOCK_MONOTONIC);
+}
+
+static long futex_wake(void *uaddr, int count)
+{
+    return syscall(SYS_futex, uaddr, FUTEX_WAKE, count, NULL, NULL, 0);
+}
+
+/* Wait on both words, returning the index of the one woken up */
+static void *waiter(void *arg)
+{
+    struct waitv waiters[] = {
+        {.val = 0, .uaddr = (uintptr_t)&word32, .flags = SIZE_U32},
+        {.val = 0, .uaddr = (uintptr_t)&word64, .flags = SIZE_U64},
+    };
+    long ret;
+
+    (void)arg;
+    waiter_tid = syscall(SYS_gettid);
+    atomic_store(&waiting, 1);
+    do
+        ret = futex_waitv(waiters, 2, NULL);
+    while (ret < 0 && errno == EINTR);
+    atomic_store(&done, 1);
+    return (void *)ret;
+}
+
+static int check_invalid(void)
+{
+    struct waitv w = {.val = 0, .uaddr = (uintptr_t)&word32, .flags = SIZE_U32};
+    struct timespec now;
+
+    /* The words are checked before sleeping */
+    w.val = 1;
+    if (futex_waitv(&w, 1, NULL) == 0 || errno != EAGAIN)
+        return fail("futex_waitv slept on a word without the expected value");
+    w.val = 0;
+
+    /* Only 32-bit and 64-bit words, aligned to their size */
+    w.flags = SIZE_U8;
+    if (futex_waitv(&w, 1, NULL) == 0 || errno != EINVAL)
+        return fail("futex_waitv took an 8-bit futex");
+    w.flags = SIZE_U64;
+    w.uaddr = (uintptr_t)&word64 + 4;
+    if (futex_waitv(&w, 1, NULL) == 0 || errno != EINVAL)
+        return fail("futex_waitv took a misaligned 64-bit futex");
+    w.uaddr = (uintptr_t)&word32;
+    w.flags = SIZE_U32;
+    w.val = 1ULL << 32;
+    if (futex_waitv(&w, 1, NULL) == 0 || errno != EINVAL)
+        return fail("futex_waitv took a 32-bit futex expecting a 64-bit value");
+    w.val = 0;
+    if (futex_waitv(&w, 0, NULL) == 0 || errno != EINVAL)
+        return fail("futex_waitv took no futexes");
+
+    /* The timeout is an absolute time */
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    now.tv_nsec += 20 * 1000 * 1000;
+    if (now.tv_nsec >= 1000000000) {
+        now.tv_sec++;
+        now.tv_nsec -= 1000000000;
+    }
+    if (futex_waitv(&w, 1, &now) == 0 || errno != ETIMEDOUT)
+        return fail("futex_waitv did not time out");
+    return 0;
+}
+
+static int check_wake(void)
+{
+    pthread_t thread;
+    void *ret;
+
+    if (pthread_create(&thread, NULL, waiter, NULL) != 0)
+        return fail("pthread_create failed");
+    while (!atomic_load(&waiting))
+        sched_yield();
+    /* Wake the 64-bit word once the thread sleeps on it */
+    while (!atomic_load(&done) && futex_wake(&word64, 1) == 0)
+        sched_yield();
+    pthread_join(thread, &ret);
+    if ((long)ret != 1)
+        return fail("futex_waitv returned %ld instead of the index of the woken futex", (long)ret);
+
+    /* Threads have their own tids, the main thread's being the pid */
+    if (syscall(SYS_gettid) != getpid())
+        return fail("the tid of the main thread is not the pid");
+    if (waiter_tid == getpid() || waiter_tid <= 0)
+        return fail("the tid of a thread is %d, the pid being %d", waiter_tid, getpid());
+    return 0;
+}
+
+int main(void)
+{
+    if (check_invalid() || check_wake())
+        return 1;
+    return pass();
+}
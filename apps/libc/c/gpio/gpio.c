@@ -0,0 +1,197 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <linux/gpio.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <unistd.h>
+
+#define TEST_NAME "gpio"
+#include "test.h"
+
+/* The software chip, whose line 0 drives /dev/led0 */
+#define CHIP "/dev/gpiochip0"
+#define CHIP_LINES 32
+
+static int line_info(int chip, unsigned int offset, struct gpio_v2_line_info *info)
+{
+    memset(info, 0, sizeof(*info));
+    info->offset = offset;
+    return ioctl(chip, GPIO_V2_GET_LINEINFO_IOCTL, info);
+}
+
+/* Request `num_lines` lines from `offsets` with `flags`, returning the fd */
+static int request(int chip, const unsigned int *offsets, unsigned int num_lines,
+                   unsigned long long flags, struct gpio_v2_line_config *config)
+{
+    struct gpio_v2_line_request req;
+    struct gpio_v2_line_config plain;
+
+    memset(&req, 0, sizeof(req));
+    memcpy(req.offsets, offsets, num_lines * sizeof(*offsets));
+    req.num_lines = num_lines;
+    strcpy(req.consumer, "gpio-test");
+    if (!config) {
+        memset(&plain, 0, sizeof(plain));
+        plain.flags = flags;
+        config = &plain;
+    }
+    req.config = *config;
+    if (ioctl(chip, GPIO_V2_GET_LINE_IOCTL, &req) < 0)
+        return -1;
+    return req.fd;
+}
+
+static int get_values(int fd, unsigned long long mask, unsigned long long *bits)
+{
+    struct gpio_v2_line_values values = {.mask = mask};
+
+    if (ioctl(fd, GPIO_V2_LINE_GET_VALUES_IOCTL, &values) < 0)
+        return -1;
+    *bits = values.bits;
+    return 0;
+}
+
+static int set_values(int fd, unsigned long long mask, unsigned long long bits)
+{
+    struct gpio_v2_line_values values = {.mask = mask, .bits = bits};
+
+    return ioctl(fd, GPIO_V2_LINE_SET_VALUES_IOCTL, &values);
+}
+
+static int test_chip(int chip)
+{
+    struct gpiochip_info info;
+    struct gpio_v2_line_info line;
+    unsigned int led = 0;
+
+    if (ioctl(chip, GPIO_GET_CHIPINFO_IOCTL, &info) < 0)
+        return fail("GPIO_GET_CHIPINFO_IOCTL failed: %s", strerror(errno));
+    if (strcmp(info.name, "gpiochip0") != 0 || strcmp(info.label, "gpio-sim") != 0 ||
+        info.lines != CHIP_LINES)
+        return fail("chip info is %s, %s, %u lines", info.name, info.label, info.lines);
+
+    /* The LED holds its line */
+    if (line_info(chip, 0, &line) < 0)
+        return fail("GPIO_V2_GET_LINEINFO_IOCTL failed: %s", strerror(errno));
+    if (!(line.flags & GPIO_V2_LINE_FLAG_USED) || !(line.flags & GPIO_V2_LINE_FLAG_OUTPUT) ||
+        strcmp(line.consumer, "led0") != 0)
+        return fail("line 0 has flags %#llx and consumer %s", line.flags, line.consumer);
+    if (request(chip, &led, 1, GPIO_V2_LINE_FLAG_OUTPUT, NULL) >= 0 || errno != EBUSY)
+        return fail("the line of the LED could be requested");
+    if (line_info(chip, CHIP_LINES, &line) == 0 || errno != EINVAL)
+        return fail("a line past the chip has info");
+    return 0;
+}
+
+static int test_request(int chip)
+{
+    static const unsigned int offsets[] = {1, 2};
+    struct gpio_v2_line_config config;
+    struct gpio_v2_line_info line;
+    unsigned long long bits;
+    int fd;
+
+    /* Two outputs, the first one starting high */
+    memset(&config, 0, sizeof(config));
+    config.flags = GPIO_V2_LINE_FLAG_OUTPUT;
+    config.num_attrs = 1;
+    config.attrs[0].attr.id = GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES;
+    config.attrs[0].attr.values = 0b01;
+    config.attrs[0].mask = 0b11;
+    fd = request(chip, offsets, 2, 0, &config);
+    if (fd < 0)
+        return fail("GPIO_V2_GET_LINE_IOCTL failed: %s", strerror(errno));
+    if (get_values(fd, 0b11, &bits) < 0 || bits != 0b01)
+        return fail("the initial output values read back as %#llx", bits);
+    if (set_values(fd, 0b11, 0b10) < 0)
+        return fail("GPIO_V2_LINE_SET_VALUES_IOCTL failed: %s", strerror(errno));
+    if (get_values(fd, 0b11, &bits) < 0 || bits != 0b10)
+        return fail("the output values read back as %#llx", bits);
+
+    if (line_info(chip, 2, &line) < 0 || strcmp(line.consumer, "gpio-test") != 0 ||
+        line.flags != (GPIO_V2_LINE_FLAG_USED | GPIO_V2_LINE_FLAG_OUTPUT))
+        return fail("line 2 has flags %#llx and consumer %s", line.flags, line.consumer);
+    if (request(chip, &offsets[1], 1, GPIO_V2_LINE_FLAG_INPUT, NULL) >= 0 || errno != EBUSY)
+        return fail("a requested line could be requested again");
+
+    /* Active-low inputs read the inverted levels, and can't be driven */
+    memset(&config, 0, sizeof(config));
+    config.flags = GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_ACTIVE_LOW;
+    if (ioctl(fd, GPIO_V2_LINE_SET_CONFIG_IOCTL, &config) < 0)
+        return fail("GPIO_V2_LINE_SET_CONFIG_IOCTL failed: %s", strerror(errno));
+    if (get_values(fd, 0b11, &bits) < 0 || bits != 0b01)
+        return fail("the active-low values read back as %#llx", bits);
+    if (set_values(fd, 0b01, 0b01) == 0 || errno != EPERM)
+        return fail("an input could be driven");
+    if (line_info(chip, 1, &line) < 0 ||
+        line.flags != (GPIO_V2_LINE_FLAG_USED | GPIO_V2_LINE_FLAG_INPUT |
+                       GPIO_V2_LINE_FLAG_ACTIVE_LOW))
+        return fail("line 1 has flags %#llx after reconfiguring", line.flags);
+
+    /* Closing the request frees the lines */
+    close(fd);
+    if (line_info(chip, 1, &line) < 0 || (line.flags & GPIO_V2_LINE_FLAG_USED))
+        return fail("line 1 is still used after closing the request");
+    fd = request(chip, offsets, 2, GPIO_V2_LINE_FLAG_INPUT, NULL);
+    if (fd < 0)
+        return fail("the lines could not be requested again: %s", strerror(errno));
+    close(fd);
+    return 0;
+}
+
+static int test_invalid(int chip)
+{
+    unsigned int offset = 3, past = CHIP_LINES;
+    int fd;
+    unsigned long long both = GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_OUTPUT;
+    unsigned long long edge = GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_EDGE_RISING;
+
+    if (request(chip, &offset, 1, both, NULL) >= 0 || errno != EINVAL)
+        return fail("a line could be both an input and an output");
+    if (request(chip, &offset, 1, edge, NULL) >= 0 || errno != EINVAL)
+        return fail("edge detection could be requested");
+    if (request(chip, &past, 1, GPIO_V2_LINE_FLAG_INPUT, NULL) >= 0 || errno != EINVAL)
+        return fail("a line past the chip could be requested");
+    if (request(chip, &offset, 0, GPIO_V2_LINE_FLAG_INPUT, NULL) >= 0 || errno != EINVAL)
+        return fail("no lines could be requested");
+    /* The failed requests hold nothing */
+    fd = request(chip, &offset, 1, GPIO_V2_LINE_FLAG_INPUT, NULL);
+    if (fd < 0)
+        return fail("a line stayed held after a failed request");
+    close(fd);
+    return 0;
+}
+
+/* The LED drives line 0 of the chip */
+static int test_led(void)
+{
+    char buf[4] = {0};
+    int fd = open("/dev/led0", O_RDWR);
+
+    if (fd < 0)
+        return fail("cannot open /dev/led0: %s", strerror(errno));
+    if (read(fd, buf, sizeof(buf)) != 2 || strcmp(buf, "0\n") != 0)
+        return fail("/dev/led0 does not start off");
+    if (write(fd, "1", 1) != 1)
+        return fail("cannot write /dev/led0: %s", strerror(errno));
+    if (read(fd, buf, sizeof(buf)) != 2 || strcmp(buf, "1\n") != 0)
+        return fail("/dev/led0 reads %s after turning it on", buf);
+    if (write(fd, "on", 2) >= 0 || errno != EINVAL)
+        return fail("/dev/led0 took a brightness which isn't a number");
+    write(fd, "0", 1);
+    close(fd);
+    return 0;
+}
+
+int main(void)
+{
+    int chip = open(CHIP, O_RDWR);
+
+    if (chip < 0)
+        return fail("cannot open " CHIP ": %s", strerror(errno));
+    if (test_chip(chip) || test_request(chip) || test_invalid(chip) || test_led())
+        return 1;
+    close(chip);
+    return pass();
+}
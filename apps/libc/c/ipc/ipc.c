@@ -0,0 +1,221 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <mqueue.h>
+#include <sched.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ipc.h>
+#include <sys/mman.h>
+#include <sys/sem.h>
+#include <sys/shm.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+#define TEST_NAME "ipc"
+#include "test.h"
+
+#define QUEUE "/ipc_test"
+#define PAGE 4096
+
+/* Run `fn` in a child and return its exit status, or -1 if it was killed */
+static int in_child(int (*fn)(void *), void *arg)
+{
+    int status;
+    pid_t pid = fork();
+
+    if (pid == 0)
+        _exit(fn(arg));
+    if (pid < 0 || waitpid(pid, &status, 0) != pid || !WIFEXITED(status))
+        return -1;
+    return WEXITSTATUS(status);
+}
+
+static int write_shared(void *arg)
+{
+    strcpy(arg, "from the child");
+    return 0;
+}
+
+/* A segment is shared by the processes attaching it, and outlives IPC_RMID
+ * while attached */
+static int check_shm(void)
+{
+    struct shmid_ds ds;
+    char *p;
+    int id = shmget(IPC_PRIVATE, PAGE, IPC_CREAT | 0600);
+
+    if (id < 0)
+        return fail("shmget failed: %s", strerror(errno));
+    p = shmat(id, NULL, 0);
+    if (p == (void *)-1)
+        return fail("shmat failed: %s", strerror(errno));
+    if (in_child(write_shared, p) != 0 || strcmp(p, "from the child") != 0)
+        return fail("a segment attached before fork is not shared with the child");
+    if (shmctl(id, IPC_STAT, &ds) < 0 || ds.shm_segsz != PAGE || ds.shm_nattch != 1)
+        return fail("IPC_STAT gives %zu bytes and %lu attachments", (size_t)ds.shm_segsz,
+                    (unsigned long)ds.shm_nattch);
+    if (shmctl(id, IPC_RMID, NULL) < 0)
+        return fail("IPC_RMID failed: %s", strerror(errno));
+    if (strcmp(p, "from the child") != 0)
+        return fail("a removed segment lost its contents while attached");
+    if (shmdt(p) < 0)
+        return fail("shmdt failed: %s", strerror(errno));
+    if (shmat(id, NULL, 0) != (void *)-1 || shmdt(p) == 0)
+        return fail("a removed segment could be attached again");
+    if (shmget(IPC_PRIVATE, 0, IPC_CREAT | 0600) >= 0 || errno != EINVAL)
+        return fail("shmget took an empty segment");
+    return 0;
+}
+
+/* Take the semaphore once, with SEM_UNDO, and exit */
+static int take_and_exit(void *arg)
+{
+    struct sembuf op = {.sem_num = 0, .sem_op = -1, .sem_flg = SEM_UNDO};
+
+    return semop(*(int *)arg, &op, 1) < 0;
+}
+
+/* Wait for the semaphore to reach zero, which fails once the set is removed */
+static int wait_zero(void *arg)
+{
+    struct sembuf op = {.sem_num = 0, .sem_op = 0, .sem_flg = 0};
+
+    return semop(*(int *)arg, &op, 1) == 0 ? 1 : errno == EIDRM ? 0 : 2;
+}
+
+static int check_sem(void)
+{
+    struct sembuf ops[2] = {{0, -1, IPC_NOWAIT}, {1, 1, 0}};
+    struct sembuf take = {0, -1, 0};
+    struct timespec short_wait = {0, 10 * 1000 * 1000};
+    unsigned short vals[2] = {1, 0};
+    int status, id = semget(IPC_PRIVATE, 2, IPC_CREAT | 0600);
+    pid_t pid;
+
+    if (id < 0)
+        return fail("semget failed: %s", strerror(errno));
+    if (semctl(id, 0, SETALL, vals) < 0)
+        return fail("SETALL failed: %s", strerror(errno));
+
+    /* The operations of a call apply together */
+    if (semop(id, ops, 2) < 0)
+        return fail("semop failed: %s", strerror(errno));
+    if (semctl(id, 0, GETVAL) != 0 || semctl(id, 1, GETVAL) != 1)
+        return fail("the values are %d and %d after semop", semctl(id, 0, GETVAL),
+                    semctl(id, 1, GETVAL));
+    if (semop(id, ops, 2) == 0 || errno != EAGAIN || semctl(id, 1, GETVAL) != 1)
+        return fail("a semop which can't proceed applied some of its operations");
+    if (semtimedop(id, &take, 1, &short_wait) == 0 || errno != EAGAIN)
+        return fail("semtimedop did not time out");
+
+    /* What a process takes with SEM_UNDO is given back when it exits */
+    semctl(id, 0, SETVAL, 1);
+    if (in_child(take_and_exit, &id) != 0)
+        return fail("the child could not take the semaphore");
+    if (semctl(id, 0, GETVAL) != 1)
+        return fail("SEM_UNDO did not give the semaphore back on exit");
+
+    /* Sleepers fail with EIDRM once the set is removed */
+    pid = fork();
+    if (pid == 0)
+        _exit(wait_zero(&id));
+    while (semctl(id, 0, GETZCNT) != 1)
+        sched_yield();
+    if (semctl(id, 0, IPC_RMID) < 0)
+        return fail("IPC_RMID failed: %s", strerror(errno));
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return fail("a sleeper did not fail with EIDRM when the set was removed");
+    return 0;
+}
+
+static int check_mqueue(void)
+{
+    struct mq_attr attr = {.mq_maxmsg = 4, .mq_msgsize = 16};
+    struct timespec deadline;
+    char buf[16];
+    unsigned int prio;
+    mqd_t q;
+
+    mq_unlink(QUEUE);
+    q = mq_open(QUEUE, O_RDWR | O_CREAT | O_EXCL | O_NONBLOCK, 0600, &attr);
+    if (q == (mqd_t)-1)
+        return fail("mq_open failed: %s", strerror(errno));
+    if (mq_open(QUEUE, O_RDWR | O_CREAT | O_EXCL, 0600, &attr) != (mqd_t)-1 || errno != EEXIST)
+        return fail("O_EXCL opened an existing queue");
+
+    /* Highest priority first, in order within a priority */
+    if (mq_send(q, "low", 4, 1) < 0 || mq_send(q, "high", 5, 9) < 0 ||
+        mq_send(q, "low2", 5, 1) < 0)
+        return fail("mq_send failed: %s", strerror(errno));
+    if (mq_send(q, "too long for the queue", 23, 0) == 0 || errno != EMSGSIZE)
+        return fail("mq_send took a message longer than mq_msgsize");
+    if (mq_receive(q, buf, sizeof(buf), &prio) != 5 || strcmp(buf, "high") != 0 || prio != 9)
+        return fail("the first message received is \"%s\"", buf);
+    if (mq_receive(q, buf, sizeof(buf), NULL) != 4 || strcmp(buf, "low") != 0 ||
+        mq_receive(q, buf, sizeof(buf), NULL) != 5 || strcmp(buf, "low2") != 0)
+        return fail("messages of a priority are not received in order");
+    if (mq_receive(q, buf, sizeof(buf), NULL) >= 0 || errno != EAGAIN)
+        return fail("mq_receive on an empty queue did not fail with EAGAIN");
+    if (mq_receive(q, buf, 8, NULL) >= 0 || errno != EMSGSIZE)
+        return fail("mq_receive took a buffer shorter than mq_msgsize");
+
+    /* A blocking receive waits until the absolute deadline */
+    attr.mq_flags = 0;
+    mq_setattr(q, &attr, NULL);
+    clock_gettime(CLOCK_REALTIME, &deadline);
+    deadline.tv_nsec += 10 * 1000 * 1000;
+    if (deadline.tv_nsec >= 1000000000) {
+        deadline.tv_sec++;
+        deadline.tv_nsec -= 1000000000;
+    }
+    if (mq_timedreceive(q, buf, sizeof(buf), NULL, &deadline) >= 0 || errno != ETIMEDOUT)
+        return fail("mq_timedreceive did not time out");
+
+    /* The name goes away, the queue stays while open */
+    if (mq_unlink(QUEUE) < 0 || mq_send(q, "still", 6, 0) < 0)
+        return fail("an unlinked queue is gone while open");
+    if (mq_open(QUEUE, O_RDWR) != (mqd_t)-1 || errno != ENOENT)
+        return fail("an unlinked queue could be opened");
+    mq_close(q);
+    return 0;
+}
+
+static int check_memfd(void)
+{
+    char buf[8] = {0};
+    char *p, *anon;
+    int fd = memfd_create("ipc_test", 0);
+
+    if (fd < 0)
+        return fail("memfd_create failed: %s", strerror(errno));
+    if (ftruncate(fd, PAGE) < 0)
+        return fail("ftruncate of a memfd failed: %s", strerror(errno));
+    p = mmap(NULL, PAGE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    anon = mmap(NULL, PAGE, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED || anon == MAP_FAILED)
+        return fail("mmap failed");
+
+    /* The mapping and the file are the same pages, shared with children */
+    if (pwrite(fd, "file", 5, 0) != 5 || strcmp(p, "file") != 0)
+        return fail("a write to a memfd is not seen in its mapping");
+    if (in_child(write_shared, p) != 0 || pread(fd, buf, 8, 0) != 8 ||
+        memcmp(buf, "from the", 8) != 0)
+        return fail("a write of a child to a memfd mapping is not in the file");
+    if (in_child(write_shared, anon) != 0 || strcmp(anon, "from the child") != 0)
+        return fail("a shared anonymous mapping is not shared with the child");
+    if (memfd_create("ipc_test", 0x100) >= 0 || errno != EINVAL)
+        return fail("memfd_create took an unknown flag");
+    munmap(p, PAGE);
+    munmap(anon, PAGE);
+    close(fd);
+    return 0;
+}
+
+int main(void)
+{
+    if (check_shm() || check_sem() || check_mqueue() || check_memfd())
+        return 1;
+    return pass();
+}
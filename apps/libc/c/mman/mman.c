@@ -0,0 +1,155 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#define TEST_NAME "mman"
+#include "test.h"
+
+#ifndef MAP_FIXED_NOREPLACE
+#define MAP_FIXED_NOREPLACE 0x100000
+#endif
+
+#define PAGE 4096
+#define FILE_PATH "/tmp/mman_test"
+
+static char *map_anon(void *addr, size_t len, int flags)
+{
+    char *p = mmap(addr, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | flags, -1, 0);
+
+    return p == MAP_FAILED ? NULL : p;
+}
+
+/* Whether the `len` bytes at `p` all hold `c` */
+static int all(const char *p, char c, size_t len)
+{
+    for (size_t i = 0; i < len; i++) {
+        if (p[i] != c)
+            return 0;
+    }
+    return 1;
+}
+
+/* MAP_FIXED replaces what is mapped, MAP_FIXED_NOREPLACE refuses to */
+static int check_fixed(void)
+{
+    char *p = map_anon(NULL, 3 * PAGE, 0);
+
+    if (!p)
+        return fail("mmap failed");
+    /* Untouched anonymous memory reads as zeroes */
+    if (!all(p, 0, 3 * PAGE))
+        return fail("fresh anonymous memory is not zeroed");
+    memset(p, 'a', 3 * PAGE);
+    if (map_anon(p + PAGE, PAGE, MAP_FIXED) != p + PAGE)
+        return fail("MAP_FIXED did not map at the address given");
+    if (!all(p, 'a', PAGE) || !all(p + PAGE, 0, PAGE) || !all(p + 2 * PAGE, 'a', PAGE))
+        return fail("MAP_FIXED did not replace just the page it maps");
+    if (map_anon(p + PAGE, PAGE, MAP_FIXED_NOREPLACE) || errno != EEXIST)
+        return fail("MAP_FIXED_NOREPLACE replaced a mapping");
+    if (map_anon(p + 1, PAGE, MAP_FIXED) || errno != EINVAL)
+        return fail("MAP_FIXED took a misaligned address");
+    munmap(p + 2 * PAGE, PAGE);
+    if (map_anon(p + 2 * PAGE, PAGE, MAP_FIXED_NOREPLACE) != p + 2 * PAGE)
+        return fail("MAP_FIXED_NOREPLACE failed on a free range");
+    munmap(p, 3 * PAGE);
+    return 0;
+}
+
+/* MADV_DONTNEED zeroes private anonymous pages, unless they are locked */
+static int check_madvise(void)
+{
+    char *p = map_anon(NULL, 2 * PAGE, 0);
+    char *shared = mmap(NULL, PAGE, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_ANONYMOUS, -1, 0);
+
+    if (!p || shared == MAP_FAILED)
+        return fail("mmap failed");
+    memset(p, 'b', 2 * PAGE);
+    if (madvise(p, PAGE, MADV_DONTNEED) < 0)
+        return fail("madvise(MADV_DONTNEED) failed: %s", strerror(errno));
+    if (!all(p, 0, PAGE) || !all(p + PAGE, 'b', PAGE))
+        return fail("MADV_DONTNEED did not drop just the page it was given");
+    if (madvise(p, PAGE, MADV_SEQUENTIAL) < 0 || madvise(p, PAGE, MADV_WILLNEED) < 0)
+        return fail("madvise did not accept a hint");
+    if (madvise(p, PAGE, 1000) == 0 || errno != EINVAL)
+        return fail("madvise took an unknown advice");
+    if (madvise(p + 1, PAGE, MADV_DONTNEED) == 0 || errno != EINVAL)
+        return fail("madvise took a misaligned address");
+    if (madvise(shared, PAGE, MADV_FREE) == 0 || errno != EINVAL)
+        return fail("MADV_FREE took a shared mapping");
+
+    if (mlock(p + 100, PAGE) < 0)
+        return fail("mlock failed: %s", strerror(errno));
+    if (madvise(p, PAGE, MADV_DONTNEED) == 0 || errno != EINVAL)
+        return fail("MADV_DONTNEED dropped locked pages");
+    if (munlock(p, 2 * PAGE) < 0)
+        return fail("munlock failed: %s", strerror(errno));
+    if (madvise(p, 2 * PAGE, MADV_DONTNEED) < 0 || !all(p, 0, 2 * PAGE))
+        return fail("MADV_DONTNEED failed after munlock");
+    munmap(p, 2 * PAGE);
+    munmap(shared, PAGE);
+
+    if (madvise(p, PAGE, MADV_DONTNEED) == 0 || errno != ENOMEM)
+        return fail("madvise took an unmapped range");
+    if (mlock(p, PAGE) == 0 || errno != ENOMEM)
+        return fail("mlock took an unmapped range");
+    return 0;
+}
+
+/* msync writes a shared file mapping back to the file */
+static int check_msync(void)
+{
+    char buf[16] = {0};
+    char *p;
+    int fd = open(FILE_PATH, O_RDWR | O_CREAT | O_TRUNC, 0644);
+
+    if (fd < 0 || ftruncate(fd, PAGE) < 0)
+        return fail("cannot create " FILE_PATH);
+    p = mmap(NULL, PAGE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    if (p == MAP_FAILED)
+        return fail("mmap of " FILE_PATH " failed");
+    strcpy(p, "written back");
+    if (msync(p, PAGE, MS_ASYNC | MS_SYNC) == 0 || errno != EINVAL)
+        return fail("msync took both MS_ASYNC and MS_SYNC");
+    if (msync(p, PAGE, MS_SYNC) < 0)
+        return fail("msync failed: %s", strerror(errno));
+    if (pread(fd, buf, sizeof(buf) - 1, 0) != sizeof(buf) - 1 || strcmp(buf, "written back") != 0)
+        return fail("the file reads \"%s\" after msync", buf);
+    munmap(p, PAGE);
+    if (msync(p, PAGE, MS_SYNC) == 0 || errno != ENOMEM)
+        return fail("msync took an unmapped range");
+    close(fd);
+    unlink(FILE_PATH);
+    return 0;
+}
+
+/* The heap grows and shrinks with brk */
+static int check_brk(void)
+{
+    char *start = (char *)syscall(SYS_brk, 0);
+    char *end = start + 4 * PAGE;
+
+    if ((char *)syscall(SYS_brk, end) != end)
+        return fail("brk did not grow the heap");
+    if (!all(start, 0, 4 * PAGE))
+        return fail("the heap grown is not zeroed");
+    memset(start, 'c', 4 * PAGE);
+    if ((char *)syscall(SYS_brk, start + PAGE) != start + PAGE)
+        return fail("brk did not shrink the heap");
+    if ((char *)syscall(SYS_brk, 0) != start + PAGE || !all(start, 'c', PAGE))
+        return fail("the heap left after shrinking it lost its contents");
+    syscall(SYS_brk, start);
+    return 0;
+}
+
+int main(void)
+{
+    if (check_fixed() || check_madvise() || check_msync() || check_brk())
+        return 1;
+    return pass();
+}
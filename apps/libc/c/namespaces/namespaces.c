@@ -0,0 +1,117 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <sched.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mount.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "namespaces"
+#include "test.h"
+
+#define MOUNT_POINT "/tmp/namespaces"
+#define INNER MOUNT_POINT "/inner"
+
+/* fork into the new namespaces of `flags` */
+static pid_t fork_into(unsigned long flags)
+{
+    return syscall(SYS_clone, flags | SIGCHLD, 0, 0, 0, 0);
+}
+
+/* Run in the new namespaces, returning what failed */
+static int child(void)
+{
+    int status, fd;
+    pid_t pid;
+
+    /* The first process of a pid namespace is its init, whose parent is
+     * outside */
+    if (getpid() != 1)
+        return 1;
+    if (getppid() != 0)
+        return 2;
+    pid = fork();
+    if (pid == 0)
+        _exit(getppid() == 1 && getpid() > 1 ? 0 : 1);
+    if (pid <= 1 || waitpid(pid, &status, 0) != pid || status != 0)
+        return 3;
+
+    /* A tmpfs mounted here is only seen from this mount namespace */
+    if (mount("none", MOUNT_POINT, "tmpfs", 0, NULL) < 0)
+        return 4;
+    fd = open(INNER, O_WRONLY | O_CREAT, 0644);
+    if (fd < 0 || write(fd, "x", 1) != 1)
+        return 5;
+    close(fd);
+    if (access(INNER, F_OK) < 0)
+        return 6;
+    return 0;
+}
+
+/* A process which unshares its mount namespace keeps its mounts to itself */
+static int check_unshare(void)
+{
+    int status, fd;
+    pid_t pid = fork();
+
+    if (pid == 0) {
+        if (unshare(CLONE_NEWNS) < 0 || mount("none", MOUNT_POINT, "tmpfs", 0, NULL) < 0)
+            _exit(1);
+        fd = open(INNER, O_WRONLY | O_CREAT, 0644);
+        _exit(fd < 0 ? 2 : 0);
+    }
+    if (pid < 0 || waitpid(pid, &status, 0) != pid)
+        return fail("cannot fork");
+    if (!WIFEXITED(status))
+        return fail("the child which unshared its mount namespace was killed");
+    if (WEXITSTATUS(status) == 1)
+        return fail("cannot mount a tmpfs after unshare(CLONE_NEWNS)");
+    if (WEXITSTATUS(status) != 0)
+        return fail("cannot create a file in a tmpfs after unshare(CLONE_NEWNS)");
+    if (access(INNER, F_OK) == 0)
+        return fail("a mount made after unshare(CLONE_NEWNS) is seen by the parent");
+    return 0;
+}
+
+int main(void)
+{
+    static const char *const what[] = {
+        [1] = "the first process of a pid namespace is not pid 1",
+        [2] = "the parent of the first process of a pid namespace is not pid 0",
+        [3] = "a child in a pid namespace did not see its parent as pid 1",
+        [4] = "cannot mount a tmpfs",
+        [5] = "cannot write a file in a tmpfs",
+        [6] = "a file written in a tmpfs is not there",
+    };
+    int status;
+    pid_t pid;
+
+    mkdir(MOUNT_POINT, 0755);
+    unlink(INNER);
+    pid = fork_into(CLONE_NEWNS | CLONE_NEWPID);
+    if (pid < 0)
+        return fail("clone with CLONE_NEWNS | CLONE_NEWPID failed: %s", strerror(errno));
+    if (pid == 0)
+        _exit(child());
+
+    /* The child is known by its pid of this namespace */
+    if (pid == 1 || waitpid(pid, &status, 0) != pid)
+        return fail("the child in a new pid namespace cannot be waited for by its pid");
+    if (!WIFEXITED(status))
+        return fail("the child in the new namespaces was killed");
+    if (WEXITSTATUS(status) != 0)
+        return fail("%s", WEXITSTATUS(status) < 7 ? what[WEXITSTATUS(status)] : "?");
+
+    /* The mount stayed in the namespace of the child */
+    if (access(INNER, F_OK) == 0)
+        return fail("a mount of a child namespace is seen by its parent");
+    if (check_unshare())
+        return 1;
+    rmdir(MOUNT_POINT);
+    return pass();
+}
@@ -0,0 +1,239 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "paths"
+#include "test.h"
+
+#define DIR_PATH "/tmp/paths"
+#define QUOTA_SYSCTL "/proc/sys/fs/quota-max-blocks"
+#define USER 1000
+
+#ifndef SYS_statx
+#define SYS_statx 332
+#endif
+#define STATX_BASIC 0x7ff
+#define STATX_RESERVED 0x80000000U
+
+/* The start of struct statx, which older headers lack */
+struct statx_head {
+    uint32_t mask;
+    uint32_t blksize;
+    uint64_t attributes;
+    uint32_t nlink;
+    uint32_t uid;
+    uint32_t gid;
+    uint16_t mode;
+    uint16_t pad;
+    uint64_t ino;
+    uint64_t size;
+    uint8_t rest[256 - 48];
+};
+
+static int statx_at(int dirfd, const char *path, int flags, unsigned int mask,
+                    struct statx_head *buf)
+{
+    return syscall(SYS_statx, dirfd, path, flags, mask, buf);
+}
+
+static int write_file(const char *path, const char *data)
+{
+    int fd = open(path, O_WRONLY | O_CREAT | O_TRUNC, 0644);
+    int ok;
+
+    if (fd < 0)
+        return -1;
+    ok = write(fd, data, strlen(data)) == (ssize_t)strlen(data);
+    close(fd);
+    return ok ? 0 : -1;
+}
+
+/* Links are followed in every component, and read back as given */
+static int check_symlinks(void)
+{
+    char buf[64] = {0};
+    struct stat st;
+
+    mkdir(DIR_PATH "/dir", 0755);
+    if (write_file(DIR_PATH "/dir/file", "data") < 0)
+        return fail("cannot create a file");
+    if (symlink("dir", DIR_PATH "/rel") < 0 || symlink(DIR_PATH "/dir/file", DIR_PATH "/abs") < 0)
+        return fail("symlink failed: %s", strerror(errno));
+    if (symlink("anything", DIR_PATH "/abs") == 0 || errno != EEXIST)
+        return fail("symlink replaced a link");
+
+    if (readlink(DIR_PATH "/rel", buf, sizeof(buf)) != 3 || memcmp(buf, "dir", 3) != 0)
+        return fail("readlink read \"%s\" instead of the target", buf);
+    if (readlink(DIR_PATH "/abs", buf, 4) != 4 || memcmp(buf, "/tmp", 4) != 0)
+        return fail("readlink did not truncate the target to the buffer");
+    if (readlink(DIR_PATH "/dir/file", buf, sizeof(buf)) == 0 || errno != EINVAL)
+        return fail("readlink took a file which isn't a link");
+
+    if (stat(DIR_PATH "/rel/file", &st) < 0 || st.st_size != 4)
+        return fail("a link to a directory was not followed");
+    if (lstat(DIR_PATH "/abs", &st) < 0 || !S_ISLNK(st.st_mode))
+        return fail("lstat did not stat the link itself");
+    if (stat(DIR_PATH "/abs", &st) < 0 || !S_ISREG(st.st_mode))
+        return fail("stat did not follow the link");
+    if (open(DIR_PATH "/abs", O_RDONLY | O_NOFOLLOW) >= 0 || errno != ELOOP)
+        return fail("O_NOFOLLOW opened a link");
+
+    /* Dangling links and loops */
+    symlink("missing", DIR_PATH "/dangling");
+    if (stat(DIR_PATH "/dangling", &st) == 0 || errno != ENOENT)
+        return fail("a dangling link could be followed");
+    symlink("loop", DIR_PATH "/loop");
+    if (stat(DIR_PATH "/loop", &st) == 0 || errno != ELOOP)
+        return fail("a link to itself did not fail with ELOOP");
+
+    /* Removing a link leaves its target */
+    if (unlink(DIR_PATH "/abs") < 0 || access(DIR_PATH "/dir/file", F_OK) < 0)
+        return fail("unlinking a link did not remove just the link");
+    unlink(DIR_PATH "/rel");
+    unlink(DIR_PATH "/dangling");
+    unlink(DIR_PATH "/loop");
+    return 0;
+}
+
+/* Each file has its own inode number, the same whichever way it is stat'ed */
+static int check_inodes(void)
+{
+    struct stat file, other, by_fd;
+    struct statx_head stx;
+    int fd;
+
+    if (write_file(DIR_PATH "/other", "") < 0)
+        return fail("cannot create a file");
+    if (stat(DIR_PATH "/dir/file", &file) < 0 || stat(DIR_PATH "/other", &other) < 0)
+        return fail("stat failed");
+    if (file.st_ino == 0 || file.st_ino == other.st_ino)
+        return fail("two files have the inode number %llu", (unsigned long long)file.st_ino);
+
+    fd = open(DIR_PATH "/dir", O_RDONLY | O_DIRECTORY);
+    if (fstatat(fd, "file", &by_fd, 0) < 0 || by_fd.st_ino != file.st_ino)
+        return fail("fstatat relative to a directory found another inode");
+    if (statx_at(fd, "file", 0, STATX_BASIC, &stx) < 0)
+        return fail("statx failed: %s", strerror(errno));
+    if (stx.ino != file.st_ino || stx.size != 4 || !S_ISREG(stx.mode))
+        return fail("statx disagrees with stat");
+    if (statx_at(fd, "", AT_EMPTY_PATH, STATX_BASIC, &stx) < 0 || !S_ISDIR(stx.mode))
+        return fail("statx with AT_EMPTY_PATH did not stat the directory");
+    if (statx_at(fd, "file", 0, STATX_RESERVED, &stx) == 0 || errno != EINVAL)
+        return fail("statx took a reserved mask bit");
+    close(fd);
+    unlink(DIR_PATH "/other");
+    return 0;
+}
+
+/* The modes of new files go through the umask, and the owner may change them */
+static int check_modes(void)
+{
+    struct stat st;
+
+    if (umask(027) != 022 || umask(027) != 027)
+        return fail("umask did not return the previous mask");
+    unlink(DIR_PATH "/masked");
+    if (write_file(DIR_PATH "/masked", "") < 0 || stat(DIR_PATH "/masked", &st) < 0)
+        return fail("cannot create a file");
+    if ((st.st_mode & 0777) != 0640)
+        return fail("a file created with 0644 under umask 027 has mode %o", st.st_mode & 0777);
+    umask(022);
+
+    if (fchmodat(AT_FDCWD, DIR_PATH "/masked", 0700, 0) < 0)
+        return fail("fchmodat failed: %s", strerror(errno));
+    if (stat(DIR_PATH "/masked", &st) < 0 || (st.st_mode & 0777) != 0700)
+        return fail("the mode is %o after fchmodat", st.st_mode & 0777);
+    if (fchownat(AT_FDCWD, DIR_PATH "/masked", USER, USER, 0) < 0)
+        return fail("fchownat failed: %s", strerror(errno));
+    if (stat(DIR_PATH "/masked", &st) < 0 || st.st_uid != USER || st.st_gid != USER)
+        return fail("the owner is %u:%u after fchownat", st.st_uid, st.st_gid);
+
+    /* Root can't execute a file nobody may execute */
+    chmod(DIR_PATH "/dir/file", 0644);
+    if (faccessat(AT_FDCWD, DIR_PATH "/dir/file", R_OK | W_OK, 0) < 0)
+        return fail("root may not read and write a file");
+    if (faccessat(AT_FDCWD, DIR_PATH "/dir/file", X_OK, 0) == 0 || errno != EACCES)
+        return fail("root may execute a file which isn't executable");
+    if (faccessat(AT_FDCWD, DIR_PATH "/missing", F_OK, 0) == 0 || errno != ENOENT)
+        return fail("faccessat found a missing file");
+    if (faccessat(AT_FDCWD, DIR_PATH "/dir/file", 8, 0) == 0 || errno != EINVAL)
+        return fail("faccessat took an unknown mode");
+    return 0;
+}
+
+/* A user other than the owner goes by the other bits, and by the quota */
+static int user(void)
+{
+    char block[512] = {0};
+    int fd;
+
+    if (setgid(USER) < 0 || setuid(USER) < 0)
+        return 1;
+    if (access(DIR_PATH "/dir/file", R_OK) < 0 || access(DIR_PATH "/dir/file", W_OK) == 0)
+        return 2;
+    if (chmod(DIR_PATH "/dir/file", 0666) == 0 || errno != EPERM)
+        return 3;
+    /* A file of the user, who may write two blocks */
+    fd = open(DIR_PATH "/quota", O_WRONLY | O_CREAT, 0644);
+    if (fd < 0 || write(fd, block, sizeof(block)) != sizeof(block))
+        return 4;
+    if (write(fd, block, sizeof(block)) != sizeof(block))
+        return 5;
+    if (write(fd, block, sizeof(block)) >= 0 || errno != EDQUOT)
+        return 6;
+    /* Shrinking the file makes room */
+    if (ftruncate(fd, 0) < 0 || pwrite(fd, block, sizeof(block), 0) != sizeof(block))
+        return 7;
+    close(fd);
+    return 0;
+}
+
+static int check_user(void)
+{
+    static const char *const what[] = {
+        [1] = "cannot become a user",
+        [2] = "a user may not read or may write a file of root with mode 0644",
+        [3] = "a user could change the mode of a file of root",
+        [4] = "a user cannot create and write a file",
+        [5] = "a user cannot write within its quota",
+        [6] = "a user could write past its quota",
+        [7] = "a user cannot write again after truncating its file",
+    };
+    int status, fd = open(QUOTA_SYSCTL, O_WRONLY);
+    pid_t pid;
+
+    if (fd < 0 || write(fd, "2", 1) != 1)
+        return fail("cannot set " QUOTA_SYSCTL);
+    chmod(DIR_PATH, 0777);
+    pid = fork();
+    if (pid == 0)
+        _exit(user());
+    waitpid(pid, &status, 0);
+    write(fd, "0", 1);
+    close(fd);
+    unlink(DIR_PATH "/quota");
+    if (!WIFEXITED(status))
+        return fail("the user process was killed");
+    if (WEXITSTATUS(status) != 0)
+        return fail("%s", WEXITSTATUS(status) < 8 ? what[WEXITSTATUS(status)] : "?");
+    return 0;
+}
+
+int main(void)
+{
+    mkdir(DIR_PATH, 0755);
+    if (check_symlinks() || check_inodes() || check_modes() || check_user())
+        return 1;
+    unlink(DIR_PATH "/masked");
+    unlink(DIR_PATH "/dir/file");
+    rmdir(DIR_PATH "/dir");
+    rmdir(DIR_PATH);
+    return pass();
+}
@@ -0,0 +1,61 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "pipe_block"
+#include "test.h"
+
+#define CHUNKS 64
+
+int main(void)
+{
+    int fds[2], status;
+    char buf[4096];
+    long total = 0;
+    ssize_t n;
+    pid_t pid;
+
+    /* The reader sleeps until the writer fills the pipe up, many times over */
+    if (pipe(fds) < 0)
+        return fail("pipe failed");
+    pid = fork();
+    if (pid < 0)
+        return fail("fork failed");
+    if (pid == 0) {
+        close(fds[0]);
+        memset(buf, 'x', sizeof(buf));
+        for (int i = 0; i < CHUNKS; i++) {
+            if (write(fds[1], buf, sizeof(buf)) != sizeof(buf))
+                _exit(1);
+        }
+        _exit(0);
+    }
+    close(fds[1]);
+    while ((n = read(fds[0], buf, sizeof(buf))) > 0)
+        total += n;
+    close(fds[0]);
+    if (n < 0 || total != CHUNKS * (long)sizeof(buf))
+        return fail("short read");
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return fail("writer failed");
+
+    /* Nonblocking ends don't wait */
+    if (pipe2(fds, O_NONBLOCK) < 0)
+        return fail("pipe2 failed");
+    if (read(fds[0], buf, 1) != -1 || errno != EAGAIN)
+        return fail("nonblocking read did not fail with EAGAIN");
+
+    /* No reader left: EPIPE, with SIGPIPE ignored */
+    signal(SIGPIPE, SIG_IGN);
+    close(fds[0]);
+    if (write(fds[1], "x", 1) != -1 || errno != EPIPE)
+        return fail("write without a reader did not fail with EPIPE");
+    close(fds[1]);
+
+    return pass();
+}
@@ -0,0 +1,84 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#define TEST_NAME "pipe_sysctl"
+#include "test.h"
+
+#define MAX_SIZE "/proc/sys/fs/pipe-max-size"
+
+static long read_sysctl(const char *path)
+{
+    char buf[32];
+    ssize_t len;
+    int fd = open(path, O_RDONLY);
+
+    if (fd < 0)
+        return -1;
+    len = read(fd, buf, sizeof(buf) - 1);
+    close(fd);
+    if (len <= 0)
+        return -1;
+    buf[len] = '\0';
+    return strtol(buf, NULL, 10);
+}
+
+static int write_sysctl(const char *path, long value)
+{
+    char buf[32];
+    int fd = open(path, O_WRONLY), ret;
+
+    if (fd < 0)
+        return -1;
+    snprintf(buf, sizeof(buf), "%ld\n", value);
+    ret = write(fd, buf, strlen(buf)) < 0 ? -1 : 0;
+    close(fd);
+    return ret;
+}
+
+/* How much a new pipe takes before a write would block */
+static long pipe_capacity(void)
+{
+    static char block[4096];
+    long total = 0;
+    ssize_t n;
+    int fds[2];
+
+    if (pipe2(fds, O_NONBLOCK) < 0)
+        return -1;
+    while ((n = write(fds[1], block, sizeof(block))) > 0)
+        total += n;
+    close(fds[0]);
+    close(fds[1]);
+    return n < 0 && errno == EAGAIN ? total : -1;
+}
+
+int main(void)
+{
+    long max_size = read_sysctl(MAX_SIZE), capacity;
+
+    if (max_size < 0)
+        return fail("cannot read " MAX_SIZE);
+    capacity = pipe_capacity();
+    if (capacity <= 4096)
+        return fail("default pipe capacity is %ld", capacity);
+
+    /* A smaller maximum shrinks the pipes made afterwards */
+    if (write_sysctl(MAX_SIZE, 4096) < 0)
+        return fail("cannot write " MAX_SIZE);
+    capacity = pipe_capacity();
+    write_sysctl(MAX_SIZE, max_size);
+    if (capacity != 4096)
+        return fail("pipe capacity is %ld with a maximum of 4096", capacity);
+
+    /* Below a page is out of range */
+    if (write_sysctl(MAX_SIZE, 1024) != -1 || errno != EINVAL)
+        return fail("a maximum below a page did not fail with EINVAL");
+    if (read_sysctl(MAX_SIZE) != max_size)
+        return fail("maximum not restored");
+    return pass();
+}
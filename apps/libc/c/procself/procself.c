@@ -0,0 +1,60 @@
+#define _GNU_SOURCE
+#include <dirent.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#define TEST_NAME "procself"
+#include "test.h"
+
+int main(int argc, char **argv)
+{
+    char exe[256], buf[256], link[64];
+    ssize_t len;
+    int fd, seen = 0;
+    DIR *dir;
+    struct dirent *entry;
+
+    /* The program being run, as an absolute path */
+    len = readlink("/proc/self/exe", exe, sizeof(exe) - 1);
+    if (len <= 0 || exe[0] != '/')
+        return fail("readlink of /proc/self/exe failed");
+    exe[len] = '\0';
+
+    /* The arguments, each ending with its NUL */
+    fd = open("/proc/self/cmdline", O_RDONLY);
+    if (fd < 0)
+        return fail("open of /proc/self/cmdline failed");
+    len = read(fd, buf, sizeof(buf));
+    close(fd);
+    if (len <= 0 || buf[len - 1] != '\0' || strcmp(buf, argv[0]) != 0)
+        return fail("/proc/self/cmdline does not start with argv[0]");
+
+    /* The standard streams, and a file opened, are listed */
+    fd = open("/proc/self/exe", O_RDONLY);
+    dir = opendir("/proc/self/fd");
+    if (fd < 0 || !dir)
+        return fail("opendir of /proc/self/fd failed");
+    while ((entry = readdir(dir))) {
+        int n = atoi(entry->d_name);
+        if (entry->d_type == DT_LNK && (n <= 2 || n == fd))
+            seen++;
+    }
+    closedir(dir);
+    if (seen < 4)
+        return fail("/proc/self/fd does not list the open fds");
+
+    /* Whose links lead to the file */
+    snprintf(link, sizeof(link), "/proc/self/fd/%d", fd);
+    len = readlink(link, buf, sizeof(buf) - 1);
+    if (len <= 0)
+        return fail("readlink of /proc/self/fd/<fd> failed");
+    buf[len] = '\0';
+    if (strcmp(buf, exe) != 0)
+        return fail("/proc/self/fd/<fd> does not lead to the file");
+    close(fd);
+
+    return pass();
+}
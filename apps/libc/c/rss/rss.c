@@ -0,0 +1,47 @@
+#include <stdio.h>
+#include <sys/mman.h>
+
+#define TEST_NAME "rss"
+#include "test.h"
+
+#define PAGES 64
+
+static long resident(void)
+{
+    long size, rss = -1;
+    FILE *f = fopen("/proc/self/statm", "r");
+    if (!f)
+        return -1;
+    if (fscanf(f, "%ld %ld", &size, &rss) != 2)
+        rss = -1;
+    fclose(f);
+    return rss;
+}
+
+int main(void)
+{
+    long before, touched, after;
+    char *p;
+
+    /* Anonymous pages are resident once touched */
+    p = mmap(NULL, PAGES * 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED)
+        return fail("mmap failed");
+    before = resident();
+    if (before <= 0)
+        return fail("no resident pages in /proc/self/statm");
+    for (int i = 0; i < PAGES; i++)
+        p[i * 4096] = 1;
+    touched = resident();
+    if (touched < before + PAGES)
+        return fail("touched pages not counted");
+
+    /* And no longer once unmapped */
+    if (munmap(p, PAGES * 4096) < 0)
+        return fail("munmap failed");
+    after = resident();
+    if (after > touched - PAGES)
+        return fail("unmapped pages still counted");
+
+    return pass();
+}
@@ -0,0 +1,189 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/prctl.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "seccomp"
+#include "test.h"
+
+#define SECCOMP_MODE_STRICT 1
+#define SECCOMP_MODE_FILTER 2
+/* The mode of this kernel which installs a list instead of a BPF filter */
+#define SECCOMP_MODE_LIST 0x53430001
+#define SECCOMP_LIST_ALLOW 0
+#define SECCOMP_LIST_DENY 1
+
+#define SECCOMP_RET_KILL_PROCESS 0x80000000u
+#define SECCOMP_RET_TRAP 0x00030000u
+#define SECCOMP_RET_ERRNO 0x00050000u
+
+#define SYS_SECCOMP 1
+#define TRAP_DATA 42
+
+/* struct seccomp_list of this kernel */
+struct seccomp_list {
+    uint32_t kind;
+    uint32_t action;
+    uint32_t len;
+    uint32_t pad;
+    uint64_t syscalls;
+};
+
+static volatile int trapped, trap_code, trap_errno, trap_syscall;
+static void *volatile trap_addr;
+
+static void on_sigsys(int sig, siginfo_t *info, void *ctx)
+{
+    (void)sig;
+    (void)ctx;
+    trapped++;
+    trap_code = info->si_code;
+    trap_errno = info->si_errno;
+    trap_syscall = info->si_syscall;
+    trap_addr = info->si_call_addr;
+}
+
+static int install_list(uint32_t kind, uint32_t action, const uint32_t *syscalls, uint32_t len)
+{
+    struct seccomp_list list = {
+        .kind = kind,
+        .action = action,
+        .len = len,
+        .syscalls = (uintptr_t)syscalls,
+    };
+
+    return prctl(PR_SET_SECCOMP, SECCOMP_MODE_LIST, &list);
+}
+
+/* Run `child` in a child process, and return how it ended, as waitpid has it */
+static int run_child(int (*child)(void))
+{
+    int status;
+    pid_t pid = fork();
+
+    if (pid < 0)
+        return -1;
+    if (pid == 0)
+        _exit(child());
+    if (waitpid(pid, &status, 0) != pid)
+        return -1;
+    return status;
+}
+
+static int strict_exit(void)
+{
+    if (prctl(PR_SET_SECCOMP, SECCOMP_MODE_STRICT) != 0)
+        return 1;
+    /* exit_group isn't allowed, exit is */
+    syscall(SYS_exit, 7);
+    return 1;
+}
+
+static int strict_getpid(void)
+{
+    if (prctl(PR_SET_SECCOMP, SECCOMP_MODE_STRICT) != 0)
+        return 1;
+    syscall(SYS_getpid);
+    syscall(SYS_exit, 0);
+    return 1;
+}
+
+/* The strict mode leaves read, write, exit and rt_sigreturn only */
+static int check_strict(void)
+{
+    int status;
+
+    if (prctl(PR_GET_SECCOMP) != 0)
+        return fail("the seccomp mode is %d without filters", prctl(PR_GET_SECCOMP));
+    status = run_child(strict_exit);
+    if (!WIFEXITED(status) || WEXITSTATUS(status) != 7)
+        return fail("exit in the strict mode ended with status %#x", status);
+    status = run_child(strict_getpid);
+    if (!WIFSIGNALED(status) || WTERMSIG(status) != SIGKILL)
+        return fail("getpid in the strict mode ended with status %#x", status);
+    return 0;
+}
+
+static int deny_errno(void)
+{
+    static const uint32_t denied[] = { SYS_getppid };
+
+    if (install_list(SECCOMP_LIST_DENY, SECCOMP_RET_ERRNO | EPERM, denied, 1) != 0)
+        return 1;
+    if (prctl(PR_GET_SECCOMP) != SECCOMP_MODE_FILTER)
+        return 2;
+    if (syscall(SYS_getppid) != -1 || errno != EPERM)
+        return 3;
+    /* Other syscalls go through */
+    if (syscall(SYS_getpid) != getpid())
+        return 4;
+    return 0;
+}
+
+static int deny_trap(void)
+{
+    static const uint32_t denied[] = { SYS_getppid };
+    struct sigaction sa = { .sa_sigaction = on_sigsys, .sa_flags = SA_SIGINFO };
+
+    if (sigaction(SIGSYS, &sa, NULL) != 0)
+        return 1;
+    if (install_list(SECCOMP_LIST_DENY, SECCOMP_RET_TRAP | TRAP_DATA, denied, 1) != 0)
+        return 1;
+    if (syscall(SYS_getppid) != -1 || errno != ENOSYS)
+        return 2;
+    if (trapped != 1)
+        return 3;
+    if (trap_code != SYS_SECCOMP || trap_errno != TRAP_DATA || trap_syscall != SYS_getppid)
+        return 4;
+    /* The address of the call comes as it is */
+    if (trap_addr == NULL)
+        return 5;
+    return 0;
+}
+
+static int allow_kill(void)
+{
+    static const uint32_t allowed[] = { SYS_exit, SYS_exit_group };
+
+    if (install_list(SECCOMP_LIST_ALLOW, SECCOMP_RET_KILL_PROCESS, allowed, 2) != 0)
+        return 1;
+    syscall(SYS_getpid);
+    return 2;
+}
+
+/* A list allows or denies the syscalls in it, with the action it has */
+static int check_lists(void)
+{
+    static const uint32_t denied[] = { SYS_getppid };
+    int status;
+
+    if (install_list(2, SECCOMP_RET_ERRNO | EPERM, denied, 1) != -1 || errno != EINVAL)
+        return fail("a list of unknown kind was installed");
+    if (install_list(SECCOMP_LIST_DENY, 0x12340000, denied, 1) != -1 || errno != EINVAL)
+        return fail("a list with an unknown action was installed");
+    status = run_child(deny_errno);
+    if (!WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return fail("denying with an errno failed at step %d", WEXITSTATUS(status));
+    status = run_child(deny_trap);
+    if (!WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return fail("denying with a trap failed at step %d", WEXITSTATUS(status));
+    status = run_child(allow_kill);
+    if (!WIFSIGNALED(status) || WTERMSIG(status) != SIGSYS)
+        return fail("a syscall out of an allow list ended with status %#x", status);
+    /* The filters of the children are theirs only */
+    if (syscall(SYS_getppid) != getppid() || prctl(PR_GET_SECCOMP) != 0)
+        return fail("the filters of a child apply to its parent");
+    return 0;
+}
+
+int main(void)
+{
+    if (check_strict() || check_lists())
+        return 1;
+    return pass();
+}
@@ -0,0 +1,64 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <stdio.h>
+#include <sys/time.h>
+#include <time.h>
+
+#define TEST_NAME "settime"
+#include "test.h"
+
+#define SHIFT 100000
+
+int main(void)
+{
+    struct timespec before, after, ts;
+    struct timeval tv, delta, left;
+    long pending;
+
+    /* Setting the realtime clock moves it, and gettimeofday, but not the monotonic one */
+    clock_gettime(CLOCK_REALTIME, &before);
+    ts.tv_sec = before.tv_sec + SHIFT;
+    ts.tv_nsec = before.tv_nsec;
+    if (clock_settime(CLOCK_REALTIME, &ts) < 0)
+        return fail("clock_settime failed");
+    clock_gettime(CLOCK_REALTIME, &after);
+    if (after.tv_sec < before.tv_sec + SHIFT || after.tv_sec > before.tv_sec + SHIFT + 1)
+        return fail("CLOCK_REALTIME not set");
+    gettimeofday(&tv, NULL);
+    if (tv.tv_sec < before.tv_sec + SHIFT || tv.tv_sec > before.tv_sec + SHIFT + 1)
+        return fail("gettimeofday not set");
+
+    /* And back */
+    tv.tv_sec = before.tv_sec;
+    tv.tv_usec = before.tv_nsec / 1000;
+    if (settimeofday(&tv, NULL) < 0)
+        return fail("settimeofday failed");
+    clock_gettime(CLOCK_REALTIME, &after);
+    if (after.tv_sec < before.tv_sec || after.tv_sec > before.tv_sec + 1)
+        return fail("CLOCK_REALTIME not set back");
+
+    /* The monotonic clock can't be set, nor a time out of range */
+    if (clock_settime(CLOCK_MONOTONIC, &ts) != -1 || errno != EINVAL)
+        return fail("setting CLOCK_MONOTONIC did not fail with EINVAL");
+    ts.tv_nsec = 1000000000;
+    if (clock_settime(CLOCK_REALTIME, &ts) != -1 || errno != EINVAL)
+        return fail("setting an invalid time did not fail with EINVAL");
+
+    /* A correction is slewed in slowly: most of it is still left right away */
+    delta.tv_sec = 1;
+    delta.tv_usec = 0;
+    if (adjtime(&delta, NULL) < 0)
+        return fail("adjtime failed");
+    if (adjtime(NULL, &left) < 0)
+        return fail("adjtime read failed");
+    pending = left.tv_sec * 1000000L + left.tv_usec;
+    if (pending < 900000 || pending > 1000000)
+        return fail("correction not pending");
+    delta.tv_sec = 0;
+    if (adjtime(&delta, &left) < 0)
+        return fail("adjtime cancel failed");
+    if (adjtime(NULL, &left) < 0 || left.tv_sec != 0 || left.tv_usec != 0)
+        return fail("correction not cancelled");
+
+    return pass();
+}
@@ -0,0 +1,58 @@
+#include <pthread.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "sighand"
+#include "test.h"
+
+static void handler(int sig)
+{
+    (void)sig;
+}
+
+static int installed(int sig, void (*expected)(int))
+{
+    struct sigaction sa;
+    if (sigaction(sig, NULL, &sa) < 0)
+        return 0;
+    return sa.sa_handler == expected;
+}
+
+/* Threads share the handlers: what one installs, the others see */
+static void *install(void *arg)
+{
+    struct sigaction sa = { .sa_handler = handler };
+    (void)arg;
+    sigaction(SIGUSR1, &sa, NULL);
+    return NULL;
+}
+
+int main(void)
+{
+    pthread_t thread;
+    int status;
+    pid_t pid;
+
+    if (pthread_create(&thread, NULL, install, NULL) != 0 || pthread_join(thread, NULL) != 0)
+        return fail("pthread failed");
+    if (!installed(SIGUSR1, handler))
+        return fail("handler of a thread not shared");
+
+    /* A child gets a copy: it has the handler, and its changes stay its own */
+    pid = fork();
+    if (pid < 0)
+        return fail("fork failed");
+    if (pid == 0) {
+        if (!installed(SIGUSR1, handler))
+            _exit(1);
+        signal(SIGUSR1, SIG_IGN);
+        _exit(0);
+    }
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return fail("handler not copied on fork");
+    if (!installed(SIGUSR1, handler))
+        return fail("child changed the handler of its parent");
+    return pass();
+}
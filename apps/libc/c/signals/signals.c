@@ -0,0 +1,157 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define TEST_NAME "signals"
+#include "test.h"
+
+#define MAX_RECORDS 16
+
+static volatile int records;
+static volatile int record_sig[MAX_RECORDS];
+static volatile int record_value[MAX_RECORDS];
+
+static void record(int sig, siginfo_t *info, void *ctx)
+{
+    (void)ctx;
+    if (records < MAX_RECORDS) {
+        record_sig[records] = sig;
+        record_value[records] = info->si_code == SI_QUEUE ? info->si_value.sival_int : 0;
+        records++;
+    }
+}
+
+static int install(int sig)
+{
+    struct sigaction sa = { .sa_sigaction = record, .sa_flags = SA_SIGINFO };
+
+    /* One handler at a time, so that the records are in delivery order */
+    sigfillset(&sa.sa_mask);
+    return sigaction(sig, &sa, NULL);
+}
+
+static int queue(int sig, int value)
+{
+    union sigval sv = { .sival_int = value };
+    return sigqueue(getpid(), sig, sv);
+}
+
+static int set_blocked(int how, int sig1, int sig2, int sig3, int sig4)
+{
+    sigset_t set;
+
+    sigemptyset(&set);
+    sigaddset(&set, sig1);
+    sigaddset(&set, sig2);
+    sigaddset(&set, sig3);
+    sigaddset(&set, sig4);
+    return sigprocmask(how, &set, NULL);
+}
+
+/* Blocked signals stay pending, and a standard one sent twice comes once */
+static int check_masking(void)
+{
+    sigset_t set;
+
+    records = 0;
+    sigemptyset(&set);
+    sigaddset(&set, SIGUSR1);
+    if (sigprocmask(SIG_BLOCK, &set, NULL) < 0)
+        return fail("sigprocmask failed");
+    kill(getpid(), SIGUSR1);
+    kill(getpid(), SIGUSR1);
+    if (records != 0)
+        return fail("blocked signal delivered");
+    if (sigprocmask(SIG_UNBLOCK, &set, NULL) < 0)
+        return fail("sigprocmask failed");
+    if (records != 1 || record_sig[0] != SIGUSR1)
+        return fail("unblocked signal delivered %d times", records);
+    return 0;
+}
+
+/* Lower numbers come first, and real-time signals keep their sending order */
+static int check_order(void)
+{
+    static const int expected_value[] = { 0, 0, 1, 2, 0 };
+    int expected_sig[] = { SIGUSR1, SIGUSR2, SIGRTMIN, SIGRTMIN, SIGRTMIN + 1 };
+
+    records = 0;
+    if (set_blocked(SIG_BLOCK, SIGUSR1, SIGUSR2, SIGRTMIN, SIGRTMIN + 1) < 0)
+        return fail("sigprocmask failed");
+    kill(getpid(), SIGRTMIN + 1);
+    kill(getpid(), SIGUSR2);
+    queue(SIGRTMIN, 1);
+    kill(getpid(), SIGUSR1);
+    queue(SIGRTMIN, 2);
+    if (set_blocked(SIG_UNBLOCK, SIGUSR1, SIGUSR2, SIGRTMIN, SIGRTMIN + 1) < 0)
+        return fail("sigprocmask failed");
+    if (records != 5)
+        return fail("%d signals delivered instead of 5", records);
+    for (int i = 0; i < 5; i++) {
+        if (record_sig[i] != expected_sig[i] || record_value[i] != expected_value[i])
+            return fail("signal %d delivered out of order", i);
+    }
+    return 0;
+}
+
+/* SIGKILL and SIGSTOP can't be caught, ignored nor blocked */
+static int check_unblockable(void)
+{
+    struct sigaction sa = { .sa_handler = SIG_IGN };
+    sigset_t set, old;
+    int pipefd[2], status;
+    char c;
+    pid_t pid;
+
+    if (sigaction(SIGKILL, &sa, NULL) != -1 || errno != EINVAL)
+        return fail("ignoring SIGKILL did not fail with EINVAL");
+    if (sigaction(SIGSTOP, &sa, NULL) != -1 || errno != EINVAL)
+        return fail("ignoring SIGSTOP did not fail with EINVAL");
+
+    sigemptyset(&set);
+    sigaddset(&set, SIGKILL);
+    sigaddset(&set, SIGSTOP);
+    if (sigprocmask(SIG_BLOCK, &set, &old) < 0 || sigprocmask(SIG_SETMASK, &old, &set) < 0)
+        return fail("sigprocmask failed");
+    if (sigismember(&set, SIGKILL) || sigismember(&set, SIGSTOP))
+        return fail("SIGKILL or SIGSTOP blocked");
+
+    /* A child blocking everything is still stopped and killed */
+    if (pipe(pipefd) < 0)
+        return fail("pipe failed");
+    pid = fork();
+    if (pid < 0)
+        return fail("fork failed");
+    if (pid == 0) {
+        sigfillset(&set);
+        sigprocmask(SIG_SETMASK, &set, NULL);
+        write(pipefd[1], "x", 1);
+        for (;;)
+            sleep(1);
+    }
+    if (read(pipefd[0], &c, 1) != 1)
+        return fail("child did not start");
+    kill(pid, SIGSTOP);
+    if (waitpid(pid, &status, WUNTRACED) != pid || !WIFSTOPPED(status)
+        || WSTOPSIG(status) != SIGSTOP)
+        return fail("blocked SIGSTOP did not stop the child");
+    kill(pid, SIGKILL);
+    if (waitpid(pid, &status, 0) != pid || !WIFSIGNALED(status) || WTERMSIG(status) != SIGKILL)
+        return fail("blocked SIGKILL did not kill the child");
+    close(pipefd[0]);
+    close(pipefd[1]);
+    return 0;
+}
+
+int main(void)
+{
+    if (install(SIGUSR1) < 0 || install(SIGUSR2) < 0 || install(SIGRTMIN) < 0
+        || install(SIGRTMIN + 1) < 0)
+        return fail("sigaction failed");
+    if (check_masking() || check_order() || check_unblockable())
+        return 1;
+    return pass();
+}
@@ -0,0 +1,82 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/swap.h>
+#include <unistd.h>
+
+#define TEST_NAME "swap"
+#include "test.h"
+
+#define SWAP_FILE "swap_test.img"
+#define SWAP_KB 1024
+
+static long meminfo_kb(const char *name)
+{
+    char line[128], key[32];
+    long kb = -1, value;
+    FILE *f = fopen("/proc/meminfo", "r");
+    if (!f)
+        return -1;
+    while (fgets(line, sizeof(line), f)) {
+        if (sscanf(line, "%31[^:]: %ld kB", key, &value) == 2 && strcmp(key, name) == 0) {
+            kb = value;
+            break;
+        }
+    }
+    fclose(f);
+    return kb;
+}
+
+/* Fail, without leaving the swap file behind */
+static int fail_unlink(const char *what)
+{
+    unlink(SWAP_FILE);
+    return fail("%s", what);
+}
+
+int main(void)
+{
+    char block[4096];
+    char *p;
+    int fd;
+
+    /* A swap file is a regular file, used whole */
+    fd = open(SWAP_FILE, O_CREAT | O_TRUNC | O_WRONLY, 0600);
+    if (fd < 0)
+        return fail_unlink("cannot create the swap file");
+    memset(block, 0, sizeof(block));
+    for (int i = 0; i < SWAP_KB / 4; i++) {
+        if (write(fd, block, sizeof(block)) != sizeof(block))
+            return fail_unlink("cannot fill the swap file");
+    }
+    close(fd);
+
+    if (swapon(SWAP_FILE, 0) < 0)
+        return fail_unlink("swapon failed");
+    if (meminfo_kb("SwapTotal") != SWAP_KB || meminfo_kb("SwapFree") != SWAP_KB)
+        return fail_unlink("swap not reported in /proc/meminfo");
+    if (swapon(SWAP_FILE, 0) == 0)
+        return fail_unlink("second swapon succeeded");
+
+    /* Locked pages stay readable and writable */
+    p = mmap(NULL, 4 * 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED)
+        return fail_unlink("mmap failed");
+    memset(p, 0x5a, 4 * 4096);
+    if (mlock(p, 4 * 4096) < 0 || munlock(p, 4 * 4096) < 0)
+        return fail_unlink("mlock failed");
+    for (int i = 0; i < 4 * 4096; i++) {
+        if (p[i] != 0x5a)
+            return fail_unlink("memory changed");
+    }
+    munmap(p, 4 * 4096);
+
+    if (swapoff(SWAP_FILE) < 0)
+        return fail_unlink("swapoff failed");
+    if (meminfo_kb("SwapTotal") != 0)
+        return fail_unlink("swap still reported after swapoff");
+    unlink(SWAP_FILE);
+
+    return pass();
+}
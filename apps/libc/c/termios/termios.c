@@ -0,0 +1,170 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <sys/stat.h>
+#include <sys/sysmacros.h>
+#include <sys/wait.h>
+#include <termios.h>
+#include <unistd.h>
+
+#define TEST_NAME "termios"
+#include "test.h"
+
+/* The settings are given back as they were set, in the layout of Linux */
+static int check_settings(void)
+{
+    struct termios saved, t;
+
+    if (!isatty(0) || tcgetattr(0, &saved) < 0)
+        return fail("the console is not a terminal");
+    if (!(saved.c_lflag & ICANON) || !(saved.c_lflag & ISIG))
+        return fail("the console does not start in canonical mode with signals");
+
+    t = saved;
+    t.c_lflag &= ~(ECHO | ICANON);
+    t.c_cc[VMIN] = 0;
+    t.c_cc[VTIME] = 1;
+    cfsetispeed(&t, B9600);
+    cfsetospeed(&t, B9600);
+    if (tcsetattr(0, TCSANOW, &t) < 0)
+        return fail("tcsetattr failed: %s", strerror(errno));
+    memset(&t, 0, sizeof(t));
+    if (tcgetattr(0, &t) < 0 || (t.c_lflag & (ECHO | ICANON)) || t.c_cc[VMIN] != 0 ||
+        t.c_cc[VTIME] != 1)
+        return fail("the settings read back are not those set");
+    if (cfgetispeed(&t) != B9600 || cfgetospeed(&t) != B9600)
+        return fail("the speed read back is not the one set");
+
+    /* Nothing typed: a raw read gives up after VTIME */
+    if (tcflush(0, TCIFLUSH) < 0)
+        return fail("tcflush failed: %s", strerror(errno));
+    if (read(0, &t, 1) != 0)
+        return fail("a raw read with VMIN 0 did not time out");
+
+    if (tcsetattr(0, TCSAFLUSH, &saved) < 0)
+        return fail("cannot restore the settings");
+    if (tcflow(0, 42) == 0 || errno != EINVAL)
+        return fail("tcflow took an unknown action");
+    return 0;
+}
+
+static int check_winsize(void)
+{
+    struct winsize saved, ws = {.ws_row = 24, .ws_col = 80};
+
+    if (ioctl(0, TIOCGWINSZ, &saved) < 0)
+        return fail("TIOCGWINSZ failed: %s", strerror(errno));
+    if (ioctl(0, TIOCSWINSZ, &ws) < 0)
+        return fail("TIOCSWINSZ failed: %s", strerror(errno));
+    memset(&ws, 0, sizeof(ws));
+    if (ioctl(0, TIOCGWINSZ, &ws) < 0 || ws.ws_row != 24 || ws.ws_col != 80)
+        return fail("the window size read back is %ux%u", ws.ws_col, ws.ws_row);
+    ioctl(0, TIOCSWINSZ, &saved);
+    return 0;
+}
+
+/* A process outside the session of the terminal has no /dev/tty */
+static int outsider(void)
+{
+    pid_t pgrp;
+
+    if (setsid() < 0)
+        return 1;
+    if (open("/dev/tty", O_RDWR) >= 0 || errno != ENXIO)
+        return 2;
+    if (ioctl(0, TIOCGPGRP, &pgrp) == 0 || errno != ENOTTY)
+        return 3;
+    return 0;
+}
+
+/* Each testcase leads a session, which the console is the terminal of */
+static int check_session(void)
+{
+    struct termios via_tty, via_stdin;
+    struct stat st;
+    int status, fd, ready[2];
+    pid_t pid, sid;
+
+    if (getsid(0) != getpid() || tcgetpgrp(0) != getpid())
+        return fail("the testcase is not the foreground of the session of the console");
+    if (ioctl(0, TIOCGSID, &sid) < 0 || sid != getpid())
+        return fail("TIOCGSID does not give the session of the testcase");
+
+    fd = open("/dev/tty", O_RDWR);
+    if (fd < 0)
+        return fail("cannot open /dev/tty: %s", strerror(errno));
+    if (tcgetattr(fd, &via_tty) < 0 || tcgetattr(0, &via_stdin) < 0 ||
+        via_tty.c_lflag != via_stdin.c_lflag || via_tty.c_cflag != via_stdin.c_cflag)
+        return fail("/dev/tty is not the console");
+    close(fd);
+    if (stat("/dev/console", &st) < 0 || !S_ISCHR(st.st_mode) || major(st.st_rdev) != 5 ||
+        minor(st.st_rdev) != 1)
+        return fail("/dev/console is not the device 5:1");
+    fd = open("/dev/stdout", O_WRONLY);
+    if (fd < 0 || write(fd, "", 0) != 0)
+        return fail("cannot write /dev/stdout");
+    close(fd);
+
+    /* Another group of the session can be put in the foreground, and the
+     * testcase put back from the background as long as it ignores SIGTTOU */
+    signal(SIGTTOU, SIG_IGN);
+    pid = fork();
+    if (pid == 0) {
+        setpgid(0, 0);
+        pause();
+        _exit(0);
+    }
+    setpgid(pid, pid);
+    if (tcsetpgrp(0, pid) < 0 || tcgetpgrp(0) != pid)
+        return fail("cannot put another group in the foreground");
+    if (tcsetpgrp(0, getpid()) < 0)
+        return fail("cannot put the testcase back in the foreground");
+    signal(SIGTTOU, SIG_DFL);
+    kill(pid, SIGKILL);
+    waitpid(pid, &status, 0);
+
+    /* But not a group of another session */
+    if (pipe(ready) < 0)
+        return fail("pipe failed");
+    pid = fork();
+    if (pid == 0) {
+        setsid();
+        write(ready[1], "", 1);
+        pause();
+        _exit(0);
+    }
+    read(ready[0], &sid, 1);
+    if (tcsetpgrp(0, pid) == 0 || errno != EPERM)
+        return fail("a group of another session was put in the foreground");
+    kill(pid, SIGKILL);
+    waitpid(pid, &status, 0);
+    close(ready[0]);
+    close(ready[1]);
+
+    pid = fork();
+    if (pid == 0)
+        _exit(outsider());
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status))
+        return fail("the process outside the session was killed");
+    switch (WEXITSTATUS(status)) {
+    case 0:
+        return 0;
+    case 1:
+        return fail("setsid failed");
+    case 2:
+        return fail("a process outside the session could open /dev/tty");
+    default:
+        return fail("a process outside the session could get the foreground group");
+    }
+}
+
+int main(void)
+{
+    if (check_settings() || check_winsize() || check_session())
+        return 1;
+    return pass();
+}
@@ -0,0 +1,39 @@
+// Helpers shared by the testcases.
+//
+// A testcase defines TEST_NAME before including this file, and prints one
+// line, which expect_off.out matches:
+//
+//     <name>: ok
+//
+// or, at the first check that fails:
+//
+//     <name>: <what failed>
+#ifndef TEST_H
+#define TEST_H
+
+#include <stdarg.h>
+#include <stdio.h>
+
+#ifndef TEST_NAME
+#error "TEST_NAME must be defined before including test.h"
+#endif
+
+__attribute__((format(printf, 1, 2))) static inline int fail(const char *fmt, ...)
+{
+    va_list args;
+
+    printf("%s: ", TEST_NAME);
+    va_start(args, fmt);
+    vprintf(fmt, args);
+    va_end(args);
+    printf("\n");
+    return 1;
+}
+
+static inline int pass(void)
+{
+    printf("%s: ok\n", TEST_NAME);
+    return 0;
+}
+
+#endif
@@ -0,0 +1,84 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <sched.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+#define TEST_NAME "timens"
+#include "test.h"
+
+#ifndef CLONE_NEWTIME
+#define CLONE_NEWTIME 0x80
+#endif
+
+#define SHIFT 1000
+
+static int set_offset(const char *line)
+{
+    int fd = open("/proc/self/timens_offsets", O_WRONLY);
+    int ret;
+
+    if (fd < 0)
+        return -1;
+    ret = write(fd, line, strlen(line)) < 0 ? -1 : 0;
+    close(fd);
+    return ret;
+}
+
+/* In the new namespace, the clock and absolute deadlines are shifted */
+static int child(time_t parent_now)
+{
+    struct timespec now, start, end;
+
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    if (now.tv_sec < parent_now + SHIFT || now.tv_sec > parent_now + SHIFT + 10)
+        return fail("CLOCK_MONOTONIC not shifted in the child");
+    clock_gettime(CLOCK_REALTIME, &start);
+    now.tv_nsec += 10000000;
+    if (now.tv_nsec >= 1000000000) {
+        now.tv_sec++;
+        now.tv_nsec -= 1000000000;
+    }
+    if (clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME, &now, NULL) != 0)
+        return fail("absolute clock_nanosleep failed");
+    clock_gettime(CLOCK_REALTIME, &end);
+    if (end.tv_sec - start.tv_sec > 2)
+        return fail("absolute deadline not shifted");
+    return 0;
+}
+
+int main(void)
+{
+    struct timespec before, after;
+    int status;
+    pid_t pid;
+
+    clock_gettime(CLOCK_MONOTONIC, &before);
+    if (unshare(CLONE_NEWTIME) < 0)
+        return fail("unshare failed");
+    if (set_offset("monotonic 1000 0\n") < 0)
+        return fail("setting the offset failed");
+
+    /* The caller stays in its namespace */
+    clock_gettime(CLOCK_MONOTONIC, &after);
+    if (after.tv_sec > before.tv_sec + 10)
+        return fail("CLOCK_MONOTONIC of the caller shifted");
+
+    pid = fork();
+    if (pid < 0)
+        return fail("fork failed");
+    if (pid == 0)
+        _exit(child(after.tv_sec));
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0)
+        return fail("child failed");
+
+    /* Once a process entered the namespace, its offsets are fixed */
+    if (set_offset("monotonic 0 0\n") != -1 || errno != EACCES)
+        return fail("setting the offset of a used namespace did not fail with EACCES");
+
+    return pass();
+}
@@ -0,0 +1,62 @@
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#define TEST_NAME "vma_maps"
+#include "test.h"
+
+#define PAGE 4096
+
+/* The permissions of the area of /proc/self/maps spanning [start, end) exactly */
+static int find_area(unsigned long start, unsigned long end, char *perms)
+{
+    char line[256];
+    unsigned long s, e;
+    int found = 0;
+    FILE *f = fopen("/proc/self/maps", "r");
+    if (!f)
+        return 0;
+    while (fgets(line, sizeof(line), f)) {
+        if (sscanf(line, "%lx-%lx %4s", &s, &e, perms) == 3 && s == start && e == end) {
+            found = 1;
+            break;
+        }
+    }
+    fclose(f);
+    return found;
+}
+
+int main(void)
+{
+    char perms[8];
+    unsigned long base;
+    char *p = mmap(NULL, 4 * PAGE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (p == MAP_FAILED)
+        return fail("mmap failed");
+    base = (unsigned long)p;
+    if (!find_area(base, base + 4 * PAGE, perms) || strcmp(perms, "rw-p") != 0)
+        return fail("new mapping not listed");
+
+    /* Unmapping a page in the middle splits the area */
+    if (munmap(p + PAGE, PAGE) < 0)
+        return fail("munmap failed");
+    if (!find_area(base, base + PAGE, perms) || !find_area(base + 2 * PAGE, base + 4 * PAGE, perms))
+        return fail("area not split by munmap");
+
+    /* Reprotecting part of an area splits it, and undoing it merges it back */
+    if (mprotect(p + 3 * PAGE, PAGE, PROT_READ) < 0)
+        return fail("mprotect failed");
+    if (!find_area(base + 3 * PAGE, base + 4 * PAGE, perms) || strcmp(perms, "r--p") != 0)
+        return fail("area not split by mprotect");
+    if (mprotect(p + 3 * PAGE, PAGE, PROT_READ | PROT_WRITE) < 0)
+        return fail("mprotect failed");
+    if (!find_area(base + 2 * PAGE, base + 4 * PAGE, perms) || strcmp(perms, "rw-p") != 0)
+        return fail("areas not merged back");
+
+    munmap(p, 4 * PAGE);
+    if (find_area(base, base + PAGE, perms))
+        return fail("unmapped area still listed");
+
+    return pass();
+}
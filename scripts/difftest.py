@@ -0,0 +1,56 @@
+#!/usr/bin/env python3
+"""Compare the results of the syscall scripts of apps/difftest.
+
+Usage: difftest.py <linux output> <starry output>
+
+Both outputs are searched for the `DIFF <script> ...` lines printed by the
+runner, so the starry one may be the whole log of a run. Every line of a
+script which differs between the two is printed, as well as the scripts
+which didn't run to their end. The exit status is 1 if anything differs.
+"""
+import re
+import sys
+
+LINE = re.compile(r"DIFF (\S+) (.*)$")
+
+
+def results(path):
+    """The lines of every script in the output at `path`, by script."""
+    scripts = {}
+    with open(path, errors="replace") as f:
+        for line in f:
+            m = LINE.search(line.rstrip("\r\n"))
+            if m:
+                scripts.setdefault(m.group(1), []).append(m.group(2))
+    return scripts
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__.strip())
+    linux, starry = results(sys.argv[1]), results(sys.argv[2])
+    diverged = 0
+    for script, expected in sorted(linux.items()):
+        actual = starry.get(script)
+        if actual is None:
+            print(f"{script}: missing")
+            diverged += 1
+            continue
+        lines = 0
+        for i in range(max(len(expected), len(actual))):
+            want = expected[i] if i < len(expected) else "<nothing>"
+            got = actual[i] if i < len(actual) else "<nothing>"
+            if want != got:
+                print(f"{script}: linux   {want}")
+                print(f"{script}: starry  {got}")
+                lines += 1
+        if lines:
+            diverged += 1
+        print(f"{script}: {'diverged on %d lines' % lines if lines else 'ok'}")
+    for script in sorted(starry.keys() - linux.keys()):
+        print(f"{script}: not run on linux")
+    sys.exit(1 if diverged else 0)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,57 @@
+#!/usr/bin/env python3
+"""Append the hash tree checked by src/fs/verity.rs to an image.
+
+Usage: verity.py <image> [<output>]
+
+The image is padded to whole blocks and followed by its hash tree and a
+superblock, in <output> or in place. The root hash to give to the kernel is
+printed on stdout.
+"""
+import hashlib
+import os
+import struct
+import sys
+
+BLOCK_SIZE = 4096
+DIGEST_SIZE = 32
+MAGIC = b"VERITYLT"
+VERSION = 1
+
+
+def block_hash(salt, block):
+    return hashlib.sha256(salt + block).digest()
+
+
+def hash_level(salt, blocks):
+    """The blocks of the level above `blocks`."""
+    digests = b"".join(block_hash(salt, block) for block in blocks)
+    level = [digests[i:i + BLOCK_SIZE] for i in range(0, len(digests), BLOCK_SIZE)]
+    return [block.ljust(BLOCK_SIZE, b"\0") for block in level]
+
+
+def main():
+    if len(sys.argv) not in (2, 3):
+        sys.exit(__doc__.strip())
+    with open(sys.argv[1], "rb") as f:
+        data = f.read()
+    if len(data) % BLOCK_SIZE:
+        data += b"\0" * (BLOCK_SIZE - len(data) % BLOCK_SIZE)
+    salt = os.urandom(32)
+
+    blocks = [data[i:i + BLOCK_SIZE] for i in range(0, len(data), BLOCK_SIZE)]
+    levels = [hash_level(salt, blocks or [b"\0" * BLOCK_SIZE])]
+    while len(levels[-1]) > 1:
+        levels.append(hash_level(salt, levels[-1]))
+    root = block_hash(salt, levels[-1][0])
+
+    superblock = MAGIC + struct.pack("<IIQ", VERSION, BLOCK_SIZE, len(blocks)) + salt
+    with open(sys.argv[-1], "wb") as f:
+        f.write(data)
+        for level in reversed(levels):
+            f.write(b"".join(level))
+        f.write(superblock.ljust(BLOCK_SIZE, b"\0"))
+    print(root.hex())
+
+
+if __name__ == "__main__":
+    main()
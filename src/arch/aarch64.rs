@@ -0,0 +1,61 @@
+use super::TrapFrameExt;
+use axhal::arch::TrapFrame;
+
+/// The length of `svc`.
+const SYSCALL_INSN_LEN: usize = 4;
+
+impl TrapFrameExt for TrapFrame {
+    fn arg0(&self) -> usize {
+        self.r[0] as _
+    }
+
+    fn set_return_value(&mut self, value: usize) {
+        self.r[0] = value as _;
+    }
+
+    fn ip(&self) -> usize {
+        self.elr as _
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.elr = ip as _;
+    }
+
+    fn sp(&self) -> usize {
+        self.usp as _
+    }
+
+    fn set_sp(&mut self, sp: usize) {
+        self.usp = sp as _;
+    }
+
+    /// `svc` saves the address of the next instruction in `ELR_EL1`, so
+    /// there is nothing to skip.
+    fn advance_pc(&mut self) {}
+
+    fn restart_syscall(&mut self, _syscall_num: usize, arg0: usize) {
+        self.elr -= SYSCALL_INSN_LEN as u64;
+        self.r[0] = arg0 as _;
+    }
+}
+
+/// `HWCAP_FP`: floating point
+const HWCAP_FP: usize = 1 << 0;
+/// `HWCAP_ASIMD`: Advanced SIMD
+const HWCAP_ASIMD: usize = 1 << 1;
+
+/// The CPU features in `AT_HWCAP`, for the fields of `ID_AA64PFR0_EL1`
+/// which are not 0xf, meaning not implemented.
+pub fn hwcap() -> usize {
+    let pfr0: u64;
+    unsafe { core::arch::asm!("mrs {}, ID_AA64PFR0_EL1", out(reg) pfr0) };
+    let implemented = |shift: u32| (pfr0 >> shift) & 0xf != 0xf;
+    let mut hwcap = 0;
+    if implemented(16) {
+        hwcap |= HWCAP_FP;
+    }
+    if implemented(20) {
+        hwcap |= HWCAP_ASIMD;
+    }
+    hwcap
+}
@@ -0,0 +1,40 @@
+//! Architecture-specific access to the user registers saved in a trap frame,
+//! and to the CPU features published to user space.
+//!
+//! Signal delivery and `clone` rewrite the trap frame a task returns to user
+//! space with, and go through [`TrapFrameExt`] to do so on any architecture.
+/// Reading and writing the user registers of an `axhal` trap frame.
+pub trait TrapFrameExt {
+    /// The first argument of the syscall the frame was saved at.
+    fn arg0(&self) -> usize;
+    /// Set the value returned to user space, e.g. by a syscall.
+    fn set_return_value(&mut self, value: usize);
+    /// The user program counter.
+    fn ip(&self) -> usize;
+    /// Set the user program counter.
+    fn set_ip(&mut self, ip: usize);
+    /// The user stack pointer.
+    fn sp(&self) -> usize;
+    /// Set the user stack pointer.
+    fn set_sp(&mut self, sp: usize);
+    /// Move past the syscall instruction the frame was saved at, for a copy
+    /// of the frame which doesn't return through the syscall handler.
+    fn advance_pc(&mut self);
+    /// Go back to the syscall instruction the frame was saved at, with the
+    /// registers it was made with, so that returning to user space makes the
+    /// syscall `syscall_num` again.
+    fn restart_syscall(&mut self, syscall_num: usize, arg0: usize);
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        mod x86_64;
+        pub use self::x86_64::hwcap;
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        mod riscv;
+        pub use self::riscv::hwcap;
+    } else if #[cfg(target_arch = "aarch64")] {
+        mod aarch64;
+        pub use self::aarch64::hwcap;
+    }
+}
@@ -0,0 +1,49 @@
+use super::TrapFrameExt;
+use axhal::arch::TrapFrame;
+
+/// The length of `ecall`.
+const SYSCALL_INSN_LEN: usize = 4;
+
+impl TrapFrameExt for TrapFrame {
+    fn arg0(&self) -> usize {
+        self.regs.a0
+    }
+
+    fn set_return_value(&mut self, value: usize) {
+        self.regs.a0 = value;
+    }
+
+    fn ip(&self) -> usize {
+        self.sepc
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.sepc = ip;
+    }
+
+    fn sp(&self) -> usize {
+        self.regs.sp
+    }
+
+    fn set_sp(&mut self, sp: usize) {
+        self.regs.sp = sp;
+    }
+
+    fn advance_pc(&mut self) {
+        self.sepc += SYSCALL_INSN_LEN;
+    }
+
+    fn restart_syscall(&mut self, _syscall_num: usize, arg0: usize) {
+        self.sepc -= SYSCALL_INSN_LEN;
+        self.regs.a0 = arg0;
+    }
+}
+
+/// The CPU features in `AT_HWCAP`: a bit per single-letter extension, from
+/// bit 0 for `A`. `misa` can't be read in S-mode, so these are the ones of
+/// RV64GC, which user space is built for.
+pub fn hwcap() -> usize {
+    b"imafdc"
+        .iter()
+        .fold(0, |hwcap, &ext| hwcap | (1 << (ext - b'a')))
+}
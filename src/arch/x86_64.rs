@@ -0,0 +1,47 @@
+use super::TrapFrameExt;
+use axhal::arch::TrapFrame;
+
+/// The length of `syscall`.
+const SYSCALL_INSN_LEN: usize = 2;
+
+impl TrapFrameExt for TrapFrame {
+    fn arg0(&self) -> usize {
+        self.rdi as _
+    }
+
+    fn set_return_value(&mut self, value: usize) {
+        self.rax = value as _;
+    }
+
+    fn ip(&self) -> usize {
+        self.rip as _
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.rip = ip as _;
+    }
+
+    fn sp(&self) -> usize {
+        self.rsp as _
+    }
+
+    fn set_sp(&mut self, sp: usize) {
+        self.rsp = sp as _;
+    }
+
+    /// `syscall` saves the address of the next instruction, so there is
+    /// nothing to skip.
+    fn advance_pc(&mut self) {}
+
+    /// The return value went to `rax`, which held the syscall number.
+    fn restart_syscall(&mut self, syscall_num: usize, _arg0: usize) {
+        self.rip -= SYSCALL_INSN_LEN as u64;
+        self.rax = syscall_num as _;
+    }
+}
+
+/// The CPU features in `AT_HWCAP`: the feature flags of CPUID leaf 1 in
+/// `EDX`, as in Linux.
+pub fn hwcap() -> usize {
+    unsafe { core::arch::x86_64::__cpuid(1) }.edx as usize
+}
@@ -0,0 +1,36 @@
+//! Timing of the testcases, for the benchmarks under `apps/bench`.
+//!
+//! With the `bench` feature, every testcase is followed on the console by a
+//! line which is easy to pick out of the output, whatever the log level:
+//!
+//! ```text
+//! BENCH_RUN testcase=pipe_c exit=0 ns=123456789
+//! ```
+//!
+//! The benchmarks themselves report their rates in `BENCH` lines, see
+//! `apps/bench/c/bench.h`.
+use axstd::println;
+
+/// A testcase being timed.
+pub struct BenchRun {
+    name: &'static str,
+    start: u64,
+}
+
+impl BenchRun {
+    pub fn start(name: &'static str) -> Self {
+        Self {
+            name,
+            start: axhal::time::monotonic_time_nanos(),
+        }
+    }
+
+    /// Report the testcase, which exited with `exit_code`.
+    pub fn finish(self, exit_code: i32) {
+        let ns = axhal::time::monotonic_time_nanos() - self.start;
+        println!(
+            "BENCH_RUN testcase={} exit={} ns={}",
+            self.name, exit_code, ns
+        );
+    }
+}
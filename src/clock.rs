@@ -0,0 +1,102 @@
+//! The realtime clock: `CLOCK_REALTIME`, which root can set and slew.
+//!
+//! It is kept as an offset from the wall time of `axhal`, itself the
+//! monotonic time plus the time of the RTC at boot, so setting it never
+//! touches the monotonic clocks. `adjtime` asks for a correction to be
+//! slewed in instead: the clock runs faster or slower by [`SLEW_PPM`] until
+//! it is, as in Linux, so that the time never jumps back.
+use crate::sync::SeqLock;
+use core::time::Duration;
+
+/// How fast a correction is slewed in, in nanoseconds per millisecond.
+pub const SLEW_PPM: u64 = 500;
+
+#[derive(Clone, Copy, Default)]
+struct Realtime {
+    /// The realtime minus the wall time, in nanoseconds
+    offset: i64,
+    /// The correction not slewed in yet, in nanoseconds
+    pending: i64,
+    /// The monotonic time `pending` was left at, in nanoseconds
+    since: u64,
+}
+
+impl Realtime {
+    /// The part of `pending` slewed in by the monotonic time `now`.
+    fn slewed(&self, now: u64) -> i64 {
+        let max = (now.saturating_sub(self.since) / 1_000_000 * SLEW_PPM).min(i64::MAX as u64);
+        self.pending.clamp(-(max as i64), max as i64)
+    }
+
+    /// Move what has been slewed in by `now` from `pending` to `offset`.
+    fn settle(&mut self, now: u64) {
+        let slewed = self.slewed(now);
+        self.offset += slewed;
+        self.pending -= slewed;
+        self.since = now;
+    }
+}
+
+static REALTIME: SeqLock<Realtime> = SeqLock::new(Realtime {
+    offset: 0,
+    pending: 0,
+    since: 0,
+});
+
+/// The realtime minus the monotonic time `now`, in nanoseconds.
+fn offset_at(now: u64) -> i64 {
+    let wall = axhal::time::wall_time_nanos() as i64 - axhal::time::monotonic_time_nanos() as i64;
+    let realtime = REALTIME.read();
+    wall + realtime.offset + realtime.slewed(now)
+}
+
+/// The realtime minus the monotonic time, in nanoseconds, as it is now.
+pub fn offset_nanos() -> i64 {
+    offset_at(axhal::time::monotonic_time_nanos())
+}
+
+/// The time of `CLOCK_REALTIME`, since the epoch. A time set before the
+/// epoch reads as the epoch.
+pub fn realtime() -> Duration {
+    let now = axhal::time::monotonic_time_nanos();
+    Duration::from_nanos((now as i64 + offset_at(now)).max(0) as u64)
+}
+
+/// Set `CLOCK_REALTIME` to `time`, dropping the correction being slewed in.
+pub fn set_realtime(time: Duration) {
+    let now = axhal::time::monotonic_time_nanos();
+    let wall = axhal::time::wall_time_nanos() as i64;
+    let time = time.as_nanos().min(i64::MAX as u128) as i64;
+    REALTIME.write(|realtime| {
+        realtime.offset = time.saturating_sub(wall);
+        realtime.pending = 0;
+        realtime.since = now;
+    });
+    crate::mm::vdso::realtime_changed();
+}
+
+/// Step `CLOCK_REALTIME` by `delta` nanoseconds.
+pub fn step_realtime(delta: i64) {
+    let now = axhal::time::monotonic_time_nanos();
+    REALTIME.write(|realtime| {
+        realtime.settle(now);
+        realtime.offset = realtime.offset.saturating_add(delta);
+    });
+    crate::mm::vdso::realtime_changed();
+}
+
+/// Start slewing in a correction of `delta` nanoseconds, in place of the one
+/// left, if `delta` isn't `None`.
+///
+/// Returns the correction which was left, in nanoseconds.
+pub fn adjust(delta: Option<i64>) -> i64 {
+    let now = axhal::time::monotonic_time_nanos();
+    let Some(delta) = delta else {
+        let realtime = REALTIME.read();
+        return realtime.pending - realtime.slewed(now);
+    };
+    REALTIME.write(|realtime| {
+        realtime.settle(now);
+        core::mem::replace(&mut realtime.pending, delta)
+    })
+}
@@ -0,0 +1,145 @@
+//! Buffered console output.
+//!
+//! User writes to the console are queued into a ring buffer and drained to the
+//! UART by a dedicated writer task, so a process printing heavily only blocks
+//! itself when the ring is full instead of spinning on every character.
+//!
+//! The HAL gives no TX interrupt (the console of riscv64 is the SBI one), so
+//! the writer task polls the UART a chunk at a time, and lets the other tasks
+//! run between chunks.
+//!
+//! The kernel logs go straight to the UART. While it logs more than warnings,
+//! user output is written synchronously too, so that both stay in order.
+use alloc::vec;
+use alloc::vec::Vec;
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The capacity of the TX ring in bytes.
+const TX_RING_SIZE: usize = 16 * 1024;
+/// The largest chunk handed to the UART at once.
+const TX_CHUNK_SIZE: usize = 256;
+
+struct TxRing {
+    buf: Vec<u8>,
+    head: usize,
+    len: usize,
+}
+
+impl TxRing {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; TX_RING_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Append as much of `data` as fits, returning the number of bytes queued.
+    fn push(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(TX_RING_SIZE - self.len);
+        for (i, &byte) in data[..count].iter().enumerate() {
+            self.buf[(self.head + self.len + i) % TX_RING_SIZE] = byte;
+        }
+        self.len += count;
+        count
+    }
+
+    /// Take up to `max` bytes from the front of the ring.
+    fn pop(&mut self, out: &mut [u8]) -> usize {
+        let count = out.len().min(self.len);
+        for (i, byte) in out[..count].iter_mut().enumerate() {
+            *byte = self.buf[(self.head + i) % TX_RING_SIZE];
+        }
+        self.head = (self.head + count) % TX_RING_SIZE;
+        self.len -= count;
+        count
+    }
+}
+
+static TX_RING: Mutex<Option<TxRing>> = Mutex::new(None);
+/// Woken when data is queued, waited on by the writer task.
+static TX_NOT_EMPTY: WaitQueue = WaitQueue::new();
+/// Woken when the writer task frees space, waited on by blocked writers.
+static TX_NOT_FULL: WaitQueue = WaitQueue::new();
+static INITED: AtomicBool = AtomicBool::new(false);
+/// Output suspended by `tcflow(TCOOFF)`.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+fn tx_len() -> usize {
+    TX_RING.lock().as_ref().map_or(0, |ring| ring.len)
+}
+
+fn writer_task() {
+    let mut chunk = [0u8; TX_CHUNK_SIZE];
+    loop {
+        TX_NOT_EMPTY.wait_until(|| tx_len() > 0 && !STOPPED.load(Ordering::Acquire));
+        let count = TX_RING.lock().as_mut().unwrap().pop(&mut chunk);
+        axhal::console::write_bytes(&chunk[..count]);
+        #[cfg(feature = "display")]
+        if crate::sysctl::FBCON.get() != 0 {
+            crate::drivers::fb::console_write(&chunk[..count]);
+        }
+        TX_NOT_FULL.notify_all(false);
+        axtask::yield_now();
+    }
+}
+
+/// Set up the TX ring and spawn the writer task.
+pub fn init() {
+    *TX_RING.lock() = Some(TxRing::new());
+    axtask::spawn(writer_task);
+    INITED.store(true, Ordering::Release);
+}
+
+/// Queue `data` for output, blocking the caller while the ring is full.
+///
+/// Falls back to writing synchronously before [`init`] is called, and after
+/// what is queued while the kernel logs more than warnings.
+pub fn write(data: &[u8]) -> usize {
+    if !INITED.load(Ordering::Acquire) {
+        axhal::console::write_bytes(data);
+        return data.len();
+    }
+    if log::max_level() > log::LevelFilter::Warn && !STOPPED.load(Ordering::Acquire) {
+        flush();
+        axhal::console::write_bytes(data);
+        return data.len();
+    }
+    let mut written = 0;
+    while written < data.len() {
+        let count = TX_RING.lock().as_mut().unwrap().push(&data[written..]);
+        written += count;
+        TX_NOT_EMPTY.notify_one(false);
+        if written < data.len() {
+            TX_NOT_FULL.wait_until(|| tx_len() < TX_RING_SIZE);
+        }
+    }
+    data.len()
+}
+
+/// Block until everything queued so far has been handed to the UART.
+pub fn flush() {
+    if INITED.load(Ordering::Acquire) {
+        TX_NOT_EMPTY.notify_one(false);
+        TX_NOT_FULL.wait_until(|| tx_len() == 0);
+    }
+}
+
+/// Drop the output which is queued but not yet handed to the UART.
+pub fn discard() {
+    if let Some(ring) = TX_RING.lock().as_mut() {
+        ring.head = 0;
+        ring.len = 0;
+    }
+    TX_NOT_FULL.notify_all(false);
+}
+
+/// Suspend or restart the output, for `tcflow`.
+pub fn set_stopped(stopped: bool) {
+    STOPPED.store(stopped, Ordering::Release);
+    if !stopped {
+        TX_NOT_EMPTY.notify_one(false);
+    }
+}
@@ -0,0 +1,19 @@
+//! Cryptographic primitives used inside the kernel.
+//!
+//! Only what the kernel itself needs is implemented: SHA-256 to check the
+//! integrity of filesystem images and to condense entropy, and ChaCha20 as
+//! the generator behind [`crate::random`]. Both are plain software versions,
+//! written for clarity rather than speed.
+pub mod chacha20;
+pub mod sha256;
+
+pub use chacha20::ChaCha20;
+pub use sha256::{sha256, Sha256};
+
+/// Compare two byte strings in time independent of where they differ.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
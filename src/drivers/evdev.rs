@@ -0,0 +1,308 @@
+//! Input devices in the Linux evdev format, as `/dev/input/event<N>`.
+//!
+//! Every virtio-input device found at boot, such as QEMU's keyboard, mouse or
+//! tablet, becomes an event device. Reads return whole `struct input_event`s
+//! and the usual `EVIOCG*` ioctls describe the device, so that libraries like
+//! SDL can use it unmodified.
+//!
+//! There is no interrupt handling for the devices: their event queues are
+//! drained by a kernel task every few milliseconds, which wakes up the
+//! blocked readers, and whenever the event device is read or polled.
+use crate::fs::devfs::{self, makedev, CharDevice};
+use crate::process::signal::wait_interruptible;
+use crate::ptr::{UserPtr, UserSlice};
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api::ctypes::timeval;
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axstd::os::arceos::modules::axinput::{
+    self, AxInputDevice, BaseDriverOps, EventType, InputDriverOps,
+};
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+const INPUT_MAJOR: u32 = 13;
+/// Event devices have minors counted from this one.
+const EVDEV_MINOR_BASE: u32 = 64;
+
+const EV_VERSION: i32 = 0x010001;
+/// The number of event types, `EV_MAX + 1`.
+const EV_CNT: usize = 0x20;
+/// The size of the largest code bitmap, the one of `EV_KEY`.
+const BITMAP_SIZE: usize = 0x300 / 8;
+const EV_SYN: usize = 0x00;
+const EV_ABS: usize = 0x03;
+/// The range reported for absolute axes, the one of QEMU's virtio-tablet.
+const ABS_MAX_VALUE: i32 = 0x7fff;
+/// Events queued beyond this are dropped, oldest first.
+const EVENT_QUEUE_SIZE: usize = 1024;
+/// How often the devices are checked for events.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// The ioctls of type 'E'. Those with a variable length encode it in the size
+// field, so they are matched on their number alone.
+const EVIOCGVERSION: usize = 0x8004_4501;
+const EVIOCGID: usize = 0x8008_4502;
+const EVIOCGRAB: usize = 0x4004_4590;
+const EVIOCGNAME_NR: usize = 0x06;
+const EVIOCGPHYS_NR: usize = 0x07;
+const EVIOCGUNIQ_NR: usize = 0x08;
+const EVIOCGPROP_NR: usize = 0x09;
+const EVIOCGKEY_NR: usize = 0x18;
+const EVIOCGBIT_NR: usize = 0x20;
+const EVIOCGABS_NR: usize = 0x40;
+
+const IOC_READ: usize = 2;
+
+/// `struct input_event` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct InputEvent {
+    time: timeval,
+    type_: u16,
+    code: u16,
+    value: i32,
+}
+
+/// `struct input_id` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+/// `struct input_absinfo` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct InputAbsinfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+/// An input device and the events read from it but not yet by user space.
+pub struct EventDevice {
+    name: String,
+    phys: String,
+    uniq: String,
+    id: InputId,
+    /// The supported codes of each event type, the type of `EV_SYN` being
+    /// the bitmap of the supported types
+    bits: Vec<[u8; BITMAP_SIZE]>,
+    dev: Mutex<AxInputDevice>,
+    events: Mutex<VecDeque<InputEvent>>,
+    /// The length of `events`, which readers wait on without the lock
+    queued: AtomicUsize,
+    /// The readers waiting for events
+    readers: WaitQueue,
+}
+
+impl EventDevice {
+    fn new(mut dev: AxInputDevice) -> Self {
+        let mut bits = alloc::vec![[0; BITMAP_SIZE]; EV_CNT];
+        bits[EV_SYN][0] |= 1 << EV_SYN;
+        for ty in 1..EV_CNT {
+            let Ok(event_type) = EventType::try_from(ty as u8) else {
+                continue;
+            };
+            if dev
+                .get_event_bits(event_type, &mut bits[ty])
+                .unwrap_or(false)
+            {
+                bits[EV_SYN][ty / 8] |= 1 << (ty % 8);
+            }
+        }
+        let device_id = dev.device_id();
+        Self {
+            name: String::from(dev.device_name()),
+            phys: String::from(dev.physical_location()),
+            uniq: String::from(dev.unique_id()),
+            id: InputId {
+                bustype: device_id.bus_type,
+                vendor: device_id.vendor,
+                product: device_id.product,
+                version: device_id.version,
+            },
+            bits,
+            dev: Mutex::new(dev),
+            events: Mutex::new(VecDeque::new()),
+            queued: AtomicUsize::new(0),
+            readers: WaitQueue::new(),
+        }
+    }
+
+    /// Move the events queued by the device into the event queue, and wake
+    /// up the readers if there were any.
+    fn fetch_events(&self) {
+        let mut dev = self.dev.lock();
+        let mut events = self.events.lock();
+        let mut fetched = false;
+        while let Ok(event) = dev.read_event() {
+            fetched = true;
+            let now = crate::clock::realtime();
+            if events.len() == EVENT_QUEUE_SIZE {
+                events.pop_front();
+            }
+            events.push_back(InputEvent {
+                time: timeval {
+                    tv_sec: now.as_secs() as _,
+                    tv_usec: now.subsec_micros() as _,
+                },
+                type_: event.event_type,
+                code: event.code,
+                value: event.value as i32,
+            });
+        }
+        self.queued.store(events.len(), Ordering::Release);
+        drop(events);
+        drop(dev);
+        if fetched {
+            self.readers.notify_all(false);
+        }
+    }
+
+    /// Copy `data` to the user buffer `argp` of `size` bytes, truncating it.
+    fn copy_out(data: &[u8], argp: usize, size: usize) -> LinuxResult<isize> {
+        let len = data.len().min(size);
+        UserSlice::new(argp as *mut u8, len)
+            .as_mut_slice()?
+            .copy_from_slice(&data[..len]);
+        Ok(len as isize)
+    }
+
+    /// Copy the NUL-terminated `s` to the user buffer `argp`.
+    fn copy_out_str(s: &str, argp: usize, size: usize) -> LinuxResult<isize> {
+        if size == 0 {
+            return Ok(0);
+        }
+        let len = s.len().min(size - 1);
+        let buf = UserSlice::new(argp as *mut u8, len + 1).as_mut_slice()?;
+        buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+        buf[len] = 0;
+        Ok(len as isize + 1)
+    }
+}
+
+impl CharDevice for EventDevice {
+    /// Read as many whole events as fit in `buf`.
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let event_size = core::mem::size_of::<InputEvent>();
+        if buf.len() < event_size {
+            return Err(LinuxError::EINVAL);
+        }
+        self.fetch_events();
+        let mut events = self.events.lock();
+        if events.is_empty() {
+            return Err(LinuxError::EAGAIN);
+        }
+        let count = (buf.len() / event_size).min(events.len());
+        for (chunk, event) in buf.chunks_exact_mut(event_size).zip(events.drain(..count)) {
+            unsafe { (chunk.as_mut_ptr() as *mut InputEvent).write_unaligned(event) };
+        }
+        self.queued.store(events.len(), Ordering::Release);
+        Ok(count * event_size)
+    }
+
+    fn wait_readable(&self) -> LinuxResult {
+        wait_interruptible(&self.readers, None, || {
+            self.queued.load(Ordering::Acquire) > 0
+        })
+        .map(|_| ())
+    }
+
+    fn ioctl(&self, op: usize, argp: usize) -> LinuxResult<isize> {
+        // There is no other reader to exclude, so grabbing always succeeds
+        if op == EVIOCGRAB {
+            return Ok(0);
+        }
+        if argp == 0 {
+            return Err(LinuxError::EFAULT);
+        }
+        match op {
+            EVIOCGVERSION => {
+                UserPtr::<i32>::from(argp).write(EV_VERSION)?;
+                return Ok(0);
+            }
+            EVIOCGID => {
+                UserPtr::<InputId>::from(argp).write(self.id)?;
+                return Ok(0);
+            }
+            _ => {}
+        }
+
+        let size = (op >> 16) & 0x3fff;
+        let nr = op & 0xff;
+        if op >> 30 != IOC_READ || (op >> 8) & 0xff != b'E' as usize {
+            return Err(LinuxError::ENOTTY);
+        }
+        match nr {
+            EVIOCGNAME_NR => Self::copy_out_str(&self.name, argp, size),
+            EVIOCGPHYS_NR => Self::copy_out_str(&self.phys, argp, size),
+            EVIOCGUNIQ_NR => Self::copy_out_str(&self.uniq, argp, size),
+            // No device properties, and no key is held down at open
+            EVIOCGPROP_NR | EVIOCGKEY_NR => Self::copy_out(&[0; BITMAP_SIZE], argp, size),
+            nr if (EVIOCGBIT_NR..EVIOCGBIT_NR + EV_CNT).contains(&nr) => {
+                Self::copy_out(&self.bits[nr - EVIOCGBIT_NR], argp, size)
+            }
+            nr if (EVIOCGABS_NR..EVIOCGABS_NR + 0x40).contains(&nr) => {
+                let code = nr - EVIOCGABS_NR;
+                if self.bits[EV_ABS][code / 8] & (1 << (code % 8)) == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                let info = InputAbsinfo {
+                    maximum: ABS_MAX_VALUE,
+                    ..Default::default()
+                };
+                UserPtr::<InputAbsinfo>::from(argp).write(info)?;
+                Ok(0)
+            }
+            _ => Err(LinuxError::ENOTTY),
+        }
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        self.fetch_events();
+        Ok(PollState {
+            readable: !self.events.lock().is_empty(),
+            writable: false,
+        })
+    }
+}
+
+/// Register the input devices found at boot as `/dev/input/event<N>`, and
+/// spawn the task which polls them for events.
+pub fn register_all() {
+    let mut devices = Vec::new();
+    for (index, dev) in axinput::take_inputs().into_iter().enumerate() {
+        let evdev = Arc::new(EventDevice::new(dev));
+        info!("Input device {}: {}", index, evdev.name);
+        let name = format!("input/event{}", index);
+        let rdev = makedev(INPUT_MAJOR, EVDEV_MINOR_BASE + index as u32);
+        if let Err(e) = devfs::register_chrdev(&name, 0o660, rdev, evdev.clone()) {
+            warn!("Failed to register /dev/{}: {:?}", name, e);
+            continue;
+        }
+        devices.push(evdev);
+    }
+    if devices.is_empty() {
+        return;
+    }
+    axtask::spawn(move || loop {
+        for evdev in &devices {
+            evdev.fetch_events();
+        }
+        axtask::sleep(INPUT_POLL_INTERVAL);
+    });
+}
@@ -0,0 +1,314 @@
+//! The framebuffer of the display, as `/dev/fb0`.
+//!
+//! Programs query the geometry with `FBIOGET_VSCREENINFO` and
+//! `FBIOGET_FSCREENINFO`, then `mmap` the pixel buffer. The display is
+//! updated by the host only on a flush, which `FBIOPAN_DISPLAY` requests.
+//!
+//! Setting the `kernel/fbcon` sysctl also draws the console output on the
+//! framebuffer, with an 8x8 font.
+use super::c_string;
+use super::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::fs::devfs::{self, makedev, CharDevice};
+use crate::ptr::UserPtr;
+use alloc::format;
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::mem::virt_to_phys;
+use axhal::paging::MappingFlags;
+use axstd::os::arceos::modules::axdisplay;
+use axsync::Mutex;
+use lazyinit::LazyInit;
+use memory_addr::{PhysAddr, VirtAddr};
+
+const FB_MAJOR: u32 = 29;
+
+const FBIOGET_VSCREENINFO: usize = 0x4600;
+const FBIOPUT_VSCREENINFO: usize = 0x4601;
+const FBIOGET_FSCREENINFO: usize = 0x4602;
+const FBIOPAN_DISPLAY: usize = 0x4606;
+
+const FB_TYPE_PACKED_PIXELS: u32 = 0;
+const FB_VISUAL_TRUECOLOR: u32 = 2;
+
+/// Pixels are 32-bit BGRA, the format of virtio-gpu and ramfb.
+const BITS_PER_PIXEL: u32 = 32;
+
+/// `struct fb_bitfield` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+impl FbBitfield {
+    const fn new(offset: u32, length: u32) -> Self {
+        Self {
+            offset,
+            length,
+            msb_right: 0,
+        }
+    }
+}
+
+/// `struct fb_var_screeninfo` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct FbVarScreeninfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    nonstd: u32,
+    activate: u32,
+    /// Height of the picture in mm
+    height: u32,
+    /// Width of the picture in mm
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
+/// `struct fb_fix_screeninfo` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct FbFixScreeninfo {
+    id: [u8; 16],
+    smem_start: usize,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: usize,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// The framebuffer of the display device.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    base: VirtAddr,
+    size: usize,
+}
+
+impl Framebuffer {
+    fn probe() -> Self {
+        let info = axdisplay::framebuffer_info();
+        Self {
+            width: info.width,
+            height: info.height,
+            base: VirtAddr::from(info.fb_base_vaddr),
+            size: info.fb_size,
+        }
+    }
+
+    fn line_length(&self) -> usize {
+        self.width as usize * (BITS_PER_PIXEL / 8) as usize
+    }
+
+    fn var_screeninfo(&self) -> FbVarScreeninfo {
+        FbVarScreeninfo {
+            xres: self.width,
+            yres: self.height,
+            xres_virtual: self.width,
+            yres_virtual: self.height,
+            bits_per_pixel: BITS_PER_PIXEL,
+            red: FbBitfield::new(16, 8),
+            green: FbBitfield::new(8, 8),
+            blue: FbBitfield::new(0, 8),
+            transp: FbBitfield::new(24, 8),
+            // The physical size is unknown
+            height: u32::MAX,
+            width: u32::MAX,
+            ..Default::default()
+        }
+    }
+
+    fn fix_screeninfo(&self) -> FbFixScreeninfo {
+        FbFixScreeninfo {
+            id: c_string("virtio-gpu"),
+            smem_start: virt_to_phys(self.base).as_usize(),
+            smem_len: self.size as u32,
+            type_: FB_TYPE_PACKED_PIXELS,
+            visual: FB_VISUAL_TRUECOLOR,
+            line_length: self.line_length() as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Have the host show the current content of the framebuffer.
+    fn flush(&self) {
+        axdisplay::framebuffer_flush();
+    }
+
+    fn pixels(&self) -> *mut u32 {
+        self.base.as_mut_ptr() as *mut u32
+    }
+}
+
+impl CharDevice for Framebuffer {
+    fn ioctl(&self, op: usize, argp: usize) -> LinuxResult<isize> {
+        if argp == 0 {
+            return Err(LinuxError::EFAULT);
+        }
+        match op {
+            FBIOGET_VSCREENINFO => UserPtr::from(argp).write(self.var_screeninfo())?,
+            FBIOGET_FSCREENINFO => UserPtr::from(argp).write(self.fix_screeninfo())?,
+            FBIOPUT_VSCREENINFO => {
+                // The mode is set by the host, so only the current one is accepted
+                let var = UserPtr::<FbVarScreeninfo>::from(argp).read()?;
+                if var.xres != self.width
+                    || var.yres != self.height
+                    || var.bits_per_pixel != BITS_PER_PIXEL
+                {
+                    return Err(LinuxError::EINVAL);
+                }
+            }
+            FBIOPAN_DISPLAY => {
+                // There is a single screen, so the only valid offset is 0
+                let var = UserPtr::<FbVarScreeninfo>::from(argp).read()?;
+                if var.xoffset != 0 || var.yoffset != 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                self.flush();
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn mmap(&self, offset: usize, size: usize) -> LinuxResult<(PhysAddr, MappingFlags)> {
+        let end = offset.checked_add(size).ok_or(LinuxError::EINVAL)?;
+        if end > memory_addr::align_up_4k(self.size) {
+            return Err(LinuxError::EINVAL);
+        }
+        // The buffer is ordinary RAM read by the host, so it stays cached
+        Ok((virt_to_phys(self.base) + offset, MappingFlags::empty()))
+    }
+}
+
+static FB: LazyInit<Arc<Framebuffer>> = LazyInit::new();
+
+/// Register the framebuffer of the display as `/dev/fb<index>`.
+pub fn register(index: u32) {
+    let fb = FB.call_once(|| Arc::new(Framebuffer::probe())).clone();
+    info!("Framebuffer: {}x{} at {:#x}", fb.width, fb.height, fb.base);
+    let name = format!("fb{}", index);
+    let rdev = makedev(FB_MAJOR, index);
+    if let Err(e) = devfs::register_chrdev(&name, 0o660, rdev, fb) {
+        warn!("Failed to register /dev/{}: {:?}", name, e);
+    }
+}
+
+const FBCON_FG: u32 = 0x00c0_c0c0;
+const FBCON_BG: u32 = 0x0000_0000;
+
+/// The position of the cursor of the framebuffer console, in characters.
+struct Cursor {
+    col: usize,
+    row: usize,
+}
+
+static CURSOR: Mutex<Cursor> = Mutex::new(Cursor { col: 0, row: 0 });
+
+impl Framebuffer {
+    fn text_size(&self) -> (usize, usize) {
+        (
+            self.width as usize / GLYPH_WIDTH,
+            self.height as usize / GLYPH_HEIGHT,
+        )
+    }
+
+    fn draw_glyph(&self, ch: u8, col: usize, row: usize) {
+        let width = self.width as usize;
+        let pixels = self.pixels();
+        for (y, bits) in font::glyph(ch).iter().enumerate() {
+            let line = (row * GLYPH_HEIGHT + y) * width + col * GLYPH_WIDTH;
+            for x in 0..GLYPH_WIDTH {
+                let color = if bits >> x & 1 != 0 {
+                    FBCON_FG
+                } else {
+                    FBCON_BG
+                };
+                unsafe { pixels.add(line + x).write_volatile(color) };
+            }
+        }
+    }
+
+    /// Move the text up by a line, clearing the last one.
+    fn scroll(&self, rows: usize) {
+        let text_line = self.line_length() * GLYPH_HEIGHT;
+        let base = self.base.as_mut_ptr();
+        unsafe {
+            core::ptr::copy(base.add(text_line), base, text_line * (rows - 1));
+            core::ptr::write_bytes(base.add(text_line * (rows - 1)), 0, text_line);
+        }
+    }
+}
+
+/// Draw `data` on the framebuffer console, if there is a framebuffer.
+///
+/// Only printable ASCII is drawn. Newlines, carriage returns, backspaces and
+/// tabs move the cursor, and other bytes such as escape sequences are drawn
+/// as is.
+pub fn console_write(data: &[u8]) {
+    let Some(fb) = FB.get() else {
+        return;
+    };
+    let (cols, rows) = fb.text_size();
+    if cols == 0 || rows == 0 {
+        return;
+    }
+    let mut cursor = CURSOR.lock();
+    for &byte in data {
+        match byte {
+            b'\n' => {
+                cursor.col = 0;
+                cursor.row += 1;
+            }
+            b'\r' => cursor.col = 0,
+            b'\x08' => cursor.col = cursor.col.saturating_sub(1),
+            b'\t' => cursor.col = (cursor.col + 8) & !7,
+            _ => {
+                fb.draw_glyph(byte, cursor.col, cursor.row);
+                cursor.col += 1;
+            }
+        }
+        if cursor.col >= cols {
+            cursor.col = 0;
+            cursor.row += 1;
+        }
+        if cursor.row >= rows {
+            fb.scroll(rows);
+            cursor.row = rows - 1;
+        }
+    }
+    fb.flush();
+}
@@ -0,0 +1,554 @@
+//! GPIO chips, as `/dev/gpiochip<N>`, with the character device ABI of Linux
+//! (version 2 of `linux/gpio.h`), so that libgpiod and board tests run
+//! unchanged.
+//!
+//! On the chip device, `GPIO_GET_CHIPINFO_IOCTL` and
+//! `GPIO_V2_GET_LINEINFO_IOCTL` describe the chip and its lines, and
+//! `GPIO_V2_GET_LINE_IOCTL` requests some of the lines, returning an fd which
+//! holds them until it is closed. The lines are then read, driven and
+//! reconfigured with the `GPIO_V2_LINE_*` ioctls on that fd. Lines may be
+//! inputs or outputs, and active-low; edge detection, debouncing and the drive
+//! and bias settings aren't supported, nor are the v1 ioctls.
+//!
+//! There are two kinds of chips:
+//!
+//! * a software chip, which latches what is written to its output lines and
+//!   reads them back, like Linux's gpio-sim;
+//! * the PL061 of QEMU's aarch64 `virt` machine, when its registers are
+//!   mapped.
+use super::c_string;
+use crate::fs::devfs::{self, makedev, CharDevice};
+use crate::ptr::UserPtr;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use core::mem::size_of;
+
+/// The number of lines of the software chip.
+pub const SIM_GPIO_LINES: u32 = 32;
+/// The major number of the GPIO chip devices.
+const GPIOCHIP_MAJOR: u32 = 254;
+
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_GET_CHIPINFO_IOCTL: usize = 0x8044_b401;
+const GPIO_V2_GET_LINEINFO_IOCTL: usize = 0xc100_b405;
+const GPIO_V2_GET_LINE_IOCTL: usize = 0xc250_b407;
+const GPIO_V2_LINE_SET_CONFIG_IOCTL: usize = 0xc110_b40d;
+const GPIO_V2_LINE_GET_VALUES_IOCTL: usize = 0xc010_b40e;
+const GPIO_V2_LINE_SET_VALUES_IOCTL: usize = 0xc010_b40f;
+
+const GPIO_V2_LINE_FLAG_USED: u64 = 1 << 0;
+const GPIO_V2_LINE_FLAG_ACTIVE_LOW: u64 = 1 << 1;
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+
+const GPIO_V2_LINE_ATTR_ID_FLAGS: u32 = 1;
+const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+/// `struct gpiochip_info` in the Linux uapi.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpioChipInfo {
+    name: [u8; GPIO_MAX_NAME_SIZE],
+    label: [u8; GPIO_MAX_NAME_SIZE],
+    lines: u32,
+}
+
+/// `struct gpio_v2_line_attribute` in the Linux uapi. The value is the
+/// union of the flags, the output values and the debounce period.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: u64,
+}
+
+/// `struct gpio_v2_line_config_attribute` in the Linux uapi.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    /// The lines the attribute applies to, by their index in the request
+    mask: u64,
+}
+
+/// `struct gpio_v2_line_config` in the Linux uapi.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineConfig {
+    /// The flags of the lines no attribute overrides them for
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+/// `struct gpio_v2_line_request` in the Linux uapi.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineRequestArgs {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    /// The fd of the request, filled in on success
+    fd: i32,
+}
+
+/// `struct gpio_v2_line_info` in the Linux uapi.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineInfo {
+    name: [u8; GPIO_MAX_NAME_SIZE],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    offset: u32,
+    num_attrs: u32,
+    flags: u64,
+    attrs: [LineAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+    padding: [u32; 4],
+}
+
+/// `struct gpio_v2_line_values` in the Linux uapi. Both are bitmaps of the
+/// lines by their index in the request.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+const _: () = {
+    assert!(size_of::<GpioChipInfo>() == 0x44);
+    assert!(size_of::<LineConfig>() == 0x110);
+    assert!(size_of::<LineRequestArgs>() == 0x250);
+    assert!(size_of::<LineInfo>() == 0x100);
+    assert!(size_of::<LineValues>() == 0x10);
+};
+
+/// The hardware of a GPIO chip. Lines are bits of a `u64`, and values are
+/// physical levels. The chip serializes the calls.
+pub trait GpioHw: Send + Sync {
+    /// The levels of all the lines, outputs included.
+    fn values(&self) -> u64;
+
+    /// Drive the output lines of `mask` to the levels in `values`.
+    fn set_values(&self, mask: u64, values: u64);
+
+    /// The lines which are outputs.
+    fn outputs(&self) -> u64;
+
+    /// Make the lines of `mask` outputs where `outputs` has their bit set,
+    /// inputs otherwise.
+    fn set_outputs(&self, mask: u64, outputs: u64);
+}
+
+/// The software chip. Its inputs read as the level they were last driven to.
+#[derive(Default)]
+pub struct SimGpio {
+    values: Mutex<u64>,
+    outputs: Mutex<u64>,
+}
+
+impl GpioHw for SimGpio {
+    fn values(&self) -> u64 {
+        *self.values.lock()
+    }
+
+    fn set_values(&self, mask: u64, values: u64) {
+        let mut current = self.values.lock();
+        *current = (*current & !mask) | (values & mask);
+    }
+
+    fn outputs(&self) -> u64 {
+        *self.outputs.lock()
+    }
+
+    fn set_outputs(&self, mask: u64, outputs: u64) {
+        let mut current = self.outputs.lock();
+        *current = (*current & !mask) | (outputs & mask);
+    }
+}
+
+/// The ARM PrimeCell PL061, with 8 lines.
+#[cfg(target_arch = "aarch64")]
+pub struct Pl061 {
+    base: usize,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Pl061 {
+    /// Where QEMU's `virt` machine has it
+    const PADDR: usize = 0x0903_0000;
+    const SIZE: usize = 0x1000;
+    pub const LINES: u32 = 8;
+    /// The data register, whose address selects the bits accessed
+    const GPIODATA: usize = 0x000;
+    const GPIODIR: usize = 0x400;
+    const GPIOPERIPHID0: usize = 0xfe0;
+
+    /// The PL061 of the board, if its registers are mapped and identify it.
+    pub fn probe() -> Option<Self> {
+        use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
+
+        let mapped = memory_regions().any(|region| {
+            region.flags.contains(MemRegionFlags::DEVICE)
+                && region.paddr.as_usize() <= Self::PADDR
+                && Self::PADDR + Self::SIZE <= region.paddr.as_usize() + region.size
+        });
+        if !mapped {
+            return None;
+        }
+        let chip = Self {
+            base: phys_to_virt(Self::PADDR.into()).as_usize(),
+        };
+        let id = [
+            chip.read(Self::GPIOPERIPHID0),
+            chip.read(Self::GPIOPERIPHID0 + 4),
+        ];
+        (id == [0x61, 0x10]).then_some(chip)
+    }
+
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) as u64 & 0xff }
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, value as u32) }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl GpioHw for Pl061 {
+    fn values(&self) -> u64 {
+        self.read(Self::GPIODATA + (0xff << 2))
+    }
+
+    fn set_values(&self, mask: u64, values: u64) {
+        self.write(Self::GPIODATA + ((mask as usize & 0xff) << 2), values);
+    }
+
+    fn outputs(&self) -> u64 {
+        self.read(Self::GPIODIR)
+    }
+
+    fn set_outputs(&self, mask: u64, outputs: u64) {
+        let dir = self.read(Self::GPIODIR);
+        self.write(Self::GPIODIR, (dir & !mask) | (outputs & mask));
+    }
+}
+
+/// A line held by a consumer.
+struct Claim {
+    consumer: String,
+    active_low: bool,
+}
+
+/// A bank of up to 64 GPIO lines.
+pub struct GpioChip {
+    /// Set when the chip is registered as `gpiochip<N>`
+    name: Mutex<String>,
+    label: &'static str,
+    lines: u32,
+    hw: Box<dyn GpioHw>,
+    /// The holder of each line. The lock serializes the accesses to `hw`
+    claims: Mutex<Vec<Option<Claim>>>,
+}
+
+impl GpioChip {
+    pub fn new(label: &'static str, lines: u32, hw: Box<dyn GpioHw>) -> Self {
+        assert!(lines <= u64::BITS);
+        Self {
+            name: Mutex::new(String::new()),
+            label,
+            lines,
+            hw,
+            claims: Mutex::new((0..lines).map(|_| None).collect()),
+        }
+    }
+
+    /// Hold `offsets` for `consumer`, failing with `EBUSY` if one is already
+    /// held, and with `EINVAL` if one isn't a line of the chip.
+    pub fn claim(&self, offsets: &[u32], consumer: &str) -> LinuxResult {
+        let mut claims = self.claims.lock();
+        if offsets.iter().any(|&offset| offset >= self.lines) {
+            return Err(LinuxError::EINVAL);
+        }
+        for (i, &offset) in offsets.iter().enumerate() {
+            if claims[offset as usize].is_some() || offsets[..i].contains(&offset) {
+                return Err(LinuxError::EBUSY);
+            }
+        }
+        for &offset in offsets {
+            claims[offset as usize] = Some(Claim {
+                consumer: String::from(consumer),
+                active_low: false,
+            });
+        }
+        Ok(())
+    }
+
+    fn release(&self, offsets: &[u32]) {
+        let mut claims = self.claims.lock();
+        for &offset in offsets {
+            claims[offset as usize] = None;
+        }
+    }
+
+    /// The level of `line`.
+    pub fn get(&self, line: u32) -> LinuxResult<bool> {
+        if line >= self.lines {
+            return Err(LinuxError::EINVAL);
+        }
+        let _claims = self.claims.lock();
+        Ok(self.hw.values() & (1 << line) != 0)
+    }
+
+    /// Drive `line` to `value`, making it an output.
+    pub fn set(&self, line: u32, value: bool) -> LinuxResult {
+        if line >= self.lines {
+            return Err(LinuxError::EINVAL);
+        }
+        let _claims = self.claims.lock();
+        self.hw.set_values(1 << line, (value as u64) << line);
+        self.hw.set_outputs(1 << line, 1 << line);
+        Ok(())
+    }
+
+    fn line_info(&self, offset: u32) -> LinuxResult<LineInfo> {
+        if offset >= self.lines {
+            return Err(LinuxError::EINVAL);
+        }
+        let claims = self.claims.lock();
+        let mut flags = if self.hw.outputs() & (1 << offset) != 0 {
+            GPIO_V2_LINE_FLAG_OUTPUT
+        } else {
+            GPIO_V2_LINE_FLAG_INPUT
+        };
+        let mut consumer = [0; GPIO_MAX_NAME_SIZE];
+        if let Some(claim) = &claims[offset as usize] {
+            flags |= GPIO_V2_LINE_FLAG_USED;
+            if claim.active_low {
+                flags |= GPIO_V2_LINE_FLAG_ACTIVE_LOW;
+            }
+            consumer = c_string(&claim.consumer);
+        }
+        Ok(LineInfo {
+            name: [0; GPIO_MAX_NAME_SIZE],
+            consumer,
+            offset,
+            num_attrs: 0,
+            flags,
+            attrs: [LineAttribute::default(); GPIO_V2_LINE_NUM_ATTRS_MAX],
+            padding: [0; 4],
+        })
+    }
+}
+
+// On the `Arc`, which the line requests keep
+impl CharDevice for Arc<GpioChip> {
+    fn ioctl(&self, op: usize, argp: usize) -> LinuxResult<isize> {
+        if argp == 0 {
+            return Err(LinuxError::EFAULT);
+        }
+        match op {
+            GPIO_GET_CHIPINFO_IOCTL => {
+                let info = GpioChipInfo {
+                    name: c_string(&self.name.lock()),
+                    label: c_string(self.label),
+                    lines: self.lines,
+                };
+                UserPtr::<GpioChipInfo>::from(argp).write(info)?;
+            }
+            GPIO_V2_GET_LINEINFO_IOCTL => {
+                let ptr = UserPtr::<LineInfo>::from(argp);
+                let offset = ptr.read()?.offset;
+                ptr.write(self.line_info(offset)?)?;
+            }
+            GPIO_V2_GET_LINE_IOCTL => {
+                let ptr = UserPtr::<LineRequestArgs>::from(argp);
+                let mut args = ptr.read()?;
+                args.fd = LineRequest::open(self, &args)?;
+                ptr.write(args)?;
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+/// Lines of a chip requested by user space, held as long as the fd which
+/// `GPIO_V2_GET_LINE_IOCTL` returned stays open.
+struct LineRequest {
+    chip: Arc<GpioChip>,
+    /// The lines of the chip, in the order of the request
+    offsets: Vec<u32>,
+}
+
+impl LineRequest {
+    /// Hold the lines of `args` and configure them, returning the fd of the
+    /// request.
+    fn open(chip: &Arc<GpioChip>, args: &LineRequestArgs) -> LinuxResult<i32> {
+        let num_lines = args.num_lines as usize;
+        if num_lines == 0 || num_lines > GPIO_V2_LINES_MAX || args.padding != [0; 5] {
+            return Err(LinuxError::EINVAL);
+        }
+        let offsets = &args.offsets[..num_lines];
+        let consumer = args.consumer.split(|&b| b == 0).next().unwrap_or(&[]);
+        let consumer = core::str::from_utf8(consumer).map_err(|_| LinuxError::EINVAL)?;
+        chip.claim(offsets, consumer)?;
+        // The lines are released when the request is dropped, on failure too
+        let request = Self {
+            chip: chip.clone(),
+            offsets: Vec::from(offsets),
+        };
+        request.configure(&args.config)?;
+        devfs::add_anon(Arc::new(request))
+    }
+
+    /// The lines of `mask`, a bitmap of the lines by their index in the
+    /// request. Fails with `EINVAL` if there are none.
+    fn lines(&self, mask: u64) -> LinuxResult<impl Iterator<Item = (usize, u32)> + '_> {
+        let mask = if self.offsets.len() == GPIO_V2_LINES_MAX {
+            mask
+        } else {
+            mask & ((1 << self.offsets.len()) - 1)
+        };
+        if mask == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(self
+            .offsets
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| mask & (1 << i) != 0)
+            .map(|(i, &offset)| (i, offset)))
+    }
+
+    /// Apply `config` to the lines: their direction, polarity and the initial
+    /// values of the outputs. Nothing changes if it is invalid.
+    fn configure(&self, config: &LineConfig) -> LinuxResult {
+        let num_attrs = config.num_attrs as usize;
+        if num_attrs > GPIO_V2_LINE_NUM_ATTRS_MAX || config.padding != [0; 5] {
+            return Err(LinuxError::EINVAL);
+        }
+        let attrs = &config.attrs[..num_attrs];
+        let mut settings = Vec::with_capacity(self.offsets.len());
+        for (i, &offset) in self.offsets.iter().enumerate() {
+            let mut flags = config.flags;
+            let mut value = false;
+            for attr in attrs.iter().filter(|attr| attr.mask & (1 << i) != 0) {
+                match attr.attr.id {
+                    GPIO_V2_LINE_ATTR_ID_FLAGS => flags = attr.attr.value,
+                    GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES => value = attr.attr.value & (1 << i) != 0,
+                    _ => return Err(LinuxError::EINVAL),
+                }
+            }
+            let supported =
+                GPIO_V2_LINE_FLAG_ACTIVE_LOW | GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_OUTPUT;
+            let both = GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_OUTPUT;
+            if flags & !supported != 0 || flags & both == both {
+                return Err(LinuxError::EINVAL);
+            }
+            settings.push((offset, flags, value));
+        }
+
+        let mut claims = self.chip.claims.lock();
+        for (offset, flags, value) in settings {
+            let active_low = flags & GPIO_V2_LINE_FLAG_ACTIVE_LOW != 0;
+            claims[offset as usize].as_mut().unwrap().active_low = active_low;
+            let bit = 1 << offset;
+            // The level is set before the line becomes an output, so that it
+            // doesn't glitch
+            if flags & GPIO_V2_LINE_FLAG_OUTPUT != 0 {
+                let level = if value != active_low { bit } else { 0 };
+                self.chip.hw.set_values(bit, level);
+                self.chip.hw.set_outputs(bit, bit);
+            } else if flags & GPIO_V2_LINE_FLAG_INPUT != 0 {
+                self.chip.hw.set_outputs(bit, 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// The values of the lines of `values.mask`, as seen by user space.
+    fn get_values(&self, mut values: LineValues) -> LinuxResult<LineValues> {
+        let claims = self.chip.claims.lock();
+        let levels = self.chip.hw.values();
+        values.bits = 0;
+        for (i, offset) in self.lines(values.mask)? {
+            let active_low = claims[offset as usize].as_ref().unwrap().active_low;
+            if (levels & (1 << offset) != 0) != active_low {
+                values.bits |= 1 << i;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Drive the lines of `values.mask`, which must all be outputs, failing
+    /// with `EPERM` otherwise.
+    fn set_values(&self, values: LineValues) -> LinuxResult {
+        let claims = self.chip.claims.lock();
+        let outputs = self.chip.hw.outputs();
+        let (mut mask, mut levels) = (0, 0);
+        for (i, offset) in self.lines(values.mask)? {
+            if outputs & (1 << offset) == 0 {
+                return Err(LinuxError::EPERM);
+            }
+            let active_low = claims[offset as usize].as_ref().unwrap().active_low;
+            mask |= 1 << offset;
+            if (values.bits & (1 << i) != 0) != active_low {
+                levels |= 1 << offset;
+            }
+        }
+        self.chip.hw.set_values(mask, levels);
+        Ok(())
+    }
+}
+
+impl CharDevice for LineRequest {
+    fn ioctl(&self, op: usize, argp: usize) -> LinuxResult<isize> {
+        if argp == 0 {
+            return Err(LinuxError::EFAULT);
+        }
+        match op {
+            GPIO_V2_LINE_SET_CONFIG_IOCTL => {
+                self.configure(&UserPtr::<LineConfig>::from(argp).read()?)?;
+            }
+            GPIO_V2_LINE_GET_VALUES_IOCTL => {
+                let ptr = UserPtr::<LineValues>::from(argp);
+                ptr.write(self.get_values(ptr.read()?)?)?;
+            }
+            GPIO_V2_LINE_SET_VALUES_IOCTL => {
+                self.set_values(UserPtr::<LineValues>::from(argp).read()?)?;
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+impl Drop for LineRequest {
+    fn drop(&mut self) {
+        self.chip.release(&self.offsets);
+    }
+}
+
+/// Register `chip` as `/dev/gpiochip<index>`.
+pub fn register(index: u32, chip: Arc<GpioChip>) {
+    let name = format!("gpiochip{}", index);
+    *chip.name.lock() = name.clone();
+    let rdev = makedev(GPIOCHIP_MAJOR, index);
+    if let Err(e) = devfs::register_chrdev(&name, 0o600, rdev, Arc::new(chip)) {
+        warn!("Failed to register /dev/{}: {:?}", name, e);
+    }
+}
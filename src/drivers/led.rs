@@ -0,0 +1,59 @@
+//! LEDs wired to GPIO lines, as `/dev/led<N>`.
+//!
+//! Reading the device gives the brightness as text, `0` or `1` followed by a
+//! newline. Writing a number sets it, any non-zero value turning the LED on.
+use super::gpio::GpioChip;
+use crate::fs::devfs::{self, makedev, CharDevice};
+use alloc::format;
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+
+/// LEDs are misc devices, with minors counted from this one.
+const LED_MISC_MINOR_BASE: u32 = 240;
+const MISC_MAJOR: u32 = 10;
+
+/// An LED driven by a GPIO line, lit when the line is high.
+pub struct GpioLed {
+    chip: Arc<GpioChip>,
+    line: u32,
+}
+
+impl CharDevice for GpioLed {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let text = if self.chip.get(self.line)? {
+            b"1\n"
+        } else {
+            b"0\n"
+        };
+        let len = text.len().min(buf.len());
+        buf[..len].copy_from_slice(&text[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        let brightness: u32 = core::str::from_utf8(buf)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .ok_or(LinuxError::EINVAL)?;
+        self.chip.set(self.line, brightness != 0)?;
+        Ok(buf.len())
+    }
+}
+
+/// Register the LED on `line` of `chip` as `/dev/led<index>`.
+pub fn register(index: u32, chip: Arc<GpioChip>, line: u32) {
+    let name = format!("led{}", index);
+    // The line is the LED's, user space can't request it from the chip
+    if let Err(e) = chip.claim(&[line], &name) {
+        warn!("Failed to claim line {} for /dev/{}: {:?}", line, name, e);
+        return;
+    }
+    // Starting off, as leds-gpio does
+    if let Err(e) = chip.set(line, false) {
+        warn!("Failed to turn /dev/{} off: {:?}", name, e);
+    }
+    let rdev = makedev(MISC_MAJOR, LED_MISC_MINOR_BASE + index);
+    if let Err(e) = devfs::register_chrdev(&name, 0o666, rdev, Arc::new(GpioLed { chip, line })) {
+        warn!("Failed to register /dev/{}: {:?}", name, e);
+    }
+}
@@ -0,0 +1,40 @@
+//! Board peripheral drivers, exposed to user space as character devices.
+//!
+//! Each driver registers its devices into devfs from [`init`], so that board
+//! tests can exercise the peripherals through plain file operations.
+#[cfg(feature = "input")]
+pub mod evdev;
+#[cfg(feature = "display")]
+pub mod fb;
+#[cfg(feature = "display")]
+mod font;
+pub mod gpio;
+pub mod led;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+/// Copy `s` into a NUL-terminated fixed-size buffer, truncating it if needed.
+fn c_string<const N: usize>(s: &str) -> [u8; N] {
+    let mut buf = [0; N];
+    let len = s.len().min(N - 1);
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    buf
+}
+
+/// Probe the peripherals and register their devices.
+pub fn init() {
+    let sim = Box::new(gpio::SimGpio::default());
+    let chip = Arc::new(gpio::GpioChip::new("gpio-sim", gpio::SIM_GPIO_LINES, sim));
+    gpio::register(0, chip.clone());
+    led::register(0, chip, 0);
+    #[cfg(target_arch = "aarch64")]
+    if let Some(pl061) = gpio::Pl061::probe() {
+        let chip = gpio::GpioChip::new("9030000.pl061", gpio::Pl061::LINES, Box::new(pl061));
+        gpio::register(1, Arc::new(chip));
+    }
+    #[cfg(feature = "display")]
+    fb::register(0);
+    #[cfg(feature = "input")]
+    evdev::register_all();
+}
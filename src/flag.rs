@@ -24,7 +24,7 @@ bitflags! {
          const CLONE_PARENT = 1 << 15;
          /// 作为一个“线程”被创建。具体来说，它同 CLONE_PARENT 一样设置 ppid，且不可被 wait
          const CLONE_THREAD = 1 << 16;
-         /// 子任务使用新的命名空间。目前还未用到
+         /// 子任务使用新的挂载命名空间，复制自父任务
          const CLONE_NEWNS = 1 << 17;
          /// 子任务共享同一组信号量。用于 sys_semop
          const CLONE_SYSVSEM = 1 << 18;
@@ -45,9 +45,31 @@ bitflags! {
      }
 }
 
+bitflags! {
+    /// wait4 与 waitid 的选项
+    #[derive(Debug, Clone, Copy)]
+    pub struct WaitOptions: u32 {
+        /// 没有子进程的状态变化时立即返回
+        const WNOHANG = 1;
+        /// 报告停止的子进程，即 wait4 的 WUNTRACED
+        const WSTOPPED = 2;
+        /// 报告退出的子进程，wait4 总是报告
+        const WEXITED = 4;
+        /// 报告被 SIGCONT 继续执行的子进程
+        const WCONTINUED = 8;
+        /// 只报告状态变化，保留子进程以便再次等待
+        const WNOWAIT = 0x0100_0000;
+        /// 只等待当前线程的子进程，所有线程共享子进程，因此被忽略
+        const __WNOTHREAD = 0x2000_0000;
+        /// 等待所有子进程，不论其退出信号
+        const __WALL = 0x4000_0000;
+        /// 只等待退出信号不是 SIGCHLD 的子进程
+        const __WCLONE = 0x8000_0000;
+    }
+}
+
 #[derive(Eq, PartialEq)]
 pub(crate) enum WaitStatus {
-    Exited,
     Running,
     NotExist,
 }
@@ -0,0 +1,439 @@
+//! The page cache.
+//!
+//! Regular files are read and written through pages kept in memory, keyed by
+//! the inode number of the file and the index of the page, so that `read`,
+//! `write` and the mappings of a file all see the same bytes: a `MAP_SHARED`
+//! mapping maps the cached pages themselves.
+//!
+//! A miss reads ahead: [`READ_AHEAD_MIN`] pages at first, twice as many each
+//! time the file keeps being read sequentially, up to [`READ_AHEAD_MAX`].
+//!
+//! A write inside a file only goes to its pages, which are written back by
+//! `fsync`, `msync` and `umount`, at the end of each testcase, or when they
+//! are evicted. A write extending a file goes to the file as well, so that the
+//! size it reports is always right. Once more than [`CACHE_PAGES_MAX`] pages
+//! are cached, the least recently used ones are evicted, except those mapped
+//! in user space. When frames run out, [`reclaim`] drops clean pages as well,
+//! see [`crate::mm::swap`].
+use super::{inode, overlay};
+use crate::mm::Frame;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axstd::fs::{File, OpenOptions};
+use axstd::io::{Read, Seek, SeekFrom, Write};
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+
+/// The pages read on a miss which doesn't follow the previous read.
+const READ_AHEAD_MIN: u64 = 4;
+/// The most pages read on a miss.
+const READ_AHEAD_MAX: u64 = 64;
+/// The number of pages above which the cache starts evicting.
+const CACHE_PAGES_MAX: usize = 8192;
+
+const PAGE_SIZE: u64 = PAGE_SIZE_4K as u64;
+
+struct Page {
+    frame: Arc<Frame>,
+    /// Whether the page was written to since it was read or written back
+    dirty: bool,
+    /// Whether the page is mapped writable and shared, so that user space may
+    /// change it anytime
+    mapped: bool,
+    /// When the page was last used, in ticks of [`PageCache::clock`]
+    used: u64,
+}
+
+struct CachedFile {
+    /// The absolute path of the file, where it is written back to
+    path: String,
+    pages: BTreeMap<u64, Page>,
+    /// The page a sequential read would miss next
+    ra_next: u64,
+    /// The pages read on the last miss
+    ra_pages: u64,
+}
+
+struct PageCache {
+    /// The cached files, by inode number
+    files: BTreeMap<u64, CachedFile>,
+    /// The number of pages cached
+    count: usize,
+    /// Incremented every time pages are used
+    clock: u64,
+}
+
+static CACHE: Mutex<PageCache> = Mutex::new(PageCache {
+    files: BTreeMap::new(),
+    count: 0,
+    clock: 0,
+});
+
+/// The size of the file at `path` as the filesystem reports it.
+fn disk_size(path: &str) -> LinuxResult<u64> {
+    Ok(axfs::api::metadata(&overlay::lookup(path))?.len())
+}
+
+/// Read from `file` into `buf` until it is full or the end of the file.
+fn read_full(file: &mut File, buf: &mut [u8]) -> LinuxResult {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(())
+}
+
+impl CachedFile {
+    fn new(path: &str) -> Self {
+        Self {
+            path: String::from(path),
+            pages: BTreeMap::new(),
+            ra_next: 0,
+            ra_pages: 0,
+        }
+    }
+
+    /// Make sure page `index` is cached, reading it and the pages after it
+    /// ahead on a miss. `size` is the size of the file. Returns the number of
+    /// pages added.
+    fn fill(&mut self, index: u64, size: u64, clock: u64) -> LinuxResult<usize> {
+        if let Some(page) = self.pages.get_mut(&index) {
+            page.used = clock;
+            return Ok(0);
+        }
+        self.ra_pages = if index == self.ra_next && self.ra_pages > 0 {
+            (self.ra_pages * 2).min(READ_AHEAD_MAX)
+        } else {
+            READ_AHEAD_MIN
+        };
+        // Read ahead up to the end of the file, or the next cached page. A
+        // page past the end is zeroed.
+        let last = size.div_ceil(PAGE_SIZE);
+        let end = (index + self.ra_pages).min(last.max(index + 1));
+        let end = self
+            .pages
+            .range(index..end)
+            .next()
+            .map_or(end, |(&next, _)| next);
+        let mut data = vec![0; (end - index) as usize * PAGE_SIZE_4K];
+        if index < last {
+            let mut file = File::open(&overlay::lookup(&self.path))?;
+            file.seek(SeekFrom::Start(index * PAGE_SIZE))?;
+            read_full(&mut file, &mut data)?;
+        }
+        for (i, chunk) in data.chunks(PAGE_SIZE_4K).enumerate() {
+            let frame = Frame::alloc()?;
+            frame.as_mut_slice().copy_from_slice(chunk);
+            let page = Page {
+                frame,
+                dirty: false,
+                mapped: false,
+                used: clock,
+            };
+            self.pages.insert(index + i as u64, page);
+        }
+        Ok((end - index) as usize)
+    }
+
+    /// Write the pages which may have changed back to the file, without
+    /// growing it.
+    fn write_back(&mut self) -> LinuxResult {
+        if !self.pages.values().any(|page| page.dirty || page.mapped) {
+            return Ok(());
+        }
+        let path = overlay::lookup(&self.path);
+        let size = axfs::api::metadata(&path)?.len();
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        for (&index, page) in self.pages.iter_mut() {
+            let offset = index * PAGE_SIZE;
+            if !(page.dirty || page.mapped) || offset >= size {
+                continue;
+            }
+            let len = (size - offset).min(PAGE_SIZE) as usize;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&page.frame.as_slice()[..len])?;
+            page.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl PageCache {
+    fn file(&mut self, ino: u64, path: &str) -> &mut CachedFile {
+        self.files
+            .entry(ino)
+            .or_insert_with(|| CachedFile::new(path))
+    }
+
+    /// Evict the least recently used pages if there are too many.
+    fn shrink(&mut self) {
+        if self.count <= CACHE_PAGES_MAX {
+            return;
+        }
+        let mut candidates: Vec<(u64, u64, u64)> = self
+            .files
+            .iter()
+            .flat_map(|(&ino, file)| {
+                file.pages
+                    .iter()
+                    .filter(|(_, page)| !page.mapped)
+                    .map(move |(&index, page)| (page.used, ino, index))
+            })
+            .collect();
+        candidates.sort_unstable();
+        let target = CACHE_PAGES_MAX * 3 / 4;
+        for (_, ino, index) in candidates {
+            if self.count <= target {
+                break;
+            }
+            let file = self.files.get_mut(&ino).unwrap();
+            if file.pages[&index].dirty {
+                if let Err(e) = file.write_back() {
+                    warn!("Failed to write back {}: {:?}", file.path, e);
+                    continue;
+                }
+            }
+            file.pages.remove(&index);
+            self.count -= 1;
+        }
+        self.files.retain(|_, file| !file.pages.is_empty());
+    }
+}
+
+/// Read the file at the absolute `path` from `offset` into `buf`. Returns the
+/// number of bytes read, less than asked at the end of the file.
+pub fn read_at(path: &str, offset: u64, buf: &mut [u8]) -> LinuxResult<usize> {
+    let size = disk_size(path)?;
+    if offset >= size {
+        return Ok(0);
+    }
+    let len = buf.len().min((size - offset) as usize);
+    let ino = inode::ino(path);
+    let mut cache = CACHE.lock();
+    cache.clock += 1;
+    let clock = cache.clock;
+    let file = cache.file(ino, path);
+    let mut added = 0;
+    let mut done = 0;
+    while done < len {
+        let pos = offset + done as u64;
+        let (index, start) = (pos / PAGE_SIZE, (pos % PAGE_SIZE) as usize);
+        added += file.fill(index, size, clock)?;
+        let n = (PAGE_SIZE_4K - start).min(len - done);
+        buf[done..done + n].copy_from_slice(&file.pages[&index].frame.as_slice()[start..start + n]);
+        file.ra_next = index + 1;
+        done += n;
+    }
+    cache.count += added;
+    cache.shrink();
+    Ok(len)
+}
+
+/// Write `data` at `offset` of the file at the absolute `path`, in the cache
+/// only. The caller makes sure the write doesn't extend the file.
+pub fn write_at(path: &str, offset: u64, data: &[u8]) -> LinuxResult<usize> {
+    let size = disk_size(path)?;
+    if offset + data.len() as u64 > size {
+        return Err(LinuxError::EINVAL);
+    }
+    let ino = inode::ino(path);
+    let mut cache = CACHE.lock();
+    cache.clock += 1;
+    let clock = cache.clock;
+    let file = cache.file(ino, path);
+    let mut added = 0;
+    let mut done = 0;
+    while done < data.len() {
+        let pos = offset + done as u64;
+        let (index, start) = (pos / PAGE_SIZE, (pos % PAGE_SIZE) as usize);
+        let n = (PAGE_SIZE_4K - start).min(data.len() - done);
+        // A page overwritten whole needn't be read first
+        if n == PAGE_SIZE_4K && !file.pages.contains_key(&index) {
+            let page = Page {
+                frame: Frame::alloc()?,
+                dirty: false,
+                mapped: false,
+                used: clock,
+            };
+            file.pages.insert(index, page);
+            added += 1;
+        } else {
+            added += file.fill(index, size, clock)?;
+        }
+        let page = file.pages.get_mut(&index).unwrap();
+        page.frame.as_mut_slice()[start..start + n].copy_from_slice(&data[done..done + n]);
+        page.dirty = true;
+        done += n;
+    }
+    cache.count += added;
+    cache.shrink();
+    Ok(data.len())
+}
+
+/// Bring the cached pages of the file at `path` up to date with `data`, just
+/// written to the file itself at `offset`.
+pub fn update(path: &str, offset: u64, data: &[u8]) {
+    let ino = inode::ino(path);
+    let mut cache = CACHE.lock();
+    let Some(file) = cache.files.get_mut(&ino) else {
+        return;
+    };
+    let mut done = 0;
+    while done < data.len() {
+        let pos = offset + done as u64;
+        let (index, start) = (pos / PAGE_SIZE, (pos % PAGE_SIZE) as usize);
+        let n = (PAGE_SIZE_4K - start).min(data.len() - done);
+        if let Some(page) = file.pages.get(&index) {
+            page.frame.as_mut_slice()[start..start + n].copy_from_slice(&data[done..done + n]);
+        }
+        done += n;
+    }
+}
+
+/// The cached pages of `[offset, offset + len)` of the file at `path`, to be
+/// mapped in user space. `shared` tells that they are mapped shared and
+/// writable, and are to be written back from then on.
+pub fn frames(path: &str, offset: u64, len: usize, shared: bool) -> LinuxResult<Vec<Arc<Frame>>> {
+    let size = disk_size(path)?;
+    let ino = inode::ino(path);
+    let mut cache = CACHE.lock();
+    cache.clock += 1;
+    let clock = cache.clock;
+    let file = cache.file(ino, path);
+    let mut added = 0;
+    let mut frames = Vec::new();
+    let first = offset / PAGE_SIZE;
+    for index in first..first + len.div_ceil(PAGE_SIZE_4K) as u64 {
+        added += file.fill(index, size, clock)?;
+        let page = file.pages.get_mut(&index).unwrap();
+        page.mapped |= shared;
+        frames.push(page.frame.clone());
+    }
+    cache.count += added;
+    cache.shrink();
+    Ok(frames)
+}
+
+/// Write the cached pages of the file at `path` back to it.
+pub fn sync(path: &str) -> LinuxResult {
+    let ino = inode::ino(path);
+    match CACHE.lock().files.get_mut(&ino) {
+        Some(file) => file.write_back(),
+        None => Ok(()),
+    }
+}
+
+/// Write all the cached pages back to their files.
+pub fn sync_all() -> LinuxResult {
+    let mut res = Ok(());
+    for file in CACHE.lock().files.values_mut() {
+        if let Err(e) = file.write_back() {
+            warn!("Failed to write back {}: {:?}", file.path, e);
+            res = Err(e);
+        }
+    }
+    res
+}
+
+/// Drop the pages of the file at `path` past `len`, after it was truncated.
+pub fn truncate(path: &str, len: u64) {
+    let ino = inode::ino(path);
+    let mut cache = CACHE.lock();
+    let Some(file) = cache.files.get_mut(&ino) else {
+        return;
+    };
+    let kept = len.div_ceil(PAGE_SIZE);
+    let before = file.pages.len();
+    file.pages.retain(|&index, page| {
+        if index >= kept && page.mapped {
+            page.frame.as_mut_slice().fill(0);
+        }
+        index < kept || page.mapped
+    });
+    let removed = before - file.pages.len();
+    // The rest of the last page reads as zeroes if the file grows again
+    if len % PAGE_SIZE != 0 {
+        if let Some(page) = file.pages.get(&(len / PAGE_SIZE)) {
+            page.frame.as_mut_slice()[(len % PAGE_SIZE) as usize..].fill(0);
+        }
+    }
+    cache.count -= removed;
+}
+
+/// Write back and forget the pages of the file at `path`, before it is
+/// removed. Its pages mapped in user space stay there.
+pub fn remove(path: &str) {
+    let ino = inode::ino(path);
+    let mut cache = CACHE.lock();
+    let Some(mut file) = cache.files.remove(&ino) else {
+        return;
+    };
+    if let Err(e) = file.write_back() {
+        warn!("Failed to write back {}: {:?}", path, e);
+    }
+    cache.count -= file.pages.len();
+}
+
+/// Keep the cached files at `old`, and below the directory `old`, for `new`.
+pub fn rename(old: &str, new: &str) {
+    for file in CACHE.lock().files.values_mut() {
+        if let Some(rest) = file.path.strip_prefix(old) {
+            if rest.is_empty() || rest.starts_with('/') {
+                file.path = format!("{}{}", new, rest);
+            }
+        }
+    }
+}
+
+/// Forget all the cached pages without writing them back, once the files
+/// they belong to have been discarded.
+pub fn clear() {
+    let mut cache = CACHE.lock();
+    cache.files.clear();
+    cache.count = 0;
+}
+
+/// Drop up to `count` clean pages which aren't mapped anywhere, the least
+/// recently used first, to free their frames. Returns the number of pages
+/// dropped.
+///
+/// Does nothing if the cache is busy, e.g. when allocating a frame for it ran
+/// out of memory.
+pub fn reclaim(count: usize) -> usize {
+    let Some(mut cache) = CACHE.try_lock() else {
+        return 0;
+    };
+    let mut candidates: Vec<(u64, u64, u64)> = cache
+        .files
+        .iter()
+        .flat_map(|(&ino, file)| {
+            file.pages
+                .iter()
+                .filter(|(_, page)| {
+                    !page.dirty && !page.mapped && Arc::strong_count(&page.frame) == 1
+                })
+                .map(move |(&index, page)| (page.used, ino, index))
+        })
+        .collect();
+    candidates.sort_unstable();
+    candidates.truncate(count);
+    for &(_, ino, index) in &candidates {
+        cache.files.get_mut(&ino).unwrap().pages.remove(&index);
+    }
+    cache.count -= candidates.len();
+    cache.files.retain(|_, file| !file.pages.is_empty());
+    candidates.len()
+}
+
+/// The memory taken by the cache, in bytes.
+pub fn cached_bytes() -> usize {
+    CACHE.lock().count * PAGE_SIZE_4K
+}
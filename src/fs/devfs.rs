@@ -0,0 +1,392 @@
+//! Device files under `/dev` which are implemented by the kernel itself.
+//!
+//! Like procfs, opening one of these paths bypasses the filesystem and
+//! installs the device in the fd table. Device files are character devices
+//! identified by their major and minor numbers, as on Linux.
+//!
+//! Drivers add their devices at boot with [`register_chrdev`]. A name with
+//! slashes, like `input/event0`, implies the directories above the device,
+//! which are listed along with it.
+use super::meta::S_IFDIR;
+use super::procfs::ProcDir;
+use super::symlink;
+use crate::process::current_process;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::mem::{memory_regions, MemRegionFlags};
+use axhal::paging::MappingFlags;
+use axio::PollState;
+use axsync::Mutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+use memory_addr::PhysAddr;
+
+/// The directory holding the device files.
+pub const DEV_ROOT: &str = "/dev";
+
+/// The file type of a character device in `st_mode`.
+const S_IFCHR: u32 = 0o020000;
+
+const O_NONBLOCK: i32 = 0o4000;
+
+/// Encode a device number the way glibc's `makedev` does.
+pub const fn makedev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    ((major & 0xfffff000) << 32)
+        | ((major & 0xfff) << 8)
+        | ((minor & 0xffffff00) << 12)
+        | (minor & 0xff)
+}
+
+/// The `stat` of a character device with the given permissions.
+pub fn chrdev_stat(mode: u32, rdev: u64) -> ctypes::stat {
+    ctypes::stat {
+        st_mode: S_IFCHR | mode,
+        st_nlink: 1,
+        st_rdev: rdev,
+        st_blksize: 4096,
+        ..Default::default()
+    }
+}
+
+const DEV_MEM_RDEV: u64 = makedev(1, 1);
+
+/// `/dev/mem`, the physical address space.
+///
+/// It can only be mapped, not read or written, and only outside RAM, so that
+/// it exposes MMIO registers but never kernel memory.
+pub struct DevMem;
+
+impl DevMem {
+    /// Check that `[paddr, paddr + size)` may be mapped into user space, i.e.
+    /// it doesn't overlap any RAM known to the kernel.
+    pub fn check_range(paddr: PhysAddr, size: usize) -> LinuxResult {
+        let end = paddr
+            .as_usize()
+            .checked_add(size)
+            .ok_or(LinuxError::EINVAL)?;
+        let overlaps_ram = memory_regions().any(|region| {
+            !region.flags.contains(MemRegionFlags::DEVICE)
+                && region.paddr.as_usize() < end
+                && paddr.as_usize() < region.paddr.as_usize() + region.size
+        });
+        if overlaps_ram {
+            return Err(LinuxError::EPERM);
+        }
+        Ok(())
+    }
+}
+
+impl api::FileLike for DevMem {
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(chrdev_stat(0o640, DEV_MEM_RDEV))
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: false,
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+fn open_mem() -> LinuxResult<Arc<dyn api::FileLike>> {
+    // Raw access to the hardware needs CAP_SYS_RAWIO
+    if !current_process().unwrap().cred().is_privileged() {
+        return Err(LinuxError::EPERM);
+    }
+    Ok(Arc::new(DevMem))
+}
+
+/// A character device registered by a driver.
+///
+/// The methods are called for the reads, writes and ioctls on any open file
+/// of the device. The defaults reject the operation.
+///
+/// A read fails with `EAGAIN` when there is no data yet. The open file then
+/// waits for data with [`wait_readable`](CharDevice::wait_readable), unless it
+/// was opened with `O_NONBLOCK`.
+pub trait CharDevice: Send + Sync {
+    /// Called on every open of the device file, which fails on an error.
+    fn open(&self) -> LinuxResult {
+        Ok(())
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn ioctl(&self, _op: usize, _argp: usize) -> LinuxResult<isize> {
+        Err(LinuxError::ENOTTY)
+    }
+
+    /// The physical memory backing `[offset, offset + size)` of the device,
+    /// and the extra flags to map it with. `size` is a multiple of the page
+    /// size.
+    fn mmap(&self, _offset: usize, _size: usize) -> LinuxResult<(PhysAddr, MappingFlags)> {
+        Err(LinuxError::ENODEV)
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: true,
+        })
+    }
+
+    /// Sleep until a read may find data, for the devices whose reads fail
+    /// with `EAGAIN`. Fails with `EINTR` if a signal arrives first.
+    fn wait_readable(&self) -> LinuxResult {
+        Err(LinuxError::EAGAIN)
+    }
+}
+
+/// An open file of a [`CharDevice`].
+pub struct DevFile {
+    dev: Arc<dyn CharDevice>,
+    stat: ctypes::stat,
+    nonblocking: AtomicBool,
+}
+
+impl DevFile {
+    pub fn ioctl(&self, op: usize, argp: usize) -> LinuxResult<isize> {
+        self.dev.ioctl(op, argp)
+    }
+
+    pub fn mmap(&self, offset: usize, size: usize) -> LinuxResult<(PhysAddr, MappingFlags)> {
+        self.dev.mmap(offset, size)
+    }
+}
+
+impl api::FileLike for DevFile {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        loop {
+            match self.dev.read(buf) {
+                Err(LinuxError::EAGAIN) if !self.nonblocking.load(Ordering::Relaxed) => {
+                    self.dev.wait_readable()?;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        self.dev.write(buf)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(self.stat)
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        self.dev.poll()
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+type OpenFn = Arc<dyn Fn() -> LinuxResult<Arc<dyn api::FileLike>> + Send + Sync>;
+
+/// A device file, opened by calling `open`.
+#[derive(Clone)]
+struct Device {
+    /// The permission bits of the device file
+    mode: u32,
+    rdev: u64,
+    open: OpenFn,
+}
+
+/// The device files, keyed by their path relative to `/dev`.
+static DEVICES: Mutex<BTreeMap<String, Device>> = Mutex::new(BTreeMap::new());
+
+fn register(name: &str, mode: u32, rdev: u64, open: OpenFn) -> LinuxResult {
+    let mut devices = DEVICES.lock();
+    if devices.contains_key(name) {
+        return Err(LinuxError::EEXIST);
+    }
+    devices.insert(String::from(name), Device { mode, rdev, open });
+    Ok(())
+}
+
+/// Make the character device `dev` appear as `/dev/<name>`.
+///
+/// Fails with `EEXIST` if the name is taken.
+pub fn register_chrdev(name: &str, mode: u32, rdev: u64, dev: Arc<dyn CharDevice>) -> LinuxResult {
+    let stat = chrdev_stat(mode, rdev);
+    register(
+        name,
+        mode,
+        rdev,
+        Arc::new(move || {
+            dev.open()?;
+            Ok(Arc::new(DevFile {
+                dev: dev.clone(),
+                stat,
+                nonblocking: AtomicBool::new(false),
+            }) as Arc<dyn api::FileLike>)
+        }),
+    )
+}
+
+/// The links under `/dev` to the fds of the calling process.
+const FD_LINKS: [(&str, &str); 4] = [
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+];
+
+/// Register the device files implemented by the kernel itself, and the
+/// standard links to the fds.
+pub fn init() {
+    register("mem", 0o640, DEV_MEM_RDEV, Arc::new(open_mem)).unwrap();
+    for (name, target) in FD_LINKS {
+        symlink::create(&format!("{}/{}", DEV_ROOT, name), target).unwrap();
+    }
+}
+
+fn find_device(path: &str) -> Option<Device> {
+    let name = path.strip_prefix(DEV_ROOT)?.strip_prefix('/')?;
+    DEVICES.lock().get(name).cloned()
+}
+
+/// The files of the kernel right in the directory `dir`, with their modes:
+/// the devices, and the directories implied by the names of the others.
+pub fn list(dir: &str) -> Vec<(String, u32)> {
+    let prefix = match dir.strip_prefix(DEV_ROOT) {
+        Some("") => String::new(),
+        Some(rel) if rel.starts_with('/') => format!("{}/", &rel[1..]),
+        _ => return Vec::new(),
+    };
+    let mut entries: Vec<(String, u32)> = Vec::new();
+    for (name, dev) in DEVICES.lock().iter() {
+        let Some(rest) = name.strip_prefix(prefix.as_str()) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            None => entries.push((String::from(rest), S_IFCHR | dev.mode)),
+            Some((subdir, _)) => {
+                if !entries.iter().any(|(name, _)| name == subdir) {
+                    entries.push((String::from(subdir), S_IFDIR | 0o555));
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// Whether the absolute, normalized `path` is a directory implied by the
+/// name of a device, like `/dev/input`.
+fn is_dir(path: &str) -> bool {
+    let Some(name) = path
+        .strip_prefix(DEV_ROOT)
+        .and_then(|rel| rel.strip_prefix('/'))
+    else {
+        return false;
+    };
+    let prefix = format!("{}/", name);
+    DEVICES
+        .lock()
+        .keys()
+        .any(|device| device.starts_with(&prefix))
+}
+
+/// Whether the absolute, normalized `path` is a device file of the kernel,
+/// or one of the directories holding them.
+pub fn is_devfs_path(path: &str) -> bool {
+    find_device(path).is_some() || is_dir(path)
+}
+
+/// The `stat` of the device file at `path`, which doesn't require opening it.
+pub fn stat(path: &str) -> LinuxResult<ctypes::stat> {
+    if is_dir(path) {
+        return Ok(ctypes::stat {
+            st_mode: S_IFDIR | 0o555,
+            st_nlink: 2,
+            st_blksize: 4096,
+            ..Default::default()
+        });
+    }
+    let dev = find_device(path).ok_or(LinuxError::ENOENT)?;
+    Ok(chrdev_stat(dev.mode, dev.rdev))
+}
+
+/// Open the device at the absolute, normalized `path`, or the directory.
+pub fn open(path: &str) -> LinuxResult<Arc<dyn api::FileLike>> {
+    if is_dir(path) {
+        return Ok(Arc::new(ProcDir::new(path, list(path))));
+    }
+    (find_device(path).ok_or(LinuxError::ENOENT)?.open)()
+}
+
+/// Open `path` as a device with the `open` flags `flags` and install it in
+/// the fd table.
+pub fn open_fd(path: &str, flags: i32) -> LinuxResult<isize> {
+    let file = open(path)?;
+    file.set_nonblocking(flags & O_NONBLOCK != 0)?;
+    api::add_file_like(file).map(|fd| fd as isize)
+}
+
+/// Install `dev` in the fd table as a file with no path, like the lines
+/// requested from a GPIO chip.
+pub fn add_anon(dev: Arc<dyn CharDevice>) -> LinuxResult<i32> {
+    let file = DevFile {
+        dev,
+        stat: ctypes::stat {
+            st_mode: 0o600,
+            st_nlink: 1,
+            st_blksize: 4096,
+            ..Default::default()
+        },
+        nonblocking: AtomicBool::new(false),
+    };
+    api::add_file_like(Arc::new(file))
+}
+
+/// Whether `fd` is a device file of devfs.
+pub fn is_devfs_fd(fd: i32) -> bool {
+    api::get_file_like(fd).is_ok_and(|file| {
+        let file = file.into_any();
+        file.is::<DevFile>() || file.is::<DevMem>()
+    })
+}
+
+/// The driver-registered device opened as `fd`, if it is one.
+pub fn dev_file_from_fd(fd: i32) -> Option<Arc<DevFile>> {
+    api::get_file_like(fd)
+        .ok()?
+        .into_any()
+        .downcast::<DevFile>()
+        .ok()
+}
@@ -0,0 +1,56 @@
+//! Read positions of the open directories of `axfs`.
+//!
+//! `axfs` keeps no position in a directory, so it is kept here for each open
+//! directory, and shared by the fds duplicated from the same `open` like the
+//! position in a file. The position is the number of entries `getdents64` has
+//! returned so far, which `lseek` can move back to or forward.
+//!
+//! The directories are known by the address of their open file. A weak
+//! reference to it is kept along, so that the address isn't reused by another
+//! file while it is in the table.
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use arceos_posix_api::{self as api, FileLike};
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+
+static POSITIONS: Mutex<BTreeMap<usize, (Weak<dyn FileLike>, u64)>> = Mutex::new(BTreeMap::new());
+
+fn key(file: &Arc<dyn FileLike>) -> usize {
+    Arc::as_ptr(file) as *const () as usize
+}
+
+/// The position in the directory opened as `fd`.
+pub fn pos(fd: i32) -> LinuxResult<u64> {
+    let file = api::get_file_like(fd)?;
+    Ok(POSITIONS.lock().get(&key(&file)).map_or(0, |&(_, pos)| pos))
+}
+
+/// Move the position in the directory opened as `fd` to `pos`.
+pub fn set_pos(fd: i32, pos: u64) -> LinuxResult {
+    let file = api::get_file_like(fd)?;
+    let mut positions = POSITIONS.lock();
+    // Forget the directories closed since
+    positions.retain(|_, (file, _)| file.strong_count() > 0);
+    positions.insert(key(&file), (Arc::downgrade(&file), pos));
+    Ok(())
+}
+
+/// Move the position in the directory opened as `fd`, as `lseek` does. There
+/// is no end to seek from.
+pub fn seek(fd: i32, offset: i64, whence: i32) -> LinuxResult<u64> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => pos(fd)? as i64,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let new = base.checked_add(offset).ok_or(LinuxError::EOVERFLOW)?;
+    if new < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    set_pos(fd, new as u64)?;
+    Ok(new as u64)
+}
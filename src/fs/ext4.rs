@@ -0,0 +1,177 @@
+//! POSIX semantics on an ext4 root filesystem.
+//!
+//! `axfs` only offers what FAT can do, which is why symbolic links, owners and
+//! modes are otherwise kept in tables of the kernel. When the root filesystem
+//! is ext4, driven by lwext4, they are stored in the image itself instead, by
+//! calling lwext4 directly, and hard links become possible.
+//!
+//! This covers the files of the image only: not those of the other
+//! filesystems of `axfs`, of the filesystems mounted by the kernel, or those
+//! the overlay keeps in `/tmp`.
+use super::meta::{S_IFLNK, S_IFMT, S_IPERM};
+use super::statfs::{FsStats, EXT4_SUPER_MAGIC};
+use super::{mount, overlay, to_cstring};
+use alloc::string::String;
+use alloc::vec;
+use arceos_posix_api::ctypes;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use core::ffi::c_int;
+use lazy_static::lazy_static;
+use lwext4_rust::bindings;
+
+/// Where lwext4 mounts the root filesystem of `axfs`.
+const MOUNT_POINT: &core::ffi::CStr = c"/";
+
+/// The filesystems `axfs` mounts over the root one.
+const AXFS_MOUNTS: [&str; 4] = ["/dev", "/proc", "/sys", "/tmp"];
+
+/// The longest target of a symbolic link.
+const PATH_MAX: usize = 4096;
+
+lazy_static! {
+    /// Whether the root filesystem is ext4.
+    static ref ENABLED: bool = {
+        let mut stats = unsafe { core::mem::zeroed::<bindings::ext4_mount_stats>() };
+        let _guard = LOCK.lock();
+        unsafe { bindings::ext4_mount_point_stats(MOUNT_POINT.as_ptr(), &mut stats) == 0 }
+    };
+}
+
+/// Serializes the calls made here, which `axfs` doesn't know about.
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn check(ret: c_int) -> LinuxResult {
+    match ret {
+        0 => Ok(()),
+        errno => Err(LinuxError::try_from(errno).unwrap_or(LinuxError::EIO)),
+    }
+}
+
+/// Whether the root filesystem is ext4.
+pub fn is_enabled() -> bool {
+    *ENABLED
+}
+
+/// Whether the file at the absolute, normalized `path` is stored in an ext4
+/// root filesystem.
+pub fn covers(path: &str) -> bool {
+    let in_axfs_mount = AXFS_MOUNTS.iter().any(|dir| {
+        path.strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    is_enabled() && !in_axfs_mount && !overlay::covers(path) && !mount::is_mount_path(path)
+}
+
+/// Make `new` another name of the file at `old`.
+pub fn link(old: &str, new: &str) -> LinuxResult {
+    let (old, new) = (to_cstring(old)?, to_cstring(new)?);
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_flink(old.as_ptr(), new.as_ptr()) })
+}
+
+/// Create a symbolic link at `path` pointing to `target`.
+pub fn symlink(target: &str, path: &str) -> LinuxResult {
+    let (target, path) = (to_cstring(target)?, to_cstring(path)?);
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_fsymlink(target.as_ptr(), path.as_ptr()) })
+}
+
+/// The target of the symbolic link at `path`, if it is one.
+pub fn read_link(path: &str) -> Option<String> {
+    if !covers(path) || mode(path).ok()? & S_IFMT != S_IFLNK {
+        return None;
+    }
+    let cpath = to_cstring(path).ok()?;
+    let mut buf = vec![0u8; PATH_MAX];
+    let mut len = 0;
+    let _guard = LOCK.lock();
+    let ret = unsafe {
+        bindings::ext4_readlink(cpath.as_ptr(), buf.as_mut_ptr() as _, buf.len(), &mut len)
+    };
+    check(ret).ok()?;
+    buf.truncate(len);
+    String::from_utf8(buf).ok()
+}
+
+/// The mode of the file at `path`, its type included.
+pub fn mode(path: &str) -> LinuxResult<u32> {
+    let cpath = to_cstring(path)?;
+    let mut mode = 0;
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_mode_get(cpath.as_ptr(), &mut mode) })?;
+    Ok(mode)
+}
+
+/// Set the permission bits of the file at `path`.
+pub fn set_mode(path: &str, mode: u32) -> LinuxResult {
+    let cpath = to_cstring(path)?;
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_mode_set(cpath.as_ptr(), mode & S_IPERM) })
+}
+
+/// The owner and group of the file at `path`.
+pub fn owner(path: &str) -> LinuxResult<(u32, u32)> {
+    let cpath = to_cstring(path)?;
+    let (mut uid, mut gid) = (0, 0);
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_owner_get(cpath.as_ptr(), &mut uid, &mut gid) })?;
+    Ok((uid, gid))
+}
+
+/// Set the owner and group of the file at `path`.
+pub fn set_owner(path: &str, uid: u32, gid: u32) -> LinuxResult {
+    let cpath = to_cstring(path)?;
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_owner_set(cpath.as_ptr(), uid, gid) })
+}
+
+/// Write the blocks lwext4 buffers back to the disk.
+pub fn flush() -> LinuxResult {
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_cache_flush(MOUNT_POINT.as_ptr()) })
+}
+
+/// The statistics of the filesystem.
+pub fn stats() -> LinuxResult<FsStats> {
+    let mut stats = unsafe { core::mem::zeroed::<bindings::ext4_mount_stats>() };
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_mount_point_stats(MOUNT_POINT.as_ptr(), &mut stats) })?;
+    Ok(FsStats {
+        bsize: stats.block_size as u64,
+        blocks: stats.blocks_count,
+        bfree: stats.free_blocks_count,
+        bavail: stats.free_blocks_count,
+        files: stats.inodes_count as u64,
+        ffree: stats.free_inodes_count as u64,
+        ..FsStats::empty(EXT4_SUPER_MAGIC)
+    })
+}
+
+fn raw_inode(path: &str) -> LinuxResult<(u32, bindings::ext4_inode)> {
+    let cpath = to_cstring(path)?;
+    let mut ino = 0;
+    let mut inode = unsafe { core::mem::zeroed::<bindings::ext4_inode>() };
+    let _guard = LOCK.lock();
+    check(unsafe { bindings::ext4_raw_inode_fill(cpath.as_ptr(), &mut ino, &mut inode) })?;
+    Ok((ino, inode))
+}
+
+/// The inode number of the file at `path`, shared by all its hard links.
+pub fn ino(path: &str) -> LinuxResult<u64> {
+    Ok(raw_inode(path)?.0 as u64)
+}
+
+/// Fill in `stat` with the permissions, owner and link count stored in the
+/// image for the file at `path`.
+pub fn apply(path: &str, stat: &mut ctypes::stat) {
+    let Ok((_, inode)) = raw_inode(path) else {
+        return;
+    };
+    stat.st_mode = (stat.st_mode & S_IFMT) | (inode.mode as u32 & S_IPERM);
+    stat.st_nlink = inode.links_count as _;
+    if let Ok((uid, gid)) = owner(path) {
+        stat.st_uid = uid;
+        stat.st_gid = gid;
+    }
+}
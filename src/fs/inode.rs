@@ -0,0 +1,71 @@
+//! Stable inode numbers.
+//!
+//! The FAT image has no inode numbers, so they are assigned here on first
+//! use, keyed by the absolute path. `stat` and `getdents64` both go through
+//! [`ino`], so the numbers they report agree for as long as the file exists.
+//! An ext4 root filesystem reports its own.
+use super::ext4;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use axsync::Mutex;
+
+/// The inode number of the root directory, as on most Linux filesystems.
+const ROOT_INO: u64 = 2;
+/// Added to the numbers assigned here on an ext4 root filesystem, so that they
+/// can't collide with the 32-bit ones of ext4.
+const EXT4_INO_END: u64 = 1 << 32;
+
+struct InodeTable {
+    inos: BTreeMap<String, u64>,
+    next: u64,
+}
+
+static INODES: Mutex<InodeTable> = Mutex::new(InodeTable {
+    inos: BTreeMap::new(),
+    next: ROOT_INO + 1,
+});
+/// The numbers when the overlay was enabled, which [`restore`] puts back.
+static SAVED: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// The inode number of the file at the absolute, normalized `path`.
+pub fn ino(path: &str) -> u64 {
+    if path == "/" {
+        return ROOT_INO;
+    }
+    // ext4 has real inode numbers, shared by hard links
+    if ext4::covers(path) {
+        if let Ok(ino) = ext4::ino(path) {
+            return ino;
+        }
+    }
+    let base = if ext4::is_enabled() { EXT4_INO_END } else { 0 };
+    let mut table = INODES.lock();
+    if let Some(&ino) = table.inos.get(path) {
+        return base + ino;
+    }
+    let ino = table.next;
+    table.next += 1;
+    table.inos.insert(String::from(path), ino);
+    base + ino
+}
+
+/// Forget the inode number of `path` after the file has been removed.
+pub fn remove(path: &str) {
+    INODES.lock().inos.remove(path);
+}
+
+/// Remember the inode numbers assigned, for [`restore`].
+pub fn save() {
+    *SAVED.lock() = INODES.lock().inos.clone();
+}
+
+/// Put back the inode numbers of the files in the overlay as [`save`] found
+/// them. The numbers assigned since aren't reused.
+pub fn restore() {
+    super::restore_keys(&mut INODES.lock().inos, &SAVED.lock());
+}
+
+/// Keep the inode numbers of `old`, and of the files below it, for `new`.
+pub fn rename(old: &str, new: &str) {
+    super::rename_keys(&mut INODES.lock().inos, old, new);
+}
@@ -0,0 +1,199 @@
+//! Anonymous files made by `memfd_create`.
+//!
+//! A memfd is a set of [`Frame`]s which grows and shrinks with `ftruncate` and
+//! writes. Mapping it `MAP_SHARED` maps those frames themselves, so every
+//! mapping, in any address space, and the file descriptor see the same bytes.
+use super::meta::S_IFREG;
+use crate::mm::Frame;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use memory_addr::PAGE_SIZE_4K;
+
+pub const MFD_CLOEXEC: u32 = 1;
+pub const MFD_ALLOW_SEALING: u32 = 2;
+
+/// The longest name, without the `memfd:` prefix.
+pub const MFD_NAME_MAX: usize = 249;
+
+/// The largest memfd, the same limit as a SysV segment.
+const MEMFD_MAX: u64 = 1 << 30;
+
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+const SEEK_END: i32 = 2;
+
+/// The inode numbers of the memfds, which have no path to derive one from.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+struct MemFdInner {
+    /// The pages of the file, enough to hold `size` bytes
+    frames: Vec<Arc<Frame>>,
+    size: u64,
+    pos: u64,
+}
+
+impl MemFdInner {
+    fn resize(&mut self, size: u64) -> LinuxResult {
+        if size > MEMFD_MAX {
+            return Err(LinuxError::EFBIG);
+        }
+        let count = (size as usize).div_ceil(PAGE_SIZE_4K);
+        if count < self.frames.len() {
+            self.frames.truncate(count);
+        }
+        while self.frames.len() < count {
+            self.frames.push(Frame::alloc()?);
+        }
+        // What lies past the end reads as zeroes if the file grows again
+        if size < self.size && size % PAGE_SIZE_4K as u64 != 0 {
+            let page = &self.frames[count - 1];
+            page.as_mut_slice()[size as usize % PAGE_SIZE_4K..].fill(0);
+        }
+        self.size = size;
+        Ok(())
+    }
+}
+
+/// An anonymous file made by `memfd_create`.
+pub struct MemFd {
+    name: String,
+    ino: u64,
+    inner: Mutex<MemFdInner>,
+}
+
+impl MemFd {
+    pub fn new(name: &str) -> Arc<Self> {
+        Arc::new(Self {
+            name: format!("/memfd:{} (deleted)", name),
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            inner: Mutex::new(MemFdInner {
+                frames: Vec::new(),
+                size: 0,
+                pos: 0,
+            }),
+        })
+    }
+
+    /// The path shown in `/proc/<pid>/fd`.
+    pub fn path(&self) -> &str {
+        &self.name
+    }
+
+    /// Change the size of the file, as `ftruncate` does.
+    pub fn set_len(&self, size: u64) -> LinuxResult {
+        self.inner.lock().resize(size)
+    }
+
+    /// Move the file position, as `lseek` does.
+    pub fn seek(&self, offset: i64, whence: i32) -> LinuxResult<u64> {
+        let mut inner = self.inner.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => inner.pos as i64,
+            SEEK_END => inner.size as i64,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let new = base.checked_add(offset).ok_or(LinuxError::EOVERFLOW)?;
+        if new < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        inner.pos = new as u64;
+        Ok(inner.pos)
+    }
+
+    /// The frames holding `[offset, offset + len)`, which must be page
+    /// aligned, growing nothing: `SIGBUS` on access past the end isn't
+    /// emulated, so the range must be inside the file.
+    pub fn frames(&self, offset: usize, len: usize) -> LinuxResult<Vec<Arc<Frame>>> {
+        let inner = self.inner.lock();
+        let first = offset / PAGE_SIZE_4K;
+        let count = len.div_ceil(PAGE_SIZE_4K);
+        inner
+            .frames
+            .get(first..first + count)
+            .map(<[_]>::to_vec)
+            .ok_or(LinuxError::ENXIO)
+    }
+}
+
+impl api::FileLike for MemFd {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let mut inner = self.inner.lock();
+        let mut count = 0;
+        while count < buf.len() && inner.pos < inner.size {
+            let pos = inner.pos as usize;
+            let in_page = pos % PAGE_SIZE_4K;
+            let len = (buf.len() - count)
+                .min(PAGE_SIZE_4K - in_page)
+                .min((inner.size - inner.pos) as usize);
+            let page = inner.frames[pos / PAGE_SIZE_4K].as_slice();
+            buf[count..count + len].copy_from_slice(&page[in_page..in_page + len]);
+            count += len;
+            inner.pos += len as u64;
+        }
+        Ok(count)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        let mut inner = self.inner.lock();
+        let end = inner.pos + buf.len() as u64;
+        if end > inner.size {
+            inner.resize(end)?;
+        }
+        let mut count = 0;
+        while count < buf.len() {
+            let pos = inner.pos as usize;
+            let in_page = pos % PAGE_SIZE_4K;
+            let len = (buf.len() - count).min(PAGE_SIZE_4K - in_page);
+            let page = inner.frames[pos / PAGE_SIZE_4K].as_mut_slice();
+            page[in_page..in_page + len].copy_from_slice(&buf[count..count + len]);
+            count += len;
+            inner.pos += len as u64;
+        }
+        Ok(count)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let size = self.inner.lock().size;
+        Ok(ctypes::stat {
+            st_ino: self.ino,
+            st_mode: S_IFREG | 0o777,
+            st_nlink: 1,
+            st_size: size as _,
+            st_blksize: PAGE_SIZE_4K as _,
+            st_blocks: size.div_ceil(512) as _,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: true,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// The memfd open as `fd`, if it is one.
+pub fn file_from_fd(fd: i32) -> Option<Arc<MemFd>> {
+    api::get_file_like(fd)
+        .ok()?
+        .into_any()
+        .downcast::<MemFd>()
+        .ok()
+}
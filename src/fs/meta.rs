@@ -0,0 +1,276 @@
+//! File ownership and permission metadata kept by the kernel.
+//!
+//! The FAT image can't store POSIX owners and modes, so they are recorded here
+//! by path and laid over the attributes reported by the underlying filesystem.
+//!
+//! Unless the `fs/meta-persist` sysctl is cleared, every change is also
+//! appended to [`META_FILE`] in the root of the image, which is read back at
+//! boot, so that the metadata survives reboots while the image stays FAT. The
+//! file can't be reached from user space.
+use crate::sysctl;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use arceos_posix_api::ctypes;
+use axstd::fs::OpenOptions;
+use axstd::io::Write as _;
+use axsync::Mutex;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The file type bits of `st_mode`.
+pub const S_IFMT: u32 = 0o170000;
+/// The file type of a symbolic link in `st_mode`.
+pub const S_IFLNK: u32 = 0o120000;
+/// The file type of a directory in `st_mode`.
+pub const S_IFDIR: u32 = 0o040000;
+/// The file type of a regular file in `st_mode`.
+pub const S_IFREG: u32 = 0o100000;
+/// The file type of a pipe in `st_mode`.
+pub const S_IFIFO: u32 = 0o010000;
+/// The permission bits of `st_mode`, including setuid, setgid and sticky.
+pub const S_IPERM: u32 = 0o7777;
+
+/// Ownership and permissions of a file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileMeta {
+    /// Permission bits, if changed from what the filesystem reports
+    pub mode: Option<u32>,
+    /// Owner, if changed from what the filesystem reports
+    pub uid: Option<u32>,
+    /// Group, if changed from what the filesystem reports
+    pub gid: Option<u32>,
+}
+
+impl FileMeta {
+    /// Whether nothing differs from what the filesystem reports.
+    pub fn is_unchanged(&self) -> bool {
+        self.mode.is_none() && self.uid.is_none() && self.gid.is_none()
+    }
+
+    /// Lay the metadata over a `stat` read from the filesystem.
+    pub fn apply(&self, stat: &mut ctypes::stat) {
+        if let Some(mode) = self.mode {
+            stat.st_mode = (stat.st_mode & S_IFMT) | (mode & S_IPERM);
+        }
+        if let Some(uid) = self.uid {
+            stat.st_uid = uid;
+        }
+        if let Some(gid) = self.gid {
+            stat.st_gid = gid;
+        }
+    }
+}
+
+static META: Mutex<BTreeMap<String, FileMeta>> = Mutex::new(BTreeMap::new());
+/// The metadata when the overlay was enabled, which [`restore`] puts back.
+static SAVED: Mutex<BTreeMap<String, FileMeta>> = Mutex::new(BTreeMap::new());
+
+/// The file persisting the metadata, which user space can't reach.
+pub const META_FILE: &str = "/.fsmeta";
+
+fn format_field(value: Option<u32>, octal: bool) -> String {
+    match value {
+        Some(value) if octal => format!("{:o}", value),
+        Some(value) => format!("{}", value),
+        None => String::from("-"),
+    }
+}
+
+fn parse_field(field: &str, radix: u32) -> Option<Option<u32>> {
+    match field {
+        "-" => Some(None),
+        field => u32::from_str_radix(field, radix).ok().map(Some),
+    }
+}
+
+/// Parse a line of [`META_FILE`]: `<mode> <uid> <gid> <path>`, where the mode
+/// is in octal and an unchanged field is `-`.
+fn parse_line(line: &str) -> Option<(&str, FileMeta)> {
+    let mut fields = line.splitn(4, ' ');
+    let mode = parse_field(fields.next()?, 8)?;
+    let uid = parse_field(fields.next()?, 10)?;
+    let gid = parse_field(fields.next()?, 10)?;
+    let path = fields.next().filter(|path| path.starts_with('/'))?;
+    Some((path, FileMeta { mode, uid, gid }))
+}
+
+/// The line of [`META_FILE`] for `path`, whose `meta` is `None` once it is
+/// forgotten.
+fn format_line(path: &str, meta: Option<&FileMeta>) -> String {
+    let meta = meta.copied().unwrap_or_default();
+    format!(
+        "{} {} {} {}\n",
+        format_field(meta.mode, true),
+        format_field(meta.uid, false),
+        format_field(meta.gid, false),
+        path
+    )
+}
+
+/// The number of lines in [`META_FILE`], which may hold several for a path.
+static JOURNAL_LINES: AtomicUsize = AtomicUsize::new(0);
+/// Whether [`META_FILE`] missed changes made while persisting was disabled.
+static JOURNAL_STALE: AtomicBool = AtomicBool::new(false);
+
+/// The lines [`META_FILE`] may hold beyond one per path before it is
+/// rewritten.
+const JOURNAL_SLACK: usize = 256;
+
+/// Append the entries of `paths` to [`META_FILE`], if persisting is enabled.
+///
+/// Later lines override earlier ones, so each change costs a line, until the
+/// outdated lines outnumber the paths and the file is rewritten from `meta`.
+fn persist<'a>(meta: &BTreeMap<String, FileMeta>, paths: impl IntoIterator<Item = &'a str>) {
+    // The overlay keeps the image unmodified
+    if super::overlay::is_enabled() {
+        return;
+    }
+    if sysctl::META_PERSIST.get() == 0 {
+        JOURNAL_STALE.store(true, Ordering::Relaxed);
+        return;
+    }
+    // A newline in a path would break the line format
+    let lines: Vec<String> = paths
+        .into_iter()
+        .filter(|path| !path.contains('\n'))
+        .map(|path| format_line(path, meta.get(path)))
+        .collect();
+    let count = JOURNAL_LINES.load(Ordering::Relaxed) + lines.len();
+    let res = if JOURNAL_STALE.load(Ordering::Relaxed) || count > 2 * meta.len() + JOURNAL_SLACK {
+        rewrite(meta)
+    } else {
+        JOURNAL_LINES.store(count, Ordering::Relaxed);
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(META_FILE)
+            .and_then(|mut file| file.write_all(lines.concat().as_bytes()))
+    };
+    if let Err(e) = res {
+        warn!("Failed to persist the file metadata: {:?}", e);
+    }
+}
+
+/// Write [`META_FILE`] anew with one line per path of `meta`.
+fn rewrite(meta: &BTreeMap<String, FileMeta>) -> axstd::io::Result<()> {
+    let lines: Vec<String> = meta
+        .iter()
+        .filter(|(path, _)| !path.contains('\n'))
+        .map(|(path, meta)| format_line(path, Some(meta)))
+        .collect();
+    axfs::api::write(META_FILE, lines.concat())?;
+    JOURNAL_LINES.store(lines.len(), Ordering::Relaxed);
+    JOURNAL_STALE.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Read back the metadata persisted in [`META_FILE`], if any.
+///
+/// A line of a path without any change forgets it.
+pub fn load() {
+    // The disk under a verified root image isn't trusted to describe its files
+    if super::mount::is_mount_point("/") {
+        return;
+    }
+    let Ok(content) = axfs::api::read_to_string(META_FILE) else {
+        return;
+    };
+    let mut meta = META.lock();
+    let mut lines = 0;
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        lines += 1;
+        match parse_line(line) {
+            Some((path, file_meta)) if file_meta.is_unchanged() => {
+                meta.remove(path);
+            }
+            Some((path, file_meta)) => {
+                meta.insert(String::from(path), file_meta);
+            }
+            None => warn!("Malformed line in {}: {:?}", META_FILE, line),
+        }
+    }
+    JOURNAL_LINES.store(lines, Ordering::Relaxed);
+}
+
+/// The metadata recorded for `path`.
+pub fn get(path: &str) -> Option<FileMeta> {
+    META.lock().get(path).copied()
+}
+
+/// Update the metadata of `path` in place.
+///
+/// On an ext4 root filesystem, the changes are written to the image instead.
+pub fn update(path: &str, f: impl FnOnce(&mut FileMeta)) {
+    if super::ext4::covers(path) {
+        let mut meta = FileMeta::default();
+        f(&mut meta);
+        write_ext4(path, &meta);
+        return;
+    }
+    let mut meta = META.lock();
+    f(meta.entry(String::from(path)).or_default());
+    persist(&meta, [path]);
+}
+
+fn write_ext4(path: &str, meta: &FileMeta) {
+    let mut res = meta
+        .mode
+        .map_or(Ok(()), |mode| super::ext4::set_mode(path, mode));
+    if res.is_ok() && (meta.uid.is_some() || meta.gid.is_some()) {
+        res = super::ext4::owner(path).and_then(|(uid, gid)| {
+            super::ext4::set_owner(path, meta.uid.unwrap_or(uid), meta.gid.unwrap_or(gid))
+        });
+    }
+    if let Err(e) = res {
+        warn!("Failed to write the metadata of {} to ext4: {:?}", path, e);
+    }
+}
+
+/// Forget the metadata of `path`, e.g. after it has been unlinked.
+pub fn remove(path: &str) {
+    let mut meta = META.lock();
+    if meta.remove(path).is_some() {
+        persist(&meta, [path]);
+    }
+}
+
+/// Remember the metadata, for [`restore`].
+pub fn save() {
+    *SAVED.lock() = META.lock().clone();
+}
+
+/// Put back the metadata of the files in the overlay as [`save`] found it.
+pub fn restore() {
+    super::restore_keys(&mut META.lock(), &SAVED.lock());
+}
+
+/// Move the metadata of `old`, and of the files below it, to `new`.
+pub fn rename(old: &str, new: &str) {
+    let mut meta = META.lock();
+    let prefix = format!("{}/", old);
+    let moved: Vec<String> = meta
+        .keys()
+        .filter(|path| *path == old || path.starts_with(&prefix))
+        .cloned()
+        .collect();
+    if moved.is_empty() {
+        return;
+    }
+    super::rename_keys(&mut meta, old, new);
+    let renamed: Vec<String> = moved
+        .iter()
+        .map(|path| format!("{}{}", new, &path[old.len()..]))
+        .collect();
+    persist(&meta, moved.iter().chain(&renamed).map(String::as_str));
+}
+
+/// Lay the recorded metadata of `path`, if any, over `stat`, or what an ext4
+/// root filesystem stores.
+pub fn apply(path: &str, stat: &mut ctypes::stat) {
+    if super::ext4::covers(path) {
+        super::ext4::apply(path, stat);
+    } else if let Some(meta) = get(path) {
+        meta.apply(stat);
+    }
+}
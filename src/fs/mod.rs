@@ -0,0 +1,296 @@
+//! Kernel-side filesystem facilities layered over `axfs`.
+pub mod cache;
+pub mod devfs;
+pub mod dir;
+pub mod ext4;
+pub mod inode;
+pub mod memfd;
+pub mod meta;
+pub mod mount;
+pub mod overlay;
+pub mod pipe;
+pub mod procfs;
+pub mod quota;
+pub mod squashfs;
+pub mod statfs;
+pub mod symlink;
+pub mod tarfs;
+pub mod tmpfs;
+pub mod verity;
+
+use alloc::collections::BTreeMap;
+use alloc::ffi::CString;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+use axstd::fs::OpenOptions;
+use axstd::io::Write;
+use axsync::Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Held while a file replaces another, and while a file is created, which
+/// mustn't come in between the removal of the target and the move.
+static REPLACE_LOCK: Mutex<()> = Mutex::new(());
+/// Odd while a file replaces another, bumped twice by each replacement.
+static REPLACE_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Special value of `dirfd` meaning the current working directory.
+pub const AT_FDCWD: i32 = -100;
+/// Don't follow a symbolic link in the last component of the path.
+pub const AT_SYMLINK_NOFOLLOW: i32 = 0x100;
+/// Operate on `dirfd` itself when the path is empty.
+pub const AT_EMPTY_PATH: i32 = 0x1000;
+
+/// Normalize an absolute path, resolving `.` and `..` components lexically.
+pub fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    let mut res = String::from("/");
+    res.push_str(&parts.join("/"));
+    res
+}
+
+/// Resolve `path` relative to the directory `dirfd` into a normalized absolute path.
+pub fn absolute_path_at(dirfd: i32, path: &str) -> LinuxResult<String> {
+    if path.starts_with('/') {
+        return Ok(normalize_path(path));
+    }
+    let base = if dirfd == AT_FDCWD {
+        axfs::api::current_dir()?
+    } else {
+        api::Directory::from_fd(dirfd)?.path().to_string()
+    };
+    let base = overlay::logical(&base);
+    Ok(normalize_path(&format!("{}/{}", base, path)))
+}
+
+/// Move the entries of a table keyed by absolute path from `old` to `new`,
+/// including those of the files below `old` if it is a directory.
+pub(crate) fn rename_keys<T>(table: &mut BTreeMap<String, T>, old: &str, new: &str) {
+    let prefix = format!("{}/", old);
+    let moved: Vec<String> = table
+        .keys()
+        .filter(|path| *path == old || path.starts_with(&prefix))
+        .cloned()
+        .collect();
+    for path in moved {
+        let value = table.remove(&path).unwrap();
+        table.insert(format!("{}{}", new, &path[old.len()..]), value);
+    }
+}
+
+/// Put back the entries of a table keyed by absolute path which are in the
+/// overlay as they were in `saved`, dropping those added since.
+pub(crate) fn restore_keys<T: Clone>(table: &mut BTreeMap<String, T>, saved: &BTreeMap<String, T>) {
+    table.retain(|path, _| !overlay::covers(path));
+    table.extend(
+        saved
+            .iter()
+            .filter(|(path, _)| overlay::covers(path))
+            .map(|(path, value)| (path.clone(), value.clone())),
+    );
+}
+
+/// Resolve `path` relative to the directory `dirfd` into an absolute path with
+/// its symbolic links followed, except for the last component unless `follow_last`.
+///
+/// The files the kernel keeps on the image for itself don't exist for user
+/// space, which can't open, rename or unlink them.
+pub fn resolve_path_at(dirfd: i32, path: &str, follow_last: bool) -> LinuxResult<String> {
+    let path = symlink::resolve(&absolute_path_at(dirfd, path)?, follow_last)?;
+    if path == meta::META_FILE || path.starts_with(&format!("{}/", meta::META_FILE)) {
+        return Err(LinuxError::ENOENT);
+    }
+    Ok(path)
+}
+
+/// An absolute path as a C string, to hand it down to `arceos_posix_api`.
+pub fn to_cstring(path: &str) -> LinuxResult<CString> {
+    CString::new(path).map_err(|_| LinuxError::EINVAL)
+}
+
+/// The absolute path of the file or directory opened as `fd`.
+pub fn fd_path(fd: i32) -> LinuxResult<String> {
+    if let Some(file) = memfd::file_from_fd(fd) {
+        return Ok(String::from(file.path()));
+    }
+    if let Some(file) = mount::file_from_fd(fd) {
+        return Ok(String::from(file.path()));
+    }
+    if let Ok(file) = api::File::from_fd(fd) {
+        return Ok(overlay::logical(file.path()));
+    }
+    Ok(overlay::logical(api::Directory::from_fd(fd)?.path()))
+}
+
+/// Stat the file at the absolute `path`, with the kernel-kept metadata applied.
+pub fn stat_path(path: &str) -> LinuxResult<api::ctypes::stat> {
+    if mount::is_mount_path(path) {
+        let mut stat = mount::stat(path)?;
+        stat.st_ino = inode::ino(path);
+        return Ok(stat);
+    }
+    let cpath = to_cstring(&overlay::lookup(path))?;
+    let mut stat = api::ctypes::stat::default();
+    let ret = unsafe { api::sys_stat(cpath.as_ptr(), &mut stat) };
+    if ret < 0 {
+        return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::ENOENT));
+    }
+    meta::apply(path, &mut stat);
+    stat.st_ino = inode::ino(path);
+    Ok(stat)
+}
+
+/// Write the file at the absolute `path` back to the disk: its pages in the
+/// page cache, then what the filesystem buffers of it.
+pub fn sync_file(path: &str) -> LinuxResult {
+    cache::sync(path)?;
+    if ext4::covers(path) {
+        return ext4::flush();
+    }
+    // Only a file opened for writing can be flushed
+    if let Ok(mut file) = OpenOptions::new().write(true).open(&overlay::lookup(path)) {
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// Write all the files back to the disk.
+pub fn sync_all() -> LinuxResult {
+    cache::sync_all()?;
+    if ext4::is_enabled() {
+        ext4::flush()?;
+    }
+    Ok(())
+}
+
+/// Read the whole file at the absolute `path`.
+pub fn read(path: &str) -> LinuxResult<Vec<u8>> {
+    if mount::is_mount_path(path) {
+        return mount::read(path);
+    }
+    cache::sync(path)?;
+    Ok(axfs::api::read(&overlay::lookup(path))?)
+}
+
+/// Read the file at the absolute `path` from `offset` into `buf`. Returns the
+/// number of bytes read, less than asked at the end of the file.
+pub fn read_at(path: &str, offset: u64, buf: &mut [u8]) -> LinuxResult<usize> {
+    if mount::is_mount_path(path) {
+        return mount::read_at(path, offset, buf);
+    }
+    cache::read_at(path, offset, buf)
+}
+
+/// Stat the file opened as `fd`, with the kernel-kept metadata applied.
+pub fn stat_fd(fd: i32) -> LinuxResult<api::ctypes::stat> {
+    let mut stat = api::ctypes::stat::default();
+    let ret = unsafe { api::sys_fstat(fd, &mut stat) };
+    if ret < 0 {
+        return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::EBADF));
+    }
+    // Pipes, memfds and other special files keep what they report
+    if memfd::file_from_fd(fd).is_some() {
+        return Ok(stat);
+    }
+    if let Ok(path) = fd_path(fd) {
+        meta::apply(&path, &mut stat);
+        stat.st_ino = inode::ino(&path);
+    }
+    Ok(stat)
+}
+
+/// Run `replace`, which removes a file and moves another in its place, so
+/// that the lookups through [`lookup_file`] see either file, never none.
+pub fn replace_file<T>(replace: impl FnOnce() -> T) -> T {
+    let _guard = REPLACE_LOCK.lock();
+    REPLACE_SEQ.fetch_add(1, Ordering::AcqRel);
+    let res = replace();
+    REPLACE_SEQ.fetch_add(1, Ordering::AcqRel);
+    res
+}
+
+/// Run `create`, which may create a file, before or after any
+/// [`replace_file`] but not during one.
+pub fn create_file<T>(create: impl FnOnce() -> T) -> T {
+    let _guard = REPLACE_LOCK.lock();
+    create()
+}
+
+/// Run `lookup` again for as long as it finds no file while a file is being
+/// replaced, as the file may be the one replaced.
+pub fn lookup_file<T>(lookup: impl Fn() -> LinuxResult<T>) -> LinuxResult<T> {
+    loop {
+        let seq = REPLACE_SEQ.load(Ordering::Acquire);
+        match lookup() {
+            Err(LinuxError::ENOENT)
+                if seq & 1 != 0 || REPLACE_SEQ.load(Ordering::Acquire) != seq =>
+            {
+                axtask::yield_now();
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Stat `path` relative to `dirfd`, following `fstatat(2)`.
+///
+/// An empty `path` refers to `dirfd` itself if `flags` contains `AT_EMPTY_PATH`.
+/// With `AT_SYMLINK_NOFOLLOW`, a symbolic link is reported instead of its target.
+pub fn stat_at(dirfd: i32, path: &str, flags: i32) -> LinuxResult<api::ctypes::stat> {
+    lookup_file(|| stat_at_once(dirfd, path, flags))
+}
+
+fn stat_at_once(dirfd: i32, path: &str, flags: i32) -> LinuxResult<api::ctypes::stat> {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if path.is_empty() {
+        if flags & AT_EMPTY_PATH == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+        if dirfd == AT_FDCWD {
+            let cwd = overlay::logical(&axfs::api::current_dir()?);
+            return stat_path(&normalize_path(&cwd));
+        }
+        return stat_fd(dirfd);
+    }
+    let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+    let path = resolve_path_at(dirfd, path, follow)?;
+    // The fd links of procfs open as the files themselves when followed
+    let target = symlink::read(&path)
+        .or_else(|| ext4::read_link(&path))
+        .or_else(|| (!follow).then(|| procfs::read_link(&path)).flatten());
+    if let Some(target) = target {
+        return Ok(api::ctypes::stat {
+            st_ino: inode::ino(&path),
+            st_mode: meta::S_IFLNK | 0o777,
+            st_nlink: 1,
+            st_size: target.len() as _,
+            st_blksize: 512,
+            ..Default::default()
+        });
+    }
+    if devfs::is_devfs_path(&path) {
+        let mut stat = devfs::stat(&path)?;
+        meta::apply(&path, &mut stat);
+        stat.st_ino = inode::ino(&path);
+        return Ok(stat);
+    }
+    if procfs::is_procfs_path(&path) {
+        let mut stat = api::FileLike::stat(&*procfs::open(&path)?)?;
+        stat.st_ino = inode::ino(&path);
+        return Ok(stat);
+    }
+    stat_path(&path)
+}
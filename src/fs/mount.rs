@@ -0,0 +1,573 @@
+//! Read-only filesystems implemented by the kernel, mounted over `axfs`.
+//!
+//! Like procfs, the files of these filesystems never reach `axfs`: the mount
+//! table is keyed by the absolute path of the mount point, and a path below a
+//! mount point is looked up in the filesystem mounted there, which hides the
+//! directory of `axfs` underneath. Opened files and directories are installed
+//! in the fd table as they are.
+//!
+//! Each process sees the mount table of its [`MountNamespace`]. A directory
+//! may also be bind-mounted elsewhere: one of a mounted filesystem is mounted
+//! again with that directory as its root, and one of `axfs` is reached by
+//! redirecting the paths below the mount point during path resolution.
+//!
+//! The filesystems read their content from an image file, through [`Image`].
+use super::squashfs::SquashFs;
+use super::statfs::FsStats;
+use super::tarfs::TarFs;
+use super::tmpfs::Tmpfs;
+use super::verity::{RootHash, Verity};
+use super::{meta, normalize_path};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axstd::fs::File;
+use axstd::io::{Read, Seek, SeekFrom};
+use axsync::Mutex;
+use axtask::TaskExtRef;
+use lazy_static::lazy_static;
+
+/// The attributes of a file of a mounted filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NodeInfo {
+    pub ino: u64,
+    /// The file type and permission bits, as in `st_mode`
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub nlink: u32,
+    pub rdev: u64,
+    /// The modification time, in seconds since the epoch
+    pub mtime: i64,
+}
+
+impl NodeInfo {
+    pub fn is_dir(&self) -> bool {
+        self.mode & meta::S_IFMT == meta::S_IFDIR
+    }
+
+    fn stat(&self) -> ctypes::stat {
+        let mut stat = ctypes::stat {
+            st_ino: self.ino,
+            st_mode: self.mode,
+            st_nlink: self.nlink,
+            st_uid: self.uid,
+            st_gid: self.gid,
+            st_rdev: self.rdev,
+            st_size: self.size as _,
+            st_blksize: 4096,
+            st_blocks: self.size.div_ceil(512) as _,
+            ..Default::default()
+        };
+        stat.st_atime.tv_sec = self.mtime as _;
+        stat.st_mtime.tv_sec = self.mtime as _;
+        stat.st_ctime.tv_sec = self.mtime as _;
+        stat
+    }
+}
+
+/// An entry of a directory of a mounted filesystem.
+pub struct DirEntry {
+    pub name: String,
+    pub node: u64,
+    /// The file type, as in `st_mode`
+    pub mode: u32,
+}
+
+/// A read-only filesystem which can be mounted.
+///
+/// Files are designated by a node number of the filesystem's choosing.
+pub trait MountFs: Send + Sync {
+    /// The node of the root directory.
+    fn root(&self) -> u64;
+
+    fn info(&self, node: u64) -> LinuxResult<NodeInfo>;
+
+    /// Find `name` in the directory `dir`.
+    fn lookup(&self, dir: u64, name: &str) -> LinuxResult<u64>;
+
+    /// The entries of the directory `dir`, without `.` and `..`.
+    fn read_dir(&self, dir: u64) -> LinuxResult<Vec<DirEntry>>;
+
+    /// Read the regular file `node` at `offset`, returning the number of
+    /// bytes read.
+    fn read_at(&self, node: u64, offset: u64, buf: &mut [u8]) -> LinuxResult<usize>;
+
+    /// The target of the symbolic link `node`.
+    fn read_link(&self, node: u64) -> LinuxResult<String>;
+
+    /// The statistics of the filesystem, for `statfs`.
+    fn stats(&self) -> LinuxResult<FsStats>;
+}
+
+/// The image file a filesystem is read from.
+///
+/// An image opened with [`Image::open_verified`] only exposes the data
+/// covered by its hash tree, and every read of it is verified.
+pub struct Image {
+    file: Mutex<File>,
+    len: u64,
+    verity: Option<Verity>,
+}
+
+impl Image {
+    pub fn open(path: &str) -> LinuxResult<Self> {
+        super::cache::sync(path)?;
+        let mut file = File::open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file: Mutex::new(file),
+            len,
+            verity: None,
+        })
+    }
+
+    /// Open the image at `path`, whose hash tree must have the root hash
+    /// `root`.
+    pub fn open_verified(path: &str, root: RootHash) -> LinuxResult<Self> {
+        let mut image = Self::open(path)?;
+        image.verity = Some(Verity::new(&image, root)?);
+        Ok(image)
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.verity {
+            Some(verity) => verity.data_len(),
+            None => self.len,
+        }
+    }
+
+    /// The size of the image file, hash tree included.
+    pub(super) fn raw_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Fill `buf` from `offset`, failing with `EIO` past the end of the image.
+    pub fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> LinuxResult {
+        match &self.verity {
+            Some(verity) => verity.read_exact_at(self, offset, buf),
+            None => self.read_raw_at(offset, buf),
+        }
+    }
+
+    pub fn read_vec_at(&self, offset: u64, len: usize) -> LinuxResult<Vec<u8>> {
+        let mut buf = alloc::vec![0; len];
+        self.read_exact_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the image file at `offset`, bypassing the verification.
+    pub(super) fn read_raw_at(&self, offset: u64, buf: &mut [u8]) -> LinuxResult {
+        if offset.saturating_add(buf.len() as u64) > self.len {
+            return Err(LinuxError::EIO);
+        }
+        let mut file = self.file.lock();
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(buf))
+            .map_err(|_| LinuxError::EIO)
+    }
+
+    pub(super) fn read_raw_vec_at(&self, offset: u64, len: usize) -> LinuxResult<Vec<u8>> {
+        let mut buf = alloc::vec![0; len];
+        self.read_raw_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+pub type MakeFs = fn(Image) -> LinuxResult<Arc<dyn MountFs>>;
+
+/// The constructor of the filesystem type `fstype`, if it is implemented by
+/// the kernel.
+pub fn kernel_fs(fstype: &str) -> Option<MakeFs> {
+    match fstype {
+        "squashfs" => Some(|image| Ok(Arc::new(SquashFs::new(image)?))),
+        "tarfs" => Some(|image| Ok(Arc::new(TarFs::new(image)?))),
+        _ => None,
+    }
+}
+
+/// What is mounted on a mount point.
+#[derive(Clone)]
+enum Mount {
+    /// The directory `root` of a filesystem implemented by the kernel, its
+    /// root directory unless bind-mounted.
+    Kernel { fs: Arc<dyn MountFs>, root: u64 },
+    /// A directory of `axfs`, bind-mounted: the paths below the mount point
+    /// are redirected there by [`super::symlink::resolve`].
+    Bind(String),
+    /// A tmpfs, whose directory is reached like a bind mount.
+    Tmpfs(Arc<Tmpfs>),
+}
+
+/// A mount namespace: the mounts seen by the processes sharing it, keyed by
+/// the absolute, normalized path of their mount point.
+///
+/// A process created with `CLONE_NEWNS` gets a copy of the namespace of its
+/// parent, and the mounts made afterwards in either one stay there.
+#[derive(Default)]
+pub struct MountNamespace {
+    mounts: Mutex<BTreeMap<String, Mount>>,
+}
+
+impl MountNamespace {
+    /// A new namespace starting with the mounts of `self`.
+    pub fn fork(&self) -> Self {
+        Self {
+            mounts: Mutex::new(self.mounts.lock().clone()),
+        }
+    }
+}
+
+lazy_static! {
+    /// The namespace of the first process, and of the kernel itself.
+    static ref INIT_NS: Arc<MountNamespace> = Arc::new(MountNamespace::default());
+}
+
+/// The namespace of the first process.
+pub fn init_ns() -> Arc<MountNamespace> {
+    INIT_NS.clone()
+}
+
+/// The namespace of the current process, or the first one for the kernel.
+fn current_ns() -> Arc<MountNamespace> {
+    let curr = axtask::current();
+    // Safety: We only check whether the task extended data is null and do not access it.
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        return init_ns();
+    }
+    match curr.task_ext().get_proc() {
+        Some(proc) => proc.mnt_ns.lock().clone(),
+        None => init_ns(),
+    }
+}
+
+/// Add `mount` on the absolute, normalized `target` in the current namespace.
+fn insert(target: &str, mount: Mount) -> LinuxResult {
+    let ns = current_ns();
+    let mut mounts = ns.mounts.lock();
+    if mounts.contains_key(target) {
+        return Err(LinuxError::EBUSY);
+    }
+    mounts.insert(String::from(target), mount);
+    Ok(())
+}
+
+/// Mount `fs` on the directory at the absolute, normalized `target`.
+pub fn mount(target: &str, fs: Arc<dyn MountFs>) -> LinuxResult {
+    let root = fs.root();
+    insert(target, Mount::Kernel { fs, root })
+}
+
+/// Mount a new tmpfs on the directory at the absolute, normalized `target`.
+pub fn mount_tmpfs(target: &str) -> LinuxResult {
+    insert(target, Mount::Tmpfs(Arc::new(Tmpfs::new()?)))
+}
+
+/// Make the directory at the absolute, normalized `source` visible at
+/// `target` as well, both having their links resolved.
+pub fn bind(source: &str, target: &str) -> LinuxResult {
+    let mount = match lookup(source) {
+        Ok((fs, node)) => {
+            if !fs.info(node)?.is_dir() {
+                return Err(LinuxError::ENOTDIR);
+            }
+            Mount::Kernel { fs, root: node }
+        }
+        Err(LinuxError::ENOENT) if !is_mount_path(source) => Mount::Bind(String::from(source)),
+        Err(e) => return Err(e),
+    };
+    insert(target, mount)
+}
+
+/// Unmount the filesystem mounted on `target`, returning whether there was one.
+pub fn umount(target: &str) -> bool {
+    // A tmpfs dropped here removes its files, out of the lock
+    let removed = current_ns().mounts.lock().remove(target);
+    removed.is_some()
+}
+
+/// Whether a filesystem is mounted on `path`.
+pub fn is_mount_point(path: &str) -> bool {
+    current_ns().mounts.lock().contains_key(path)
+}
+
+/// The directory of `axfs` bind-mounted on `path`, or holding the tmpfs
+/// mounted there, if there is one.
+pub fn bind_source(path: &str) -> Option<String> {
+    match current_ns().mounts.lock().get(path)? {
+        // A directory bound on itself stays where it is
+        Mount::Bind(source) if source != path => Some(source.clone()),
+        Mount::Tmpfs(tmpfs) => Some(String::from(tmpfs.dir())),
+        _ => None,
+    }
+}
+
+/// The filesystem the absolute, normalized `path` lies in, the node of the
+/// directory mounted, and the path relative to it.
+fn find(path: &str) -> Option<(Arc<dyn MountFs>, u64, String)> {
+    let ns = current_ns();
+    let mounts = ns.mounts.lock();
+    if mounts.is_empty() {
+        return None;
+    }
+    // The deepest mount point wins, as mounts may be stacked
+    let (point, mount) = mounts.iter().rev().find(|(point, _)| {
+        point.as_str() == "/"
+            || path
+                .strip_prefix(point.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })?;
+    // The paths below a bind mount of `axfs` or a tmpfs were redirected
+    // already
+    let Mount::Kernel { fs, root } = mount else {
+        return None;
+    };
+    let rest = if point == "/" {
+        path
+    } else {
+        &path[point.len()..]
+    };
+    Some((
+        fs.clone(),
+        *root,
+        String::from(rest.trim_start_matches('/')),
+    ))
+}
+
+/// Whether anything is mounted.
+pub fn has_mounts() -> bool {
+    !current_ns().mounts.lock().is_empty()
+}
+
+/// Whether the absolute, normalized `path` lies in a mounted filesystem.
+pub fn is_mount_path(path: &str) -> bool {
+    find(path).is_some()
+}
+
+/// The node at `path` relative to the directory `root` of `fs`. The symbolic
+/// links in it have already been followed.
+fn walk(fs: &dyn MountFs, root: u64, path: &str) -> LinuxResult<u64> {
+    let mut node = root;
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        if !fs.info(node)?.is_dir() {
+            return Err(LinuxError::ENOTDIR);
+        }
+        node = fs.lookup(node, name)?;
+    }
+    Ok(node)
+}
+
+/// The filesystem and node of the file at `path`, which must lie in a
+/// mounted filesystem.
+fn lookup(path: &str) -> LinuxResult<(Arc<dyn MountFs>, u64)> {
+    let (fs, root, rest) = find(path).ok_or(LinuxError::ENOENT)?;
+    let node = walk(&*fs, root, &rest)?;
+    Ok((fs, node))
+}
+
+/// The target of the symbolic link at `path`, if it is one in a mounted
+/// filesystem.
+pub fn read_link(path: &str) -> Option<String> {
+    let (fs, node) = lookup(path).ok()?;
+    if fs.info(node).ok()?.mode & meta::S_IFMT != meta::S_IFLNK {
+        return None;
+    }
+    fs.read_link(node).ok()
+}
+
+/// The statistics of the filesystem `path` lies in, if it is a mounted one.
+pub fn stats(path: &str) -> Option<LinuxResult<FsStats>> {
+    let (fs, _, _) = find(path)?;
+    Some(fs.stats())
+}
+
+/// The `stat` of the file at `path` in a mounted filesystem.
+pub fn stat(path: &str) -> LinuxResult<ctypes::stat> {
+    let (fs, node) = lookup(path)?;
+    Ok(fs.info(node)?.stat())
+}
+
+/// Read the whole regular file at `path` in a mounted filesystem.
+pub fn read(path: &str) -> LinuxResult<Vec<u8>> {
+    let (fs, node) = lookup(path)?;
+    let info = fs.info(node)?;
+    if info.is_dir() {
+        return Err(LinuxError::EISDIR);
+    }
+    let mut data = alloc::vec![0; info.size as usize];
+    let mut pos = 0;
+    while pos < data.len() {
+        match fs.read_at(node, pos as u64, &mut data[pos..])? {
+            0 => return Err(LinuxError::EIO),
+            count => pos += count,
+        }
+    }
+    Ok(data)
+}
+
+/// Read the regular file at `path` in a mounted filesystem from `offset`
+/// into `buf`, returning the number of bytes read.
+pub fn read_at(path: &str, offset: u64, buf: &mut [u8]) -> LinuxResult<usize> {
+    let (fs, node) = lookup(path)?;
+    if fs.info(node)?.is_dir() {
+        return Err(LinuxError::EISDIR);
+    }
+    let mut pos = 0;
+    while pos < buf.len() {
+        match fs.read_at(node, offset + pos as u64, &mut buf[pos..])? {
+            0 => break,
+            count => pos += count,
+        }
+    }
+    Ok(pos)
+}
+
+/// Fail with `EROFS` if `path` lies in a mounted filesystem, for the calls
+/// modifying it.
+pub fn check_writable(path: &str) -> LinuxResult {
+    if is_mount_path(path) {
+        return Err(LinuxError::EROFS);
+    }
+    Ok(())
+}
+
+/// An open file or directory of a mounted filesystem.
+pub struct MountFile {
+    fs: Arc<dyn MountFs>,
+    node: u64,
+    info: NodeInfo,
+    path: String,
+    pos: Mutex<u64>,
+}
+
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+const SEEK_END: i32 = 2;
+
+impl MountFile {
+    /// The absolute path the file was opened at.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The entries of the directory, failing with `ENOTDIR` for other files.
+    pub fn read_dir(&self) -> LinuxResult<Vec<DirEntry>> {
+        if !self.info.is_dir() {
+            return Err(LinuxError::ENOTDIR);
+        }
+        self.fs.read_dir(self.node)
+    }
+
+    /// Move the file position, as `lseek` does.
+    pub fn seek(&self, offset: i64, whence: i32) -> LinuxResult<u64> {
+        let mut pos = self.pos.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *pos as i64,
+            SEEK_END => self.info.size as i64,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let new = base.checked_add(offset).ok_or(LinuxError::EOVERFLOW)?;
+        if new < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        *pos = new as u64;
+        Ok(*pos)
+    }
+}
+
+impl api::FileLike for MountFile {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        if self.info.is_dir() {
+            return Err(LinuxError::EISDIR);
+        }
+        let mut pos = self.pos.lock();
+        if *pos >= self.info.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((self.info.size - *pos) as usize);
+        let count = self.fs.read_at(self.node, *pos, &mut buf[..len])?;
+        *pos += count as u64;
+        Ok(count)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EBADF)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(self.info.stat())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+const O_ACCMODE: i32 = 0o3;
+const O_CREAT: i32 = 0o100;
+const O_EXCL: i32 = 0o200;
+const O_TRUNC: i32 = 0o1000;
+const O_DIRECTORY: i32 = 0o200000;
+
+/// Open the file at `path` in a mounted filesystem.
+pub fn open(path: &str) -> LinuxResult<Arc<MountFile>> {
+    let (fs, node) = lookup(path)?;
+    let info = fs.info(node)?;
+    Ok(Arc::new(MountFile {
+        fs,
+        node,
+        info,
+        path: normalize_path(path),
+        pos: Mutex::new(0),
+    }))
+}
+
+/// Open `path` in a mounted filesystem with the `open` flags `flags` and
+/// install it in the fd table.
+pub fn open_fd(path: &str, flags: i32) -> LinuxResult<isize> {
+    let file = match open(path) {
+        Ok(_) if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL => return Err(LinuxError::EEXIST),
+        Err(LinuxError::ENOENT) if flags & O_CREAT != 0 => return Err(LinuxError::EROFS),
+        res => res?,
+    };
+    if flags & O_ACCMODE != 0 || flags & O_TRUNC != 0 {
+        return Err(if file.info.is_dir() {
+            LinuxError::EISDIR
+        } else {
+            LinuxError::EROFS
+        });
+    }
+    if file.info.mode & meta::S_IFMT == meta::S_IFLNK {
+        return Err(LinuxError::ELOOP);
+    }
+    if flags & O_DIRECTORY != 0 && !file.info.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    api::add_file_like(file).map(|fd| fd as isize)
+}
+
+/// The file of a mounted filesystem opened as `fd`, if it is one.
+pub fn file_from_fd(fd: i32) -> Option<Arc<MountFile>> {
+    api::get_file_like(fd)
+        .ok()?
+        .into_any()
+        .downcast::<MountFile>()
+        .ok()
+}
@@ -0,0 +1,272 @@
+//! An overlay keeping the image unmodified.
+//!
+//! Once enabled, the image is the lower layer of the overlay and a directory
+//! in the tmpfs at `/tmp` is the upper one. Files are read from the upper
+//! layer if they are there, and from the image otherwise. A file of the image
+//! is copied up as a whole when it is first opened for writing, new files are
+//! created in the upper layer, and removed files of the image are recorded as
+//! whiteouts which hide them. [`reset`] discards all the changes, including
+//! those to the links, owners, modes, inode numbers and quotas the kernel
+//! keeps by path.
+//!
+//! Programs and the tables of the kernel keep seeing the paths of the image:
+//! only the paths handed down to `axfs` are translated, by [`lookup`] for
+//! reading and by [`copy_up`] for writing.
+use super::{inode, meta, normalize_path, quota, symlink};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axfs::api::FileType;
+use axstd::fs::File;
+use axstd::io::{Read, Write};
+use axsync::Mutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The mount point of the tmpfs, which the overlay doesn't cover.
+const TMPFS_ROOT: &str = "/tmp";
+/// The directory holding the upper layer, hidden from listings of `/tmp`.
+pub const UPPER_ROOT: &str = "/tmp/.overlay";
+/// The size of the pieces a file is copied up in.
+const COPY_CHUNK: usize = 64 * 1024;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The files of the image which have been removed, hiding them and all the
+/// files below them in the image. A directory created again over a whiteout
+/// is thus opaque.
+static WHITEOUTS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Lay the upper layer over the image.
+pub fn enable() -> LinuxResult {
+    if !exists(UPPER_ROOT) {
+        axfs::api::create_dir(UPPER_ROOT)?;
+    }
+    symlink::save();
+    meta::save();
+    inode::save();
+    quota::save();
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether the absolute, normalized `path` is in the overlay.
+pub fn covers(path: &str) -> bool {
+    is_enabled() && path.starts_with('/') && !is_in(path, TMPFS_ROOT)
+}
+
+/// Whether `path` is `dir` or below it.
+fn is_in(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn upper(path: &str) -> String {
+    if path == "/" {
+        String::from(UPPER_ROOT)
+    } else {
+        format!("{}{}", UPPER_ROOT, path)
+    }
+}
+
+fn parent(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+fn exists(path: &str) -> bool {
+    axfs::api::metadata(path).is_ok()
+}
+
+/// Whether `path` of the image is hidden by a whiteout on it or an ancestor.
+fn is_whited_out(path: &str) -> bool {
+    let whiteouts = WHITEOUTS.lock();
+    !whiteouts.is_empty() && whiteouts.iter().any(|whiteout| is_in(path, whiteout))
+}
+
+/// Whether `path` exists in the image and isn't hidden.
+fn in_lower(path: &str) -> bool {
+    !is_whited_out(path) && exists(path)
+}
+
+/// The path in `axfs` to read the file at `path` from.
+///
+/// A file which is in neither layer gets a path in the upper layer, which
+/// doesn't exist either.
+pub fn lookup(path: &str) -> String {
+    if !covers(path) {
+        return String::from(path);
+    }
+    let upper = upper(path);
+    if exists(&upper) || is_whited_out(path) {
+        upper
+    } else {
+        String::from(path)
+    }
+}
+
+/// The path of the image for the path `path` in `axfs`, e.g. of an open file.
+pub fn logical(path: &str) -> String {
+    match path.strip_prefix(UPPER_ROOT) {
+        Some("") => String::from("/"),
+        Some(rest) if rest.starts_with('/') => String::from(rest),
+        _ => String::from(path),
+    }
+}
+
+/// Make sure the directory `path` exists in the upper layer, copying it and
+/// its ancestors up without their content.
+fn copy_up_dir(path: &str) -> LinuxResult {
+    let upper = upper(path);
+    if exists(&upper) {
+        return Ok(());
+    }
+    if !in_lower(path) {
+        return Err(LinuxError::ENOENT);
+    }
+    if !axfs::api::metadata(path)?.is_dir() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    copy_up_dir(parent(path))?;
+    axfs::api::create_dir(&upper)?;
+    Ok(())
+}
+
+/// The path in `axfs` to write the file at `path` at, or to create it at.
+///
+/// A file of the image is copied up first, a directory without its content.
+/// Fails with `ENOENT` if the parent directory doesn't exist.
+pub fn copy_up(path: &str) -> LinuxResult<String> {
+    if !covers(path) {
+        return Ok(String::from(path));
+    }
+    let upper = upper(path);
+    if exists(&upper) {
+        return Ok(upper);
+    }
+    if path != "/" {
+        copy_up_dir(parent(path))?;
+    }
+    if in_lower(path) {
+        if axfs::api::metadata(path)?.is_dir() {
+            axfs::api::create_dir(&upper)?;
+        } else {
+            copy_file(path, &upper)?;
+        }
+    }
+    Ok(upper)
+}
+
+/// Copy the regular file `src` to `dst`, a piece at a time.
+fn copy_file(src: &str, dst: &str) -> LinuxResult {
+    let mut src = File::open(src)?;
+    let mut dst = File::create(dst)?;
+    let mut buf = vec![0; COPY_CHUNK];
+    loop {
+        match src.read(&mut buf)? {
+            0 => return Ok(()),
+            len => dst.write_all(&buf[..len])?,
+        }
+    }
+}
+
+/// The entries of the directory at `path` in the overlay, merged from both
+/// layers.
+pub fn read_dir(path: &str) -> LinuxResult<Vec<(String, FileType)>> {
+    let mut entries = BTreeMap::new();
+    if in_lower(path) {
+        for entry in axfs::api::read_dir(path)?.flatten() {
+            let name = entry.file_name();
+            if !is_whited_out(&normalize_path(&format!("{}/{}", path, name))) {
+                entries.insert(name, entry.file_type());
+            }
+        }
+    }
+    let upper = upper(path);
+    if exists(&upper) {
+        for entry in axfs::api::read_dir(&upper)?.flatten() {
+            entries.insert(entry.file_name(), entry.file_type());
+        }
+    }
+    Ok(entries.into_iter().collect())
+}
+
+/// Remove the file at `path` in the overlay, or the empty directory if `dir`
+/// is set.
+pub fn remove(path: &str, dir: bool) -> LinuxResult {
+    let is_dir = axfs::api::metadata(&lookup(path))?.is_dir();
+    match (dir, is_dir) {
+        (false, true) => return Err(LinuxError::EISDIR),
+        (true, false) => return Err(LinuxError::ENOTDIR),
+        (true, true) if !read_dir(path)?.is_empty() => return Err(LinuxError::ENOTEMPTY),
+        _ => {}
+    }
+    let upper = upper(path);
+    if exists(&upper) {
+        if is_dir {
+            axfs::api::remove_dir(&upper)?;
+        } else {
+            axfs::api::remove_file(&upper)?;
+        }
+    }
+    if in_lower(path) {
+        WHITEOUTS.lock().insert(String::from(path));
+    }
+    Ok(())
+}
+
+/// Move the file at `old` to `new`, which doesn't exist. Files outside the
+/// overlay are moved as is.
+///
+/// Like overlayfs without `redirect_dir`, directories of the image can't be
+/// moved and fail with `EXDEV`, which makes `mv` copy them instead.
+pub fn rename(old: &str, new: &str) -> LinuxResult {
+    let from_lower = covers(old) && in_lower(old);
+    if from_lower && axfs::api::metadata(old)?.is_dir() {
+        return Err(LinuxError::EXDEV);
+    }
+    let src = copy_up(old)?;
+    let dst = copy_up(new)?;
+    axfs::api::rename(&src, &dst)?;
+    if from_lower {
+        WHITEOUTS.lock().insert(String::from(old));
+    }
+    Ok(())
+}
+
+fn remove_dir_all(path: &str) -> LinuxResult {
+    for entry in axfs::api::read_dir(path)?.flatten() {
+        let child = format!("{}/{}", path, entry.file_name());
+        if entry.file_type().is_dir() {
+            remove_dir_all(&child)?;
+            axfs::api::remove_dir(&child)?;
+        } else {
+            axfs::api::remove_file(&child)?;
+        }
+    }
+    Ok(())
+}
+
+/// Discard all the changes made over the image.
+pub fn reset() -> LinuxResult {
+    if !is_enabled() {
+        return Ok(());
+    }
+    remove_dir_all(UPPER_ROOT)?;
+    WHITEOUTS.lock().clear();
+    symlink::restore();
+    meta::restore();
+    inode::restore();
+    quota::restore();
+    // The cached pages of the files of the upper layer are stale
+    super::cache::clear();
+    Ok(())
+}
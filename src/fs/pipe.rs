@@ -0,0 +1,282 @@
+//! Pipes made by `pipe2`.
+//!
+//! A pipe is a bounded byte buffer with a read end and a write end, each an
+//! fd table entry. A reader with nothing to read and a writer with no room
+//! sleep on a wait queue of the pipe until the other end makes progress, is
+//! closed, or a signal arrives.
+//!
+//! Writes of at most [`PIPE_BUF`] bytes are atomic: they wait until they fit
+//! as a whole. Writing to a pipe whose read end is closed raises `SIGPIPE`
+//! and fails with `EPIPE`.
+//!
+//! The buffer of a pipe is charged to the real uid of its creator, as on
+//! Linux: over `fs/pipe-user-pages-soft` pages a new pipe only gets one page,
+//! and over `fs/pipe-user-pages-hard` an unprivileged user can't create pipes
+//! at all. The pages are given back as the buffer goes, with the last end.
+use super::meta::S_IFIFO;
+use crate::process::cred::Credentials;
+use crate::process::current_process;
+use crate::process::signal::{send_signal_to_proc, wait_interruptible};
+use crate::signal::signal_no::SignalNo;
+use crate::sysctl::{PIPE_MAX_SIZE, PIPE_USER_PAGES_HARD, PIPE_USER_PAGES_SOFT};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use memory_addr::PAGE_SIZE_4K;
+
+/// The largest write which is never interleaved with other writes.
+pub const PIPE_BUF: usize = 4096;
+
+/// The number of pages of a pipe of default size.
+const PIPE_DEF_PAGES: usize = 16;
+
+/// uid -> pages of pipe buffers charged to the user
+static USER_PAGES: Mutex<BTreeMap<u32, usize>> = Mutex::new(BTreeMap::new());
+
+struct Pipe {
+    buf: Mutex<VecDeque<u8>>,
+    capacity: usize,
+    /// The bytes in the buffer, which the waits check without locking
+    len: AtomicUsize,
+    read_closed: AtomicBool,
+    write_closed: AtomicBool,
+    /// Woken when bytes are written or the write end is closed
+    readable: WaitQueue,
+    /// Woken when bytes are read or the read end is closed
+    writable: WaitQueue,
+    uid: u32,
+    gid: u32,
+    /// The user the buffer is charged to, and for how many pages
+    charged_uid: u32,
+    pages: usize,
+}
+
+impl Pipe {
+    fn room(&self) -> usize {
+        self.capacity - self.len.load(Ordering::Acquire)
+    }
+}
+
+/// One end of a pipe, as it sits in the fd table.
+pub struct PipeEnd {
+    pipe: Arc<Pipe>,
+    write: bool,
+    nonblocking: AtomicBool,
+}
+
+impl PipeEnd {
+    /// Wait for `ready`, unless the end is nonblocking.
+    fn wait(&self, wq: &WaitQueue, ready: impl Fn() -> bool) -> LinuxResult {
+        if self.nonblocking.load(Ordering::Relaxed) {
+            return Err(LinuxError::EAGAIN);
+        }
+        wait_interruptible(wq, None, ready).map(|_| ())
+    }
+}
+
+impl api::FileLike for PipeEnd {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        if self.write {
+            return Err(LinuxError::EBADF);
+        }
+        let pipe = &self.pipe;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut data = pipe.buf.lock();
+            if data.is_empty() {
+                drop(data);
+                if pipe.write_closed.load(Ordering::Acquire) {
+                    return Ok(0);
+                }
+                self.wait(&pipe.readable, || {
+                    pipe.len.load(Ordering::Acquire) != 0
+                        || pipe.write_closed.load(Ordering::Acquire)
+                })?;
+                continue;
+            }
+            let count = buf.len().min(data.len());
+            for (dst, src) in buf.iter_mut().zip(data.drain(..count)) {
+                *dst = src;
+            }
+            pipe.len.store(data.len(), Ordering::Release);
+            drop(data);
+            pipe.writable.notify_all(false);
+            return Ok(count);
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        if !self.write {
+            return Err(LinuxError::EBADF);
+        }
+        let pipe = &self.pipe;
+        let mut written = 0;
+        while written < buf.len() {
+            if pipe.read_closed.load(Ordering::Acquire) {
+                let pid = current_process().unwrap().pid;
+                let _ = send_signal_to_proc(pid, SignalNo::SIGPIPE as isize, None);
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(LinuxError::EPIPE)
+                };
+            }
+            // A small write goes in as a whole, a large one as room allows
+            let left = buf.len() - written;
+            let needed = if written == 0 && left <= PIPE_BUF {
+                left
+            } else {
+                1
+            };
+            let mut data = pipe.buf.lock();
+            let room = pipe.capacity - data.len();
+            if room < needed {
+                drop(data);
+                match self.wait(&pipe.writable, || {
+                    pipe.room() >= needed || pipe.read_closed.load(Ordering::Acquire)
+                }) {
+                    Ok(()) => continue,
+                    Err(_) if written > 0 => return Ok(written),
+                    Err(err) => return Err(err),
+                }
+            }
+            let count = left.min(room);
+            data.extend(&buf[written..written + count]);
+            pipe.len.store(data.len(), Ordering::Release);
+            drop(data);
+            written += count;
+            pipe.readable.notify_all(false);
+        }
+        Ok(written)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(ctypes::stat {
+            st_mode: S_IFIFO | 0o600,
+            st_nlink: 1,
+            st_uid: self.pipe.uid,
+            st_gid: self.pipe.gid,
+            st_blksize: PIPE_BUF as _,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        let pipe = &self.pipe;
+        Ok(if self.write {
+            PollState {
+                readable: false,
+                writable: pipe.room() > 0 || pipe.read_closed.load(Ordering::Acquire),
+            }
+        } else {
+            PollState {
+                readable: pipe.len.load(Ordering::Acquire) != 0
+                    || pipe.write_closed.load(Ordering::Acquire),
+                writable: false,
+            }
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        // Wake up whoever waits for this end
+        if self.write {
+            self.pipe.write_closed.store(true, Ordering::Release);
+            self.pipe.readable.notify_all(false);
+        } else {
+            self.pipe.read_closed.store(true, Ordering::Release);
+            self.pipe.writable.notify_all(false);
+        }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // Both ends are closed, the buffer goes
+        let mut user_pages = USER_PAGES.lock();
+        if let Some(pages) = user_pages.get_mut(&self.charged_uid) {
+            *pages -= self.pages;
+            if *pages == 0 {
+                user_pages.remove(&self.charged_uid);
+            }
+        }
+    }
+}
+
+/// Decide how many pages a new pipe of a user who already has `used` gets,
+/// following the Linux rules: over the soft limit a pipe is shrunk to a single
+/// page, over the hard limit unprivileged users can't create pipes at all.
+fn pages_for_new_pipe(used: usize, privileged: bool) -> LinuxResult<usize> {
+    let max_pages = PIPE_MAX_SIZE.get() / PAGE_SIZE_4K;
+    let mut pages = PIPE_DEF_PAGES.min(max_pages).max(1);
+
+    let soft = PIPE_USER_PAGES_SOFT.get();
+    if !privileged && soft != 0 && used + pages > soft {
+        pages = 1;
+    }
+    let hard = PIPE_USER_PAGES_HARD.get();
+    if !privileged && hard != 0 && used + pages > hard {
+        return Err(LinuxError::ENFILE);
+    }
+    Ok(pages)
+}
+
+/// Make a pipe for a process with the credentials `cred`, owned by its
+/// effective IDs, and charge its buffer to its real uid.
+///
+/// Returns its read end and its write end.
+pub fn new_pipe(
+    cred: &Credentials,
+    nonblocking: bool,
+) -> LinuxResult<(Arc<PipeEnd>, Arc<PipeEnd>)> {
+    let mut user_pages = USER_PAGES.lock();
+    let used = user_pages.get(&cred.uid).copied().unwrap_or(0);
+    let pages = pages_for_new_pipe(used, cred.is_privileged())?;
+    user_pages.insert(cred.uid, used + pages);
+    drop(user_pages);
+    debug!(
+        "pipe: charged {} pages to uid {}, {} pages in total",
+        pages,
+        cred.uid,
+        used + pages
+    );
+
+    let pipe = Arc::new(Pipe {
+        buf: Mutex::new(VecDeque::new()),
+        capacity: pages * PAGE_SIZE_4K,
+        len: AtomicUsize::new(0),
+        read_closed: AtomicBool::new(false),
+        write_closed: AtomicBool::new(false),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+        uid: cred.euid,
+        gid: cred.egid,
+        charged_uid: cred.uid,
+        pages,
+    });
+    let end = |write| {
+        Arc::new(PipeEnd {
+            pipe: pipe.clone(),
+            write,
+            nonblocking: AtomicBool::new(nonblocking),
+        })
+    };
+    Ok((end(false), end(true)))
+}
@@ -0,0 +1,544 @@
+//! A synthetic `/proc` filesystem.
+//!
+//! Files under `/proc` are generated when they are opened: the content is
+//! rendered into a buffer which later reads consume. Every `write` call is
+//! handed to the handler of the file as a whole, like most single-value Linux
+//! proc files.
+use super::meta::{S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT};
+use crate::process::{current_process, get_process, AxProcessRef};
+use crate::sysctl;
+use crate::trace::{self, TraceEvent};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+
+/// The mount point of procfs.
+pub const PROC_ROOT: &str = "/proc";
+
+/// The size of the fd table of `arceos_posix_api`.
+const FD_LIMIT: usize = 1024;
+
+type WriteHandler = Box<dyn Fn(&[u8]) -> LinuxResult + Send + Sync>;
+
+/// An open file of procfs.
+pub struct ProcFile {
+    /// The content rendered at open time
+    content: Vec<u8>,
+    /// The read position
+    pos: Mutex<usize>,
+    write_handler: Option<WriteHandler>,
+}
+
+impl ProcFile {
+    fn new(content: Vec<u8>, write_handler: Option<WriteHandler>) -> Self {
+        Self {
+            content,
+            pos: Mutex::new(0),
+            write_handler,
+        }
+    }
+}
+
+impl api::FileLike for ProcFile {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let mut pos = self.pos.lock();
+        let remaining = &self.content[(*pos).min(self.content.len())..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        *pos += count;
+        Ok(count)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        let handler = self.write_handler.as_ref().ok_or(LinuxError::EACCES)?;
+        handler(buf)?;
+        Ok(buf.len())
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let mode = if self.write_handler.is_some() {
+            0o100644
+        } else {
+            0o100444
+        };
+        Ok(ctypes::stat {
+            st_mode: mode,
+            st_nlink: 1,
+            st_blksize: 1024,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: self.write_handler.is_some(),
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// A directory of procfs, or of devfs, listed as it was when opened.
+pub struct ProcDir {
+    path: String,
+    /// The names and modes of the entries
+    entries: Vec<(String, u32)>,
+    /// The entries read so far
+    pos: Mutex<usize>,
+}
+
+impl ProcDir {
+    pub(super) fn new(path: &str, entries: Vec<(String, u32)>) -> Self {
+        Self {
+            path: String::from(path),
+            entries,
+            pos: Mutex::new(0),
+        }
+    }
+
+    /// The absolute path of the directory.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The names and modes of the entries.
+    pub fn entries(&self) -> &[(String, u32)] {
+        &self.entries
+    }
+
+    /// The number of entries read so far.
+    pub fn pos(&self) -> usize {
+        *self.pos.lock()
+    }
+
+    /// Set the number of entries read so far.
+    pub fn set_pos(&self, pos: usize) {
+        *self.pos.lock() = pos;
+    }
+}
+
+impl api::FileLike for ProcDir {
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EISDIR)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EISDIR)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(ctypes::stat {
+            st_mode: S_IFDIR | 0o555,
+            st_nlink: 2,
+            st_blksize: 1024,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// The directory of procfs opened as `fd`, if it is one.
+pub fn dir_from_fd(fd: i32) -> Option<Arc<ProcDir>> {
+    api::get_file_like(fd)
+        .ok()?
+        .into_any()
+        .downcast::<ProcDir>()
+        .ok()
+}
+
+/// Whether the absolute, normalized `path` lies in procfs.
+pub fn is_procfs_path(path: &str) -> bool {
+    path == PROC_ROOT
+        || path
+            .strip_prefix(PROC_ROOT)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn parse_str(buf: &[u8]) -> LinuxResult<&str> {
+    core::str::from_utf8(buf)
+        .map(str::trim)
+        .map_err(|_| LinuxError::EINVAL)
+}
+
+/// `/proc/sys/<name>`
+fn open_sysctl(name: &str) -> LinuxResult<ProcFile> {
+    let ctl = sysctl::find(name).ok_or(LinuxError::ENOENT)?;
+    Ok(ProcFile::new(
+        format!("{}\n", ctl.get()).into_bytes(),
+        Some(Box::new(move |buf| {
+            if !current_process().unwrap().cred().is_privileged() {
+                return Err(LinuxError::EPERM);
+            }
+            let value = parse_str(buf)?.parse().map_err(|_| LinuxError::EINVAL)?;
+            ctl.set(value)
+        })),
+    ))
+}
+
+/// `/proc/<pid>/maps`
+///
+/// A line per mapped area, from the lowest, in the format of Linux, without
+/// device and inode numbers.
+fn open_maps(proc: AxProcessRef) -> ProcFile {
+    let mut content = String::new();
+    for area in proc.vm_areas.lock().iter() {
+        content += &format!("{}\n", area);
+    }
+    ProcFile::new(content.into_bytes(), None)
+}
+
+/// `/proc/<pid>/statm`
+///
+/// `size resident shared text lib data dt` in pages, of which only the
+/// size of the areas mapped and the resident pages are known.
+fn open_statm(proc: AxProcessRef) -> ProcFile {
+    let size: usize = proc
+        .vm_areas
+        .lock()
+        .iter()
+        .map(|area| (area.end - area.start) / memory_addr::PAGE_SIZE_4K)
+        .sum();
+    let content = format!("{} {} 0 0 0 0 0\n", size, proc.rss());
+    ProcFile::new(content.into_bytes(), None)
+}
+
+/// `/proc/<pid>/timens_offsets`
+///
+/// Each line is `<clock> <secs> <nanos>`, where `<clock>` is `monotonic`,
+/// `boottime`, or the numeric clock id. The offsets are those of the namespace
+/// the children of the process enter, which can be written until one does.
+fn open_timens_offsets(proc: AxProcessRef) -> ProcFile {
+    use crate::process::timens::{CLOCK_BOOTTIME, CLOCK_MONOTONIC};
+
+    let time_ns = proc.time_ns_for_children.lock().clone();
+    let mut content = String::new();
+    for (name, clock_id) in [("monotonic", CLOCK_MONOTONIC), ("boottime", CLOCK_BOOTTIME)] {
+        let offset = time_ns.offset(clock_id);
+        content += &format!(
+            "{} {} {}\n",
+            name,
+            offset.div_euclid(1_000_000_000),
+            offset.rem_euclid(1_000_000_000)
+        );
+    }
+    ProcFile::new(
+        content.into_bytes(),
+        Some(Box::new(move |buf| {
+            // Changing the clocks of a namespace needs CAP_SYS_TIME
+            if !current_process().unwrap().cred().is_privileged() {
+                return Err(LinuxError::EPERM);
+            }
+            for line in parse_str(buf)?.lines() {
+                let mut fields = line.split_whitespace();
+                let (Some(clock), Some(secs), Some(nanos), None) =
+                    (fields.next(), fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(LinuxError::EINVAL);
+                };
+                let clock_id = match clock {
+                    "monotonic" => CLOCK_MONOTONIC,
+                    "boottime" => CLOCK_BOOTTIME,
+                    id => id.parse().map_err(|_| LinuxError::EINVAL)?,
+                };
+                let secs: i64 = secs.parse().map_err(|_| LinuxError::EINVAL)?;
+                let nanos: i64 = nanos.parse().map_err(|_| LinuxError::EINVAL)?;
+                if !(0..1_000_000_000).contains(&nanos) {
+                    return Err(LinuxError::EINVAL);
+                }
+                let offset = secs
+                    .checked_mul(1_000_000_000)
+                    .and_then(|ns| ns.checked_add(nanos))
+                    .ok_or(LinuxError::ERANGE)?;
+                time_ns.set_offset(clock_id, offset)?;
+            }
+            Ok(())
+        })),
+    )
+}
+
+/// `/proc/trace/<name>`
+///
+/// `enable` lists the enabled tracepoints, one per line, and accepts a list of
+/// tracepoint names, `all` or `none`. Reading `buffer` dumps the trace rings
+/// and writing to it discards them.
+fn open_trace(name: &str) -> LinuxResult<ProcFile> {
+    fn check_privileged() -> LinuxResult {
+        if !current_process().unwrap().cred().is_privileged() {
+            return Err(LinuxError::EPERM);
+        }
+        Ok(())
+    }
+
+    match name {
+        "enable" => {
+            let mut content = String::new();
+            for event in trace::enabled_events() {
+                content += event.name();
+                content.push('\n');
+            }
+            Ok(ProcFile::new(
+                content.into_bytes(),
+                Some(Box::new(|buf| {
+                    check_privileged()?;
+                    let mut events = Vec::new();
+                    for name in parse_str(buf)?.split_whitespace() {
+                        match name {
+                            "all" => events.extend(TraceEvent::ALL),
+                            "none" => events.clear(),
+                            name => {
+                                events.push(TraceEvent::from_name(name).ok_or(LinuxError::EINVAL)?)
+                            }
+                        }
+                    }
+                    trace::set_enabled(&events);
+                    Ok(())
+                })),
+            ))
+        }
+        "buffer" => Ok(ProcFile::new(
+            trace::dump(),
+            Some(Box::new(|_| {
+                check_privileged()?;
+                trace::clear();
+                Ok(())
+            })),
+        )),
+        _ => Err(LinuxError::ENOENT),
+    }
+}
+
+/// `/proc/meminfo`, in kB like on Linux.
+///
+/// The memory available is the free memory and the page cache, which can be
+/// evicted. The swap is the swap file, if any.
+fn open_meminfo() -> ProcFile {
+    let ram = crate::mm::ram_usage();
+    let cached = super::cache::cached_bytes();
+    let (swap_total, swap_free) = crate::mm::swap::usage();
+    let mut content = String::new();
+    for (name, bytes) in [
+        ("MemTotal", ram.total),
+        ("MemFree", ram.free),
+        ("MemAvailable", ram.free + cached),
+        ("Buffers", 0),
+        ("Cached", cached),
+        ("SwapTotal", swap_total),
+        ("SwapFree", swap_free),
+    ] {
+        content += &format!("{:<16}{:8} kB\n", format!("{}:", name), bytes / 1024);
+    }
+    ProcFile::new(content.into_bytes(), None)
+}
+
+/// `/proc/loadavg`: the load averages, the runnable and total processes, and
+/// the last pid allocated.
+fn open_loadavg() -> ProcFile {
+    use crate::process::loadavg::{loadavg, nr_running, FSHIFT};
+
+    let mut content = String::new();
+    for load in loadavg() {
+        let hundredths = (load * 100 + (1 << (FSHIFT - 1))) >> FSHIFT;
+        content += &format!("{}.{:02} ", hundredths / 100, hundredths % 100);
+    }
+    let procs = crate::process::all_processes();
+    let last_pid = procs.iter().map(|proc| proc.pid).max().unwrap_or(0);
+    content += &format!("{}/{} {}\n", nr_running(), procs.len(), last_pid);
+    ProcFile::new(content.into_bytes(), None)
+}
+
+/// `/proc/uptime`: the time since boot, and the time spent idle, in seconds.
+fn open_uptime() -> ProcFile {
+    use crate::process::timens::CLOCK_BOOTTIME;
+
+    let uptime = current_process()
+        .unwrap()
+        .time_ns
+        .lock()
+        .apply(CLOCK_BOOTTIME, axhal::time::monotonic_time());
+    // Idle time isn't accounted
+    let content = format!(
+        "{}.{:02} 0.00\n",
+        uptime.as_secs(),
+        uptime.subsec_millis() / 10
+    );
+    ProcFile::new(content.into_bytes(), None)
+}
+
+/// `/proc/<pid>/fd/<fd>`: the file open as `fd`, shared rather than opened
+/// again.
+///
+/// Only the fds of the calling process are reachable, as the fd tables of
+/// other processes aren't accessible.
+fn open_fd_entry(proc: AxProcessRef, fd: &str) -> LinuxResult<Arc<dyn api::FileLike>> {
+    if proc.pid != current_process().unwrap().pid {
+        return Err(LinuxError::EACCES);
+    }
+    let fd = fd.parse().map_err(|_| LinuxError::ENOENT)?;
+    api::get_file_like(fd).map_err(|_| LinuxError::ENOENT)
+}
+
+/// `/proc/<pid>/fd`, a link per open fd of the calling process.
+fn open_fd_dir(proc: AxProcessRef, path: &str) -> LinuxResult<ProcDir> {
+    if proc.pid != current_process().unwrap().pid {
+        return Err(LinuxError::EACCES);
+    }
+    let entries = (0..FD_LIMIT as i32)
+        .filter(|&fd| api::get_file_like(fd).is_ok())
+        .map(|fd| (format!("{}", fd), S_IFLNK | 0o700))
+        .collect();
+    Ok(ProcDir::new(path, entries))
+}
+
+/// The target of `/proc/<pid>/fd/<fd>`: the path of the file, else what kind
+/// of file it is, as in Linux.
+fn fd_target(fd: i32) -> Option<String> {
+    let file = api::get_file_like(fd).ok()?;
+    if let Some(dir) = dir_from_fd(fd) {
+        return Some(String::from(dir.path()));
+    }
+    if let Ok(path) = super::fd_path(fd) {
+        return Some(path);
+    }
+    let stat = file.stat().ok()?;
+    Some(if stat.st_mode & S_IFMT == S_IFIFO {
+        format!("pipe:[{}]", stat.st_ino)
+    } else {
+        String::from("anon_inode:[file]")
+    })
+}
+
+/// `/proc/<pid>/cmdline` and `/proc/<pid>/environ`: the strings on the
+/// stack, as the program may have changed them, each ending with its NUL.
+/// Those given to execve if the stack can't be read, e.g. once the process
+/// is gone.
+fn open_stack_strings(proc: AxProcessRef, env: bool) -> LinuxResult<ProcFile> {
+    let caller = current_process().unwrap().cred();
+    // The environment may hold secrets
+    if env && !caller.is_privileged() && caller.euid != proc.cred().uid {
+        return Err(LinuxError::EACCES);
+    }
+    let arg_env = proc.arg_env.lock().clone();
+    let range = if env { arg_env.env } else { arg_env.args };
+    let mut content = alloc::vec![0; range.end - range.start];
+    if proc.aspace.lock().read(range.start, &mut content).is_err() {
+        let args = proc.exec_args();
+        content.clear();
+        for arg in if env { args.envp } else { args.argv } {
+            content.extend_from_slice(arg.as_bytes());
+            content.push(0);
+        }
+    }
+    Ok(ProcFile::new(content, None))
+}
+
+/// `/proc/<pid>/<name>`, whose path is `path`
+fn open_pid_entry(
+    proc: AxProcessRef,
+    name: &str,
+    path: &str,
+) -> LinuxResult<Arc<dyn api::FileLike>> {
+    if let Some(fd) = name.strip_prefix("fd/") {
+        return open_fd_entry(proc, fd);
+    }
+    let file = match name {
+        "fd" => return Ok(Arc::new(open_fd_dir(proc, path)?)),
+        "cmdline" => open_stack_strings(proc, false)?,
+        "environ" => open_stack_strings(proc, true)?,
+        "maps" => open_maps(proc),
+        "statm" => open_statm(proc),
+        "timens_offsets" => open_timens_offsets(proc),
+        _ => return Err(LinuxError::ENOENT),
+    };
+    Ok(Arc::new(file))
+}
+
+/// Open the file at the absolute, normalized `path` inside procfs.
+///
+/// The entries under `/proc/<pid>/fd` are the open files themselves, the
+/// others are [`ProcFile`]s.
+pub fn open(path: &str) -> LinuxResult<Arc<dyn api::FileLike>> {
+    let rest = path
+        .strip_prefix(PROC_ROOT)
+        .map(|rest| rest.trim_start_matches('/'))
+        .ok_or(LinuxError::ENOENT)?;
+    let (first, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let file = match first {
+        "" => return Err(LinuxError::EISDIR),
+        "sys" => open_sysctl(rest)?,
+        "trace" => open_trace(rest)?,
+        "meminfo" if rest.is_empty() => open_meminfo(),
+        "loadavg" if rest.is_empty() => open_loadavg(),
+        "uptime" if rest.is_empty() => open_uptime(),
+        "self" => return open_pid_entry(current_process().unwrap(), rest, path),
+        pid => {
+            let pid = pid.parse().map_err(|_| LinuxError::ENOENT)?;
+            let proc = get_process(pid).ok_or(LinuxError::ENOENT)?;
+            return open_pid_entry(proc, rest, path);
+        }
+    };
+    Ok(Arc::new(file))
+}
+
+/// The process `/proc/<first>` is about.
+fn pid_dir(first: &str) -> Option<AxProcessRef> {
+    match first {
+        "self" => current_process(),
+        pid => get_process(pid.parse().ok()?),
+    }
+}
+
+/// The target of the symbolic link at the absolute, normalized `path` in
+/// procfs, if it is one: `/proc/<pid>/exe` or `/proc/<pid>/fd/<fd>`.
+pub fn read_link(path: &str) -> Option<String> {
+    let rest = path.strip_prefix(PROC_ROOT)?.strip_prefix('/')?;
+    let (first, name) = rest.split_once('/')?;
+    let proc = pid_dir(first)?;
+    match name {
+        "exe" => Some(proc.exe.lock().clone()).filter(|exe| !exe.is_empty()),
+        name => {
+            let fd = name.strip_prefix("fd/")?.parse().ok()?;
+            (proc.pid == current_process()?.pid)
+                .then(|| fd_target(fd))
+                .flatten()
+        }
+    }
+}
+
+/// The target of `/proc/<pid>/exe` if `path` is one, the only link of procfs
+/// which is followed. Those of the fds are opened as the files themselves.
+pub fn exe_link(path: &str) -> Option<String> {
+    path.ends_with("/exe").then(|| read_link(path)).flatten()
+}
+
+/// Open `path` in procfs and install it in the fd table.
+pub fn open_fd(path: &str) -> LinuxResult<isize> {
+    let file = open(path)?;
+    api::add_file_like(file).map(|fd| fd as isize)
+}
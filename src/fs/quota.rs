@@ -0,0 +1,178 @@
+//! Per-uid block usage accounting with a simple quota limit.
+//!
+//! Every regular file written is charged to its owner, as with the quotas of
+//! Linux, once whatever the number of its hard links. Once a user owns more
+//! blocks than `fs/quota-max-blocks`, writes that would grow their files fail
+//! with `EDQUOT`. Privileged writers are never limited.
+use super::{overlay, stat_path};
+use crate::sysctl::QUOTA_MAX_BLOCKS;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use arceos_posix_api::ctypes;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+
+/// The accounting granularity.
+pub const QUOTA_BLOCK_SIZE: u64 = 512;
+
+/// The blocks charged for a file.
+#[derive(Clone)]
+struct Charge {
+    /// The uid of the owner of the file when it was last charged
+    owner: u32,
+    blocks: u64,
+    /// A path of the file, to tell whether it is in the overlay
+    path: String,
+}
+
+struct QuotaTable {
+    /// uid -> blocks charged to the user, and reserved by writes in progress
+    usage: BTreeMap<u32, u64>,
+    /// inode number -> blocks charged for the file
+    files: BTreeMap<u64, Charge>,
+}
+
+static QUOTA: Mutex<QuotaTable> = Mutex::new(QuotaTable {
+    usage: BTreeMap::new(),
+    files: BTreeMap::new(),
+});
+/// The files charged when the overlay was enabled, which [`restore`] puts
+/// back.
+static SAVED: Mutex<BTreeMap<u64, Charge>> = Mutex::new(BTreeMap::new());
+
+fn size_to_blocks(size: u64) -> u64 {
+    size.div_ceil(QUOTA_BLOCK_SIZE)
+}
+
+/// Run `op`, which makes the file at `path` grow to at most the size
+/// `new_size` gives, then charge the file with the size `size_after` finds, if
+/// any. Unless `privileged`, fails with `EDQUOT` without running `op` if the
+/// owner of the file may not own that much more.
+///
+/// The blocks the file may grow by are reserved for its owner while `op`
+/// runs, so that writers can't all pass the check first and exceed the quota
+/// together, and the lock of the table isn't held during the I/O.
+pub fn grow<T>(
+    path: &str,
+    privileged: bool,
+    new_size: impl FnOnce() -> u64,
+    op: impl FnOnce() -> T,
+    size_after: impl FnOnce(&T) -> Option<u64>,
+) -> LinuxResult<T> {
+    // A file unlinked while open isn't charged anymore
+    let Ok(stat) = stat_path(path) else {
+        return Ok(op());
+    };
+    let (ino, owner) = (stat.st_ino, stat.st_uid);
+    let limit = QUOTA_MAX_BLOCKS.get() as u64;
+    let reserved = if limit == 0 || privileged {
+        0
+    } else {
+        QUOTA.lock().reserve(ino, owner, new_size(), limit)?
+    };
+    let res = op();
+    let mut table = QUOTA.lock();
+    table.add_usage(owner, 0, reserved);
+    if let Some(size) = size_after(&res) {
+        table.charge(ino, owner, path, size);
+    }
+    Ok(res)
+}
+
+impl QuotaTable {
+    /// Add `add` blocks to the usage of `uid`, less `sub`.
+    fn add_usage(&mut self, uid: u32, add: u64, sub: u64) {
+        let usage = self.usage.entry(uid).or_insert(0);
+        *usage = (*usage + add).saturating_sub(sub);
+    }
+
+    /// Reserve the blocks for the file `ino` to grow to `new_size` bytes to
+    /// `owner`, within `limit`, returning how many.
+    fn reserve(&mut self, ino: u64, owner: u32, new_size: u64, limit: u64) -> LinuxResult<u64> {
+        let old_blocks = self
+            .files
+            .get(&ino)
+            .filter(|charge| charge.owner == owner)
+            .map_or(0, |charge| charge.blocks);
+        let more = size_to_blocks(new_size).saturating_sub(old_blocks);
+        if more == 0 {
+            return Ok(0);
+        }
+        let used = self.usage.get(&owner).copied().unwrap_or(0);
+        if used + more > limit {
+            return Err(LinuxError::EDQUOT);
+        }
+        self.add_usage(owner, more, 0);
+        Ok(more)
+    }
+
+    /// Record that the file `ino` at `path`, owned by `owner`, now has
+    /// `new_size` bytes. A file given to another owner since it was last
+    /// charged moves to them.
+    fn charge(&mut self, ino: u64, owner: u32, path: &str, new_size: u64) {
+        let blocks = size_to_blocks(new_size);
+        if let Some(old) = self.files.get(&ino).cloned() {
+            self.add_usage(old.owner, 0, old.blocks);
+        }
+        self.add_usage(owner, blocks, 0);
+        let path = String::from(path);
+        self.files.insert(
+            ino,
+            Charge {
+                owner,
+                blocks,
+                path,
+            },
+        );
+    }
+}
+
+/// Release the blocks charged for the file `stat` describes as it is
+/// unlinked, if that was its last link.
+pub fn release(stat: &ctypes::stat) {
+    if stat.st_nlink > 1 {
+        return;
+    }
+    let mut table = QUOTA.lock();
+    if let Some(charge) = table.files.remove(&stat.st_ino) {
+        table.add_usage(charge.owner, 0, charge.blocks);
+    }
+}
+
+/// Remember the files charged, for [`restore`].
+pub fn save() {
+    *SAVED.lock() = QUOTA.lock().files.clone();
+}
+
+/// Charge the files in the overlay as [`save`] found them again, and the
+/// users for them.
+pub fn restore() {
+    let mut table = QUOTA.lock();
+    table
+        .files
+        .retain(|_, charge| !overlay::covers(&charge.path));
+    table.files.extend(
+        SAVED
+            .lock()
+            .iter()
+            .filter(|(_, charge)| overlay::covers(&charge.path))
+            .map(|(&ino, charge)| (ino, charge.clone())),
+    );
+    let mut usage = BTreeMap::new();
+    for charge in table.files.values() {
+        *usage.entry(charge.owner).or_insert(0) += charge.blocks;
+    }
+    table.usage = usage;
+}
+
+/// Keep the paths of the files charged at `old`, and below it, up to date as
+/// they move to `new`.
+pub fn rename(old: &str, new: &str) {
+    let prefix = format!("{}/", old);
+    for charge in QUOTA.lock().files.values_mut() {
+        if charge.path == old || charge.path.starts_with(&prefix) {
+            charge.path = format!("{}{}", new, &charge.path[old.len()..]);
+        }
+    }
+}
@@ -0,0 +1,614 @@
+//! Squashfs images, mounted read-only.
+//!
+//! Version 4 images are supported, with their blocks compressed with gzip
+//! (zlib) or zstd, or stored as is. Extended attributes and the export table
+//! are ignored.
+//!
+//! The files are designated by their inode reference, the position of the
+//! inode in the inode table. Decompressed blocks are kept in a small cache,
+//! as sequential reads and path lookups go through the same blocks again and
+//! again.
+use super::devfs::makedev;
+use super::meta::{S_IFDIR, S_IFLNK, S_IFREG};
+use super::mount::{DirEntry, Image, MountFs, NodeInfo};
+use super::statfs::{FsStats, ST_RDONLY};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+
+const SQUASHFS_MAGIC: u32 = 0x7371_7368;
+const SUPERBLOCK_SIZE: usize = 96;
+
+const COMPRESSION_GZIP: u16 = 1;
+const COMPRESSION_ZSTD: u16 = 6;
+
+/// The size of a decompressed metadata block.
+const METADATA_SIZE: usize = 8192;
+/// Set in the header of a metadata block stored uncompressed.
+const METADATA_UNCOMPRESSED: u16 = 1 << 15;
+/// Set in the size of a data block stored uncompressed.
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const DATA_SIZE_MASK: u32 = DATA_UNCOMPRESSED - 1;
+/// The fragment index of a file without a tail in a fragment.
+const NO_FRAGMENT: u32 = 0xffff_ffff;
+
+/// The number of decompressed blocks kept in the cache.
+const CACHE_BLOCKS: usize = 64;
+
+// The inode types. The extended ones follow the basic ones in the same order.
+const INODE_DIR: u16 = 1;
+const INODE_FILE: u16 = 2;
+const INODE_SYMLINK: u16 = 3;
+const INODE_BLKDEV: u16 = 4;
+const INODE_CHRDEV: u16 = 5;
+const INODE_FIFO: u16 = 6;
+const INODE_SOCKET: u16 = 7;
+const INODE_EXT_DIR: u16 = 8;
+const INODE_EXT_FILE: u16 = 9;
+const INODE_EXT_SYMLINK: u16 = 10;
+const INODE_EXT_BLKDEV: u16 = 11;
+const INODE_EXT_CHRDEV: u16 = 12;
+const INODE_EXT_FIFO: u16 = 13;
+const INODE_EXT_SOCKET: u16 = 14;
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// The file type bits of `st_mode` for an inode or directory entry type.
+fn type_mode(inode_type: u16) -> u32 {
+    match inode_type {
+        INODE_DIR | INODE_EXT_DIR => S_IFDIR,
+        INODE_FILE | INODE_EXT_FILE => S_IFREG,
+        INODE_SYMLINK | INODE_EXT_SYMLINK => S_IFLNK,
+        INODE_BLKDEV | INODE_EXT_BLKDEV => 0o060000,
+        INODE_CHRDEV | INODE_EXT_CHRDEV => 0o020000,
+        INODE_FIFO | INODE_EXT_FIFO => 0o010000,
+        _ => 0o140000,
+    }
+}
+
+/// The fields of the superblock which are looked at.
+struct Superblock {
+    inode_count: u32,
+    block_size: u32,
+    frag_count: u32,
+    compressor: u16,
+    id_count: u16,
+    root_inode: u64,
+    bytes_used: u64,
+    id_table: u64,
+    inode_table: u64,
+    dir_table: u64,
+    frag_table: u64,
+}
+
+impl Superblock {
+    fn parse(buf: &[u8]) -> LinuxResult<Self> {
+        let (major, minor) = (u16_at(buf, 28), u16_at(buf, 30));
+        if u32_at(buf, 0) != SQUASHFS_MAGIC || (major, minor) != (4, 0) {
+            return Err(LinuxError::EINVAL);
+        }
+        let sb = Self {
+            inode_count: u32_at(buf, 4),
+            block_size: u32_at(buf, 12),
+            frag_count: u32_at(buf, 16),
+            compressor: u16_at(buf, 20),
+            id_count: u16_at(buf, 26),
+            root_inode: u64_at(buf, 32),
+            bytes_used: u64_at(buf, 40),
+            id_table: u64_at(buf, 48),
+            inode_table: u64_at(buf, 64),
+            dir_table: u64_at(buf, 72),
+            frag_table: u64_at(buf, 80),
+        };
+        if !sb.block_size.is_power_of_two() || !(4096..=1 << 20).contains(&sb.block_size) {
+            return Err(LinuxError::EINVAL);
+        }
+        if !matches!(sb.compressor, COMPRESSION_GZIP | COMPRESSION_ZSTD) {
+            warn!("squashfs: unsupported compressor {}", sb.compressor);
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(sb)
+    }
+}
+
+/// A decompressed block, and the position in the image following it.
+struct Block {
+    data: Vec<u8>,
+    end: u64,
+}
+
+enum InodeData {
+    Dir {
+        /// The start of the listing, relative to the directory table
+        block: u32,
+        offset: u16,
+        /// The size of the listing, plus 3 for the implicit `.` and `..`
+        size: u32,
+    },
+    File {
+        blocks_start: u64,
+        size: u64,
+        fragment: u32,
+        frag_offset: u32,
+        block_sizes: Vec<u32>,
+    },
+    Symlink(String),
+    Device(u32),
+    Ipc,
+}
+
+struct Inode {
+    inode_type: u16,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: u32,
+    ino: u32,
+    nlink: u32,
+    data: InodeData,
+}
+
+/// A mounted squashfs image.
+pub struct SquashFs {
+    image: Image,
+    sb: Superblock,
+    ids: Vec<u32>,
+    /// The position and size of every fragment block
+    fragments: Vec<(u64, u32)>,
+    cache: Mutex<BlockCache>,
+}
+
+/// The most recently read blocks, keyed by their position in the image.
+#[derive(Default)]
+struct BlockCache {
+    blocks: BTreeMap<u64, Arc<Block>>,
+    order: VecDeque<u64>,
+}
+
+/// Reads consecutive bytes of a metadata table, across block boundaries.
+struct MetaReader<'a> {
+    fs: &'a SquashFs,
+    block: Arc<Block>,
+    offset: usize,
+}
+
+impl<'a> MetaReader<'a> {
+    fn new(fs: &'a SquashFs, pos: u64, offset: usize) -> LinuxResult<Self> {
+        Ok(Self {
+            fs,
+            block: fs.metadata_block(pos)?,
+            offset,
+        })
+    }
+
+    fn read(&mut self, mut buf: &mut [u8]) -> LinuxResult {
+        while !buf.is_empty() {
+            if self.offset >= self.block.data.len() {
+                self.offset -= self.block.data.len();
+                self.block = self.fs.metadata_block(self.block.end)?;
+                continue;
+            }
+            let count = buf.len().min(self.block.data.len() - self.offset);
+            buf[..count].copy_from_slice(&self.block.data[self.offset..self.offset + count]);
+            self.offset += count;
+            buf = &mut buf[count..];
+        }
+        Ok(())
+    }
+
+    fn read_vec(&mut self, len: usize) -> LinuxResult<Vec<u8>> {
+        let mut buf = alloc::vec![0; len];
+        self.read(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u16(&mut self) -> LinuxResult<u16> {
+        let mut buf = [0; 2];
+        self.read(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> LinuxResult<u32> {
+        let mut buf = [0; 4];
+        self.read(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> LinuxResult<u64> {
+        let mut buf = [0; 8];
+        self.read(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl SquashFs {
+    /// Check the superblock of `image` and load its id and fragment tables.
+    pub fn new(image: Image) -> LinuxResult<Self> {
+        let sb = Superblock::parse(&image.read_vec_at(0, SUPERBLOCK_SIZE)?)?;
+        let mut fs = Self {
+            image,
+            sb,
+            ids: Vec::new(),
+            fragments: Vec::new(),
+            cache: Mutex::new(BlockCache::default()),
+        };
+        let ids = fs.read_table(fs.sb.id_table, fs.sb.id_count as usize * 4)?;
+        fs.ids = ids.chunks_exact(4).map(|id| u32_at(id, 0)).collect();
+        if fs.sb.frag_count > 0 {
+            let frags = fs.read_table(fs.sb.frag_table, fs.sb.frag_count as usize * 16)?;
+            fs.fragments = frags
+                .chunks_exact(16)
+                .map(|entry| (u64_at(entry, 0), u32_at(entry, 8)))
+                .collect();
+        }
+        Ok(fs)
+    }
+
+    /// Decompress the block `raw`, which holds at most `limit` bytes.
+    fn decompress(&self, raw: &[u8], limit: usize) -> LinuxResult<Vec<u8>> {
+        match self.sb.compressor {
+            COMPRESSION_GZIP => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(raw, limit)
+                .map_err(|_| LinuxError::EIO),
+            _ => {
+                // Fails rather than grow past `limit`
+                let mut data = vec![0; limit];
+                let len = ruzstd::FrameDecoder::new()
+                    .decode_all(raw, &mut data)
+                    .map_err(|_| LinuxError::EIO)?;
+                data.truncate(len);
+                Ok(data)
+            }
+        }
+    }
+
+    /// The block at `pos` in the cache, or `load`ed into it.
+    fn cached(
+        &self,
+        pos: u64,
+        load: impl FnOnce() -> LinuxResult<Block>,
+    ) -> LinuxResult<Arc<Block>> {
+        if let Some(block) = self.cache.lock().blocks.get(&pos) {
+            return Ok(block.clone());
+        }
+        let block = Arc::new(load()?);
+        let mut cache = self.cache.lock();
+        if cache.blocks.insert(pos, block.clone()).is_none() {
+            cache.order.push_back(pos);
+            if cache.order.len() > CACHE_BLOCKS {
+                let oldest = cache.order.pop_front().unwrap();
+                cache.blocks.remove(&oldest);
+            }
+        }
+        Ok(block)
+    }
+
+    /// The metadata block at `pos`.
+    fn metadata_block(&self, pos: u64) -> LinuxResult<Arc<Block>> {
+        self.cached(pos, || {
+            let mut header = [0; 2];
+            self.image.read_exact_at(pos, &mut header)?;
+            let header = u16::from_le_bytes(header);
+            let size = (header & !METADATA_UNCOMPRESSED) as usize;
+            let raw = self.image.read_vec_at(pos + 2, size)?;
+            let data = if header & METADATA_UNCOMPRESSED != 0 {
+                raw
+            } else {
+                self.decompress(&raw, METADATA_SIZE)?
+            };
+            Ok(Block {
+                data,
+                end: pos + 2 + size as u64,
+            })
+        })
+    }
+
+    /// The data block at `pos`, of the size `size` as stored in the inode.
+    fn data_block(&self, pos: u64, size: u32) -> LinuxResult<Arc<Block>> {
+        self.cached(pos, || {
+            let len = (size & DATA_SIZE_MASK) as usize;
+            let raw = self.image.read_vec_at(pos, len)?;
+            let data = if size & DATA_UNCOMPRESSED != 0 {
+                raw
+            } else {
+                self.decompress(&raw, self.sb.block_size as usize)?
+            };
+            Ok(Block {
+                data,
+                end: pos + len as u64,
+            })
+        })
+    }
+
+    /// Read the first `len` bytes of the table at `start`, which is indexed
+    /// by a list of the positions of its metadata blocks.
+    fn read_table(&self, start: u64, len: usize) -> LinuxResult<Vec<u8>> {
+        let count = len.div_ceil(METADATA_SIZE);
+        let index = self.image.read_vec_at(start, count * 8)?;
+        let mut table = Vec::with_capacity(len);
+        for pos in index.chunks_exact(8) {
+            table.extend_from_slice(&self.metadata_block(u64_at(pos, 0))?.data);
+        }
+        if table.len() < len {
+            return Err(LinuxError::EIO);
+        }
+        table.truncate(len);
+        Ok(table)
+    }
+
+    fn id(&self, index: u16) -> LinuxResult<u32> {
+        self.ids.get(index as usize).copied().ok_or(LinuxError::EIO)
+    }
+
+    fn read_inode(&self, node: u64) -> LinuxResult<Inode> {
+        let mut r = MetaReader::new(
+            self,
+            self.sb.inode_table + (node >> 16),
+            node as u16 as usize,
+        )?;
+        let inode_type = r.u16()?;
+        let perm = r.u16()? as u32;
+        let uid = self.id(r.u16()?)?;
+        let gid = self.id(r.u16()?)?;
+        let mtime = r.u32()?;
+        let ino = r.u32()?;
+        let mut nlink = 1;
+        let data = match inode_type {
+            INODE_DIR => {
+                let block = r.u32()?;
+                nlink = r.u32()?;
+                let size = r.u16()? as u32;
+                let offset = r.u16()?;
+                InodeData::Dir {
+                    block,
+                    offset,
+                    size,
+                }
+            }
+            INODE_EXT_DIR => {
+                nlink = r.u32()?;
+                let size = r.u32()?;
+                let block = r.u32()?;
+                let _parent = r.u32()?;
+                let _index_count = r.u16()?;
+                let offset = r.u16()?;
+                InodeData::Dir {
+                    block,
+                    offset,
+                    size,
+                }
+            }
+            INODE_FILE | INODE_EXT_FILE => {
+                let (blocks_start, fragment, frag_offset, size) = if inode_type == INODE_FILE {
+                    let blocks_start = r.u32()? as u64;
+                    let fragment = r.u32()?;
+                    let frag_offset = r.u32()?;
+                    (blocks_start, fragment, frag_offset, r.u32()? as u64)
+                } else {
+                    let blocks_start = r.u64()?;
+                    let size = r.u64()?;
+                    let _sparse = r.u64()?;
+                    nlink = r.u32()?;
+                    let fragment = r.u32()?;
+                    let frag_offset = r.u32()?;
+                    let _xattr = r.u32()?;
+                    (blocks_start, fragment, frag_offset, size)
+                };
+                let block_size = self.sb.block_size as u64;
+                // The tail of the file is in a fragment, if it has one
+                let count = if fragment == NO_FRAGMENT {
+                    size.div_ceil(block_size)
+                } else {
+                    size / block_size
+                };
+                let sizes = r.read_vec(count as usize * 4)?;
+                InodeData::File {
+                    blocks_start,
+                    size,
+                    fragment,
+                    frag_offset,
+                    block_sizes: sizes.chunks_exact(4).map(|s| u32_at(s, 0)).collect(),
+                }
+            }
+            INODE_SYMLINK | INODE_EXT_SYMLINK => {
+                nlink = r.u32()?;
+                let len = r.u32()? as usize;
+                let target = r.read_vec(len)?;
+                InodeData::Symlink(String::from_utf8(target).map_err(|_| LinuxError::EIO)?)
+            }
+            INODE_BLKDEV | INODE_CHRDEV | INODE_EXT_BLKDEV | INODE_EXT_CHRDEV => {
+                nlink = r.u32()?;
+                InodeData::Device(r.u32()?)
+            }
+            INODE_FIFO | INODE_SOCKET | INODE_EXT_FIFO | INODE_EXT_SOCKET => {
+                nlink = r.u32()?;
+                InodeData::Ipc
+            }
+            _ => return Err(LinuxError::EIO),
+        };
+        Ok(Inode {
+            inode_type,
+            mode: type_mode(inode_type) | perm,
+            uid,
+            gid,
+            mtime,
+            ino,
+            nlink,
+            data,
+        })
+    }
+
+    /// Copy the part of `data` at `offset` to `buf`, returning its length.
+    fn copy_from(data: &[u8], offset: usize, buf: &mut [u8]) -> usize {
+        let data = data.get(offset..).unwrap_or_default();
+        let count = buf.len().min(data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        count
+    }
+}
+
+impl MountFs for SquashFs {
+    fn root(&self) -> u64 {
+        self.sb.root_inode
+    }
+
+    fn info(&self, node: u64) -> LinuxResult<NodeInfo> {
+        let inode = self.read_inode(node)?;
+        let (size, rdev) = match &inode.data {
+            InodeData::Dir { size, .. } => (*size as u64, 0),
+            InodeData::File { size, .. } => (*size, 0),
+            InodeData::Symlink(target) => (target.len() as u64, 0),
+            // The device number is in the encoding of the Linux `new_encode_dev`
+            InodeData::Device(dev) => {
+                let major = (dev & 0xfff00) >> 8;
+                let minor = (dev & 0xff) | ((dev >> 12) & 0xfff00);
+                (0, makedev(major, minor))
+            }
+            InodeData::Ipc => (0, 0),
+        };
+        Ok(NodeInfo {
+            ino: inode.ino as u64,
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            size,
+            nlink: inode.nlink,
+            rdev,
+            mtime: inode.mtime as i64,
+        })
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> LinuxResult<u64> {
+        self.read_dir(dir)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.node)
+            .ok_or(LinuxError::ENOENT)
+    }
+
+    fn read_dir(&self, dir: u64) -> LinuxResult<Vec<DirEntry>> {
+        let inode = self.read_inode(dir)?;
+        let InodeData::Dir {
+            block,
+            offset,
+            size,
+        } = inode.data
+        else {
+            return Err(LinuxError::ENOTDIR);
+        };
+        let mut entries = Vec::new();
+        // The listing is a sequence of runs of entries with their inodes in
+        // the same metadata block, each run preceded by a header
+        let mut remaining = size.saturating_sub(3) as usize;
+        let mut r = MetaReader::new(self, self.sb.dir_table + block as u64, offset as usize)?;
+        while remaining >= 12 {
+            let count = r.u32()? as usize + 1;
+            let start = r.u32()? as u64;
+            let _base_ino = r.u32()?;
+            remaining -= 12;
+            for _ in 0..count {
+                let offset = r.u16()? as u64;
+                let _ino_delta = r.u16()?;
+                let entry_type = r.u16()?;
+                let name_len = r.u16()? as usize + 1;
+                let name = r.read_vec(name_len)?;
+                remaining = remaining.saturating_sub(8 + name_len);
+                entries.push(DirEntry {
+                    name: String::from_utf8(name).map_err(|_| LinuxError::EIO)?,
+                    node: start << 16 | offset,
+                    mode: type_mode(entry_type),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_at(&self, node: u64, offset: u64, buf: &mut [u8]) -> LinuxResult<usize> {
+        let inode = self.read_inode(node)?;
+        let InodeData::File {
+            blocks_start,
+            size,
+            fragment,
+            frag_offset,
+            block_sizes,
+        } = inode.data
+        else {
+            return Err(
+                if inode.inode_type == INODE_DIR || inode.inode_type == INODE_EXT_DIR {
+                    LinuxError::EISDIR
+                } else {
+                    LinuxError::EINVAL
+                },
+            );
+        };
+        let block_size = self.sb.block_size as u64;
+        let end = size.min(offset.saturating_add(buf.len() as u64));
+        let mut pos = offset;
+        // The position of the block holding `pos`
+        let mut block_pos = blocks_start;
+        for stored in block_sizes.iter().take((pos / block_size) as usize) {
+            block_pos += (stored & DATA_SIZE_MASK) as u64;
+        }
+        while pos < end {
+            let index = (pos / block_size) as usize;
+            let within = (pos % block_size) as usize;
+            let dst = &mut buf[(pos - offset) as usize..(end - offset) as usize];
+            let count = if let Some(&stored) = block_sizes.get(index) {
+                let count = if stored & DATA_SIZE_MASK == 0 {
+                    // A sparse block
+                    let count = dst.len().min(block_size as usize - within);
+                    dst[..count].fill(0);
+                    count
+                } else {
+                    let block = self.data_block(block_pos, stored)?;
+                    Self::copy_from(&block.data, within, dst)
+                };
+                block_pos += (stored & DATA_SIZE_MASK) as u64;
+                count
+            } else {
+                let &(frag_pos, frag_size) = self
+                    .fragments
+                    .get(fragment as usize)
+                    .ok_or(LinuxError::EIO)?;
+                let block = self.data_block(frag_pos, frag_size)?;
+                Self::copy_from(&block.data, frag_offset as usize + within, dst)
+            };
+            if count == 0 {
+                return Err(LinuxError::EIO);
+            }
+            pos += count as u64;
+        }
+        Ok((pos - offset) as usize)
+    }
+
+    fn read_link(&self, node: u64) -> LinuxResult<String> {
+        match self.read_inode(node)?.data {
+            InodeData::Symlink(target) => Ok(target),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+
+    fn stats(&self) -> LinuxResult<FsStats> {
+        Ok(FsStats {
+            bsize: self.sb.block_size as u64,
+            blocks: self.sb.bytes_used.div_ceil(self.sb.block_size as u64),
+            files: self.sb.inode_count as u64,
+            namelen: 256,
+            flags: ST_RDONLY,
+            ..FsStats::empty(SQUASHFS_MAGIC as u64)
+        })
+    }
+}
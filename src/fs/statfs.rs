@@ -0,0 +1,111 @@
+//! Filesystem statistics, as `statfs` reports them.
+//!
+//! The FAT image doesn't tell its size through `axfs`, so its blocks are
+//! reported as 0, like those of the filesystems without a backing store.
+//! tmpfs, and the RAM-backed `/tmp` of `axfs`, may take half the RAM, like a
+//! tmpfs on Linux by default.
+use super::{devfs, ext4, mount, overlay, procfs};
+use crate::mm::ram_usage;
+use axerrno::LinuxResult;
+use memory_addr::PAGE_SIZE_4K;
+
+pub const EXT4_SUPER_MAGIC: u64 = 0xef53;
+pub const MSDOS_SUPER_MAGIC: u64 = 0x4d44;
+pub const TMPFS_MAGIC: u64 = 0x0102_1994;
+pub const PROC_SUPER_MAGIC: u64 = 0x9fa0;
+pub const SYSFS_MAGIC: u64 = 0x6265_6572;
+pub const OVERLAYFS_SUPER_MAGIC: u64 = 0x794c_7630;
+pub const PIPEFS_MAGIC: u64 = 0x5049_5045;
+
+/// Mounted read-only.
+pub const ST_RDONLY: u64 = 1;
+
+/// The longest file name of FAT with long names, and of most filesystems.
+const NAME_MAX: u64 = 255;
+
+/// The statistics of a filesystem, in units of `bsize` bytes for the blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct FsStats {
+    /// The type of the filesystem, as the magic of its Linux driver
+    pub magic: u64,
+    pub bsize: u64,
+    pub blocks: u64,
+    pub bfree: u64,
+    /// The free blocks unprivileged users may take
+    pub bavail: u64,
+    /// The total number of inodes, 0 if unlimited
+    pub files: u64,
+    pub ffree: u64,
+    pub namelen: u64,
+    /// `ST_*` mount flags
+    pub flags: u64,
+}
+
+impl FsStats {
+    /// The statistics of a filesystem without any blocks, like procfs.
+    pub fn empty(magic: u64) -> Self {
+        Self {
+            magic,
+            bsize: PAGE_SIZE_4K as u64,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            namelen: NAME_MAX,
+            flags: 0,
+        }
+    }
+
+    /// The statistics of a tmpfs, which may take half the RAM.
+    pub fn tmpfs() -> Self {
+        let ram = ram_usage();
+        let blocks = (ram.total / 2 / PAGE_SIZE_4K) as u64;
+        let free = ((ram.free / PAGE_SIZE_4K) as u64).min(blocks);
+        Self {
+            blocks,
+            bfree: free,
+            bavail: free,
+            ..Self::empty(TMPFS_MAGIC)
+        }
+    }
+}
+
+/// Whether `path` is `dir` or lies below it.
+fn is_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The statistics of the filesystem holding the file at the absolute,
+/// resolved `path`.
+pub fn stats(path: &str) -> LinuxResult<FsStats> {
+    if procfs::is_procfs_path(path) {
+        return Ok(FsStats::empty(PROC_SUPER_MAGIC));
+    }
+    if devfs::is_devfs_path(path) {
+        return Ok(FsStats::empty(TMPFS_MAGIC));
+    }
+    if is_below(path, "/sys") {
+        return Ok(FsStats::empty(SYSFS_MAGIC));
+    }
+    if let Some(stats) = mount::stats(path) {
+        return stats;
+    }
+    // The tmpfs mounts lie there too
+    if is_below(path, "/tmp") {
+        return Ok(FsStats::tmpfs());
+    }
+    let stats = if ext4::is_enabled() {
+        ext4::stats()?
+    } else {
+        FsStats::empty(MSDOS_SUPER_MAGIC)
+    };
+    if overlay::covers(path) {
+        return Ok(FsStats {
+            magic: OVERLAYFS_SUPER_MAGIC,
+            ..stats
+        });
+    }
+    Ok(stats)
+}
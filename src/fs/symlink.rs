@@ -0,0 +1,162 @@
+//! Symbolic links.
+//!
+//! FAT has no symbolic links, so they are kept here by the absolute path of
+//! the link, and path resolution substitutes their targets before a path is
+//! handed down to the filesystem. Those of an ext4 root filesystem are stored
+//! in the image, see [`super::ext4`], and followed the same way.
+use super::{ext4, mount, normalize_path, procfs};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+
+/// The maximum number of links followed while resolving a single path.
+const MAXSYMLINKS: usize = 40;
+/// The maximum length of a link target, including the trailing NUL.
+const PATH_MAX: usize = 4096;
+
+static SYMLINKS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+/// The links when the overlay was enabled, which [`restore`] puts back.
+static SAVED: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// The target of the link at the absolute, normalized `path`.
+pub fn read(path: &str) -> Option<String> {
+    SYMLINKS.lock().get(path).cloned()
+}
+
+/// Whether there is a link at the absolute, normalized `path`.
+pub fn is_symlink(path: &str) -> bool {
+    SYMLINKS.lock().contains_key(path)
+}
+
+/// Create a link at the absolute, normalized `path` pointing to `target`.
+///
+/// The caller checks that no file exists at `path`.
+pub fn create(path: &str, target: &str) -> LinuxResult {
+    if target.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    if target.len() >= PATH_MAX {
+        return Err(LinuxError::ENAMETOOLONG);
+    }
+    let mut links = SYMLINKS.lock();
+    if links.contains_key(path) {
+        return Err(LinuxError::EEXIST);
+    }
+    links.insert(String::from(path), String::from(target));
+    Ok(())
+}
+
+/// Remove the link at `path`, returning whether there was one.
+pub fn remove(path: &str) -> bool {
+    SYMLINKS.lock().remove(path).is_some()
+}
+
+/// Remember the links, for [`restore`].
+pub fn save() {
+    *SAVED.lock() = SYMLINKS.lock().clone();
+}
+
+/// Put back the links in the overlay as [`save`] found them.
+pub fn restore() {
+    super::restore_keys(&mut SYMLINKS.lock(), &SAVED.lock());
+}
+
+/// Move the link at `old`, or the links below the directory `old`, to `new`.
+pub fn rename(old: &str, new: &str) {
+    super::rename_keys(&mut SYMLINKS.lock(), old, new);
+}
+
+/// The names of the links directly inside the directory `dir`.
+pub fn list(dir: &str) -> Vec<String> {
+    let prefix = if dir == "/" {
+        String::from("/")
+    } else {
+        format!("{}/", dir)
+    };
+    SYMLINKS
+        .lock()
+        .keys()
+        .filter_map(|path| path.strip_prefix(prefix.as_str()))
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+        .map(String::from)
+        .collect()
+}
+
+/// Resolve the links in the absolute, normalized `path`, including those of
+/// the mounted filesystems, of an ext4 root filesystem, `/proc/<pid>/exe` and
+/// the bind mounts of `axfs` directories.
+///
+/// The last component is only followed if `follow_last` is set. Fails with
+/// `ELOOP` if more than `MAXSYMLINKS` links are met.
+pub fn resolve(path: &str, follow_last: bool) -> LinuxResult<String> {
+    let links = SYMLINKS.lock();
+    if links.is_empty()
+        && !mount::has_mounts()
+        && !ext4::is_enabled()
+        && !procfs::is_procfs_path(path)
+    {
+        return Ok(String::from(path));
+    }
+
+    let mut path = String::from(path);
+    let mut followed = 0;
+    // The leading components which a bind mount led to, and which aren't
+    // mount points themselves even if they look like one, e.g. for a tmpfs
+    // mounted on `/tmp`
+    let mut redirected = 0;
+    'restart: loop {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let mut resolved = String::new();
+        for (i, component) in components.iter().enumerate() {
+            let parent_len = resolved.len();
+            resolved.push('/');
+            resolved.push_str(component);
+
+            let is_last = i + 1 == components.len();
+            if is_last && !follow_last {
+                break;
+            }
+            // A bind mount of `axfs` leads to its source, like a link
+            if let Some(source) = (i >= redirected)
+                .then(|| mount::bind_source(&resolved))
+                .flatten()
+            {
+                followed += 1;
+                if followed > MAXSYMLINKS {
+                    return Err(LinuxError::ELOOP);
+                }
+                let rest = components[i + 1..].join("/");
+                path = normalize_path(&format!("{}/{}", source, rest));
+                redirected = source.split('/').filter(|c| !c.is_empty()).count();
+                continue 'restart;
+            }
+            let Some(target) = links
+                .get(&resolved)
+                .cloned()
+                .or_else(|| mount::read_link(&resolved))
+                .or_else(|| ext4::read_link(&resolved))
+                .or_else(|| procfs::exe_link(&resolved))
+            else {
+                continue;
+            };
+            followed += 1;
+            if followed > MAXSYMLINKS {
+                return Err(LinuxError::ELOOP);
+            }
+            // A relative target is relative to the directory holding the link
+            let base = if target.starts_with('/') {
+                String::new()
+            } else {
+                String::from(&resolved[..parent_len])
+            };
+            let rest = components[i + 1..].join("/");
+            path = normalize_path(&format!("{}/{}/{}", base, target, rest));
+            redirected = 0;
+            continue 'restart;
+        }
+        return Ok(normalize_path(&path));
+    }
+}
@@ -0,0 +1,526 @@
+//! Tar and cpio archives, mounted read-only.
+//!
+//! The archive is scanned once at mount time to build the directory tree, and
+//! the content of the files is then read from the archive as is. Ustar, GNU
+//! and pax tar archives are supported, as are the `newc` cpio archives of the
+//! Linux initramfs. Directories missing from the archive are implied by the
+//! paths of their files.
+use super::devfs::makedev;
+use super::meta::{S_IFDIR, S_IFLNK, S_IFMT, S_IFREG, S_IPERM};
+use super::mount::{DirEntry, Image, MountFs, NodeInfo};
+use super::statfs::{FsStats, ST_RDONLY};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+
+const BLOCK_SIZE: u64 = 512;
+/// Linux has no driver for archives, so this is made up, "tarf".
+const TARFS_MAGIC: u64 = 0x7461_7266;
+const CPIO_NEWC_MAGIC: &[u8] = b"070701";
+const CPIO_CRC_MAGIC: &[u8] = b"070702";
+const CPIO_HEADER_SIZE: u64 = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+/// The longest path, or symbolic link target, read from the archive
+const PATH_MAX: u64 = 4096;
+/// The largest pax extended header read from the archive
+const PAX_HEADER_MAX: u64 = 1 << 20;
+
+/// A file of the archive.
+struct Node {
+    info: NodeInfo,
+    /// Where the content of a regular file starts in the archive
+    data: u64,
+    /// The target of a symbolic link
+    target: String,
+    children: BTreeMap<String, u64>,
+}
+
+impl Node {
+    fn new(mode: u32) -> Self {
+        Self {
+            info: NodeInfo {
+                mode,
+                nlink: 1,
+                ..Default::default()
+            },
+            data: 0,
+            target: String::new(),
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+/// A mounted tar or cpio archive. The node of a file is its index in `nodes`.
+pub struct TarFs {
+    image: Image,
+    nodes: Vec<Node>,
+}
+
+/// The null-terminated string at the start of `field`.
+fn c_str(field: &[u8]) -> LinuxResult<String> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8(field[..len].to_vec()).map_err(|_| LinuxError::EIO)
+}
+
+/// A numeric field of a tar header, in octal or in GNU base-256.
+fn tar_number(field: &[u8]) -> LinuxResult<u64> {
+    if field[0] & 0x80 != 0 {
+        let value = field[1..]
+            .iter()
+            .fold(0u64, |value, &b| value << 8 | b as u64);
+        return Ok(value);
+    }
+    let digits = c_str(field)?;
+    let digits = digits.trim_matches(' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| LinuxError::EIO)
+}
+
+/// A field of a `newc` cpio header, in hexadecimal.
+fn cpio_number(field: &[u8]) -> LinuxResult<u64> {
+    core::str::from_utf8(field)
+        .ok()
+        .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+        .ok_or(LinuxError::EIO)
+}
+
+/// Whether the checksum of the tar header `header` is right.
+fn tar_checksum_ok(header: &[u8]) -> bool {
+    let Ok(expected) = tar_number(&header[148..156]) else {
+        return false;
+    };
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+        .sum();
+    sum == expected
+}
+
+/// The records of a pax extended header, `<length> <key>=<value>\n` each.
+fn pax_records(data: &[u8]) -> BTreeMap<String, String> {
+    let mut records = BTreeMap::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|&b| b == b' ') {
+        let Some(len) = core::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len > space && len <= rest.len())
+        else {
+            break;
+        };
+        let record = &rest[space + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Ok(record) = core::str::from_utf8(record) {
+            if let Some((key, value)) = record.split_once('=') {
+                records.insert(String::from(key), String::from(value));
+            }
+        }
+        rest = &rest[len..];
+    }
+    records
+}
+
+/// The fields of an archive entry, whatever the format.
+struct Entry {
+    path: String,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: i64,
+    rdev: u64,
+    /// The target of a symbolic or hard link
+    link: String,
+    hard_link: bool,
+    data: u64,
+}
+
+impl TarFs {
+    /// Scan the archive in `image`, which is a cpio archive if it starts with
+    /// a cpio magic and a tar archive otherwise.
+    pub fn new(image: Image) -> LinuxResult<Self> {
+        let mut fs = Self {
+            image,
+            nodes: alloc::vec![Node::new(S_IFDIR | 0o755)],
+        };
+        let magic = fs
+            .image
+            .read_vec_at(0, CPIO_NEWC_MAGIC.len().min(fs.image.len() as usize))?;
+        if magic == CPIO_NEWC_MAGIC || magic == CPIO_CRC_MAGIC {
+            fs.scan_cpio()?;
+        } else {
+            fs.scan_tar()?;
+        }
+        for (index, node) in fs.nodes.iter_mut().enumerate() {
+            node.info.ino = index as u64 + 1;
+        }
+        Ok(fs)
+    }
+
+    fn scan_tar(&mut self) -> LinuxResult {
+        let mut pos = 0;
+        let mut long_name = None;
+        let mut long_link = None;
+        let mut pax = BTreeMap::new();
+        while pos + BLOCK_SIZE <= self.image.len() {
+            let header = self.image.read_vec_at(pos, BLOCK_SIZE as usize)?;
+            // The archive ends with zero blocks
+            if header.iter().all(|&b| b == 0) {
+                break;
+            }
+            if !tar_checksum_ok(&header) {
+                return Err(if pos == 0 {
+                    LinuxError::EINVAL
+                } else {
+                    LinuxError::EIO
+                });
+            }
+            let data = pos + BLOCK_SIZE;
+            let mut size = tar_number(&header[124..136])?;
+            let type_flag = header[156];
+            if let Some(pax_size) = pax.get("size").and_then(|s: &String| s.parse().ok()) {
+                size = pax_size;
+            }
+            // The content, padded to a block, must be in the archive
+            if size > self.image.len() - data {
+                return Err(LinuxError::EIO);
+            }
+            pos = data + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            match type_flag {
+                b'L' | b'K' => {
+                    if size > PATH_MAX {
+                        return Err(LinuxError::EIO);
+                    }
+                    let value = c_str(&self.image.read_vec_at(data, size as usize)?)?;
+                    if type_flag == b'L' {
+                        long_name = Some(value);
+                    } else {
+                        long_link = Some(value);
+                    }
+                    continue;
+                }
+                b'x' => {
+                    if size > PAX_HEADER_MAX {
+                        return Err(LinuxError::EIO);
+                    }
+                    pax = pax_records(&self.image.read_vec_at(data, size as usize)?);
+                    continue;
+                }
+                // Global pax headers and GNU volume labels describe no file
+                b'g' | b'V' => continue,
+                _ => {}
+            }
+
+            let mut path = c_str(&header[0..100])?;
+            if &header[257..262] == b"ustar" {
+                let prefix = c_str(&header[345..500])?;
+                if !prefix.is_empty() {
+                    path = alloc::format!("{}/{}", prefix, path);
+                }
+            }
+            let path = pax.remove("path").or(long_name.take()).unwrap_or(path);
+            let link = pax
+                .remove("linkpath")
+                .or(long_link.take())
+                .map_or_else(|| c_str(&header[157..257]), Ok)?;
+            let file_type = match type_flag {
+                b'0' | b'\0' | b'7' | b'1' => S_IFREG,
+                b'2' => S_IFLNK,
+                b'3' => 0o020000,
+                b'4' => 0o060000,
+                b'5' => S_IFDIR,
+                b'6' => 0o010000,
+                _ => {
+                    pax.clear();
+                    continue;
+                }
+            };
+            let mtime = pax
+                .get("mtime")
+                .and_then(|mtime| mtime.split('.').next()?.parse().ok())
+                .unwrap_or(tar_number(&header[136..148])? as i64);
+            let entry = Entry {
+                path,
+                mode: file_type | (tar_number(&header[100..108])? as u32 & S_IPERM),
+                uid: tar_number(&header[108..116])? as u32,
+                gid: tar_number(&header[116..124])? as u32,
+                size: if file_type == S_IFREG { size } else { 0 },
+                mtime,
+                rdev: makedev(
+                    tar_number(&header[329..337])? as u32,
+                    tar_number(&header[337..345])? as u32,
+                ),
+                link,
+                hard_link: type_flag == b'1',
+                data,
+            };
+            pax.clear();
+            self.add(entry)?;
+        }
+        Ok(())
+    }
+
+    fn scan_cpio(&mut self) -> LinuxResult {
+        let mut pos = 0;
+        // The files with several links, by their device and inode numbers
+        let mut links = BTreeMap::new();
+        while pos + CPIO_HEADER_SIZE <= self.image.len() {
+            let header = self.image.read_vec_at(pos, CPIO_HEADER_SIZE as usize)?;
+            if &header[..6] != CPIO_NEWC_MAGIC && &header[..6] != CPIO_CRC_MAGIC {
+                return Err(LinuxError::EIO);
+            }
+            let field = |index: usize| cpio_number(&header[6 + index * 8..14 + index * 8]);
+            let (ino, mode, uid, gid, nlink) =
+                (field(0)?, field(1)?, field(2)?, field(3)?, field(4)?);
+            let (mtime, size) = (field(5)?, field(6)?);
+            let dev = (field(7)?, field(8)?);
+            let rdev = makedev(field(9)? as u32, field(10)? as u32);
+            let name_size = field(11)?;
+            if name_size > PATH_MAX {
+                return Err(LinuxError::EIO);
+            }
+
+            let name = c_str(
+                &self
+                    .image
+                    .read_vec_at(pos + CPIO_HEADER_SIZE, name_size as usize)?,
+            )?;
+            // The name and the data are both padded to 4 bytes
+            let data = (pos + CPIO_HEADER_SIZE + name_size).next_multiple_of(4);
+            pos = (data + size).next_multiple_of(4);
+            if name == CPIO_TRAILER {
+                break;
+            }
+
+            let mode = mode as u32;
+            let link = if mode & S_IFMT == S_IFLNK {
+                if size > PATH_MAX {
+                    return Err(LinuxError::EIO);
+                }
+                c_str(&self.image.read_vec_at(data, size as usize)?)?
+            } else {
+                String::new()
+            };
+            let mut entry = Entry {
+                path: name,
+                mode,
+                uid: uid as u32,
+                gid: gid as u32,
+                size: if mode & S_IFMT == S_IFREG { size } else { 0 },
+                mtime: mtime as i64,
+                rdev,
+                link,
+                hard_link: false,
+                data,
+            };
+            // Only the last link of a file carries its content
+            if nlink > 1 && mode & S_IFMT != S_IFDIR {
+                if let Some(first) = links.get(&(dev, ino)) {
+                    if entry.size > 0 {
+                        let node: &mut Node = &mut self.nodes[*first as usize];
+                        node.info.size = entry.size;
+                        node.data = entry.data;
+                    }
+                    entry.link = self.path_of(*first).unwrap_or_default();
+                    entry.hard_link = true;
+                } else {
+                    let node = self.add(entry)?;
+                    links.insert((dev, ino), node);
+                    continue;
+                }
+            }
+            self.add(entry)?;
+        }
+        Ok(())
+    }
+
+    /// The path of `node` in the archive, as given when it was added.
+    fn path_of(&self, node: u64) -> Option<String> {
+        fn find(nodes: &[Node], dir: u64, node: u64, path: &mut Vec<String>) -> bool {
+            for (name, &child) in nodes[dir as usize].children.iter() {
+                path.push(name.clone());
+                if child == node
+                    || (nodes[child as usize].info.is_dir() && find(nodes, child, node, path))
+                {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        }
+        let mut path = Vec::new();
+        find(&self.nodes, 0, node, &mut path).then(|| path.join("/"))
+    }
+
+    /// The directory `path`, created along with its parents if missing.
+    fn make_dirs(&mut self, path: &[&str]) -> LinuxResult<u64> {
+        let mut dir = 0;
+        for &name in path {
+            dir = match self.nodes[dir as usize].children.get(name) {
+                Some(&child) if self.nodes[child as usize].info.is_dir() => child,
+                Some(_) => return Err(LinuxError::EIO),
+                None => {
+                    let child = self.nodes.len() as u64;
+                    self.nodes.push(Node::new(S_IFDIR | 0o755));
+                    self.nodes[dir as usize]
+                        .children
+                        .insert(String::from(name), child);
+                    child
+                }
+            };
+        }
+        Ok(dir)
+    }
+
+    /// Add the file of `entry` to the tree, returning its node.
+    fn add(&mut self, entry: Entry) -> LinuxResult<u64> {
+        let components: Vec<&str> = entry
+            .path
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .collect();
+        let Some((&name, parents)) = components.split_last() else {
+            // The root directory itself
+            self.nodes[0].info = NodeInfo {
+                nlink: 1,
+                ..Self::info_of(&entry)
+            };
+            return Ok(0);
+        };
+        if parents.contains(&"..") || name == ".." {
+            return Err(LinuxError::EIO);
+        }
+        let dir = self.make_dirs(parents)?;
+
+        if entry.hard_link {
+            let target: Vec<&str> = entry
+                .link
+                .split('/')
+                .filter(|c| !c.is_empty() && *c != ".")
+                .collect();
+            let mut node = 0;
+            for name in target {
+                node = *self.nodes[node as usize]
+                    .children
+                    .get(name)
+                    .ok_or(LinuxError::EIO)?;
+            }
+            self.nodes[node as usize].info.nlink += 1;
+            self.nodes[dir as usize]
+                .children
+                .insert(String::from(name), node);
+            return Ok(node);
+        }
+
+        // A directory listed after its content keeps it
+        if let Some(&existing) = self.nodes[dir as usize].children.get(name) {
+            if self.nodes[existing as usize].info.is_dir() && entry.mode & S_IFMT == S_IFDIR {
+                self.nodes[existing as usize].info = Self::info_of(&entry);
+                return Ok(existing);
+            }
+        }
+        let node = self.nodes.len() as u64;
+        self.nodes.push(Node {
+            info: Self::info_of(&entry),
+            data: entry.data,
+            target: entry.link,
+            children: BTreeMap::new(),
+        });
+        self.nodes[dir as usize]
+            .children
+            .insert(String::from(name), node);
+        Ok(node)
+    }
+
+    fn info_of(entry: &Entry) -> NodeInfo {
+        NodeInfo {
+            ino: 0,
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            size: if entry.mode & S_IFMT == S_IFLNK {
+                entry.link.len() as u64
+            } else {
+                entry.size
+            },
+            nlink: 1,
+            rdev: entry.rdev,
+            mtime: entry.mtime,
+        }
+    }
+
+    fn node(&self, node: u64) -> LinuxResult<&Node> {
+        self.nodes.get(node as usize).ok_or(LinuxError::ENOENT)
+    }
+}
+
+impl MountFs for TarFs {
+    fn root(&self) -> u64 {
+        0
+    }
+
+    fn info(&self, node: u64) -> LinuxResult<NodeInfo> {
+        Ok(self.node(node)?.info)
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> LinuxResult<u64> {
+        let dir = self.node(dir)?;
+        if !dir.info.is_dir() {
+            return Err(LinuxError::ENOTDIR);
+        }
+        dir.children.get(name).copied().ok_or(LinuxError::ENOENT)
+    }
+
+    fn read_dir(&self, dir: u64) -> LinuxResult<Vec<DirEntry>> {
+        let dir = self.node(dir)?;
+        if !dir.info.is_dir() {
+            return Err(LinuxError::ENOTDIR);
+        }
+        Ok(dir
+            .children
+            .iter()
+            .map(|(name, &node)| DirEntry {
+                name: name.clone(),
+                node,
+                mode: self.nodes[node as usize].info.mode & S_IFMT,
+            })
+            .collect())
+    }
+
+    fn read_at(&self, node: u64, offset: u64, buf: &mut [u8]) -> LinuxResult<usize> {
+        let node = self.node(node)?;
+        if node.info.is_dir() {
+            return Err(LinuxError::EISDIR);
+        }
+        if offset >= node.info.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((node.info.size - offset) as usize);
+        self.image
+            .read_exact_at(node.data + offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn read_link(&self, node: u64) -> LinuxResult<String> {
+        let node = self.node(node)?;
+        if node.info.mode & S_IFMT != S_IFLNK {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(node.target.clone())
+    }
+
+    fn stats(&self) -> LinuxResult<FsStats> {
+        Ok(FsStats {
+            bsize: BLOCK_SIZE,
+            blocks: self.image.len().div_ceil(BLOCK_SIZE),
+            files: self.nodes.len() as u64,
+            flags: ST_RDONLY,
+            ..FsStats::empty(TARFS_MAGIC)
+        })
+    }
+}
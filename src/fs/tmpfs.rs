@@ -0,0 +1,79 @@
+//! tmpfs, mounted with `mount -t tmpfs`.
+//!
+//! The files of each tmpfs live in a directory of its own in the RAM-backed
+//! filesystem of `axfs` at `/tmp`, under [`TMPFS_ROOT`], which is hidden from
+//! listings. The mount point leads there during path resolution, like a bind
+//! mount, so that the files of a tmpfs support everything those of `/tmp` do:
+//! directories, symbolic links, permissions, truncation and `mmap`.
+//!
+//! A tmpfs is shared by the mount namespaces which inherited it, and its files
+//! are discarded once it is unmounted from the last of them.
+use super::{inode, meta, quota, stat_path, symlink};
+use alloc::format;
+use alloc::string::String;
+use axerrno::{LinuxError, LinuxResult};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The directory holding the files of all the tmpfs instances.
+pub const TMPFS_ROOT: &str = "/tmp/.tmpfs";
+
+/// The number of the next tmpfs, naming its directory.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A mounted tmpfs.
+pub struct Tmpfs {
+    dir: String,
+}
+
+impl Tmpfs {
+    /// A new, empty tmpfs.
+    pub fn new() -> LinuxResult<Self> {
+        if axfs::api::metadata(TMPFS_ROOT).is_err() {
+            axfs::api::create_dir(TMPFS_ROOT)?;
+        }
+        let dir = format!("{}/{}", TMPFS_ROOT, NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        axfs::api::create_dir(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// The directory of `axfs` holding the files.
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+}
+
+/// Remove the content of the directory `path` and what the kernel recorded
+/// about it.
+fn remove_dir_all(path: &str) -> LinuxResult {
+    for name in symlink::list(path) {
+        symlink::remove(&format!("{}/{}", path, name));
+    }
+    for entry in axfs::api::read_dir(path)?.flatten() {
+        let child = format!("{}/{}", path, entry.file_name());
+        if entry.file_type().is_dir() {
+            remove_dir_all(&child)?;
+            axfs::api::remove_dir(&child)?;
+        } else {
+            let stat = stat_path(&child);
+            axfs::api::remove_file(&child)?;
+            if let Ok(stat) = &stat {
+                quota::release(stat);
+            }
+        }
+        meta::remove(&child);
+        inode::remove(&child);
+    }
+    Ok(())
+}
+
+impl Drop for Tmpfs {
+    fn drop(&mut self) {
+        let removed = remove_dir_all(&self.dir)
+            .and_then(|_| axfs::api::remove_dir(&self.dir).map_err(LinuxError::from));
+        if let Err(e) = removed {
+            warn!("tmpfs: failed to remove {}: {:?}", self.dir, e);
+        }
+        meta::remove(&self.dir);
+        inode::remove(&self.dir);
+    }
+}
@@ -0,0 +1,208 @@
+//! Integrity verification of read-only images against a hash tree, like a
+//! minimal dm-verity.
+//!
+//! A verified image is the plain image, padded to whole blocks, followed by
+//! its hash tree and a superblock in the last block:
+//!
+//! - Every block of the image is hashed with SHA-256 over the salt followed by
+//!   the block. Level 0 of the tree holds the hashes of the data blocks, 128
+//!   to a block, and each level above holds the hashes of the blocks of the
+//!   level below, up to a level of a single block.
+//! - The levels are stored from the top one down to level 0.
+//! - The root hash is the hash of the top block. It isn't stored in the
+//!   image, but given at mount time, so an image can't vouch for itself.
+//!
+//! Blocks are verified on every read up to a hash block already verified,
+//! and the verified hash blocks are kept. The first mismatch fails the read
+//! with `EIO` and every later read of the image too, so that a tampered
+//! image is never half used.
+//!
+//! `scripts/verity.py` builds the tree and prints the root hash. The root
+//! filesystem itself is verified by building it as such an image, which
+//! [`mount_at_boot`] mounts on `/`.
+use super::mount::{self, Image};
+use crate::crypto::ct_eq;
+use crate::crypto::sha256::{Sha256, DIGEST_SIZE};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub const BLOCK_SIZE: u64 = 4096;
+const HASHES_PER_BLOCK: u64 = BLOCK_SIZE / DIGEST_SIZE as u64;
+const MAGIC: &[u8; 8] = b"VERITYLT";
+const VERSION: u32 = 1;
+const SALT_SIZE: usize = 32;
+
+pub type RootHash = [u8; DIGEST_SIZE];
+
+/// Parse a root hash written in hex.
+pub fn parse_root_hash(hex: &str) -> LinuxResult<RootHash> {
+    if hex.len() != 2 * DIGEST_SIZE {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut hash = [0; DIGEST_SIZE];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2).ok_or(LinuxError::EINVAL)?, 16)
+            .map_err(|_| LinuxError::EINVAL)?;
+    }
+    Ok(hash)
+}
+
+/// The hash tree of an image.
+pub struct Verity {
+    salt: [u8; SALT_SIZE],
+    data_blocks: u64,
+    /// The block number of the start of each level, level 0 first
+    levels: Vec<u64>,
+    root: RootHash,
+    /// The hash blocks verified so far, keyed by block number
+    verified: Mutex<BTreeMap<u64, Vec<u8>>>,
+    failed: AtomicBool,
+}
+
+impl Verity {
+    /// Read the superblock of `image` and check that the tree it describes
+    /// fills the image exactly.
+    pub fn new(image: &Image, root: RootHash) -> LinuxResult<Self> {
+        let total_blocks = image.raw_len() / BLOCK_SIZE;
+        if image.raw_len() % BLOCK_SIZE != 0 || total_blocks == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut sb = [0; 64];
+        image.read_raw_at((total_blocks - 1) * BLOCK_SIZE, &mut sb)?;
+        let u32_at = |pos: usize| u32::from_le_bytes(sb[pos..pos + 4].try_into().unwrap());
+        if &sb[..8] != MAGIC || u32_at(8) != VERSION || u32_at(12) as u64 != BLOCK_SIZE {
+            return Err(LinuxError::EINVAL);
+        }
+        let data_blocks = u64::from_le_bytes(sb[16..24].try_into().unwrap());
+        let salt = sb[24..24 + SALT_SIZE].try_into().unwrap();
+
+        // The size of each level, from level 0 up
+        let mut sizes = Vec::new();
+        let mut blocks = data_blocks.max(1);
+        loop {
+            blocks = blocks.div_ceil(HASHES_PER_BLOCK);
+            sizes.push(blocks);
+            if blocks == 1 {
+                break;
+            }
+        }
+        let tree_blocks: u64 = sizes.iter().sum();
+        if data_blocks.checked_add(tree_blocks + 1) != Some(total_blocks) {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut levels = Vec::with_capacity(sizes.len());
+        let mut start = data_blocks + tree_blocks;
+        for size in sizes {
+            start -= size;
+            levels.push(start);
+        }
+
+        Ok(Self {
+            salt,
+            data_blocks,
+            levels,
+            root,
+            verified: Mutex::new(BTreeMap::new()),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// The size of the data the tree covers.
+    pub fn data_len(&self) -> u64 {
+        self.data_blocks * BLOCK_SIZE
+    }
+
+    fn hash(&self, block: &[u8]) -> RootHash {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(block);
+        hasher.finish()
+    }
+
+    fn check(&self, block: &[u8], expected: &[u8], number: u64) -> LinuxResult {
+        if ct_eq(&self.hash(block), expected) {
+            return Ok(());
+        }
+        error!("verity: block {} doesn't match its hash", number);
+        self.failed.store(true, Ordering::Relaxed);
+        Err(LinuxError::EIO)
+    }
+
+    /// The verified content of the block `index` of the hash tree `level`.
+    fn hash_block(&self, image: &Image, level: usize, index: u64) -> LinuxResult<Vec<u8>> {
+        let number = self.levels[level] + index;
+        if let Some(block) = self.verified.lock().get(&number) {
+            return Ok(block.clone());
+        }
+        let block = image.read_raw_vec_at(number * BLOCK_SIZE, BLOCK_SIZE as usize)?;
+        if level + 1 == self.levels.len() {
+            self.check(&block, &self.root, number)?;
+        } else {
+            let parent = self.hash_block(image, level + 1, index / HASHES_PER_BLOCK)?;
+            self.check(&block, digest_at(&parent, index), number)?;
+        }
+        self.verified.lock().insert(number, block.clone());
+        Ok(block)
+    }
+
+    /// Fill `buf` from `offset` in the data, verifying every block read.
+    pub fn read_exact_at(&self, image: &Image, offset: u64, buf: &mut [u8]) -> LinuxResult {
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(LinuxError::EIO);
+        }
+        if offset.saturating_add(buf.len() as u64) > self.data_len() {
+            return Err(LinuxError::EIO);
+        }
+        let mut block = alloc::vec![0; BLOCK_SIZE as usize];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let number = pos / BLOCK_SIZE;
+            image.read_raw_at(number * BLOCK_SIZE, &mut block)?;
+            let hashes = self.hash_block(image, 0, number / HASHES_PER_BLOCK)?;
+            self.check(&block, digest_at(&hashes, number), number)?;
+
+            let start = (pos % BLOCK_SIZE) as usize;
+            let len = (BLOCK_SIZE as usize - start).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// The digest of the `index`-th block covered by the hash block `hashes`.
+fn digest_at(hashes: &[u8], index: u64) -> &[u8] {
+    let start = (index % HASHES_PER_BLOCK) as usize * DIGEST_SIZE;
+    &hashes[start..start + DIGEST_SIZE]
+}
+
+/// Mount the image given by `AX_VERITY` at build time, if any.
+///
+/// `AX_VERITY` is `<fstype>:<image>:<root hash>[:<mount point>]`. Without a
+/// mount point, the image is mounted on `/` and becomes the root filesystem
+/// the testcases are loaded from: every block they read is verified, and the
+/// disk underneath only holds the image file. Any failure to verify the image
+/// stops the kernel, rather than letting the testcases run on something else.
+pub fn mount_at_boot() {
+    let Some(spec) = option_env!("AX_VERITY").filter(|spec| !spec.is_empty()) else {
+        return;
+    };
+    let mut parts = spec.splitn(4, ':');
+    let (Some(fstype), Some(path), Some(root)) = (parts.next(), parts.next(), parts.next()) else {
+        panic!("verity: malformed AX_VERITY {:?}", spec);
+    };
+    let target = parts.next().unwrap_or("/");
+    let res = (|| {
+        let make = mount::kernel_fs(fstype).ok_or(LinuxError::ENODEV)?;
+        let image = Image::open_verified(path, parse_root_hash(root)?)?;
+        mount::mount(target, make(image)?)
+    })();
+    match res {
+        Ok(()) => info!("verity: mounted {} on {}", path, target),
+        Err(e) => panic!("verity: failed to mount {}: {:?}", path, e),
+    }
+}
@@ -0,0 +1,396 @@
+//! Futex support.
+//!
+//! Every blocked thread owns a [`FutexWaiter`], which is queued on the buckets of
+//! all the addresses it waits on. A single waiter can therefore be linked into
+//! several buckets at once, which is what `futex_waitv` needs.
+//!
+//! The futex words are compared with the table locked, so that a waker can't
+//! slip in between. They are read through the page table then, as a fault
+//! could sleep, e.g. to read the page back from swap, with every futex
+//! blocked: a word whose page isn't resident is faulted in with the table
+//! unlocked, and the reads start over. The table is thus locked before the
+//! address space.
+use crate::process::current_process;
+use crate::process::signal::wait_interruptible;
+use crate::ptr::{check_region, UserPtr};
+use crate::sync::{AdaptiveMutex, AdaptiveMutexGuard};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::mem::phys_to_virt;
+use axhal::paging::MappingFlags;
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::sync::atomic::{AtomicIsize, AtomicU32, Ordering};
+use core::time::Duration;
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// Match any bit in `FUTEX_WAIT_BITSET`/`FUTEX_WAKE_BITSET`.
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// The key of a futex: the address space it lives in and its user address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FutexKey {
+    aspace: usize,
+    uaddr: usize,
+}
+
+impl FutexKey {
+    pub fn new(aspace: usize, uaddr: usize) -> Self {
+        Self { aspace, uaddr }
+    }
+
+    /// Build the key of `uaddr` in the address space of the current process.
+    pub fn current(uaddr: usize) -> Self {
+        let proc = crate::process::current_process().unwrap();
+        Self::new(Arc::as_ptr(&proc.aspace) as usize, uaddr)
+    }
+}
+
+/// A thread blocked on one or more futexes.
+pub struct FutexWaiter {
+    /// The index of the futex which woke us up, or -1 if not woken yet
+    woken: AtomicIsize,
+    /// The bucket each futex is queued on, which a requeue changes. Only
+    /// accessed with the table locked
+    keys: Mutex<Vec<FutexKey>>,
+    wq: WaitQueue,
+}
+
+impl FutexWaiter {
+    fn new(keys: Vec<FutexKey>) -> Self {
+        Self {
+            woken: AtomicIsize::new(-1),
+            keys: Mutex::new(keys),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    fn woken_index(&self) -> Option<usize> {
+        let idx = self.woken.load(Ordering::Acquire);
+        (idx >= 0).then_some(idx as usize)
+    }
+
+    /// Mark the waiter as woken by the futex at `index`.
+    ///
+    /// Returns false if it has been woken by someone else already.
+    fn wake(&self, index: usize) -> bool {
+        if self
+            .woken
+            .compare_exchange(-1, index as isize, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            self.wq.notify_one(true);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct FutexEntry {
+    waiter: Arc<FutexWaiter>,
+    /// The position of this futex in the waiter's vector
+    index: usize,
+    bitset: u32,
+}
+
+type FutexTable = BTreeMap<FutexKey, Vec<FutexEntry>>;
+
+static FUTEX_TABLE: AdaptiveMutex<FutexTable> = AdaptiveMutex::new(BTreeMap::new());
+
+/// Read the futex word at `uaddr` of the current process, 64 bits wide if
+/// `is_u64`, through its page table. `None` if its page isn't resident and
+/// readable.
+fn read_resident(uaddr: usize, is_u64: bool) -> Option<u64> {
+    let proc = current_process()?;
+    let aspace = proc.aspace.lock();
+    let page = VirtAddr::from(uaddr).align_down_4k();
+    let (paddr, flags, _) = aspace.page_table().query(page).ok()?;
+    if !flags.contains(MappingFlags::READ | MappingFlags::USER) {
+        return None;
+    }
+    let ptr = phys_to_virt(paddr + uaddr % PAGE_SIZE_4K).as_ptr();
+    // The word is aligned, so within the page, which stays mapped while the
+    // address space is locked
+    Some(unsafe {
+        if is_u64 {
+            core::ptr::read_volatile(ptr as *const u64)
+        } else {
+            core::ptr::read_volatile(ptr as *const u32) as u64
+        }
+    })
+}
+
+/// Lock the table, and read the futex words `words`, given by their address
+/// and whether they are 64 bits wide, with it locked. Fails with `EFAULT` if
+/// one can't be read.
+fn lock_with_words(
+    words: &[(usize, bool)],
+) -> LinuxResult<(AdaptiveMutexGuard<'static, FutexTable>, Vec<u64>)> {
+    loop {
+        let table = FUTEX_TABLE.lock();
+        let values: Vec<_> = words
+            .iter()
+            .map_while(|&(uaddr, is_u64)| read_resident(uaddr, is_u64))
+            .collect();
+        let Some(&(uaddr, _)) = words.get(values.len()) else {
+            return Ok((table, values));
+        };
+        drop(table);
+        UserPtr::<u32>::from(uaddr).read()?;
+    }
+}
+
+/// A single futex to wait on, with the value it is expected to contain.
+pub struct FutexWaitItem {
+    pub key: FutexKey,
+    pub uaddr: usize,
+    pub expected: u64,
+    /// Whether the futex word is 64 bits wide instead of 32 bits
+    pub is_u64: bool,
+    pub bitset: u32,
+}
+
+impl FutexWaitItem {
+    /// Whether `value`, read from the futex word, is the one expected.
+    fn matches(&self, value: u64) -> bool {
+        if self.is_u64 {
+            value == self.expected
+        } else {
+            value == self.expected as u32 as u64
+        }
+    }
+}
+
+/// Block the current thread on all the given futexes.
+///
+/// # Returns
+/// The index of the futex which woke the thread up.
+///
+/// # Errors
+/// - `EAGAIN` if any futex word doesn't hold its expected value.
+/// - `ETIMEDOUT` if `timeout` elapsed before any wakeup.
+/// - `EINTR` if a signal arrived before any wakeup.
+/// - `EFAULT` if a futex word can't be read.
+pub fn futex_wait_multiple(
+    items: &[FutexWaitItem],
+    timeout: Option<Duration>,
+) -> LinuxResult<usize> {
+    let waiter = Arc::new(FutexWaiter::new(
+        items.iter().map(|item| item.key).collect(),
+    ));
+    {
+        // The values are checked while holding the table lock, so a waker which
+        // changes the value and then calls `futex_wake` can't be missed.
+        let words: Vec<_> = items.iter().map(|item| (item.uaddr, item.is_u64)).collect();
+        let (mut table, values) = lock_with_words(&words)?;
+        if !items
+            .iter()
+            .zip(values)
+            .all(|(item, value)| item.matches(value))
+        {
+            return Err(LinuxError::EAGAIN);
+        }
+        for (index, item) in items.iter().enumerate() {
+            table.entry(item.key).or_default().push(FutexEntry {
+                waiter: waiter.clone(),
+                index,
+                bitset: item.bitset,
+            });
+        }
+    }
+
+    let res = wait_interruptible(&waiter.wq, timeout, || waiter.woken_index().is_some());
+
+    // Unlink ourselves from every bucket, whether woken or not, including the
+    // ones we were requeued to. Once unlinked, no waker can count us.
+    let mut table = FUTEX_TABLE.lock();
+    for key in waiter.keys.lock().iter() {
+        if let Some(bucket) = table.get_mut(key) {
+            bucket.retain(|entry| !Arc::ptr_eq(&entry.waiter, &waiter));
+            if bucket.is_empty() {
+                table.remove(key);
+            }
+        }
+    }
+    drop(table);
+
+    match (waiter.woken_index(), res) {
+        (Some(index), _) => Ok(index),
+        // Not woken, so either interrupted or timed out
+        (None, Err(_)) => Err(LinuxError::EINTR),
+        (None, Ok(_)) => Err(LinuxError::ETIMEDOUT),
+    }
+}
+
+/// Wake up at most `count` waiters blocked on `key` whose bitset intersects `bitset`.
+///
+/// Returns the number of woken waiters.
+pub fn futex_wake(key: FutexKey, count: usize, bitset: u32) -> usize {
+    wake_locked(&mut FUTEX_TABLE.lock(), key, count, bitset)
+}
+
+fn wake_locked(table: &mut FutexTable, key: FutexKey, count: usize, bitset: u32) -> usize {
+    let Some(bucket) = table.get_mut(&key) else {
+        return 0;
+    };
+    let mut woken = 0;
+    bucket.retain(|entry| {
+        if woken >= count || entry.bitset & bitset == 0 {
+            return true;
+        }
+        if entry.waiter.wake(entry.index) {
+            woken += 1;
+        }
+        false
+    });
+    if bucket.is_empty() {
+        table.remove(&key);
+    }
+    woken
+}
+
+/// Wake up every waiter on a futex of the address space `aspace`, which is
+/// going away, so that no stale entry can match a later address space at the
+/// same place.
+///
+/// Returns the number of woken waiters.
+pub fn forget_aspace(aspace: usize) -> usize {
+    let mut table = FUTEX_TABLE.lock();
+    let keys: Vec<_> = table
+        .range(FutexKey::new(aspace, 0)..=FutexKey::new(aspace, usize::MAX))
+        .map(|(&key, _)| key)
+        .collect();
+    let mut woken = 0;
+    for key in keys {
+        for entry in table.remove(&key).unwrap_or_default() {
+            if entry.waiter.wake(entry.index) {
+                woken += 1;
+            }
+        }
+    }
+    woken
+}
+
+/// Wake up at most `nr_wake` waiters blocked on `from`, and move at most
+/// `nr_requeue` of the others to `to` without waking them. With `expected`,
+/// as for `FUTEX_CMP_REQUEUE`, fails with `EAGAIN` unless the word at `from`
+/// holds it, which is checked under the same lock.
+///
+/// Returns the number of woken and requeued waiters.
+pub fn futex_requeue(
+    from: FutexKey,
+    to: FutexKey,
+    nr_wake: usize,
+    nr_requeue: usize,
+    expected: Option<u32>,
+) -> LinuxResult<usize> {
+    let mut table = match expected {
+        Some(expected) => {
+            let (table, values) = lock_with_words(&[(from.uaddr, false)])?;
+            if values[0] != expected as u64 {
+                return Err(LinuxError::EAGAIN);
+            }
+            table
+        }
+        None => FUTEX_TABLE.lock(),
+    };
+    let woken = wake_locked(&mut table, from, nr_wake, FUTEX_BITSET_MATCH_ANY);
+    Ok(woken + requeue_locked(&mut table, from, to, nr_requeue))
+}
+
+/// Move at most `count` waiters from `from` to `to` without waking them.
+fn requeue_locked(table: &mut FutexTable, from: FutexKey, to: FutexKey, count: usize) -> usize {
+    let Some(mut bucket) = table.remove(&from) else {
+        return 0;
+    };
+    let count = count.min(bucket.len());
+    let moved: Vec<_> = bucket.drain(..count).collect();
+    if !bucket.is_empty() {
+        table.insert(from, bucket);
+    }
+    for entry in &moved {
+        entry.waiter.keys.lock()[entry.index] = to;
+    }
+    table.entry(to).or_default().extend(moved);
+    count
+}
+
+/// The head of the robust futex list of a thread, `struct robust_list_head`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RobustListHead {
+    /// The first entry, or the head itself if the list is empty
+    pub next: usize,
+    /// Where the futex word is relative to an entry
+    pub futex_offset: isize,
+    /// The entry being added or removed, if any
+    pub list_op_pending: usize,
+}
+
+/// The bit of a futex word set while it has waiters.
+const FUTEX_WAITERS: u32 = 0x8000_0000;
+/// The bit of a futex word set when its owner died holding it.
+const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+/// The bits of a futex word holding the tid of its owner.
+const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+/// The most entries walked, so that a circular list can't hang the exit.
+const ROBUST_LIST_LIMIT: usize = 2048;
+
+/// Release the futexes still held by the exiting thread `tid`, which are
+/// linked from the robust list at `head`.
+///
+/// Each of them is marked `FUTEX_OWNER_DIED` and one of its waiters is woken
+/// up, so that it takes the lock and sees `EOWNERDEAD`. The list is in user
+/// space and may be broken: the walk stops at the first bad entry.
+pub fn exit_robust_list(head: usize, tid: u32) {
+    let Ok(list) = UserPtr::<RobustListHead>::from(head).read() else {
+        return;
+    };
+    // The low bit of the pointers tells a PI futex, which is released all the same
+    let pending = list.list_op_pending & !1;
+    let mut entry = list.next & !1;
+    for _ in 0..ROBUST_LIST_LIMIT {
+        if entry == head {
+            break;
+        }
+        // Read the next entry first: the waiter woken up may reuse this one
+        let Ok(next) = UserPtr::<usize>::from(entry).read() else {
+            return;
+        };
+        if entry != pending {
+            handle_futex_death(entry.wrapping_add_signed(list.futex_offset), tid);
+        }
+        entry = next & !1;
+    }
+    if pending != 0 {
+        handle_futex_death(pending.wrapping_add_signed(list.futex_offset), tid);
+    }
+}
+
+/// Mark the futex at `uaddr` as abandoned if the thread `tid` holds it, and
+/// wake up one of its waiters.
+fn handle_futex_death(uaddr: usize, tid: u32) {
+    if uaddr % 4 != 0 || check_region(uaddr, 4, MappingFlags::READ | MappingFlags::WRITE).is_err() {
+        return;
+    }
+    // SAFETY: the word was checked to be mapped, and is only ever accessed
+    // atomically by the users of the futex
+    let word = unsafe { &*(uaddr as *const AtomicU32) };
+    let mut value = word.load(Ordering::Relaxed);
+    loop {
+        if value & FUTEX_TID_MASK != tid {
+            return;
+        }
+        let new = (value & FUTEX_WAITERS) | FUTEX_OWNER_DIED;
+        match word.compare_exchange(value, new, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(current) => value = current,
+        }
+    }
+    if value & FUTEX_WAITERS != 0 {
+        futex_wake(FutexKey::current(uaddr), 1, FUTEX_BITSET_MATCH_ANY);
+    }
+}
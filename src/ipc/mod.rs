@@ -0,0 +1,153 @@
+//! System V and POSIX IPC objects.
+//!
+//! Each kind of object has its own registry, where an object is found by its
+//! id or by the `key_t` it was created with, or by its name for the POSIX
+//! ones. What they share is here: the flags of the `*get` and `*ctl`
+//! syscalls and the permissions of an object.
+pub mod mqueue;
+pub mod sem;
+pub mod shm;
+
+use crate::process::cred::Credentials;
+use axerrno::{LinuxError, LinuxResult};
+
+/// The key asking for a new object which can't be found by key.
+pub const IPC_PRIVATE: i32 = 0;
+
+/// Create the object if the key doesn't exist.
+pub const IPC_CREAT: i32 = 0o1000;
+/// Fail if the key exists.
+pub const IPC_EXCL: i32 = 0o2000;
+
+pub const IPC_RMID: i32 = 0;
+pub const IPC_SET: i32 = 1;
+pub const IPC_STAT: i32 = 2;
+/// The flag libcs add to the `*ctl` commands to ask for the 64-bit layouts,
+/// which are the only ones here.
+pub const IPC_64: i32 = 0x100;
+
+/// The access an operation needs on an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn mode_bits(self) -> u32 {
+        match self {
+            Access::Read => 0o4,
+            Access::Write => 0o2,
+            Access::ReadWrite => 0o6,
+        }
+    }
+}
+
+/// The owner, creator and permission bits of an object.
+#[derive(Debug, Clone, Copy)]
+pub struct IpcPerm {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    /// The low 9 bits of the flags at creation
+    pub mode: u32,
+}
+
+/// `struct ipc64_perm`, the same on all the supported architectures.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IpcPerm64 {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: u16,
+    _pad: u16,
+    _unused: [u64; 2],
+}
+
+impl IpcPerm {
+    /// The permissions of an object created by `cred` with the flags `flags`.
+    pub fn new(key: i32, cred: &Credentials, flags: i32) -> Self {
+        Self {
+            key,
+            uid: cred.euid,
+            gid: cred.egid,
+            cuid: cred.euid,
+            cgid: cred.egid,
+            mode: flags as u32 & 0o777,
+        }
+    }
+
+    /// Check that `cred` may access the object as `access`, with `EACCES`.
+    pub fn check(&self, cred: &Credentials, access: Access) -> LinuxResult {
+        if cred.is_privileged() {
+            return Ok(());
+        }
+        let granted = if cred.euid == self.uid || cred.euid == self.cuid {
+            self.mode >> 6
+        } else if cred.egid == self.gid || cred.egid == self.cgid {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        let wanted = access.mode_bits();
+        if granted & wanted != wanted {
+            return Err(LinuxError::EACCES);
+        }
+        Ok(())
+    }
+
+    /// Check that `cred` owns or created the object, for `IPC_SET` and
+    /// `IPC_RMID`, with `EPERM`.
+    pub fn check_owner(&self, cred: &Credentials) -> LinuxResult {
+        if cred.is_privileged() || cred.euid == self.uid || cred.euid == self.cuid {
+            return Ok(());
+        }
+        Err(LinuxError::EPERM)
+    }
+
+    /// Apply `IPC_SET`: the owner and the permission bits change.
+    pub fn set(&mut self, new: &IpcPerm64) {
+        self.uid = new.uid;
+        self.gid = new.gid;
+        self.mode = new.mode & 0o777;
+    }
+
+    pub fn to_user(self, seq: u16) -> IpcPerm64 {
+        IpcPerm64 {
+            key: self.key,
+            uid: self.uid,
+            gid: self.gid,
+            cuid: self.cuid,
+            cgid: self.cgid,
+            mode: self.mode,
+            seq,
+            ..Default::default()
+        }
+    }
+}
+
+/// The access requested by the permission bits of the flags of a `*get`
+/// syscall looking up an existing key.
+pub fn requested_access(flags: i32) -> Option<Access> {
+    let mode = flags as u32 & 0o777;
+    let read = mode & 0o444 != 0;
+    let write = mode & 0o222 != 0;
+    match (read, write) {
+        (true, true) => Some(Access::ReadWrite),
+        (true, false) => Some(Access::Read),
+        (false, true) => Some(Access::Write),
+        (false, false) => None,
+    }
+}
+
+/// The current time in seconds, for the timestamps of the objects.
+pub fn now() -> i64 {
+    crate::clock::realtime().as_secs() as i64
+}
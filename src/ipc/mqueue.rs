@@ -0,0 +1,349 @@
+//! POSIX message queues.
+//!
+//! A queue is found by its name in a registry of its own, and used through a
+//! descriptor in the fd table, which `poll` can wait on. Messages are
+//! received highest priority first, and in the order they were sent within a
+//! priority.
+//!
+//! `mq_unlink` only frees the name: the queue lives on until its last
+//! descriptor is closed.
+use super::{Access, IpcPerm};
+use crate::process::current_process;
+use crate::process::signal::wait_interruptible;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+/// Priorities are below this.
+pub const MQ_PRIO_MAX: u32 = 32768;
+
+/// The longest name.
+const NAME_MAX: usize = 255;
+
+/// The capacity of a queue created without attributes.
+const DFLT_MAXMSG: i64 = 10;
+const DFLT_MSGSIZE: i64 = 8192;
+/// The largest capacity an unprivileged process may ask for.
+const MSG_MAX: i64 = 10;
+const MSGSIZE_MAX: i64 = 8192;
+/// The largest capacity anyone may ask for.
+const HARD_MSG_MAX: i64 = 65536;
+const HARD_MSGSIZE_MAX: i64 = 16 << 20;
+
+const O_ACCMODE: i32 = 0o3;
+const O_CREAT: i32 = 0o100;
+const O_EXCL: i32 = 0o200;
+const O_NONBLOCK: i32 = 0o4000;
+
+/// `struct mq_attr`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MqAttr {
+    pub mq_flags: i64,
+    pub mq_maxmsg: i64,
+    pub mq_msgsize: i64,
+    pub mq_curmsgs: i64,
+    _reserved: [i64; 4],
+}
+
+struct MqInner {
+    perm: IpcPerm,
+    /// The messages by priority, highest first, then by sequence number
+    msgs: BTreeMap<(Reverse<u32>, u64), Vec<u8>>,
+    next_seq: u64,
+    /// The total size of the messages
+    bytes: usize,
+}
+
+/// A message queue.
+pub struct MessageQueue {
+    maxmsg: usize,
+    msgsize: usize,
+    inner: Mutex<MqInner>,
+    /// The number of messages, which the waits check without locking
+    count: AtomicUsize,
+    /// Woken when a message is sent
+    not_empty: WaitQueue,
+    /// Woken when a message is received
+    not_full: WaitQueue,
+}
+
+impl MessageQueue {
+    fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Acquire) == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.count.load(Ordering::Acquire) >= self.maxmsg
+    }
+}
+
+/// An open message queue, as it sits in the fd table.
+pub struct MqDescriptor {
+    queue: Arc<MessageQueue>,
+    access: Access,
+    nonblocking: AtomicBool,
+}
+
+impl MqDescriptor {
+    fn can(&self, access: Access) -> bool {
+        self.access == access || self.access == Access::ReadWrite
+    }
+
+    fn attr(&self) -> MqAttr {
+        MqAttr {
+            mq_flags: if self.nonblocking.load(Ordering::Relaxed) {
+                O_NONBLOCK as i64
+            } else {
+                0
+            },
+            mq_maxmsg: self.queue.maxmsg as i64,
+            mq_msgsize: self.queue.msgsize as i64,
+            mq_curmsgs: self.queue.count.load(Ordering::Acquire) as i64,
+            ..Default::default()
+        }
+    }
+
+    /// Wait up to `timeout` for `ready`, unless the descriptor is
+    /// nonblocking, failing with `EAGAIN`, `ETIMEDOUT` or `EINTR`.
+    fn wait(
+        &self,
+        wq: &WaitQueue,
+        timeout: Option<Duration>,
+        ready: impl Fn() -> bool,
+    ) -> LinuxResult {
+        if self.nonblocking.load(Ordering::Relaxed) {
+            return Err(LinuxError::EAGAIN);
+        }
+        if wait_interruptible(wq, timeout, ready)? {
+            return Err(LinuxError::ETIMEDOUT);
+        }
+        Ok(())
+    }
+
+    /// `mq_timedsend`
+    pub fn send(&self, msg: &[u8], prio: u32, timeout: Option<Duration>) -> LinuxResult {
+        if !self.can(Access::Write) {
+            return Err(LinuxError::EBADF);
+        }
+        if msg.len() > self.queue.msgsize {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        if prio >= MQ_PRIO_MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        let queue = &self.queue;
+        loop {
+            {
+                let mut inner = queue.inner.lock();
+                if inner.msgs.len() < queue.maxmsg {
+                    let seq = inner.next_seq;
+                    inner.next_seq += 1;
+                    inner.bytes += msg.len();
+                    inner.msgs.insert((Reverse(prio), seq), msg.to_vec());
+                    queue.count.store(inner.msgs.len(), Ordering::Release);
+                    break;
+                }
+            }
+            self.wait(&queue.not_full, timeout, || !queue.is_full())?;
+        }
+        queue.not_empty.notify_one(false);
+        Ok(())
+    }
+
+    /// `mq_timedreceive`: the oldest message of the highest priority, into a
+    /// buffer of `len` bytes.
+    pub fn receive(&self, len: usize, timeout: Option<Duration>) -> LinuxResult<(Vec<u8>, u32)> {
+        if !self.can(Access::Read) {
+            return Err(LinuxError::EBADF);
+        }
+        if len < self.queue.msgsize {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        let queue = &self.queue;
+        let (Reverse(prio), msg) = loop {
+            {
+                let mut inner = queue.inner.lock();
+                if let Some(((prio, _), msg)) = inner.msgs.pop_first() {
+                    inner.bytes -= msg.len();
+                    queue.count.store(inner.msgs.len(), Ordering::Release);
+                    break (prio, msg);
+                }
+            }
+            self.wait(&queue.not_empty, timeout, || !queue.is_empty())?;
+        };
+        queue.not_full.notify_one(false);
+        Ok((msg, prio))
+    }
+
+    /// `mq_getsetattr`: only `O_NONBLOCK` can be changed.
+    pub fn set_attr(&self, new: Option<&MqAttr>) -> MqAttr {
+        let old = self.attr();
+        if let Some(new) = new {
+            self.nonblocking
+                .store(new.mq_flags & O_NONBLOCK as i64 != 0, Ordering::Relaxed);
+        }
+        old
+    }
+}
+
+impl api::FileLike for MqDescriptor {
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let inner = self.queue.inner.lock();
+        Ok(ctypes::stat {
+            st_mode: crate::fs::meta::S_IFREG | inner.perm.mode,
+            st_nlink: 1,
+            st_uid: inner.perm.uid,
+            st_gid: inner.perm.gid,
+            st_size: inner.bytes as _,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: self.can(Access::Read) && !self.queue.is_empty(),
+            writable: self.can(Access::Write) && !self.queue.is_full(),
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The queues which have a name, by name.
+static QUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+/// Check a name as the kernel gets it, without the leading slash.
+fn check_name(name: &str) -> LinuxResult {
+    if name.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    if name.len() > NAME_MAX {
+        return Err(LinuxError::ENAMETOOLONG);
+    }
+    if name.contains('/') || name == "." || name == ".." {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(())
+}
+
+/// The capacity asked for by `attr`, within what `privileged` may ask for.
+fn capacity(attr: Option<&MqAttr>, privileged: bool) -> LinuxResult<(usize, usize)> {
+    let Some(attr) = attr else {
+        return Ok((DFLT_MAXMSG as usize, DFLT_MSGSIZE as usize));
+    };
+    let (msg_max, msgsize_max) = if privileged {
+        (HARD_MSG_MAX, HARD_MSGSIZE_MAX)
+    } else {
+        (MSG_MAX, MSGSIZE_MAX)
+    };
+    if !(1..=msg_max).contains(&attr.mq_maxmsg) || !(1..=msgsize_max).contains(&attr.mq_msgsize) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok((attr.mq_maxmsg as usize, attr.mq_msgsize as usize))
+}
+
+/// `mq_open`: open the queue `name`, or create it with the permissions
+/// `mode` and the capacity `attr`.
+pub fn open(
+    name: &str,
+    oflag: i32,
+    mode: u32,
+    attr: Option<&MqAttr>,
+) -> LinuxResult<Arc<MqDescriptor>> {
+    check_name(name)?;
+    let access = match oflag & O_ACCMODE {
+        0 => Access::Read,
+        1 => Access::Write,
+        2 => Access::ReadWrite,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let proc = current_process().unwrap();
+    let cred = proc.cred();
+    let mut queues = QUEUES.lock();
+    let queue = match queues.get(name) {
+        Some(_) if oflag & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL => {
+            return Err(LinuxError::EEXIST)
+        }
+        Some(queue) => {
+            queue.inner.lock().perm.check(&cred, access)?;
+            queue.clone()
+        }
+        None if oflag & O_CREAT == 0 => return Err(LinuxError::ENOENT),
+        None => {
+            let (maxmsg, msgsize) = capacity(attr, cred.is_privileged())?;
+            let queue = Arc::new(MessageQueue {
+                maxmsg,
+                msgsize,
+                inner: Mutex::new(MqInner {
+                    perm: IpcPerm::new(
+                        0,
+                        &cred,
+                        (mode & !proc.umask.load(Ordering::Relaxed)) as i32,
+                    ),
+                    msgs: BTreeMap::new(),
+                    next_seq: 0,
+                    bytes: 0,
+                }),
+                count: AtomicUsize::new(0),
+                not_empty: WaitQueue::new(),
+                not_full: WaitQueue::new(),
+            });
+            queues.insert(String::from(name), queue.clone());
+            queue
+        }
+    };
+    Ok(Arc::new(MqDescriptor {
+        queue,
+        access,
+        nonblocking: AtomicBool::new(oflag & O_NONBLOCK != 0),
+    }))
+}
+
+/// `mq_unlink`: free the name, the queue goes away with its last descriptor.
+pub fn unlink(name: &str) -> LinuxResult {
+    check_name(name)?;
+    let cred = current_process().unwrap().cred();
+    let mut queues = QUEUES.lock();
+    let queue = queues.get(name).ok_or(LinuxError::ENOENT)?;
+    queue
+        .inner
+        .lock()
+        .perm
+        .check_owner(&cred)
+        .map_err(|_| LinuxError::EACCES)?;
+    queues.remove(name);
+    Ok(())
+}
+
+/// The message queue open as `fd`, failing with `EBADF` for other files.
+pub fn descriptor_from_fd(fd: i32) -> LinuxResult<Arc<MqDescriptor>> {
+    api::get_file_like(fd)?
+        .into_any()
+        .downcast::<MqDescriptor>()
+        .map_err(|_| LinuxError::EBADF)
+}
@@ -0,0 +1,438 @@
+//! System V semaphore sets.
+//!
+//! All the operations of a `semop` call are applied at once or not at all.
+//! A call which can't proceed sleeps until the set changes, then tries again
+//! from the start. Every change bumps the version of the set, which the
+//! sleepers wait on without taking its lock.
+//!
+//! The operations made with `SEM_UNDO` are recorded per process, and undone
+//! when it exits. Unlike segments, a set is destroyed by `IPC_RMID` right
+//! away, and its sleepers fail with `EIDRM`.
+use super::{now, Access, IpcPerm, IpcPerm64, IPC_CREAT, IPC_EXCL, IPC_PRIVATE};
+use crate::process::current_process;
+use crate::process::signal::wait_interruptible;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// The most semaphores in a set.
+pub const SEMMSL: usize = 32000;
+/// The most sets in the system.
+pub const SEMMNI: usize = 32000;
+/// The most operations in a `semop` call.
+pub const SEMOPM: usize = 500;
+/// The largest value of a semaphore.
+pub const SEMVMX: i32 = 32767;
+
+pub const GETPID: i32 = 11;
+pub const GETVAL: i32 = 12;
+pub const GETALL: i32 = 13;
+pub const GETNCNT: i32 = 14;
+pub const GETZCNT: i32 = 15;
+pub const SETVAL: i32 = 16;
+pub const SETALL: i32 = 17;
+
+pub const SEM_UNDO: i16 = 0x1000;
+pub const IPC_NOWAIT: i16 = 0o4000;
+
+/// `struct sembuf`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SemBuf {
+    pub sem_num: u16,
+    pub sem_op: i16,
+    pub sem_flg: i16,
+}
+
+/// `struct semid64_ds`, which x86_64 pads after each time.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SemidDs {
+    pub sem_perm: IpcPerm64,
+    pub sem_otime: i64,
+    #[cfg(target_arch = "x86_64")]
+    _unused1: u64,
+    pub sem_ctime: i64,
+    #[cfg(target_arch = "x86_64")]
+    _unused2: u64,
+    pub sem_nsems: u64,
+    _unused: [u64; 2],
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sem {
+    val: i32,
+    /// The pid of the last process which operated on it
+    pid: i32,
+    /// The number of sleepers waiting for it to grow
+    ncnt: u32,
+    /// The number of sleepers waiting for it to be 0
+    zcnt: u32,
+}
+
+impl Sem {
+    fn sleepers(&mut self, zero: bool) -> &mut u32 {
+        if zero {
+            &mut self.zcnt
+        } else {
+            &mut self.ncnt
+        }
+    }
+}
+
+struct SemState {
+    perm: IpcPerm,
+    otime: i64,
+    ctime: i64,
+    sems: Vec<Sem>,
+    /// Set by `IPC_RMID`
+    removed: bool,
+}
+
+/// A semaphore set.
+pub struct SemSet {
+    id: i32,
+    nsems: usize,
+    state: Mutex<SemState>,
+    /// Bumped under the lock on every change to the set
+    version: AtomicU64,
+    wq: WaitQueue,
+}
+
+impl SemSet {
+    /// Wake the sleepers after a change to the set.
+    fn changed(&self) {
+        self.version.fetch_add(1, Ordering::Release);
+        self.wq.notify_all(false);
+    }
+}
+
+/// What happens to a `semop` call.
+enum Outcome {
+    Done,
+    /// The operation `index` has to wait for its semaphore to grow, or to be
+    /// 0 if `zero`
+    Blocked {
+        index: usize,
+        zero: bool,
+    },
+}
+
+struct SemRegistry {
+    /// The sets not removed yet, by id
+    sets: BTreeMap<i32, Arc<SemSet>>,
+    /// The ids of the sets by key, without the private ones
+    keys: BTreeMap<i32, i32>,
+    /// The adjustments to undo on exit, by pid and set
+    undos: BTreeMap<(u64, i32), Vec<i32>>,
+    next_id: i32,
+}
+
+static SEM: Mutex<SemRegistry> = Mutex::new(SemRegistry {
+    sets: BTreeMap::new(),
+    keys: BTreeMap::new(),
+    undos: BTreeMap::new(),
+    next_id: 0,
+});
+
+impl SemRegistry {
+    fn get(&self, id: i32) -> LinuxResult<Arc<SemSet>> {
+        self.sets.get(&id).cloned().ok_or(LinuxError::EINVAL)
+    }
+
+    /// Forget the adjustments of all processes to the semaphore `num` of
+    /// the set `id`, or to all of them, when they are set explicitly.
+    fn clear_undos(&mut self, id: i32, num: Option<usize>) {
+        for ((_, set), adj) in self.undos.iter_mut() {
+            if *set != id {
+                continue;
+            }
+            match num {
+                Some(num) => adj[num] = 0,
+                None => adj.fill(0),
+            }
+        }
+    }
+}
+
+/// `semget`: find the set of `key`, or create one of `nsems` semaphores.
+pub fn get(key: i32, nsems: usize, flags: i32) -> LinuxResult<i32> {
+    let cred = current_process().unwrap().cred();
+    let mut sem = SEM.lock();
+    if key != IPC_PRIVATE {
+        if let Some(&id) = sem.keys.get(&key) {
+            if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                return Err(LinuxError::EEXIST);
+            }
+            let set = sem.get(id)?;
+            if nsems > set.nsems {
+                return Err(LinuxError::EINVAL);
+            }
+            if let Some(access) = super::requested_access(flags) {
+                set.state.lock().perm.check(&cred, access)?;
+            }
+            return Ok(id);
+        }
+        if flags & IPC_CREAT == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+    }
+    if nsems == 0 || nsems > SEMMSL {
+        return Err(LinuxError::EINVAL);
+    }
+    if sem.sets.len() >= SEMMNI {
+        return Err(LinuxError::ENOSPC);
+    }
+    let id = sem.next_id;
+    sem.next_id = sem.next_id.checked_add(1).unwrap_or(0);
+    let set = SemSet {
+        id,
+        nsems,
+        state: Mutex::new(SemState {
+            perm: IpcPerm::new(key, &cred, flags),
+            otime: 0,
+            ctime: now(),
+            sems: alloc::vec![Sem::default(); nsems],
+            removed: false,
+        }),
+        version: AtomicU64::new(0),
+        wq: WaitQueue::new(),
+    };
+    sem.sets.insert(id, Arc::new(set));
+    if key != IPC_PRIVATE {
+        sem.keys.insert(key, id);
+    }
+    Ok(id)
+}
+
+/// Apply `ops` to `sems` if they can all proceed.
+fn try_ops(sems: &mut [Sem], ops: &[SemBuf], pid: i32) -> LinuxResult<Outcome> {
+    let mut vals: Vec<i32> = sems.iter().map(|sem| sem.val).collect();
+    for (index, op) in ops.iter().enumerate() {
+        let val = &mut vals[op.sem_num as usize];
+        match op.sem_op {
+            0 if *val != 0 => return Ok(Outcome::Blocked { index, zero: true }),
+            0 => {}
+            delta if *val + (delta as i32) < 0 => {
+                return Ok(Outcome::Blocked { index, zero: false })
+            }
+            delta if *val + (delta as i32) > SEMVMX => return Err(LinuxError::ERANGE),
+            delta => *val += delta as i32,
+        }
+    }
+    for op in ops {
+        sems[op.sem_num as usize].pid = pid;
+    }
+    for (sem, val) in sems.iter_mut().zip(vals) {
+        sem.val = val;
+    }
+    Ok(Outcome::Done)
+}
+
+/// `semop` and `semtimedop`: apply `ops` to the set `id` all at once,
+/// waiting up to `timeout` for them to be able to proceed.
+pub fn op(id: i32, ops: &[SemBuf], timeout: Option<Duration>) -> LinuxResult {
+    if ops.is_empty() {
+        return Err(LinuxError::EINVAL);
+    }
+    if ops.len() > SEMOPM {
+        return Err(LinuxError::E2BIG);
+    }
+    let proc = current_process().unwrap();
+    let set = SEM.lock().get(id)?;
+    if ops.iter().any(|op| op.sem_num as usize >= set.nsems) {
+        return Err(LinuxError::EFBIG);
+    }
+    let access = if ops.iter().any(|op| op.sem_op != 0) {
+        Access::Write
+    } else {
+        Access::Read
+    };
+    set.state.lock().perm.check(&proc.cred(), access)?;
+
+    let deadline = timeout.map(|dur| axhal::time::monotonic_time() + dur);
+    loop {
+        let mut state = set.state.lock();
+        if state.removed {
+            return Err(LinuxError::EIDRM);
+        }
+        let (index, zero) = match try_ops(&mut state.sems, ops, proc.pid as i32)? {
+            Outcome::Done => {
+                state.otime = now();
+                drop(state);
+                record_undo(proc.pid, &set, ops);
+                set.changed();
+                return Ok(());
+            }
+            Outcome::Blocked { index, zero } => (index, zero),
+        };
+        if ops[index].sem_flg & IPC_NOWAIT != 0 {
+            return Err(LinuxError::EAGAIN);
+        }
+
+        let num = ops[index].sem_num as usize;
+        *state.sems[num].sleepers(zero) += 1;
+        let seen = set.version.load(Ordering::Acquire);
+        drop(state);
+        let changed = || set.version.load(Ordering::Acquire) != seen;
+        let left = deadline.map(|deadline| deadline.saturating_sub(axhal::time::monotonic_time()));
+        let res = wait_interruptible(&set.wq, left, changed);
+        *set.state.lock().sems[num].sleepers(zero) -= 1;
+        if res? {
+            return Err(LinuxError::EAGAIN);
+        }
+    }
+}
+
+/// Remember to undo the operations of `ops` made with `SEM_UNDO` when the
+/// process `pid` exits.
+fn record_undo(pid: u64, set: &SemSet, ops: &[SemBuf]) {
+    if ops.iter().all(|op| op.sem_flg & SEM_UNDO == 0) {
+        return;
+    }
+    let mut sem = SEM.lock();
+    // The set may have been removed meanwhile, with the undos to it
+    if !sem.sets.contains_key(&set.id) {
+        return;
+    }
+    let adj = sem
+        .undos
+        .entry((pid, set.id))
+        .or_insert_with(|| alloc::vec![0; set.nsems]);
+    for op in ops.iter().filter(|op| op.sem_flg & SEM_UNDO != 0) {
+        adj[op.sem_num as usize] -= op.sem_op as i32;
+    }
+}
+
+/// Undo the `SEM_UNDO` operations of the process `pid`, which exits.
+pub fn exit(pid: u64) {
+    let mut sem = SEM.lock();
+    let undos: Vec<_> = sem
+        .undos
+        .range((pid, i32::MIN)..=(pid, i32::MAX))
+        .map(|(&k, _)| k)
+        .collect();
+    for k in undos {
+        let adj = sem.undos.remove(&k).unwrap();
+        let Some(set) = sem.sets.get(&k.1) else {
+            continue;
+        };
+        {
+            let mut state = set.state.lock();
+            for (s, adj) in state.sems.iter_mut().zip(adj) {
+                s.val = (s.val + adj).clamp(0, SEMVMX);
+                if adj != 0 {
+                    s.pid = pid as i32;
+                }
+            }
+        }
+        set.changed();
+    }
+}
+
+/// The argument of `semctl`, a `union semun` passed by value.
+pub enum SemArg<'a> {
+    Val(i32),
+    Array(&'a mut [u16]),
+    Ds(&'a mut SemidDs),
+}
+
+/// The number of semaphores in the set `id`, to size the array of `GETALL`
+/// and `SETALL`.
+pub fn nsems(id: i32) -> LinuxResult<usize> {
+    Ok(SEM.lock().get(id)?.nsems)
+}
+
+/// `semctl`: the commands on a single semaphore or on the whole set.
+pub fn ctl(id: i32, num: usize, cmd: i32, arg: SemArg) -> LinuxResult<isize> {
+    let cred = current_process().unwrap().cred();
+    let mut sem = SEM.lock();
+    let set = sem.get(id)?;
+    let mut state = set.state.lock();
+    let access = match cmd {
+        super::IPC_STAT | GETPID | GETVAL | GETALL | GETNCNT | GETZCNT => Some(Access::Read),
+        SETVAL | SETALL => Some(Access::Write),
+        _ => None,
+    };
+    match access {
+        Some(access) => state.perm.check(&cred, access)?,
+        None => state.perm.check_owner(&cred)?,
+    }
+    if matches!(cmd, GETPID | GETVAL | GETNCNT | GETZCNT | SETVAL) && num >= set.nsems {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let ret = match (cmd, arg) {
+        (GETPID, _) => state.sems[num].pid as isize,
+        (GETVAL, _) => state.sems[num].val as isize,
+        (GETNCNT, _) => state.sems[num].ncnt as isize,
+        (GETZCNT, _) => state.sems[num].zcnt as isize,
+        (GETALL, SemArg::Array(vals)) => {
+            for (val, s) in vals.iter_mut().zip(&state.sems) {
+                *val = s.val as u16;
+            }
+            0
+        }
+        (SETVAL, SemArg::Val(val)) => {
+            if !(0..=SEMVMX).contains(&val) {
+                return Err(LinuxError::ERANGE);
+            }
+            state.sems[num].val = val;
+            state.sems[num].pid = current_process().unwrap().pid as i32;
+            state.ctime = now();
+            drop(state);
+            sem.clear_undos(id, Some(num));
+            set.changed();
+            0
+        }
+        (SETALL, SemArg::Array(vals)) => {
+            if vals.iter().any(|&val| val as i32 > SEMVMX) {
+                return Err(LinuxError::ERANGE);
+            }
+            let pid = current_process().unwrap().pid as i32;
+            for (s, &val) in state.sems.iter_mut().zip(vals.iter()) {
+                s.val = val as i32;
+                s.pid = pid;
+            }
+            state.ctime = now();
+            drop(state);
+            sem.clear_undos(id, None);
+            set.changed();
+            0
+        }
+        (super::IPC_STAT, SemArg::Ds(ds)) => {
+            *ds = SemidDs {
+                sem_perm: state.perm.to_user(0),
+                sem_otime: state.otime,
+                sem_ctime: state.ctime,
+                sem_nsems: set.nsems as u64,
+                ..Default::default()
+            };
+            0
+        }
+        (super::IPC_SET, SemArg::Ds(ds)) => {
+            state.perm.set(&ds.sem_perm);
+            state.ctime = now();
+            0
+        }
+        (super::IPC_RMID, _) => {
+            state.removed = true;
+            let key = state.perm.key;
+            drop(state);
+            sem.sets.remove(&id);
+            if key != IPC_PRIVATE {
+                sem.keys.remove(&key);
+            }
+            sem.undos.retain(|&(_, set), _| set != id);
+            set.changed();
+            0
+        }
+        _ => return Err(LinuxError::EINVAL),
+    };
+    Ok(ret)
+}
@@ -0,0 +1,377 @@
+//! System V shared memory segments.
+//!
+//! A segment owns its physical pages, [`Frame`]s allocated one at a time, and
+//! is kept alive by the registry and by every attachment. An attachment maps
+//! the pages linearly into an address space. It is keyed by that address
+//! space and the address it is mapped at, like a futex, so the threads and the
+//! processes sharing an address space share its attachments too.
+//!
+//! `IPC_RMID` only frees the key: the segment is destroyed, and its pages
+//! freed, when the last attachment goes away.
+use super::{now, Access, IpcPerm, IpcPerm64, IPC_CREAT, IPC_EXCL, IPC_PRIVATE};
+use crate::mm::{self, aspace_key, tlb, vma, Frame, VmArea};
+use crate::process::cred::Credentials;
+use crate::process::current_process;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+/// The largest segment.
+pub const SHMMAX: usize = 1 << 30;
+/// The most segments in the system.
+pub const SHMMNI: usize = 4096;
+/// The alignment of the addresses a segment is attached at.
+pub const SHMLBA: usize = PAGE_SIZE_4K;
+
+pub const SHM_RDONLY: i32 = 0o10000;
+pub const SHM_RND: i32 = 0o20000;
+pub const SHM_REMAP: i32 = 0o40000;
+pub const SHM_EXEC: i32 = 0o100000;
+
+/// `struct shmid64_ds`, the same on all the supported architectures.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShmidDs {
+    pub shm_perm: IpcPerm64,
+    pub shm_segsz: usize,
+    pub shm_atime: i64,
+    pub shm_dtime: i64,
+    pub shm_ctime: i64,
+    pub shm_cpid: i32,
+    pub shm_lpid: i32,
+    pub shm_nattch: u64,
+    _unused: [u64; 2],
+}
+
+struct ShmState {
+    perm: IpcPerm,
+    atime: i64,
+    dtime: i64,
+    ctime: i64,
+    /// The pid of the last process which attached or detached it
+    lpid: i32,
+    nattch: u64,
+    /// Set by `IPC_RMID`
+    removed: bool,
+}
+
+/// A shared memory segment.
+pub struct ShmSegment {
+    id: i32,
+    size: usize,
+    cpid: i32,
+    pages: Vec<Arc<Frame>>,
+    state: Mutex<ShmState>,
+}
+
+impl ShmSegment {
+    fn new(id: i32, key: i32, size: usize, cred: &Credentials, flags: i32) -> LinuxResult<Self> {
+        let count = size.div_ceil(PAGE_SIZE_4K);
+        let pages = (0..count)
+            .map(|_| Frame::alloc())
+            .collect::<LinuxResult<Vec<_>>>()?;
+        Ok(Self {
+            id,
+            size,
+            cpid: current_process().map_or(0, |proc| proc.pid as i32),
+            pages,
+            state: Mutex::new(ShmState {
+                perm: IpcPerm::new(key, cred, flags),
+                atime: 0,
+                dtime: 0,
+                ctime: now(),
+                lpid: 0,
+                nattch: 0,
+                removed: false,
+            }),
+        })
+    }
+
+    /// The size of the segment rounded up to whole pages.
+    fn mapped_size(&self) -> usize {
+        self.pages.len() * PAGE_SIZE_4K
+    }
+}
+
+struct ShmRegistry {
+    /// The segments not destroyed yet, by id
+    segments: BTreeMap<i32, Arc<ShmSegment>>,
+    /// The ids of the segments by key, without the private and removed ones
+    keys: BTreeMap<i32, i32>,
+    /// The attachments, by address space and start address
+    attachments: BTreeMap<(usize, usize), Arc<ShmSegment>>,
+    next_id: i32,
+}
+
+static SHM: Mutex<ShmRegistry> = Mutex::new(ShmRegistry {
+    segments: BTreeMap::new(),
+    keys: BTreeMap::new(),
+    attachments: BTreeMap::new(),
+    next_id: 0,
+});
+
+impl ShmRegistry {
+    fn get(&self, id: i32) -> LinuxResult<Arc<ShmSegment>> {
+        self.segments.get(&id).cloned().ok_or(LinuxError::EINVAL)
+    }
+
+    /// Drop the segment from the registry if it is removed and unused.
+    fn reap(&mut self, seg: &ShmSegment) {
+        let state = seg.state.lock();
+        if state.removed && state.nattch == 0 {
+            self.segments.remove(&seg.id);
+        }
+    }
+}
+
+/// `shmget`: find the segment of `key`, or create one of `size` bytes.
+pub fn get(key: i32, size: usize, flags: i32) -> LinuxResult<i32> {
+    let cred = current_process().unwrap().cred();
+    let mut shm = SHM.lock();
+    if key != IPC_PRIVATE {
+        if let Some(&id) = shm.keys.get(&key) {
+            if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                return Err(LinuxError::EEXIST);
+            }
+            let seg = shm.get(id)?;
+            if size > seg.size {
+                return Err(LinuxError::EINVAL);
+            }
+            if let Some(access) = super::requested_access(flags) {
+                seg.state.lock().perm.check(&cred, access)?;
+            }
+            return Ok(id);
+        }
+        if flags & IPC_CREAT == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+    }
+    if size == 0 || size > SHMMAX {
+        return Err(LinuxError::EINVAL);
+    }
+    if shm.segments.len() >= SHMMNI {
+        return Err(LinuxError::ENOSPC);
+    }
+    let id = shm.next_id;
+    let seg = ShmSegment::new(id, key, size, &cred, flags)?;
+    shm.next_id = shm.next_id.checked_add(1).unwrap_or(0);
+    shm.segments.insert(id, Arc::new(seg));
+    if key != IPC_PRIVATE {
+        shm.keys.insert(key, id);
+    }
+    Ok(id)
+}
+
+/// `shmat`: attach the segment `id` to the address space of the calling
+/// process, at `addr` or wherever there is room if it is 0.
+pub fn attach(id: i32, addr: usize, flags: i32) -> LinuxResult<usize> {
+    let proc = current_process().unwrap();
+    let seg = SHM.lock().get(id)?;
+    {
+        let state = seg.state.lock();
+        if state.removed {
+            return Err(LinuxError::EIDRM);
+        }
+        let access = if flags & SHM_RDONLY != 0 {
+            Access::Read
+        } else {
+            Access::ReadWrite
+        };
+        state.perm.check(&proc.cred(), access)?;
+    }
+
+    let mut map_flags = MappingFlags::USER | MappingFlags::READ;
+    if flags & SHM_RDONLY == 0 {
+        map_flags |= MappingFlags::WRITE;
+    }
+    if flags & SHM_EXEC != 0 {
+        map_flags |= MappingFlags::EXECUTE;
+    }
+
+    let size = seg.mapped_size();
+    let key = seg.state.lock().perm.key;
+    let mut aspace = proc.aspace.lock();
+    let limit = VirtAddrRange::new(aspace.base(), aspace.end());
+    let start = if addr == 0 {
+        aspace
+            .find_free_area(aspace.base(), size, limit)
+            .ok_or(LinuxError::ENOMEM)?
+    } else {
+        let addr = if flags & SHM_RND != 0 {
+            addr & !(SHMLBA - 1)
+        } else if addr % SHMLBA != 0 {
+            return Err(LinuxError::EINVAL);
+        } else {
+            addr
+        };
+        let start = VirtAddr::from(addr);
+        if !aspace.contains_range(start, size) {
+            return Err(LinuxError::EINVAL);
+        }
+        if aspace.find_free_area(start, size, limit) != Some(start) {
+            if flags & SHM_REMAP == 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let resident = mm::rss::mapped(&aspace, start, size);
+            mm::zero::release(&aspace, start, size);
+            aspace.unmap(start, size)?;
+            tlb::flush(&aspace, start, size);
+            mm::rss::uncharge(aspace_key(&proc.aspace), resident);
+            proc.vm_areas.lock().remove(start, size);
+        }
+        start
+    };
+    mm::map_frames(
+        aspace_key(&proc.aspace),
+        &mut aspace,
+        start,
+        &seg.pages,
+        map_flags,
+    )?;
+    proc.vm_areas.lock().insert(
+        VmArea::new(
+            start,
+            start + size,
+            map_flags,
+            vma::MAP_SHARED | vma::MAP_ANONYMOUS,
+        )
+        .with_name(format!("/SYSV{:08x} (deleted)", key), 0),
+    );
+    drop(aspace);
+    axhal::arch::flush_tlb(None);
+
+    {
+        let mut state = seg.state.lock();
+        state.nattch += 1;
+        state.atime = now();
+        state.lpid = proc.pid as i32;
+    }
+    SHM.lock()
+        .attachments
+        .insert((aspace_key(&proc.aspace), start.as_usize()), seg);
+    Ok(start.as_usize())
+}
+
+/// `shmdt`: detach the segment attached at `addr`.
+pub fn detach(addr: usize) -> LinuxResult {
+    let proc = current_process().unwrap();
+    let seg = SHM
+        .lock()
+        .attachments
+        .remove(&(aspace_key(&proc.aspace), addr))
+        .ok_or(LinuxError::EINVAL)?;
+    let start = VirtAddr::from(addr);
+    {
+        let mut aspace = proc.aspace.lock();
+        let resident = mm::rss::mapped(&aspace, start, seg.mapped_size());
+        aspace.unmap(start, seg.mapped_size())?;
+        tlb::flush(&aspace, start, seg.mapped_size());
+        mm::rss::uncharge(aspace_key(&proc.aspace), resident);
+        proc.vm_areas.lock().remove(start, seg.mapped_size());
+    }
+    mm::forget_frames(aspace_key(&proc.aspace), start, seg.mapped_size());
+    {
+        let mut state = seg.state.lock();
+        state.nattch -= 1;
+        state.dtime = now();
+        state.lpid = proc.pid as i32;
+    }
+    SHM.lock().reap(&seg);
+    Ok(())
+}
+
+/// Attach the segments attached to `parent` to `child` as well, which has
+/// them mapped at the same addresses, on `fork`.
+pub fn fork(parent: &Arc<Mutex<AddrSpace>>, child: &Arc<Mutex<AddrSpace>>) {
+    let (key, child_key) = (aspace_key(parent), aspace_key(child));
+    let mut shm = SHM.lock();
+    let attached: Vec<_> = shm
+        .attachments
+        .range((key, 0)..=(key, usize::MAX))
+        .map(|(&(_, start), seg)| (start, seg.clone()))
+        .collect();
+    for (start, seg) in attached {
+        seg.state.lock().nattch += 1;
+        shm.attachments.insert((child_key, start), seg);
+    }
+}
+
+/// Forget the attachments to `aspace`, whose mappings are going away with
+/// it, on exit or on `execve`. The caller drops its frames with
+/// [`mm::forget_all_frames`].
+pub fn detach_all(aspace: &Arc<Mutex<AddrSpace>>) {
+    let key = aspace_key(aspace);
+    let mut shm = SHM.lock();
+    let attached: Vec<_> = shm
+        .attachments
+        .range((key, 0)..=(key, usize::MAX))
+        .map(|(&k, _)| k)
+        .collect();
+    for k in attached {
+        let seg = shm.attachments.remove(&k).unwrap();
+        seg.state.lock().nattch -= 1;
+        shm.reap(&seg);
+    }
+}
+
+/// `shmctl(IPC_STAT)`
+pub fn stat(id: i32) -> LinuxResult<ShmidDs> {
+    let seg = SHM.lock().get(id)?;
+    let state = seg.state.lock();
+    state
+        .perm
+        .check(&current_process().unwrap().cred(), Access::Read)?;
+    let mut perm = state.perm.to_user(0);
+    if state.removed {
+        // SHM_DEST
+        perm.mode |= 0o1000;
+    }
+    Ok(ShmidDs {
+        shm_perm: perm,
+        shm_segsz: seg.size,
+        shm_atime: state.atime,
+        shm_dtime: state.dtime,
+        shm_ctime: state.ctime,
+        shm_cpid: seg.cpid,
+        shm_lpid: state.lpid,
+        shm_nattch: state.nattch,
+        ..Default::default()
+    })
+}
+
+/// `shmctl(IPC_SET)`
+pub fn set(id: i32, ds: &ShmidDs) -> LinuxResult {
+    let seg = SHM.lock().get(id)?;
+    let mut state = seg.state.lock();
+    state.perm.check_owner(&current_process().unwrap().cred())?;
+    state.perm.set(&ds.shm_perm);
+    state.ctime = now();
+    Ok(())
+}
+
+/// `shmctl(IPC_RMID)`: free the key, and destroy the segment once it is
+/// detached everywhere.
+pub fn remove(id: i32) -> LinuxResult {
+    let mut shm = SHM.lock();
+    let seg = shm.get(id)?;
+    {
+        let mut state = seg.state.lock();
+        state.perm.check_owner(&current_process().unwrap().cred())?;
+        if !state.removed {
+            state.removed = true;
+            state.ctime = now();
+            if state.perm.key != IPC_PRIVATE {
+                let key = state.perm.key;
+                shm.keys.remove(&key);
+            }
+        }
+    }
+    shm.reap(&seg);
+    Ok(())
+}
@@ -3,6 +3,11 @@
 //! It will read and parse ELF files.
 //!
 //! Now these apps are loaded into memory as a part of the kernel image.
+//!
+//! Executables linked at fixed addresses (`ET_EXEC`) are loaded where they
+//! were linked. Position-independent ones (`ET_DYN`) are moved by a load bias
+//! so that their lowest segment starts at the base chosen by the caller, and
+//! the addresses in their auxv are moved with them.
 use alloc::boxed::Box;
 use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 
@@ -21,8 +26,21 @@ pub struct ELFSegment {
     pub data: &'static [u8],
     /// The offset of the segment relative to the start of the page
     pub offset: usize,
+    /// The offset in the file of the page at `start_vaddr`
+    pub file_offset: u64,
 }
 
+/// The address of the program headers in memory
+const AT_PHDR: u8 = 3;
+/// The size of a program header
+const AT_PHENT: u8 = 4;
+/// The number of program headers
+const AT_PHNUM: u8 = 5;
+/// The base address of the interpreter
+const AT_BASE: u8 = 7;
+/// The entry point of the program
+const AT_ENTRY: u8 = 9;
+
 /// The information of a given ELF file
 pub struct ELFInfo {
     /// The entry point of the ELF file
@@ -38,15 +56,16 @@ pub struct ELFInfo {
 ///
 /// # Arguments
 /// * `name` - The name of the app
-/// * `base_addr` - The minimal address of user space
+/// * `dyn_base` - Where the lowest segment of a position-independent
+///   executable goes, 4K-aligned
 ///
 /// # Returns
 /// Entry and information about segments of the given ELF file
-pub(crate) fn load_elf(name: &str, base_addr: VirtAddr) -> ELFInfo {
-    use xmas_elf::program::{Flags, SegmentData};
+pub(crate) fn load_elf(name: &str, dyn_base: VirtAddr) -> ELFInfo {
+    use xmas_elf::program::{Flags, SegmentData, Type};
     use xmas_elf::{header, ElfFile};
 
-    let file = axfs::api::read(name).unwrap();
+    let file = crate::fs::read(name).unwrap();
     let file_inner = Box::leak(file.into_boxed_slice());
 
     let elf = ElfFile::new(file_inner).expect("invalid ELF file");
@@ -85,14 +104,37 @@ pub(crate) fn load_elf(name: &str, base_addr: VirtAddr) -> ELFInfo {
 
     let mut segments = Vec::new();
 
-    let elf_offset = kernel_elf_parser::get_elf_base_addr(&elf, base_addr.as_usize()).unwrap();
+    let loads = || {
+        elf.program_iter()
+            .filter(|ph| ph.get_type() == Ok(Type::Load))
+    };
+    if elf
+        .program_iter()
+        .any(|ph| ph.get_type() == Ok(Type::Interp))
+    {
+        warn!("{}: dynamic linking is not supported", name);
+    }
+    let elf_offset = if elf_header.pt2.type_().as_type() == header::Type::SharedObject {
+        // Usually linked at 0, but only the relative placement matters. One
+        // linked above the base stays where it is.
+        let lowest = loads()
+            .map(|ph| ph.virtual_addr() as usize)
+            .min()
+            .unwrap_or(0);
+        dyn_base
+            .as_usize()
+            .checked_sub(memory_addr::align_down_4k(lowest))
+            .unwrap_or(0)
+    } else {
+        0
+    };
     assert!(
         memory_addr::is_aligned_4k(elf_offset),
         "ELF base address must be aligned to 4k"
     );
 
     elf.program_iter()
-        .filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load))
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
         .for_each(|ph| {
             // align the segment to 4k
             let st_vaddr = VirtAddr::from(ph.virtual_addr() as usize) + elf_offset;
@@ -110,11 +152,34 @@ pub(crate) fn load_elf(name: &str, base_addr: VirtAddr) -> ELFInfo {
                 flags: into_mapflag(ph.flags()),
                 data,
                 offset: st_vaddr.align_offset_4k(),
+                file_offset: ph
+                    .offset()
+                    .saturating_sub(st_vaddr.align_offset_4k() as u64),
             });
         });
+    let entry = elf.header.pt2.entry_point() as usize + elf_offset;
+    // The program headers are where `PT_PHDR` says, or else in the loaded
+    // segment covering them in the file
+    let ph_offset = elf.header.pt2.ph_offset();
+    let phdr = elf
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(Type::Phdr))
+        .map(|ph| ph.virtual_addr())
+        .or_else(|| {
+            loads()
+                .find(|ph| (ph.offset()..ph.offset() + ph.file_size()).contains(&ph_offset))
+                .map(|ph| ph.virtual_addr() + (ph_offset - ph.offset()))
+        })
+        .map_or(0, |vaddr| vaddr as usize + elf_offset);
+    let mut auxv = kernel_elf_parser::get_auxv_vector(&elf, elf_offset);
+    auxv.insert(AT_PHDR, phdr);
+    auxv.insert(AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
+    auxv.insert(AT_PHNUM, elf.header.pt2.ph_count() as usize);
+    auxv.insert(AT_BASE, 0);
+    auxv.insert(AT_ENTRY, entry);
     ELFInfo {
-        entry: VirtAddr::from(elf.header.pt2.entry_point() as usize + elf_offset),
+        entry: VirtAddr::from(entry),
         segments,
-        auxv: kernel_elf_parser::get_auxv_vector(&elf, elf_offset),
+        auxv,
     }
 }
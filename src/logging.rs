@@ -0,0 +1,35 @@
+//! Logging macros of this crate.
+//!
+//! They shadow the macros of the `log` crate and prefix every line with the
+//! pid and tid of the current user thread (see [`crate::task::LogPrefix`]).
+
+macro_rules! error {
+    ($($arg:tt)+) => {
+        log::error!("{}{}", $crate::task::LogPrefix, format_args!($($arg)+))
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        log::warn!("{}{}", $crate::task::LogPrefix, format_args!($($arg)+))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => {
+        log::info!("{}{}", $crate::task::LogPrefix, format_args!($($arg)+))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        log::debug!("{}{}", $crate::task::LogPrefix, format_args!($($arg)+))
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        log::trace!("{}{}", $crate::task::LogPrefix, format_args!($($arg)+))
+    };
+}
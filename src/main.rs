@@ -2,43 +2,106 @@
 #![no_main]
 #![doc = include_str!("../README.md")]
 
-#[macro_use]
-extern crate log;
 extern crate alloc;
 extern crate axstd;
 
+#[macro_use]
+mod logging;
+
 #[rustfmt::skip]
 mod config {
     include!(concat!(env!("OUT_DIR"), "/uspace_config.rs"));
 }
-pub mod signal;
+mod arch;
+#[cfg(feature = "bench")]
+mod bench;
+mod clock;
+mod console;
+mod crypto;
+mod drivers;
 mod flag;
+mod fs;
+mod futex;
+mod ipc;
 mod loader;
 mod mm;
+mod perf;
 mod process;
+mod ptr;
+mod random;
+mod seccomp;
+pub mod signal;
 mod syscall_imp;
+mod strace;
+mod sync;
+mod sysctl;
 mod task;
+mod trace;
+mod tty;
+mod uring;
+pub mod uapi;
+
+pub use process::events;
 
 use alloc::sync::Arc;
 
 use axhal::arch::UspaceContext;
 use axsync::Mutex;
+use axtask::TaskExtRef;
 
 #[no_mangle]
 fn main() {
     // loader::list_apps();
+    console::init();
+    tty::init();
+    fs::devfs::init();
+    random::init();
+    process::pid::init();
+    process::init::init();
+    mm::aslr::init();
+    mm::vdso::init();
+    // A verified root image is mounted before anything is read from the disk
+    fs::verity::mount_at_boot();
+    fs::meta::load();
+    #[cfg(feature = "overlay")]
+    if let Err(e) = fs::overlay::enable() {
+        warn!("Failed to enable the overlay: {:?}", e);
+    }
+    drivers::init();
     let testcases = option_env!("AX_TESTCASES_LIST")
         .unwrap_or_else(|| "Please specify the testcases list by making user_apps")
         .split(',')
         .filter(|&x| !x.is_empty());
     for testcase in testcases {
         info!("Running testcase: {}", testcase);
-        let (entry_vaddr, ustack_top, uspace) = mm::load_user_app(testcase).unwrap();
+        process::pid::restart_sequence();
+        let (layout, uspace) = mm::load_user_app(testcase).unwrap();
+        #[cfg(feature = "bench")]
+        let run = bench::BenchRun::start(testcase);
         let user_task = task::spawn_user_task(
+            testcase,
             Arc::new(Mutex::new(uspace)),
-            UspaceContext::new(entry_vaddr.into(), ustack_top, 0),
+            UspaceContext::new(layout.entry.into(), layout.ustack_pointer, 0),
+            &layout,
         );
+        // Each testcase runs as a session leader with the console as its
+        // controlling terminal
+        tty::CONSOLE_TTY
+            .lock()
+            .set_session(user_task.task_ext().tid());
         let exit_code = user_task.join();
+        // Let the output of the testcase reach the UART before the kernel logs,
+        // even if it suspended the output
+        console::set_stopped(false);
+        console::flush();
+        #[cfg(feature = "bench")]
+        run.finish(exit_code.unwrap_or(-1));
         info!("User task {} exited with code: {:?}", testcase, exit_code);
+        if let Err(e) = fs::sync_all() {
+            warn!("Failed to write back the files: {:?}", e);
+        }
+        if let Err(e) = fs::overlay::reset() {
+            warn!("Failed to reset the overlay: {:?}", e);
+        }
     }
 }
@@ -0,0 +1,62 @@
+//! Address space layout randomization.
+//!
+//! When a program is loaded, `kernel/randomize_va_space` decides which parts
+//! of its address space are moved by a random number of pages, as in Linux:
+//!
+//! - at 1, the stack, the address mmap starts looking for free areas from,
+//!   and the load address of position-independent executables;
+//! - at 2, the start of the heap as well.
+//!
+//! Its value at boot comes from `AX_ASLR`, 0 if the kernel was built without
+//! it. A process opts out for the programs it executes with
+//! `personality(ADDR_NO_RANDOMIZE)`.
+use crate::sysctl::RANDOMIZE_VA_SPACE;
+use memory_addr::PAGE_SIZE_4K;
+
+/// The personality flag disabling the randomization.
+pub const ADDR_NO_RANDOMIZE: u32 = 0x0040000;
+
+/// The range the top of the stack is moved down by.
+pub const STACK_RANGE: usize = 0x400_0000;
+/// The range the start of mmap searches is moved up by.
+pub const MMAP_RANGE: usize = 0x1000_0000;
+/// The range the load address of position-independent executables is moved
+/// up by.
+pub const PIE_RANGE: usize = 0x1000_0000;
+/// The range the start of the heap is moved up by.
+pub const HEAP_RANGE: usize = 0x200_0000;
+
+/// Apply `AX_ASLR`, if the kernel was built with it.
+pub fn init() {
+    let Some(level) = option_env!("AX_ASLR").filter(|level| !level.is_empty()) else {
+        return;
+    };
+    if !matches!(
+        level.parse().map(|level| RANDOMIZE_VA_SPACE.set(level)),
+        Ok(Ok(()))
+    ) {
+        warn!("Invalid AX_ASLR: {}", level);
+    }
+}
+
+/// How much of the layout of a program loaded by a process with
+/// `personality` is randomized, the value of `kernel/randomize_va_space`
+/// unless it opted out.
+pub fn level(personality: u32) -> usize {
+    if personality & ADDR_NO_RANDOMIZE != 0 {
+        0
+    } else {
+        RANDOMIZE_VA_SPACE.get()
+    }
+}
+
+/// A random number of pages, less than `range` in bytes, if the randomization
+/// `level` is at least `min`, or else 0.
+pub fn offset(level: usize, min: usize, range: usize) -> usize {
+    if level < min {
+        return 0;
+    }
+    let mut bytes = [0; 8];
+    crate::random::fill(&mut bytes);
+    u64::from_le_bytes(bytes) as usize % (range / PAGE_SIZE_4K) * PAGE_SIZE_4K
+}
@@ -0,0 +1,181 @@
+//! The copy of an address space for `fork`.
+//!
+//! A child created without `CLONE_VM` gets an address space of its own, made
+//! from the table of areas of its parent, page by page:
+//!
+//! - The pages of shared memory, a SysV segment, a memfd, a shared file or
+//!   anonymous mapping, are frames mapped into both, as are the pages of
+//!   devices and those of the kernel, the signal trampoline and the vDSO.
+//! - A private page the parent faulted in is copied into a frame of the
+//!   child's own. One swapped out is read back first.
+//! - A page never touched stays lazily allocated, and so does one mapped to
+//!   the zero page, which the child maps again on its first read.
+//!
+//! There is no copy-on-write: the private pages are copied right away, with
+//! the address space of the parent locked. Memory locks aren't inherited, as
+//! in Linux.
+use super::{aspace_key, forget_all_frames, rss, swap, trampoline, vdso, zero};
+use super::{VmArea, VmAreas, SHARED_FRAMES};
+use crate::config;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// Private pages next to each other, allocated in the child at once.
+struct Run {
+    start: VirtAddr,
+    pages: usize,
+    flags: MappingFlags,
+    /// Whether the parent faulted them in, so that they are copied
+    populated: bool,
+}
+
+/// Copy the address space `parent`, whose areas are `areas`, for a child.
+///
+/// Returns the address space of the child and its table of areas.
+pub fn fork(
+    parent: &Arc<Mutex<AddrSpace>>,
+    areas: &Mutex<VmAreas>,
+) -> AxResult<(Arc<Mutex<AddrSpace>>, Arc<Mutex<VmAreas>>)> {
+    let key = aspace_key(parent);
+    let mut parent_space = parent.lock();
+    let mut areas = areas.lock().clone();
+    areas.set_locked(parent_space.base(), parent_space.size(), false);
+
+    let child = Arc::new(Mutex::new(axmm::new_user_aspace(
+        VirtAddr::from_usize(config::USER_SPACE_BASE),
+        config::USER_SPACE_SIZE,
+    )?));
+    let child_key = aspace_key(&child);
+    let mut child_space = child.lock();
+    let mut copied = Vec::new();
+    let res = areas.iter().try_fold(0, |resident, area| {
+        let (parent, child) = (&mut *parent_space, &mut *child_space);
+        Ok(resident + copy_area(key, parent, child_key, child, area, &mut copied)?)
+    });
+    drop(child_space);
+    let resident = match res {
+        Ok(resident) => resident,
+        Err(e) => {
+            forget_all_frames(child_key);
+            return Err(e);
+        }
+    };
+    rss::set(child_key, resident);
+    // The private pages copied may be swapped out like those of the parent
+    for page in copied {
+        swap::record(&child, page);
+    }
+    Ok((child, Arc::new(Mutex::new(areas))))
+}
+
+/// Map `area` of `parent`, whose key is `key`, into `child`, whose key is
+/// `child_key`, adding the private pages copied to `copied`. Returns the
+/// number of frames mapped.
+fn copy_area(
+    key: usize,
+    parent: &mut AddrSpace,
+    child_key: usize,
+    child: &mut AddrSpace,
+    area: &VmArea,
+    copied: &mut Vec<VirtAddr>,
+) -> AxResult<usize> {
+    let mut resident = 0;
+    let mut run: Option<Run> = None;
+    for page in (area.start.as_usize()..area.end.as_usize()).step_by(PAGE_SIZE_4K) {
+        let page = VirtAddr::from(page);
+        // A page swapped out comes back in the parent to be copied
+        if parent.page_table().query(page).is_err()
+            && swap::is_swapped(key, page)
+            && !swap::handle_fault(key, parent, page)
+        {
+            return Err(AxError::NoMemory);
+        }
+        // A page on the zero page is untouched in the child, as it was
+        let (paddr, flags) = match parent.page_table().query(page) {
+            Ok((paddr, flags, _)) if !zero::is_zero_frame(paddr) => (paddr, flags),
+            _ => {
+                let flags = area.prot | MappingFlags::USER;
+                extend(&mut run, parent, child, page, flags, false)?;
+                continue;
+            }
+        };
+        let frame = SHARED_FRAMES.lock().get(&(key, page.as_usize())).cloned();
+        if let Some(frame) = frame {
+            flush(&mut run, parent, child)?;
+            child.map_linear(page, frame.paddr(), PAGE_SIZE_4K, flags)?;
+            SHARED_FRAMES
+                .lock()
+                .insert((child_key, page.as_usize()), frame);
+        } else if is_borrowed(area, paddr, flags) {
+            flush(&mut run, parent, child)?;
+            child.map_linear(page, paddr, PAGE_SIZE_4K, flags)?;
+        } else {
+            extend(&mut run, parent, child, page, flags, true)?;
+            copied.push(page);
+        }
+        resident += 1;
+    }
+    flush(&mut run, parent, child)?;
+    Ok(resident)
+}
+
+/// Whether the page at `paddr`, mapped with `flags` in `area`, is memory
+/// which no area owns: that of a device, or of the kernel.
+fn is_borrowed(area: &VmArea, paddr: PhysAddr, flags: MappingFlags) -> bool {
+    area.is_shared()
+        || flags.contains(MappingFlags::DEVICE)
+        || trampoline::is_trampoline(paddr)
+        || vdso::is_vdso(paddr)
+}
+
+/// Add `page` to the run of private pages, or start another one if it isn't
+/// alike.
+fn extend(
+    run: &mut Option<Run>,
+    parent: &AddrSpace,
+    child: &mut AddrSpace,
+    page: VirtAddr,
+    flags: MappingFlags,
+    populated: bool,
+) -> AxResult {
+    if let Some(run) = run.as_mut().filter(|run| {
+        run.start + run.pages * PAGE_SIZE_4K == page
+            && run.flags == flags
+            && run.populated == populated
+    }) {
+        run.pages += 1;
+        return Ok(());
+    }
+    flush(run, parent, child)?;
+    *run = Some(Run {
+        start: page,
+        pages: 1,
+        flags,
+        populated,
+    });
+    Ok(())
+}
+
+/// Allocate the pages of `run` in `child`, copying them from `parent` if they
+/// were faulted in there.
+fn flush(run: &mut Option<Run>, parent: &AddrSpace, child: &mut AddrSpace) -> AxResult {
+    let Some(run) = run.take() else {
+        return Ok(());
+    };
+    let size = run.pages * PAGE_SIZE_4K;
+    child.map_alloc(run.start, size, run.flags, run.populated)?;
+    if run.populated {
+        let mut data = vec![0; PAGE_SIZE_4K];
+        for page in (run.start.as_usize()..run.start.as_usize() + size).step_by(PAGE_SIZE_4K) {
+            parent.read(VirtAddr::from(page), &mut data)?;
+            child.write(VirtAddr::from(page), &data)?;
+        }
+    }
+    Ok(())
+}
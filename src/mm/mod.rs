@@ -0,0 +1,470 @@
+pub mod aslr;
+pub mod fork;
+pub mod oom;
+mod pte;
+pub mod rss;
+mod stack;
+pub mod swap;
+pub mod tlb;
+pub mod trampoline;
+pub mod vdso;
+pub mod vma;
+pub mod zero;
+
+pub use stack::{ArgEnv, USER_HZ};
+pub use vma::{VmArea, VmAreas};
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use crate::process::cred::Credentials;
+use crate::signal::signal_no::SignalNo;
+use crate::trace::{self, TraceEvent};
+use crate::{config, loader};
+use axerrno::{AxError, AxResult, LinuxError, LinuxResult};
+use axhal::{
+    mem::{memory_regions, virt_to_phys, MemRegionFlags},
+    paging::MappingFlags,
+    trap::{register_trap_handler, PAGE_FAULT},
+};
+use axmm::AddrSpace;
+use axstd::os::arceos::modules::axalloc;
+use axsync::Mutex;
+use axtask::TaskExtRef;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// The RAM usage of the system, in bytes.
+pub struct RamUsage {
+    /// All the RAM, except the regions of devices
+    pub total: usize,
+    /// The RAM not allocated yet
+    pub free: usize,
+}
+
+/// The current RAM usage, taken from the memory map and the global allocator.
+pub fn ram_usage() -> RamUsage {
+    let total = memory_regions()
+        .filter(|region| !region.flags.contains(MemRegionFlags::DEVICE))
+        .map(|region| region.size)
+        .sum();
+    let free = axalloc::global_allocator().available_pages() * PAGE_SIZE_4K;
+    RamUsage { total, free }
+}
+
+/// A zeroed physical page owned by the kernel, freed when dropped.
+///
+/// Memory shared between address spaces, like a SysV segment or a memfd, is
+/// made of frames mapped linearly into each of them.
+pub struct Frame {
+    /// The kernel address of the page
+    vaddr: usize,
+}
+
+impl Frame {
+    pub fn alloc() -> LinuxResult<Arc<Self>> {
+        let alloc = || axalloc::global_allocator().alloc_pages(1, PAGE_SIZE_4K);
+        // Out of frames, try again once some are reclaimed
+        let vaddr = alloc()
+            .or_else(|_| {
+                swap::reclaim(swap::RECLAIM_BATCH, None);
+                alloc()
+            })
+            .map_err(|_| {
+                // Still none, memory has to be freed for the next attempt
+                oom::kill();
+                LinuxError::ENOMEM
+            })?;
+        unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, PAGE_SIZE_4K) };
+        Ok(Arc::new(Self { vaddr }))
+    }
+
+    pub fn paddr(&self) -> PhysAddr {
+        virt_to_phys(VirtAddr::from(self.vaddr))
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.vaddr as *const u8, PAGE_SIZE_4K) }
+    }
+
+    /// The content of the page, which user space may be changing meanwhile.
+    #[allow(clippy::mut_from_ref)]
+    pub fn as_mut_slice(&self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.vaddr as *mut u8, PAGE_SIZE_4K) }
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        axalloc::global_allocator().dealloc_pages(self.vaddr, 1);
+    }
+}
+
+/// The frames mapped in user space, by address space and page address. A
+/// frame stays allocated as long as it is mapped somewhere, whatever happens
+/// to the object it belongs to.
+static SHARED_FRAMES: Mutex<BTreeMap<(usize, usize), Arc<Frame>>> = Mutex::new(BTreeMap::new());
+
+/// The key of the address space `aspace` in the tables of shared memory.
+pub fn aspace_key(aspace: &Arc<Mutex<AddrSpace>>) -> usize {
+    Arc::as_ptr(aspace) as usize
+}
+
+/// Map `frames` at `start` in `aspace`, whose key is `key`.
+pub fn map_frames(
+    key: usize,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    frames: &[Arc<Frame>],
+    flags: MappingFlags,
+) -> LinuxResult {
+    for (i, frame) in frames.iter().enumerate() {
+        let vaddr = start + i * PAGE_SIZE_4K;
+        if let Err(e) = aspace.map_linear(vaddr, frame.paddr(), PAGE_SIZE_4K, flags) {
+            if i > 0 {
+                aspace.unmap(start, i * PAGE_SIZE_4K)?;
+                tlb::flush(aspace, start, i * PAGE_SIZE_4K);
+                forget_frames(key, start, i * PAGE_SIZE_4K);
+                rss::uncharge(key, i);
+            }
+            return Err(e.into());
+        }
+        SHARED_FRAMES
+            .lock()
+            .insert((key, vaddr.as_usize()), frame.clone());
+    }
+    rss::charge(key, frames.len());
+    Ok(())
+}
+
+/// Drop the references to the frames in `[start, start + len)` of the
+/// address space `key`, once they are unmapped.
+pub fn forget_frames(key: usize, start: VirtAddr, len: usize) {
+    let (start, end) = (start.as_usize(), start.as_usize().saturating_add(len));
+    swap::forget(key, start, end);
+    let mut frames = SHARED_FRAMES.lock();
+    let mapped: Vec<_> = frames
+        .range((key, start)..(key, end))
+        .map(|(&k, _)| k)
+        .collect();
+    for k in mapped {
+        frames.remove(&k);
+    }
+}
+
+/// Drop the references to all the frames mapped in the address space `key`,
+/// when it is cleared or dropped.
+pub fn forget_all_frames(key: usize) {
+    forget_frames(key, VirtAddr::from(0), usize::MAX);
+    swap::forget_space(key);
+    rss::forget(key);
+}
+
+/// Where the parts of a user app were placed when it was loaded.
+pub struct UserLayout {
+    /// The entry point
+    pub entry: VirtAddr,
+    /// The initial stack pointer, below the arguments, the environment
+    /// variables and auxv
+    pub ustack_pointer: VirtAddr,
+    /// The start of the heap
+    pub heap_bottom: VirtAddr,
+    /// The address from which mmap looks for free areas
+    pub mmap_base: VirtAddr,
+    /// The code signal handlers return to without `SA_RESTORER`
+    pub signal_trampoline: VirtAddr,
+    /// The areas mapped
+    pub areas: VmAreas,
+    /// The pages mapped, all populated
+    pub resident: usize,
+    /// The path of the program
+    pub exe: String,
+    /// Where its arguments and environment variables are on the stack
+    pub arg_env: ArgEnv,
+}
+
+/// Where the heap of a program made of `segments` starts: right after the
+/// highest segment, like in Linux, moved up if randomization `level` asks
+/// for it.
+fn heap_bottom(segments: &[loader::ELFSegment], level: usize) -> VirtAddr {
+    let end = segments
+        .iter()
+        .map(|seg| seg.start_vaddr + seg.size)
+        .max()
+        .unwrap_or_default()
+        .align_up_4k();
+    end + aslr::offset(level, 2, aslr::HEAP_RANGE)
+}
+
+/// Load a user app, with the layout of its address space randomized as
+/// `kernel/randomize_va_space` says.
+///
+/// # Returns
+/// - The first return value is where the parts of the user app were placed.
+/// - The second return value is the address space of the user app.
+pub fn load_user_app(app_name: &str) -> AxResult<(UserLayout, AddrSpace)> {
+    let mut uspace = axmm::new_user_aspace(
+        VirtAddr::from_usize(config::USER_SPACE_BASE),
+        config::USER_SPACE_SIZE,
+    )?;
+
+    let layout = load_elf(app_name, &mut uspace)?;
+
+    Ok((layout, uspace))
+}
+
+/// Load the ELF file `app_name` into `uspace` and set up its stack with
+/// `argv`, `envp` and the auxv, for a process with `personality` running
+/// with `cred`.
+pub fn load_elf_with_arg(
+    app_name: &str,
+    uspace: &mut AddrSpace,
+    argv: &[String],
+    envp: &[String],
+    personality: u32,
+    cred: &Credentials,
+) -> AxResult<UserLayout> {
+    let level = aslr::level(personality);
+    // Only position-independent executables are placed at this base
+    let elf_info = loader::load_elf(
+        app_name,
+        VirtAddr::from_usize(config::USER_ELF_DYN_BASE) + aslr::offset(level, 1, aslr::PIE_RANGE),
+    );
+    let heap_bottom = heap_bottom(&elf_info.segments, level);
+    // The testcases are loaded by a path relative to the root
+    let exe = crate::fs::resolve_path_at(crate::fs::AT_FDCWD, app_name, true)
+        .unwrap_or_else(|_| app_name.to_string());
+    let mut areas = VmAreas::default();
+    let mut resident = 0;
+    for segement in elf_info.segments {
+        debug!(
+            "Mapping ELF segment: [{:#x?}, {:#x?}) flags: {:#x?}",
+            segement.start_vaddr,
+            segement.start_vaddr + segement.size,
+            segement.flags
+        );
+        uspace.map_alloc(segement.start_vaddr, segement.size, segement.flags, true)?;
+        resident += segement.size / PAGE_SIZE_4K;
+        // The pages holding data of the file are a private mapping of it, as
+        // on Linux, and the rest of the bss is anonymous
+        let start = segement.start_vaddr;
+        let end = start + segement.size;
+        let file_end = (start + segement.offset + segement.data.len())
+            .align_up_4k()
+            .min(end);
+        if file_end > start {
+            areas.insert(
+                VmArea::new(start, file_end, segement.flags, vma::MAP_PRIVATE)
+                    .with_file(exe.clone(), segement.file_offset),
+            );
+        }
+        if end > file_end {
+            areas.insert(VmArea::new(
+                file_end,
+                end,
+                segement.flags,
+                vma::MAP_PRIVATE | vma::MAP_ANONYMOUS,
+            ));
+        }
+
+        if segement.data.is_empty() {
+            continue;
+        }
+
+        uspace.write(segement.start_vaddr + segement.offset, segement.data)?;
+
+        // TDOO: flush the I-cache
+    }
+
+    // The user stack is divided into two parts:
+    // `ustack_start` -> `ustack_pointer`: It is the stack space that users actually read and write.
+    // `ustack_pointer` -> `ustack_end`: It is the space that contains the arguments, environment variables and auxv passed to the app.
+    //  When the app starts running, the stack pointer points to `ustack_pointer`.
+    let ustack_end =
+        VirtAddr::from_usize(config::USER_STACK_TOP - aslr::offset(level, 1, aslr::STACK_RANGE));
+    let ustack_size = config::USER_STACK_SIZE;
+    let ustack_start = ustack_end - ustack_size;
+    debug!(
+        "Mapping user stack: {:#x?} -> {:#x?}",
+        ustack_start, ustack_end
+    );
+    let mut auxv = elf_info.auxv;
+    auxv.insert(stack::AT_PAGESZ, PAGE_SIZE_4K);
+    auxv.insert(stack::AT_HWCAP, crate::arch::hwcap());
+    auxv.insert(stack::AT_CLKTCK, stack::USER_HZ);
+    auxv.insert(stack::AT_UID, cred.uid as usize);
+    auxv.insert(stack::AT_EUID, cred.euid as usize);
+    auxv.insert(stack::AT_GID, cred.gid as usize);
+    auxv.insert(stack::AT_EGID, cred.egid as usize);
+    let secure = cred.uid != cred.euid || cred.gid != cred.egid;
+    auxv.insert(stack::AT_SECURE, secure as usize);
+    // Below the stack, past a guard page: the signal trampoline, then the vDSO
+    let trampoline = trampoline::map(uspace, ustack_start - PAGE_SIZE_4K)?;
+    auxv.insert(trampoline::AT_SIGNAL_TRAMPOLINE, trampoline.as_usize());
+    resident += 1;
+    areas.insert(
+        VmArea::new(
+            trampoline,
+            trampoline + PAGE_SIZE_4K,
+            MappingFlags::READ | MappingFlags::EXECUTE,
+            vma::MAP_PRIVATE,
+        )
+        .with_name("[sigpage]", 0),
+    );
+    if let Some(vdso) = vdso::map(uspace, trampoline)? {
+        auxv.insert(vdso::AT_SYSINFO_EHDR, vdso.as_usize());
+        resident += 3;
+        areas.insert(
+            VmArea::new(
+                vdso - 2 * PAGE_SIZE_4K,
+                vdso,
+                MappingFlags::READ,
+                vma::MAP_PRIVATE,
+            )
+            .with_name("[vvar]", 0),
+        );
+        areas.insert(
+            VmArea::new(
+                vdso,
+                vdso + PAGE_SIZE_4K,
+                MappingFlags::READ | MappingFlags::EXECUTE,
+                vma::MAP_PRIVATE,
+            )
+            .with_name("[vdso]", 0),
+        );
+    }
+    let (stack_data, ustack_pointer, arg_env) =
+        stack::build(argv, envp, &auxv, app_name, ustack_end);
+    if stack_data.len() > ustack_size {
+        return Err(AxError::NoMemory);
+    }
+    uspace.map_alloc(
+        ustack_start,
+        ustack_size,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+        true,
+    )?;
+    resident += ustack_size / PAGE_SIZE_4K;
+    areas.insert(
+        VmArea::new(
+            ustack_start,
+            ustack_end,
+            MappingFlags::READ | MappingFlags::WRITE,
+            vma::MAP_PRIVATE | vma::MAP_ANONYMOUS,
+        )
+        .with_name("[stack]", 0),
+    );
+
+    uspace.write(ustack_pointer, stack_data.as_slice())?;
+
+    Ok(UserLayout {
+        entry: elf_info.entry,
+        ustack_pointer,
+        heap_bottom,
+        mmap_base: uspace.base() + aslr::offset(level, 1, aslr::MMAP_RANGE),
+        signal_trampoline: trampoline,
+        areas,
+        resident,
+        exe,
+        arg_env,
+    })
+}
+
+pub fn load_elf(app_name: &str, uspace: &mut AddrSpace) -> AxResult<UserLayout> {
+    load_elf_with_arg(
+        app_name,
+        uspace,
+        &[app_name.to_string()],
+        &[],
+        0,
+        &Credentials::root(),
+    )
+}
+
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    trace::record(
+        TraceEvent::PageFault,
+        vaddr.as_usize() as u64,
+        access_flags.bits() as u64,
+    );
+    if is_user {
+        tlb::leave_user();
+        crate::perf::leave_user();
+    } else {
+        warn!(
+            "Kernel page fault at {:#x}, access_flags: {:#x?}",
+            vaddr, access_flags
+        );
+    }
+    let task = axtask::current();
+    if unsafe { task.task_ext_ptr().is_null() } {
+        error!("No task extended data found for the current task");
+        return false;
+    }
+    let proc = task.task_ext().get_proc().unwrap();
+    let key = aspace_key(&proc.aspace);
+    let mut aspace = proc.aspace.lock();
+    let fault = |aspace: &mut AddrSpace| {
+        if swap::handle_fault(key, aspace, vaddr)
+            || zero::handle_fault(key, aspace, &proc.vm_areas.lock(), vaddr, access_flags)
+        {
+            return true;
+        }
+        let handled = aspace.handle_page_fault(vaddr, access_flags);
+        if handled {
+            rss::charge(key, 1);
+        }
+        handled
+    };
+    let mut handled = fault(&mut *aspace);
+    // An access the area allows may have failed for lack of a frame
+    let access = access_flags & (MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE);
+    let out_of_memory = |handled| {
+        !handled
+            && swap::low_on_memory()
+            && proc
+                .vm_areas
+                .lock()
+                .overlapping(vaddr.align_down_4k(), PAGE_SIZE_4K)
+                .next()
+                .is_some_and(|area| area.prot.contains(access))
+    };
+    if out_of_memory(handled) && swap::reclaim(swap::RECLAIM_BATCH, Some((key, &mut *aspace))) > 0 {
+        handled = fault(&mut *aspace);
+    }
+    if out_of_memory(handled) {
+        if let Some(victim) = oom::kill() {
+            drop(aspace);
+            if victim == proc.pid {
+                crate::process::signal::terminate_process(SignalNo::SIGKILL);
+            }
+            // Fault again once the victim has freed its pages
+            axtask::yield_now();
+            return true;
+        }
+    }
+    if !handled {
+        drop(aspace);
+        warn!(
+            "{}: segmentation fault at {:#x}, exit!",
+            axtask::current().id_name(),
+            vaddr
+        );
+        crate::syscall_imp::sys_exit(-1);
+    }
+    // A private page may be swapped out later, unless locked in memory
+    let swappable = proc
+        .vm_areas
+        .lock()
+        .overlapping(vaddr.align_down_4k(), PAGE_SIZE_4K)
+        .next()
+        .is_some_and(|area| !area.is_shared() && !area.locked);
+    if swappable {
+        swap::record(&proc.aspace, vaddr);
+    }
+    true
+}
@@ -0,0 +1,45 @@
+//! The OOM killer.
+//!
+//! When memory runs out even after [`super::swap::reclaim`], the process
+//! with the most resident pages, init aside, is killed with `SIGKILL` and the
+//! decision logged. There is one victim at a time: until it is gone, memory
+//! stays short rather than another process being killed, as the pages of the
+//! first one are about to come back.
+use crate::process::init::INIT_PID;
+use crate::process::signal::send_signal_to_proc;
+use crate::process::{all_processes, get_process};
+use crate::signal::signal_no::SignalNo;
+use axtask::TaskExtRef;
+use core::sync::atomic::{AtomicU64, Ordering};
+use memory_addr::PAGE_SIZE_4K;
+
+/// The pid of the last process killed, 0 if none.
+static VICTIM: AtomicU64 = AtomicU64::new(0);
+
+/// Kill the process with the most resident pages, unless the last one killed
+/// hasn't exited yet.
+///
+/// Returns the pid of the process being killed, `None` if there is nothing
+/// to kill.
+pub fn kill() -> Option<u64> {
+    let last = VICTIM.load(Ordering::Acquire);
+    // A zombie has given its pages back already
+    let freeing = get_process(last).is_some_and(|proc| !proc.is_exited.load(Ordering::Acquire));
+    if last != 0 && freeing {
+        return Some(last);
+    }
+    let victim = all_processes()
+        .into_iter()
+        .filter(|proc| proc.pid != INIT_PID && !proc.is_exiting())
+        .max_by_key(|proc| proc.rss())
+        .filter(|proc| proc.rss() > 0)?;
+    VICTIM.store(victim.pid, Ordering::Release);
+    warn!(
+        "Out of memory: killing process {} ({}), {} kB resident",
+        victim.pid,
+        victim.main_thread().task_ext().comm(),
+        victim.rss() * PAGE_SIZE_4K / 1024
+    );
+    let _ = send_signal_to_proc(victim.pid, SignalNo::SIGKILL as isize, None);
+    Some(victim.pid)
+}
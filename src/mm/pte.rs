@@ -0,0 +1,249 @@
+//! The leaf entries of the page table of a user address space, written
+//! directly.
+//!
+//! `axmm` maps and unmaps whole areas of its own, splitting them when part of
+//! one changes, and gives no mutable access to its page table. The zero page
+//! comes and goes a page at a time inside the areas of anonymous memory, so
+//! its entries are written here, in the format `page_table_multiarch` uses on
+//! each architecture. Only the leaf entries are touched: a page whose table
+//! doesn't exist yet is left to `axmm`, which allocates the tables it needs.
+//!
+//! The areas of `axmm` don't know about these entries. They must be cleared
+//! before their range is unmapped, or the area would free the frame they map.
+use axhal::mem::phys_to_virt;
+use axhal::paging::MappingFlags;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// The entries of a table, at every level.
+const ENTRIES: usize = 512;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        const LEVELS: usize = 4;
+        const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+        const PRESENT: u64 = 1 << 0;
+        const WRITABLE: u64 = 1 << 1;
+        const USER: u64 = 1 << 2;
+        const HUGE_PAGE: u64 = 1 << 7;
+        const NO_EXECUTE: u64 = 1 << 63;
+
+        fn is_table(entry: u64) -> bool {
+            entry & PRESENT != 0 && entry & HUGE_PAGE == 0
+        }
+
+        fn table_addr(entry: u64) -> PhysAddr {
+            PhysAddr::from((entry & ADDR_MASK) as usize)
+        }
+
+        fn page_addr(entry: u64) -> PhysAddr {
+            table_addr(entry)
+        }
+
+        fn page_entry(paddr: PhysAddr, flags: MappingFlags) -> u64 {
+            let mut entry = PRESENT | paddr.as_usize() as u64;
+            if flags.contains(MappingFlags::WRITE) {
+                entry |= WRITABLE;
+            }
+            if flags.contains(MappingFlags::USER) {
+                entry |= USER;
+            }
+            if !flags.contains(MappingFlags::EXECUTE) {
+                entry |= NO_EXECUTE;
+            }
+            entry
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        const LEVELS: usize = 4;
+        const ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+        const VALID: u64 = 1 << 0;
+        /// A table, or a page at the last level, rather than a block
+        const NON_BLOCK: u64 = 1 << 1;
+        /// `MAIR_EL1` index 1, normal memory
+        const ATTR_NORMAL: u64 = 1 << 2;
+        const AP_EL0: u64 = 1 << 6;
+        const AP_RO: u64 = 1 << 7;
+        const INNER_SHAREABLE: u64 = 0b11 << 8;
+        const ACCESSED: u64 = 1 << 10;
+        const PXN: u64 = 1 << 53;
+        const UXN: u64 = 1 << 54;
+
+        fn is_table(entry: u64) -> bool {
+            entry & (VALID | NON_BLOCK) == VALID | NON_BLOCK
+        }
+
+        fn table_addr(entry: u64) -> PhysAddr {
+            PhysAddr::from((entry & ADDR_MASK) as usize)
+        }
+
+        fn page_addr(entry: u64) -> PhysAddr {
+            table_addr(entry)
+        }
+
+        fn page_entry(paddr: PhysAddr, flags: MappingFlags) -> u64 {
+            let mut entry = VALID | NON_BLOCK | ATTR_NORMAL | INNER_SHAREABLE | ACCESSED;
+            entry |= paddr.as_usize() as u64;
+            if !flags.contains(MappingFlags::WRITE) {
+                entry |= AP_RO;
+            }
+            if flags.contains(MappingFlags::USER) {
+                entry |= AP_EL0 | PXN;
+                if !flags.contains(MappingFlags::EXECUTE) {
+                    entry |= UXN;
+                }
+            } else {
+                entry |= UXN;
+                if !flags.contains(MappingFlags::EXECUTE) {
+                    entry |= PXN;
+                }
+            }
+            entry
+        }
+    } else if #[cfg(target_arch = "riscv64")] {
+        /// Sv39
+        const LEVELS: usize = 3;
+        const VALID: u64 = 1 << 0;
+        const READ: u64 = 1 << 1;
+        const WRITE: u64 = 1 << 2;
+        const EXECUTE: u64 = 1 << 3;
+        const USER: u64 = 1 << 4;
+        const ACCESSED: u64 = 1 << 6;
+        const DIRTY: u64 = 1 << 7;
+        /// The bits of the physical page number, from bit 10
+        const PPN_MASK: u64 = (1 << 44) - 1;
+
+        fn is_table(entry: u64) -> bool {
+            entry & VALID != 0 && entry & (READ | WRITE | EXECUTE) == 0
+        }
+
+        fn table_addr(entry: u64) -> PhysAddr {
+            PhysAddr::from((((entry >> 10) & PPN_MASK) << 12) as usize)
+        }
+
+        fn page_addr(entry: u64) -> PhysAddr {
+            table_addr(entry)
+        }
+
+        fn page_entry(paddr: PhysAddr, flags: MappingFlags) -> u64 {
+            let mut entry = VALID | ACCESSED | DIRTY | ((paddr.as_usize() as u64 >> 12) << 10);
+            if flags.contains(MappingFlags::READ) {
+                entry |= READ;
+            }
+            if flags.contains(MappingFlags::WRITE) {
+                entry |= WRITE;
+            }
+            if flags.contains(MappingFlags::EXECUTE) {
+                entry |= EXECUTE;
+            }
+            if flags.contains(MappingFlags::USER) {
+                entry |= USER;
+            }
+            entry
+        }
+    }
+}
+
+/// The bits of the address above the offset in a page a table entry at
+/// `level` covers, the root being at level 0.
+fn shift(level: usize) -> usize {
+    12 + 9 * (LEVELS - 1 - level)
+}
+
+/// The entry `index` of the table at `table`.
+fn entry_mut(table: PhysAddr, index: usize) -> &'static mut u64 {
+    let entries = phys_to_virt(table).as_mut_ptr() as *mut u64;
+    // The tables of a user address space are kernel pages of 512 entries,
+    // which live as long as it, and its lock is held by the caller
+    unsafe { &mut *entries.add(index) }
+}
+
+/// The leaf entry of `vaddr` under the root table `root`, if the tables
+/// leading to it exist and none is a huge page.
+fn leaf(root: PhysAddr, vaddr: VirtAddr) -> Option<&'static mut u64> {
+    let mut table = root;
+    for level in 0..LEVELS - 1 {
+        let entry = *entry_mut(table, (vaddr.as_usize() >> shift(level)) % ENTRIES);
+        if !is_table(entry) {
+            return None;
+        }
+        table = table_addr(entry);
+    }
+    Some(entry_mut(
+        table,
+        (vaddr.as_usize() >> shift(LEVELS - 1)) % ENTRIES,
+    ))
+}
+
+/// Map `paddr` at the page `vaddr` under `root` with `flags`, if the page
+/// isn't mapped and its table exists. Returns whether it was mapped.
+pub fn map(root: PhysAddr, vaddr: VirtAddr, paddr: PhysAddr, flags: MappingFlags) -> bool {
+    match leaf(root, vaddr) {
+        Some(entry) if *entry == 0 => {
+            *entry = page_entry(paddr, flags);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Call `f` with each leaf entry in `[start, start + len)` under `root`
+/// which maps `paddr`, present or not.
+fn for_each_mapping(
+    root: PhysAddr,
+    start: VirtAddr,
+    len: usize,
+    paddr: PhysAddr,
+    f: &mut impl FnMut(&mut u64),
+) {
+    fn walk(
+        table: PhysAddr,
+        level: usize,
+        base: usize,
+        range: (usize, usize),
+        paddr: PhysAddr,
+        f: &mut impl FnMut(&mut u64),
+    ) {
+        let span = 1 << shift(level);
+        let first = range.0.saturating_sub(base) / span;
+        let last = (range.1 - base).div_ceil(span).min(ENTRIES);
+        for index in first..last {
+            let entry = entry_mut(table, index);
+            let vaddr = base + index * span;
+            if level == LEVELS - 1 {
+                if *entry != 0 && page_addr(*entry) == paddr {
+                    f(entry);
+                }
+            } else if is_table(*entry) {
+                walk(table_addr(*entry), level + 1, vaddr, range, paddr, f);
+            }
+        }
+    }
+    let start = start.as_usize() / PAGE_SIZE_4K * PAGE_SIZE_4K;
+    let end = start.saturating_add(len).min(ENTRIES << shift(0));
+    if start < end {
+        walk(root, 0, 0, (start, end), paddr, f);
+    }
+}
+
+/// Clear the leaf entries in `[start, start + len)` under `root` which map
+/// `paddr`.
+pub fn unmap_all(root: PhysAddr, start: VirtAddr, len: usize, paddr: PhysAddr) {
+    for_each_mapping(root, start, len, paddr, &mut |entry| *entry = 0);
+}
+
+/// Give the leaf entries in `[start, start + len)` under `root` which map
+/// `paddr` the flags `flags`, or clear them if `flags` doesn't allow reading.
+pub fn protect_all(
+    root: PhysAddr,
+    start: VirtAddr,
+    len: usize,
+    paddr: PhysAddr,
+    flags: MappingFlags,
+) {
+    for_each_mapping(root, start, len, paddr, &mut |entry| {
+        *entry = if flags.contains(MappingFlags::READ) {
+            page_entry(paddr, flags)
+        } else {
+            0
+        };
+    });
+}
@@ -0,0 +1,58 @@
+//! The resident set size of the address spaces: the frames mapped in them.
+//!
+//! The count is kept by address space, keyed like the shared frames, so that
+//! the processes sharing one share it too. It grows when a fault maps a frame
+//! or a mapping is populated, and shrinks when pages are unmapped or swapped
+//! out. Every page mapped counts, but the zero page.
+use super::zero;
+use alloc::collections::BTreeMap;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+/// The resident pages, by address space.
+static RSS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Count `pages` more frames mapped in the address space `key`.
+pub fn charge(key: usize, pages: usize) {
+    if pages > 0 {
+        *RSS.lock().entry(key).or_default() += pages;
+    }
+}
+
+/// Count `pages` frames less, once unmapped from the address space `key`.
+pub fn uncharge(key: usize, pages: usize) {
+    if let Some(rss) = RSS.lock().get_mut(&key) {
+        *rss = rss.saturating_sub(pages);
+    }
+}
+
+/// Set the count of the address space `key` to `pages`, once a program is
+/// loaded into it.
+pub fn set(key: usize, pages: usize) {
+    RSS.lock().insert(key, pages);
+}
+
+/// The resident pages of the address space `key`.
+pub fn get(key: usize) -> usize {
+    RSS.lock().get(&key).copied().unwrap_or(0)
+}
+
+/// The frames mapped in `[start, start + len)` of `aspace`, to uncharge
+/// before unmapping them.
+pub fn mapped(aspace: &AddrSpace, start: VirtAddr, len: usize) -> usize {
+    (start.as_usize()..start.as_usize().saturating_add(len))
+        .step_by(PAGE_SIZE_4K)
+        .filter(|&page| {
+            aspace
+                .page_table()
+                .query(VirtAddr::from(page))
+                .is_ok_and(|(paddr, _, _)| !zero::is_zero_frame(paddr))
+        })
+        .count()
+}
+
+/// Forget the address space `key`, when it is cleared or dropped.
+pub(super) fn forget(key: usize) {
+    RSS.lock().remove(&key);
+}
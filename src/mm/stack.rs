@@ -0,0 +1,122 @@
+//! The initial user stack of a program: its arguments, environment variables
+//! and auxiliary vector, laid out as in Linux.
+//!
+//! From the top of the stack down:
+//!
+//! - the path of the program for `AT_EXECFN`, the environment strings, the
+//!   argument strings and the 16 random bytes of `AT_RANDOM`;
+//! - padding, so that the stack pointer is 16-byte aligned;
+//! - the auxiliary vector, ended by `AT_NULL`;
+//! - the pointers to the environment strings, then before them the pointers
+//!   to the argument strings, each ended by a null pointer;
+//! - the number of arguments, where the stack pointer starts.
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use memory_addr::VirtAddr;
+
+/// The end of the auxiliary vector
+const AT_NULL: u8 = 0;
+/// The size of a page
+pub const AT_PAGESZ: u8 = 6;
+/// The real user ID
+pub const AT_UID: u8 = 11;
+/// The effective user ID
+pub const AT_EUID: u8 = 12;
+/// The real group ID
+pub const AT_GID: u8 = 13;
+/// The effective group ID
+pub const AT_EGID: u8 = 14;
+/// The CPU features
+pub const AT_HWCAP: u8 = 16;
+/// The frequency of `times`
+pub const AT_CLKTCK: u8 = 17;
+/// Whether the program runs with other credentials than its caller
+pub const AT_SECURE: u8 = 23;
+/// The address of 16 random bytes
+const AT_RANDOM: u8 = 25;
+/// The address of the path of the program
+const AT_EXECFN: u8 = 31;
+
+/// The ticks per second `times` counts in, as in Linux.
+pub const USER_HZ: usize = 100;
+
+/// The size of the random bytes of `AT_RANDOM`.
+const RANDOM_SIZE: usize = 16;
+
+/// Where the argument and the environment strings lie on the stack, each
+/// ending with its NUL, for `/proc/<pid>/cmdline` and `environ`.
+#[derive(Clone, Default)]
+pub struct ArgEnv {
+    pub args: Range<VirtAddr>,
+    pub env: Range<VirtAddr>,
+}
+
+/// Append `s` to `buf` as a C string.
+fn push_cstr(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    // The strings copied from user space already end with their NUL
+    if !s.ends_with('\0') {
+        buf.push(0);
+    }
+}
+
+/// Lay out the initial stack of a program ending at `top`, with the entries
+/// of `auxv` and those pointing into the stack itself.
+///
+/// Returns the contents of the stack, where they start, which is the initial
+/// stack pointer, and where the strings were put.
+pub fn build(
+    argv: &[String],
+    envp: &[String],
+    auxv: &BTreeMap<u8, usize>,
+    execfn: &str,
+    top: VirtAddr,
+) -> (Vec<u8>, VirtAddr, ArgEnv) {
+    // The strings and the random bytes, in increasing addresses
+    let mut strings = alloc::vec![0; RANDOM_SIZE];
+    crate::random::fill(&mut strings);
+    let mut offsets = Vec::new();
+    for s in argv.iter().chain(envp).map(String::as_str).chain([execfn]) {
+        offsets.push(strings.len());
+        push_cstr(&mut strings, s);
+    }
+    let strings_start = top.as_usize() - strings.len();
+    let addr_of = |offset: usize| strings_start + offset;
+    let string_at = |i: usize| VirtAddr::from(addr_of(offsets[i]));
+    let arg_env = ArgEnv {
+        args: string_at(0)..string_at(argv.len()),
+        env: string_at(argv.len())..string_at(argv.len() + envp.len()),
+    };
+
+    let mut auxv = auxv.clone();
+    auxv.remove(&AT_NULL);
+    auxv.insert(AT_RANDOM, addr_of(0));
+    auxv.insert(AT_EXECFN, addr_of(*offsets.last().unwrap()));
+
+    let mut words = Vec::new();
+    words.push(argv.len());
+    words.extend(offsets[..argv.len()].iter().map(|&offset| addr_of(offset)));
+    words.push(0);
+    words.extend(
+        offsets[argv.len()..argv.len() + envp.len()]
+            .iter()
+            .map(|&offset| addr_of(offset)),
+    );
+    words.push(0);
+    for (&key, &value) in &auxv {
+        words.extend([key as usize, value]);
+    }
+    words.extend([AT_NULL as usize, 0]);
+
+    let words_size = words.len() * core::mem::size_of::<usize>();
+    let sp = (strings_start - words_size) & !0xf;
+    let mut data = Vec::with_capacity(top.as_usize() - sp);
+    for word in words {
+        data.extend_from_slice(&word.to_ne_bytes());
+    }
+    data.resize(strings_start - sp, 0);
+    data.extend_from_slice(&strings);
+    (data, VirtAddr::from(sp), arg_env)
+}
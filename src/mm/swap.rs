@@ -0,0 +1,414 @@
+//! Reclaim of memory when frames run out.
+//!
+//! When a frame can't be allocated, or a page fault can't be served for lack
+//! of memory, [`reclaim`] frees some: clean pages of the page cache first,
+//! the least recently used ones, then private pages of user space, written
+//! out to the swap file if `swapon` set one up.
+//!
+//! The pages which may be swapped out are the private ones user space
+//! faulted in, outside of `mlock`ed areas. They are taken in the order they
+//! were last faulted in, which approximates LRU: a page swapped out while
+//! still in use soon faults back in, and goes to the end of the queue.
+//!
+//! A swapped out page is unmapped, and remembered with its slot in the swap
+//! file and its flags, keyed by address space and address like the shared
+//! frames. The next fault maps a frame there again and reads the page back.
+//! The swap file is a regular file of the disk, used whole: every 4 KiB of it
+//! is a slot, no header is expected.
+use super::{rss, tlb, zero};
+use crate::fs::{meta, overlay, stat_path};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axstd::fs::{File, OpenOptions};
+use axstd::io::{Read, Seek, SeekFrom, Write};
+use axsync::Mutex;
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// The pages reclaimed at once, so that the next allocations find some.
+pub const RECLAIM_BATCH: usize = 32;
+
+struct SwapFile {
+    /// The absolute path of the file, as `swapon` got it
+    path: String,
+    file: File,
+    /// The number of slots
+    slots: usize,
+    /// The slots not in use
+    free: Vec<usize>,
+    /// Set by `swapoff` while it reads the pages back, so that no more are
+    /// written out
+    draining: bool,
+}
+
+impl SwapFile {
+    fn write(&mut self, slot: usize, data: &[u8]) -> LinuxResult {
+        self.file
+            .seek(SeekFrom::Start((slot * PAGE_SIZE_4K) as u64))?;
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn read(&mut self, slot: usize, data: &mut [u8]) -> LinuxResult {
+        self.file
+            .seek(SeekFrom::Start((slot * PAGE_SIZE_4K) as u64))?;
+        self.file.read_exact(data)?;
+        Ok(())
+    }
+}
+
+/// A page written out to the swap file.
+#[derive(Clone, Copy)]
+struct Swapped {
+    slot: usize,
+    /// The flags it is mapped with again
+    flags: MappingFlags,
+}
+
+struct Swap {
+    file: Option<SwapFile>,
+    /// The pages which may be swapped out, by when they were last faulted in
+    resident: BTreeMap<u64, (usize, usize)>,
+    /// When each page of `resident` was last faulted in, by address space
+    /// and address
+    stamps: BTreeMap<(usize, usize), u64>,
+    /// Incremented every time a page is faulted in
+    clock: u64,
+    /// The pages swapped out, by address space and address
+    swapped: BTreeMap<(usize, usize), Swapped>,
+    /// The address spaces of the pages above, to unmap them
+    spaces: BTreeMap<usize, Weak<Mutex<AddrSpace>>>,
+}
+
+static SWAP: Mutex<Swap> = Mutex::new(Swap {
+    file: None,
+    resident: BTreeMap::new(),
+    stamps: BTreeMap::new(),
+    clock: 0,
+    swapped: BTreeMap::new(),
+    spaces: BTreeMap::new(),
+});
+
+impl Swap {
+    /// Put `page` of the address space `key` at the end of the queue.
+    fn record(&mut self, key: usize, page: usize) {
+        self.clock += 1;
+        if let Some(stamp) = self.stamps.insert((key, page), self.clock) {
+            self.resident.remove(&stamp);
+        }
+        self.resident.insert(self.clock, (key, page));
+    }
+
+    /// Take `page` of the address space `key` out of the queue.
+    fn unrecord(&mut self, key: usize, page: usize) {
+        if let Some(stamp) = self.stamps.remove(&(key, page)) {
+            self.resident.remove(&stamp);
+        }
+    }
+
+    /// Write `page` of `aspace`, whose key is `key`, out to the swap file and
+    /// unmap it. Returns whether its frame was freed.
+    fn swap_out(&mut self, key: usize, aspace: &mut AddrSpace, page: VirtAddr) -> bool {
+        let Some(file) = self.file.as_mut().filter(|file| !file.draining) else {
+            return false;
+        };
+        let Ok((paddr, flags, _)) = aspace.page_table().query(page) else {
+            return false;
+        };
+        if zero::is_zero_frame(paddr) {
+            return false;
+        }
+        let Some(slot) = file.free.pop() else {
+            return false;
+        };
+        // Other threads may be writing to the page: make it read-only first,
+        // so that they fault and wait for the address space until it is gone
+        let mut data = vec![0; PAGE_SIZE_4K];
+        if aspace
+            .protect(page, PAGE_SIZE_4K, flags - MappingFlags::WRITE)
+            .is_err()
+        {
+            file.free.push(slot);
+            return false;
+        }
+        tlb::flush(aspace, page, PAGE_SIZE_4K);
+        let written = aspace.read(page, &mut data).is_ok()
+            && file
+                .write(slot, &data)
+                .inspect_err(|e| warn!("Failed to write to the swap file: {:?}", e))
+                .is_ok();
+        if !written || aspace.unmap(page, PAGE_SIZE_4K).is_err() {
+            let _ = aspace.protect(page, PAGE_SIZE_4K, flags);
+            tlb::flush(aspace, page, PAGE_SIZE_4K);
+            file.free.push(slot);
+            return false;
+        }
+        tlb::flush(aspace, page, PAGE_SIZE_4K);
+        rss::uncharge(key, 1);
+        self.swapped
+            .insert((key, page.as_usize()), Swapped { slot, flags });
+        true
+    }
+
+    /// Map `page` of `aspace`, whose key is `key`, again and read it back
+    /// from the swap file. Returns whether it was swapped out and is back.
+    fn swap_in(&mut self, key: usize, aspace: &mut AddrSpace, page: VirtAddr) -> bool {
+        let Some(&Swapped { slot, flags }) = self.swapped.get(&(key, page.as_usize())) else {
+            return false;
+        };
+        let Some(file) = self.file.as_mut() else {
+            return false;
+        };
+        let mut data = vec![0; PAGE_SIZE_4K];
+        if let Err(e) = file.read(slot, &mut data) {
+            warn!("Failed to read from the swap file: {:?}", e);
+            return false;
+        }
+        if aspace.map_alloc(page, PAGE_SIZE_4K, flags, true).is_err() {
+            return false;
+        }
+        if aspace.write(page, &data).is_err() {
+            let _ = aspace.unmap(page, PAGE_SIZE_4K);
+            return false;
+        }
+        file.free.push(slot);
+        rss::charge(key, 1);
+        self.swapped.remove(&(key, page.as_usize()));
+        self.record(key, page.as_usize());
+        true
+    }
+
+    /// Swap out up to `count` pages, the ones faulted in first. `locked` is
+    /// the address space the caller holds the lock of, with its key.
+    fn evict(&mut self, count: usize, mut locked: Option<(usize, &mut AddrSpace)>) -> usize {
+        let mut freed = 0;
+        // Each page is tried once, those of a busy address space go back in
+        // the queue
+        for _ in 0..self.resident.len() {
+            if freed == count || self.file.as_ref().map_or(true, |file| file.free.is_empty()) {
+                break;
+            }
+            let Some((_, (key, page))) = self.resident.pop_first() else {
+                break;
+            };
+            self.stamps.remove(&(key, page));
+            let page = VirtAddr::from(page);
+            let swapped = match &mut locked {
+                Some((locked_key, aspace)) if *locked_key == key => {
+                    self.swap_out(key, aspace, page)
+                }
+                _ => {
+                    let Some(space) = self.spaces.get(&key).and_then(Weak::upgrade) else {
+                        continue;
+                    };
+                    let Some(mut aspace) = space.try_lock() else {
+                        self.record(key, page.as_usize());
+                        continue;
+                    };
+                    self.swap_out(key, &mut aspace, page)
+                }
+            };
+            if swapped {
+                freed += 1;
+            }
+        }
+        freed
+    }
+}
+
+/// Free up to `count` frames: clean pages of the page cache first, then
+/// private pages of user space written out to the swap file. `locked` is the
+/// address space the caller holds the lock of, if any, with its key.
+///
+/// Returns the number of frames freed.
+pub fn reclaim(count: usize, locked: Option<(usize, &mut AddrSpace)>) -> usize {
+    let mut freed = crate::fs::cache::reclaim(count);
+    if freed < count {
+        freed += SWAP.lock().evict(count - freed, locked);
+    }
+    if freed > 0 {
+        debug!("Reclaimed {} pages", freed);
+    }
+    freed
+}
+
+/// Whether free frames are so few that a failed page fault may be for lack
+/// of memory.
+pub fn low_on_memory() -> bool {
+    super::ram_usage().free < RECLAIM_BATCH * PAGE_SIZE_4K
+}
+
+/// Handle a fault at `vaddr` of the address space `key` if its page was
+/// swapped out. Returns whether the fault was handled.
+pub fn handle_fault(key: usize, aspace: &mut AddrSpace, vaddr: VirtAddr) -> bool {
+    SWAP.lock().swap_in(key, aspace, vaddr.align_down_4k())
+}
+
+/// Whether `page` of the address space `key` is swapped out.
+pub fn is_swapped(key: usize, page: VirtAddr) -> bool {
+    SWAP.lock().swapped.contains_key(&(key, page.as_usize()))
+}
+
+/// Let the page at `vaddr` of `aspace`, just faulted in, be swapped out once
+/// the pages faulted in before it are.
+pub fn record(aspace: &Arc<Mutex<AddrSpace>>, vaddr: VirtAddr) {
+    let key = super::aspace_key(aspace);
+    let mut swap = SWAP.lock();
+    swap.spaces
+        .entry(key)
+        .or_insert_with(|| Arc::downgrade(aspace));
+    swap.record(key, vaddr.align_down_4k().as_usize());
+}
+
+/// Let the pages mapped in `[start, start + len)` of `aspace` be swapped out
+/// again, after `munlock`. `locked` is the address space itself, which the
+/// caller holds the lock of.
+pub fn record_mapped(
+    aspace: &Arc<Mutex<AddrSpace>>,
+    locked: &AddrSpace,
+    start: VirtAddr,
+    len: usize,
+) {
+    for page in (start.as_usize()..start.as_usize() + len).step_by(PAGE_SIZE_4K) {
+        if locked.page_table().query(VirtAddr::from(page)).is_ok() {
+            record(aspace, VirtAddr::from(page));
+        }
+    }
+}
+
+/// Keep the pages of `[start, start + len)` of the address space `key` in
+/// memory, for `mlock`: read back those swapped out, and never swap them out
+/// again.
+pub fn pin(key: usize, aspace: &mut AddrSpace, start: VirtAddr, len: usize) -> LinuxResult {
+    let mut swap = SWAP.lock();
+    for page in (start.as_usize()..start.as_usize() + len).step_by(PAGE_SIZE_4K) {
+        if swap.swapped.contains_key(&(key, page)) && !swap.swap_in(key, aspace, page.into()) {
+            return Err(LinuxError::EAGAIN);
+        }
+        swap.unrecord(key, page);
+    }
+    Ok(())
+}
+
+/// Apply a change of protection to `flags` of `[start, start + len)` of the
+/// address space `key` to the pages swapped out in the range.
+pub fn protect(key: usize, start: VirtAddr, len: usize, flags: MappingFlags) {
+    let (start, end) = (start.as_usize(), start.as_usize() + len);
+    for (_, swapped) in SWAP.lock().swapped.range_mut((key, start)..(key, end)) {
+        swapped.flags = flags;
+    }
+}
+
+/// Forget the pages in `[start, end)` of the address space `key`, once they
+/// are unmapped.
+pub(super) fn forget(key: usize, start: usize, end: usize) {
+    let mut swap = SWAP.lock();
+    let pages: Vec<_> = swap
+        .stamps
+        .range((key, start)..(key, end))
+        .map(|(&(_, page), _)| page)
+        .collect();
+    for page in pages {
+        swap.unrecord(key, page);
+    }
+    let swapped: Vec<_> = swap
+        .swapped
+        .range((key, start)..(key, end))
+        .map(|(&k, _)| k)
+        .collect();
+    for k in swapped {
+        let slot = swap.swapped.remove(&k).unwrap().slot;
+        if let Some(file) = swap.file.as_mut() {
+            file.free.push(slot);
+        }
+    }
+}
+
+/// Forget the address space `key`, when it is cleared or dropped, once its
+/// pages are forgotten.
+pub(super) fn forget_space(key: usize) {
+    SWAP.lock().spaces.remove(&key);
+}
+
+/// The size of the swap file and the room left in it, in bytes.
+pub fn usage() -> (usize, usize) {
+    SWAP.lock().file.as_ref().map_or((0, 0), |file| {
+        (file.slots * PAGE_SIZE_4K, file.free.len() * PAGE_SIZE_4K)
+    })
+}
+
+/// Swap to the regular file at the absolute `path`, for `swapon`.
+pub fn swapon(path: &str) -> LinuxResult {
+    if stat_path(path)?.st_mode & meta::S_IFMT != meta::S_IFREG {
+        return Err(LinuxError::EINVAL);
+    }
+    let disk_path = overlay::lookup(path);
+    let slots = axfs::api::metadata(&disk_path)?.len() as usize / PAGE_SIZE_4K;
+    if slots == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let file = OpenOptions::new().read(true).write(true).open(&disk_path)?;
+    let mut swap = SWAP.lock();
+    if swap.file.is_some() {
+        return Err(LinuxError::EBUSY);
+    }
+    swap.file = Some(SwapFile {
+        path: String::from(path),
+        file,
+        slots,
+        free: (0..slots).rev().collect(),
+        draining: false,
+    });
+    info!("Swapping to {}, {} pages", path, slots);
+    Ok(())
+}
+
+/// Stop swapping to the file at the absolute `path`, for `swapoff`: read all
+/// the pages swapped out back first.
+pub fn swapoff(path: &str) -> LinuxResult {
+    let keys: Vec<usize> = {
+        let mut swap = SWAP.lock();
+        match swap.file.as_mut() {
+            Some(file) if file.path == path => file.draining = true,
+            _ => return Err(LinuxError::EINVAL),
+        }
+        let mut keys: Vec<usize> = swap.swapped.keys().map(|&(key, _)| key).collect();
+        keys.dedup();
+        keys
+    };
+    let mut res = Ok(());
+    for key in keys {
+        let space = SWAP.lock().spaces.get(&key).and_then(Weak::upgrade);
+        let Some(space) = space else {
+            // The address space is gone, so are its pages
+            forget(key, 0, usize::MAX);
+            continue;
+        };
+        // The address space comes first, as in the page fault handler
+        let mut aspace = space.lock();
+        let mut swap = SWAP.lock();
+        let pages: Vec<usize> = swap
+            .swapped
+            .range((key, 0)..=(key, usize::MAX))
+            .map(|(&(_, page), _)| page)
+            .collect();
+        for page in pages {
+            if !swap.swap_in(key, &mut aspace, page.into()) {
+                res = Err(LinuxError::ENOMEM);
+            }
+        }
+    }
+    let mut swap = SWAP.lock();
+    if res.is_err() || !swap.swapped.is_empty() {
+        swap.file.as_mut().unwrap().draining = false;
+        return res.and(Err(LinuxError::ENOMEM));
+    }
+    swap.file = None;
+    info!("No longer swapping to {}", path);
+    Ok(())
+}
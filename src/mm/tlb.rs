@@ -0,0 +1,97 @@
+//! TLB maintenance after changes to the mappings of an address space.
+//!
+//! Whoever unmaps, remaps or changes the protection of a range calls
+//! [`flush`] right after, with the address space still locked. The range is
+//! flushed on the local CPU, and on SMP the other CPUs are shot down:
+//!
+//! - Every CPU flushes its whole TLB on its way back to user space if a
+//!   shootdown was requested since it last did, and records the page table it
+//!   returns with.
+//! - The CPU requesting a shootdown then waits for the CPUs which may be
+//!   running user code on the same page table to come back through that path,
+//!   which they do at the latest on their next timer interrupt. A CPU in a
+//!   syscall or handling a user page fault doesn't run user code, and flushes
+//!   before it does again.
+//!
+//! The request and the return to user space each write their side before
+//! reading the other's, so either the CPU sees the request, or the requester
+//! sees the CPU in user space and waits for it.
+use axhal::arch::flush_tlb;
+use axmm::AddrSpace;
+use axstd::os::arceos::modules::axconfig;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use linkme::distributed_slice;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+/// Ranges of more pages than this flush the whole TLB instead.
+const FLUSH_ALL_PAGES: usize = 32;
+
+/// The number of shootdowns requested so far
+static SHOOTDOWNS: AtomicU64 = AtomicU64::new(0);
+/// The number of shootdowns each CPU has flushed for
+static FLUSHED: [AtomicU64; axconfig::SMP] = [const { AtomicU64::new(0) }; axconfig::SMP];
+/// Whether each CPU may be running user code
+static IN_USER: [AtomicBool; axconfig::SMP] = [const { AtomicBool::new(false) }; axconfig::SMP];
+/// The page table each CPU last returned to user space with
+static ACTIVE_ROOT: [AtomicUsize; axconfig::SMP] = [const { AtomicUsize::new(0) }; axconfig::SMP];
+
+fn flush_local(start: VirtAddr, size: usize) {
+    let pages = size.div_ceil(PAGE_SIZE_4K);
+    if pages > FLUSH_ALL_PAGES {
+        flush_tlb(None);
+    } else {
+        for i in 0..pages {
+            flush_tlb(Some(start + i * PAGE_SIZE_4K));
+        }
+    }
+}
+
+/// Flush the translations of `[start, start + size)` in `aspace` on every
+/// CPU, after its mappings changed.
+pub fn flush(aspace: &AddrSpace, start: VirtAddr, size: usize) {
+    flush_local(start, size);
+    if axconfig::SMP == 1 {
+        return;
+    }
+    let root = aspace.page_table_root().as_usize();
+    let this = axhal::cpu::this_cpu_id();
+    let target = SHOOTDOWNS.fetch_add(1, Ordering::SeqCst) + 1;
+    FLUSHED[this].fetch_max(target, Ordering::SeqCst);
+    for cpu in (0..axconfig::SMP).filter(|&cpu| cpu != this) {
+        while IN_USER[cpu].load(Ordering::SeqCst)
+            && ACTIVE_ROOT[cpu].load(Ordering::SeqCst) == root
+            && FLUSHED[cpu].load(Ordering::SeqCst) < target
+        {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Flush all the translations of `aspace` on every CPU, after it was cleared.
+pub fn flush_all(aspace: &AddrSpace) {
+    flush(aspace, aspace.base(), aspace.size());
+}
+
+/// Note that the current CPU left user space, on the entry of a syscall or of
+/// a user page fault.
+pub fn leave_user() {
+    IN_USER[axhal::cpu::this_cpu_id()].store(false, Ordering::SeqCst);
+}
+
+#[distributed_slice(axhal::arch::HANDLE_SIGNAL)]
+fn flush_on_return() {
+    if axconfig::SMP == 1 {
+        return;
+    }
+    let cpu = axhal::cpu::this_cpu_id();
+    ACTIVE_ROOT[cpu].store(
+        axhal::arch::read_page_table_root().as_usize(),
+        Ordering::SeqCst,
+    );
+    IN_USER[cpu].store(true, Ordering::SeqCst);
+    let requested = SHOOTDOWNS.load(Ordering::SeqCst);
+    if FLUSHED[cpu].load(Ordering::SeqCst) < requested {
+        flush_tlb(None);
+        FLUSHED[cpu].fetch_max(requested, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,60 @@
+//! The signal trampoline: the code a signal handler returns to when its
+//! `sigaction` has no `SA_RESTORER`, which makes the `rt_sigreturn` syscall.
+//!
+//! The code lives in a single global page, mapped read and execute only into
+//! every address space when a program is loaded. Its address is published to
+//! the program with `AT_SIGNAL_TRAMPOLINE` and kept in the process.
+use alloc::sync::Arc;
+use axerrno::AxResult;
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use lazy_static::lazy_static;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::Frame;
+
+/// The address of the signal trampoline. Not a Linux entry: it is out of the
+/// range Linux uses, so that C libraries ignore it.
+pub const AT_SIGNAL_TRAMPOLINE: u8 = 0x80;
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        const _: () = assert!(syscalls::Sysno::rt_sigreturn as usize == 139);
+        /// li a7, 139; ecall
+        const CODE: [u8; 8] = [0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+    } else if #[cfg(target_arch = "x86_64")] {
+        const _: () = assert!(syscalls::Sysno::rt_sigreturn as usize == 15);
+        /// mov $15, %eax; syscall
+        const CODE: [u8; 7] = [0xb8, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05];
+    } else if #[cfg(target_arch = "aarch64")] {
+        const _: () = assert!(syscalls::Sysno::rt_sigreturn as usize == 139);
+        /// mov x8, #139; svc #0
+        const CODE: [u8; 8] = [0x68, 0x11, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4];
+    }
+}
+
+lazy_static! {
+    static ref TRAMPOLINE: Arc<Frame> = {
+        let frame = Frame::alloc().expect("failed to allocate the signal trampoline");
+        frame.as_mut_slice()[..CODE.len()].copy_from_slice(&CODE);
+        frame
+    };
+}
+
+/// Whether the frame at `paddr` is the trampoline.
+pub(super) fn is_trampoline(paddr: PhysAddr) -> bool {
+    paddr == TRAMPOLINE.paddr()
+}
+
+/// Map the trampoline in `uspace` on the page ending at `end`, and return
+/// where it starts.
+pub fn map(uspace: &mut AddrSpace, end: VirtAddr) -> AxResult<VirtAddr> {
+    let start = end - PAGE_SIZE_4K;
+    uspace.map_linear(
+        start,
+        TRAMPOLINE.paddr(),
+        PAGE_SIZE_4K,
+        MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER,
+    )?;
+    Ok(start)
+}
@@ -0,0 +1,634 @@
+//! The vDSO: a small shared object mapped into every user address space, so
+//! that programs read `CLOCK_REALTIME` and `CLOCK_MONOTONIC` without a syscall.
+//!
+//! Three pages are mapped below the user stack, and the program finds them
+//! through `AT_SYSINFO_EHDR`:
+//!
+//! - the page of the time namespace, read-only to user space, holds the
+//!   offset of its `CLOCK_MONOTONIC`. The namespaces without one share a page
+//!   of zeros, the others each get theirs once their offsets are fixed;
+//! - the data page ("vvar"), global and read-only to user space, holds a
+//!   snapshot of the monotonic clock along with the value of the hardware
+//!   counter it was taken at, refreshed by a kernel task on every timer tick
+//!   under a sequence count;
+//! - the code page right above it, global too, holds a minimal ELF image
+//!   exporting `__vdso_clock_gettime`, or `__kernel_clock_gettime` on
+//!   aarch64, the names the C libraries look up. It reads the counter, which
+//!   user space may read on all three architectures, and extrapolates from
+//!   the snapshot.
+//!
+//! The stub makes the real syscall for the other clocks.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axsync::Mutex;
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::vma::VmAreas;
+use super::{forget_frames, tlb, Frame, SHARED_FRAMES};
+use crate::process::timens::TimeNamespace;
+
+/// The address of the ELF header of the vDSO
+pub const AT_SYSINFO_EHDR: u8 = 33;
+
+/// Where the stub starts in the code page, after the ELF headers and tables.
+const CODE_OFFSET: usize = 0x200;
+
+/// The snapshot of the clocks in the data page.
+///
+/// The stub reads it with the offsets below hard-coded, and retries while
+/// `seq` is odd or changed meanwhile.
+#[repr(C)]
+struct VvarData {
+    seq: AtomicU32,
+    _pad: u32,
+    _reserved: u64,
+    /// The hardware counter when `base_ns` was taken
+    base_ticks: AtomicU64,
+    /// The monotonic time in nanoseconds
+    base_ns: AtomicU64,
+    /// The nanoseconds per tick of the counter, as a fixed-point number with
+    /// 32 fraction bits
+    mult: AtomicU64,
+    /// The wall time minus the monotonic time, in nanoseconds
+    wall_offset: AtomicU64,
+}
+
+const _: () = {
+    assert!(offset_of!(VvarData, seq) == 0);
+    assert!(offset_of!(VvarData, base_ticks) == 16);
+    assert!(offset_of!(VvarData, base_ns) == 24);
+    assert!(offset_of!(VvarData, mult) == 32);
+    assert!(offset_of!(VvarData, wall_offset) == 40);
+    assert!(size_of::<VvarData>() <= PAGE_SIZE_4K);
+    // The distances from the stub back to the data page and to the page of
+    // the time namespace
+    assert!(PAGE_SIZE_4K + CODE_OFFSET == 0x1200);
+    assert!(2 * PAGE_SIZE_4K + CODE_OFFSET == 0x2200);
+};
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "riscv64")] {
+        const _: () = assert!(syscalls::Sysno::clock_gettime as usize == 113);
+
+        /// `e_machine` of the image
+        const EM_HOST: u16 = 243;
+        /// `e_flags` of the image: compressed instructions, double-float ABI
+        const EF_HOST: u32 = 0x5;
+        /// The symbol the C library looks up
+        const SYMBOL: &[u8] = b"__vdso_clock_gettime";
+
+        // a0: clock id, a1: timespec. Only t0-t6 and a2-a5 are clobbered.
+        core::arch::global_asm!(
+            "
+            .section .text.vdso, \"ax\"
+            .balign 4
+            .global __vdso_text_start
+        __vdso_text_start:
+            .option push
+            .option norelax
+            auipc   t1, 0
+            li      t2, 0x1200
+            sub     t1, t1, t2
+            li      t0, 1
+            bgtu    a0, t0, 3f
+        2:
+            lw      t3, 0(t1)
+            andi    t4, t3, 1
+            bnez    t4, 2b
+            fence   r, r
+            rdtime  t4
+            ld      t5, 16(t1)
+            ld      t6, 24(t1)
+            ld      a2, 32(t1)
+            ld      a3, 40(t1)
+            fence   r, r
+            lw      a4, 0(t1)
+            bne     a4, t3, 2b
+            sub     t4, t4, t5
+            mul     a4, t4, a2
+            mulhu   a5, t4, a2
+            srli    a4, a4, 32
+            slli    a5, a5, 32
+            or      a4, a4, a5
+            add     t6, t6, a4
+            beqz    a0, 4f
+            li      t2, 0x1000
+            sub     t2, t1, t2
+            ld      a3, 0(t2)
+        4:
+            add     t6, t6, a3
+            li      t0, 1000000000
+            divu    t2, t6, t0
+            remu    t3, t6, t0
+            sd      t2, 0(a1)
+            sd      t3, 8(a1)
+            li      a0, 0
+            ret
+        3:
+            li      a7, 113
+            ecall
+            ret
+            .option pop
+            .global __vdso_text_end
+        __vdso_text_end:
+            .text
+            "
+        );
+
+        /// The counter the stub reads.
+        fn read_counter() -> u64 {
+            let value: u64;
+            unsafe { core::arch::asm!("rdtime {}", out(reg) value) };
+            value
+        }
+
+        /// Let user space read the `time` CSR on this CPU.
+        fn enable_user_counter() {
+            unsafe { core::arch::asm!("csrs scounteren, {}", in(reg) 1 << 1) };
+        }
+
+        /// Handle an illegal `insn` of user space which may be a read of the
+        /// counter, made before this CPU let user space read it. Returns
+        /// whether the instruction should just be retried.
+        pub fn handle_counter_read(insn: u32) -> bool {
+            // rdtime of any register: csrrs rd, time, zero
+            if insn & !(0x1f << 7) != 0xc010_2073 {
+                return false;
+            }
+            let scounteren: usize;
+            unsafe { core::arch::asm!("csrr {}, scounteren", out(reg) scounteren) };
+            if scounteren & (1 << 1) != 0 {
+                return false;
+            }
+            enable_user_counter();
+            true
+        }
+    } else if #[cfg(target_arch = "x86_64")] {
+        const _: () = assert!(syscalls::Sysno::clock_gettime as usize == 228);
+
+        /// `e_machine` of the image
+        const EM_HOST: u16 = 62;
+        /// `e_flags` of the image
+        const EF_HOST: u32 = 0;
+        /// The symbol the C library looks up
+        const SYMBOL: &[u8] = b"__vdso_clock_gettime";
+
+        // rdi: clock id, rsi: timespec. Only the scratch registers are
+        // clobbered.
+        core::arch::global_asm!(
+            "
+            .section .text.vdso, \"ax\"
+            .global __vdso_text_start
+        __vdso_text_start:
+            lea     __vdso_text_start - 0x1200(%rip), %rcx
+            cmp     $1, %edi
+            ja      3f
+        2:
+            mov     0(%rcx), %r8d
+            test    $1, %r8d
+            jnz     2b
+            lfence
+            rdtsc
+            shl     $32, %rdx
+            or      %rdx, %rax
+            sub     16(%rcx), %rax
+            mulq    32(%rcx)
+            shrd    $32, %rdx, %rax
+            add     24(%rcx), %rax
+            mov     40(%rcx), %r9
+            cmp     0(%rcx), %r8d
+            jne     2b
+            test    %edi, %edi
+            jz      4f
+            mov     -0x1000(%rcx), %r9
+        4:
+            add     %r9, %rax
+            xor     %edx, %edx
+            mov     $1000000000, %r10
+            div     %r10
+            mov     %rax, 0(%rsi)
+            mov     %rdx, 8(%rsi)
+            xor     %eax, %eax
+            ret
+        3:
+            mov     $228, %eax
+            syscall
+            ret
+            .global __vdso_text_end
+        __vdso_text_end:
+            .text
+            ",
+            options(att_syntax)
+        );
+
+        /// The counter the stub reads.
+        fn read_counter() -> u64 {
+            unsafe { core::arch::x86_64::_rdtsc() }
+        }
+
+        /// User space may read the TSC unless `CR4.TSD` is set, which it
+        /// isn't.
+        fn enable_user_counter() {}
+
+        /// Nothing to do: reading the counter doesn't trap.
+        pub fn handle_counter_read(_insn: u32) -> bool {
+            false
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        use axstd::os::arceos::modules::axconfig;
+        use core::sync::atomic::AtomicBool;
+        use linkme::distributed_slice;
+
+        const _: () = assert!(syscalls::Sysno::clock_gettime as usize == 113);
+
+        /// `e_machine` of the image
+        const EM_HOST: u16 = 183;
+        /// `e_flags` of the image
+        const EF_HOST: u32 = 0;
+        /// The symbol the C library looks up
+        const SYMBOL: &[u8] = b"__kernel_clock_gettime";
+
+        // x0: clock id, x1: timespec. Only x8-x17 are clobbered.
+        core::arch::global_asm!(
+            "
+            .section .text.vdso, \"ax\"
+            .balign 4
+            .global __vdso_text_start
+        __vdso_text_start:
+            adr     x9, __vdso_text_start
+            sub     x9, x9, #1, lsl #12
+            sub     x9, x9, #0x200
+            cmp     w0, #1
+            b.hi    3f
+        2:
+            ldr     w10, [x9]
+            tbnz    w10, #0, 2b
+            dmb     ishld
+            isb
+            mrs     x11, cntvct_el0
+            ldr     x12, [x9, #16]
+            ldr     x13, [x9, #24]
+            ldr     x14, [x9, #32]
+            ldr     x15, [x9, #40]
+            dmb     ishld
+            ldr     w16, [x9]
+            cmp     w16, w10
+            b.ne    2b
+            sub     x11, x11, x12
+            mul     x16, x11, x14
+            umulh   x17, x11, x14
+            extr    x16, x17, x16, #32
+            add     x13, x13, x16
+            cbz     w0, 4f
+            sub     x15, x9, #1, lsl #12
+            ldr     x15, [x15]
+        4:
+            add     x13, x13, x15
+            movz    x16, #0xca00
+            movk    x16, #0x3b9a, lsl #16
+            udiv    x17, x13, x16
+            msub    x12, x17, x16, x13
+            stp     x17, x12, [x1]
+            mov     w0, #0
+            ret
+        3:
+            mov     x8, #113
+            svc     #0
+            ret
+            .global __vdso_text_end
+        __vdso_text_end:
+            .text
+            "
+        );
+
+        /// The counter the stub reads.
+        fn read_counter() -> u64 {
+            let value: u64;
+            unsafe { core::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) value) };
+            value
+        }
+
+        /// Whether each CPU lets user space read the virtual counter
+        static COUNTER_ENABLED: [AtomicBool; axconfig::SMP] =
+            [const { AtomicBool::new(false) }; axconfig::SMP];
+
+        /// Let user space read `CNTVCT_EL0` on this CPU.
+        fn enable_user_counter() {
+            const EL0VCTEN: u64 = 1 << 1;
+            unsafe {
+                core::arch::asm!(
+                    "mrs {tmp}, cntkctl_el1",
+                    "orr {tmp}, {tmp}, {bit}",
+                    "msr cntkctl_el1, {tmp}",
+                    "isb",
+                    tmp = out(reg) _,
+                    bit = in(reg) EL0VCTEN,
+                )
+            };
+            COUNTER_ENABLED[axhal::cpu::this_cpu_id()].store(true, Ordering::Relaxed);
+        }
+
+        /// Let user space read the counter on the CPUs other than the boot
+        /// one, the first time they return to it.
+        #[distributed_slice(axhal::arch::HANDLE_SIGNAL)]
+        fn enable_counter_on_return() {
+            if !COUNTER_ENABLED[axhal::cpu::this_cpu_id()].load(Ordering::Relaxed)
+                && VDSO.is_some()
+            {
+                enable_user_counter();
+            }
+        }
+
+        /// Nothing to do: the counter is readable before any user code runs.
+        pub fn handle_counter_read(_insn: u32) -> bool {
+            false
+        }
+    }
+}
+
+extern "C" {
+    static __vdso_text_start: u8;
+    static __vdso_text_end: u8;
+}
+
+/// The pages shared by all the address spaces.
+struct Vdso {
+    /// The page of the time namespaces without offsets
+    timens: Arc<Frame>,
+    data: Arc<Frame>,
+    code: Arc<Frame>,
+}
+
+lazy_static! {
+    static ref VDSO: Option<Vdso> = build();
+}
+
+/// Serializes the writers of the data page.
+static WRITER: Mutex<()> = Mutex::new(());
+
+/// Append the little-endian bytes of `values` to `buf`.
+fn push<const N: usize>(buf: &mut Vec<u8>, values: &[[u8; N]]) {
+    for value in values {
+        buf.extend_from_slice(value);
+    }
+}
+
+/// Lay out the ELF image of the code page around `code`: the ELF header, a
+/// `PT_LOAD` and a `PT_DYNAMIC` segment, then the hash table, symbol table,
+/// string table and dynamic section which resolve the symbol of the stub.
+/// Addresses in the image are offsets from its start.
+fn image(code: &[u8]) -> Vec<u8> {
+    const PHDRS: usize = 64;
+    const HASH: usize = PHDRS + 2 * 56;
+    const SYMTAB: usize = HASH + 24;
+    const STRTAB: usize = SYMTAB + 2 * 24;
+    // The symbol between two NULs
+    const STRINGS_LEN: usize = SYMBOL.len() + 2;
+    const DYNAMIC: usize = (STRTAB + STRINGS_LEN).next_multiple_of(8);
+    const DYNAMIC_SIZE: usize = 6 * 16;
+    const _: () = assert!(DYNAMIC + DYNAMIC_SIZE <= CODE_OFFSET);
+
+    let mut buf = Vec::with_capacity(PAGE_SIZE_4K);
+    // ELF header: 64-bit, little-endian, ET_DYN
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    push(&mut buf, &[3u16.to_le_bytes(), EM_HOST.to_le_bytes()]);
+    push(&mut buf, &[1u32.to_le_bytes()]);
+    push(
+        &mut buf,
+        &[
+            0u64.to_le_bytes(),
+            (PHDRS as u64).to_le_bytes(),
+            0u64.to_le_bytes(),
+        ],
+    );
+    push(&mut buf, &[EF_HOST.to_le_bytes()]);
+    push(&mut buf, &[64u16, 56, 2, 64, 0, 0].map(u16::to_le_bytes));
+    // PT_LOAD of the whole page, readable and executable
+    push(&mut buf, &[1u32, 5].map(u32::to_le_bytes));
+    push(
+        &mut buf,
+        &[
+            0u64,
+            0,
+            0,
+            PAGE_SIZE_4K as u64,
+            PAGE_SIZE_4K as u64,
+            PAGE_SIZE_4K as u64,
+        ]
+        .map(u64::to_le_bytes),
+    );
+    // PT_DYNAMIC, readable
+    push(&mut buf, &[2u32, 4].map(u32::to_le_bytes));
+    let dynamic = DYNAMIC as u64;
+    push(
+        &mut buf,
+        &[
+            dynamic,
+            dynamic,
+            dynamic,
+            DYNAMIC_SIZE as u64,
+            DYNAMIC_SIZE as u64,
+            8,
+        ]
+        .map(u64::to_le_bytes),
+    );
+    // Hash table: one bucket, holding the only symbol
+    push(&mut buf, &[1u32, 2, 1, 0, 0].map(u32::to_le_bytes));
+    buf.resize(SYMTAB, 0);
+    // The null symbol, then a global function in a made-up section 1
+    buf.resize(SYMTAB + 24, 0);
+    push(&mut buf, &[1u32.to_le_bytes()]);
+    buf.extend_from_slice(&[0x12, 0]);
+    push(&mut buf, &[1u16.to_le_bytes()]);
+    push(
+        &mut buf,
+        &[CODE_OFFSET as u64, code.len() as u64].map(u64::to_le_bytes),
+    );
+    buf.push(0);
+    buf.extend_from_slice(SYMBOL);
+    buf.push(0);
+    buf.resize(DYNAMIC, 0);
+    // DT_HASH, DT_STRTAB, DT_SYMTAB, DT_STRSZ, DT_SYMENT, DT_NULL
+    push(
+        &mut buf,
+        &[
+            4,
+            HASH as u64,
+            5,
+            STRTAB as u64,
+            6,
+            SYMTAB as u64,
+            10,
+            STRINGS_LEN as u64,
+            11,
+            24,
+            0,
+            0,
+        ]
+        .map(u64::to_le_bytes),
+    );
+    buf.resize(CODE_OFFSET, 0);
+    buf.extend_from_slice(code);
+    buf
+}
+
+fn build() -> Option<Vdso> {
+    let code = unsafe {
+        let start = core::ptr::addr_of!(__vdso_text_start);
+        let end = core::ptr::addr_of!(__vdso_text_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    let image = image(code);
+    assert!(image.len() <= PAGE_SIZE_4K, "vDSO image too large");
+    let timens = Frame::alloc().expect("failed to allocate the vDSO time namespace page");
+    let data = Frame::alloc().expect("failed to allocate the vDSO data page");
+    let code = Frame::alloc().expect("failed to allocate the vDSO code page");
+    code.as_mut_slice()[..image.len()].copy_from_slice(&image);
+    Some(Vdso { timens, data, code })
+}
+
+impl Vdso {
+    fn data(&self) -> &VvarData {
+        unsafe { &*(self.data.as_slice().as_ptr() as *const VvarData) }
+    }
+
+    /// Change the data page under the sequence count.
+    fn update(&self, f: impl FnOnce(&VvarData)) {
+        let _guard = WRITER.lock();
+        let data = self.data();
+        data.seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        f(data);
+        data.seq.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Take a new snapshot of the clocks, never behind what the previous one
+/// extrapolates to, so that the time user space reads doesn't go back.
+fn refresh(vdso: &Vdso) {
+    let ticks = read_counter();
+    let now = axhal::time::monotonic_time_nanos();
+    let wall_offset = crate::clock::offset_nanos() as u64;
+    vdso.update(|data| {
+        let elapsed = ticks.wrapping_sub(data.base_ticks.load(Ordering::Relaxed));
+        let mult = data.mult.load(Ordering::Relaxed);
+        let extrapolated =
+            data.base_ns.load(Ordering::Relaxed) + ((elapsed as u128 * mult as u128) >> 32) as u64;
+        data.base_ticks.store(ticks, Ordering::Relaxed);
+        data.base_ns.store(now.max(extrapolated), Ordering::Relaxed);
+        data.wall_offset.store(wall_offset, Ordering::Relaxed);
+    });
+}
+
+/// Fill the data page and start refreshing it on every timer tick.
+pub fn init() {
+    if let Some(vdso) = VDSO.as_ref() {
+        enable_user_counter();
+        vdso.update(|data| {
+            data.base_ticks.store(read_counter(), Ordering::Relaxed);
+            data.base_ns
+                .store(axhal::time::monotonic_time_nanos(), Ordering::Relaxed);
+            data.mult
+                .store(axhal::time::ticks_to_nanos(1 << 32), Ordering::Relaxed);
+        });
+        refresh(vdso);
+        let tick = core::time::Duration::from_secs(1)
+            / axstd::os::arceos::modules::axconfig::TICKS_PER_SEC as u32;
+        axtask::spawn(move || loop {
+            axtask::sleep(tick);
+            refresh(vdso);
+        });
+    }
+}
+
+/// Take a new snapshot right away, once `CLOCK_REALTIME` is set, rather than
+/// on the next timer tick.
+pub fn realtime_changed() {
+    if let Some(vdso) = VDSO.as_ref() {
+        refresh(vdso);
+    }
+}
+
+/// Whether the frame at `paddr` is one of the pages shared by all the
+/// address spaces.
+pub(super) fn is_vdso(paddr: PhysAddr) -> bool {
+    VDSO.as_ref().is_some_and(|vdso| {
+        paddr == vdso.timens.paddr() || paddr == vdso.data.paddr() || paddr == vdso.code.paddr()
+    })
+}
+
+/// Map the vDSO in `uspace`, ending at `end`: the page of the time namespaces
+/// without offsets, the data page then the code page. A process in a
+/// namespace with offsets then calls [`enter_timens`].
+///
+/// Returns where the code page, and so the ELF header, is mapped, if there is
+/// a vDSO.
+pub fn map(uspace: &mut AddrSpace, end: VirtAddr) -> AxResult<Option<VirtAddr>> {
+    let Some(vdso) = VDSO.as_ref() else {
+        return Ok(None);
+    };
+    let code_start = end - PAGE_SIZE_4K;
+    let data_start = code_start - PAGE_SIZE_4K;
+    let timens_start = data_start - PAGE_SIZE_4K;
+    let flags = MappingFlags::READ | MappingFlags::USER;
+    uspace.map_linear(timens_start, vdso.timens.paddr(), PAGE_SIZE_4K, flags)?;
+    uspace.map_linear(data_start, vdso.data.paddr(), PAGE_SIZE_4K, flags)?;
+    uspace.map_linear(
+        code_start,
+        vdso.code.paddr(),
+        PAGE_SIZE_4K,
+        flags | MappingFlags::EXECUTE,
+    )?;
+    Ok(Some(code_start))
+}
+
+/// Show the processes of `ns` using `aspace`, whose key is `key` and areas
+/// `areas`, the offsets of their namespace, by mapping its page at the start
+/// of the "[vvar]" area. Nothing is done if the area was unmapped.
+pub fn enter_timens(
+    key: usize,
+    aspace: &mut AddrSpace,
+    areas: &VmAreas,
+    ns: &TimeNamespace,
+) -> AxResult {
+    let Some(vdso) = VDSO.as_ref() else {
+        return Ok(());
+    };
+    let Some(vvar) = areas
+        .iter()
+        .find(|area| area.name.as_deref() == Some("[vvar]"))
+    else {
+        return Ok(());
+    };
+    let page = vvar.start;
+    let Ok((mapped, ..)) = aspace.page_table().query(page) else {
+        return Ok(());
+    };
+    let frame = ns.vdso_page().map_err(|_| AxError::NoMemory)?;
+    let paddr = frame
+        .as_ref()
+        .map_or(vdso.timens.paddr(), |frame| frame.paddr());
+    if mapped == paddr {
+        return Ok(());
+    }
+    aspace.unmap(page, PAGE_SIZE_4K)?;
+    tlb::flush(aspace, page, PAGE_SIZE_4K);
+    forget_frames(key, page, PAGE_SIZE_4K);
+    aspace.map_linear(
+        page,
+        paddr,
+        PAGE_SIZE_4K,
+        MappingFlags::READ | MappingFlags::USER,
+    )?;
+    // The frame stays allocated as long as it is mapped here
+    if let Some(frame) = frame {
+        SHARED_FRAMES.lock().insert((key, page.as_usize()), frame);
+    }
+    Ok(())
+}
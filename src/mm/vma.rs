@@ -0,0 +1,259 @@
+//! The areas mapped in the address space of a process, with what they were
+//! mapped from.
+//!
+//! The address space only knows pages and the flags of their entries. A
+//! [`VmArea`] keeps what was asked for when they were mapped: the protection,
+//! the `mmap` flags, and the file and offset behind the pages, for `msync`
+//! and `/proc/<pid>/maps`.
+//!
+//! The table belongs to the address space: the processes sharing one, after
+//! a `clone` with `CLONE_VM`, share the table too. Whoever maps or unmaps
+//! pages updates it while holding the lock of the address space. Unmapping
+//! or reprotecting part of an area splits it, and contiguous areas which end
+//! up alike are merged.
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use axhal::paging::MappingFlags;
+use core::fmt;
+use memory_addr::VirtAddr;
+
+/// `MAP_SHARED`, as in the flags of `mmap`.
+pub const MAP_SHARED: u32 = 0x01;
+/// `MAP_PRIVATE`, as in the flags of `mmap`.
+pub const MAP_PRIVATE: u32 = 0x02;
+/// `MAP_ANONYMOUS`, as in the flags of `mmap`.
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+/// The column of `/proc/<pid>/maps` where the path starts, as on Linux.
+const PATH_COLUMN: usize = 73;
+
+/// A range of pages mapped at once, or what is left of it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct VmArea {
+    /// The first address, page aligned
+    pub start: VirtAddr,
+    /// The address past the end, page aligned
+    pub end: VirtAddr,
+    /// Which of `READ`, `WRITE` and `EXECUTE` the pages have
+    pub prot: MappingFlags,
+    /// The `MAP_*` flags it was mapped with, without the placement ones
+    pub flags: u32,
+    /// The path of the regular file mapped, whose pages come from the page
+    /// cache
+    pub file: Option<String>,
+    /// The offset in the file of the page at `start`
+    pub offset: u64,
+    /// What `/proc/<pid>/maps` shows for an area without a regular file
+    /// behind it, e.g. `[heap]`
+    pub name: Option<String>,
+    /// Whether `mlock` keeps the pages in memory
+    pub locked: bool,
+}
+
+/// The bits of `flags` an area keeps.
+fn prot_of(flags: MappingFlags) -> MappingFlags {
+    flags & (MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE)
+}
+
+impl VmArea {
+    /// An area of `[start, end)` mapped with `prot` and the `mmap` `flags`,
+    /// without a file or a name.
+    pub fn new(start: VirtAddr, end: VirtAddr, prot: MappingFlags, flags: u32) -> Self {
+        Self {
+            start,
+            end,
+            prot: prot_of(prot),
+            flags,
+            file: None,
+            offset: 0,
+            name: None,
+            locked: false,
+        }
+    }
+
+    /// The area with the regular file `path` mapped from `offset`.
+    pub fn with_file(mut self, path: String, offset: u64) -> Self {
+        self.file = Some(path);
+        self.offset = offset;
+        self
+    }
+
+    /// The area with `name` shown in `/proc/<pid>/maps`, and `offset` there.
+    pub fn with_name(mut self, name: impl Into<String>, offset: u64) -> Self {
+        self.name = Some(name.into());
+        self.offset = offset;
+        self
+    }
+
+    /// Whether it was mapped with `MAP_SHARED`.
+    pub fn is_shared(&self) -> bool {
+        self.flags & MAP_SHARED != 0
+    }
+
+    /// Whether it was mapped with `MAP_ANONYMOUS`, its offset meaningless.
+    pub fn is_anonymous(&self) -> bool {
+        self.flags & MAP_ANONYMOUS != 0
+    }
+
+    /// Whether the area overlaps `[start, start + len)`.
+    pub fn overlaps(&self, start: VirtAddr, len: usize) -> bool {
+        self.start < start + len && start < self.end
+    }
+
+    /// Keep `[start, addr)` and return `[addr, end)`.
+    fn split_off(&mut self, addr: VirtAddr) -> Self {
+        let mut tail = self.clone();
+        tail.start = addr;
+        if !self.is_anonymous() {
+            tail.offset += (addr - self.start) as u64;
+        }
+        self.end = addr;
+        tail
+    }
+
+    /// Whether `next` can be merged into the area.
+    fn mergeable(&self, next: &Self) -> bool {
+        self.end == next.start
+            && self.prot == next.prot
+            && self.flags == next.flags
+            && self.file == next.file
+            && self.name == next.name
+            && self.locked == next.locked
+            && (self.is_anonymous() || self.offset + (self.end - self.start) as u64 == next.offset)
+    }
+}
+
+/// The line of `/proc/<pid>/maps` for the area.
+impl fmt::Display for VmArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let perm = |flag, c| if self.prot.contains(flag) { c } else { '-' };
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 0 ",
+            self.start.as_usize(),
+            self.end.as_usize(),
+            perm(MappingFlags::READ, 'r'),
+            perm(MappingFlags::WRITE, 'w'),
+            perm(MappingFlags::EXECUTE, 'x'),
+            if self.is_shared() { 's' } else { 'p' },
+            self.offset,
+        );
+        match self.file.as_deref().or(self.name.as_deref()) {
+            Some(path) => write!(f, "{:<width$}{}", line, path, width = PATH_COLUMN),
+            None => f.write_str(&line),
+        }
+    }
+}
+
+/// The areas of an address space, by start address.
+#[derive(Clone, Default)]
+pub struct VmAreas {
+    areas: BTreeMap<usize, VmArea>,
+}
+
+impl VmAreas {
+    /// The areas, from the lowest.
+    pub fn iter(&self) -> impl Iterator<Item = &VmArea> {
+        self.areas.values()
+    }
+
+    /// The areas overlapping `[start, start + len)`, from the lowest.
+    pub fn overlapping(&self, start: VirtAddr, len: usize) -> impl Iterator<Item = &VmArea> {
+        // The area starting last before `start` may reach into the range
+        let first = self
+            .areas
+            .range(..=start.as_usize())
+            .next_back()
+            .map_or(start.as_usize(), |(&key, _)| key);
+        self.areas
+            .range(first..(start + len).as_usize())
+            .map(|(_, area)| area)
+            .filter(move |area| area.end > start)
+    }
+
+    /// Record `area`, mapped in place of whatever was in its range.
+    pub fn insert(&mut self, area: VmArea) {
+        let (start, end) = (area.start, area.end);
+        self.remove(start, end - start);
+        self.areas.insert(start.as_usize(), area);
+        self.merge(start, end);
+    }
+
+    /// Forget `[start, start + len)`, once unmapped.
+    pub fn remove(&mut self, start: VirtAddr, len: usize) {
+        let end = start + len;
+        self.split_at(start);
+        self.split_at(end);
+        let mut removed = self.areas.split_off(&start.as_usize());
+        let mut after = removed.split_off(&end.as_usize());
+        self.areas.append(&mut after);
+    }
+
+    /// Set the protection of what is mapped in `[start, start + len)`.
+    pub fn protect(&mut self, start: VirtAddr, len: usize, prot: MappingFlags) {
+        self.update(start, len, |area| area.prot = prot_of(prot));
+    }
+
+    /// Set whether what is mapped in `[start, start + len)` is locked in
+    /// memory.
+    pub fn set_locked(&mut self, start: VirtAddr, len: usize, locked: bool) {
+        self.update(start, len, |area| area.locked = locked);
+    }
+
+    /// Apply `f` to what is mapped in `[start, start + len)`, splitting the
+    /// areas across its ends.
+    fn update(&mut self, start: VirtAddr, len: usize, f: impl Fn(&mut VmArea)) {
+        let end = start + len;
+        self.split_at(start);
+        self.split_at(end);
+        for (_, area) in self.areas.range_mut(start.as_usize()..end.as_usize()) {
+            f(area);
+        }
+        self.merge(start, end);
+    }
+
+    /// Forget all the areas, when the address space is cleared.
+    pub fn clear(&mut self) {
+        self.areas.clear();
+    }
+
+    /// Split the area across `addr`, if any, so that one starts there.
+    fn split_at(&mut self, addr: VirtAddr) {
+        let Some((_, area)) = self.areas.range_mut(..addr.as_usize()).next_back() else {
+            return;
+        };
+        if area.end > addr {
+            let tail = area.split_off(addr);
+            self.areas.insert(addr.as_usize(), tail);
+        }
+    }
+
+    /// Merge the areas alike in `[start, end)` and next to it.
+    fn merge(&mut self, start: VirtAddr, end: VirtAddr) {
+        let first = self
+            .areas
+            .range(..start.as_usize())
+            .next_back()
+            .map_or(start.as_usize(), |(&key, _)| key);
+        let keys: Vec<usize> = self
+            .areas
+            .range(first..=end.as_usize())
+            .map(|(&key, _)| key)
+            .collect();
+        let mut keys = keys.into_iter();
+        let Some(mut head) = keys.next() else {
+            return;
+        };
+        for key in keys {
+            let next = self.areas.remove(&key).unwrap();
+            let area = self.areas.get_mut(&head).unwrap();
+            if area.mergeable(&next) {
+                area.end = next.end;
+            } else {
+                self.areas.insert(key, next);
+                head = key;
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! The shared zero page behind untouched anonymous memory.
+//!
+//! A read fault on a page of a private anonymous area which was never touched
+//! maps the global zero page there, read-only, with the protection of the
+//! area, instead of a frame of its own. The first write then faults again and
+//! gets a private zeroed frame from the area. A program with a large sparse
+//! heap, which it mostly reads, thus only pays for the pages it writes to.
+//!
+//! The zero page is written into the page table directly, with [`pte`]: the
+//! area of `axmm` stays whole, and nothing is allocated for a read. The page
+//! table itself tells which pages are on the zero page, so its entries must be
+//! released with [`release`] before their range is unmapped, or the area
+//! would free the zero page with its own frames.
+use super::{pte, rss, tlb, Frame, VmAreas};
+use alloc::sync::Arc;
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use lazy_static::lazy_static;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+lazy_static! {
+    static ref ZERO_FRAME: Arc<Frame> = Frame::alloc().expect("failed to allocate the zero page");
+}
+
+/// Handle a fault at `vaddr` of the address space `key`, whose areas are
+/// `areas`, if the zero page is involved: map it for a read of an untouched
+/// page, or replace it with a private frame for a write. Returns whether the
+/// fault was handled.
+pub fn handle_fault(
+    key: usize,
+    aspace: &mut AddrSpace,
+    areas: &VmAreas,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    let page = vaddr.align_down_4k();
+    let Some(area) = areas
+        .overlapping(page, PAGE_SIZE_4K)
+        .find(|area| !area.is_shared() && area.is_anonymous())
+    else {
+        return false;
+    };
+    match aspace.page_table().query(page) {
+        Ok((paddr, _, _)) if paddr == ZERO_FRAME.paddr() => {
+            // A write the area doesn't allow is a genuine fault
+            if !access_flags.contains(MappingFlags::WRITE)
+                || !area.prot.contains(MappingFlags::WRITE)
+            {
+                return false;
+            }
+            release(aspace, page, PAGE_SIZE_4K);
+            // The page is an untouched one of the area again
+            let handled = aspace.handle_page_fault(page, access_flags);
+            tlb::flush(aspace, page, PAGE_SIZE_4K);
+            if handled {
+                rss::charge(key, 1);
+            }
+            handled
+        }
+        Ok(_) => false,
+        Err(_) if access_flags == MappingFlags::READ && area.prot.contains(MappingFlags::READ) => {
+            let flags = (area.prot - MappingFlags::WRITE) | MappingFlags::USER;
+            // Without a table for the page yet, the area allocates it
+            let mapped = pte::map(aspace.page_table_root(), page, ZERO_FRAME.paddr(), flags);
+            if mapped {
+                tlb::flush(aspace, page, PAGE_SIZE_4K);
+            }
+            mapped
+        }
+        Err(_) => false,
+    }
+}
+
+/// Unmap the zero page from `[start, start + len)` of `aspace`, right before
+/// the range is unmapped, or the address space cleared or dropped. The caller
+/// flushes the TLB once done.
+pub fn release(aspace: &AddrSpace, start: VirtAddr, len: usize) {
+    pte::unmap_all(aspace.page_table_root(), start, len, ZERO_FRAME.paddr());
+}
+
+/// Unmap the zero page from the whole of `aspace`.
+pub fn release_all(aspace: &AddrSpace) {
+    release(aspace, aspace.base(), aspace.size());
+}
+
+/// Whether the frame at `paddr` is the zero page.
+pub(super) fn is_zero_frame(paddr: PhysAddr) -> bool {
+    paddr == ZERO_FRAME.paddr()
+}
+
+/// Apply a change of protection to `flags` of `[start, start + len)` to the
+/// pages in the range on the zero page, which must stay read-only. Those left
+/// unreadable are unmapped, to be faulted in again.
+pub fn protect(aspace: &AddrSpace, start: VirtAddr, len: usize, flags: MappingFlags) {
+    let flags = (flags - MappingFlags::WRITE) | MappingFlags::USER;
+    pte::protect_all(
+        aspace.page_table_root(),
+        start,
+        len,
+        ZERO_FRAME.paddr(),
+        flags,
+    );
+}
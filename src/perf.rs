@@ -0,0 +1,385 @@
+//! A subset of `perf_event_open(2)`: counting CPU cycles and retired
+//! instructions of a thread with the hardware counters.
+//!
+//! Without preemption a thread only leaves its CPU from the kernel, so an
+//! event counts the thread while it runs in user space: it is paused when
+//! the thread enters a syscall or a page fault, and resumed when it returns
+//! to user space. The counts are those of `exclude_kernel` on Linux.
+use alloc::sync::Arc;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+use axtask::TaskExtRef;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use core::sync::atomic::{AtomicBool, Ordering};
+use linkme::distributed_slice;
+
+pub const PERF_TYPE_HARDWARE: u32 = 0;
+pub const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+pub const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+
+pub const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+pub const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+const PERF_EVENT_IOC_ENABLE: usize = 0x2400;
+const PERF_EVENT_IOC_DISABLE: usize = 0x2401;
+const PERF_EVENT_IOC_RESET: usize = 0x2403;
+
+/// The hardware counters which can be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfCounter {
+    Cycles,
+    Instructions,
+}
+
+impl PerfCounter {
+    /// The counter selected by `perf_event_attr.config` of a hardware event.
+    pub fn from_config(config: u64) -> LinuxResult<Self> {
+        let counter = match config {
+            PERF_COUNT_HW_CPU_CYCLES => PerfCounter::Cycles,
+            PERF_COUNT_HW_INSTRUCTIONS => PerfCounter::Instructions,
+            _ => return Err(LinuxError::ENOENT),
+        };
+        counter.read().map(|_| counter)
+    }
+
+    /// The current value of the counter on this CPU, or `ENOENT` if the CPU
+    /// can't count it.
+    fn read(self) -> LinuxResult<u64> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "riscv64")] {
+                let value: u64;
+                unsafe {
+                    match self {
+                        PerfCounter::Cycles => core::arch::asm!("rdcycle {}", out(reg) value),
+                        PerfCounter::Instructions => {
+                            core::arch::asm!("rdinstret {}", out(reg) value)
+                        }
+                    }
+                }
+                Ok(value)
+            } else if #[cfg(target_arch = "x86_64")] {
+                match self {
+                    PerfCounter::Cycles => Ok(unsafe { core::arch::x86_64::_rdtsc() }),
+                    PerfCounter::Instructions => {
+                        start_pmu()?;
+                        Ok(unsafe { x86::msr::rdmsr(x86::msr::IA32_FIXED_CTR0) })
+                    }
+                }
+            } else if #[cfg(target_arch = "aarch64")] {
+                start_pmu()?;
+                let value: u64;
+                unsafe {
+                    match self {
+                        PerfCounter::Cycles => {
+                            core::arch::asm!("mrs {}, pmccntr_el0", out(reg) value)
+                        }
+                        PerfCounter::Instructions => {
+                            core::arch::asm!("mrs {}, pmevcntr0_el0", out(reg) value)
+                        }
+                    }
+                }
+                Ok(value)
+            } else {
+                Err(LinuxError::ENOENT)
+            }
+        }
+    }
+
+    /// The count between two values of the counter, which wraps at its width.
+    fn delta(self, start: u64, end: u64) -> u64 {
+        let bits = match self {
+            #[cfg(target_arch = "x86_64")]
+            PerfCounter::Instructions => FIXED_CTR_BITS,
+            #[cfg(target_arch = "aarch64")]
+            PerfCounter::Instructions => 32,
+            _ => 64,
+        };
+        end.wrapping_sub(start) & (u64::MAX >> (64 - bits))
+    }
+}
+
+/// Whether the counters which have to be set up are running on each CPU.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+static PMU_STARTED: [AtomicBool; axconfig::SMP] = [const { AtomicBool::new(false) }; axconfig::SMP];
+
+/// The width of the fixed counters of the architectural performance
+/// monitoring, which are 48 bits on every Intel CPU having them.
+#[cfg(target_arch = "x86_64")]
+const FIXED_CTR_BITS: u32 = 48;
+
+/// Start the counters on this CPU if they aren't yet, or fail with `ENOENT`
+/// if it has none.
+///
+/// On x86_64 the retired instructions are the fixed counter 0 of the
+/// architectural performance monitoring, version 2 or later.
+#[cfg(target_arch = "x86_64")]
+fn start_pmu() -> LinuxResult {
+    use core::arch::x86_64::__cpuid;
+    use x86::msr::{rdmsr, wrmsr, IA32_FIXED_CTR_CTRL, IA32_PERF_GLOBAL_CTRL};
+
+    let cpu = axhal::cpu::this_cpu_id();
+    if PMU_STARTED[cpu].load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    // SAFETY: CPUID is available on every x86_64 CPU
+    let (max_leaf, pmu) = unsafe { (__cpuid(0).eax, __cpuid(0xa)) };
+    let version = pmu.eax & 0xff;
+    let fixed_counters = pmu.edx & 0x1f;
+    let fixed_bits = (pmu.edx >> 5) & 0xff;
+    if max_leaf < 0xa || version < 2 || fixed_counters < 1 || fixed_bits != FIXED_CTR_BITS {
+        return Err(LinuxError::ENOENT);
+    }
+    // SAFETY: the MSRs exist with version 2, and only the kernel uses them.
+    // Count in both rings, the events are paused from the kernel.
+    unsafe {
+        wrmsr(IA32_FIXED_CTR_CTRL, rdmsr(IA32_FIXED_CTR_CTRL) | 0b11);
+        wrmsr(
+            IA32_PERF_GLOBAL_CTRL,
+            rdmsr(IA32_PERF_GLOBAL_CTRL) | 1 << 32,
+        );
+    }
+    PMU_STARTED[cpu].store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Start the counters on this CPU if they aren't yet, or fail with `ENOENT`
+/// if it has no PMU.
+///
+/// On aarch64 the cycles are the 64-bit cycle counter of PMUv3, and the
+/// retired instructions (event `INST_RETIRED`) the 32-bit event counter 0.
+#[cfg(target_arch = "aarch64")]
+fn start_pmu() -> LinuxResult {
+    const PMCR_E: u64 = 1 << 0;
+    const PMCR_LC: u64 = 1 << 6;
+    const INST_RETIRED: u64 = 0x08;
+
+    let cpu = axhal::cpu::this_cpu_id();
+    if PMU_STARTED[cpu].load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let dfr0: u64;
+    // SAFETY: the ID registers can always be read at EL1
+    unsafe { core::arch::asm!("mrs {}, id_aa64dfr0_el1", out(reg) dfr0) };
+    let pmu_version = (dfr0 >> 8) & 0xf;
+    if pmu_version == 0 || pmu_version == 0xf {
+        return Err(LinuxError::ENOENT);
+    }
+    // SAFETY: the CPU has PMUv3, whose counters only the kernel uses
+    unsafe {
+        core::arch::asm!(
+            "msr pmevtyper0_el0, {event}",
+            "msr pmcntenset_el0, {enable}",
+            "mrs {pmcr}, pmcr_el0",
+            "orr {pmcr}, {pmcr}, {flags}",
+            "msr pmcr_el0, {pmcr}",
+            "isb",
+            event = in(reg) INST_RETIRED,
+            enable = in(reg) (1u64 << 31) | 1,
+            flags = in(reg) PMCR_E | PMCR_LC,
+            pmcr = out(reg) _,
+        );
+    }
+    PMU_STARTED[cpu].store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+struct PerfState {
+    enabled: bool,
+    /// The thread runs in user space with the event enabled, counting from
+    /// `start` on the CPU it runs on
+    running: bool,
+    /// The count accumulated over the previous running periods
+    count: u64,
+    /// The counter value when the event last started running
+    start: u64,
+    /// Nanoseconds enabled over the previous enabled periods
+    time_enabled: u64,
+    /// The time in nanoseconds when the event was last enabled
+    enable_time: u64,
+    /// Nanoseconds running over the previous running periods
+    time_running: u64,
+    /// The time in nanoseconds when the event last started running
+    start_time: u64,
+}
+
+/// An open counting event of a thread.
+pub struct PerfEvent {
+    counter: PerfCounter,
+    read_format: u64,
+    state: Mutex<PerfState>,
+}
+
+impl PerfEvent {
+    pub fn new(counter: PerfCounter, read_format: u64, disabled: bool) -> Self {
+        Self {
+            counter,
+            read_format,
+            state: Mutex::new(PerfState {
+                enabled: !disabled,
+                running: false,
+                count: 0,
+                start: 0,
+                time_enabled: 0,
+                enable_time: axhal::time::monotonic_time_nanos(),
+                time_running: 0,
+                start_time: 0,
+            }),
+        }
+    }
+
+    /// Start counting, on the return of the thread to user space.
+    fn resume(&self) {
+        let mut state = self.state.lock();
+        if state.enabled && !state.running {
+            if let Ok(value) = self.counter.read() {
+                state.start = value;
+                state.start_time = axhal::time::monotonic_time_nanos();
+                state.running = true;
+            }
+        }
+    }
+
+    /// Stop counting, when the thread enters the kernel.
+    fn pause(&self) {
+        let mut state = self.state.lock();
+        if state.running {
+            let value = self.counter.read().unwrap_or(state.start);
+            state.count += self.counter.delta(state.start, value);
+            state.time_running += axhal::time::monotonic_time_nanos() - state.start_time;
+            state.running = false;
+        }
+    }
+
+    fn enable(&self) {
+        let mut state = self.state.lock();
+        if !state.enabled {
+            state.enable_time = axhal::time::monotonic_time_nanos();
+            state.enabled = true;
+        }
+    }
+
+    /// Disable the event. If another thread than the counted one disables
+    /// it, the count stops when the counted thread next enters the kernel.
+    fn disable(&self) {
+        let mut state = self.state.lock();
+        if state.enabled {
+            state.time_enabled += axhal::time::monotonic_time_nanos() - state.enable_time;
+            state.enabled = false;
+        }
+    }
+
+    fn reset(&self) {
+        let mut state = self.state.lock();
+        state.count = 0;
+        state.time_running = 0;
+    }
+
+    /// The count and the nanoseconds the event has been enabled and running.
+    ///
+    /// The counted thread is in the kernel when it reads its own event, so
+    /// the count is exact. Another thread gets the count as of the last time
+    /// the counted one entered the kernel.
+    fn snapshot(&self) -> (u64, u64, u64) {
+        let state = self.state.lock();
+        let mut time_enabled = state.time_enabled;
+        if state.enabled {
+            time_enabled += axhal::time::monotonic_time_nanos() - state.enable_time;
+        }
+        (state.count, time_enabled, state.time_running)
+    }
+
+    /// Handle the `PERF_EVENT_IOC_*` requests.
+    pub fn ioctl(&self, op: usize) -> LinuxResult<isize> {
+        match op {
+            PERF_EVENT_IOC_ENABLE => self.enable(),
+            PERF_EVENT_IOC_DISABLE => self.disable(),
+            PERF_EVENT_IOC_RESET => self.reset(),
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+/// Pause the events counting the current thread, on the entry of a syscall
+/// or of a user page fault.
+pub fn leave_user() {
+    axtask::current()
+        .task_ext()
+        .for_each_perf_event(PerfEvent::pause);
+}
+
+/// Resume the events counting the current thread on its way back to user
+/// space.
+#[distributed_slice(axhal::arch::HANDLE_SIGNAL)]
+pub fn enter_user() {
+    let task = axtask::current();
+    if unsafe { task.task_ext_ptr().is_null() } {
+        return;
+    }
+    task.task_ext().for_each_perf_event(PerfEvent::resume);
+}
+
+impl api::FileLike for PerfEvent {
+    /// Read the count, followed by the time enabled and running if asked for
+    /// by `read_format`. An event runs while its thread is in user space.
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let (count, time_enabled, time_running) = self.snapshot();
+        let mut values = [count, 0, 0];
+        let mut len = 1;
+        for (format, time) in [
+            (PERF_FORMAT_TOTAL_TIME_ENABLED, time_enabled),
+            (PERF_FORMAT_TOTAL_TIME_RUNNING, time_running),
+        ] {
+            if self.read_format & format != 0 {
+                values[len] = time;
+                len += 1;
+            }
+        }
+        let size = len * core::mem::size_of::<u64>();
+        if buf.len() < size {
+            return Err(LinuxError::ENOSPC);
+        }
+        for (chunk, value) in buf.chunks_exact_mut(8).zip(&values[..len]) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        Ok(size)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(ctypes::stat {
+            st_mode: 0o600,
+            st_nlink: 1,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// The perf event opened as `fd`, if it is one.
+pub fn perf_event_from_fd(fd: i32) -> Option<Arc<PerfEvent>> {
+    api::get_file_like(fd)
+        .ok()?
+        .into_any()
+        .downcast::<PerfEvent>()
+        .ok()
+}
@@ -1,16 +1,29 @@
-use crate::flag::WaitStatus;
+use crate::flag::{WaitOptions, WaitStatus};
+use crate::mm::VmAreas;
+use crate::process::events::{self, ProcessEvent};
 use crate::process::{AxProcessRef, Process};
-use alloc::collections::BTreeMap;
+use crate::signal::info::SigInfo;
+use crate::signal::signal_no::SignalNo;
+use crate::sync::Rcu;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use axmm::AddrSpace;
 use axsync::Mutex;
 use axtask::{current, TaskExtRef};
+use core::sync::atomic::Ordering;
 use lazy_static::lazy_static;
 
+/// The processes by pid.
+///
+/// Lookups happen on every signal and every wait, while processes come and
+/// go far less often, so readers go through [`Rcu`] without any lock and
+/// each change copies the table.
 struct ProcessManager {
-    inner: Mutex<ProcessManagerInner>,
+    inner: Rcu<ProcessManagerInner>,
 }
 
+#[derive(Clone)]
 struct ProcessManagerInner {
     processes: BTreeMap<u64, AxProcessRef>,
 }
@@ -26,43 +39,48 @@ impl ProcessManagerInner {
         }
     }
 
-    fn new_process(&mut self, ppid: u64, pid: u64, aspace: Arc<Mutex<AddrSpace>>) -> AxProcessRef {
-        let process = Arc::new(Process::new(ppid, pid, aspace));
-        let pid = process.pid;
-        self.processes.insert(pid, process.clone());
-        process
-    }
-
     fn get_process(&self, pid: u64) -> Option<AxProcessRef> {
         self.processes.get(&pid).cloned()
     }
-
-    fn remove_process(&mut self, pid: u64) {
-        self.processes.remove(&pid);
-    }
 }
 
 impl ProcessManager {
     fn new() -> Self {
         Self {
-            inner: Mutex::new(ProcessManagerInner::new()),
+            inner: Rcu::new(ProcessManagerInner::new()),
         }
     }
 }
 
+/// Remove an exiting process from the table. Its pid stays allocated until
+/// the process goes away, after it has been reaped.
 pub fn remove_process(pid: u64) {
-    let mut inner = PID2PROC.inner.lock();
-    inner.remove_process(pid);
+    PID2PROC.inner.update(|inner| inner.processes.remove(&pid));
 }
 
-pub fn new_process(ppid: u64, pid: u64, aspace: Arc<Mutex<AddrSpace>>) -> AxProcessRef {
-    let mut inner = PID2PROC.inner.lock();
-    inner.new_process(ppid, pid, aspace)
+pub fn new_process(
+    ppid: u64,
+    pid: u64,
+    aspace: Arc<Mutex<AddrSpace>>,
+    vm_areas: Arc<Mutex<VmAreas>>,
+) -> AxProcessRef {
+    let process = Arc::new(Process::new(ppid, pid, aspace, vm_areas));
+    PID2PROC
+        .inner
+        .update(|inner| inner.processes.insert(process.pid, process.clone()));
+    events::emit(ProcessEvent::Created { pid, ppid });
+    process
 }
 
 pub fn get_process(pid: u64) -> Option<AxProcessRef> {
-    let inner = PID2PROC.inner.lock();
-    inner.get_process(pid)
+    PID2PROC.inner.read(|inner| inner.get_process(pid))
+}
+
+/// All the processes which haven't exited yet.
+pub fn all_processes() -> Vec<AxProcessRef> {
+    PID2PROC
+        .inner
+        .read(|inner| inner.processes.values().cloned().collect())
 }
 
 pub fn current_process() -> Option<AxProcessRef> {
@@ -71,82 +89,244 @@ pub fn current_process() -> Option<AxProcessRef> {
     proc
 }
 
-pub(crate) fn wait_pid(pid: i32, exit_code_ptr: *mut i32, _option: u32) -> Result<u64, WaitStatus> {
-    if pid <= 0 {
-        return wait_pid_negative(pid, exit_code_ptr, _option);
+/// 子进程的一次状态变化，由 wait4 与 waitid 报告
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildState {
+    /// 以退出码退出
+    Exited(i32),
+    /// 被信号终止
+    Killed(u32),
+    /// 被信号停止
+    Stopped(u32),
+    /// 被 SIGCONT 继续执行
+    Continued,
+}
+
+/// SIGCHLD 的 si_code：子进程退出
+pub const CLD_EXITED: i32 = 1;
+/// SIGCHLD 的 si_code：子进程被信号终止
+pub const CLD_KILLED: i32 = 2;
+/// SIGCHLD 的 si_code：子进程被停止
+pub const CLD_STOPPED: i32 = 5;
+/// SIGCHLD 的 si_code：子进程被继续执行
+pub const CLD_CONTINUED: i32 = 6;
+
+impl ChildState {
+    /// wait4 写出的状态字，即 `WIFEXITED` 等宏解析的值
+    pub fn wait_status(self) -> i32 {
+        match self {
+            Self::Exited(code) => (code & 0xff) << 8,
+            Self::Killed(signal) => signal as i32 & 0x7f,
+            Self::Stopped(signal) => ((signal as i32) << 8) | 0x7f,
+            Self::Continued => 0xffff,
+        }
     }
 
-    let curr_task = current();
-    let proc = curr_task.task_ext().get_proc().unwrap();
-    let mut proc_status = WaitStatus::NotExist;
-
-    let child = proc
-        .children
-        .lock()
-        .iter()
-        .enumerate()
-        .find(|(_id, child)| child.pid as i32 == pid)
-        .map(|(id, child)| (id, child.clone()));
-
-    let Some((loc, child)) = child else {
-        return Err(WaitStatus::NotExist);
-    };
+    /// 报告这次变化的 SIGCHLD 的 si_code 与 si_status
+    pub fn code_and_status(self) -> (i32, i32) {
+        match self {
+            Self::Exited(code) => (CLD_EXITED, code & 0xff),
+            Self::Killed(signal) => (CLD_KILLED, signal as i32),
+            Self::Stopped(signal) => (CLD_STOPPED, signal as i32),
+            Self::Continued => (CLD_CONTINUED, SignalNo::SIGCONT as i32),
+        }
+    }
+}
 
-    let state = child.state();
-    if state == axtask::TaskState::Running {
-        proc_status = WaitStatus::Running;
-    } else if state == axtask::TaskState::Exited {
-        let exit_code = child.exit_code();
+/// 等待到的子进程及其状态变化
+#[derive(Debug, Clone, Copy)]
+pub struct WaitResult {
+    /// 子进程的全局 pid
+    pub pid: u64,
+    /// 子进程的真实用户 ID
+    pub uid: u32,
+    /// 状态变化
+    pub state: ChildState,
+}
 
-        if !exit_code_ptr.is_null() {
-            unsafe {
-                *exit_code_ptr = exit_code << 8;
-            }
+impl WaitResult {
+    /// 描述这次变化的 SIGCHLD 信息，pid 为全局 pid
+    pub fn siginfo(&self) -> SigInfo {
+        let (si_code, status) = self.state.code_and_status();
+        SigInfo {
+            si_signo: SignalNo::SIGCHLD as i32,
+            si_code,
+            pid: self.pid as i32,
+            uid: self.uid,
+            // SIGCHLD 的 si_status 与 si_value 位于同一位置
+            si_val_int: status,
+            ..Default::default()
+        }
+    }
+}
+
+/// 进程的子进程
+///
+/// 以 pid 为键保存，按 pid 等待时直接查找；已退出、尚未回收的子进程另按退出的顺序排队，
+/// 等待任意子进程退出时从队首取得，不必遍历所有子进程。
+#[derive(Default)]
+pub struct Children {
+    /// pid -> 子进程
+    procs: BTreeMap<u64, AxProcessRef>,
+    /// 已退出、尚未回收的子进程的 pid，按退出的顺序
+    exited: VecDeque<u64>,
+}
+
+impl Children {
+    pub fn get(&self, pid: u64) -> Option<&AxProcessRef> {
+        self.procs.get(&pid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AxProcessRef> {
+        self.procs.values()
+    }
+
+    /// 符合 `target` 的子进程，按 pid 选择时直接查找
+    pub fn matching(&self, target: WaitTarget) -> impl Iterator<Item = &AxProcessRef> {
+        let pids = match target {
+            WaitTarget::Pid(pid) => pid..=pid,
+            _ => 0..=u64::MAX,
+        };
+        self.procs
+            .range(pids)
+            .map(|(_, child)| child)
+            .filter(move |child| target.matches(child))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.procs.is_empty()
+    }
+
+    pub fn insert(&mut self, child: AxProcessRef) {
+        if child.exit_state().is_some() {
+            self.exited.push_back(child.pid);
         }
+        self.procs.insert(child.pid, child);
+    }
+
+    /// 移除子进程 `pid`，即回收它
+    pub fn remove(&mut self, pid: u64) -> Option<AxProcessRef> {
+        let child = self.procs.remove(&pid)?;
+        // 通常回收的就是最早退出的子进程
+        if self.exited.front() == Some(&pid) {
+            self.exited.pop_front();
+        } else {
+            self.exited.retain(|&exited| exited != pid);
+        }
+        Some(child)
+    }
 
-        let child_task = proc.children.lock().remove(loc);
-        curr_task.add_child_time(&child_task.main_thread());
+    /// 记下子进程 `pid` 已退出，等待回收
+    pub fn set_exited(&mut self, pid: u64) {
+        if self.procs.contains_key(&pid) && !self.exited.contains(&pid) {
+            self.exited.push_back(pid);
+        }
+    }
 
-        return Ok(child_task.pid);
+    /// 已退出、尚未回收的子进程，按退出的顺序
+    pub fn exited(&self) -> impl Iterator<Item = &AxProcessRef> {
+        self.exited.iter().map(|pid| &self.procs[pid])
     }
 
-    Err(proc_status)
+    /// 移除全部子进程，用于父进程退出时交给收养者
+    pub fn take_all(&mut self) -> Vec<AxProcessRef> {
+        self.exited.clear();
+        core::mem::take(&mut self.procs).into_values().collect()
+    }
+}
+
+/// wait 选择的子进程
+#[derive(Debug, Clone, Copy)]
+pub enum WaitTarget {
+    /// 全局 pid 为此值的子进程
+    Pid(u64),
+    /// 进程组中的子进程
+    Pgid(u64),
+    /// 任意子进程
+    Any,
 }
 
-fn wait_pid_negative(pid: i32, exit_code_ptr: *mut i32, _option: u32) -> Result<u64, WaitStatus> {
-    assert!(pid <= 0);
+impl WaitTarget {
+    fn matches(self, child: &Process) -> bool {
+        match self {
+            Self::Pid(pid) => child.pid == pid,
+            Self::Pgid(pgid) => child.pgid() == pgid,
+            Self::Any => true,
+        }
+    }
+}
 
-    if pid == 0 {
-        warn!("wait process group is not implemented");
+/// 子进程是否在 `options` 的等待范围内：默认只等待退出信号为 SIGCHLD 的子进程，
+/// `__WCLONE` 只等待其他子进程，`__WALL` 等待全部子进程
+fn eligible(child: &Process, options: WaitOptions) -> bool {
+    if options.contains(WaitOptions::__WALL) {
+        return true;
     }
+    let sigchld = child.exit_signal.load(Ordering::Relaxed) == SignalNo::SIGCHLD as u32;
+    sigchld != options.contains(WaitOptions::__WCLONE)
+}
 
+/// 在当前进程的子进程中找一个符合 `target` 且有 `options` 所选状态变化的子进程。
+///
+/// 退出的子进程被回收，停止或继续只报告一次；带 `WNOWAIT` 时二者都保留。
+/// 没有符合 `target` 的子进程时返回 `NotExist`，有但尚无状态变化时返回 `Running`。
+pub(crate) fn wait_child(
+    target: WaitTarget,
+    options: WaitOptions,
+) -> Result<WaitResult, WaitStatus> {
     let curr_task = current();
     let proc = curr_task.task_ext().get_proc().unwrap();
-    let mut proc_status = WaitStatus::NotExist;
-    let mut child_id = 0;
-
-    for (id, task) in proc.children.lock().iter().enumerate() {
-        proc_status = WaitStatus::Running;
-        if task.state() == axtask::TaskState::Exited {
-            proc_status = WaitStatus::Exited;
-            child_id = id;
-            break;
+    let keep = options.contains(WaitOptions::WNOWAIT);
+    let mut children = proc.children.lock();
+    let result = |child: &AxProcessRef, state| WaitResult {
+        pid: child.pid,
+        uid: child.cred().uid,
+        state,
+    };
+
+    // 退出的子进程按退出的顺序取得，通常就在队首
+    let exited = match target {
+        WaitTarget::Pid(_) => children
+            .matching(target)
+            .find(|child| child.exit_state().is_some() && eligible(child, options)),
+        _ => children
+            .exited()
+            .find(|child| target.matches(child) && eligible(child, options)),
+    };
+    if let Some(child) = exited.filter(|_| options.contains(WaitOptions::WEXITED)) {
+        let res = result(child, child.exit_state().unwrap());
+        if !keep {
+            let child = children.remove(res.pid).unwrap();
+            curr_task.add_child_time(&child.main_thread());
         }
+        return Ok(res);
     }
 
-    if proc_status == WaitStatus::Exited {
-        let child = proc.children.lock().remove(child_id);
-        curr_task.add_child_time(&child.main_thread());
-
-        let exit_code = child.exit_code();
-        if !exit_code_ptr.is_null() {
-            unsafe {
-                *exit_code_ptr = exit_code << 8;
+    // 停止与继续只在要求时查找
+    if options.intersects(WaitOptions::WSTOPPED | WaitOptions::WCONTINUED) {
+        for child in children
+            .matching(target)
+            .filter(|child| eligible(child, options))
+        {
+            let mut change = child.state_change.lock();
+            let wanted = match *change {
+                Some(ChildState::Stopped(_)) => WaitOptions::WSTOPPED,
+                Some(ChildState::Continued) => WaitOptions::WCONTINUED,
+                _ => continue,
+            };
+            if options.contains(wanted) && child.exit_state().is_none() {
+                let state = if keep { *change } else { change.take() };
+                return Ok(result(child, state.unwrap()));
             }
         }
-
-        return Ok(child.pid);
     }
 
-    Err(proc_status)
+    let found = children
+        .matching(target)
+        .any(|child| eligible(child, options));
+    Err(if found {
+        WaitStatus::Running
+    } else {
+        WaitStatus::NotExist
+    })
 }
@@ -0,0 +1,58 @@
+//! Process credentials.
+
+/// The user and group identities of a process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Credentials {
+    /// Real user ID
+    pub uid: u32,
+    /// Real group ID
+    pub gid: u32,
+    /// Effective user ID, used for permission checks
+    pub euid: u32,
+    /// Effective group ID, used for permission checks
+    pub egid: u32,
+    /// Saved user ID, which an unprivileged process may switch back to
+    pub suid: u32,
+    /// Saved group ID, which an unprivileged process may switch back to
+    pub sgid: u32,
+}
+
+impl Credentials {
+    /// The credentials of the superuser.
+    pub const fn root() -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+            suid: 0,
+            sgid: 0,
+        }
+    }
+
+    /// Whether the process is privileged, i.e. runs as root.
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Whether an unprivileged process may take `uid` as one of its user IDs:
+    /// it must be its real, effective or saved one already.
+    pub fn holds_uid(&self, uid: u32) -> bool {
+        [self.uid, self.euid, self.suid].contains(&uid)
+    }
+
+    /// Whether an unprivileged process may take `gid` as one of its group IDs.
+    pub fn holds_gid(&self, gid: u32) -> bool {
+        [self.gid, self.egid, self.sgid].contains(&gid)
+    }
+
+    /// Whether a process with these credentials may send signals to one with
+    /// `target`: root may signal anyone, others the processes whose real or
+    /// effective user ID matches their own real or effective one.
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_privileged()
+            || [self.uid, self.euid]
+                .iter()
+                .any(|uid| *uid == target.uid || *uid == target.euid)
+    }
+}
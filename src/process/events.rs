@@ -0,0 +1,71 @@
+//! Notifications of the lifecycle of processes, for components of the kernel
+//! outside the process code, like a supervisor.
+//!
+//! A subscriber is a callback registered with [`subscribe`], called with
+//! every [`ProcessEvent`] by the thread which caused it, right after the
+//! change. It must not block: it runs in the middle of `execve` or `exit`,
+//! and may be called concurrently from several CPUs. It is called without any
+//! lock held, so it may subscribe or unsubscribe itself. Emitting an event
+//! without any subscriber costs a single atomic load.
+// The kernel itself emits the events but doesn't subscribe to them
+#![allow(dead_code)]
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axsync::Mutex;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// A change in the lifecycle of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessEvent {
+    /// The process `pid` was created by `ppid`.
+    Created { pid: u64, ppid: u64 },
+    /// The process `pid` replaced its program with `execve`.
+    Exec { pid: u64 },
+    /// The process `pid` was stopped by `signal`.
+    Stopped { pid: u64, signal: u32 },
+    /// The process `pid` was continued by `SIGCONT`.
+    Continued { pid: u64 },
+    /// The process `pid` exited with `code`, and may now be waited for.
+    Exited { pid: u64, code: i32 },
+}
+
+/// The handle of a subscription, to cancel it with [`unsubscribe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+type Subscriber = Arc<dyn Fn(&ProcessEvent) + Send + Sync>;
+
+static SUBSCRIBERS: Mutex<BTreeMap<SubscriptionId, Subscriber>> = Mutex::new(BTreeMap::new());
+/// The number of subscribers, which [`emit`] checks without locking
+static SUBSCRIBER_COUNT: AtomicUsize = AtomicUsize::new(0);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Call `callback` with every process event from now on.
+pub fn subscribe(callback: impl Fn(&ProcessEvent) + Send + Sync + 'static) -> SubscriptionId {
+    let id = SubscriptionId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let mut subscribers = SUBSCRIBERS.lock();
+    subscribers.insert(id, Arc::new(callback));
+    SUBSCRIBER_COUNT.store(subscribers.len(), Ordering::Release);
+    id
+}
+
+/// Cancel the subscription `id`, telling whether it existed. An event being
+/// emitted on another CPU may still reach it.
+pub fn unsubscribe(id: SubscriptionId) -> bool {
+    let mut subscribers = SUBSCRIBERS.lock();
+    let removed = subscribers.remove(&id).is_some();
+    SUBSCRIBER_COUNT.store(subscribers.len(), Ordering::Release);
+    removed
+}
+
+/// Tell every subscriber about `event`.
+pub(crate) fn emit(event: ProcessEvent) {
+    if SUBSCRIBER_COUNT.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let subscribers: Vec<Subscriber> = SUBSCRIBERS.lock().values().cloned().collect();
+    for subscriber in subscribers {
+        subscriber(&event);
+    }
+}
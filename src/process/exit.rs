@@ -0,0 +1,78 @@
+//! The teardown of an exiting thread.
+//!
+//! Everything a thread leaves behind is released by [`thread_exit`], in this
+//! order:
+//!
+//! 1. The robust futex list registered with `set_robust_list` is walked, and
+//!    the futexes the thread still holds are marked `FUTEX_OWNER_DIED`, one
+//!    waiter of each being woken up.
+//! 2. The word at `clear_child_tid` is cleared and its futex woken, while the
+//!    address space is certainly still there. This comes after the robust
+//!    list, as on Linux, so that a thread joining this one finds the futexes
+//!    it held already marked.
+//! 3. The signal module of the thread is removed. From then on, a signal sent
+//!    to the thread is dropped instead of finding no module to queue it in.
+//! 4. The thread leaves its process and its tid is freed, or the whole process
+//!    exits if it is the main thread. The process then, in order, hands its
+//!    children over to a reaper, waits for its other threads, closes its
+//!    files, stops its io_uring workers and undoes its `SEM_UNDO` operations.
+//!    If no other process shares its address space, it detaches its shared
+//!    memory, wakes up the futex waiters in it and unmaps it all, which gives
+//!    the frames back right away. Only then is the parent told, and what the
+//!    zombie keeps is little more than what `wait4` reports.
+//! 5. Its files are closed, unless a thread still running shares its fd
+//!    table. They don't wait for a zombie to be reaped: a pipe sees its
+//!    writer go away as soon as it exits.
+//! 6. The task exits. The rest of its namespace is released by
+//!    `TaskExt::drop` when the last reference to the task goes away, after
+//!    nothing can run on its behalf anymore.
+//!
+//! [`group_exit`] makes the whole process exit: the other threads are
+//! interrupted and go through [`thread_exit`] on their way back to user
+//! space, and the main thread tears the process down once they are gone.
+use crate::futex::{exit_robust_list, futex_wake, FutexKey, FUTEX_BITSET_MATCH_ANY};
+use crate::process::pid::to_user;
+use crate::ptr::UserPtr;
+use axtask::{current, TaskExtRef};
+
+/// Tear down the current thread, which exits with `status`.
+pub fn thread_exit(status: i32) -> ! {
+    let curr = current();
+
+    let robust_list = curr.task_ext().robust_list();
+    if robust_list != 0 {
+        // The futex words hold the tids the threads see
+        exit_robust_list(robust_list as usize, to_user(curr.task_ext().tid()) as u32);
+    }
+
+    let clear_child_tid = curr.task_ext().clear_child_tid() as *mut i32;
+    if !clear_child_tid.is_null() {
+        // The thread is exiting anyway, so a bad address is ignored
+        let _ = UserPtr::from(clear_child_tid).write(0);
+        futex_wake(
+            FutexKey::current(clear_child_tid as usize),
+            1,
+            FUTEX_BITSET_MATCH_ANY,
+        );
+    }
+
+    match curr.task_ext().get_proc() {
+        Some(proc) => {
+            proc.exit_thread(curr.as_task_ref().clone(), status);
+            curr.task_ext().close_files();
+        }
+        None => {
+            warn!("No process found for the current task");
+        }
+    }
+    axtask::exit(status);
+}
+
+/// Make the whole process of the current thread exit with `status`, as
+/// `exit_group` does, then tear down the current thread.
+pub fn group_exit(status: i32) -> ! {
+    if let Some(proc) = current().task_ext().get_proc() {
+        proc.start_group_exit(status);
+    }
+    thread_exit(status)
+}
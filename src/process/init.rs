@@ -0,0 +1,71 @@
+//! The kernel's init process, pid 1.
+//!
+//! When a process exits, its children are reparented to the init of their
+//! pid namespace, the process which is pid 1 there, or to this one if that
+//! init is gone or is the root namespace's. A user init reaps them with
+//! `wait4` as on Linux. This one is a kernel task which never enters user
+//! space: it sleeps until one of its children changes state and releases the
+//! ones which have exited, so that orphans don't stay zombies forever.
+use super::{get_process, new_process, AxProcessRef, Process};
+use crate::task::{task_name, TaskExt};
+use alloc::sync::Arc;
+use axhal::arch::UspaceContext;
+use axsync::Mutex;
+use axtask::TaskInner;
+use core::sync::atomic::Ordering;
+use memory_addr::VirtAddr;
+
+/// The pid of init, which the pid allocator never hands out.
+pub const INIT_PID: u64 = 1;
+
+/// Create the init process and start reaping.
+pub fn init() {
+    let aspace = axmm::new_user_aspace(
+        VirtAddr::from_usize(crate::config::USER_SPACE_BASE),
+        crate::config::USER_SPACE_SIZE,
+    )
+    .expect("failed to create the address space of init");
+    let proc = new_process(
+        0,
+        INIT_PID,
+        Arc::new(Mutex::new(aspace)),
+        Default::default(),
+    );
+    let mut task = TaskInner::new(
+        reap_orphans,
+        task_name("init", INIT_PID),
+        crate::config::KERNEL_STACK_SIZE,
+    );
+    task.init_task_ext(TaskExt::new(
+        INIT_PID,
+        "init",
+        UspaceContext::new(0, VirtAddr::from_usize(0), 0),
+        &proc,
+    ));
+    proc.set_main_thread(axtask::spawn_task(task));
+}
+
+/// The process the children of the exiting process `proc` are reparented to.
+pub fn child_reaper(proc: &Process) -> AxProcessRef {
+    proc.pid_ns
+        .lock()
+        .global(INIT_PID)
+        .and_then(get_process)
+        .filter(|reaper| reaper.pid != proc.pid && !reaper.is_exiting())
+        .or_else(|| get_process(INIT_PID))
+        .expect("init is gone")
+}
+
+fn reap_orphans() {
+    let init = get_process(INIT_PID).unwrap();
+    loop {
+        let seen = init.child_events.load(Ordering::Acquire);
+        let mut children = init.children.lock();
+        while let Some(pid) = children.exited().next().map(|child| child.pid) {
+            children.remove(pid);
+        }
+        drop(children);
+        init.child_wq
+            .wait_until(|| init.child_events.load(Ordering::Acquire) != seen);
+    }
+}
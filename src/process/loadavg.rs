@@ -0,0 +1,60 @@
+//! Load averages, as reported by `sysinfo` and `/proc/loadavg`.
+//!
+//! The scheduler keeps no run queue statistics, so the load is the number of
+//! processes which aren't stopped, folded into the averages with the same
+//! exponential decay as Linux every [`LOAD_FREQ`].
+use super::all_processes;
+use axsync::Mutex;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+/// The number of fractional bits of the averages.
+pub const FSHIFT: u32 = 11;
+const FIXED_1: u64 = 1 << FSHIFT;
+/// The interval between two samples.
+const LOAD_FREQ: Duration = Duration::from_secs(5);
+/// `FIXED_1 / exp(LOAD_FREQ / 1min)`, and likewise for 5 and 15 minutes
+const EXP: [u64; 3] = [1884, 2014, 2037];
+/// Beyond this many missed samples, the averages have converged anyway.
+const MAX_CATCH_UP: u64 = 1024;
+
+struct LoadAvg {
+    /// The 1, 5 and 15 minute averages, with `FSHIFT` fractional bits
+    loads: [u64; 3],
+    /// The time of the last sample
+    last: Duration,
+}
+
+static LOADAVG: Mutex<LoadAvg> = Mutex::new(LoadAvg {
+    loads: [0; 3],
+    last: Duration::ZERO,
+});
+
+/// The number of runnable processes.
+pub fn nr_running() -> usize {
+    all_processes()
+        .iter()
+        .filter(|proc| !proc.stopped.load(Ordering::Relaxed))
+        .count()
+}
+
+/// The 1, 5 and 15 minute load averages, with `FSHIFT` fractional bits.
+///
+/// The samples missed since the last call are taken now, all with the
+/// current load.
+pub fn loadavg() -> [u64; 3] {
+    let mut avg = LOADAVG.lock();
+    let now = axhal::time::monotonic_time();
+    let samples = ((now - avg.last).as_nanos() / LOAD_FREQ.as_nanos()) as u64;
+    if samples == 0 {
+        return avg.loads;
+    }
+    let active = nr_running() as u64 * FIXED_1;
+    for _ in 0..samples.min(MAX_CATCH_UP) {
+        for (load, exp) in avg.loads.iter_mut().zip(EXP) {
+            *load = (*load * exp + active * (FIXED_1 - exp)) / FIXED_1;
+        }
+    }
+    avg.last += LOAD_FREQ * samples as u32;
+    avg.loads
+}
@@ -1,9 +1,27 @@
 mod api;
+pub mod cred;
+pub mod events;
+mod exit;
+pub mod init;
+pub mod loadavg;
+pub mod pid;
 pub mod signal;
+pub mod timens;
 
+use crate::arch::TrapFrameExt;
 use crate::flag::CloneFlags;
-use crate::process::signal::SignalModule;
-use crate::task::{read_trap_frame_from_kstack, TaskExt};
+use crate::fs::mount::{self, MountNamespace};
+use crate::mm::{ArgEnv, UserLayout, VmAreas};
+use crate::process::cred::Credentials;
+use crate::process::events::ProcessEvent;
+use crate::process::pid::{alloc_tid, dealloc_tid, PidNamespace};
+use crate::process::signal::{notify_parent, send_signal_to_proc};
+use crate::process::timens::TimeNamespace;
+use crate::seccomp::Seccomp;
+use crate::signal::signal_no::{SignalNo, MAX_SIG_NUM};
+use crate::strace;
+use crate::sync::{AdaptiveMutex, Rcu};
+use crate::task::{exe_basename, read_trap_frame_from_kstack, task_name, TaskExt};
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
@@ -11,22 +29,72 @@ use alloc::vec::Vec;
 pub use api::*;
 use axerrno::AxResult;
 use axhal::arch::UspaceContext;
-use axhal::paging::MappingFlags;
 use axmm::AddrSpace;
 use axsync::Mutex;
-use axtask::{current, yield_now, AxTaskRef, TaskExtRef, TaskInner};
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
-use memory_addr::{MemoryAddr, VirtAddr};
+use axtask::{current, yield_now, AxTaskRef, TaskExtRef, TaskInner, WaitQueue};
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+pub use exit::{group_exit, thread_exit};
 
 pub type AxProcessRef = Arc<Process>;
 
+/// clone 的参数中退出信号所在的位
+const CSIGNAL: usize = 0xff;
+
+/// 进程退出时先让出 CPU 等待其他线程的时间
+const EXIT_SPIN_PERIOD: Duration = Duration::from_millis(10);
+/// 之后每次睡眠等待的时间
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// 等待其他线程退出的最长时间
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 进程退出时未能按时退出而被脱离的线程，tid -> 其所属进程
+static ORPHAN_THREADS: Mutex<BTreeMap<u64, AxProcessRef>> = Mutex::new(BTreeMap::new());
+
+/// 被脱离的线程最终退出时，释放其所属进程
+fn release_orphan(tid: u64) -> bool {
+    // 先取出再释放，进程可能在此被销毁
+    let proc = ORPHAN_THREADS.lock().remove(&tid);
+    proc.is_some()
+}
+
+/// 进程是否有被脱离的线程
+fn has_orphan_threads(pid: u64) -> bool {
+    ORPHAN_THREADS.lock().values().any(|proc| proc.pid == pid)
+}
+
+/// 启动程序时 execve 的参数，加载后保留一份，供 procfs 与线程名使用
+///
+/// 字符串均不含结尾的 `\0`
+#[derive(Clone, Default)]
+pub struct ExecArgs {
+    /// execve 的路径，脚本为脚本本身而非解释器
+    pub path: String,
+    /// 参数，脚本的参数以解释器开头
+    pub argv: Vec<String>,
+    /// 环境变量
+    pub envp: Vec<String>,
+}
+
+/// 进程
+///
+/// # 锁的顺序
+///
+/// 需要同时持有多个锁时按以下顺序获取，以免死锁：
+///
+/// 1. 进程表只在增删进程时短暂加写锁，持有期间不获取其他锁，查找不加锁；
+/// 2. 父进程的 `children`；
+/// 3. 子进程的 `state_change`、`cred` 与 `threads`；
+/// 4. 线程的信号状态，之后是其信号处理函数表。
+///
+/// 不要在持有子进程的锁时获取父进程的锁：通知父进程前须先释放子进程的锁。
 pub struct Process {
     /// 进程 ID
     pub pid: u64,
     /// 父进程 ID
     pub ppid: AtomicU64,
     /// 子进程
-    pub children: Mutex<Vec<AxProcessRef>>,
+    pub children: Mutex<Children>,
     /// 线程，tid -> thread
     pub threads: Mutex<BTreeMap<u64, AxTaskRef>>,
     /// 地址空间
@@ -39,32 +107,167 @@ pub struct Process {
     pub heap_top: AtomicU64,
     /// 当前堆顶
     pub heap_current: AtomicU64,
-    /// 进程状态
+    /// mmap 查找空闲区域的起点
+    pub mmap_base: AtomicUsize,
+    /// 信号处理函数没有 SA_RESTORER 时返回到的跳板代码的地址
+    pub signal_trampoline: AtomicUsize,
+    /// 执行域，目前只用到 `ADDR_NO_RANDOMIZE`，子进程继承，execve 后保留
+    pub personality: AtomicU32,
+    /// 进程状态，退出完成后置位
     pub is_exited: AtomicBool,
-    /// 信号处理
-    pub signal_module: Mutex<BTreeMap<u64, SignalModule>>,
+    /// 进程正在退出，其他线程应尽快退出
+    exiting: AtomicBool,
+    /// 用户与组凭据
+    pub cred: Mutex<Credentials>,
+    /// 地址空间中映射的区域，随 mmap、munmap、mprotect 与 brk 更新，
+    /// 须在持有地址空间的锁时修改。与地址空间一同为共享它的进程共用
+    pub vm_areas: Arc<Mutex<VmAreas>>,
+    /// 正在运行的程序的路径，execve 后更新
+    pub exe: Mutex<String>,
+    /// 参数与环境变量在用户栈上的位置
+    pub arg_env: Mutex<ArgEnv>,
+    /// 启动当前程序时的路径、参数与环境变量的副本，execve 后更新
+    exec_args: Mutex<ExecArgs>,
+    /// 时间命名空间
+    pub time_ns: AdaptiveMutex<Arc<TimeNamespace>>,
+    /// 子进程将进入的时间命名空间，unshare(CLONE_NEWTIME) 后与 `time_ns` 不同
+    pub time_ns_for_children: AdaptiveMutex<Arc<TimeNamespace>>,
+    /// 挂载命名空间
+    pub mnt_ns: AdaptiveMutex<Arc<MountNamespace>>,
+    /// 所在的 pid 命名空间，创建后不变
+    pub pid_ns: AdaptiveMutex<Arc<PidNamespace>>,
+    /// 文件创建掩码
+    pub umask: AtomicU32,
+    /// 进程组 ID
+    pub pgid: AtomicU64,
+    /// 会话 ID
+    pub sid: AtomicU64,
+    /// 是否被 SIGSTOP 等信号停止
+    pub stopped: AtomicBool,
+    /// 尚未被 wait 报告的停止或继续
+    pub state_change: Mutex<Option<ChildState>>,
+    /// 子进程状态变化的次数，等待子进程时用作唤醒条件
+    pub child_events: AtomicU64,
+    /// 等待子进程状态变化的线程
+    pub child_wq: WaitQueue,
+    /// 终止进程的信号，0 表示进程自行退出
+    pub term_signal: AtomicU32,
+    /// 父进程退出时发送给本进程的信号，0 表示不发送
+    pub pdeath_signal: AtomicU32,
+    /// 本进程退出时发送给父进程的信号，由 clone 的低 8 位指定，0 表示不发送
+    pub exit_signal: AtomicU32,
+    /// 是否跟踪本进程的系统调用，子进程继承
+    pub strace: AtomicBool,
+    /// 系统调用跟踪的输出，子进程继承
+    pub strace_output: Mutex<strace::Output>,
+    /// 系统调用过滤，子进程继承
+    pub seccomp: Rcu<Seccomp>,
 }
 
-const BRK_BOTTOM: u64 = 0x40000000;
-const BRK_TOP: u64 = 0x80000000;
+/// 堆的最大大小
+const HEAP_MAX_SIZE: u64 = 0x40000000;
 
 impl Process {
-    pub fn new(ppid: u64, pid: u64, aspace: Arc<Mutex<AddrSpace>>) -> Self {
+    pub fn new(
+        ppid: u64,
+        pid: u64,
+        aspace: Arc<Mutex<AddrSpace>>,
+        vm_areas: Arc<Mutex<VmAreas>>,
+    ) -> Self {
         Self {
             pid,
             ppid: AtomicU64::new(ppid),
-            children: Mutex::new(Vec::new()),
+            children: Mutex::new(Children::default()),
             threads: Mutex::new(BTreeMap::new()),
             aspace,
             exit_code: AtomicI32::new(0),
-            heap_bottom: AtomicU64::new(BRK_BOTTOM),
-            heap_top: AtomicU64::new(BRK_TOP),
-            heap_current: AtomicU64::new(BRK_BOTTOM),
+            heap_bottom: AtomicU64::new(0),
+            heap_top: AtomicU64::new(0),
+            heap_current: AtomicU64::new(0),
+            mmap_base: AtomicUsize::new(0),
+            signal_trampoline: AtomicUsize::new(0),
+            personality: AtomicU32::new(0),
             is_exited: AtomicBool::new(false),
-            signal_module: Mutex::new(BTreeMap::new()),
+            exiting: AtomicBool::new(false),
+            cred: Mutex::new(Credentials::root()),
+            vm_areas,
+            exe: Mutex::new(String::new()),
+            arg_env: Mutex::new(ArgEnv::default()),
+            exec_args: Mutex::new(ExecArgs::default()),
+            time_ns: AdaptiveMutex::new(timens::init_ns()),
+            time_ns_for_children: AdaptiveMutex::new(timens::init_ns()),
+            mnt_ns: AdaptiveMutex::new(mount::init_ns()),
+            pid_ns: AdaptiveMutex::new(PidNamespace::root()),
+            umask: AtomicU32::new(0o022),
+            pgid: AtomicU64::new(pid),
+            sid: AtomicU64::new(pid),
+            stopped: AtomicBool::new(false),
+            state_change: Mutex::new(None),
+            child_events: AtomicU64::new(0),
+            child_wq: WaitQueue::new(),
+            term_signal: AtomicU32::new(0),
+            pdeath_signal: AtomicU32::new(0),
+            exit_signal: AtomicU32::new(SignalNo::SIGCHLD as u32),
+            strace: AtomicBool::new(false),
+            strace_output: Mutex::new(strace::Output::Log),
+            seccomp: Rcu::new(Seccomp::default()),
         }
     }
 
+    /// 按加载程序得到的布局设置堆与 mmap 的起点，堆此时为空
+    pub fn init_layout(&self, layout: &UserLayout) {
+        let bottom = layout.heap_bottom.as_usize() as u64;
+        self.heap_bottom.store(bottom, Ordering::SeqCst);
+        self.heap_current.store(bottom, Ordering::SeqCst);
+        self.heap_top
+            .store(bottom + HEAP_MAX_SIZE, Ordering::SeqCst);
+        self.mmap_base
+            .store(layout.mmap_base.as_usize(), Ordering::SeqCst);
+        self.signal_trampoline
+            .store(layout.signal_trampoline.as_usize(), Ordering::SeqCst);
+        *self.vm_areas.lock() = layout.areas.clone();
+        *self.exe.lock() = layout.exe.clone();
+        *self.arg_env.lock() = layout.arg_env.clone();
+        crate::mm::rss::set(crate::mm::aspace_key(&self.aspace), layout.resident);
+    }
+
+    /// 启动当前程序时的路径、参数与环境变量
+    pub fn exec_args(&self) -> ExecArgs {
+        self.exec_args.lock().clone()
+    }
+
+    /// 记录启动当前程序时的路径、参数与环境变量，在加载程序后调用
+    pub fn set_exec_args(&self, args: ExecArgs) {
+        *self.exec_args.lock() = args;
+    }
+
+    /// 线程的默认名称，即启动当前程序时的路径的最后一段
+    pub fn default_comm(&self) -> String {
+        String::from(exe_basename(&self.exec_args.lock().path))
+    }
+
+    /// 地址空间中驻留的页数，与共享地址空间的进程共用
+    pub fn rss(&self) -> usize {
+        crate::mm::rss::get(crate::mm::aspace_key(&self.aspace))
+    }
+
+    pub fn personality(&self) -> u32 {
+        self.personality.load(Ordering::Relaxed)
+    }
+
+    pub fn pgid(&self) -> u64 {
+        self.pgid.load(Ordering::Relaxed)
+    }
+
+    pub fn sid(&self) -> u64 {
+        self.sid.load(Ordering::Relaxed)
+    }
+
+    /// A copy of the current credentials of the process.
+    pub fn cred(&self) -> Credentials {
+        *self.cred.lock()
+    }
+
     pub fn state(&self) -> axtask::TaskState {
         if self.is_exited.load(Ordering::Relaxed) {
             axtask::TaskState::Exited
@@ -78,69 +281,202 @@ impl Process {
     }
 
     pub fn set_main_thread(&self, thread: AxTaskRef) {
-        assert_eq!(thread.id().as_u64(), self.pid);
+        assert_eq!(thread.task_ext().tid(), self.pid);
         self.add_thread(thread);
     }
 
     pub fn add_thread(&self, thread: AxTaskRef) {
-        let tid = thread.id().as_u64();
-        self.signal_module
-            .lock()
-            .insert(tid, SignalModule::new(None));
+        let tid = thread.task_ext().tid();
         self.threads.lock().insert(tid, thread);
     }
 
     pub fn is_main_thread(&self, thread: &AxTaskRef) -> bool {
-        thread.id().as_u64() == self.pid
+        thread.task_ext().tid() == self.pid
     }
 
+    /// 线程离开进程，由 [`thread_exit`] 调用
     pub fn exit_thread(&self, thread: AxTaskRef, status: i32) {
-        let tid = thread.id().as_u64();
-        self.signal_module.lock().remove(&tid);
+        let tid = thread.task_ext().tid();
         // 主线程退出时，退出整个进程
         if self.is_main_thread(&thread) {
             self.exit(status);
             return;
         }
-        let mut threads = self.threads.lock();
-        let _thread = threads.remove(&tid).unwrap();
+        // 被强制脱离的线程不在 threads 中，由孤儿表持有其进程
+        if self.threads.lock().remove(&tid).is_some() || release_orphan(tid) {
+            dealloc_tid(tid);
+        }
     }
 
     pub fn exit_code(&self) -> i32 {
         self.exit_code.load(Ordering::Relaxed)
     }
 
-    pub fn exit(&self, code: i32) {
-        for child in self.children.lock().iter_mut() {
-            child.ppid.store(1, Ordering::SeqCst);
+    /// 进程退出后等待它的父进程看到的状态，尚未退出时为 `None`
+    pub fn exit_state(&self) -> Option<ChildState> {
+        if self.state() != axtask::TaskState::Exited {
+            return None;
         }
-        self.is_exited.store(true, Ordering::Relaxed);
+        Some(match self.term_signal.load(Ordering::Relaxed) {
+            0 => ChildState::Exited(self.exit_code()),
+            signal => ChildState::Killed(signal),
+        })
+    }
 
-        // 等待其他线程退出
-        // TODO: 直接退出其他线程
-        while self.threads.lock().len() > 1 {
-            yield_now();
-        }
+    /// 有子进程的状态发生了变化，唤醒等待子进程的线程
+    pub fn notify_child_change(&self) {
+        self.child_events.fetch_add(1, Ordering::Release);
+        self.child_wq.notify_all(false);
+    }
 
+    /// 进程是否正在退出或已经退出
+    pub fn is_exiting(&self) -> bool {
+        self.exiting.load(Ordering::Acquire)
+    }
+
+    /// 开始以 `code` 退出整个进程，要求除当前线程外的所有线程退出。
+    ///
+    /// 返回是否由本次调用开始退出；进程已在退出时保留先前的退出码。
+    pub fn start_group_exit(&self, code: i32) -> bool {
+        if self.exiting.swap(true, Ordering::AcqRel) {
+            return false;
+        }
         self.exit_code.store(code, Ordering::Relaxed);
+        self.kill_threads();
+        true
+    }
+
+    /// 要求除当前线程外的所有线程停下：使其返回用户态前进入信号处理的慢速路径，
+    /// 在那里等待进程继续执行
+    pub fn stop_threads(&self) {
+        let curr = current();
+        for thread in self.threads.lock().values() {
+            if !Arc::ptr_eq(thread, curr.as_task_ref()) {
+                thread.task_ext().set_signal_pending();
+            }
+        }
+    }
+
+    /// 要求除当前线程外的所有线程退出：使其返回用户态前处理退出，
+    /// 并打断其阻塞的等待
+    fn kill_threads(&self) {
+        let curr = current();
+        for thread in self.threads.lock().values() {
+            if !Arc::ptr_eq(thread, curr.as_task_ref()) {
+                thread.task_ext().set_signal_pending();
+                thread.task_ext().interrupt();
+            }
+        }
+    }
+
+    pub fn exit(&self, code: i32) {
+        // 整个进程退出时，沿用开始退出时的退出码
+        if !self.exiting.swap(true, Ordering::AcqRel) {
+            self.exit_code.store(code, Ordering::Relaxed);
+        }
+        // 子进程交给 init 收养，由它回收
+        let orphans = self.children.lock().take_all();
+        if !orphans.is_empty() {
+            let reaper = init::child_reaper(self);
+            let mut children = reaper.children.lock();
+            for child in orphans.iter() {
+                children.insert(child.clone());
+            }
+            drop(children);
+            for child in orphans {
+                child.ppid.store(reaper.pid, Ordering::SeqCst);
+                // 收养者只认 SIGCHLD
+                child
+                    .exit_signal
+                    .store(SignalNo::SIGCHLD as u32, Ordering::Relaxed);
+                let signal = child.pdeath_signal.load(Ordering::Relaxed);
+                if signal != 0 {
+                    let _ = send_signal_to_proc(child.pid, signal as isize, None);
+                }
+                // 已经退出的子进程由收养它的进程回收
+                if let Some(state) = child.exit_state() {
+                    notify_parent(&child, state);
+                }
+            }
+        }
+        self.reap_threads();
+        // 其他线程已经退出，在通知父进程之前关闭文件
+        current().task_ext().close_files();
+
+        // 停止 io_uring 的工作任务，使其离开进程的地址空间
+        crate::uring::exit(self.pid);
+        // 撤销以 SEM_UNDO 进行的信号量操作
+        crate::ipc::sem::exit(self.pid);
+        // 地址空间不再被其他进程共享时随之释放：解除共享内存，唤醒其上的 futex 等待者，
+        // 并解除全部映射以立即归还物理页。被脱离的线程可能仍在访问用户内存，此时保留映射
+        if Arc::strong_count(&self.aspace) == 1 {
+            let key = crate::mm::aspace_key(&self.aspace);
+            crate::ipc::shm::detach_all(&self.aspace);
+            crate::futex::forget_aspace(key);
+            if !has_orphan_threads(self.pid) {
+                let mut aspace = self.aspace.lock();
+                crate::mm::zero::release_all(&aspace);
+                aspace.clear();
+                crate::mm::tlb::flush_all(&aspace);
+                self.vm_areas.lock().clear();
+            }
+            crate::mm::forget_all_frames(key);
+        }
+        // 僵尸进程不再需要命名空间
+        *self.mnt_ns.lock() = mount::init_ns();
+        *self.time_ns.lock() = timens::init_ns();
+        *self.time_ns_for_children.lock() = timens::init_ns();
+        self.is_exited.store(true, Ordering::Release);
         remove_process(self.pid);
-        debug!("Process {} exited with code {}", self.pid, code);
+        // 父进程由 SIGCHLD 得知子进程退出，可以等待它了
+        if let Some(state) = self.exit_state() {
+            notify_parent(self, state);
+        }
+        events::emit(ProcessEvent::Exited {
+            pid: self.pid,
+            code: self.exit_code(),
+        });
+        debug!("Process {} exited with code {}", self.pid, self.exit_code());
     }
 
-    pub fn alloc_range_lazy(
-        &self,
-        start: VirtAddr,
-        end: VirtAddr,
-        flags: MappingFlags,
-    ) -> AxResult<()> {
-        if start > end {
-            return Err(axerrno::AxError::InvalidInput);
+    /// 等待其他线程退出
+    ///
+    /// 其他线程被打断阻塞的等待，在返回用户态前看到进程正在退出后自行退出。
+    /// 先让出 CPU 等待，再以睡眠等待，超过 `EXIT_TIMEOUT` 仍未退出的线程多半
+    /// 阻塞在不可中断的等待中：将其从进程中脱离并放入孤儿表，由孤儿表保持进程及其地址空间存活，
+    /// 直到该线程最终退出，从而保证进程退出总能完成。
+    fn reap_threads(&self) {
+        self.kill_threads();
+        let others = || self.threads.lock().len() > 1;
+        let start = axhal::time::monotonic_time();
+        while others() && axhal::time::monotonic_time() - start < EXIT_SPIN_PERIOD {
+            yield_now();
+        }
+        while others() && axhal::time::monotonic_time() - start < EXIT_TIMEOUT {
+            axtask::sleep(EXIT_POLL_INTERVAL);
+        }
+        if !others() {
+            return;
+        }
+
+        let Some(this) = get_process(self.pid) else {
+            return;
+        };
+        let mut threads = self.threads.lock();
+        let stuck: Vec<u64> = threads
+            .keys()
+            .copied()
+            .filter(|&tid| tid != self.pid)
+            .collect();
+        let mut orphans = ORPHAN_THREADS.lock();
+        for tid in stuck {
+            warn!(
+                "Process {} exit: thread {} did not exit within {:?}, detaching it",
+                self.pid, tid, EXIT_TIMEOUT
+            );
+            threads.remove(&tid);
+            orphans.insert(tid, this.clone());
         }
-        let start = start.align_down_4k();
-        let end = end.align_up_4k();
-        let mut aspace = self.aspace.lock();
-        aspace.map_alloc(start, end - start, flags, false)?;
-        Ok(())
     }
 
     pub fn clone_proc(
@@ -151,7 +487,36 @@ impl Process {
         _tls: usize,
         ctid: usize,
     ) -> AxResult<u64> {
-        let clone_flags = CloneFlags::from_bits((flags & !0x3f) as u32).unwrap();
+        let clone_flags = CloneFlags::from_bits((flags & !CSIGNAL) as u32)
+            .ok_or(axerrno::AxError::InvalidInput)?;
+        let exit_signal = flags & CSIGNAL;
+        if exit_signal > MAX_SIG_NUM {
+            return Err(axerrno::AxError::InvalidInput);
+        }
+        // 与 Linux 相同，线程须共享信号处理函数表，共享信号处理函数表须共享地址空间
+        if (clone_flags.contains(CloneFlags::CLONE_THREAD)
+            && !clone_flags.contains(CloneFlags::CLONE_SIGHAND))
+            || (clone_flags.contains(CloneFlags::CLONE_SIGHAND)
+                && !clone_flags.contains(CloneFlags::CLONE_VM))
+        {
+            return Err(axerrno::AxError::InvalidInput);
+        }
+
+        // 只有特权进程可以创建新的命名空间
+        if clone_flags.intersects(
+            CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWTIME,
+        ) && !self.cred().is_privileged()
+        {
+            return Err(axerrno::AxError::PermissionDenied);
+        }
+
+        // 与 Linux 相同，共享地址空间的进程须在同一时间命名空间中，vDSO 才能给出它们的时间
+        let time_ns = self.time_ns_for_children.lock().clone();
+        let new_time_ns = clone_flags.contains(CloneFlags::CLONE_NEWTIME)
+            || !Arc::ptr_eq(&time_ns, &self.time_ns.lock());
+        if new_time_ns && clone_flags.contains(CloneFlags::CLONE_VM) {
+            return Err(axerrno::AxError::InvalidInput);
+        }
 
         // 对于 CLONE_THREAD，特殊处理
         if clone_flags.contains(CloneFlags::CLONE_THREAD) {
@@ -162,45 +527,116 @@ impl Process {
         let mut trap_frame =
             read_trap_frame_from_kstack(curr.kernel_stack_top().unwrap().as_usize());
 
-        let new_aspace = if clone_flags.contains(CloneFlags::CLONE_VM) {
-            self.aspace.clone()
+        let (new_aspace, vm_areas) = if clone_flags.contains(CloneFlags::CLONE_VM) {
+            (self.aspace.clone(), self.vm_areas.clone())
         } else {
-            // TODO: 现有的复制方式似乎会破坏原有进程的空间，需要进一步优化，现在用共享空间代替
-            // let new_aspace = AddrSpace::from_exited_space(&self.aspace.lock())?;
-            // Arc::new(Mutex::new(new_aspace))
-            self.aspace.clone()
+            // 复制一份地址空间，共享内存仍与父进程共享
+            let (aspace, vm_areas) = crate::mm::fork::fork(&self.aspace, &self.vm_areas)?;
+            crate::ipc::shm::fork(&self.aspace, &aspace);
+            (aspace, vm_areas)
         };
 
-        let mut new_task = new_task();
+        // 子进程进入父进程为子进程准备的时间命名空间，此后其偏移不能再改
+        let time_ns = if clone_flags.contains(CloneFlags::CLONE_NEWTIME) {
+            Arc::new(time_ns.fork())
+        } else {
+            time_ns
+        };
+        time_ns.enter();
+        if new_time_ns {
+            crate::mm::vdso::enter_timens(
+                crate::mm::aspace_key(&new_aspace),
+                &mut new_aspace.lock(),
+                &vm_areas.lock(),
+                &time_ns,
+            )?;
+        }
+
+        // 子进程在新的 pid 命名空间中是 1 号进程
+        let pid_ns = self.pid_ns.lock().clone();
+        let pid_ns = if clone_flags.contains(CloneFlags::CLONE_NEWPID) {
+            pid_ns.new_child()
+        } else {
+            pid_ns
+        };
+        let pid = alloc_tid(&pid_ns).ok_or(axerrno::AxError::NoMemory)?;
+        // The child inherits the name of its parent until it calls execve
+        let comm = curr.task_ext().comm();
+        let mut new_task = new_task(&comm, pid);
 
-        let pid = new_task.id().as_u64();
         let proc = if clone_flags.contains(CloneFlags::CLONE_PARENT) {
             // 共享父进程
             let ppid = self.ppid.load(Ordering::Relaxed);
-            let proc = new_process(ppid, pid, new_aspace.clone());
+            let proc = new_process(ppid, pid, new_aspace.clone(), vm_areas);
             // 将子进程加入父进程的子进程列表
             // 由于现有进程模型的限制，系统进程不会被加入到进程管理器中
-            get_process(ppid).map(|p| p.children.lock().push(proc.clone()));
+            get_process(ppid).map(|p| p.children.lock().insert(proc.clone()));
             proc
         } else {
-            let proc = new_process(self.pid, pid, new_aspace.clone());
-            self.children.lock().push(proc.clone());
+            let proc = new_process(self.pid, pid, new_aspace.clone(), vm_areas);
+            self.children.lock().insert(proc.clone());
             proc
         };
 
+        *proc.pid_ns.lock() = pid_ns;
+        *proc.cred.lock() = self.cred();
+        proc.umask
+            .store(self.umask.load(Ordering::Relaxed), Ordering::Relaxed);
+        proc.pgid.store(self.pgid(), Ordering::Relaxed);
+        // 与父进程同级的子进程沿用父进程的退出信号
+        let exit_signal = if clone_flags.contains(CloneFlags::CLONE_PARENT) {
+            self.exit_signal.load(Ordering::Relaxed)
+        } else {
+            exit_signal as u32
+        };
+        proc.exit_signal.store(exit_signal, Ordering::Relaxed);
+        // 子进程的地址空间与父进程的相同，无论共享还是复制，堆也相同
+        for (dst, src) in [
+            (&proc.heap_bottom, &self.heap_bottom),
+            (&proc.heap_top, &self.heap_top),
+            (&proc.heap_current, &self.heap_current),
+        ] {
+            dst.store(src.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+        proc.mmap_base
+            .store(self.mmap_base.load(Ordering::SeqCst), Ordering::SeqCst);
+        *proc.exe.lock() = self.exe.lock().clone();
+        *proc.arg_env.lock() = self.arg_env.lock().clone();
+        *proc.exec_args.lock() = self.exec_args();
+        proc.signal_trampoline.store(
+            self.signal_trampoline.load(Ordering::SeqCst),
+            Ordering::SeqCst,
+        );
+        proc.personality
+            .store(self.personality(), Ordering::Relaxed);
+        proc.sid.store(self.sid(), Ordering::Relaxed);
+        proc.strace
+            .store(self.strace.load(Ordering::Relaxed), Ordering::Relaxed);
+        *proc.strace_output.lock() = self.strace_output.lock().clone();
+        let seccomp = self.seccomp.read(Seccomp::clone);
+        proc.seccomp.update(|s| *s = seccomp);
+        *proc.time_ns.lock() = time_ns.clone();
+        *proc.time_ns_for_children.lock() = time_ns;
+        let mnt_ns = self.mnt_ns.lock().clone();
+        *proc.mnt_ns.lock() = if clone_flags.contains(CloneFlags::CLONE_NEWNS) {
+            Arc::new(mnt_ns.fork())
+        } else {
+            mnt_ns
+        };
+
         let page_root = new_aspace.lock().page_table_root();
         new_task.ctx_mut().set_page_table_root(page_root);
 
-        trap_frame.regs.a0 = 0;
-        trap_frame.sepc += 4;
+        trap_frame.set_return_value(0);
+        trap_frame.advance_pc();
 
         if let Some(stack) = stack {
-            trap_frame.regs.sp = stack;
+            trap_frame.set_sp(stack);
         }
 
         let new_uctx = UspaceContext::from(&trap_frame);
 
-        let new_task_ext = TaskExt::new(new_uctx, &proc);
+        let new_task_ext = TaskExt::new(pid, &comm, new_uctx, &proc);
 
         // 共享文件描述符
         if clone_flags.contains(CloneFlags::CLONE_FILES) {
@@ -211,6 +647,14 @@ impl Process {
             new_task_ext.set_clear_child_tid(ctid as u64);
         }
 
+        // CLONE_SIGHAND 共享当前线程的信号处理函数表，否则复制一份
+        let sig_handler = curr.task_ext().signal().sig_handler.clone();
+        if clone_flags.contains(CloneFlags::CLONE_SIGHAND) {
+            new_task_ext.set_sig_handler(sig_handler);
+        } else {
+            new_task_ext.set_sig_handler(Arc::new(Mutex::new(sig_handler.lock().clone())));
+        }
+
         new_task_ext.init_ns();
         new_task.init_task_ext(new_task_ext);
 
@@ -229,49 +673,60 @@ impl Process {
         _tls: usize,
         ctid: usize,
     ) -> AxResult<u64> {
-        let clone_flags = CloneFlags::from_bits((flags & !0x3f) as u32).unwrap();
+        let clone_flags = CloneFlags::from_bits((flags & !CSIGNAL) as u32)
+            .ok_or(axerrno::AxError::InvalidInput)?;
         assert!(clone_flags.contains(CloneFlags::CLONE_THREAD));
 
-        let mut new_task = new_task();
+        let tid = alloc_tid(&self.pid_ns.lock()).ok_or(axerrno::AxError::NoMemory)?;
 
         let curr_task = current();
         let proc = curr_task.task_ext().get_proc().unwrap();
+        let comm = curr_task.task_ext().comm();
+        let mut new_task = new_task(&comm, tid);
 
         let mut trap_frame =
             read_trap_frame_from_kstack(curr_task.kernel_stack_top().unwrap().as_usize());
 
-        trap_frame.regs.a0 = 0;
-        trap_frame.sepc += 4;
+        trap_frame.set_return_value(0);
+        trap_frame.advance_pc();
 
         if let Some(stack) = stack {
-            trap_frame.regs.sp = stack;
+            trap_frame.set_sp(stack);
         }
 
         let new_uctx = UspaceContext::from(&trap_frame);
-        let new_task_ext = TaskExt::new(new_uctx, &proc);
+        let new_task_ext = TaskExt::new(tid, &comm, new_uctx, &proc);
         new_task_ext.init_fs_shared();
 
         if clone_flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
             new_task_ext.set_clear_child_tid(ctid as u64);
         }
 
+        // 同一进程的线程共享信号处理函数表
+        new_task_ext.set_sig_handler(curr_task.task_ext().signal().sig_handler.clone());
         new_task_ext.init_ns();
         new_task.init_task_ext(new_task_ext);
 
         let new_task_ref = axtask::spawn_task(new_task);
         proc.add_thread(new_task_ref);
 
-        Ok(proc.pid)
+        Ok(tid)
     }
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
         info!("Process {} dropped", self.pid);
+        // 地址空间随之释放时，其中的零页不能被当作它的页释放
+        if Arc::strong_count(&self.aspace) == 1 {
+            crate::mm::zero::release_all(&self.aspace.lock());
+        }
+        // 进程已被回收，其 pid 可以重新使用
+        dealloc_tid(self.pid);
     }
 }
 
-fn new_task() -> TaskInner {
+fn new_task(comm: &str, tid: u64) -> TaskInner {
     TaskInner::new(
         || {
             let curr = current();
@@ -284,7 +739,7 @@ fn new_task() -> TaskInner {
             );
             unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
         },
-        String::from(current().id_name()),
+        task_name(comm, tid),
         crate::config::KERNEL_STACK_SIZE,
     )
 }
@@ -0,0 +1,246 @@
+//! Allocation of process and thread IDs.
+//!
+//! Pids and tids share one ID space, as on Linux: the main thread of a process
+//! has `tid == pid`, and other threads get IDs which no process can collide with.
+//!
+//! IDs are handed out in increasing order, wrapping around at
+//! `kernel/pid_max`. The pid of a process is only freed when the process goes
+//! away, once it has been reaped, so that a zombie's pid is never handed to
+//! another process while its parent may still wait for it.
+//!
+//! With `kernel/pid-sequential` set,
+//! the order restarts from `kernel/pid-base` before every testcase, so that
+//! the pids in its output are the same from one run to the next. Building
+//! with `AX_PID_BASE=<pid>` sets both at boot.
+//!
+//! The kernel knows every process and thread by its global ID, the one of the
+//! root pid namespace. A process created with `CLONE_NEWPID` starts a child
+//! namespace, where it is pid 1: a thread created in a namespace also gets an
+//! ID in it and in each of its ancestors, and the IDs crossing the syscall
+//! boundary are translated with [`to_user`] and [`from_user`]. A process
+//! outside the namespace of the caller is seen as pid 0.
+use crate::sync::AdaptiveMutex;
+use crate::sysctl::{PID_BASE, PID_MAX, PID_SEQUENTIAL};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axtask::TaskExtRef;
+use lazy_static::lazy_static;
+
+/// IDs below this are reserved and never handed out. Pid 1 belongs to init.
+const RESERVED_IDS: u64 = 2;
+
+/// IDs are allocated in `[RESERVED_IDS, pid_max())`.
+fn pid_max() -> u64 {
+    PID_MAX.get() as u64
+}
+
+struct IdAllocator {
+    /// The lowest ID handed out
+    first: u64,
+    /// The next ID to try
+    next: u64,
+    /// The IDs currently in use
+    used: BTreeSet<u64>,
+}
+
+impl IdAllocator {
+    const fn new(first: u64) -> Self {
+        Self {
+            first,
+            next: first,
+            used: BTreeSet::new(),
+        }
+    }
+
+    /// Allocate the next free ID, wrapping around to the lowest one once
+    /// `pid_max()` is reached.
+    fn alloc(&mut self) -> Option<u64> {
+        let max = pid_max();
+        if self.used.range(self.first..max).count() as u64 >= max - self.first {
+            return None;
+        }
+        loop {
+            // `pid_max` may have been lowered since
+            let id = if self.next >= max {
+                self.first
+            } else {
+                self.next
+            };
+            self.next = if id + 1 >= max { self.first } else { id + 1 };
+            if self.used.insert(id) {
+                return Some(id);
+            }
+        }
+    }
+
+    fn dealloc(&mut self, id: u64) {
+        self.used.remove(&id);
+    }
+}
+
+static ID_ALLOCATOR: AdaptiveMutex<IdAllocator> =
+    AdaptiveMutex::new(IdAllocator::new(RESERVED_IDS));
+
+/// The namespace each global ID was allocated in.
+static OWNERS: AdaptiveMutex<BTreeMap<u64, Arc<PidNamespace>>> =
+    AdaptiveMutex::new(BTreeMap::new());
+
+/// The IDs of a child namespace, next to the global ones.
+struct LocalIds {
+    allocator: IdAllocator,
+    /// The local ID of each global one
+    local: BTreeMap<u64, u64>,
+    /// The global ID of each local one
+    global: BTreeMap<u64, u64>,
+}
+
+/// A pid namespace. The root one has no parent, and its IDs are the global
+/// ones.
+pub struct PidNamespace {
+    parent: Option<Arc<PidNamespace>>,
+    ids: AdaptiveMutex<LocalIds>,
+}
+
+lazy_static! {
+    static ref ROOT_NS: Arc<PidNamespace> = Arc::new(PidNamespace {
+        parent: None,
+        ids: AdaptiveMutex::new(LocalIds::new()),
+    });
+}
+
+impl LocalIds {
+    fn new() -> Self {
+        Self {
+            allocator: IdAllocator::new(1),
+            local: BTreeMap::new(),
+            global: BTreeMap::new(),
+        }
+    }
+}
+
+impl PidNamespace {
+    /// The root namespace, the one of the first process.
+    pub fn root() -> Arc<Self> {
+        ROOT_NS.clone()
+    }
+
+    /// A new namespace below `self`.
+    pub fn new_child(self: &Arc<Self>) -> Arc<Self> {
+        Arc::new(Self {
+            parent: Some(self.clone()),
+            ids: AdaptiveMutex::new(LocalIds::new()),
+        })
+    }
+
+    /// `self` and its ancestors, except the root namespace.
+    fn chain(self: &Arc<Self>) -> Vec<Arc<Self>> {
+        let mut chain = Vec::new();
+        let mut ns = self.clone();
+        while let Some(parent) = ns.parent.clone() {
+            chain.push(ns);
+            ns = parent;
+        }
+        chain
+    }
+
+    /// The ID of the global ID `global` in this namespace, if it is visible
+    /// from here.
+    pub fn local(&self, global: u64) -> Option<u64> {
+        if self.parent.is_none() {
+            return Some(global);
+        }
+        self.ids.lock().local.get(&global).copied()
+    }
+
+    /// The global ID of `local` in this namespace, if it is in use.
+    pub fn global(&self, local: u64) -> Option<u64> {
+        if self.parent.is_none() {
+            return Some(local);
+        }
+        self.ids.lock().global.get(&local).copied()
+    }
+}
+
+/// Allocate a new thread ID in the namespace `ns`, which is also the pid if
+/// the thread is the main thread of a new process. Returns the global ID.
+pub fn alloc_tid(ns: &Arc<PidNamespace>) -> Option<u64> {
+    let global = ID_ALLOCATOR.lock().alloc()?;
+    let chain = ns.chain();
+    for (i, ns) in chain.iter().enumerate() {
+        let mut ids = ns.ids.lock();
+        let Some(local) = ids.allocator.alloc() else {
+            drop(ids);
+            release(&chain[..i], global);
+            ID_ALLOCATOR.lock().dealloc(global);
+            return None;
+        };
+        ids.local.insert(global, local);
+        ids.global.insert(local, global);
+    }
+    OWNERS.lock().insert(global, ns.clone());
+    Some(global)
+}
+
+/// Release the IDs of the global ID `global` in the namespaces `chain`.
+fn release(chain: &[Arc<PidNamespace>], global: u64) {
+    for ns in chain {
+        let mut ids = ns.ids.lock();
+        if let Some(local) = ids.local.remove(&global) {
+            ids.global.remove(&local);
+            ids.allocator.dealloc(local);
+        }
+    }
+}
+
+/// Release a thread ID so that it can be reused.
+pub fn dealloc_tid(tid: u64) {
+    if let Some(ns) = OWNERS.lock().remove(&tid) {
+        release(&ns.chain(), tid);
+    }
+    ID_ALLOCATOR.lock().dealloc(tid);
+}
+
+/// The pid namespace of the current process, the root one for the kernel.
+pub fn current_ns() -> Arc<PidNamespace> {
+    let curr = axtask::current();
+    // Safety: We only check whether the task extended data is null and do not access it.
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        return PidNamespace::root();
+    }
+    match curr.task_ext().get_proc() {
+        Some(proc) => proc.pid_ns.lock().clone(),
+        None => PidNamespace::root(),
+    }
+}
+
+/// The global ID `global` as the current process sees it, 0 if it is outside
+/// its namespace.
+pub fn to_user(global: u64) -> u64 {
+    current_ns().local(global).unwrap_or(0)
+}
+
+/// The global ID of the ID `local` given by the current process, if it is in
+/// use in its namespace.
+pub fn from_user(local: u64) -> Option<u64> {
+    current_ns().global(local)
+}
+
+/// Apply `AX_PID_BASE`, if the kernel was built with it.
+pub fn init() {
+    let Some(base) = option_env!("AX_PID_BASE").filter(|base| !base.is_empty()) else {
+        return;
+    };
+    match base.parse().map(|base| PID_BASE.set(base)) {
+        Ok(Ok(())) => PID_SEQUENTIAL.set(1).unwrap(),
+        _ => warn!("Invalid AX_PID_BASE: {}", base),
+    }
+}
+
+/// Start the IDs over from `kernel/pid-base` if they are sequential. The IDs
+/// still in use are skipped.
+pub fn restart_sequence() {
+    if PID_SEQUENTIAL.get() != 0 {
+        ID_ALLOCATOR.lock().next = PID_BASE.get() as u64;
+    }
+}
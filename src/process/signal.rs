@@ -1,29 +1,36 @@
-use crate::process::get_process;
-use crate::signal::action::{SigActionFlags, SignalDefault, SIG_DFL, SIG_IGN};
+use crate::arch::TrapFrameExt;
+use crate::process::events::{self, ProcessEvent};
+use crate::process::pid::to_user;
+use crate::process::{all_processes, get_process, group_exit, ChildState, Process, WaitResult};
+use crate::ptr::{in_user_space, UserPtr};
+use crate::signal::action::{SigAction, SigActionFlags, SignalDefault, SIG_DFL, SIG_IGN};
 use crate::signal::info::SigInfo;
 use crate::signal::signal_no::SignalNo;
-use crate::signal::ucontext::{SignalStack, SignalUserContext};
+use crate::signal::ucontext::{set_handler_args, set_restorer, SignalStack, SignalUserContext};
 use crate::signal::{SignalHandler, SignalSet};
 use crate::syscall_imp::sys_exit;
 use crate::task::{read_trap_frame_from_kstack, write_trap_frame_to_kstack};
+use crate::trace::{self, TraceEvent};
 use alloc::sync::Arc;
-use axerrno::AxResult;
+use alloc::vec::Vec;
+use axerrno::{AxResult, LinuxError, LinuxResult};
 use axhal::arch::TrapFrame;
-use axhal::paging::MappingFlags;
-use axstd::os::arceos::modules::axconfig;
+use axhal::time::monotonic_time;
+use axhal::trap::{register_trap_handler, ILLEGAL_INSTRUCTION};
 use axsync::Mutex;
-use axtask::{current, TaskExtRef};
+use axtask::{current, yield_now, TaskExtRef, WaitQueue};
 use core::sync::atomic::Ordering;
+use core::time::Duration;
 use linkme::distributed_slice;
 
 const USER_SIGNAL_PROTECT: usize = 512;
 
+/// 线程的信号状态，每个线程一个，位于其 [`TaskExt`](crate::task::TaskExt) 中
 pub struct SignalModule {
     pub sig_info: bool,
     pub last_trap_frame: Option<TrapFrame>,
     pub sig_handler: Arc<Mutex<SignalHandler>>,
     pub sig_set: SignalSet,
-    exit_sig: Option<SignalNo>,
     pub stack: SignalStack,
 }
 
@@ -38,7 +45,6 @@ impl SignalModule {
             last_trap_frame,
             sig_handler,
             sig_set,
-            exit_sig: None,
             stack: SignalStack::default(),
         }
     }
@@ -48,14 +54,6 @@ impl SignalModule {
             .find_sig()
             .map(|sig_num| self.sig_handler.lock().get_action(sig_num).need_restart())
     }
-
-    pub fn set_exit_signal(&mut self, sig_num: SignalNo) {
-        self.exit_sig = Some(sig_num);
-    }
-
-    pub fn get_exit_signal(&self) -> Option<SignalNo> {
-        self.exit_sig
-    }
 }
 
 #[no_mangle]
@@ -66,22 +64,29 @@ pub fn load_trap_for_signal() -> bool {
             return false;
         }
     }
-    let Some(proc) = task.task_ext().get_proc() else {
+    if task.task_ext().get_proc().is_none() {
         return false;
-    };
+    }
 
-    let mut sig_modules = proc.signal_module.lock();
-    let sig_module = sig_modules.get_mut(&task.id().as_u64()).unwrap();
+    let sig_module = task.task_ext().signal();
     if let Some(old_trap_frame) = sig_module.last_trap_frame {
         let mut now_trap_frame =
             read_trap_frame_from_kstack(task.kernel_stack_top().unwrap().as_usize());
-        let sp = now_trap_frame.regs.sp;
+        let sp = now_trap_frame.sp();
         now_trap_frame = old_trap_frame;
         if sig_module.sig_info {
-            let pc = unsafe { (*(sp as *const SignalUserContext)).get_pc() };
-            now_trap_frame.sepc = pc;
+            let Ok(ucontext) = UserPtr::<SignalUserContext>::from(sp).read() else {
+                // 信号栈帧已无法读取
+                drop(sig_module);
+                terminate_process(SignalNo::SIGSEGV);
+            };
+            now_trap_frame.set_ip(ucontext.get_pc());
         }
         write_trap_frame_to_kstack(task.kernel_stack_top().unwrap().as_usize(), now_trap_frame);
+        // 处理期间到达的信号在返回用户态前投递
+        if sig_module.sig_set.find_sig().is_some() {
+            task.task_ext().set_signal_pending();
+        }
         true
     } else {
         false
@@ -96,35 +101,62 @@ pub fn handle_signals() {
         // 系统进程不会收到信号，所以不需要处理
         return;
     }
-    let proc = task.task_ext().get_proc().unwrap();
-    if proc.is_exited.load(Ordering::Relaxed) {
-        // 进程已经退出，不再处理信号
+    let interrupted = task.task_ext().take_interrupted_syscall();
+    // 没有信号待处理时直接返回，不必查看进程与加锁。进程退出或停止时，
+    // 其他线程的标志也会被置位
+    if !task.task_ext().take_signal_pending() {
+        return;
+    }
+    let Some(proc) = task.task_ext().get_proc() else {
+        sys_exit(0);
+    };
+    if proc.is_exiting() {
+        // 进程正在退出，不再处理信号
         sys_exit(0);
     }
-    let mut sig_modules = proc.signal_module.lock();
-
-    let sig_module = sig_modules.get_mut(&task.id().as_u64()).unwrap();
+    // 进程被停止时，其他线程返回用户态前也要停下
+    wait_while_stopped(&proc);
+    let mut guard = task.task_ext().signal();
+    let sig_module = &mut *guard;
     let sig_set = &mut sig_module.sig_set;
-    let sig_num = if let Some(sig_num) = sig_set.get_one_sig() {
-        sig_num
+    let (sig_num, sig_info) = if let Some(sig) = sig_set.get_one_sig() {
+        sig
     } else {
         return;
     };
+    if sig_set.find_sig().is_some() {
+        // 还有其他信号，下次返回用户态时继续处理
+        task.task_ext().set_signal_pending();
+    }
 
     let signal = SignalNo::from(sig_num);
-    let mask = sig_set.mask;
+    let mask = sig_set.mask();
 
     if sig_module.last_trap_frame.is_some() {
         // 之前的信号处理还没有完成
         // 产生了信号嵌套
-        if signal == SignalNo::SIGSEGV || signal == SignalNo::SIGBUS {
-            // 在处理信号的过程中又触发 SIGSEGV 或 SIGBUS，此时会导致死循环，所以直接结束当前进程
-            drop(sig_modules);
-            sys_exit(-1);
+        if matches!(
+            signal,
+            SignalNo::SIGSEGV | SignalNo::SIGBUS | SignalNo::SIGILL
+        ) {
+            // 在处理信号的过程中又触发 SIGSEGV、SIGBUS 或 SIGILL，此时会导致死循环，所以直接结束当前进程
+            drop(guard);
+            group_exit(-1);
         }
         return;
     }
 
+    if let Some((syscall_num, arg0)) = interrupted {
+        // 被信号打断的系统调用按信号的处理方式决定是否重新执行
+        let action = sig_module.sig_handler.lock().get_action(sig_num).clone();
+        if restarts(signal, &action) {
+            let kstack_top = task.kernel_stack_top().unwrap().as_usize();
+            let mut trap_frame = read_trap_frame_from_kstack(kstack_top);
+            trap_frame.restart_syscall(syscall_num, arg0);
+            write_trap_frame_to_kstack(kstack_top, trap_frame);
+        }
+    }
+
     // 保存当前的 trap frame
     sig_module.last_trap_frame = Some(read_trap_frame_from_kstack(
         task.kernel_stack_top().unwrap().as_usize(),
@@ -135,25 +167,42 @@ pub fn handle_signals() {
     // 处理信号
     let sig_handler = sig_module.sig_handler.lock();
     let action = sig_handler.get_action(sig_num).clone();
+    trace::record(
+        TraceEvent::SignalDeliver,
+        sig_num as u64,
+        action.sa_handler as u64,
+    );
     if action.sa_handler == SIG_DFL {
         drop(sig_handler);
-        drop(sig_modules);
+        drop(guard);
         match SignalDefault::get_action(signal) {
             SignalDefault::Ignore => {
                 // 忽略，此时相当于已经完成了处理，所以要把trap上下文清空
                 load_trap_for_signal();
             }
             SignalDefault::Terminate => {
-                terminate_process(signal, None);
+                terminate_process(signal);
             }
             SignalDefault::Stop => {
-                unimplemented!();
+                load_trap_for_signal();
+                if !proc.stopped.swap(true, Ordering::AcqRel) {
+                    proc.stop_threads();
+                    let state = ChildState::Stopped(sig_num as u32);
+                    *proc.state_change.lock() = Some(state);
+                    events::emit(ProcessEvent::Stopped {
+                        pid: proc.pid,
+                        signal: sig_num as u32,
+                    });
+                    notify_parent(&proc, state);
+                }
+                wait_while_stopped(&proc);
             }
             SignalDefault::Cont => {
-                unimplemented!();
+                // 发送 SIGCONT 时进程已经继续执行，这里只需忽略
+                load_trap_for_signal();
             }
             SignalDefault::Core => {
-                terminate_process(signal, None);
+                terminate_process(signal);
             }
         }
         return;
@@ -171,64 +220,81 @@ pub fn handle_signals() {
         debug!("Use alternate stack");
         (sig_module.stack.sp + sig_module.stack.size - 1) & !0xf
     } else {
-        trap_frame.regs.sp - USER_SIGNAL_PROTECT
+        (trap_frame.sp() - USER_SIGNAL_PROTECT) & !0xf
     };
 
     debug!("user signal stack: {:#x}", sp);
 
-    let restorer = if let Some(addr) = action.get_storer() {
-        addr
-    } else {
-        axconfig::SIGNAL_TRAMPOLINE
-    };
+    let restorer = action
+        .get_storer()
+        .unwrap_or_else(|| proc.signal_trampoline.load(Ordering::Relaxed));
 
     debug!(
         "restorer: {:#x}, handler: {:#x}",
         restorer, action.sa_handler
     );
+    if !in_user_space(restorer) {
+        // 处理函数无法返回，与 Linux 无法建立信号栈帧时相同，强制以 SIGSEGV 结束
+        warn!(
+            "{}: signal restorer {:#x} is not in user space",
+            task.id_name(),
+            restorer
+        );
+        drop(sig_handler);
+        drop(guard);
+        terminate_process(SignalNo::SIGSEGV);
+    }
 
-    let old_pc = trap_frame.sepc;
+    let old_pc = trap_frame.ip();
 
-    trap_frame.sepc = action.sa_handler;
-    trap_frame.regs.a0 = sig_num;
+    trap_frame.set_ip(action.sa_handler);
+    let mut info_addr = 0;
+    let mut ucontext_addr = 0;
     if action.sa_flags.contains(SigActionFlags::SA_SIGINFO) {
         sig_module.sig_info = true;
-        let sp_base = (((sp - core::mem::size_of::<SigInfo>()) & !0xf)
-            - core::mem::size_of::<SignalUserContext>())
-            & !0xf;
-
-        proc.alloc_range_lazy(sp_base.into(), sp.into(), MappingFlags::all())
-            .expect("failed to alloc signal stack");
-
         sp = (sp - core::mem::size_of::<SigInfo>()) & !0xf;
-        let info = if let Some(info) = sig_set.info.get(&(sig_num - 1)) {
-            info!("test SigInfo: {:?}", info.0.si_val_int);
-            info.0
+        let info = if let Some(mut info) = sig_info {
+            info!("test SigInfo: {:?}", info.si_val_int);
+            // 发送者的 pid 以接收者所在的 pid 命名空间表示，其他信号在此处另有内容
+            if info.has_sender() {
+                info.pid = to_user(info.pid as u64) as i32;
+            }
+            info
         } else {
             SigInfo {
                 si_signo: sig_num as i32,
                 ..Default::default()
             }
         };
-        unsafe {
-            *(sp as *mut SigInfo) = info;
-        }
-        trap_frame.regs.a1 = sp;
+        info_addr = sp;
 
         sp = (sp - core::mem::size_of::<SignalUserContext>()) & !0xf;
 
         let ucontext = SignalUserContext::init(old_pc, mask);
-        unsafe {
-            *(sp as *mut SignalUserContext) = ucontext;
+        ucontext_addr = sp;
+        let written = UserPtr::<SigInfo>::from(info_addr)
+            .write(info)
+            .and_then(|_| UserPtr::<SignalUserContext>::from(ucontext_addr).write(ucontext));
+        if written.is_err() {
+            // 信号栈无法写入，与 Linux 无法建立信号栈帧时相同，强制以 SIGSEGV 结束
+            warn!("{}: bad signal stack {:#x}", task.id_name(), sp);
+            drop(sig_handler);
+            drop(guard);
+            terminate_process(SignalNo::SIGSEGV);
         }
-        trap_frame.regs.a2 = sp;
     }
+    set_handler_args(&mut trap_frame, sig_num, info_addr, ucontext_addr);
 
-    trap_frame.regs.sp = sp;
+    trap_frame.set_sp(sp);
+    if set_restorer(&mut trap_frame, restorer).is_err() {
+        drop(sig_handler);
+        drop(guard);
+        terminate_process(SignalNo::SIGSEGV);
+    }
 
     write_trap_frame_to_kstack(task.kernel_stack_top().unwrap().as_usize(), trap_frame);
     drop(sig_handler);
-    drop(sig_modules);
+    drop(guard);
 }
 
 pub fn signal_return() -> isize {
@@ -240,26 +306,291 @@ pub fn signal_return() -> isize {
     }
 }
 
-fn terminate_process(signal: SignalNo, info: Option<SigInfo>) {
-    let task = current();
-    let proc = task.task_ext().get_proc().unwrap();
+/// 以信号 `signal` 终止当前进程
+pub fn terminate_process(signal: SignalNo) -> ! {
+    let proc = current().task_ext().get_proc().unwrap();
     warn!("Terminate process: {}", proc.pid);
-    if proc.is_main_thread(task.as_task_ref()) {
-        sys_exit(signal as i32)
-    } else {
-        send_signal_to_proc(proc.pid, signal as isize, info).unwrap();
-        sys_exit(-1)
+    if !proc.is_exiting() {
+        proc.term_signal.store(signal as u32, Ordering::Relaxed);
     }
+    group_exit(signal as i32)
 }
 
 pub fn send_signal_to_proc(pid: u64, signal: isize, info: Option<SigInfo>) -> AxResult<()> {
     let Some(proc) = get_process(pid) else {
         return Err(axerrno::AxError::NotFound);
     };
+    // SIGCONT 和 SIGKILL 在发送时就让停止的进程继续执行
+    if signal == SignalNo::SIGCONT as isize || signal == SignalNo::SIGKILL as isize {
+        let was_stopped = proc.stopped.swap(false, Ordering::AcqRel);
+        if was_stopped && signal == SignalNo::SIGCONT as isize {
+            *proc.state_change.lock() = Some(ChildState::Continued);
+            events::emit(ProcessEvent::Continued { pid });
+            notify_parent(&proc, ChildState::Continued);
+        }
+    }
     let main_thread = proc.main_thread();
-    let mut sig_modules = proc.signal_module.lock();
-    let sig_module = sig_modules.get_mut(&main_thread.id().as_u64()).unwrap();
-    sig_module.sig_set.try_add_sig(signal as usize, info);
-    // TODO: 如果主线程休眠，则唤醒处理信号
+    let mut sig_module = main_thread.task_ext().signal();
+    if !sig_module.sig_set.add_pending(signal as usize, info) {
+        // 实时信号队列已满
+        return Err(axerrno::AxError::WouldBlock);
+    }
+    main_thread.task_ext().set_signal_pending();
+    // 主线程阻塞在可中断的等待中时将其唤醒，使系统调用返回 EINTR
+    if interrupts(&sig_module, signal as usize) {
+        main_thread.task_ext().interrupt();
+    }
     Ok(())
 }
+
+/// SIGILL 的 si_code：非法的操作码
+const ILL_ILLOPC: i32 = 1;
+
+/// 用户程序执行了无法识别的指令，例如尚未支持的扩展中的指令
+///
+/// 记录 pc 和指令的字节，并以 [`force_signal`] 向当前线程发送 SIGILL。
+/// 内核自身的非法指令仍交给 axhal 处理（panic）。
+#[register_trap_handler(ILLEGAL_INSTRUCTION)]
+fn handle_illegal_instruction(tf: &TrapFrame, is_user: bool) -> bool {
+    let task = current();
+    if !is_user || unsafe { task.task_ext_ptr().is_null() } {
+        return false;
+    }
+    let pc = tf.ip();
+    match UserPtr::<[u8; 4]>::from(pc).read() {
+        // 在允许用户态读取计数器之前执行的 vDSO 读时钟指令，允许后重新执行即可
+        Ok(bytes) if crate::mm::vdso::handle_counter_read(u32::from_le_bytes(bytes)) => {
+            return true
+        }
+        Ok(bytes) => warn!(
+            "{}: illegal instruction at {:#x}: {:02x?}, sending SIGILL",
+            task.id_name(),
+            pc,
+            bytes
+        ),
+        Err(_) => warn!(
+            "{}: illegal instruction at {:#x}, sending SIGILL",
+            task.id_name(),
+            pc
+        ),
+    }
+    force_signal(
+        SignalNo::SIGILL,
+        SigInfo {
+            si_signo: SignalNo::SIGILL as i32,
+            si_code: ILL_ILLOPC,
+            ..Default::default()
+        },
+    );
+    true
+}
+
+/// 向当前线程强制发送信号 `signal`：被阻塞时解除阻塞，被忽略时恢复默认处理，与 Linux 的
+/// force_sig 相同。用于由当前线程自身的操作引起、不能不处理的信号
+pub fn force_signal(signal: SignalNo, info: SigInfo) {
+    let task = current();
+    let sig_num = signal as usize;
+    let mut guard = task.task_ext().signal();
+    let sig_module = &mut *guard;
+    sig_module.sig_set.unblock(1 << (sig_num - 1));
+    let mut sig_handler = sig_module.sig_handler.lock();
+    if sig_handler.get_action(sig_num).sa_handler == SIG_IGN {
+        sig_handler.handlers[sig_num - 1] = SigAction::default();
+    }
+    drop(sig_handler);
+    sig_module.sig_set.add_pending(sig_num, Some(info));
+    task.task_ext().set_signal_pending();
+}
+
+/// 子进程 `child` 的状态变为 `state` 时通知其父进程：唤醒等待子进程的线程，
+/// 并按父进程对 SIGCHLD 的处理方式发送带有状态的信号。
+///
+/// 退出时发送子进程创建时指定的退出信号，为 0 时不发送；停止与继续总是发送 SIGCHLD，
+/// 父进程设置了 SA_NOCLDSTOP 时不发送。退出信号为 SIGCHLD 的子进程退出时，若父进程
+/// 忽略 SIGCHLD 或设置了 SA_NOCLDWAIT，子进程不成为僵尸进程，直接被回收；忽略时也不发送信号。
+pub fn notify_parent(child: &Process, state: ChildState) {
+    let Some(parent) = get_process(child.ppid.load(Ordering::Acquire)) else {
+        return;
+    };
+    let sigchld = SignalNo::SIGCHLD as usize;
+    let action = match parent.threads.lock().get(&parent.pid) {
+        Some(thread) => *thread
+            .task_ext()
+            .signal()
+            .sig_handler
+            .lock()
+            .get_action(sigchld),
+        None => SigAction::default(),
+    };
+    let ignored = action.sa_handler == SIG_IGN;
+    let signal = match state {
+        ChildState::Exited(_) | ChildState::Killed(_) => {
+            let exit_signal = child.exit_signal.load(Ordering::Relaxed) as usize;
+            let mut children = parent.children.lock();
+            if exit_signal == sigchld
+                && (ignored || action.sa_flags.contains(SigActionFlags::SA_NOCLDWAIT))
+            {
+                children.remove(child.pid);
+            } else {
+                children.set_exited(child.pid);
+            }
+            drop(children);
+            if exit_signal != sigchld {
+                exit_signal
+            } else if ignored {
+                0
+            } else {
+                sigchld
+            }
+        }
+        ChildState::Stopped(_) | ChildState::Continued => {
+            if action.sa_flags.contains(SigActionFlags::SA_NOCLDSTOP) {
+                0
+            } else {
+                sigchld
+            }
+        }
+    };
+    if signal != 0 {
+        let mut info = WaitResult {
+            pid: child.pid,
+            uid: child.cred().uid,
+            state,
+        }
+        .siginfo();
+        info.si_signo = signal as i32;
+        let _ = send_signal_to_proc(parent.pid, signal as isize, Some(info));
+    }
+    parent.notify_child_change();
+}
+
+/// Send `signal` to every process in the process group `pgid`.
+pub fn send_signal_to_pgrp(pgid: u64, signal: isize) -> AxResult<()> {
+    let members: Vec<u64> = all_processes()
+        .iter()
+        .filter(|proc| proc.pgid() == pgid)
+        .map(|proc| proc.pid)
+        .collect();
+    if members.is_empty() {
+        return Err(axerrno::AxError::NotFound);
+    }
+    for pid in members {
+        send_signal_to_proc(pid, signal, None)?;
+    }
+    Ok(())
+}
+
+/// Whether the current thread has a signal to handle, which should interrupt
+/// a blocking syscall. An exit of the whole process counts as one.
+pub fn has_pending_signal() -> bool {
+    let task = current();
+    if !task.task_ext().signal_pending() {
+        return false;
+    }
+    let proc = task.task_ext().get_proc().unwrap();
+    if proc.is_exiting() {
+        return true;
+    }
+    let pending = task.task_ext().signal().sig_set.find_sig().is_some();
+    if !pending && !proc.stopped.load(Ordering::Acquire) {
+        // 标志已过时，例如信号已被阻塞，清除它以免等待立刻返回。
+        // 进程停止时保留标志，使线程返回用户态前停下
+        task.task_ext().clear_signal_pending();
+    }
+    pending
+}
+
+/// Whether `signal` interrupts a blocking syscall: it must be delivered, and
+/// do something once it is.
+fn interrupts(sig_module: &SignalModule, signal: usize) -> bool {
+    if sig_module.sig_set.is_blocked(signal) {
+        return false;
+    }
+    let handler = sig_module.sig_handler.lock().get_action(signal).sa_handler;
+    match handler {
+        SIG_IGN => false,
+        SIG_DFL => !matches!(
+            SignalDefault::get_action(SignalNo::from(signal)),
+            SignalDefault::Ignore
+        ),
+        _ => true,
+    }
+}
+
+/// Whether a syscall interrupted by `signal`, which has the action `action`,
+/// is made again: if the signal runs no handler, or if the handler asks for
+/// it with `SA_RESTART`.
+fn restarts(signal: SignalNo, action: &SigAction) -> bool {
+    match action.sa_handler {
+        SIG_IGN => true,
+        SIG_DFL => !matches!(
+            SignalDefault::get_action(signal),
+            SignalDefault::Terminate | SignalDefault::Core
+        ),
+        _ => action.need_restart(),
+    }
+}
+
+/// Sleep on `wq` until `condition` holds, `timeout` elapses or a signal is to
+/// be handled by the current thread, which is woken up by its sender.
+///
+/// # Returns
+/// Whether the timeout elapsed.
+///
+/// # Errors
+/// `EINTR` if a signal arrived before `condition` held.
+pub fn wait_interruptible(
+    wq: &WaitQueue,
+    timeout: Option<Duration>,
+    condition: impl Fn() -> bool,
+) -> LinuxResult<bool> {
+    let task = current();
+    let task_ext = task.task_ext();
+    let deadline = timeout.map(|timeout| monotonic_time() + timeout);
+    // 标志的检查不需要加锁，可以放在等待条件中
+    let ready = || condition() || task_ext.signal_pending();
+    task_ext.set_interrupt_wq(Some(wq));
+    let res = loop {
+        let timed_out = match deadline {
+            Some(deadline) => {
+                wq.wait_timeout_until(deadline.saturating_sub(monotonic_time()), ready)
+            }
+            None => {
+                wq.wait_until(ready);
+                false
+            }
+        };
+        if condition() {
+            break Ok(false);
+        }
+        if has_pending_signal() {
+            break Err(LinuxError::EINTR);
+        }
+        if timed_out {
+            break Ok(true);
+        }
+    };
+    task_ext.set_interrupt_wq(None);
+    res
+}
+
+/// Whether the current thread ignores or blocks `signal`.
+pub fn is_ignored_or_blocked(signal: SignalNo) -> bool {
+    let sig_module = current().task_ext().signal();
+    let sig_num = signal as usize;
+    sig_module.sig_set.is_blocked(sig_num)
+        || sig_module.sig_handler.lock().get_action(sig_num).sa_handler == SIG_IGN
+}
+
+/// 进程被停止时，等待 SIGCONT 或 SIGKILL 使其继续执行
+fn wait_while_stopped(proc: &Process) {
+    if !proc.stopped.load(Ordering::Acquire) {
+        return;
+    }
+    // 停止期间不计入 perf 事件，它们可能已在返回用户态时开始计数
+    crate::perf::leave_user();
+    while proc.stopped.load(Ordering::Acquire) && !proc.is_exiting() {
+        yield_now();
+    }
+    crate::perf::enter_user();
+}
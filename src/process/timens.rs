@@ -0,0 +1,127 @@
+//! Time namespaces: per-process offsets of the monotonic clocks.
+//!
+//! Processes in a namespace see `CLOCK_MONOTONIC` and `CLOCK_BOOTTIME` shifted by
+//! the offsets of the namespace, so that a restored checkpoint or a test in
+//! deterministic-time mode observes a continuous timeline.
+//!
+//! As on Linux, a new namespace is made by `unshare(CLONE_NEWTIME)` for the
+//! children of the caller, and its offsets can only be set until the first of
+//! them enters it: the clocks of a process never jump.
+//!
+//! The vDSO reads the offset of `CLOCK_MONOTONIC` from a page of the
+//! namespace, mapped in the address spaces of its processes.
+use alloc::sync::Arc;
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+
+use crate::mm::Frame;
+
+pub const CLOCK_MONOTONIC: u32 = 1;
+pub const CLOCK_MONOTONIC_RAW: u32 = 4;
+pub const CLOCK_MONOTONIC_COARSE: u32 = 6;
+pub const CLOCK_BOOTTIME: u32 = 7;
+
+/// The clock offsets of a time namespace, in nanoseconds.
+#[derive(Default)]
+pub struct TimeNamespace {
+    monotonic_offset: AtomicI64,
+    boottime_offset: AtomicI64,
+    /// Whether a process has entered the namespace, after which the offsets
+    /// are fixed.
+    frozen: AtomicBool,
+    /// The page of the vDSO holding the offset of `CLOCK_MONOTONIC`
+    vdso_page: Mutex<Option<Arc<Frame>>>,
+}
+
+lazy_static! {
+    /// The namespace of the first process, which has no offsets.
+    static ref INIT_NS: Arc<TimeNamespace> = {
+        let ns = Arc::new(TimeNamespace::default());
+        ns.enter();
+        ns
+    };
+}
+
+/// The namespace of the first process.
+pub fn init_ns() -> Arc<TimeNamespace> {
+    INIT_NS.clone()
+}
+
+impl TimeNamespace {
+    /// A new namespace starting with the offsets of `self`, which no process
+    /// has entered yet.
+    pub fn fork(&self) -> Self {
+        Self {
+            monotonic_offset: AtomicI64::new(self.monotonic_offset.load(Ordering::Relaxed)),
+            boottime_offset: AtomicI64::new(self.boottime_offset.load(Ordering::Relaxed)),
+            frozen: AtomicBool::new(false),
+            vdso_page: Mutex::new(None),
+        }
+    }
+
+    /// Note that a process entered the namespace.
+    pub fn enter(&self) {
+        self.frozen.store(true, Ordering::Release);
+    }
+
+    /// The offset applied to `clock_id`, in nanoseconds.
+    pub fn offset(&self, clock_id: u32) -> i64 {
+        match clock_id {
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE => {
+                self.monotonic_offset.load(Ordering::Relaxed)
+            }
+            CLOCK_BOOTTIME => self.boottime_offset.load(Ordering::Relaxed),
+            _ => 0,
+        }
+    }
+
+    /// Set the offset of `CLOCK_MONOTONIC` or `CLOCK_BOOTTIME`, which fails
+    /// with `EACCES` once a process has entered the namespace, and with
+    /// `ERANGE` if it would make the clock negative.
+    pub fn set_offset(&self, clock_id: u32, offset_ns: i64) -> LinuxResult {
+        if self.frozen.load(Ordering::Acquire) {
+            return Err(LinuxError::EACCES);
+        }
+        let offset = match clock_id {
+            CLOCK_MONOTONIC => &self.monotonic_offset,
+            CLOCK_BOOTTIME => &self.boottime_offset,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let now = axhal::time::monotonic_time_nanos();
+        if offset_ns < 0 && offset_ns.unsigned_abs() > now {
+            return Err(LinuxError::ERANGE);
+        }
+        offset.store(offset_ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The page the vDSO reads the offset of `CLOCK_MONOTONIC` from, made
+    /// the first time a process of the namespace asks for it. `None` without
+    /// an offset, the vDSO then reads a page of zeros.
+    pub fn vdso_page(&self) -> LinuxResult<Option<Arc<Frame>>> {
+        let offset = self.monotonic_offset.load(Ordering::Relaxed);
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut page = self.vdso_page.lock();
+        if page.is_none() {
+            let frame = Frame::alloc()?;
+            frame.as_mut_slice()[..8].copy_from_slice(&offset.to_le_bytes());
+            *page = Some(frame);
+        }
+        Ok(page.clone())
+    }
+
+    /// Apply the offset of `clock_id` to a time read from the host clock.
+    pub fn apply(&self, clock_id: u32, time: Duration) -> Duration {
+        let offset = self.offset(clock_id);
+        if offset >= 0 {
+            time + Duration::from_nanos(offset as u64)
+        } else {
+            time.saturating_sub(Duration::from_nanos(offset.unsigned_abs()))
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Checked access to user memory.
+//!
+//! Syscalls receive pointers into the address space of the calling process.
+//! Before the kernel dereferences one, the range it covers is checked against
+//! the mappings of that address space, so that a bad pointer fails the
+//! syscall with `EFAULT` instead of faulting in the kernel. Pages mapped but
+//! not populated yet are faulted in on access as usual.
+use crate::config;
+use crate::process::current_process;
+use alloc::string::String;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use core::ffi::{c_char, CStr};
+use core::marker::PhantomData;
+use memory_addr::{VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+/// Check that `[addr, addr + size)` is mapped in the address space of the
+/// calling process with at least the permissions `flags`.
+pub fn check_region(addr: usize, size: usize, flags: MappingFlags) -> LinuxResult {
+    if size == 0 {
+        return Ok(());
+    }
+    let end = addr.checked_add(size).ok_or(LinuxError::EFAULT)?;
+    let range = VirtAddrRange::new(VirtAddr::from(addr), VirtAddr::from(end));
+    let proc = current_process().ok_or(LinuxError::EFAULT)?;
+    if addr == 0 || !proc.aspace.lock().check_region_access(range, flags) {
+        return Err(LinuxError::EFAULT);
+    }
+    Ok(())
+}
+
+/// Whether `addr` is in the range of user addresses, mapped or not.
+pub fn in_user_space(addr: usize) -> bool {
+    (config::USER_SPACE_BASE..config::USER_SPACE_BASE + config::USER_SPACE_SIZE).contains(&addr)
+}
+
+/// A pointer to a `T` in user space.
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> From<usize> for UserPtr<T> {
+    fn from(addr: usize) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> From<*mut T> for UserPtr<T> {
+    fn from(ptr: *mut T) -> Self {
+        Self::from(ptr as usize)
+    }
+}
+
+impl<T> From<*const T> for UserPtr<T> {
+    fn from(ptr: *const T) -> Self {
+        Self::from(ptr as usize)
+    }
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Copy the value in from user space.
+    pub fn read(&self) -> LinuxResult<T> {
+        check_region(self.addr, core::mem::size_of::<T>(), MappingFlags::READ)?;
+        Ok(unsafe { (self.addr as *const T).read_unaligned() })
+    }
+
+    /// Copy the value in from user space, or `None` if the pointer is null.
+    pub fn read_opt(&self) -> LinuxResult<Option<T>> {
+        if self.is_null() {
+            return Ok(None);
+        }
+        self.read().map(Some)
+    }
+
+    /// Copy `value` out to user space.
+    pub fn write(&self, value: T) -> LinuxResult {
+        check_region(self.addr, core::mem::size_of::<T>(), MappingFlags::WRITE)?;
+        unsafe { (self.addr as *mut T).write_unaligned(value) };
+        Ok(())
+    }
+
+    /// Copy `value` out to user space, unless the pointer is null.
+    pub fn write_opt(&self, value: T) -> LinuxResult {
+        if self.is_null() {
+            return Ok(());
+        }
+        self.write(value)
+    }
+}
+
+/// An array of `len` `T`s in user space.
+pub struct UserSlice<T> {
+    addr: usize,
+    len: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> UserSlice<T> {
+    pub fn new(ptr: impl Into<UserPtr<T>>, len: usize) -> Self {
+        Self {
+            addr: ptr.into().addr,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    fn check(&self, flags: MappingFlags) -> LinuxResult {
+        if self.len == 0 {
+            return Ok(());
+        }
+        if self.addr % core::mem::align_of::<T>() != 0 {
+            return Err(LinuxError::EFAULT);
+        }
+        let size = self
+            .len
+            .checked_mul(core::mem::size_of::<T>())
+            .ok_or(LinuxError::EFAULT)?;
+        check_region(self.addr, size, flags)
+    }
+
+    /// Borrow the array for reading.
+    pub fn as_slice<'a>(&self) -> LinuxResult<&'a [T]> {
+        self.check(MappingFlags::READ)?;
+        if self.len == 0 {
+            return Ok(&[]);
+        }
+        Ok(unsafe { core::slice::from_raw_parts(self.addr as *const T, self.len) })
+    }
+
+    /// Borrow the array for writing.
+    pub fn as_mut_slice<'a>(&self) -> LinuxResult<&'a mut [T]> {
+        self.check(MappingFlags::WRITE)?;
+        if self.len == 0 {
+            return Ok(&mut []);
+        }
+        Ok(unsafe { core::slice::from_raw_parts_mut(self.addr as *mut T, self.len) })
+    }
+}
+
+/// Copy in the NUL-terminated string at `ptr` in user space.
+///
+/// The string is checked a page at a time, up to its terminating NUL, and
+/// copied so that the caller doesn't see user space change it. Fails with
+/// `EINVAL` if it isn't valid UTF-8.
+pub fn read_cstr(ptr: *const c_char) -> LinuxResult<String> {
+    let start = ptr as usize;
+    let mut addr = start;
+    loop {
+        let page_end = (addr & !(PAGE_SIZE_4K - 1)) + PAGE_SIZE_4K;
+        check_region(addr, page_end - addr, MappingFlags::READ)?;
+        let chunk = unsafe { core::slice::from_raw_parts(addr as *const u8, page_end - addr) };
+        if chunk.contains(&0) {
+            break;
+        }
+        addr = page_end;
+    }
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map(String::from)
+        .map_err(|_| LinuxError::EINVAL)
+}
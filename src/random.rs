@@ -0,0 +1,122 @@
+//! The kernel random number generator, behind `getrandom`, `/dev/random` and
+//! `/dev/urandom`.
+//!
+//! Output is ChaCha20 keystream. The key is replaced with fresh keystream
+//! after every request, so that output already handed out can't be
+//! recomputed from a later state. Entropy is collected into a SHA-256 pool,
+//! from the timing jitter sampled at boot, the times of the requests and
+//! whatever is written to the device files, and folded into the key at most
+//! every [`RESEED_INTERVAL`].
+//!
+//! The pool is never accounted, so both device files behave like
+//! `/dev/urandom` and reads never block.
+use crate::crypto::chacha20::{self, ChaCha20};
+use crate::crypto::Sha256;
+use crate::fs::devfs::{self, makedev, CharDevice};
+use alloc::sync::Arc;
+use axerrno::LinuxResult;
+use axsync::Mutex;
+use core::time::Duration;
+
+/// The minimum time between two reseeds.
+pub const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of timer samples taken for the initial seed.
+const BOOT_SAMPLES: usize = 256;
+
+struct Rng {
+    key: [u8; chacha20::KEY_SIZE],
+    pool: Sha256,
+    /// The number of reseeds so far, used as the nonce.
+    generation: u64,
+    last_reseed: Duration,
+}
+
+impl Rng {
+    fn reseed(&mut self, now: Duration) {
+        let mut hasher = core::mem::take(&mut self.pool);
+        hasher.update(&self.key);
+        self.key = hasher.finish();
+        self.generation += 1;
+        self.last_reseed = now;
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        let now = axhal::time::monotonic_time();
+        self.pool.update(&now.as_nanos().to_le_bytes());
+        if now - self.last_reseed >= RESEED_INTERVAL {
+            self.reseed(now);
+        }
+
+        let mut nonce = [0; chacha20::NONCE_SIZE];
+        nonce[..8].copy_from_slice(&self.generation.to_le_bytes());
+        let mut stream = ChaCha20::new(&self.key, &nonce, 0);
+        self.key
+            .copy_from_slice(&stream.next_block()[..chacha20::KEY_SIZE]);
+        buf.fill(0);
+        stream.apply_keystream(buf);
+    }
+}
+
+static RNG: Mutex<Option<Rng>> = Mutex::new(None);
+
+/// The initial seed: the boot time and the jitter between consecutive reads
+/// of the monotonic clock.
+fn boot_seed() -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update(&axhal::time::wall_time().as_nanos().to_le_bytes());
+    let mut last = axhal::time::monotonic_time_nanos();
+    for _ in 0..BOOT_SAMPLES {
+        let now = axhal::time::monotonic_time_nanos();
+        hasher.update(&(now - last).to_le_bytes());
+        last = now;
+    }
+    hasher
+}
+
+fn with_rng<R>(f: impl FnOnce(&mut Rng) -> R) -> R {
+    let mut rng = RNG.lock();
+    let rng = rng.get_or_insert_with(|| {
+        let mut rng = Rng {
+            key: [0; chacha20::KEY_SIZE],
+            pool: boot_seed(),
+            generation: 0,
+            last_reseed: Duration::ZERO,
+        };
+        rng.reseed(axhal::time::monotonic_time());
+        rng
+    });
+    f(rng)
+}
+
+/// Fill `buf` with random bytes.
+pub fn fill(buf: &mut [u8]) {
+    with_rng(|rng| rng.fill(buf));
+}
+
+/// Mix `data` into the entropy pool. It takes effect at the next reseed.
+pub fn add_entropy(data: &[u8]) {
+    with_rng(|rng| rng.pool.update(data));
+}
+
+/// `/dev/random` and `/dev/urandom`.
+struct RandomDevice;
+
+impl CharDevice for RandomDevice {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        add_entropy(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Seed the generator and register its device files.
+pub fn init() {
+    with_rng(|_| {});
+    devfs::register_chrdev("random", 0o666, makedev(1, 8), Arc::new(RandomDevice)).unwrap();
+    devfs::register_chrdev("urandom", 0o666, makedev(1, 9), Arc::new(RandomDevice)).unwrap();
+}
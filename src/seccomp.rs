@@ -0,0 +1,219 @@
+//! Syscall filtering of selected processes, in the style of seccomp.
+//!
+//! `prctl(PR_SET_SECCOMP, SECCOMP_MODE_STRICT)` leaves the calling process
+//! with `read`, `write`, `exit` and `rt_sigreturn` only, any other syscall
+//! killing it with `SIGKILL`, as on Linux.
+//!
+//! BPF filters aren't supported. Instead, `prctl(PR_SET_SECCOMP,
+//! SECCOMP_MODE_LIST, &list)` installs a [`SeccompList`]: a list of the
+//! syscalls allowed, or of the ones denied, and what a syscall which isn't
+//! allowed gets, with the `SECCOMP_RET_*` values of Linux:
+//!
+//! - `SECCOMP_RET_KILL_PROCESS` kills the process with `SIGSYS`;
+//! - `SECCOMP_RET_TRAP` sends `SIGSYS` to the thread, with `si_code` set to
+//!   `SYS_SECCOMP`, `si_errno` to the data of the action and the number of
+//!   the syscall in `si_syscall`, and the syscall fails with `ENOSYS`;
+//! - `SECCOMP_RET_ERRNO | errno` makes the syscall fail with `errno`.
+//!
+//! Filters are checked in the dispatcher, before the handler runs. They can
+//! only be added, never removed: a syscall must get through all of them.
+//! Children inherit them on fork, and they stay across `execve`.
+use crate::process::current_process;
+use crate::process::signal::{force_signal, terminate_process};
+use crate::ptr::{UserPtr, UserSlice};
+use crate::signal::info::SigInfo;
+use crate::signal::signal_no::SignalNo;
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
+use syscalls::Sysno;
+
+/// `prctl` option to get the seccomp mode of the calling process.
+pub const PR_GET_SECCOMP: i32 = 21;
+/// `prctl` option to set the seccomp mode of the calling process.
+pub const PR_SET_SECCOMP: i32 = 22;
+
+const SECCOMP_MODE_DISABLED: usize = 0;
+const SECCOMP_MODE_STRICT: usize = 1;
+/// The mode Linux uses for BPF filters, reported for lists as well.
+const SECCOMP_MODE_FILTER: usize = 2;
+/// The mode which installs a [`SeccompList`].
+///
+/// Specific to this kernel, so far from the modes Linux uses.
+pub const SECCOMP_MODE_LIST: usize = 0x5343_0001;
+
+/// [`SeccompList::kind`] of a list of the syscalls allowed.
+pub const SECCOMP_LIST_ALLOW: u32 = 0;
+/// [`SeccompList::kind`] of a list of the syscalls denied.
+pub const SECCOMP_LIST_DENY: u32 = 1;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ACTION: u32 = 0xffff_0000;
+const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+
+/// The `si_code` of a `SIGSYS` sent by a filter.
+const SYS_SECCOMP: i32 = 1;
+/// The highest errno value, as on Linux.
+const MAX_ERRNO: u32 = 4095;
+/// The most syscalls in a list, the most instructions of a BPF filter on Linux.
+const MAX_LIST_LEN: u32 = 4096;
+
+/// The syscalls left by the strict mode.
+const STRICT_SYSCALLS: [Sysno; 4] = [Sysno::read, Sysno::write, Sysno::exit, Sysno::rt_sigreturn];
+
+/// The argument of `SECCOMP_MODE_LIST`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SeccompList {
+    /// `SECCOMP_LIST_ALLOW` or `SECCOMP_LIST_DENY`
+    pub kind: u32,
+    /// What a syscall which isn't allowed gets
+    pub action: u32,
+    /// The number of syscalls at `syscalls`
+    pub len: u32,
+    pub pad: u32,
+    /// The numbers of the syscalls, as `u32`s
+    pub syscalls: u64,
+}
+
+/// What happens to a syscall which isn't allowed.
+#[derive(Clone, Copy)]
+enum Action {
+    /// Kill the process with the signal
+    Kill(SignalNo),
+    Trap(u16),
+    Errno(u16),
+}
+
+impl Action {
+    fn from_raw(raw: u32) -> LinuxResult<Self> {
+        let data = raw & SECCOMP_RET_DATA;
+        match raw & SECCOMP_RET_ACTION {
+            SECCOMP_RET_KILL_PROCESS if data == 0 => Ok(Self::Kill(SignalNo::SIGSYS)),
+            SECCOMP_RET_TRAP => Ok(Self::Trap(data as u16)),
+            SECCOMP_RET_ERRNO => Ok(Self::Errno(data.min(MAX_ERRNO) as u16)),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+}
+
+struct Filter {
+    deny: bool,
+    syscalls: BTreeSet<u32>,
+    action: Action,
+}
+
+impl Filter {
+    fn allows(&self, sysno: u32) -> bool {
+        self.syscalls.contains(&sysno) != self.deny
+    }
+}
+
+/// The filters of a process.
+#[derive(Clone, Default)]
+pub struct Seccomp {
+    strict: bool,
+    filters: Vec<Arc<Filter>>,
+}
+
+impl Seccomp {
+    fn mode(&self) -> usize {
+        if self.strict {
+            SECCOMP_MODE_STRICT
+        } else if !self.filters.is_empty() {
+            SECCOMP_MODE_FILTER
+        } else {
+            SECCOMP_MODE_DISABLED
+        }
+    }
+
+    /// What the syscall `sysno` gets, `None` if it is allowed.
+    fn check(&self, sysno: u32) -> Option<Action> {
+        if self.strict && !STRICT_SYSCALLS.iter().any(|&s| s as u32 == sysno) {
+            return Some(Action::Kill(SignalNo::SIGKILL));
+        }
+        self.filters
+            .iter()
+            .find(|filter| !filter.allows(sysno))
+            .map(|filter| filter.action)
+    }
+}
+
+/// The seccomp mode of the calling process, for `PR_GET_SECCOMP`.
+pub fn get_mode() -> usize {
+    current_process().unwrap().seccomp.read(Seccomp::mode)
+}
+
+/// Set the seccomp mode of the calling process to `mode`, for
+/// `PR_SET_SECCOMP`. `arg` is the [`SeccompList`] of `SECCOMP_MODE_LIST`.
+pub fn set_mode(mode: usize, arg: usize) -> LinuxResult {
+    let proc = current_process().unwrap();
+    match mode {
+        SECCOMP_MODE_STRICT => {
+            proc.seccomp.update(|seccomp| seccomp.strict = true);
+        }
+        SECCOMP_MODE_LIST => {
+            let list = UserPtr::<SeccompList>::from(arg).read()?;
+            if list.len > MAX_LIST_LEN {
+                return Err(LinuxError::EINVAL);
+            }
+            let deny = match list.kind {
+                SECCOMP_LIST_ALLOW => false,
+                SECCOMP_LIST_DENY => true,
+                _ => return Err(LinuxError::EINVAL),
+            };
+            let action = Action::from_raw(list.action)?;
+            let syscalls = UserSlice::new(
+                UserPtr::<u32>::from(list.syscalls as usize),
+                list.len as usize,
+            )
+            .as_slice()?;
+            let filter = Arc::new(Filter {
+                deny,
+                syscalls: syscalls.iter().copied().collect(),
+                action,
+            });
+            proc.seccomp.update(|seccomp| seccomp.filters.push(filter));
+        }
+        _ => return Err(LinuxError::EINVAL),
+    }
+    Ok(())
+}
+
+/// Check the syscall `sysno` made at `pc` against the filters of the calling
+/// process. Returns what it returns instead if it isn't allowed.
+pub fn check(sysno: usize, pc: usize) -> Option<isize> {
+    let action = current_process()?
+        .seccomp
+        .read(|seccomp| seccomp.check(sysno as u32))?;
+    match action {
+        Action::Kill(signal) => {
+            warn!(
+                "seccomp: syscall {} is not allowed, killing the process",
+                sysno
+            );
+            terminate_process(signal)
+        }
+        Action::Trap(data) => {
+            // `_sigsys` has the address of the call where the sender is,
+            // and the number of the syscall where the value is
+            force_signal(
+                SignalNo::SIGSYS,
+                SigInfo {
+                    si_signo: SignalNo::SIGSYS as i32,
+                    si_errno: data as i32,
+                    si_code: SYS_SECCOMP,
+                    pid: pc as u32 as i32,
+                    uid: (pc as u64 >> 32) as u32,
+                    si_val_int: sysno as i32,
+                    ..Default::default()
+                },
+            );
+            Some(-(LinuxError::ENOSYS.code() as isize))
+        }
+        Action::Errno(errno) => Some(-(errno as isize)),
+    }
+}
@@ -1,5 +1,8 @@
 use super::signal_no::SignalNo::*;
+use super::UNBLOCKABLE;
+use crate::ptr::in_user_space;
 use crate::signal::signal_no::SignalNo;
+use axerrno::{LinuxError, LinuxResult};
 use bitflags::bitflags;
 
 /// 特殊取值，代表默认处理函数
@@ -82,6 +85,9 @@ impl SignalDefault {
     }
 }
 
+/// rt_sigaction 使用的 `struct sigaction`
+///
+/// RISC-V 的内核不支持 SA_RESTORER，结构体中没有 `sa_restorer`
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct SigAction {
@@ -91,17 +97,36 @@ pub struct SigAction {
     pub sa_flags: SigActionFlags,
     /// 信号处理的跳板页地址，存储了sig_return的函数处理地址
     /// 仅在SA_RESTORER标志被设置时有效
+    #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
     pub sa_restorer: usize,
     /// 该信号处理函数的信号掩码
     pub sa_mask: usize,
 }
 
+// 与 Linux 各架构的 `struct sigaction` 布局一致，`sa_flags` 为 unsigned long 的低 32 位
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const _: () = {
+    assert!(core::mem::offset_of!(SigAction, sa_handler) == 0);
+    assert!(core::mem::offset_of!(SigAction, sa_flags) == 8);
+    assert!(core::mem::offset_of!(SigAction, sa_restorer) == 16);
+    assert!(core::mem::offset_of!(SigAction, sa_mask) == 24);
+    assert!(core::mem::size_of::<SigAction>() == 32);
+};
+#[cfg(target_arch = "riscv64")]
+const _: () = {
+    assert!(core::mem::offset_of!(SigAction, sa_handler) == 0);
+    assert!(core::mem::offset_of!(SigAction, sa_flags) == 8);
+    assert!(core::mem::offset_of!(SigAction, sa_mask) == 16);
+    assert!(core::mem::size_of::<SigAction>() == 24);
+};
+
 impl SigAction {
     /// get the restorer address of the signal action
     ///
     /// When the SA_RESTORER flag is set, the restorer address is valid
     ///
     /// or it will return None, and the core will set the restore address as the signal trampoline
+    #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
     pub fn get_storer(&self) -> Option<usize> {
         if self.sa_flags.contains(SigActionFlags::SA_RESTORER) {
             Some(self.sa_restorer)
@@ -110,6 +135,39 @@ impl SigAction {
         }
     }
 
+    /// RISC-V 上信号处理函数总是返回到内核的跳板页
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    pub fn get_storer(&self) -> Option<usize> {
+        None
+    }
+
+    /// 检查从用户态复制来的 `sig_num` 的新处理方式，并去掉其中无效的部分
+    ///
+    /// SIGKILL 与 SIGSTOP 的处理方式不能修改；处理函数和 SA_RESTORER 的恢复函数须位于
+    /// 用户地址空间。与 Linux 相同，未知的标志被忽略，掩码中不能阻塞的信号被去掉。
+    pub fn check(mut self, sig_num: usize) -> LinuxResult<Self> {
+        if sig_num == SIGKILL as usize || sig_num == SIGSTOP as usize {
+            return Err(LinuxError::EINVAL);
+        }
+        self.sa_flags = SigActionFlags::from_bits_truncate(self.sa_flags.bits());
+        if self.sa_handler != SIG_DFL
+            && self.sa_handler != SIG_IGN
+            && !in_user_space(self.sa_handler)
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        self.sa_flags.remove(SigActionFlags::SA_RESTORER);
+        if self
+            .get_storer()
+            .is_some_and(|restorer| !in_user_space(restorer))
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        self.sa_mask &= !UNBLOCKABLE;
+        Ok(self)
+    }
+
     /// Whether the syscall should be restarted after the signal handler returns
     pub fn need_restart(&self) -> bool {
         self.sa_flags.contains(SigActionFlags::SA_RESTART)
@@ -1,3 +1,5 @@
+use crate::signal::signal_no::SignalNo;
+
 /// The information of the signal
 ///
 /// When the `SigAction` specifies that it needs information, it will return it to the user
@@ -39,3 +41,12 @@ impl Default for SigInfo {
         }
     }
 }
+
+impl SigInfo {
+    /// Whether `pid` and `uid` are those of a sender: a process sending the
+    /// signal, or the child `SIGCHLD` is about. Other signals keep something
+    /// else there, like the address of the call for a `SIGSYS` of seccomp.
+    pub fn has_sender(&self) -> bool {
+        self.si_code <= 0 || self.si_signo == SignalNo::SIGCHLD as i32
+    }
+}
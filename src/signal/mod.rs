@@ -1,8 +1,7 @@
-use crate::signal::action::SigAction;
+use crate::signal::action::{SigAction, SIG_IGN};
 use crate::signal::info::SigInfo;
 use crate::signal::signal_no::{SignalNo, MAX_SIG_NUM};
-use crate::signal::ucontext::SignalUserContext;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 
 pub mod action;
 pub mod info;
@@ -28,12 +27,24 @@ impl SignalHandler {
         }
     }
 
+    /// execve 后新程序的处理方式：被忽略的信号仍被忽略，其余恢复默认处理，与 Linux 相同
+    pub fn reset_on_exec(&mut self) {
+        for action in self.handlers.iter_mut() {
+            let ignored = action.sa_handler == SIG_IGN;
+            *action = SigAction::default();
+            if ignored {
+                action.sa_handler = SIG_IGN;
+            }
+        }
+    }
+
     pub fn get_action(&self, sig_num: usize) -> &SigAction {
         &self.handlers[sig_num - 1]
     }
 
-    pub unsafe fn set_action(&mut self, sig_num: usize, action: *const SigAction) {
-        self.handlers[sig_num - 1] = unsafe { *action };
+    /// 设置 `sig_num` 的处理方式，`action` 须已经过 [`SigAction::check`] 检查
+    pub fn set_action(&mut self, sig_num: usize, action: SigAction) {
+        self.handlers[sig_num - 1] = action;
     }
 }
 
@@ -43,15 +54,32 @@ impl Default for SignalHandler {
     }
 }
 
-/// 接受信号的结构，每一个进程都有一个
+/// 每个线程最多排队的实时信号数
+pub const SIGQUEUE_MAX: usize = 1024;
+
+/// 是否为实时信号，实时信号会排队，而标准信号多次发送只算一次
+pub fn is_rt_signal(sig_num: usize) -> bool {
+    sig_num >= SignalNo::SIGRTMIN as usize
+}
+
+/// 不能被阻塞的信号
+const UNBLOCKABLE: usize =
+    (1 << (SignalNo::SIGKILL as usize - 1)) | (1 << (SignalNo::SIGSTOP as usize - 1));
+
+/// 接受信号的结构，每一个线程都有一个
+///
+/// 发送信号只会使其未决，是否投递由掩码决定。SIGKILL 与 SIGSTOP 不能被阻塞，
+/// 设置掩码时会被去掉。
 #[derive(Clone)]
 pub struct SignalSet {
     /// 信号掩码
-    pub mask: usize,
-    /// 未决信号集
-    pub pending: usize,
-    /// 附加信息
-    pub info: BTreeMap<usize, (SigInfo, SignalUserContext)>,
+    mask: usize,
+    /// 未决信号集，实时信号在队列中还有项时置位
+    pending: usize,
+    /// 标准信号的附加信息
+    info: BTreeMap<usize, SigInfo>,
+    /// 排队的实时信号，按发送顺序
+    rt_queue: VecDeque<SigInfo>,
 }
 
 impl SignalSet {
@@ -60,47 +88,101 @@ impl SignalSet {
             mask: 0,
             pending: 0,
             info: BTreeMap::new(),
+            rt_queue: VecDeque::new(),
         }
     }
 
     pub fn clear(&mut self) {
         self.mask = 0;
         self.pending = 0;
+        self.info.clear();
+        self.rt_queue.clear();
     }
 
-    pub fn find_sig(&self) -> Option<usize> {
-        let mut pending = self.pending;
-        loop {
-            let pos = pending.trailing_zeros();
-            if pos == MAX_SIG_NUM as u32 {
-                return None;
-            }
+    /// 信号掩码
+    pub fn mask(&self) -> usize {
+        self.mask
+    }
 
-            pending &= !(1 << pos);
-            if self.mask & (1 << pos) == 0
-                || pos == SignalNo::SIGKILL as u32 - 1
-                || pos == SignalNo::SIGSTOP as u32 - 1
-            {
-                return Some(pos as usize + 1);
-            }
+    /// 未决的信号，包括被阻塞的
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// 阻塞 `set` 中的信号
+    pub fn block(&mut self, set: usize) {
+        self.mask |= set & !UNBLOCKABLE;
+    }
+
+    /// 解除阻塞 `set` 中的信号
+    pub fn unblock(&mut self, set: usize) {
+        self.mask &= !set;
+    }
+
+    /// 将掩码设为 `set`
+    pub fn set_mask(&mut self, set: usize) {
+        self.mask = set & !UNBLOCKABLE;
+    }
+
+    pub fn is_blocked(&self, sig_num: usize) -> bool {
+        self.mask & (1 << (sig_num - 1)) != 0
+    }
+
+    /// 编号最小的可投递信号
+    pub fn find_sig(&self) -> Option<usize> {
+        let deliverable = self.pending & !self.mask;
+        if deliverable == 0 {
+            return None;
         }
+        Some(deliverable.trailing_zeros() as usize + 1)
     }
 
-    pub fn get_one_sig(&mut self) -> Option<usize> {
-        if let Some(sig) = self.find_sig() {
+    /// 取出一个待处理的信号及其附加信息
+    ///
+    /// 实时信号取出队列中最早的一项，队列中还有同一信号时保持未决。
+    pub fn get_one_sig(&mut self) -> Option<(usize, Option<SigInfo>)> {
+        let sig = self.find_sig()?;
+        if !is_rt_signal(sig) {
             self.pending &= !(1 << (sig - 1));
-            Some(sig)
-        } else {
-            None
+            return Some((sig, self.info.remove(&sig)));
         }
+        let pos = self
+            .rt_queue
+            .iter()
+            .position(|info| info.si_signo as usize == sig);
+        // 快速模式下实时信号不排队，其信息与标准信号放在一起
+        let info = pos
+            .and_then(|pos| self.rt_queue.remove(pos))
+            .or_else(|| self.info.remove(&sig));
+        if !self
+            .rt_queue
+            .iter()
+            .any(|info| info.si_signo as usize == sig)
+        {
+            self.pending &= !(1 << (sig - 1));
+        }
+        Some((sig, info))
     }
 
-    pub fn try_add_sig(&mut self, sig_num: usize, info: Option<SigInfo>) {
-        let now_mask = 1 << (sig_num - 1);
-        self.mask |= now_mask;
-        if let Some(info) = info {
-            self.info
-                .insert(sig_num, (info, SignalUserContext::default()));
+    /// 使信号未决，实时信号队列已满时返回 false
+    ///
+    /// 标准信号已未决时不再重复，只保留第一次发送的附加信息。快速模式下实时
+    /// 信号也是如此，见 [`crate::sysctl::strict_posix`]。
+    pub fn add_pending(&mut self, sig_num: usize, info: Option<SigInfo>) -> bool {
+        let bit = 1 << (sig_num - 1);
+        if is_rt_signal(sig_num) && crate::sysctl::strict_posix() {
+            if self.rt_queue.len() >= SIGQUEUE_MAX {
+                return false;
+            }
+            let mut info = info.unwrap_or_default();
+            info.si_signo = sig_num as i32;
+            self.rt_queue.push_back(info);
+        } else if self.pending & bit == 0 {
+            if let Some(info) = info {
+                self.info.insert(sig_num, info);
+            }
         }
+        self.pending |= bit;
+        true
     }
 }
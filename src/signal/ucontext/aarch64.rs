@@ -0,0 +1,107 @@
+//! 信号处理时保存的用户上下文，aarch64 版本。
+use super::SignalStack;
+use axerrno::LinuxResult;
+use axhal::arch::TrapFrame;
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug)]
+/// The `mcontext` struct for the signal action, i.e. `struct sigcontext`
+pub struct MContext {
+    fault_address: usize,
+    /// `x0`-`x30`
+    regs: [usize; 31],
+    sp: usize,
+    pc: usize,
+    pstate: usize,
+    /// Aligns `reserved` to 16 bytes
+    pad: usize,
+    /// Records of the FP/SIMD and other extended states, ended by an empty
+    /// record. None is saved, so it is all zero.
+    reserved: [u8; 4096],
+}
+
+impl Default for MContext {
+    fn default() -> Self {
+        Self {
+            fault_address: 0,
+            regs: [0; 31],
+            sp: 0,
+            pc: 0,
+            pstate: 0,
+            pad: 0,
+            reserved: [0; 4096],
+        }
+    }
+}
+
+impl MContext {
+    fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// The user context saved for the signal action, which can be accessed by the signal handler
+pub struct SignalUserContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    sigmask: u64,
+    /// The rest of the 1024-bit signal set of the kernel
+    reserved: [u8; 120],
+    mcontext: MContext,
+}
+
+impl Default for SignalUserContext {
+    fn default() -> Self {
+        Self {
+            flags: 0,
+            link: 0,
+            stack: SignalStack::default(),
+            sigmask: 0,
+            reserved: [0; 120],
+            mcontext: MContext::default(),
+        }
+    }
+}
+
+impl SignalUserContext {
+    /// init the user context by the pc and the mask
+    pub fn init(pc: usize, mask: usize) -> Self {
+        let mut ucontext = Self {
+            sigmask: mask as u64,
+            ..Default::default()
+        };
+        ucontext.set_pc(pc);
+        ucontext
+    }
+
+    /// get the pc from the user context
+    pub fn get_pc(&self) -> usize {
+        self.mcontext.get_pc()
+    }
+
+    /// set the pc to return to from the signal handler
+    pub fn set_pc(&mut self, pc: usize) {
+        self.mcontext.set_pc(pc);
+    }
+}
+
+/// Pass the signal number, the `siginfo` and the `ucontext` to the signal
+/// handler in `x0`, `x1` and `x2`.
+pub fn set_handler_args(trap_frame: &mut TrapFrame, sig_num: usize, info: usize, ucontext: usize) {
+    trap_frame.r[0] = sig_num as _;
+    trap_frame.r[1] = info as _;
+    trap_frame.r[2] = ucontext as _;
+}
+
+/// Make the signal handler return to `restorer`, through the link register.
+pub fn set_restorer(trap_frame: &mut TrapFrame, restorer: usize) -> LinuxResult {
+    trap_frame.r[30] = restorer as _;
+    Ok(())
+}
@@ -2,13 +2,40 @@ pub const SS_ONSTACK: u32 = 1;
 pub const SS_DISABLE: u32 = 2;
 pub const SS_AUTODISARM: u32 = 4;
 
+/// 处理信号时使用的栈
+///
+/// 详细信息见`https://man7.org/linux/man-pages/man2/sigaltstack.2.html`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SignalStack {
+    /// Base address of the stack
+    pub sp: usize,
+    /// Flags for the stack
+    pub flags: u32,
+    /// Size of the stack
+    pub size: usize,
+}
+
+impl Default for SignalStack {
+    fn default() -> Self {
+        Self {
+            sp: 0,
+            // 代表SS_DISABLE，即不使用栈
+            flags: SS_DISABLE,
+            size: 0,
+        }
+    }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
-        // TODO
+        mod x86_64;
+        pub use self::x86_64::*;
     } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
         mod riscv;
         pub use self::riscv::*;
-    } else if #[cfg(target_arch = "aarch64")]{
-        // TODO
+    } else if #[cfg(target_arch = "aarch64")] {
+        mod aarch64;
+        pub use self::aarch64::*;
     }
 }
@@ -1,29 +1,7 @@
 //! 信号处理时保存的用户上下文。
-
-/// 处理信号时使用的栈
-///
-/// 详细信息见`https://man7.org/linux/man-pages/man2/sigaltstack.2.html`
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-pub struct SignalStack {
-    /// Base address of the stack
-    pub sp: usize,
-    /// Flags for the stack
-    pub flags: u32,
-    /// Size of the stack
-    pub size: usize,
-}
-
-impl Default for SignalStack {
-    fn default() -> Self {
-        Self {
-            sp: 0,
-            // 代表SS_DISABLE，即不使用栈
-            flags: super::SS_DISABLE,
-            size: 0,
-        }
-    }
-}
+use super::SignalStack;
+use axerrno::LinuxResult;
+use axhal::arch::TrapFrame;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -59,6 +37,10 @@ impl MContext {
     fn get_pc(&self) -> usize {
         self.pc
     }
+
+    fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
 }
 
 #[repr(C)]
@@ -100,4 +82,23 @@ impl SignalUserContext {
     pub fn get_pc(&self) -> usize {
         self.mcontext.get_pc()
     }
+
+    /// set the pc to return to from the signal handler
+    pub fn set_pc(&mut self, pc: usize) {
+        self.mcontext.set_pc(pc);
+    }
+}
+
+/// Pass the signal number, the `siginfo` and the `ucontext` to the signal
+/// handler in `a0`, `a1` and `a2`.
+pub fn set_handler_args(trap_frame: &mut TrapFrame, sig_num: usize, info: usize, ucontext: usize) {
+    trap_frame.regs.a0 = sig_num;
+    trap_frame.regs.a1 = info;
+    trap_frame.regs.a2 = ucontext;
+}
+
+/// Make the signal handler return to `restorer`, through `ra`.
+pub fn set_restorer(trap_frame: &mut TrapFrame, restorer: usize) -> LinuxResult {
+    trap_frame.regs.ra = restorer;
+    Ok(())
 }
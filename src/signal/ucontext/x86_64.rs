@@ -0,0 +1,105 @@
+//! 信号处理时保存的用户上下文，x86_64 版本。
+use super::SignalStack;
+use crate::ptr::UserPtr;
+use axerrno::LinuxResult;
+use axhal::arch::TrapFrame;
+
+/// The index of `rip` in the general registers of the `mcontext`
+const REG_RIP: usize = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+/// The `mcontext` struct for the signal action, i.e. `struct sigcontext`
+pub struct MContext {
+    /// `r8`-`r15`, `rdi`, `rsi`, `rbp`, `rbx`, `rdx`, `rax`, `rcx`, `rsp`,
+    /// `rip`, `eflags`, the segment selectors, `err`, `trapno`, `oldmask`
+    /// and `cr2`
+    gregs: [usize; 23],
+    /// Pointer to the saved FPU state, or 0 if there is none
+    fpstate: usize,
+    reserved: [usize; 8],
+}
+
+impl Default for MContext {
+    fn default() -> Self {
+        Self {
+            gregs: [0; 23],
+            fpstate: 0,
+            reserved: [0; 8],
+        }
+    }
+}
+
+impl MContext {
+    fn get_pc(&self) -> usize {
+        self.gregs[REG_RIP]
+    }
+
+    fn set_pc(&mut self, pc: usize) {
+        self.gregs[REG_RIP] = pc;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// The user context saved for the signal action, which can be accessed by the signal handler
+///
+/// Unlike on the other architectures, the signal mask follows the `mcontext`.
+pub struct SignalUserContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    mcontext: MContext,
+    sigmask: u64,
+}
+
+impl Default for SignalUserContext {
+    fn default() -> Self {
+        Self {
+            flags: 0,
+            link: 0,
+            stack: SignalStack::default(),
+            mcontext: MContext::default(),
+            sigmask: 0,
+        }
+    }
+}
+
+impl SignalUserContext {
+    /// init the user context by the pc and the mask
+    pub fn init(pc: usize, mask: usize) -> Self {
+        let mut ucontext = Self {
+            sigmask: mask as u64,
+            ..Default::default()
+        };
+        ucontext.set_pc(pc);
+        ucontext
+    }
+
+    /// get the pc from the user context
+    pub fn get_pc(&self) -> usize {
+        self.mcontext.get_pc()
+    }
+
+    /// set the pc to return to from the signal handler
+    pub fn set_pc(&mut self, pc: usize) {
+        self.mcontext.set_pc(pc);
+    }
+}
+
+/// Pass the signal number, the `siginfo` and the `ucontext` to the signal
+/// handler in `rdi`, `rsi` and `rdx`.
+pub fn set_handler_args(trap_frame: &mut TrapFrame, sig_num: usize, info: usize, ucontext: usize) {
+    trap_frame.rdi = sig_num as _;
+    trap_frame.rsi = info as _;
+    trap_frame.rdx = ucontext as _;
+}
+
+/// Make the signal handler return to `restorer`, by pushing it on the user
+/// stack as if the handler had been called from there.
+pub fn set_restorer(trap_frame: &mut TrapFrame, restorer: usize) -> LinuxResult {
+    let sp = trap_frame.rsp as usize - core::mem::size_of::<usize>();
+    UserPtr::<usize>::from(sp).write(restorer)?;
+    trap_frame.rsp = sp as _;
+    Ok(())
+}
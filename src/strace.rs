@@ -0,0 +1,501 @@
+//! Syscall tracing of selected processes, in the style of `strace`.
+//!
+//! A process is traced when it was started from a testcase listed in
+//! `AX_STRACE` at build time (`*` traces them all), or after it called
+//! `prctl(PR_SET_STRACE, 1)`. Children inherit the setting on fork.
+//!
+//! Every syscall of a traced process is written as one line with the
+//! decoded arguments and the result, in the syntax of `strace`:
+//!
+//! ```text
+//! openat(AT_FDCWD, "/etc/passwd", O_RDONLY|O_CLOEXEC) = 3
+//! read(3, "root:x:0:0:root:/root:/bin/sh\n", 4096) = 30
+//! ```
+//!
+//! The lines go to the [`Output`] of the process, the kernel log at the info
+//! level by default. `AX_STRACE_OUTPUT` chooses another one for the traced
+//! testcases, and `prctl(PR_SET_STRACE_OUTPUT, path)` for the calling
+//! process and its future children. The console and file outputs hold nothing
+//! else than the lines, so that they can be compared with the ones of
+//! `strace -f` or `strace -ff -o` on Linux.
+//!
+//! The arguments are decoded with [`signature`]. Syscalls missing from it
+//! have their six raw arguments printed in hex.
+use crate::process::current_process;
+use crate::ptr::{read_cstr, UserSlice};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use axerrno::LinuxError;
+use axstd::fs::{File, OpenOptions};
+use axstd::io::Write as _;
+use axsync::Mutex;
+use axtask::{current, TaskExtRef};
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+use syscalls::Sysno;
+
+/// `prctl` option to turn tracing of the calling process on or off.
+///
+/// Specific to this kernel, so far from the options Linux uses.
+pub const PR_SET_STRACE: i32 = 0x5354_0001;
+/// `prctl` option to store whether the calling process is traced in the
+/// `int` at `arg2`.
+pub const PR_GET_STRACE: i32 = 0x5354_0002;
+/// `prctl` option to send the lines of the calling process to the output
+/// named by the string at `arg2`, see [`Output::from_name`].
+pub const PR_SET_STRACE_OUTPUT: i32 = 0x5354_0003;
+
+/// The name of the console output.
+const CONSOLE_NAME: &str = "/dev/console";
+
+/// Where the lines of a traced process are written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Output {
+    /// The kernel log, at the info level
+    #[default]
+    Log,
+    /// The console, each line prefixed with the thread like `strace -f` does
+    Console,
+    /// A file per thread, `<path>.<tid>`, like `strace -ff -o <path>` does
+    Files(String),
+}
+
+impl Output {
+    /// The output named `name`: the console for `/dev/console`, files named
+    /// after any other path, and the kernel log without a name.
+    pub fn from_name(name: Option<&str>) -> Self {
+        match name {
+            None => Self::Log,
+            Some(CONSOLE_NAME) => Self::Console,
+            Some(path) => Self::Files(String::from(path)),
+        }
+    }
+}
+
+/// The files of the threads traced to files, by thread ID.
+static FILES: Mutex<BTreeMap<u64, File>> = Mutex::new(BTreeMap::new());
+
+/// The most bytes of a string or buffer which are printed.
+const MAX_STR_LEN: usize = 32;
+
+/// How a syscall argument is printed.
+#[derive(Debug, Clone, Copy)]
+pub enum Arg {
+    /// A signed decimal number
+    Int,
+    /// An unsigned decimal number
+    Uint,
+    Hex,
+    Octal,
+    /// An address, or `NULL`
+    Ptr,
+    /// A file descriptor, where `AT_FDCWD` is spelled out
+    Fd,
+    /// A NUL-terminated string
+    Str,
+    /// A buffer read by the syscall, whose length is the argument at the index
+    InBuf(usize),
+    /// A buffer filled by the syscall, whose length is the return value
+    OutBuf,
+    /// The flags of `open`
+    OpenFlags,
+    /// The protection of `mmap`
+    Prot,
+    /// The flags of `mmap`
+    MapFlags,
+    /// A signal number
+    Signal,
+}
+
+/// How the arguments of `sysno` are printed, if it is decoded.
+pub fn signature(sysno: Sysno) -> Option<&'static [Arg]> {
+    use Arg::*;
+    Some(match sysno {
+        Sysno::read => &[Fd, OutBuf, Uint],
+        Sysno::write => &[Fd, InBuf(2), Uint],
+        Sysno::pread64 => &[Fd, OutBuf, Uint, Int],
+        Sysno::pwrite64 => &[Fd, InBuf(2), Uint, Int],
+        Sysno::readv | Sysno::writev => &[Fd, Ptr, Uint],
+        Sysno::openat => &[Fd, Str, OpenFlags, Octal],
+        Sysno::close | Sysno::dup => &[Fd],
+        Sysno::dup3 => &[Fd, Fd, Hex],
+        Sysno::pipe2 => &[Ptr, Hex],
+        Sysno::lseek => &[Fd, Int, Int],
+        Sysno::ioctl => &[Fd, Hex, Hex],
+        Sysno::getdents64 => &[Fd, Ptr, Uint],
+        Sysno::getcwd => &[Ptr, Uint],
+        Sysno::chdir => &[Str],
+        Sysno::mkdirat => &[Fd, Str, Octal],
+        Sysno::unlinkat => &[Fd, Str, Hex],
+        Sysno::symlinkat => &[Str, Fd, Str],
+        Sysno::linkat => &[Fd, Str, Fd, Str, Hex],
+        Sysno::renameat2 => &[Fd, Str, Fd, Str, Hex],
+        Sysno::readlinkat => &[Fd, Str, OutBuf, Uint],
+        Sysno::faccessat | Sysno::faccessat2 => &[Fd, Str, Octal, Hex],
+        Sysno::fchmodat => &[Fd, Str, Octal, Hex],
+        Sysno::fchownat => &[Fd, Str, Int, Int, Hex],
+        Sysno::fstat => &[Fd, Ptr],
+        Sysno::newfstatat => &[Fd, Str, Ptr, Hex],
+        Sysno::statx => &[Fd, Str, Hex, Hex, Ptr],
+        Sysno::statfs => &[Str, Ptr],
+        Sysno::fstatfs => &[Fd, Ptr],
+        Sysno::utimensat => &[Fd, Str, Ptr, Hex],
+        Sysno::truncate => &[Str, Int],
+        Sysno::ftruncate => &[Fd, Int],
+        Sysno::fsync | Sysno::fdatasync | Sysno::syncfs => &[Fd],
+        Sysno::umask => &[Octal],
+        Sysno::mount => &[Str, Str, Str, Hex, Ptr],
+        Sysno::umount2 => &[Str, Hex],
+        Sysno::shmget => &[Int, Uint, Hex],
+        Sysno::shmat => &[Int, Ptr, Hex],
+        Sysno::shmdt => &[Ptr],
+        Sysno::shmctl => &[Int, Int, Ptr],
+        Sysno::getrandom => &[OutBuf, Uint, Hex],
+        Sysno::memfd_create => &[Str, Hex],
+        Sysno::io_uring_setup => &[Uint, Ptr],
+        Sysno::io_uring_enter => &[Fd, Uint, Uint, Hex, Ptr, Uint],
+        Sysno::semget => &[Int, Int, Hex],
+        Sysno::semop => &[Int, Ptr, Uint],
+        Sysno::semtimedop => &[Int, Ptr, Uint, Ptr],
+        Sysno::semctl => &[Int, Int, Int, Hex],
+        Sysno::mq_open => &[Str, OpenFlags, Octal, Ptr],
+        Sysno::mq_unlink => &[Str],
+        Sysno::mq_timedsend => &[Fd, InBuf(2), Uint, Uint, Ptr],
+        Sysno::mq_timedreceive => &[Fd, OutBuf, Uint, Ptr, Ptr],
+        Sysno::mq_getsetattr => &[Fd, Ptr, Ptr],
+        Sysno::brk => &[Ptr],
+        Sysno::mmap => &[Ptr, Uint, Prot, MapFlags, Fd, Hex],
+        Sysno::munmap | Sysno::mlock | Sysno::munlock => &[Ptr, Uint],
+        Sysno::msync | Sysno::madvise | Sysno::mprotect => &[Ptr, Uint, Hex],
+        Sysno::execve => &[Str, Ptr, Ptr],
+        Sysno::exit | Sysno::exit_group => &[Int],
+        Sysno::clone => &[Hex, Ptr, Ptr, Ptr, Ptr],
+        Sysno::wait4 => &[Int, Ptr, Hex, Ptr],
+        Sysno::waitid => &[Int, Int, Ptr, Hex, Ptr],
+        Sysno::kill => &[Int, Signal],
+        Sysno::rt_sigqueueinfo => &[Int, Signal, Ptr],
+        Sysno::rt_sigprocmask => &[Int, Ptr, Ptr, Uint],
+        Sysno::rt_sigaction => &[Signal, Ptr, Ptr, Uint],
+        Sysno::set_tid_address => &[Ptr],
+        Sysno::futex => &[Ptr, Int, Int, Ptr, Ptr, Int],
+        Sysno::set_robust_list => &[Ptr, Uint],
+        Sysno::get_robust_list => &[Int, Ptr, Ptr],
+        Sysno::prctl => &[Int, Hex, Hex],
+        Sysno::personality => &[Hex],
+        Sysno::setpgid => &[Int, Int],
+        Sysno::getpgid | Sysno::getsid => &[Int],
+        Sysno::nanosleep => &[Ptr, Ptr],
+        Sysno::clock_gettime | Sysno::clock_getres => &[Int, Ptr],
+        Sysno::gettimeofday => &[Ptr, Ptr],
+        Sysno::times | Sysno::uname | Sysno::sysinfo => &[Ptr],
+        Sysno::getpid
+        | Sysno::getppid
+        | Sysno::gettid
+        | Sysno::getuid
+        | Sysno::geteuid
+        | Sysno::getgid
+        | Sysno::getegid
+        | Sysno::setsid
+        | Sysno::rt_sigreturn
+        | Sysno::sync
+        | Sysno::sched_yield => &[],
+        #[cfg(target_arch = "x86_64")]
+        Sysno::getpgrp => &[],
+        _ => return None,
+    })
+}
+
+/// Whether the testcase `name` is traced from its start, according to
+/// `AX_STRACE`.
+pub fn traced_at_boot(name: &str) -> bool {
+    option_env!("AX_STRACE")
+        .unwrap_or("")
+        .split(',')
+        .any(|entry| entry == "*" || entry == name)
+}
+
+/// The output of the testcases traced from their start, according to
+/// `AX_STRACE_OUTPUT`.
+pub fn output_at_boot() -> Output {
+    Output::from_name(option_env!("AX_STRACE_OUTPUT").filter(|name| !name.is_empty()))
+}
+
+/// Write `line` to the output of the current process.
+fn emit(line: &str) {
+    let Some(proc) = current_process() else {
+        return;
+    };
+    let output = proc.strace_output.lock().clone();
+    let tid = current().task_ext().tid();
+    match output {
+        Output::Log => info!("{}", line),
+        Output::Console => {
+            crate::console::write(format!("[pid {:>5}] {}\n", tid, line).as_bytes());
+        }
+        Output::Files(path) => {
+            let mut files = FILES.lock();
+            if !files.contains_key(&tid) {
+                let name = format!("{}.{}", path, tid);
+                match OpenOptions::new().append(true).create(true).open(&name) {
+                    Ok(file) => {
+                        files.insert(tid, file);
+                    }
+                    Err(e) => {
+                        warn!("Failed to open the strace output {}: {:?}", name, e);
+                        return;
+                    }
+                }
+            }
+            let file = files.get_mut(&tid).unwrap();
+            let _ = file
+                .write_all(line.as_bytes())
+                .and_then(|_| file.write_all(b"\n"));
+        }
+    }
+}
+
+/// Close the files of the threads which are about to exit with `sysno`.
+fn close_files(sysno: Sysno) {
+    let mut files = FILES.lock();
+    if files.is_empty() {
+        return;
+    }
+    if sysno == Sysno::exit_group {
+        if let Some(proc) = current_process() {
+            for tid in proc.threads.lock().keys() {
+                files.remove(tid);
+            }
+        }
+    }
+    files.remove(&current().task_ext().tid());
+}
+
+/// Whether the current thread belongs to a traced process.
+#[inline]
+pub fn is_traced() -> bool {
+    current_process().is_some_and(|proc| proc.strace.load(Ordering::Relaxed))
+}
+
+/// A syscall of a traced process, between its entry and its exit.
+pub struct SyscallTrace {
+    sysno: Sysno,
+    raw: [usize; 6],
+    /// The printed arguments, empty for the ones printed on exit
+    args: Vec<String>,
+}
+
+impl SyscallTrace {
+    /// Decode the arguments of the syscall as they are on entry.
+    ///
+    /// The syscalls which never return are logged right away.
+    pub fn enter(sysno: Sysno, raw: [usize; 6]) -> Self {
+        let args = match signature(sysno) {
+            Some(sig) => sig
+                .iter()
+                .zip(raw)
+                .map(|(&arg, value)| match arg {
+                    Arg::OutBuf => String::new(),
+                    _ => format_arg(arg, value, &raw, None),
+                })
+                .collect(),
+            None => raw.iter().map(|value| format!("{:#x}", value)).collect(),
+        };
+        let trace = Self { sysno, raw, args };
+        if matches!(sysno, Sysno::exit | Sysno::exit_group) {
+            emit(&format!("{}({}) = ?", sysno.name(), trace.args.join(", ")));
+            close_files(sysno);
+        }
+        trace
+    }
+
+    /// Log the syscall with its return value `ret`.
+    pub fn exit(mut self, ret: isize) {
+        if let Some(sig) = signature(self.sysno) {
+            for (i, &arg) in sig.iter().enumerate() {
+                if let Arg::OutBuf = arg {
+                    self.args[i] = format_arg(arg, self.raw[i], &self.raw, Some(ret));
+                }
+            }
+        }
+        emit(&format!(
+            "{}({}) = {}",
+            self.sysno.name(),
+            self.args.join(", "),
+            format_ret(ret)
+        ));
+    }
+}
+
+fn format_ret(ret: isize) -> String {
+    if (-4095..0).contains(&ret) {
+        if let Ok(err) = LinuxError::try_from(-ret as i32) {
+            return format!("-1 {} ({})", err.name(), err.as_str());
+        }
+    }
+    if ret > 0xffff {
+        return format!("{:#x}", ret);
+    }
+    format!("{}", ret)
+}
+
+fn format_arg(arg: Arg, value: usize, raw: &[usize; 6], ret: Option<isize>) -> String {
+    match arg {
+        Arg::Int => format!("{}", value as isize),
+        Arg::Uint => format!("{}", value),
+        Arg::Hex => format!("{:#x}", value),
+        Arg::Octal => format!("{:#o}", value),
+        Arg::Ptr | Arg::Str if value == 0 => String::from("NULL"),
+        Arg::Ptr => format!("{:#x}", value),
+        Arg::Fd if value as i32 == -100 => String::from("AT_FDCWD"),
+        Arg::Fd => format!("{}", value as i32),
+        Arg::Str => match read_cstr(value as *const _) {
+            Ok(s) => quote(s.as_bytes()),
+            Err(_) => format!("{:#x}", value),
+        },
+        Arg::InBuf(len) => format_buf(value, raw[len]),
+        Arg::OutBuf => match ret {
+            Some(ret) if ret >= 0 => format_buf(value, ret as usize),
+            _ => format!("{:#x}", value),
+        },
+        Arg::OpenFlags => format_open_flags(value as u32),
+        Arg::Prot => format_flags(value, PROT_FLAGS, "PROT_NONE"),
+        Arg::MapFlags => format_flags(value, MAP_FLAGS, "0"),
+        Arg::Signal => format_signal(value),
+    }
+}
+
+fn format_buf(addr: usize, len: usize) -> String {
+    match UserSlice::new(addr as *const u8, len.min(MAX_STR_LEN)).as_slice() {
+        Ok(buf) if len > MAX_STR_LEN => quote(buf) + "...",
+        Ok(buf) => quote(buf),
+        Err(_) => format!("{:#x}", addr),
+    }
+}
+
+/// Quote `bytes` as a C string, truncated to [`MAX_STR_LEN`] bytes.
+fn quote(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes.iter().take(MAX_STR_LEN) {
+        match b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            0x20..=0x7e => s.push(b as char),
+            _ => {
+                let _ = write!(s, "\\x{:02x}", b);
+            }
+        }
+    }
+    s.push('"');
+    if bytes.len() > MAX_STR_LEN {
+        s.push_str("...");
+    }
+    s
+}
+
+/// The names of the bits in `value`, with the unknown bits left in hex.
+fn format_flags(value: usize, names: &[(usize, &str)], zero: &str) -> String {
+    if value == 0 {
+        return String::from(zero);
+    }
+    let mut parts = Vec::new();
+    let mut rest = value;
+    for &(bit, name) in names {
+        if rest & bit == bit {
+            parts.push(String::from(name));
+            rest &= !bit;
+        }
+    }
+    if rest != 0 {
+        parts.push(format!("{:#x}", rest));
+    }
+    parts.join("|")
+}
+
+const OPEN_FLAGS: &[(usize, &str)] = &[
+    (0o100, "O_CREAT"),
+    (0o200, "O_EXCL"),
+    (0o400, "O_NOCTTY"),
+    (0o1000, "O_TRUNC"),
+    (0o2000, "O_APPEND"),
+    (0o4000, "O_NONBLOCK"),
+    (0o200000, "O_DIRECTORY"),
+    (0o400000, "O_NOFOLLOW"),
+    (0o2000000, "O_CLOEXEC"),
+    (0o10000000, "O_PATH"),
+];
+
+fn format_open_flags(flags: u32) -> String {
+    let access = match flags & 0o3 {
+        0 => "O_RDONLY",
+        1 => "O_WRONLY",
+        2 => "O_RDWR",
+        _ => "O_ACCMODE",
+    };
+    let rest = (flags & !0o3) as usize;
+    if rest == 0 {
+        return String::from(access);
+    }
+    format!("{}|{}", access, format_flags(rest, OPEN_FLAGS, ""))
+}
+
+const PROT_FLAGS: &[(usize, &str)] = &[(1, "PROT_READ"), (2, "PROT_WRITE"), (4, "PROT_EXEC")];
+
+const MAP_FLAGS: &[(usize, &str)] = &[
+    (0x01, "MAP_SHARED"),
+    (0x02, "MAP_PRIVATE"),
+    (0x10, "MAP_FIXED"),
+    (0x20, "MAP_ANONYMOUS"),
+    (0x100, "MAP_GROWSDOWN"),
+    (0x2000, "MAP_LOCKED"),
+    (0x4000, "MAP_NORESERVE"),
+    (0x8000, "MAP_POPULATE"),
+    (0x20000, "MAP_STACK"),
+    (0x100000, "MAP_FIXED_NOREPLACE"),
+];
+
+const SIGNAL_NAMES: [&str; 31] = [
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+fn format_signal(signo: usize) -> String {
+    match signo {
+        0 => String::from("0"),
+        1..=31 => String::from(SIGNAL_NAMES[signo - 1]),
+        32..=64 => format!("SIGRT_{}", signo - 32),
+        _ => format!("{}", signo),
+    }
+}
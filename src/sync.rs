@@ -0,0 +1,253 @@
+//! Locks for short critical sections.
+//!
+//! `axsync::Mutex` puts a contending thread to sleep right away, which costs
+//! two context switches for a critical section of a few instructions, such
+//! as a pid lookup. [`AdaptiveMutex`] spins for a while first, and only parks
+//! the thread once the owner looks like it won't release the lock soon.
+//! [`SeqLock`] lets readers of small `Copy` data run without writing to any
+//! shared cache line at all. [`Rcu`] does the same for larger data which is
+//! replaced as a whole by its writers, like the process table.
+use alloc::boxed::Box;
+use axstd::os::arceos::modules::axconfig;
+use axtask::WaitQueue;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// The number of times a contended lock is retried before parking.
+const SPIN_LIMIT: usize = 100;
+
+/// A mutex which spins before parking.
+///
+/// On a single CPU the owner can't make progress while we spin, so a
+/// contending thread parks right away.
+pub struct AdaptiveMutex<T: ?Sized> {
+    locked: AtomicBool,
+    /// The number of threads parked on `wq`
+    parked: AtomicUsize,
+    wq: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for AdaptiveMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for AdaptiveMutex<T> {}
+
+impl<T> AdaptiveMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            parked: AtomicUsize::new(0),
+            wq: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> AdaptiveMutex<T> {
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn try_lock(&self) -> Option<AdaptiveMutexGuard<'_, T>> {
+        self.try_acquire()
+            .then_some(AdaptiveMutexGuard { lock: self })
+    }
+
+    pub fn lock(&self) -> AdaptiveMutexGuard<'_, T> {
+        if axconfig::SMP > 1 {
+            for _ in 0..SPIN_LIMIT {
+                if !self.locked.load(Ordering::Relaxed) && self.try_acquire() {
+                    return AdaptiveMutexGuard { lock: self };
+                }
+                core::hint::spin_loop();
+            }
+        }
+        loop {
+            if self.try_acquire() {
+                return AdaptiveMutexGuard { lock: self };
+            }
+            self.parked.fetch_add(1, Ordering::SeqCst);
+            self.wq.wait_until(|| !self.locked.load(Ordering::SeqCst));
+            self.parked.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::SeqCst);
+        if self.parked.load(Ordering::SeqCst) > 0 {
+            self.wq.notify_one(false);
+        }
+    }
+}
+
+impl<T: Default> Default for AdaptiveMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct AdaptiveMutexGuard<'a, T: ?Sized> {
+    lock: &'a AdaptiveMutex<T>,
+}
+
+impl<T: ?Sized> Deref for AdaptiveMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AdaptiveMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for AdaptiveMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// A sequence lock over read-mostly `Copy` data.
+///
+/// Readers never block writers: they copy the data out and retry if a write
+/// happened meanwhile. Writers are serialized by an [`AdaptiveMutex`].
+pub struct SeqLock<T: Copy> {
+    /// Odd while a write is in progress
+    seq: AtomicUsize,
+    writer: AdaptiveMutex<()>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            writer: AdaptiveMutex::new(()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// A consistent copy of the data.
+    pub fn read(&self) -> T {
+        loop {
+            let start = self.seq.load(Ordering::Acquire);
+            if start % 2 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+            // SAFETY: a torn copy is thrown away below, and `T` is `Copy`
+            let data = unsafe { core::ptr::read_volatile(self.data.get()) };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == start {
+                return data;
+            }
+        }
+    }
+
+    /// Change the data with `f`.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _writer = self.writer.lock();
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        // SAFETY: writers are serialized, and readers retry on a torn read
+        let res = f(unsafe { &mut *self.data.get() });
+        self.seq.fetch_add(1, Ordering::Release);
+        res
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Read-copy-update over data which is read much more often than written.
+///
+/// Readers never take a lock: they only count themselves in the current
+/// epoch while they look at the published copy. A writer publishes a changed
+/// copy, moves to the next epoch and waits for the readers of the previous one
+/// to leave before freeing the old copy. Writers are serialized by an
+/// [`AdaptiveMutex`], and each of them copies the whole data.
+pub struct Rcu<T> {
+    data: AtomicPtr<T>,
+    epoch: AtomicUsize,
+    /// The readers inside each parity of the epoch
+    readers: [AtomicUsize; 2],
+    writer: AdaptiveMutex<()>,
+}
+
+unsafe impl<T: Send + Sync> Send for Rcu<T> {}
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+
+impl<T> Rcu<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data: AtomicPtr::new(Box::into_raw(Box::new(data))),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: AdaptiveMutex::new(()),
+        }
+    }
+
+    /// Look at the current copy of the data with `f`.
+    ///
+    /// `f` must not update the same `Rcu`, which would wait for itself.
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let idx = loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let idx = epoch & 1;
+            self.readers[idx].fetch_add(1, Ordering::SeqCst);
+            // A writer which moved on meanwhile may not have seen us: count
+            // ourselves in the new epoch instead
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                break idx;
+            }
+            self.readers[idx].fetch_sub(1, Ordering::Release);
+        };
+        // SAFETY: we were counted while the epoch hadn't moved on, so the
+        // next writer waits for us, and the one after it can't start before
+        let res = f(unsafe { &*self.data.load(Ordering::SeqCst) });
+        self.readers[idx].fetch_sub(1, Ordering::Release);
+        res
+    }
+
+    /// Publish a copy of the data changed by `f`.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        let _writer = self.writer.lock();
+        let old = self.data.load(Ordering::Relaxed);
+        // SAFETY: only writers free a copy, and they are serialized
+        let mut new = Box::new(unsafe { (*old).clone() });
+        let res = f(&mut new);
+        self.data.store(Box::into_raw(new), Ordering::SeqCst);
+
+        // Wait out the readers which may still see the old copy
+        let idx = self.epoch.fetch_add(1, Ordering::SeqCst) & 1;
+        while self.readers[idx].load(Ordering::Acquire) != 0 {
+            axtask::yield_now();
+        }
+        // SAFETY: nobody can see it anymore
+        drop(unsafe { Box::from_raw(old) });
+        res
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        // SAFETY: there are no readers left
+        drop(unsafe { Box::from_raw(*self.data.get_mut()) });
+    }
+}
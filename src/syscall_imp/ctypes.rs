@@ -1,4 +1,3 @@
-
 pub const SIGSET_SIZE_IN_BYTE: usize = 8;
 
 /// sys_sigprocmask 中指定的结构体类型
@@ -21,4 +20,4 @@ impl SigMaskFlag {
             _ => panic!("SIG_MASK_FLAG::from: invalid value"),
         }
     }
-}
\ No newline at end of file
+}
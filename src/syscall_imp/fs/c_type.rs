@@ -1,13 +1,20 @@
+/// getdents64 写出的目录项头部 `struct linux_dirent64`，其后紧跟以 NUL 结尾的文件名
 #[derive(Debug, Clone, Copy, Default)]
 #[repr(C)]
 pub struct DirEnt {
+    /// inode 编号
     pub d_ino: u64,
+    /// 下一项的偏移
     pub d_off: i64,
+    /// 本项的长度，包括文件名
     pub d_reclen: u16,
+    /// 文件类型，见 [`FileType`]
     pub d_type: u8,
+    /// 文件名
     pub d_name: [u8; 0],
 }
 
+/// 目录项中的文件类型 `d_type`
 #[allow(dead_code)]
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
@@ -33,6 +40,22 @@ impl From<axfs::api::FileType> for FileType {
     }
 }
 
+impl FileType {
+    /// The type of a file with the mode `mode`, as in `st_mode`.
+    pub(crate) fn from_mode(mode: u32) -> Self {
+        match mode & 0o170000 {
+            0o010000 => FileType::Fifo,
+            0o020000 => FileType::Chr,
+            0o040000 => FileType::Dir,
+            0o060000 => FileType::Blk,
+            0o100000 => FileType::Reg,
+            0o120000 => FileType::Lnk,
+            0o140000 => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+}
+
 pub(crate) const DIR_ENT_SIZE: usize = core::mem::size_of::<u64>()
     + core::mem::size_of::<i64>()
     + core::mem::size_of::<u16>()
@@ -82,6 +105,7 @@ impl<'a> DirBuffer<'a> {
     }
 }
 
+/// fstat 等返回的 `struct stat`，除 x86_64 外的架构使用通用布局
 #[derive(Debug, Clone, Copy, Default)]
 #[repr(C)]
 #[cfg(not(target_arch = "x86_64"))]
@@ -124,6 +148,76 @@ pub struct Kstat {
     pub st_ctime_nsec: isize,
 }
 
+/// fstat 等返回的 `struct stat`，x86_64 的布局
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+#[cfg(target_arch = "x86_64")]
+pub struct Kstat {
+    /// 设备
+    pub st_dev: u64,
+    /// inode 编号
+    pub st_ino: u64,
+    /// 硬链接数
+    pub st_nlink: u64,
+    /// 文件类型
+    pub st_mode: u32,
+    /// 用户id
+    pub st_uid: u32,
+    /// 用户组id
+    pub st_gid: u32,
+    /// padding
+    pub _pad0: u32,
+    /// 设备号
+    pub st_rdev: u64,
+    /// 文件大小
+    pub st_size: u64,
+    /// 块大小
+    pub st_blksize: u64,
+    /// 块个数
+    pub st_blocks: u64,
+    /// 最后一次访问时间(秒)
+    pub st_atime_sec: isize,
+    /// 最后一次访问时间(纳秒)
+    pub st_atime_nsec: isize,
+    /// 最后一次修改时间(秒)
+    pub st_mtime_sec: isize,
+    /// 最后一次修改时间(纳秒)
+    pub st_mtime_nsec: isize,
+    /// 最后一次改变状态时间(秒)
+    pub st_ctime_sec: isize,
+    /// 最后一次改变状态时间(纳秒)
+    pub st_ctime_nsec: isize,
+    /// padding
+    pub _unused: [i64; 3],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<arceos_posix_api::ctypes::stat> for Kstat {
+    fn from(stat: arceos_posix_api::ctypes::stat) -> Self {
+        Self {
+            st_dev: stat.st_dev,
+            st_ino: stat.st_ino,
+            st_nlink: stat.st_nlink as u64,
+            st_mode: stat.st_mode,
+            st_uid: stat.st_uid,
+            st_gid: stat.st_gid,
+            _pad0: 0,
+            st_rdev: stat.st_rdev,
+            st_size: stat.st_size as u64,
+            st_blksize: stat.st_blksize as u64,
+            st_blocks: stat.st_blocks as u64,
+            st_atime_sec: stat.st_atime.tv_sec as isize,
+            st_atime_nsec: stat.st_atime.tv_nsec as isize,
+            st_mtime_sec: stat.st_mtime.tv_sec as isize,
+            st_mtime_nsec: stat.st_mtime.tv_nsec as isize,
+            st_ctime_sec: stat.st_ctime.tv_sec as isize,
+            st_ctime_nsec: stat.st_ctime.tv_nsec as isize,
+            _unused: [0; 3],
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
 impl From<arceos_posix_api::ctypes::stat> for Kstat {
     fn from(stat: arceos_posix_api::ctypes::stat) -> Self {
         Self {
@@ -148,3 +242,153 @@ impl From<arceos_posix_api::ctypes::stat> for Kstat {
         }
     }
 }
+
+/// statx 中的时间戳
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct StatxTimestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub __reserved: i32,
+}
+
+impl From<arceos_posix_api::ctypes::timespec> for StatxTimestamp {
+    fn from(ts: arceos_posix_api::ctypes::timespec) -> Self {
+        Self {
+            tv_sec: ts.tv_sec as i64,
+            tv_nsec: ts.tv_nsec as u32,
+            __reserved: 0,
+        }
+    }
+}
+
+/// The fields of `struct stat` which `statx` always fills in.
+pub(crate) const STATX_BASIC_STATS: u32 = 0x7ff;
+/// Reserved for future extension of `struct statx`.
+pub(crate) const STATX_RESERVED: u32 = 0x8000_0000;
+
+/// `struct statx` in the Linux uapi.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Statx {
+    /// 已填写的字段
+    pub stx_mask: u32,
+    /// 块大小
+    pub stx_blksize: u32,
+    /// 文件属性
+    pub stx_attributes: u64,
+    /// 硬链接数
+    pub stx_nlink: u32,
+    /// 用户id
+    pub stx_uid: u32,
+    /// 用户组id
+    pub stx_gid: u32,
+    /// 文件类型
+    pub stx_mode: u16,
+    pub __spare0: u16,
+    /// inode 编号
+    pub stx_ino: u64,
+    /// 文件大小
+    pub stx_size: u64,
+    /// 块个数
+    pub stx_blocks: u64,
+    /// 支持的文件属性
+    pub stx_attributes_mask: u64,
+    /// 最后一次访问时间
+    pub stx_atime: StatxTimestamp,
+    /// 创建时间
+    pub stx_btime: StatxTimestamp,
+    /// 最后一次改变状态时间
+    pub stx_ctime: StatxTimestamp,
+    /// 最后一次修改时间
+    pub stx_mtime: StatxTimestamp,
+    /// 设备号
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    /// 设备
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub stx_mnt_id: u64,
+    pub stx_dio_mem_align: u32,
+    pub stx_dio_offset_align: u32,
+    pub __spare3: [u64; 12],
+}
+
+fn dev_major(dev: u64) -> u32 {
+    (((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0xfff)) as u32
+}
+
+fn dev_minor(dev: u64) -> u32 {
+    (((dev >> 12) & 0xffff_ff00) | (dev & 0xff)) as u32
+}
+
+impl From<arceos_posix_api::ctypes::stat> for Statx {
+    fn from(stat: arceos_posix_api::ctypes::stat) -> Self {
+        Self {
+            stx_mask: STATX_BASIC_STATS,
+            stx_blksize: stat.st_blksize as u32,
+            stx_nlink: stat.st_nlink,
+            stx_uid: stat.st_uid,
+            stx_gid: stat.st_gid,
+            stx_mode: stat.st_mode as u16,
+            stx_ino: stat.st_ino,
+            stx_size: stat.st_size as u64,
+            stx_blocks: stat.st_blocks as u64,
+            stx_atime: stat.st_atime.into(),
+            stx_ctime: stat.st_ctime.into(),
+            stx_mtime: stat.st_mtime.into(),
+            stx_rdev_major: dev_major(stat.st_rdev),
+            stx_rdev_minor: dev_minor(stat.st_rdev),
+            stx_dev_major: dev_major(stat.st_dev),
+            stx_dev_minor: dev_minor(stat.st_dev),
+            ..Default::default()
+        }
+    }
+}
+
+/// statfs 等返回的 `struct statfs`，64 位架构的布局
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct StatFs {
+    /// 文件系统类型
+    pub f_type: i64,
+    /// 块大小
+    pub f_bsize: i64,
+    /// 块个数
+    pub f_blocks: u64,
+    /// 空闲块个数
+    pub f_bfree: u64,
+    /// 非特权用户可用的空闲块个数
+    pub f_bavail: u64,
+    /// inode 个数
+    pub f_files: u64,
+    /// 空闲 inode 个数
+    pub f_ffree: u64,
+    /// 文件系统 id
+    pub f_fsid: [i32; 2],
+    /// 文件名的最大长度
+    pub f_namelen: i64,
+    /// 片段大小
+    pub f_frsize: i64,
+    /// 挂载选项
+    pub f_flags: i64,
+    pub f_spare: [i64; 4],
+}
+
+impl From<crate::fs::statfs::FsStats> for StatFs {
+    fn from(stats: crate::fs::statfs::FsStats) -> Self {
+        Self {
+            f_type: stats.magic as i64,
+            f_bsize: stats.bsize as i64,
+            f_blocks: stats.blocks,
+            f_bfree: stats.bfree,
+            f_bavail: stats.bavail,
+            f_files: stats.files,
+            f_ffree: stats.ffree,
+            f_namelen: stats.namelen as i64,
+            f_frsize: stats.bsize as i64,
+            f_flags: stats.flags as i64,
+            ..Default::default()
+        }
+    }
+}
@@ -1,9 +1,24 @@
-use alloc::string::ToString;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use arceos_posix_api as api;
+use axerrno::LinuxError;
 use core::ffi::{c_char, c_void};
 
+use crate::fs::devfs::{self, dev_file_from_fd};
+use crate::fs::statfs::{self, FsStats, PIPEFS_MAGIC};
+use crate::fs::{
+    cache, dir, ext4, fd_path, inode, memfd, meta, mount, normalize_path, overlay, procfs, quota,
+    resolve_path_at, stat_at, stat_fd, stat_path, symlink, tmpfs, to_cstring, AT_FDCWD,
+    AT_SYMLINK_NOFOLLOW,
+};
+use crate::perf::perf_event_from_fd;
+use crate::ptr::{read_cstr, UserPtr, UserSlice};
 use crate::syscall_body;
-use crate::syscall_imp::fs::c_type::{DirBuffer, DirEnt, FileType, Kstat, DIR_ENT_SIZE};
+use crate::syscall_imp::fs::c_type::{
+    DirBuffer, DirEnt, FileType, Kstat, StatFs, Statx, DIR_ENT_SIZE, STATX_RESERVED,
+};
+use crate::tty::{is_tty, is_tty_request, tty_ioctl};
 
 /// The ioctl() system call manipulates the underlying device parameters
 /// of special files.
@@ -13,93 +28,303 @@ use crate::syscall_imp::fs::c_type::{DirBuffer, DirEnt, FileType, Kstat, DIR_ENT
 /// * `op` - The request code. It is of type unsigned long in glibc and BSD,
 /// and of type int in musl and other UNIX systems.
 /// * `argp` - The argument to the request. It is a pointer to a memory location
-pub(crate) fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
+pub(crate) fn sys_ioctl(fd: i32, op: usize, argp: *mut c_void) -> i32 {
+    debug!("sys_ioctl <= fd: {}, op: {:#x}, argp: {:p}", fd, op, argp);
     syscall_body!(sys_ioctl, {
-        warn!("Unimplemented syscall: SYS_IOCTL");
+        if let Some(event) = perf_event_from_fd(fd) {
+            return event.ioctl(op);
+        }
+        if let Some(dev) = dev_file_from_fd(fd) {
+            return dev.ioctl(op, argp as usize);
+        }
+        if is_tty(fd) {
+            if let Some(res) = tty_ioctl(op, argp) {
+                return res;
+            }
+        } else if is_tty_request(op) {
+            return Err(axerrno::LinuxError::ENOTTY);
+        }
+        warn!("Unimplemented ioctl: {:#x}", op);
         Ok(0)
     })
 }
 
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+
+/// Read the entries of the directory opened as `fd` into `buf`, from where
+/// the previous call stopped.
+///
+/// The position in the directory is the number of entries read so far,
+/// which is also the `d_off` of each entry. Returns 0 once all the entries
+/// have been read, and fails with `EINVAL` if `buf` can't hold the next one.
 pub(crate) fn sys_getdents64(fd: i32, buf: *mut c_void, len: usize) -> i32 {
     syscall_body!(sys_getdent64, {
         if len < DIR_ENT_SIZE {
-            return Err(axerrno::LinuxError::EPERM);
+            return Err(LinuxError::EINVAL);
         }
 
-        let path = api::Directory::from_fd(fd).map(|dir| dir.path().to_string())?;
-
-        let mut buffer =
-            unsafe { DirBuffer::new(core::slice::from_raw_parts_mut(buf as *mut u8, len)) };
+        let mount_file = mount::file_from_fd(fd);
+        let proc_dir = procfs::dir_from_fd(fd);
+        let (dir, entries): (String, Vec<(String, FileType)>) = if let Some(dir) = &proc_dir {
+            let entries = dir
+                .entries()
+                .iter()
+                .map(|(name, mode)| (name.clone(), FileType::from_mode(*mode)))
+                .collect();
+            (String::from(dir.path()), entries)
+        } else if let Some(file) = &mount_file {
+            let entries = file
+                .read_dir()?
+                .into_iter()
+                .map(|entry| (entry.name, FileType::from_mode(entry.mode)))
+                .collect();
+            (String::from(file.path()), entries)
+        } else {
+            let path = api::Directory::from_fd(fd).map(|dir| dir.path().to_string())?;
+            let dir = overlay::logical(&normalize_path(&path));
+            let entries: Vec<(String, axfs::api::FileType)> = if overlay::covers(&dir) {
+                overlay::read_dir(&dir)?
+            } else {
+                axfs::api::read_dir(&path)?
+                    .flatten()
+                    .map(|entry| (entry.file_name(), entry.file_type()))
+                    .collect()
+            };
+            // The devices of the kernel aren't in the filesystem of /dev
+            let devices = devfs::list(&dir)
+                .into_iter()
+                .map(|(name, mode)| (name, FileType::from_mode(mode)));
+            let entries = entries
+                .into_iter()
+                .map(|(name, file_type)| (name, FileType::from(file_type)))
+                .chain(devices)
+                .collect();
+            (dir, entries)
+        };
 
-        axfs::api::read_dir(&path)
-            .map_err(Into::into)
-            .and_then(|entries| {
-                let mut offset = 0;
-                for entry in entries.flatten() {
-                    let mut name = entry.file_name();
-                    name.push('\0');
+        let buf = UserSlice::new(buf as *mut u8, len).as_mut_slice()?;
+        let mut buffer = unsafe { DirBuffer::new(buf) };
 
-                    let entry_size = name.len() + DIR_ENT_SIZE;
-                    offset += entry_size;
+        let start = match (&proc_dir, &mount_file) {
+            (Some(dir), _) => dir.pos() as u64,
+            (None, Some(file)) => file.seek(0, SEEK_CUR)?,
+            (None, None) => dir::pos(fd)?,
+        };
+        let links = symlink::list(&dir)
+            .into_iter()
+            .map(|name| (name, FileType::Lnk));
+        let entries = entries
+            .into_iter()
+            .filter(|(name, _)| !is_hidden(&dir, name))
+            .chain(links)
+            .skip(start as usize);
 
-                    let dirent =
-                        DirEnt::new(1, offset as i64, entry_size, entry.file_type().into());
-
-                    unsafe {
-                        if buffer.write(dirent, name.as_bytes()).is_err() {
-                            break;
-                        }
-                    }
-                }
-                if offset > 0 && buffer.fit(DIR_ENT_SIZE) {
-                    let terminal = DirEnt::new(1, offset as i64, 0, FileType::Reg);
-                    unsafe {
-                        let _ = buffer.write(terminal, &[]);
-                    }
+        let mut pos = start;
+        let mut written = 0;
+        for (mut name, file_type) in entries {
+            let ino = inode::ino(&normalize_path(&format!("{}/{}", dir, name)));
+            name.push('\0');
+            // Entries are 8-byte aligned, as on Linux
+            let entry_size = (name.len() + DIR_ENT_SIZE).next_multiple_of(8);
+            let dirent = DirEnt::new(ino, pos as i64 + 1, entry_size, file_type);
+            if unsafe { buffer.write(dirent, name.as_bytes()) }.is_err() {
+                if written == 0 {
+                    return Err(LinuxError::EINVAL);
                 }
-
-                Ok(offset as isize)
-            })
+                break;
+            }
+            pos += 1;
+            written += entry_size;
+        }
+        match (&proc_dir, &mount_file) {
+            (Some(dir), _) => dir.set_pos(pos as usize),
+            (None, Some(file)) => file.seek(pos as i64, SEEK_SET).map(|_| ())?,
+            (None, None) => dir::set_pos(fd, pos)?,
+        }
+        Ok(written as isize)
     })
 }
 
-/// Because the FAT32 file system does not support the `link` system call,
-/// So we just return -1.
+/// Whether `name` in the directory `dir` is a file of the kernel itself.
+fn is_hidden(dir: &str, name: &str) -> bool {
+    let path = normalize_path(&format!("{}/{}", dir, name));
+    path == meta::META_FILE || path == overlay::UPPER_ROOT || path == tmpfs::TMPFS_ROOT
+}
+
+/// Follow a symbolic link at the source of `linkat`.
+const AT_SYMLINK_FOLLOW: i32 = 0x400;
+
+/// Create a hard link at `new_path` to the file at `old_path`.
+///
+/// Only an ext4 root filesystem can store hard links: elsewhere, this fails
+/// with `EPERM`, as Linux does for filesystems without them.
 pub(crate) fn sys_linkat(
-    _old_dirfd: i32,
-    _old_path: *const c_void,
-    _new_dirfd: i32,
-    _new_path: *const c_void,
+    old_dirfd: i32,
+    old_path: *const c_char,
+    new_dirfd: i32,
+    new_path: *const c_char,
     flags: i32,
 ) -> i32 {
-    if flags != 0 {
-        warn!("Unsupport flags: {}", flags);
-    }
-
-    -1
+    syscall_body!(sys_linkat, {
+        if flags & !AT_SYMLINK_FOLLOW != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let old = resolve_path_at(
+            old_dirfd,
+            &read_cstr(old_path)?,
+            flags & AT_SYMLINK_FOLLOW != 0,
+        )?;
+        let new = resolve_path_at(new_dirfd, &read_cstr(new_path)?, false)?;
+        let stat = stat_at(AT_FDCWD, &old, AT_SYMLINK_NOFOLLOW)?;
+        if symlink::is_symlink(&new) || stat_at(AT_FDCWD, &new, AT_SYMLINK_NOFOLLOW).is_ok() {
+            return Err(LinuxError::EEXIST);
+        }
+        mount::check_writable(&new)?;
+        if stat.st_mode & meta::S_IFMT == meta::S_IFDIR
+            || !ext4::covers(&old)
+            || !ext4::covers(&new)
+        {
+            return Err(LinuxError::EPERM);
+        }
+        ext4::link(&old, &new)?;
+        Ok(0)
+    })
 }
 
+/// Remove a directory instead of a file.
+pub(crate) const AT_REMOVEDIR: i32 = 0x200;
+
 pub(crate) fn sys_unlinkat(dirfd: i32, pathname: *const c_char, flags: i32) -> i32 {
     if flags != 0 {
         warn!("Unsupport flags: {}", flags);
     }
-    api::sys_unlinkat(dirfd, pathname, flags)
+    syscall_body!(sys_unlinkat, {
+        let path = resolve_path_at(dirfd, &read_cstr(pathname)?, false)?;
+        mount::check_writable(&path)?;
+        if flags & AT_REMOVEDIR == 0 {
+            cache::remove(&path);
+        }
+        let stat = stat_path(&path);
+        // Links live outside the filesystem and are removed without touching it
+        let ret = if symlink::remove(&path) {
+            0
+        } else if overlay::covers(&path) {
+            overlay::remove(&path, flags & AT_REMOVEDIR != 0)?;
+            0
+        } else {
+            let cpath = to_cstring(&path)?;
+            api::sys_unlinkat(AT_FDCWD, cpath.as_ptr(), flags)
+        };
+        if ret == 0 {
+            if let Ok(stat) = &stat {
+                quota::release(stat);
+            }
+            meta::remove(&path);
+            inode::remove(&path);
+        }
+        Ok(ret)
+    })
 }
 
 pub(crate) fn sys_fstat(fd: i32, statbuf: *mut c_void) -> i32 {
-    let kstat_ptr = statbuf as *mut Kstat;
-    let mut stat = api::ctypes::stat::default();
-    let ret = unsafe { api::sys_fstat(fd, &mut stat) };
-    if ret < 0 {
-        return -1;
-    }
-    let kstat = Kstat::from(stat);
-    unsafe {
-        kstat_ptr.write(kstat);
-    }
-    0
+    syscall_body!(sys_fstat, {
+        let kstat = Kstat::from(stat_fd(fd)?);
+        UserPtr::from(statbuf as *mut Kstat).write(kstat)?;
+        Ok(0)
+    })
+}
+
+/// Get the status of the file at `path` relative to `dirfd`.
+pub(crate) fn sys_fstatat(
+    dirfd: i32,
+    path: *const c_char,
+    statbuf: *mut c_void,
+    flags: i32,
+) -> i32 {
+    syscall_body!(sys_fstatat, {
+        let path = read_cstr(path)?;
+        let kstat = Kstat::from(stat_at(dirfd, &path, flags)?);
+        UserPtr::from(statbuf as *mut Kstat).write(kstat)?;
+        Ok(0)
+    })
+}
+
+/// The statx flags selecting how to synchronize with a remote filesystem,
+/// which make no difference here.
+const AT_STATX_SYNC_TYPE: i32 = 0x6000;
+const AT_NO_AUTOMOUNT: i32 = 0x800;
+
+/// Get the extended status of the file at `path` relative to `dirfd`.
+///
+/// All the basic fields are always filled in, whatever `mask` asks for.
+pub(crate) fn sys_statx(
+    dirfd: i32,
+    path: *const c_char,
+    flags: i32,
+    mask: u32,
+    statxbuf: *mut c_void,
+) -> i32 {
+    syscall_body!(sys_statx, {
+        if mask & STATX_RESERVED != 0 || flags & AT_STATX_SYNC_TYPE == AT_STATX_SYNC_TYPE {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = read_cstr(path)?;
+        let flags = flags & !(AT_STATX_SYNC_TYPE | AT_NO_AUTOMOUNT);
+        let statx = Statx::from(stat_at(dirfd, &path, flags)?);
+        UserPtr::from(statxbuf as *mut Statx).write(statx)?;
+        Ok(0)
+    })
+}
+
+/// Get the statistics of the filesystem holding the file at `path`.
+pub(crate) fn sys_statfs(path: *const c_char, buf: *mut c_void) -> i32 {
+    syscall_body!(sys_statfs, {
+        let path = read_cstr(path)?;
+        // Fails like `stat` if there is no such file
+        stat_at(AT_FDCWD, &path, 0)?;
+        let path = resolve_path_at(AT_FDCWD, &path, true)?;
+        let stats = StatFs::from(statfs::stats(&path)?);
+        UserPtr::from(buf as *mut StatFs).write(stats)?;
+        Ok(0)
+    })
+}
+
+/// Get the statistics of the filesystem holding the file opened as `fd`.
+pub(crate) fn sys_fstatfs(fd: i32, buf: *mut c_void) -> i32 {
+    syscall_body!(sys_fstatfs, {
+        let stats = if memfd::file_from_fd(fd).is_some() {
+            FsStats::tmpfs()
+        } else if let Ok(path) = fd_path(fd) {
+            statfs::stats(&path)?
+        } else {
+            // Pipes, sockets and the other files without a path
+            api::get_file_like(fd)?;
+            FsStats::empty(PIPEFS_MAGIC)
+        };
+        UserPtr::from(buf as *mut StatFs).write(StatFs::from(stats))?;
+        Ok(0)
+    })
 }
 
 pub(crate) fn sys_lseek(fd: i32, offset: i64, whence: i32) -> i64 {
+    if let Some(file) = memfd::file_from_fd(fd) {
+        return match file.seek(offset, whence) {
+            Ok(pos) => pos as i64,
+            Err(e) => -(e.code() as i64),
+        };
+    }
+    if let Some(file) = mount::file_from_fd(fd) {
+        return match file.seek(offset, whence) {
+            Ok(pos) => pos as i64,
+            Err(e) => -(e.code() as i64),
+        };
+    }
+    if api::Directory::from_fd(fd).is_ok() {
+        return match dir::seek(fd, offset, whence) {
+            Ok(pos) => pos as i64,
+            Err(e) => -(e.code() as i64),
+        };
+    }
     api::sys_lseek(fd, offset, whence)
 }
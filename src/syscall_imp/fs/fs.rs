@@ -1,9 +1,124 @@
+use super::perm::check_write_access;
+use crate::fs::{
+    cache, create_file, devfs, ext4, inode, lookup_file, memfd, meta, mount, overlay, procfs,
+    quota, replace_file, resolve_path_at, stat_path, symlink, to_cstring, AT_FDCWD,
+};
+use crate::process::current_process;
+use crate::ptr::{read_cstr, UserPtr, UserSlice};
+use crate::syscall_body;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use arceos_posix_api as api;
 use arceos_posix_api::ctypes::{mode_t, size_t, timespec};
+use axerrno::{LinuxError, LinuxResult};
+use axstd::fs::OpenOptions;
+use axsync::Mutex;
 use core::ffi::{c_char, c_int};
+use core::sync::atomic::Ordering;
+
+const O_ACCMODE: i32 = 0o3;
+const O_CREAT: i32 = 0o100;
+const O_TRUNC: i32 = 0o1000;
+const O_APPEND: i32 = 0o2000;
+#[cfg(target_arch = "aarch64")]
+const O_NOFOLLOW: i32 = 0o100000;
+#[cfg(not(target_arch = "aarch64"))]
+const O_NOFOLLOW: i32 = 0o400000;
 
 pub(crate) fn sys_openat(dirfd: i32, path: *const c_char, flags: i32, modes: mode_t) -> isize {
-    api::sys_openat(dirfd, path, flags, modes) as isize
+    syscall_body!(sys_openat, {
+        let path = read_cstr(path)?;
+        let open = || open_at(dirfd, &path, flags, modes);
+        if flags & O_CREAT != 0 {
+            return create_file(open);
+        }
+        // Not finding the file the moment a rename replaces it
+        lookup_file(|| match open() {
+            Ok(ret) if ret == -(LinuxError::ENOENT.code() as isize) => Err(LinuxError::ENOENT),
+            res => res,
+        })
+    })
+}
+
+/// The regular files opened with `O_APPEND`, by the address of their
+/// [`api::File`]. The weak references keep the addresses from being reused
+/// while they are in the table.
+static APPEND_FILES: Mutex<BTreeMap<usize, Weak<api::File>>> = Mutex::new(BTreeMap::new());
+
+/// Remember that the regular file opened as `fd` appends.
+fn record_append(fd: i32) {
+    if let Ok(file) = api::File::from_fd(fd) {
+        let mut files = APPEND_FILES.lock();
+        files.retain(|_, file| file.strong_count() > 0);
+        files.insert(Arc::as_ptr(&file) as usize, Arc::downgrade(&file));
+    }
+}
+
+/// Whether the regular file `file` was opened with `O_APPEND`.
+pub(crate) fn is_append(file: &Arc<api::File>) -> bool {
+    APPEND_FILES
+        .lock()
+        .contains_key(&(Arc::as_ptr(file) as usize))
+}
+
+/// Open the file at `path` relative to `dirfd`, for [`sys_openat`].
+fn open_at(dirfd: i32, path: &str, flags: i32, modes: mode_t) -> LinuxResult<isize> {
+    let ret = open_file_at(dirfd, path, flags, modes)?;
+    if ret >= 0 && flags & O_APPEND != 0 {
+        record_append(ret as i32);
+    }
+    Ok(ret)
+}
+
+fn open_file_at(dirfd: i32, path: &str, flags: i32, modes: mode_t) -> LinuxResult<isize> {
+    let abs_path = resolve_path_at(dirfd, path, flags & O_NOFOLLOW == 0)?;
+    if symlink::is_symlink(&abs_path) {
+        return Err(LinuxError::ELOOP);
+    }
+    if procfs::is_procfs_path(&abs_path) {
+        return procfs::open_fd(&abs_path);
+    }
+    if devfs::is_devfs_path(&abs_path) {
+        return devfs::open_fd(&abs_path, flags);
+    }
+    if mount::is_mount_path(&abs_path) {
+        return mount::open_fd(&abs_path, flags);
+    }
+    let writes = flags & O_ACCMODE != 0 || flags & (O_CREAT | O_TRUNC) != 0;
+    let cpath = to_cstring(&if writes {
+        overlay::copy_up(&abs_path)?
+    } else {
+        overlay::lookup(&abs_path)
+    })?;
+    if flags & O_CREAT == 0 {
+        let ret = api::sys_openat(AT_FDCWD, cpath.as_ptr(), flags, modes);
+        if ret >= 0 && flags & O_TRUNC != 0 {
+            cache::truncate(&abs_path, 0);
+        }
+        return Ok(ret as isize);
+    }
+    let umask = current_process().unwrap().umask.load(Ordering::Relaxed);
+    let mode = modes & !umask & meta::S_IPERM;
+    let created = stat_path(&abs_path).is_err();
+    let ret = api::sys_openat(AT_FDCWD, cpath.as_ptr(), flags, mode);
+    if ret >= 0 && created {
+        record_new_file(&abs_path, mode);
+    } else if ret >= 0 && flags & O_TRUNC != 0 {
+        cache::truncate(&abs_path, 0);
+    }
+    Ok(ret as isize)
+}
+
+/// Record the owner and mode of a newly created file.
+fn record_new_file(path: &str, mode: u32) {
+    let cred = current_process().unwrap().cred();
+    meta::update(path, |meta| {
+        meta.mode = Some(mode);
+        meta.uid = Some(cred.euid);
+        meta.gid = Some(cred.egid);
+    });
 }
 
 pub(crate) fn sys_close(fd: i32) -> i32 {
@@ -23,16 +138,112 @@ pub(crate) fn sys_dup3(old_fd: i32, new_fd: i32, _flags: i32) -> i32 {
     sys_dup2(old_fd, new_fd)
 }
 
+/// Get the current working directory, as a path of the image when it is in
+/// the upper layer of the overlay.
 pub(crate) fn sys_getcwd(buf: *mut c_char, size: size_t) -> *mut c_char {
-    api::sys_getcwd(buf, size)
+    if UserSlice::new(buf as *mut u8, size).as_mut_slice().is_err() {
+        return core::ptr::null_mut();
+    }
+    let ret = api::sys_getcwd(buf, size);
+    if ret.is_null() || !overlay::is_enabled() {
+        return ret;
+    }
+    let Ok(cwd) = read_cstr(buf) else {
+        return ret;
+    };
+    let cwd = overlay::logical(&cwd);
+    // The path of the image is never longer
+    let Ok(buf) = UserSlice::new(buf as *mut u8, cwd.len() + 1).as_mut_slice() else {
+        return core::ptr::null_mut();
+    };
+    buf[..cwd.len()].copy_from_slice(cwd.as_bytes());
+    buf[cwd.len()] = 0;
+    ret
 }
 
 pub(crate) fn sys_chdir(filename: *const c_char) -> i32 {
-    api::sys_chdir(filename)
+    syscall_body!(sys_chdir, {
+        let path = resolve_path_at(AT_FDCWD, &read_cstr(filename)?, true)?;
+        let cpath = to_cstring(&overlay::lookup(&path))?;
+        Ok(api::sys_chdir(cpath.as_ptr()))
+    })
 }
 
 pub(crate) fn sys_mkdirat(dirfd: i32, pathname: *const c_char, mode: mode_t) -> i32 {
-    api::sys_mkdirat(dirfd, pathname, mode)
+    let umask = current_process().unwrap().umask.load(Ordering::Relaxed);
+    let mode = mode & !umask & meta::S_IPERM;
+    syscall_body!(sys_mkdirat, {
+        let path = resolve_path_at(dirfd, &read_cstr(pathname)?, false)?;
+        if symlink::is_symlink(&path) || stat_path(&path).is_ok() {
+            return Err(LinuxError::EEXIST);
+        }
+        mount::check_writable(&path)?;
+        let cpath = to_cstring(&overlay::copy_up(&path)?)?;
+        let ret = api::sys_mkdirat(AT_FDCWD, cpath.as_ptr(), mode);
+        if ret == 0 {
+            record_new_file(&path, mode);
+        }
+        Ok(ret)
+    })
+}
+
+/// Create a symbolic link at `linkpath` pointing to `target`.
+pub(crate) fn sys_symlinkat(target: *const c_char, newdirfd: i32, linkpath: *const c_char) -> i32 {
+    syscall_body!(sys_symlinkat, {
+        let target = read_cstr(target)?;
+        let linkpath = read_cstr(linkpath)?;
+        if linkpath.is_empty() {
+            return Err(LinuxError::ENOENT);
+        }
+        let path = resolve_path_at(newdirfd, &linkpath, false)?;
+        if symlink::is_symlink(&path) || stat_path(&path).is_ok() {
+            return Err(LinuxError::EEXIST);
+        }
+        mount::check_writable(&path)?;
+        let (parent, _) = path.rsplit_once('/').ok_or(LinuxError::EINVAL)?;
+        let parent = stat_path(if parent.is_empty() { "/" } else { parent })?;
+        if parent.st_mode & meta::S_IFMT != 0o040000 {
+            return Err(LinuxError::ENOTDIR);
+        }
+        if ext4::covers(&path) {
+            ext4::symlink(&target, &path)?;
+        } else {
+            symlink::create(&path, &target)?;
+        }
+        let cred = current_process().unwrap().cred();
+        meta::update(&path, |meta| {
+            meta.uid = Some(cred.euid);
+            meta.gid = Some(cred.egid);
+        });
+        Ok(0)
+    })
+}
+
+/// Read the target of the symbolic link at `path` into `buf`, without a trailing NUL.
+pub(crate) fn sys_readlinkat(
+    dirfd: i32,
+    path: *const c_char,
+    buf: *mut c_char,
+    bufsiz: usize,
+) -> isize {
+    syscall_body!(sys_readlinkat, {
+        if bufsiz == 0 || (bufsiz as isize) < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = resolve_path_at(dirfd, &read_cstr(path)?, false)?;
+        let target = symlink::read(&path)
+            .or_else(|| mount::read_link(&path))
+            .or_else(|| ext4::read_link(&path))
+            .or_else(|| procfs::read_link(&path));
+        let Some(target) = target else {
+            stat_path(&path)?;
+            return Err(LinuxError::EINVAL);
+        };
+        let len = target.len().min(bufsiz);
+        let buf = UserSlice::new(buf as *mut u8, len).as_mut_slice()?;
+        buf.copy_from_slice(&target.as_bytes()[..len]);
+        Ok(len as isize)
+    })
 }
 
 pub(crate) fn sys_utimensat(
@@ -41,5 +252,238 @@ pub(crate) fn sys_utimensat(
     times: *const timespec,
     flags: c_int,
 ) -> c_int {
-    api::sys_utimensat(dirfd, pathname, times, flags)
+    syscall_body!(sys_utimensat, {
+        if !pathname.is_null() {
+            read_cstr(pathname)?;
+        }
+        if !UserPtr::from(times).is_null() {
+            UserSlice::new(times, 2).as_slice()?;
+        }
+        Ok(api::sys_utimensat(dirfd, pathname, times, flags))
+    })
+}
+
+/// Don't overwrite the target of a rename.
+const RENAME_NOREPLACE: u32 = 1 << 0;
+/// Exchange the source and the target atomically.
+const RENAME_EXCHANGE: u32 = 1 << 1;
+/// Leave a whiteout object at the source, for overlay filesystems.
+const RENAME_WHITEOUT: u32 = 1 << 2;
+
+fn is_dir(stat: &api::ctypes::stat) -> bool {
+    stat.st_mode & meta::S_IFMT == 0o040000
+}
+
+/// Remove what is at `path` so that a file of the type of `source` can be
+/// renamed over it, following the rules of `rename(2)`.
+fn remove_rename_target(path: &str, source: Option<&api::ctypes::stat>) -> LinuxResult {
+    if symlink::remove(path) {
+        return Ok(());
+    }
+    let Ok(target) = stat_path(path) else {
+        return Ok(());
+    };
+    if !is_dir(&target) && !source.is_some_and(is_dir) {
+        cache::remove(path);
+    }
+    match (source.is_some_and(is_dir), is_dir(&target)) {
+        (dir, _) if overlay::covers(path) => overlay::remove(path, dir)?,
+        (true, true) => axfs::api::remove_dir(path)?,
+        (false, false) => axfs::api::remove_file(path)?,
+        (true, false) => return Err(LinuxError::ENOTDIR),
+        (false, true) => return Err(LinuxError::EISDIR),
+    }
+    quota::release(&target);
+    meta::remove(path);
+    inode::remove(path);
+    Ok(())
+}
+
+/// Rename the file at `oldpath` to `newpath`, replacing the target if it
+/// exists unless `RENAME_NOREPLACE` is given.
+///
+/// The target is removed before the file is moved, but as one step for the
+/// opens and stats, which find either file at `newpath`.
+pub(crate) fn sys_renameat2(
+    olddirfd: i32,
+    oldpath: *const c_char,
+    newdirfd: i32,
+    newpath: *const c_char,
+    flags: u32,
+) -> i32 {
+    syscall_body!(sys_renameat2, {
+        if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE | RENAME_WHITEOUT) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        if flags & (RENAME_EXCHANGE | RENAME_WHITEOUT) != 0 {
+            warn!("renameat2: unsupported flags {:#x}", flags);
+            return Err(LinuxError::EINVAL);
+        }
+        let old = resolve_path_at(olddirfd, &read_cstr(oldpath)?, false)?;
+        let new = resolve_path_at(newdirfd, &read_cstr(newpath)?, false)?;
+        mount::check_writable(&old)?;
+        mount::check_writable(&new)?;
+        if old == "/" || new == "/" {
+            return Err(LinuxError::EBUSY);
+        }
+        // A directory can't be moved below itself
+        if new.starts_with(&format!("{}/", old)) {
+            return Err(LinuxError::EINVAL);
+        }
+
+        let is_link = symlink::is_symlink(&old);
+        let source = if is_link {
+            None
+        } else {
+            Some(stat_path(&old)?)
+        };
+        if old == new {
+            return Ok(0);
+        }
+        replace_file(|| {
+            let target_exists = symlink::is_symlink(&new) || stat_path(&new).is_ok();
+            if target_exists {
+                if flags & RENAME_NOREPLACE != 0 {
+                    return Err(LinuxError::EEXIST);
+                }
+                remove_rename_target(&new, source.as_ref())?;
+            }
+
+            if is_link {
+                symlink::rename(&old, &new);
+            } else {
+                overlay::rename(&old, &new)?;
+                // Links below a renamed directory move along
+                symlink::rename(&old, &new);
+            }
+            quota::rename(&old, &new);
+            cache::rename(&old, &new);
+            meta::rename(&old, &new);
+            inode::rename(&old, &new);
+            Ok(0)
+        })
+    })
+}
+
+/// Resize the regular file at the absolute `path` to `length` bytes.
+fn truncate_path(path: &str, length: i64) -> LinuxResult {
+    if length < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    mount::check_writable(path)?;
+    if is_dir(&stat_path(path)?) {
+        return Err(LinuxError::EISDIR);
+    }
+    let cred = current_process().unwrap().cred();
+    let length = length as u64;
+    let truncate = || -> LinuxResult {
+        OpenOptions::new()
+            .write(true)
+            .open(&overlay::copy_up(path)?)?
+            .set_len(length)?;
+        cache::truncate(path, length);
+        Ok(())
+    };
+    quota::grow(
+        path,
+        cred.is_privileged(),
+        || length,
+        truncate,
+        |res| res.is_ok().then_some(length),
+    )?
+}
+
+/// Resize the file at `path` to `length` bytes, following symbolic links.
+pub(crate) fn sys_truncate(path: *const c_char, length: i64) -> i32 {
+    syscall_body!(sys_truncate, {
+        let path = resolve_path_at(AT_FDCWD, &read_cstr(path)?, true)?;
+        check_write_access(&stat_path(&path)?)?;
+        truncate_path(&path, length)?;
+        Ok(0)
+    })
+}
+
+/// Resize the regular file opened as `fd` to `length` bytes. Fails with
+/// `EINVAL` if `fd` isn't open for writing.
+pub(crate) fn sys_ftruncate(fd: i32, length: i64) -> i32 {
+    syscall_body!(sys_ftruncate, {
+        if let Some(file) = memfd::file_from_fd(fd) {
+            if length < 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            file.set_len(length as u64)?;
+            return Ok(0);
+        }
+        api::get_file_like(fd)?;
+        let file = api::File::from_fd(fd).map_err(|_| LinuxError::EINVAL)?;
+        // An empty write fails like the real one if `fd` isn't open for writing
+        if api::sys_write(fd, [0u8].as_ptr() as _, 0) < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        truncate_path(&overlay::logical(file.path()), length)?;
+        Ok(0)
+    })
+}
+
+/// The path of the file opened as `fd` to write back, if it is a regular
+/// file. Fails with `EBADF` if `fd` isn't open.
+fn sync_target(fd: i32) -> LinuxResult<Option<String>> {
+    api::get_file_like(fd)?;
+    Ok(api::File::from_fd(fd)
+        .ok()
+        .map(|file| overlay::logical(file.path())))
+}
+
+/// Write the file opened as `fd` back to the disk, data and metadata.
+///
+/// Pipes, sockets, devices and memfds have nothing to write back.
+pub(crate) fn sys_fsync(fd: i32) -> i32 {
+    syscall_body!(sys_fsync, {
+        if let Some(path) = sync_target(fd)? {
+            crate::fs::sync_file(&path).map_err(|_| LinuxError::EIO)?;
+        }
+        Ok(0)
+    })
+}
+
+/// Write the data of the file opened as `fd` back to the disk. The metadata
+/// is written back along, as there is no cheaper way.
+pub(crate) fn sys_fdatasync(fd: i32) -> i32 {
+    sys_fsync(fd)
+}
+
+/// Write all the files back to the disk. The kernel has a single disk, so
+/// this is the same as `sync`, except that `fd` must be open.
+pub(crate) fn sys_syncfs(fd: i32) -> i32 {
+    syscall_body!(sys_syncfs, {
+        api::get_file_like(fd)?;
+        crate::fs::sync_all().map_err(|_| LinuxError::EIO)?;
+        Ok(0)
+    })
+}
+
+/// Write all the files back to the disk. Never fails.
+pub(crate) fn sys_sync() -> i32 {
+    if let Err(e) = crate::fs::sync_all() {
+        warn!("sync: failed to write back the files: {:?}", e);
+    }
+    0
+}
+
+/// Create an anonymous file, which lives in memory until its last reference
+/// is dropped.
+///
+/// `MFD_CLOEXEC` is accepted but the fd table has no close-on-exec flag, and
+/// `MFD_ALLOW_SEALING` is accepted but no seal can be added.
+pub(crate) fn sys_memfd_create(name: *const c_char, flags: u32) -> isize {
+    syscall_body!(sys_memfd_create, {
+        let name = read_cstr(name)?;
+        if flags & !(memfd::MFD_CLOEXEC | memfd::MFD_ALLOW_SEALING) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        if name.len() > memfd::MFD_NAME_MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        api::add_file_like(memfd::MemFd::new(&name)).map(|fd| fd as isize)
+    })
 }
@@ -1,17 +1,376 @@
 use core::ffi::c_void;
 
+use super::ctl::sys_lseek;
+use super::fs::is_append;
+use crate::fs::{cache, overlay, quota};
+use crate::process::current_process;
+use crate::ptr::{UserPtr, UserSlice};
+use crate::syscall_body;
+use crate::tty::{self, is_tty};
+use crate::uring::{self, IoUring, IoUringParams};
+use alloc::string::String;
+use alloc::vec::Vec;
 use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+use axstd::fs::OpenOptions;
+use axstd::io::{Seek, SeekFrom, Write};
+
+/// Run `write` on `fd`, charging the growth of a regular file to the
+/// quota of the current user.
+///
+/// `count` is the number of bytes about to be written, at `offset` or else at
+/// the file offset, used to refuse writes that would exceed the quota before
+/// any data reaches the file.
+fn write_with_quota(
+    fd: i32,
+    count: usize,
+    offset: Option<u64>,
+    write: impl FnOnce() -> isize,
+) -> isize {
+    let Ok(file) = api::File::from_fd(fd) else {
+        // Not a regular file, nothing to account
+        return write();
+    };
+    let path = overlay::logical(file.path());
+    let cred = current_process().unwrap().cred();
+
+    let file_size = || -> LinuxResult<u64> {
+        let mut stat = api::ctypes::stat::default();
+        if unsafe { api::sys_fstat(fd, &mut stat) } < 0 {
+            return Err(LinuxError::EBADF);
+        }
+        Ok(stat.st_size as u64)
+    };
+
+    if file_size().is_err() {
+        return write();
+    }
+    let append = offset.is_none() && is_append(&file);
+    let offset = offset.unwrap_or_else(|| api::sys_lseek(fd, 0, SEEK_CUR).max(0) as u64);
+    // The size is read as the blocks are reserved, as appends start there
+    let new_size = || {
+        let size = file_size().unwrap_or(0);
+        let start = if append { size } else { offset };
+        size.max(start + count as u64)
+    };
+    let size_after = |&ret: &isize| if ret > 0 { file_size().ok() } else { None };
+    match quota::grow(&path, cred.is_privileged(), new_size, write, size_after) {
+        Ok(ret) => ret,
+        Err(e) => -e.code() as isize,
+    }
+}
+
+/// The path of `fd` if it is a regular file, whose I/O goes through the page
+/// cache, and its file offset.
+fn cached_file(fd: i32) -> Option<(String, u64)> {
+    let file = api::File::from_fd(fd).ok()?;
+    let pos = api::sys_lseek(fd, 0, SEEK_CUR);
+    (pos >= 0).then(|| (overlay::logical(file.path()), pos as u64))
+}
 
 pub(crate) fn sys_read(fd: i32, buf: *mut c_void, count: usize) -> isize {
-    api::sys_read(fd, buf, count)
+    syscall_body!(sys_read, {
+        let buf = UserSlice::new(buf as *mut u8, count).as_mut_slice()?;
+        read_bytes(fd, buf, None)
+    })
+}
+
+/// Read from `fd` into `buf`, in kernel memory, at `offset` if given, or else
+/// at the file offset, which it moves.
+fn read_bytes(fd: i32, buf: &mut [u8], offset: Option<u64>) -> LinuxResult<isize> {
+    if is_tty(fd) {
+        if offset.is_some() {
+            return Err(LinuxError::ESPIPE);
+        }
+        return tty::read(buf);
+    }
+    let read = || api::sys_read(fd, buf.as_mut_ptr() as _, buf.len());
+    let Some((path, pos)) = cached_file(fd) else {
+        return Ok(match offset {
+            Some(offset) => at_offset(fd, offset, read),
+            None => read(),
+        });
+    };
+    // An empty read fails like the real one if `fd` isn't open for reading
+    let ret = api::sys_read(fd, buf.as_mut_ptr() as _, 0);
+    if ret < 0 {
+        return Ok(ret);
+    }
+    let n = cache::read_at(&path, offset.unwrap_or(pos), buf)?;
+    if offset.is_none() {
+        api::sys_lseek(fd, (pos + n as u64) as i64, SEEK_SET);
+    }
+    Ok(n as isize)
+}
+
+/// Write `data`, in kernel memory, to `fd`, at `offset` if given, or else at
+/// the file offset, which it moves.
+///
+/// A write inside a regular file only goes to the page cache. One extending
+/// the file goes to the file, where the quota is charged, then to the cache.
+fn write_bytes(fd: i32, data: &[u8], offset: Option<u64>) -> LinuxResult<isize> {
+    if is_tty(fd) {
+        if offset.is_some() {
+            return Err(LinuxError::ESPIPE);
+        }
+        return tty::write(data).map(|count| count as isize);
+    }
+    let write = || api::sys_write(fd, data.as_ptr() as _, data.len());
+    let Some((path, pos)) = cached_file(fd) else {
+        return Ok(match offset {
+            Some(offset) => at_offset(fd, offset, || {
+                write_with_quota(fd, data.len(), Some(offset), write)
+            }),
+            None => write_with_quota(fd, data.len(), None, write),
+        });
+    };
+    // An empty write fails like the real one if `fd` isn't open for writing
+    let ret = api::sys_write(fd, data.as_ptr() as _, 0);
+    if ret < 0 || data.is_empty() {
+        return Ok(ret);
+    }
+    let size = axfs::api::metadata(&overlay::lookup(&path))?.len();
+    let append = offset.is_none() && api::File::from_fd(fd).is_ok_and(|file| is_append(&file));
+    let start = offset.unwrap_or(pos);
+    if !append && start + data.len() as u64 <= size {
+        let n = cache::write_at(&path, start, data)?;
+        if offset.is_none() {
+            api::sys_lseek(fd, (pos + n as u64) as i64, SEEK_SET);
+        }
+        return Ok(n as isize);
+    }
+    if let Some(offset) = offset {
+        // Through a handle of our own, to leave the file offset alone
+        let write_at = || match write_file_at(&path, offset, data) {
+            Ok(n) => n as isize,
+            Err(e) => -(e.code() as isize),
+        };
+        let ret = write_with_quota(fd, data.len(), Some(offset), write_at);
+        if ret > 0 {
+            cache::update(&path, offset, &data[..ret as usize]);
+        }
+        return Ok(ret);
+    }
+    let ret = write_with_quota(fd, data.len(), None, write);
+    if ret > 0 {
+        // With `O_APPEND`, the data went to the end rather than to `pos`
+        let end = api::sys_lseek(fd, 0, SEEK_CUR) as u64;
+        cache::update(&path, end - ret as u64, &data[..ret as usize]);
+    }
+    Ok(ret)
+}
+
+/// Write `data` at `offset` of the regular file at the absolute `path`, open
+/// for writing, through a handle of its own.
+fn write_file_at(path: &str, offset: u64, data: &[u8]) -> LinuxResult<usize> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(&overlay::lookup(path))?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)?;
+    Ok(data.len())
 }
 
 pub(crate) fn sys_write(fd: i32, buf: *const c_void, count: usize) -> isize {
-    api::sys_write(fd, buf, count)
+    syscall_body!(sys_write, {
+        let data = UserSlice::new(buf as *const u8, count).as_slice()?;
+        write_bytes(fd, data, None)
+    })
+}
+
+/// The maximum number of segments in an iovec array.
+const IOV_MAX: i32 = 1024;
+
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+
+/// Validate an iovec array from user space and return it as a slice.
+fn iovec_slice<'a>(
+    iov: *const api::ctypes::iovec,
+    iocnt: i32,
+) -> LinuxResult<&'a [api::ctypes::iovec]> {
+    if !(0..=IOV_MAX).contains(&iocnt) {
+        return Err(LinuxError::EINVAL);
+    }
+    let iovs = UserSlice::new(iov, iocnt as usize).as_slice()?;
+    // The total length must fit in the return value
+    let mut total: usize = 0;
+    for iov in iovs {
+        if iov.iov_base.is_null() && iov.iov_len != 0 {
+            return Err(LinuxError::EFAULT);
+        }
+        total = total
+            .checked_add(iov.iov_len)
+            .filter(|&total| total <= isize::MAX as usize)
+            .ok_or(LinuxError::EINVAL)?;
+    }
+    Ok(iovs)
+}
+
+/// Run `op` on every segment in turn, stopping at the first short transfer.
+///
+/// An error is only reported if nothing has been transferred yet, like Linux.
+fn for_each_iovec(
+    iovs: &[api::ctypes::iovec],
+    mut op: impl FnMut(*mut c_void, usize) -> isize,
+) -> isize {
+    let mut total = 0;
+    for iov in iovs.iter().filter(|iov| iov.iov_len != 0) {
+        let ret = op(iov.iov_base, iov.iov_len);
+        if ret < 0 {
+            return if total > 0 { total } else { ret };
+        }
+        total += ret;
+        if (ret as usize) < iov.iov_len {
+            break;
+        }
+    }
+    total
+}
+
+/// Run `op` with the file offset of `fd` temporarily moved to `offset`.
+///
+/// Only for the files which aren't regular, read and written at an explicit
+/// offset otherwise. Fails with `ESPIPE` if `fd` isn't seekable.
+fn at_offset(fd: i32, offset: u64, op: impl FnOnce() -> isize) -> isize {
+    let saved = sys_lseek(fd, 0, SEEK_CUR);
+    if saved < 0 {
+        return -(LinuxError::ESPIPE.code() as isize);
+    }
+    if sys_lseek(fd, offset as i64, SEEK_SET) < 0 {
+        return -(LinuxError::EINVAL.code() as isize);
+    }
+    let ret = op();
+    sys_lseek(fd, saved, SEEK_SET);
+    ret
+}
+
+/// The offset of a positional read or write, which can't be negative.
+fn position(offset: i64) -> LinuxResult<u64> {
+    u64::try_from(offset).map_err(|_| LinuxError::EINVAL)
+}
+
+pub(crate) fn sys_readv(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
+    syscall_body!(sys_readv, {
+        let iovs = iovec_slice(iov, iocnt)?;
+        Ok(for_each_iovec(iovs, |buf, len| sys_read(fd, buf, len)))
+    })
 }
 
 pub(crate) fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    syscall_body!(sys_writev, {
+        let iovs = iovec_slice(iov, iocnt)?;
+        if iovs.len() > 1 && crate::sysctl::strict_posix() {
+            // Gather the segments, so that they reach the file in one write
+            // which nobody can come in the middle of
+            let mut data = Vec::new();
+            for iov in iovs {
+                data.extend_from_slice(
+                    UserSlice::new(iov.iov_base as *const u8, iov.iov_len).as_slice()?,
+                );
+            }
+            return write_bytes(fd, &data, None);
+        }
+        Ok(for_each_iovec(iovs, |buf, len| sys_write(fd, buf, len)))
+    })
+}
+
+pub(crate) fn sys_pread64(fd: i32, buf: *mut c_void, count: usize, offset: i64) -> isize {
+    syscall_body!(sys_pread64, {
+        let offset = position(offset)?;
+        let buf = UserSlice::new(buf as *mut u8, count).as_mut_slice()?;
+        read_bytes(fd, buf, Some(offset))
+    })
+}
+
+pub(crate) fn sys_pwrite64(fd: i32, buf: *const c_void, count: usize, offset: i64) -> isize {
+    syscall_body!(sys_pwrite64, {
+        let offset = position(offset)?;
+        let data = UserSlice::new(buf as *const u8, count).as_slice()?;
+        write_bytes(fd, data, Some(offset))
+    })
+}
+
+pub(crate) fn sys_preadv(
+    fd: i32,
+    iov: *const api::ctypes::iovec,
+    iocnt: i32,
+    offset: i64,
+) -> isize {
+    syscall_body!(sys_preadv, {
+        let mut offset = position(offset)?;
+        let iovs = iovec_slice(iov, iocnt)?;
+        Ok(for_each_iovec(iovs, |buf, len| {
+            let ret = UserSlice::new(buf as *mut u8, len)
+                .as_mut_slice()
+                .and_then(|buf| read_bytes(fd, buf, Some(offset)))
+                .unwrap_or_else(|e| -(e.code() as isize));
+            offset += ret.max(0) as u64;
+            ret
+        }))
+    })
+}
+
+pub(crate) fn sys_pwritev(
+    fd: i32,
+    iov: *const api::ctypes::iovec,
+    iocnt: i32,
+    offset: i64,
+) -> isize {
+    syscall_body!(sys_pwritev, {
+        let mut offset = position(offset)?;
+        let iovs = iovec_slice(iov, iocnt)?;
+        if iovs.len() > 1 && crate::sysctl::strict_posix() {
+            // Gathered into one write, as for `writev`
+            let mut data = Vec::new();
+            for iov in iovs {
+                data.extend_from_slice(
+                    UserSlice::new(iov.iov_base as *const u8, iov.iov_len).as_slice()?,
+                );
+            }
+            return write_bytes(fd, &data, Some(offset));
+        }
+        Ok(for_each_iovec(iovs, |buf, len| {
+            let ret = UserSlice::new(buf as *const u8, len)
+                .as_slice()
+                .and_then(|data| write_bytes(fd, data, Some(offset)))
+                .unwrap_or_else(|e| -(e.code() as isize));
+            offset += ret.max(0) as u64;
+            ret
+        }))
+    })
+}
+
+/// Set up an io_uring with room for `entries` submissions.
+pub(crate) fn sys_io_uring_setup(entries: u32, params: *mut IoUringParams) -> isize {
+    syscall_body!(sys_io_uring_setup, {
+        let params = UserPtr::from(params);
+        let mut value = params.read()?;
+        let ring = IoUring::new(entries, &mut value)?;
+        params.write(value)?;
+        api::add_file_like(ring).map(|fd| fd as isize)
+    })
+}
+
+/// Submit entries of the io_uring opened as `fd` and wait for completions.
+///
+/// Changing the signal mask during the wait isn't supported.
+pub(crate) fn sys_io_uring_enter(
+    fd: i32,
+    to_submit: u32,
+    min_complete: u32,
+    flags: u32,
+    sig: *const c_void,
+    _sigsz: usize,
+) -> isize {
+    syscall_body!(sys_io_uring_enter, {
+        api::get_file_like(fd)?;
+        let ring = uring::ring_from_fd(fd).ok_or(LinuxError::EOPNOTSUPP)?;
+        if !sig.is_null() {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(ring.enter(to_submit, min_complete, flags)? as isize)
+    })
 }
 
 // pub(crate) fn sys_chdir(path: *const c_char) -> i32 {
@@ -3,10 +3,15 @@ mod ctl;
 mod fs;
 mod io;
 mod mount;
+mod perm;
 mod pipe;
+mod poll;
 
+pub use self::c_type::{DirEnt, FileType, Kstat, Statx, StatxTimestamp};
 pub(crate) use self::ctl::*;
 pub(crate) use self::fs::*;
 pub(crate) use self::io::*;
 pub(crate) use self::mount::*;
+pub(crate) use self::perm::*;
 pub(crate) use self::pipe::*;
+pub(crate) use self::poll::*;
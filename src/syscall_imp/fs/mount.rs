@@ -1,6 +1,27 @@
+use crate::fs::mount::{self, kernel_fs, Image};
+use crate::fs::verity;
+use crate::fs::{meta, overlay, resolve_path_at, stat_path, AT_FDCWD};
+use crate::process::current_process;
+use crate::ptr::read_cstr;
+use crate::syscall_body;
 use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
 use core::ffi::{c_char, c_void};
 
+/// `mount` flag making a directory visible at `target` as well.
+const MS_BIND: u64 = 0x1000;
+
+/// Mount a filesystem on the directory `target`.
+///
+/// With `MS_BIND`, the directory `source` is mounted on `target` instead, in
+/// the mount namespace of the caller like any other mount. A `tmpfs` needs
+/// no `source`, see [`crate::fs::tmpfs`].
+///
+/// The filesystems implemented by the kernel are read from the image file at
+/// `source` and always mounted read-only, whatever `flags` says. With the
+/// option `verity=<root hash>` in `data`, the image must carry a hash tree
+/// with that root, see [`crate::fs::verity`]. Other types are handed to
+/// `axfs`.
 pub(crate) fn sys_mount(
     source: *const c_char,
     target: *const c_char,
@@ -8,9 +29,99 @@ pub(crate) fn sys_mount(
     flags: u64,
     data: *const c_void,
 ) -> i32 {
-    api::sys_mount(source, target, fstype, flags, data)
+    syscall_body!(sys_mount, {
+        if flags & MS_BIND != 0 {
+            return bind(source, target).map(|_| 0);
+        }
+        let fs_name = if fstype.is_null() {
+            None
+        } else {
+            Some(read_cstr(fstype)?)
+        };
+        if fs_name.as_deref() == Some("tmpfs") {
+            return mount_tmpfs(target).map(|_| 0);
+        }
+        let make = fs_name.as_deref().and_then(kernel_fs);
+        let Some(make) = make else {
+            return Ok(api::sys_mount(source, target, fstype, flags, data));
+        };
+        if !current_process().unwrap().cred().is_privileged() {
+            return Err(LinuxError::EPERM);
+        }
+        let source = resolve_path_at(AT_FDCWD, &read_cstr(source)?, true)?;
+        let target = resolve_path_at(AT_FDCWD, &read_cstr(target)?, true)?;
+        if stat_path(&target)?.st_mode & meta::S_IFMT != meta::S_IFDIR {
+            return Err(LinuxError::ENOTDIR);
+        }
+        let mut root_hash = None;
+        if !data.is_null() {
+            for option in read_cstr(data as *const c_char)?.split(',') {
+                if let Some(hex) = option.strip_prefix("verity=") {
+                    root_hash = Some(verity::parse_root_hash(hex)?);
+                }
+            }
+        }
+        let source = overlay::lookup(&source);
+        let image = match root_hash {
+            Some(root) => Image::open_verified(&source, root)?,
+            None => Image::open(&source)?,
+        };
+        let fs = make(image)?;
+        mount::mount(&target, fs)?;
+        Ok(0)
+    })
+}
+
+/// Bind-mount the directory `source` on the directory `target`.
+fn bind(source: *const c_char, target: *const c_char) -> LinuxResult {
+    if !current_process().unwrap().cred().is_privileged() {
+        return Err(LinuxError::EPERM);
+    }
+    let source = resolve_path_at(AT_FDCWD, &read_cstr(source)?, true)?;
+    let target = resolve_path_at(AT_FDCWD, &read_cstr(target)?, true)?;
+    for path in [&source, &target] {
+        if stat_path(path)?.st_mode & meta::S_IFMT != meta::S_IFDIR {
+            return Err(LinuxError::ENOTDIR);
+        }
+    }
+    mount::bind(&source, &target)
+}
+
+/// Mount a new tmpfs on the directory `target`.
+fn mount_tmpfs(target: *const c_char) -> LinuxResult {
+    if !current_process().unwrap().cred().is_privileged() {
+        return Err(LinuxError::EPERM);
+    }
+    let target = resolve_path_at(AT_FDCWD, &read_cstr(target)?, true)?;
+    if stat_path(&target)?.st_mode & meta::S_IFMT != meta::S_IFDIR {
+        return Err(LinuxError::ENOTDIR);
+    }
+    mount::mount_tmpfs(&target)
 }
 
+/// Unmount the filesystem mounted on `target`.
+///
+/// The files still open in a filesystem implemented by the kernel keep
+/// working, as with a lazy unmount.
 pub(crate) fn sys_umount(target: *const c_char) -> i32 {
-    api::sys_umount(target)
+    syscall_body!(sys_umount, {
+        let target_path = read_cstr(target)?;
+        // The files written through the cache reach the disk first
+        if let Err(e) = crate::fs::sync_all() {
+            warn!("umount: failed to write back the files: {:?}", e);
+        }
+        // Resolving the mount point of a bind mount would lead to its source
+        let mut path = resolve_path_at(AT_FDCWD, &target_path, false)?;
+        if !mount::is_mount_point(&path) {
+            path = resolve_path_at(AT_FDCWD, &target_path, true)?;
+        }
+        if mount::is_mount_point(&path) {
+            if !current_process().unwrap().cred().is_privileged() {
+                return Err(LinuxError::EPERM);
+            }
+            mount::umount(&path);
+            return Ok(0);
+        }
+        Ok(api::sys_umount(target))
+    })
 }
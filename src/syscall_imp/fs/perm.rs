@@ -0,0 +1,163 @@
+use crate::fs::meta::{self, S_IPERM};
+use crate::fs::{
+    mount, procfs, resolve_path_at, stat_at, stat_path, AT_EMPTY_PATH, AT_FDCWD,
+    AT_SYMLINK_NOFOLLOW,
+};
+use crate::process::current_process;
+use crate::ptr::read_cstr;
+use crate::syscall_body;
+use alloc::string::String;
+use arceos_posix_api::ctypes;
+use axerrno::{LinuxError, LinuxResult};
+use core::ffi::c_char;
+use core::sync::atomic::Ordering;
+
+const F_OK: i32 = 0;
+const X_OK: i32 = 1;
+const W_OK: i32 = 2;
+const R_OK: i32 = 4;
+
+/// Check the access against the effective instead of the real ids.
+const AT_EACCESS: i32 = 0x200;
+
+fn user_path_at(dirfd: i32, path: *const c_char, flags: i32) -> LinuxResult<String> {
+    let path = read_cstr(path)?;
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    resolve_path_at(dirfd, &path, flags & AT_SYMLINK_NOFOLLOW == 0)
+}
+
+/// Check whether the calling process can access the file at `path`.
+///
+/// # Arguments
+/// * `mode` - `F_OK` to only check existence, or a mask of `R_OK`, `W_OK` and `X_OK`
+pub(crate) fn sys_faccessat(dirfd: i32, path: *const c_char, mode: i32, flags: i32) -> i32 {
+    syscall_body!(sys_faccessat, {
+        if mode & !(R_OK | W_OK | X_OK) != 0 || flags & !(AT_EACCESS | AT_SYMLINK_NOFOLLOW) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = user_path_at(dirfd, path, flags)?;
+        if procfs::is_procfs_path(&path) {
+            procfs::open(&path)?;
+            return Ok(0);
+        }
+        let stat = stat_at(AT_FDCWD, &path, AT_SYMLINK_NOFOLLOW)?;
+        if mode == F_OK {
+            return Ok(0);
+        }
+
+        let cred = current_process().unwrap().cred();
+        let (uid, gid) = if flags & AT_EACCESS != 0 {
+            (cred.euid, cred.egid)
+        } else {
+            (cred.uid, cred.gid)
+        };
+        if mode & !granted(&stat, uid, gid) != 0 {
+            return Err(LinuxError::EACCES);
+        }
+        Ok(0)
+    })
+}
+
+/// The accesses among `R_OK`, `W_OK` and `X_OK` granted to `uid` and `gid` on
+/// the file of `stat`.
+fn granted(stat: &ctypes::stat, uid: u32, gid: u32) -> i32 {
+    let perm = stat.st_mode & 0o777;
+    if uid == 0 {
+        // Root may read and write anything, and execute anything executable by someone
+        let exec = if perm & 0o111 != 0 || stat.st_mode & meta::S_IFMT == 0o040000 {
+            X_OK
+        } else {
+            0
+        };
+        R_OK | W_OK | exec
+    } else if uid == stat.st_uid {
+        (perm >> 6) as i32 & 0o7
+    } else if gid == stat.st_gid {
+        (perm >> 3) as i32 & 0o7
+    } else {
+        perm as i32 & 0o7
+    }
+}
+
+/// Check that the calling process may write the file of `stat`, going by its
+/// effective ids.
+pub(crate) fn check_write_access(stat: &ctypes::stat) -> LinuxResult {
+    let cred = current_process().unwrap().cred();
+    if granted(stat, cred.euid, cred.egid) & W_OK == 0 {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(())
+}
+
+/// Change the permission bits of the file at `path`.
+///
+/// Only the owner of the file or root may change its mode.
+pub(crate) fn sys_fchmodat(dirfd: i32, path: *const c_char, mode: u32, flags: i32) -> i32 {
+    syscall_body!(sys_fchmodat, {
+        if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = user_path_at(dirfd, path, flags)?;
+        let stat = stat_path(&path)?;
+        mount::check_writable(&path)?;
+        let cred = current_process().unwrap().cred();
+        if !cred.is_privileged() && cred.euid != stat.st_uid {
+            return Err(LinuxError::EPERM);
+        }
+        meta::update(&path, |meta| meta.mode = Some(mode & S_IPERM));
+        Ok(0)
+    })
+}
+
+/// Change the owner and group of the file at `path`.
+///
+/// An id of -1 leaves it unchanged. Only root may give a file away; the owner
+/// may only change the group to its own.
+pub(crate) fn sys_fchownat(
+    dirfd: i32,
+    path: *const c_char,
+    owner: u32,
+    group: u32,
+    flags: i32,
+) -> i32 {
+    syscall_body!(sys_fchownat, {
+        if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = user_path_at(dirfd, path, flags)?;
+        let stat = stat_path(&path)?;
+        mount::check_writable(&path)?;
+        let owner = (owner != u32::MAX).then_some(owner);
+        let group = (group != u32::MAX).then_some(group);
+
+        let cred = current_process().unwrap().cred();
+        if !cred.is_privileged() {
+            let changes_owner = owner.is_some_and(|owner| owner != stat.st_uid);
+            let foreign_group = group.is_some_and(|group| group != cred.egid);
+            if changes_owner || cred.euid != stat.st_uid || foreign_group {
+                return Err(LinuxError::EPERM);
+            }
+        }
+        meta::update(&path, |meta| {
+            if owner.is_some() {
+                meta.uid = owner;
+            }
+            if group.is_some() {
+                meta.gid = group;
+            }
+            // Changing the owner clears the setuid and setgid bits
+            if !cred.is_privileged() {
+                meta.mode = Some((stat.st_mode & S_IPERM) & !0o6000);
+            }
+        });
+        Ok(0)
+    })
+}
+
+/// Set the file mode creation mask of the process and return the previous one.
+pub(crate) fn sys_umask(mask: u32) -> i32 {
+    let proc = current_process().unwrap();
+    proc.umask.swap(mask & 0o777, Ordering::Relaxed) as i32
+}
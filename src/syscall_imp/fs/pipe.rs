@@ -1,14 +1,29 @@
+use crate::fs::pipe::new_pipe;
+use crate::process::current_process;
+use crate::ptr::UserSlice;
 use crate::syscall_body;
 use arceos_posix_api as api;
 
+const O_NONBLOCK: i32 = 0o4000;
+
 pub(crate) fn sys_pipe2(fds: *mut i32, flags: i32) -> i32 {
     debug!("pipe2(fds: {:?}, flags: {:#x})", fds, flags);
     syscall_body!(sys_pipe2, {
-        if flags != 0 {
-            warn!("Now only support no flags for pipe2");
+        if flags & !O_NONBLOCK != 0 {
+            warn!("pipe2: only O_NONBLOCK is supported, flags {:#x}", flags);
         }
 
-        let mut fds = unsafe { core::slice::from_raw_parts_mut(fds, 2) };
-        Ok(api::sys_pipe(&mut fds))
+        let cred = current_process().unwrap().cred();
+        let fds = UserSlice::new(fds, 2).as_mut_slice()?;
+        let (read_end, write_end) = new_pipe(&cred, flags & O_NONBLOCK != 0)?;
+        fds[0] = api::add_file_like(read_end)?;
+        fds[1] = match api::add_file_like(write_end) {
+            Ok(fd) => fd,
+            Err(err) => {
+                api::sys_close(fds[0]);
+                return Err(err);
+            }
+        };
+        Ok(0)
     })
 }
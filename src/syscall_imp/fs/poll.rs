@@ -0,0 +1,138 @@
+use crate::process::signal::wait_interruptible;
+use crate::ptr::{UserPtr, UserSlice};
+use crate::syscall_body;
+use crate::syscall_imp::task::timespec_to_duration;
+use crate::syscall_imp::SIGSET_SIZE_IN_BYTE;
+use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::monotonic_time;
+use axtask::{current, TaskExtRef, WaitQueue};
+use core::time::Duration;
+
+const POLLIN: i16 = 0x001;
+const POLLOUT: i16 = 0x004;
+const POLLERR: i16 = 0x008;
+const POLLHUP: i16 = 0x010;
+const POLLNVAL: i16 = 0x020;
+
+/// The most descriptors one call may poll, the default `RLIMIT_NOFILE`.
+const POLL_MAX_FDS: usize = 1024;
+
+/// How often the descriptors are polled again while none is ready. Files
+/// have no common queue to be woken up on when they become ready.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// `struct pollfd` in the Linux uapi.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+/// Fill in the `revents` of `fds`, returning how many are nonzero.
+fn poll_once(fds: &mut [PollFd]) -> usize {
+    let mut ready = 0;
+    for pfd in fds.iter_mut() {
+        pfd.revents = 0;
+        if pfd.fd < 0 {
+            continue;
+        }
+        pfd.revents = match api::get_file_like(pfd.fd).and_then(|file| file.poll()) {
+            Ok(state) => {
+                let mut revents = 0;
+                if state.readable {
+                    revents |= POLLIN;
+                }
+                if state.writable {
+                    revents |= POLLOUT;
+                }
+                revents & (pfd.events | POLLERR | POLLHUP)
+            }
+            Err(LinuxError::EBADF) => POLLNVAL,
+            Err(_) => POLLERR,
+        };
+        if pfd.revents != 0 {
+            ready += 1;
+        }
+    }
+    ready
+}
+
+/// Poll `fds` until one is ready or `deadline` passes, returning how many are
+/// ready.
+fn wait_ready(fds: &mut [PollFd], deadline: Option<Duration>) -> LinuxResult<isize> {
+    let wq = WaitQueue::new();
+    loop {
+        let ready = poll_once(fds);
+        if ready > 0 {
+            return Ok(ready as isize);
+        }
+        let left = deadline.map_or(POLL_INTERVAL, |deadline| {
+            deadline.saturating_sub(monotonic_time())
+        });
+        if left.is_zero() {
+            return Ok(0);
+        }
+        wait_interruptible(&wq, Some(left.min(POLL_INTERVAL)), || false)?;
+    }
+}
+
+/// Wait for one of `fds` to be ready, for at most `timeout`, with the signal
+/// mask `sigmask` in place of that of the thread while waiting.
+///
+/// The mask of the thread is put back before returning, so a signal which
+/// only `sigmask` lets through interrupts the wait, but is delivered only once
+/// the mask of the thread allows it.
+pub(crate) fn sys_ppoll(
+    fds: *mut PollFd,
+    nfds: usize,
+    timeout: *const api::ctypes::timespec,
+    sigmask: *const usize,
+    sigsetsize: usize,
+) -> isize {
+    syscall_body!(sys_ppoll, {
+        if nfds > POLL_MAX_FDS {
+            return Err(LinuxError::EINVAL);
+        }
+        let fds = UserSlice::new(fds, nfds).as_mut_slice()?;
+        let timeout = match UserPtr::from(timeout).read_opt()? {
+            Some(ts) => Some(timespec_to_duration(&ts)?),
+            None => None,
+        };
+        let sigmask = UserPtr::from(sigmask).read_opt()?;
+        if sigmask.is_some() && sigsetsize != SIGSET_SIZE_IN_BYTE {
+            return Err(LinuxError::EINVAL);
+        }
+
+        let task = current();
+        let saved_mask = sigmask.map(|mask| {
+            let mut sig_module = task.task_ext().signal();
+            let saved = sig_module.sig_set.mask();
+            sig_module.sig_set.set_mask(mask);
+            task.task_ext().set_signal_pending();
+            saved
+        });
+        let res = wait_ready(fds, timeout.map(|timeout| monotonic_time() + timeout));
+        if let Some(saved) = saved_mask {
+            task.task_ext().signal().sig_set.set_mask(saved);
+        }
+        res
+    })
+}
+
+/// `poll`, which x86_64 still has: a timeout in milliseconds, negative for
+/// none.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn sys_poll(fds: *mut PollFd, nfds: usize, timeout_ms: i32) -> isize {
+    syscall_body!(sys_poll, {
+        if nfds > POLL_MAX_FDS {
+            return Err(LinuxError::EINVAL);
+        }
+        let fds = UserSlice::new(fds, nfds).as_mut_slice()?;
+        let deadline =
+            (timeout_ms >= 0).then(|| monotonic_time() + Duration::from_millis(timeout_ms as u64));
+        wait_ready(fds, deadline)
+    })
+}
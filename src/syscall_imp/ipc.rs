@@ -0,0 +1,186 @@
+use super::task::{deadline_to_timeout, timespec_to_duration};
+use crate::ipc::mqueue::{self, MqAttr};
+use crate::ipc::sem::{self, SemArg, SemBuf, SemidDs, GETALL, SETALL};
+use crate::ipc::shm::{self, ShmidDs};
+use crate::ipc::{IPC_64, IPC_RMID, IPC_SET, IPC_STAT};
+use crate::ptr::{read_cstr, UserPtr, UserSlice};
+use crate::syscall_body;
+use alloc::vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use core::ffi::c_char;
+use core::time::Duration;
+
+pub(crate) fn sys_shmget(key: i32, size: usize, flags: i32) -> isize {
+    syscall_body!(sys_shmget, { shm::get(key, size, flags) })
+}
+
+pub(crate) fn sys_shmat(id: i32, addr: usize, flags: i32) -> isize {
+    syscall_body!(sys_shmat, { shm::attach(id, addr, flags) })
+}
+
+pub(crate) fn sys_shmdt(addr: usize) -> isize {
+    syscall_body!(sys_shmdt, {
+        shm::detach(addr)?;
+        Ok(0)
+    })
+}
+
+/// Only `IPC_STAT`, `IPC_SET` and `IPC_RMID` are supported.
+pub(crate) fn sys_shmctl(id: i32, cmd: i32, buf: *mut ShmidDs) -> isize {
+    syscall_body!(sys_shmctl, {
+        let buf = UserPtr::from(buf);
+        match cmd & !IPC_64 {
+            IPC_STAT => buf.write(shm::stat(id)?)?,
+            IPC_SET => shm::set(id, &buf.read()?)?,
+            IPC_RMID => shm::remove(id)?,
+            _ => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_semget(key: i32, nsems: i32, flags: i32) -> isize {
+    syscall_body!(sys_semget, {
+        let nsems = usize::try_from(nsems).map_err(|_| LinuxError::EINVAL)?;
+        sem::get(key, nsems, flags)
+    })
+}
+
+/// `timeout` is relative, unlike the one of the message queues.
+pub(crate) fn sys_semtimedop(
+    id: i32,
+    sops: *const SemBuf,
+    nsops: usize,
+    timeout: *const ctypes::timespec,
+) -> isize {
+    syscall_body!(sys_semtimedop, {
+        let timeout = match UserPtr::from(timeout).read_opt()? {
+            Some(ts) => Some(timespec_to_duration(&ts)?),
+            None => None,
+        };
+        if nsops > sem::SEMOPM {
+            return Err(LinuxError::E2BIG);
+        }
+        let ops = UserSlice::new(sops, nsops).as_slice()?.to_vec();
+        sem::op(id, &ops, timeout)?;
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_semop(id: i32, sops: *const SemBuf, nsops: usize) -> isize {
+    sys_semtimedop(id, sops, nsops, core::ptr::null())
+}
+
+/// `arg` is the `union semun`, which holds a value or a pointer.
+pub(crate) fn sys_semctl(id: i32, num: i32, cmd: i32, arg: usize) -> isize {
+    syscall_body!(sys_semctl, {
+        let cmd = cmd & !IPC_64;
+        let num = usize::try_from(num).map_err(|_| LinuxError::EINVAL)?;
+        match cmd {
+            GETALL => {
+                let mut vals = vec![0; sem::nsems(id)?];
+                let ret = sem::ctl(id, num, cmd, SemArg::Array(&mut vals))?;
+                UserSlice::new(arg as *mut u16, vals.len())
+                    .as_mut_slice()?
+                    .copy_from_slice(&vals[..]);
+                Ok(ret)
+            }
+            SETALL => {
+                let mut vals = UserSlice::new(arg as *const u16, sem::nsems(id)?)
+                    .as_slice()?
+                    .to_vec();
+                sem::ctl(id, num, cmd, SemArg::Array(&mut vals))
+            }
+            IPC_STAT => {
+                let mut ds = SemidDs::default();
+                let ret = sem::ctl(id, num, cmd, SemArg::Ds(&mut ds))?;
+                UserPtr::from(arg as *mut SemidDs).write(ds)?;
+                Ok(ret)
+            }
+            IPC_SET => {
+                let mut ds = UserPtr::from(arg as *const SemidDs).read()?;
+                sem::ctl(id, num, cmd, SemArg::Ds(&mut ds))
+            }
+            _ => sem::ctl(id, num, cmd, SemArg::Val(arg as i32)),
+        }
+    })
+}
+
+/// The time left until the absolute `CLOCK_REALTIME` deadline at `timeout`,
+/// if there is one.
+fn mq_timeout(timeout: *const ctypes::timespec) -> LinuxResult<Option<Duration>> {
+    match UserPtr::from(timeout).read_opt()? {
+        Some(ts) => Ok(Some(deadline_to_timeout(
+            ctypes::CLOCK_REALTIME,
+            timespec_to_duration(&ts)?,
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// `name` comes without the leading slash, which the libc strips.
+pub(crate) fn sys_mq_open(
+    name: *const c_char,
+    oflag: i32,
+    mode: u32,
+    attr: *const MqAttr,
+) -> isize {
+    syscall_body!(sys_mq_open, {
+        let name = read_cstr(name)?;
+        let attr = UserPtr::from(attr).read_opt()?;
+        let desc = mqueue::open(&name, oflag, mode, attr.as_ref())?;
+        api::add_file_like(desc).map(|fd| fd as isize)
+    })
+}
+
+pub(crate) fn sys_mq_unlink(name: *const c_char) -> isize {
+    syscall_body!(sys_mq_unlink, {
+        mqueue::unlink(&read_cstr(name)?)?;
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_mq_timedsend(
+    mqdes: i32,
+    msg: *const u8,
+    len: usize,
+    prio: u32,
+    timeout: *const ctypes::timespec,
+) -> isize {
+    syscall_body!(sys_mq_timedsend, {
+        let desc = mqueue::descriptor_from_fd(mqdes)?;
+        let timeout = mq_timeout(timeout)?;
+        let msg = UserSlice::new(msg, len).as_slice()?;
+        desc.send(msg, prio, timeout)?;
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_mq_timedreceive(
+    mqdes: i32,
+    msg: *mut u8,
+    len: usize,
+    prio: *mut u32,
+    timeout: *const ctypes::timespec,
+) -> isize {
+    syscall_body!(sys_mq_timedreceive, {
+        let desc = mqueue::descriptor_from_fd(mqdes)?;
+        let timeout = mq_timeout(timeout)?;
+        let buf = UserSlice::new(msg, len).as_mut_slice()?;
+        let (data, msg_prio) = desc.receive(len, timeout)?;
+        buf[..data.len()].copy_from_slice(&data);
+        UserPtr::from(prio).write_opt(msg_prio)?;
+        Ok(data.len())
+    })
+}
+
+pub(crate) fn sys_mq_getsetattr(mqdes: i32, new: *const MqAttr, old: *mut MqAttr) -> isize {
+    syscall_body!(sys_mq_getsetattr, {
+        let desc = mqueue::descriptor_from_fd(mqdes)?;
+        let new = UserPtr::from(new).read_opt()?;
+        let attr = desc.set_attr(new.as_ref());
+        UserPtr::from(old).write_opt(attr)?;
+        Ok(0)
+    })
+}
@@ -1,3 +1,4 @@
+use crate::mm::{self, tlb, vma, VmArea};
 use axhal::paging::MappingFlags;
 use axtask::{current, TaskExtRef};
 use core::sync::atomic::Ordering::SeqCst;
@@ -9,13 +10,14 @@ pub(crate) fn sys_brk(addr: *mut u8) -> isize {
     let proc = curr_ext.get_proc().unwrap();
     let brk = proc.heap_current.load(SeqCst) as usize;
     let bottom = proc.heap_bottom.load(SeqCst) as usize;
+    let top = proc.heap_top.load(SeqCst) as usize;
     let addr = addr as usize;
     // 如果 addr 为 0，则返回当前 brk 地址
     if addr == 0 {
         return brk as isize;
     }
-    // 如果 addr 小于 brk_bottom，则返回 -1
-    if addr < bottom {
+    // 如果 addr 不在堆的范围内，则返回 -1
+    if addr < bottom || addr > top {
         return -1;
     }
     let mut aspace = proc.aspace.lock();
@@ -24,20 +26,38 @@ pub(crate) fn sys_brk(addr: *mut u8) -> isize {
     if addr < brk {
         let start_addr = VirtAddr::from(addr).align_up_4k();
         let end_addr = VirtAddr::from(brk).align_up_4k();
-        if aspace
-            .unmap(start_addr, end_addr.sub(start_addr.as_usize()).as_usize())
-            .is_err()
-        {
+        let size = end_addr.sub(start_addr.as_usize()).as_usize();
+        let resident = mm::rss::mapped(&aspace, start_addr, size);
+        mm::zero::release(&aspace, start_addr, size);
+        if aspace.unmap(start_addr, size).is_err() {
             return -1;
         }
+        tlb::flush(&aspace, start_addr, size);
+        mm::forget_frames(mm::aspace_key(&proc.aspace), start_addr, size);
+        mm::rss::uncharge(mm::aspace_key(&proc.aspace), resident);
+        proc.vm_areas.lock().remove(start_addr, size);
     } else {
         let start_addr = VirtAddr::from(brk).align_up_4k();
         let end_addr = VirtAddr::from(addr).align_up_4k();
         let permission = MappingFlags::all();
 
-        match proc.alloc_range_lazy(start_addr, end_addr, permission) {
-            Ok(_) | Err(axerrno::AxError::InvalidInput) => {}
-            Err(_) => return -1,
+        // 地址空间已被锁住，直接映射
+        if start_addr < end_addr {
+            if aspace
+                .map_alloc(start_addr, end_addr - start_addr, permission, false)
+                .is_err()
+            {
+                return -1;
+            }
+            proc.vm_areas.lock().insert(
+                VmArea::new(
+                    start_addr,
+                    end_addr,
+                    permission,
+                    vma::MAP_PRIVATE | vma::MAP_ANONYMOUS,
+                )
+                .with_name("[heap]", 0),
+            );
         }
     }
 
@@ -1,14 +1,23 @@
-use crate::{process::current_process, syscall_body};
-use axerrno::LinuxError;
+use crate::fs::devfs::{self, DevMem};
+use crate::fs::{cache, memfd, overlay};
+use crate::mm::{self, aspace_key, tlb, Frame, VmArea};
+use crate::{process::current_process, syscall_body, uring};
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use axerrno::{LinuxError, LinuxResult};
 use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
 use axtask::{current, TaskExtRef};
-use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange};
+use core::sync::atomic::Ordering;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange};
 
 bitflags::bitflags! {
     /// permissions for sys_mmap
     ///
     /// See <https://github.com/bminor/glibc/blob/master/bits/mman.h>
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     struct MmapProt: i32 {
         /// Page can be read.
         const PROT_READ = 1 << 0;
@@ -39,7 +48,7 @@ bitflags::bitflags! {
     /// flags for sys_mmap
     ///
     /// See <https://github.com/bminor/glibc/blob/master/bits/mman.h>
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     struct MmapFlags: i32 {
         /// Share changes
         const MAP_SHARED = 1 << 0;
@@ -53,6 +62,8 @@ bitflags::bitflags! {
         const MAP_NORESERVE = 1 << 14;
         /// Allocation is for a stack.
         const MAP_STACK = 0x20000;
+        /// Like `MAP_FIXED`, but fail with `EEXIST` instead of replacing existing mappings.
+        const MAP_FIXED_NOREPLACE = 0x100000;
     }
 }
 
@@ -76,12 +87,44 @@ pub(crate) fn sys_mmap(
         // An example is the flags contained none of MAP_PRIVATE, MAP_SHARED, or MAP_SHARED_VALIDATE.
         let map_flags = MmapFlags::from_bits_truncate(flags);
 
-        let start_addr = if map_flags.contains(MmapFlags::MAP_FIXED) {
-            VirtAddr::from(addr as usize)
+        if length == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+
+        // The size of the mappings a MAP_FIXED mapping replaces
+        let mut replaced = None;
+        let start_addr = if map_flags
+            .intersects(MmapFlags::MAP_FIXED | MmapFlags::MAP_FIXED_NOREPLACE)
+        {
+            let start = VirtAddr::from(addr as usize);
+            if !start.is_aligned_4k() {
+                return Err(LinuxError::EINVAL);
+            }
+            let size = memory_addr::align_up_4k(length);
+            if !aspace.contains_range(start, size) {
+                return Err(LinuxError::ENOMEM);
+            }
+            // The range is free iff the first free area found from `start` begins at `start`
+            let is_free =
+                aspace.find_free_area(start, size, VirtAddrRange::new(aspace.base(), aspace.end()))
+                    == Some(start);
+            if !is_free {
+                if map_flags.contains(MmapFlags::MAP_FIXED_NOREPLACE) {
+                    return Err(LinuxError::EEXIST);
+                }
+                replaced = Some(size);
+            }
+            start
         } else {
+            // Without a hint, start from the possibly randomized base
+            let hint = if addr.is_null() {
+                VirtAddr::from(proc.mmap_base.load(Ordering::SeqCst))
+            } else {
+                VirtAddr::from(addr as usize)
+            };
             aspace
                 .find_free_area(
-                    VirtAddr::from(addr as usize),
+                    hint,
                     length,
                     VirtAddrRange::new(aspace.base(), aspace.end()),
                 )
@@ -92,44 +135,226 @@ pub(crate) fn sys_mmap(
                 ))
                 .ok_or(LinuxError::ENOMEM)?
         };
+        // Replace whatever is mapped in the range, once the new mapping has
+        // been checked and its backing found, so that a failure leaves the old
+        // one in place. The address space stays locked until the new mapping
+        // is in place, so nobody can observe the hole in between.
+        let replace = |aspace: &mut AddrSpace| -> LinuxResult {
+            let Some(size) = replaced else {
+                return Ok(());
+            };
+            let resident = mm::rss::mapped(aspace, start_addr, size);
+            mm::zero::release(aspace, start_addr, size);
+            aspace.unmap(start_addr, size)?;
+            tlb::flush(aspace, start_addr, size);
+            mm::forget_frames(aspace_key(&proc.aspace), start_addr, size);
+            mm::rss::uncharge(aspace_key(&proc.aspace), resident);
+            proc.vm_areas.lock().remove(start_addr, size);
+            Ok(())
+        };
+        // What the table of areas records, whichever way the pages are mapped
+        let area = VmArea::new(
+            start_addr,
+            start_addr + memory_addr::align_up_4k(length),
+            permission_flags.into(),
+            (map_flags - MmapFlags::MAP_FIXED - MmapFlags::MAP_FIXED_NOREPLACE).bits() as u32,
+        );
+
+        if is_dev_mem(fd) {
+            // Map the physical range itself, uncached as it is MMIO
+            if offset < 0 || !memory_addr::is_aligned_4k(offset as usize) {
+                return Err(LinuxError::EINVAL);
+            }
+            let paddr = PhysAddr::from(offset as usize);
+            let size = memory_addr::align_up_4k(length);
+            DevMem::check_range(paddr, size)?;
+            let flags = MappingFlags::from(permission_flags) | MappingFlags::DEVICE;
+            replace(&mut aspace)?;
+            aspace.map_linear(start_addr, paddr, size, flags)?;
+            mm::rss::charge(aspace_key(&proc.aspace), size / memory_addr::PAGE_SIZE_4K);
+            proc.vm_areas
+                .lock()
+                .insert(area.with_name("/dev/mem", offset as u64));
+            return Ok(start_addr.as_usize());
+        }
+
+        if let Some(dev) = devfs::dev_file_from_fd(fd) {
+            // Devices hand out their own memory, e.g. a framebuffer
+            if offset < 0 || !memory_addr::is_aligned_4k(offset as usize) {
+                return Err(LinuxError::EINVAL);
+            }
+            let size = memory_addr::align_up_4k(length);
+            let (paddr, dev_flags) = dev.mmap(offset as usize, size)?;
+            let flags = MappingFlags::from(permission_flags) | dev_flags;
+            replace(&mut aspace)?;
+            aspace.map_linear(start_addr, paddr, size, flags)?;
+            mm::rss::charge(aspace_key(&proc.aspace), size / memory_addr::PAGE_SIZE_4K);
+            proc.vm_areas.lock().insert(VmArea {
+                offset: offset as u64,
+                ..area
+            });
+            return Ok(start_addr.as_usize());
+        }
+
+        if let Some(ring) = uring::ring_from_fd(fd) {
+            // The rings are shared with the kernel, never copied
+            if !map_flags.contains(MmapFlags::MAP_SHARED) || offset < 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let frames = ring.frames(offset as u64, memory_addr::align_up_4k(length))?;
+            replace(&mut aspace)?;
+            let key = aspace_key(&proc.aspace);
+            mm::map_frames(
+                key,
+                &mut aspace,
+                start_addr,
+                &frames,
+                permission_flags.into(),
+            )?;
+            proc.vm_areas
+                .lock()
+                .insert(area.with_name("anon_inode:[io_uring]", offset as u64));
+            return Ok(start_addr.as_usize());
+        }
+
+        if let Some(file) = memfd::file_from_fd(fd) {
+            if offset < 0 || !memory_addr::is_aligned_4k(offset as usize) {
+                return Err(LinuxError::EINVAL);
+            }
+            let size = memory_addr::align_up_4k(length);
+            let frames = file.frames(offset as usize, size)?;
+            let area = area.with_name(file.path(), offset as u64);
+            replace(&mut aspace)?;
+            if map_flags.contains(MmapFlags::MAP_SHARED) {
+                let key = aspace_key(&proc.aspace);
+                mm::map_frames(
+                    key,
+                    &mut aspace,
+                    start_addr,
+                    &frames,
+                    permission_flags.into(),
+                )?;
+                proc.vm_areas.lock().insert(area);
+                return Ok(start_addr.as_usize());
+            }
+            // A private mapping starts as a copy of the file
+            aspace.map_alloc(start_addr, size, permission_flags.into(), true)?;
+            mm::rss::charge(aspace_key(&proc.aspace), size / memory_addr::PAGE_SIZE_4K);
+            proc.vm_areas.lock().insert(area);
+            // Through the page table, as the pages may not be writable
+            for (i, frame) in frames.iter().enumerate() {
+                let page = start_addr + i * memory_addr::PAGE_SIZE_4K;
+                aspace.write(page, frame.as_slice())?;
+            }
+            return Ok(start_addr.as_usize());
+        }
+
+        let file = (!map_flags.contains(MmapFlags::MAP_ANONYMOUS))
+            .then(|| arceos_posix_api::File::from_fd(fd).ok())
+            .flatten();
+        if let Some(file) = file {
+            // Regular files are mapped from the page cache
+            if offset < 0 || !memory_addr::is_aligned_4k(offset as usize) {
+                return Err(LinuxError::EINVAL);
+            }
+            let size = memory_addr::align_up_4k(length);
+            let path = overlay::logical(file.path());
+            let area = area.with_file(path.clone(), offset as u64);
+            if map_flags.contains(MmapFlags::MAP_SHARED) {
+                let writable = permission_flags.contains(MmapProt::PROT_WRITE);
+                let frames = cache::frames(&path, offset as u64, size, writable)?;
+                replace(&mut aspace)?;
+                let key = aspace_key(&proc.aspace);
+                mm::map_frames(
+                    key,
+                    &mut aspace,
+                    start_addr,
+                    &frames,
+                    permission_flags.into(),
+                )?;
+                proc.vm_areas.lock().insert(area);
+                return Ok(start_addr.as_usize());
+            }
+            // A private mapping starts as a copy of the file
+            let mut data = vec![0; size];
+            cache::read_at(&path, offset as u64, &mut data)?;
+            replace(&mut aspace)?;
+            aspace.map_alloc(start_addr, size, permission_flags.into(), true)?;
+            mm::rss::charge(aspace_key(&proc.aspace), size / memory_addr::PAGE_SIZE_4K);
+            aspace.write(start_addr, &data)?;
+            proc.vm_areas.lock().insert(area);
+            return Ok(start_addr.as_usize());
+        }
+
+        if map_flags.contains(MmapFlags::MAP_SHARED | MmapFlags::MAP_ANONYMOUS) {
+            // Zeroed frames of our own, which a child sharing them sees too
+            let size = memory_addr::align_up_4k(length);
+            let frames = (0..size / memory_addr::PAGE_SIZE_4K)
+                .map(|_| Frame::alloc())
+                .collect::<LinuxResult<Vec<_>>>()?;
+            replace(&mut aspace)?;
+            let key = aspace_key(&proc.aspace);
+            mm::map_frames(
+                key,
+                &mut aspace,
+                start_addr,
+                &frames,
+                permission_flags.into(),
+            )?;
+            proc.vm_areas
+                .lock()
+                .insert(area.with_name("/dev/zero (deleted)", 0));
+            return Ok(start_addr.as_usize());
+        }
 
         let populate = if fd == -1 {
             false
         } else {
             !map_flags.contains(MmapFlags::MAP_ANONYMOUS)
         };
+        if offset < 0 {
+            return Err(LinuxError::EINVAL);
+        }
 
         let end_addr = (start_addr + length).align_up_4k();
+        let size = end_addr
+            .sub(start_addr.align_down_4k().as_usize())
+            .as_usize();
+        let file_inner = if populate {
+            Some(arceos_posix_api::read_file(fd, offset as usize, length)?)
+        } else {
+            None
+        };
 
+        replace(&mut aspace)?;
         aspace.map_alloc(
             start_addr.align_down_4k(),
-            end_addr
-                .sub(start_addr.align_down_4k().as_usize())
-                .as_usize(),
+            size,
             permission_flags.into(),
             populate,
         )?;
-
-        drop(aspace);
-
-        if offset < 0 {
-            return Err(LinuxError::EINVAL);
-        }
-
         if populate {
-            let file_inner = arceos_posix_api::read_file(fd, offset as usize, length)?;
-
-            let ptr = start_addr.as_mut_ptr();
+            mm::rss::charge(aspace_key(&proc.aspace), size / memory_addr::PAGE_SIZE_4K);
+        }
+        proc.vm_areas.lock().insert(area);
 
-            unsafe {
-                core::ptr::copy_nonoverlapping(file_inner.as_ptr(), ptr, length);
-            }
+        if let Some(file_inner) = file_inner {
+            // Through the page table, as the pages may not be writable
+            let len = length.min(file_inner.len());
+            aspace.write(start_addr, &file_inner[..len])?;
         }
 
         Ok(start_addr.as_usize())
     })
 }
 
+/// Whether `fd` is an open `/dev/mem`.
+fn is_dev_mem(fd: i32) -> bool {
+    fd >= 0
+        && arceos_posix_api::get_file_like(fd)
+            .is_ok_and(|file| file.into_any().downcast::<DevMem>().is_ok())
+}
+
 pub(crate) fn sys_munmap(addr: *mut usize, mut length: usize) -> i32 {
     syscall_body!(sys_munmap, {
         let curr = current();
@@ -137,8 +362,210 @@ pub(crate) fn sys_munmap(addr: *mut usize, mut length: usize) -> i32 {
         let mut aspace = proc.aspace.lock();
         length = memory_addr::align_up_4k(length);
         let start_addr = VirtAddr::from(addr as usize);
+        let resident = mm::rss::mapped(&aspace, start_addr, length);
+        mm::zero::release(&aspace, start_addr, length);
         aspace.unmap(start_addr, length)?;
-        axhal::arch::flush_tlb(None);
+        tlb::flush(&aspace, start_addr, length);
+        mm::forget_frames(aspace_key(&proc.aspace), start_addr, length);
+        mm::rss::uncharge(aspace_key(&proc.aspace), resident);
+        proc.vm_areas.lock().remove(start_addr, length);
+        Ok(0)
+    })
+}
+
+/// Change the protection of the pages in `[addr, addr + length)`, which must
+/// all be mapped.
+pub(crate) fn sys_mprotect(addr: usize, length: usize, prot: i32) -> i32 {
+    syscall_body!(sys_mprotect, {
+        let prot = MmapProt::from_bits(prot).ok_or(LinuxError::EINVAL)?;
+        let (start, length) = user_range(addr, length)?;
+        if length == 0 {
+            return Ok(0);
+        }
+        let proc = current_process().unwrap();
+        let mut aspace = proc.aspace.lock();
+        aspace.protect(start, length, prot.into())?;
+        mm::zero::protect(&aspace, start, length, prot.into());
+        mm::swap::protect(aspace_key(&proc.aspace), start, length, prot.into());
+        proc.vm_areas.lock().protect(start, length, prot.into());
+        tlb::flush(&aspace, start, length);
+        Ok(0)
+    })
+}
+
+bitflags::bitflags! {
+    /// flags for sys_msync
+    #[derive(Debug)]
+    struct MsyncFlags: i32 {
+        /// Schedule the write back and return immediately.
+        const MS_ASYNC = 1;
+        /// Invalidate other mappings of the same file.
+        const MS_INVALIDATE = 2;
+        /// Write back and wait for it to complete.
+        const MS_SYNC = 4;
+    }
+}
+
+/// Check that `[addr, addr + length)` is a page-aligned range inside the user
+/// address space, and return it aligned up to whole pages.
+fn user_range(addr: usize, length: usize) -> LinuxResult<(VirtAddr, usize)> {
+    if !memory_addr::is_aligned_4k(addr) {
+        return Err(LinuxError::EINVAL);
+    }
+    let length = length
+        .checked_add(memory_addr::PAGE_SIZE_4K - 1)
+        .ok_or(LinuxError::ENOMEM)?
+        & !(memory_addr::PAGE_SIZE_4K - 1);
+    let start = VirtAddr::from(addr);
+    let aspace = current_process().unwrap().aspace.clone();
+    if length != 0 && !aspace.lock().contains_range(start, length) {
+        return Err(LinuxError::ENOMEM);
+    }
+    Ok((start, length))
+}
+
+/// Write the files with shared mappings in the range back from the page cache.
+pub(crate) fn sys_msync(addr: usize, length: usize, flags: i32) -> i32 {
+    syscall_body!(sys_msync, {
+        let Some(flags) = MsyncFlags::from_bits(flags) else {
+            return Err(LinuxError::EINVAL);
+        };
+        if flags.contains(MsyncFlags::MS_ASYNC | MsyncFlags::MS_SYNC) {
+            return Err(LinuxError::EINVAL);
+        }
+        let (start, length) = user_range(addr, length)?;
+
+        let proc = current_process().unwrap();
+        let paths: BTreeSet<String> = proc
+            .vm_areas
+            .lock()
+            .overlapping(start, length)
+            .filter(|area| area.is_shared())
+            .filter_map(|area| area.file.clone())
+            .collect();
+        // The mappings share the pages of the cache, which are written back
+        // whole
+        for path in paths {
+            cache::sync(&path).map_err(|_| LinuxError::EIO)?;
+        }
+        Ok(0)
+    })
+}
+
+const MADV_NORMAL: i32 = 0;
+const MADV_RANDOM: i32 = 1;
+const MADV_SEQUENTIAL: i32 = 2;
+const MADV_WILLNEED: i32 = 3;
+const MADV_DONTNEED: i32 = 4;
+const MADV_FREE: i32 = 8;
+
+/// Give advice about the use of memory.
+///
+/// `MADV_DONTNEED` drops the private pages of the range but keeps it mapped:
+/// the next access faults in a zeroed page if the mapping is anonymous, and
+/// the page is read again from the file otherwise. Shared pages are those of
+/// their backing, which they keep. `MADV_FREE` is only for private anonymous
+/// memory, and drops it the same way. Other advice is only a hint and is
+/// accepted without effect.
+pub(crate) fn sys_madvise(addr: usize, length: usize, advice: i32) -> i32 {
+    syscall_body!(sys_madvise, {
+        let (start, length) = user_range(addr, length)?;
+        match advice {
+            MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED => Ok(0),
+            MADV_DONTNEED | MADV_FREE => {
+                let proc = current_process().unwrap();
+                let mut aspace = proc.aspace.lock();
+                let areas: Vec<VmArea> = proc
+                    .vm_areas
+                    .lock()
+                    .overlapping(start, length)
+                    .cloned()
+                    .collect();
+                // Locked pages can't be dropped, as on Linux
+                let private_anonymous = |area: &VmArea| !area.is_shared() && area.is_anonymous();
+                if areas
+                    .iter()
+                    .any(|area| area.locked || (advice == MADV_FREE && !private_anonymous(area)))
+                {
+                    return Err(LinuxError::EINVAL);
+                }
+                let key = aspace_key(&proc.aspace);
+                for area in &areas {
+                    // Other private areas, like the vDSO, hold no memory of
+                    // their own
+                    if private_anonymous(area) || (!area.is_shared() && area.file.is_some()) {
+                        let (from, to) = (area.start.max(start), area.end.min(start + length));
+                        drop_private_pages(key, &mut aspace, area, from, to - from)?;
+                    }
+                }
+                tlb::flush(&aspace, start, length);
+                Ok(0)
+            }
+            _ => Err(LinuxError::EINVAL),
+        }
+    })
+}
+
+/// Drop the pages of `[start, start + len)` in the private `area` of
+/// `aspace`, whose key is `key`, whether resident, on the zero page or
+/// swapped out. They are mapped again at once, to fault in zeroed pages if
+/// the area is anonymous, or filled from its file.
+fn drop_private_pages(
+    key: usize,
+    aspace: &mut AddrSpace,
+    area: &VmArea,
+    start: VirtAddr,
+    len: usize,
+) -> LinuxResult {
+    let resident = mm::rss::mapped(aspace, start, len);
+    mm::zero::release(aspace, start, len);
+    aspace.unmap(start, len)?;
+    mm::forget_frames(key, start, len);
+    mm::rss::uncharge(key, resident);
+    let flags = area.prot | MappingFlags::USER;
+    let Some(path) = area.file.as_ref().filter(|_| !area.is_anonymous()) else {
+        aspace.map_alloc(start, len, flags, false)?;
+        return Ok(());
+    };
+    aspace.map_alloc(start, len, flags, true)?;
+    mm::rss::charge(key, len / memory_addr::PAGE_SIZE_4K);
+    let mut data = vec![0; len];
+    cache::read_at(path, area.offset + (start - area.start) as u64, &mut data)?;
+    // Through the page table, as the pages may not be writable
+    aspace.write(start, &data)?;
+    Ok(())
+}
+
+/// Lock pages in memory: read back those swapped out, and never swap them
+/// out again.
+pub(crate) fn sys_mlock(addr: usize, length: usize) -> i32 {
+    syscall_body!(sys_mlock, {
+        let start = memory_addr::align_down_4k(addr);
+        let (start, length) = user_range(start, length + (addr - start))?;
+        let proc = current_process().unwrap();
+        let mut aspace = proc.aspace.lock();
+        mm::swap::pin(aspace_key(&proc.aspace), &mut aspace, start, length)?;
+        proc.vm_areas.lock().set_locked(start, length, true);
+        Ok(0)
+    })
+}
+
+/// Unlock pages in memory, so that the private ones may be swapped out.
+pub(crate) fn sys_munlock(addr: usize, length: usize) -> i32 {
+    syscall_body!(sys_munlock, {
+        let start = memory_addr::align_down_4k(addr);
+        let (start, length) = user_range(start, length + (addr - start))?;
+        let proc = current_process().unwrap();
+        let aspace = proc.aspace.lock();
+        let mut areas = proc.vm_areas.lock();
+        areas.set_locked(start, length, false);
+        for area in areas
+            .overlapping(start, length)
+            .filter(|area| !area.is_shared())
+        {
+            let (from, to) = (area.start.max(start), area.end.min(start + length));
+            mm::swap::record_mapped(&proc.aspace, &aspace, from, to - from);
+        }
         Ok(0)
     })
 }
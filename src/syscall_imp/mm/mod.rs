@@ -1,5 +1,7 @@
 mod brk;
 mod mmap;
+mod swap;
 
 pub(crate) use self::brk::*;
 pub(crate) use self::mmap::*;
+pub(crate) use self::swap::*;
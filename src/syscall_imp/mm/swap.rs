@@ -0,0 +1,35 @@
+use crate::fs::{resolve_path_at, AT_FDCWD};
+use crate::mm::swap;
+use crate::process::current_process;
+use crate::ptr::read_cstr;
+use crate::syscall_body;
+use axerrno::LinuxError;
+use core::ffi::c_char;
+
+/// Start swapping to the regular file `path`, see [`crate::mm::swap`].
+///
+/// Only one swap file can be in use at a time. `flags`, the priority and
+/// discard options, are ignored.
+pub(crate) fn sys_swapon(path: *const c_char, _flags: i32) -> i32 {
+    syscall_body!(sys_swapon, {
+        if !current_process().unwrap().cred().is_privileged() {
+            return Err(LinuxError::EPERM);
+        }
+        let path = resolve_path_at(AT_FDCWD, &read_cstr(path)?, true)?;
+        swap::swapon(&path)?;
+        Ok(0)
+    })
+}
+
+/// Stop swapping to the file `path`, once the pages swapped out are read
+/// back.
+pub(crate) fn sys_swapoff(path: *const c_char) -> i32 {
+    syscall_body!(sys_swapoff, {
+        if !current_process().unwrap().cred().is_privileged() {
+            return Err(LinuxError::EPERM);
+        }
+        let path = resolve_path_at(AT_FDCWD, &read_cstr(path)?, true)?;
+        swap::swapoff(&path)?;
+        Ok(0)
+    })
+}
@@ -1,5 +1,6 @@
 mod ctypes;
 mod fs;
+mod ipc;
 mod mm;
 mod signal;
 mod sys;
@@ -7,20 +8,41 @@ mod task;
 mod time;
 
 pub use ctypes::*;
+pub use fs::{DirEnt, FileType, Kstat, Statx, StatxTimestamp};
 
 use self::fs::*;
+pub(crate) use self::fs::{
+    sys_fdatasync, sys_fsync, sys_pread64, sys_preadv, sys_pwrite64, sys_pwritev, sys_read,
+    sys_readv, sys_write, sys_writev,
+};
+use self::ipc::*;
 use self::mm::*;
 use self::signal::*;
 use self::sys::*;
 pub(crate) use self::task::sys_exit;
 use self::task::*;
 use self::time::*;
+use crate::arch::TrapFrameExt;
+#[cfg(target_arch = "x86_64")]
+use crate::fs::{AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+#[cfg(target_arch = "x86_64")]
+use crate::signal::signal_no::SignalNo;
+use crate::strace::{self, SyscallTrace};
+use crate::sync::AdaptiveMutex;
+use crate::trace::{self, TraceEvent};
 use axerrno::LinuxError;
 use axhal::{
     arch::TrapFrame,
+    time::monotonic_time,
     trap::{register_trap_handler, SYSCALL},
 };
+use axtask::{current, TaskExtRef};
+use core::time::Duration;
 use syscalls::Sysno;
+
+/// The flags `creat` opens with: `O_WRONLY | O_CREAT | O_TRUNC`.
+#[cfg(target_arch = "x86_64")]
+const CREAT_FLAGS: i32 = 0o1 | 0o100 | 0o1000;
 /// Macro to generate syscall body
 ///
 /// It will receive a function which return Result<_, LinuxError> and convert it to
@@ -45,91 +67,506 @@ macro_rules! syscall_body {
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    match Sysno::from(syscall_num as u32) {
-        Sysno::read => sys_read(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        Sysno::write => sys_write(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        Sysno::brk => sys_brk(tf.arg0() as _) as _,
-        Sysno::mmap => sys_mmap(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-            tf.arg4() as _,
-            tf.arg5() as _,
-        ) as _,
-        Sysno::munmap => sys_munmap(tf.arg0() as _, tf.arg1() as _) as _,
-        Sysno::ioctl => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::getppid => sys_getppid() as isize,
-        Sysno::writev => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        Sysno::lseek => sys_lseek(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::utimensat => sys_utimensat(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-        ) as _,
-        Sysno::linkat => sys_linkat(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-            tf.arg4() as _,
-        ) as _,
-        Sysno::sched_yield => sys_sched_yield() as isize,
-        Sysno::nanosleep => sys_nanosleep(tf.arg0() as _, tf.arg1() as _) as _,
-        Sysno::getpid => sys_getpid() as isize,
-        Sysno::exit => sys_exit(tf.arg0() as _),
-        #[cfg(target_arch = "x86_64")]
-        Sysno::arch_prctl => sys_arch_prctl(tf.arg0() as _, tf.arg1() as _),
-        Sysno::set_tid_address => sys_set_tid_address(tf.arg0() as _),
-        Sysno::clock_gettime => sys_clock_gettime(tf.arg0() as _, tf.arg1() as _) as _,
-        Sysno::exit_group => sys_exit_group(tf.arg0() as _),
-        Sysno::clone => sys_clone(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-            tf.arg4() as _,
-        ),
-        Sysno::dup => sys_dup(tf.arg0() as _) as _,
-        Sysno::dup3 => sys_dup3(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::fstat => sys_fstat(tf.arg0() as _, tf.arg1() as _) as _,
-        Sysno::wait4 => sys_wait4(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::gettimeofday => sys_get_time_of_day(tf.arg0() as _) as _,
-        Sysno::execve => sys_execve(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::getcwd => sys_getcwd(tf.arg0() as _, tf.arg1() as _) as _,
-        Sysno::close => sys_close(tf.arg0() as _) as _,
-        Sysno::chdir => sys_chdir(tf.arg0() as _) as _,
-        Sysno::pipe2 => sys_pipe2(tf.arg0() as _, tf.arg1() as _) as _,
-        Sysno::mkdirat => sys_mkdirat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::getdents64 => sys_getdents64(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::times => sys_times(tf.arg0() as _) as _,
-        Sysno::unlinkat => sys_unlinkat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        Sysno::openat => sys_openat(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-        ) as _,
-        Sysno::uname => sys_uname(tf.arg0() as _) as _,
-        Sysno::mount => sys_mount(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-            tf.arg4() as _,
-        ) as _,
-        Sysno::umount2 => sys_umount(tf.arg0() as _) as _,
-        Sysno::rt_sigprocmask => sys_sigprocmask(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-        ) as _,
-        Sysno::kill => sys_kill(tf.arg0() as _, tf.arg1() as _) as _,
-        _ => {
-            warn!("Unimplemented syscall: {}", syscall_num);
-            sys_exit(LinuxError::ENOSYS as _)
+    crate::mm::tlb::leave_user();
+    crate::perf::leave_user();
+    trace::record(TraceEvent::SyscallEnter, syscall_num as u64, 0);
+    let strace = strace::is_traced().then(|| {
+        let args = [tf.arg0(), tf.arg1(), tf.arg2(), tf.arg3(), tf.arg4(), tf.arg5()];
+        SyscallTrace::enter(Sysno::from(syscall_num as u32), args.map(|arg| arg as usize))
+    });
+    let ret = match crate::seccomp::check(syscall_num, tf.ip()) {
+        Some(ret) => ret,
+        None => dispatch_syscall(tf, syscall_num),
+    };
+    if ret == -(LinuxError::EINTR.code() as isize)
+        && restartable(Sysno::from(syscall_num as u32))
+    {
+        // 返回用户态投递信号时，再按信号决定是否重新执行
+        current()
+            .task_ext()
+            .set_interrupted_syscall(syscall_num, tf.arg0());
+    }
+    if let Some(strace) = strace {
+        strace.exit(ret);
+    }
+    trace::record(TraceEvent::SyscallExit, syscall_num as u64, ret as u64);
+    ret
+}
+
+/// Whether a syscall interrupted by a signal may be made again. A sleep
+/// would start over for its whole length, and `semop` never restarts.
+fn restartable(sysno: Sysno) -> bool {
+    !matches!(
+        sysno,
+        Sysno::nanosleep | Sysno::clock_nanosleep | Sysno::semop | Sysno::semtimedop
+    )
+}
+
+/// A syscall handler, which takes its arguments from the trap frame.
+type Handler = fn(&TrapFrame) -> isize;
+
+/// The size of the dispatch table: the highest syscall number of the target
+/// architecture, plus one.
+const TABLE_SIZE: usize = Sysno::last().id() as usize + 1;
+
+/// Build `SYSCALL_TABLE`, the handlers indexed by syscall number, from the
+/// handlers of the syscalls by name.
+///
+/// The numbers are the ones [`Sysno`] has for the target architecture, so
+/// the same table serves riscv64, x86_64 and aarch64. A syscall which only
+/// exists on some of them is put under `#[cfg(target_arch = ...)]`.
+macro_rules! syscall_table {
+    ($($(#[$attr:meta])* $name:ident => |$tf:pat_param| $body:expr,)*) => {
+        static SYSCALL_TABLE: [Option<Handler>; TABLE_SIZE] = {
+            let mut table: [Option<Handler>; TABLE_SIZE] = [None; TABLE_SIZE];
+            $(
+                $(#[$attr])*
+                {
+                    table[Sysno::$name as usize] =
+                        Some((|$tf: &TrapFrame| -> isize { $body }) as Handler);
+                }
+            )*
+            table
+        };
+    };
+}
+
+syscall_table! {
+    read => |tf| sys_read(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    write => |tf| sys_write(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    brk => |tf| sys_brk(tf.arg0() as _) as _,
+    mmap => |tf| sys_mmap(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+        tf.arg5() as _,
+    ) as _,
+    munmap => |tf| sys_munmap(tf.arg0() as _, tf.arg1() as _) as _,
+    mprotect => |tf| sys_mprotect(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    msync => |tf| sys_msync(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    madvise => |tf| sys_madvise(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    mlock => |tf| sys_mlock(tf.arg0() as _, tf.arg1() as _) as _,
+    munlock => |tf| sys_munlock(tf.arg0() as _, tf.arg1() as _) as _,
+    swapon => |tf| sys_swapon(tf.arg0() as _, tf.arg1() as _) as _,
+    swapoff => |tf| sys_swapoff(tf.arg0() as _) as _,
+    ioctl => |tf| sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    getppid => |_| sys_getppid() as isize,
+    setpgid => |tf| sys_setpgid(tf.arg0() as _, tf.arg1() as _),
+    getpgid => |tf| sys_getpgid(tf.arg0() as _),
+    #[cfg(target_arch = "x86_64")]
+    getpgrp => |_| sys_getpgrp(),
+    setsid => |_| sys_setsid(),
+    getsid => |tf| sys_getsid(tf.arg0() as _),
+    getuid => |_| sys_getuid() as isize,
+    geteuid => |_| sys_geteuid() as isize,
+    getgid => |_| sys_getgid() as isize,
+    getegid => |_| sys_getegid() as isize,
+    setuid => |tf| sys_setuid(tf.arg0() as _),
+    setgid => |tf| sys_setgid(tf.arg0() as _),
+    setreuid => |tf| sys_setreuid(tf.arg0() as _, tf.arg1() as _),
+    setregid => |tf| sys_setregid(tf.arg0() as _, tf.arg1() as _),
+    setresuid => |tf| sys_setresuid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    setresgid => |tf| sys_setresgid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    getresuid => |tf| sys_getresuid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    getresgid => |tf| sys_getresgid(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    readv => |tf| sys_readv(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    writev => |tf| sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    pread64 => |tf| sys_pread64(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    pwrite64 => |tf| sys_pwrite64(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    preadv => |tf| sys_preadv(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    pwritev => |tf| sys_pwritev(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    io_uring_setup => |tf| sys_io_uring_setup(tf.arg0() as _, tf.arg1() as _),
+    io_uring_enter => |tf| sys_io_uring_enter(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+        tf.arg5() as _,
+    ),
+    lseek => |tf| sys_lseek(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    utimensat => |tf| sys_utimensat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ) as _,
+    linkat => |tf| sys_linkat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ) as _,
+    sched_yield => |_| sys_sched_yield() as isize,
+    nanosleep => |tf| sys_nanosleep(tf.arg0() as _, tf.arg1() as _) as _,
+    clock_nanosleep => |tf| sys_clock_nanosleep(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    getpid => |_| sys_getpid() as isize,
+    gettid => |_| sys_gettid() as isize,
+    exit => |tf| sys_exit(tf.arg0() as _),
+    #[cfg(target_arch = "x86_64")]
+    arch_prctl => |tf| sys_arch_prctl(tf.arg0() as _, tf.arg1() as _),
+    set_tid_address => |tf| sys_set_tid_address(tf.arg0() as _),
+    prctl => |tf| sys_prctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    personality => |tf| sys_personality(tf.arg0() as _),
+    clock_gettime => |tf| sys_clock_gettime(tf.arg0() as _, tf.arg1() as _) as _,
+    clock_getres => |tf| sys_clock_getres(tf.arg0() as _, tf.arg1() as _) as _,
+    clock_settime => |tf| sys_clock_settime(tf.arg0() as _, tf.arg1() as _) as _,
+    clock_adjtime => |tf| sys_clock_adjtime(tf.arg0() as _, tf.arg1() as _) as _,
+    adjtimex => |tf| sys_adjtimex(tf.arg0() as _) as _,
+    exit_group => |tf| sys_exit_group(tf.arg0() as _),
+    clone => |tf| sys_clone(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ),
+    unshare => |tf| sys_unshare(tf.arg0() as _),
+    dup => |tf| sys_dup(tf.arg0() as _) as _,
+    dup3 => |tf| sys_dup3(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    fstat => |tf| sys_fstat(tf.arg0() as _, tf.arg1() as _) as _,
+    newfstatat => |tf| sys_fstatat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ) as _,
+    statx => |tf| sys_statx(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ) as _,
+    statfs => |tf| sys_statfs(tf.arg0() as _, tf.arg1() as _) as _,
+    fstatfs => |tf| sys_fstatfs(tf.arg0() as _, tf.arg1() as _) as _,
+    wait4 => |tf| sys_wait4(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    waitid => |tf| sys_waitid(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    gettimeofday => |tf| sys_get_time_of_day(tf.arg0() as _) as _,
+    settimeofday => |tf| sys_settimeofday(tf.arg0() as _, tf.arg1() as _) as _,
+    execve => |tf| sys_execve(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    getcwd => |tf| sys_getcwd(tf.arg0() as _, tf.arg1() as _) as _,
+    close => |tf| sys_close(tf.arg0() as _) as _,
+    chdir => |tf| sys_chdir(tf.arg0() as _) as _,
+    pipe2 => |tf| sys_pipe2(tf.arg0() as _, tf.arg1() as _) as _,
+    #[cfg(target_arch = "x86_64")]
+    pipe => |tf| sys_pipe2(tf.arg0() as _, 0) as _,
+    mkdirat => |tf| sys_mkdirat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    getdents64 => |tf| sys_getdents64(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    times => |tf| sys_times(tf.arg0() as _) as _,
+    #[cfg(not(target_arch = "riscv64"))]
+    renameat => |tf| sys_renameat2(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        0,
+    ) as _,
+    renameat2 => |tf| sys_renameat2(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ) as _,
+    truncate => |tf| sys_truncate(tf.arg0() as _, tf.arg1() as _) as _,
+    ftruncate => |tf| sys_ftruncate(tf.arg0() as _, tf.arg1() as _) as _,
+    fsync => |tf| sys_fsync(tf.arg0() as _) as _,
+    fdatasync => |tf| sys_fdatasync(tf.arg0() as _) as _,
+    syncfs => |tf| sys_syncfs(tf.arg0() as _) as _,
+    sync => |_| sys_sync() as _,
+    unlinkat => |tf| sys_unlinkat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    openat => |tf| sys_openat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ) as _,
+    uname => |tf| sys_uname(tf.arg0() as _) as _,
+    sysinfo => |tf| sys_sysinfo(tf.arg0() as _),
+    shmget => |tf| sys_shmget(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    shmat => |tf| sys_shmat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    shmdt => |tf| sys_shmdt(tf.arg0() as _),
+    shmctl => |tf| sys_shmctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    semget => |tf| sys_semget(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    semop => |tf| sys_semop(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    semtimedop => |tf| sys_semtimedop(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    semctl => |tf| sys_semctl(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    mq_open => |tf| sys_mq_open(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    mq_unlink => |tf| sys_mq_unlink(tf.arg0() as _),
+    mq_timedsend => |tf| sys_mq_timedsend(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ),
+    mq_timedreceive => |tf| sys_mq_timedreceive(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ),
+    mq_getsetattr => |tf| {
+        sys_mq_getsetattr(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _)
+    },
+    memfd_create => |tf| sys_memfd_create(tf.arg0() as _, tf.arg1() as _),
+    getrandom => |tf| sys_getrandom(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    symlinkat => |tf| {
+        sys_symlinkat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _
+    },
+    readlinkat => |tf| sys_readlinkat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    perf_event_open => |tf| sys_perf_event_open(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ),
+    faccessat => |tf| {
+        sys_faccessat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _, 0) as _
+    },
+    faccessat2 => |tf| sys_faccessat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ) as _,
+    fchmodat => |tf| {
+        sys_fchmodat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _, 0) as _
+    },
+    fchownat => |tf| sys_fchownat(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ) as _,
+    umask => |tf| sys_umask(tf.arg0() as _) as _,
+    mount => |tf| sys_mount(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ) as _,
+    umount2 => |tf| sys_umount(tf.arg0() as _) as _,
+    rt_sigprocmask => |tf| sys_sigprocmask(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ) as _,
+    rt_sigaction => |tf| sys_rt_sigaction(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+    ),
+    rt_sigreturn => |_| crate::process::signal::signal_return(),
+    kill => |tf| sys_kill(tf.arg0() as _, tf.arg1() as _) as _,
+    rt_sigqueueinfo => |tf| {
+        sys_rt_sigqueueinfo(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _)
+    },
+    futex => |tf| sys_futex(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+        tf.arg5() as _,
+    ),
+    set_robust_list => |tf| sys_set_robust_list(tf.arg0() as _, tf.arg1() as _),
+    get_robust_list => |tf| sys_get_robust_list(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    futex_waitv => |tf| sys_futex_waitv(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ),
+    ppoll => |tf| sys_ppoll(
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        tf.arg3() as _,
+        tf.arg4() as _,
+    ),
+    // The older syscalls which x86_64 still has, and musl calls there
+    #[cfg(target_arch = "x86_64")]
+    poll => |tf| sys_poll(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    #[cfg(target_arch = "x86_64")]
+    fork => |_| sys_clone(SignalNo::SIGCHLD as _, 0, 0, 0, 0),
+    // A copy of the address space serves vfork as well as sharing it would
+    #[cfg(target_arch = "x86_64")]
+    vfork => |_| sys_clone(SignalNo::SIGCHLD as _, 0, 0, 0, 0),
+    #[cfg(target_arch = "x86_64")]
+    open => |tf| sys_openat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+    #[cfg(target_arch = "x86_64")]
+    creat => |tf| sys_openat(
+        AT_FDCWD,
+        tf.arg0() as _,
+        CREAT_FLAGS,
+        tf.arg1() as _,
+    ) as _,
+    #[cfg(target_arch = "x86_64")]
+    mkdir => |tf| sys_mkdirat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _) as _,
+    #[cfg(target_arch = "x86_64")]
+    rmdir => |tf| sys_unlinkat(AT_FDCWD, tf.arg0() as _, AT_REMOVEDIR) as _,
+    #[cfg(target_arch = "x86_64")]
+    unlink => |tf| sys_unlinkat(AT_FDCWD, tf.arg0() as _, 0) as _,
+    #[cfg(target_arch = "x86_64")]
+    link => |tf| sys_linkat(AT_FDCWD, tf.arg0() as _, AT_FDCWD, tf.arg1() as _, 0) as _,
+    #[cfg(target_arch = "x86_64")]
+    symlink => |tf| sys_symlinkat(tf.arg0() as _, AT_FDCWD, tf.arg1() as _) as _,
+    #[cfg(target_arch = "x86_64")]
+    readlink => |tf| sys_readlinkat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+    #[cfg(target_arch = "x86_64")]
+    rename => |tf| sys_renameat2(AT_FDCWD, tf.arg0() as _, AT_FDCWD, tf.arg1() as _, 0) as _,
+    #[cfg(target_arch = "x86_64")]
+    access => |tf| sys_faccessat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, 0) as _,
+    #[cfg(target_arch = "x86_64")]
+    chmod => |tf| sys_fchmodat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, 0) as _,
+    #[cfg(target_arch = "x86_64")]
+    chown => |tf| sys_fchownat(
+        AT_FDCWD,
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        0,
+    ) as _,
+    #[cfg(target_arch = "x86_64")]
+    lchown => |tf| sys_fchownat(
+        AT_FDCWD,
+        tf.arg0() as _,
+        tf.arg1() as _,
+        tf.arg2() as _,
+        AT_SYMLINK_NOFOLLOW,
+    ) as _,
+    #[cfg(target_arch = "x86_64")]
+    stat => |tf| sys_fstatat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, 0) as _,
+    #[cfg(target_arch = "x86_64")]
+    lstat => |tf| sys_fstatat(
+        AT_FDCWD,
+        tf.arg0() as _,
+        tf.arg1() as _,
+        AT_SYMLINK_NOFOLLOW,
+    ) as _,
+    #[cfg(target_arch = "x86_64")]
+    dup2 => |tf| sys_dup2(tf.arg0() as _, tf.arg1() as _) as _,
+    #[cfg(target_arch = "x86_64")]
+    pause => |_| sys_ppoll(core::ptr::null_mut(), 0, core::ptr::null(), core::ptr::null(), 0),
+}
+
+fn dispatch_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    match SYSCALL_TABLE.get(syscall_num).copied().flatten() {
+        Some(handler) => handler(tf),
+        None => unimplemented_syscall(syscall_num),
+    }
+}
+
+/// At most this many unimplemented syscalls are logged per second, so that
+/// a program retrying one in a loop doesn't flood the log.
+const UNIMPLEMENTED_LOG_BURST: usize = 10;
+
+/// The unimplemented syscalls logged in the current second.
+struct LogLimit {
+    /// When the current second started
+    start: Duration,
+    logged: usize,
+    /// How many were not logged
+    suppressed: usize,
+}
+
+static UNIMPLEMENTED_LOG: AdaptiveMutex<LogLimit> = AdaptiveMutex::new(LogLimit {
+    start: Duration::ZERO,
+    logged: 0,
+    suppressed: 0,
+});
+
+/// A syscall which has no handler. It fails with `ENOSYS` with the `enosys`
+/// feature, and kills the thread otherwise, so that it can't go unnoticed.
+fn unimplemented_syscall(syscall_num: usize) -> isize {
+    let now = monotonic_time();
+    let mut limit = UNIMPLEMENTED_LOG.lock();
+    if now - limit.start >= Duration::from_secs(1) {
+        if limit.suppressed > 0 {
+            warn!(
+                "{} more unimplemented syscalls not logged",
+                limit.suppressed
+            );
         }
+        *limit = LogLimit {
+            start: now,
+            logged: 0,
+            suppressed: 0,
+        };
+    }
+    if limit.logged < UNIMPLEMENTED_LOG_BURST {
+        limit.logged += 1;
+        let name = Sysno::new(syscall_num).map_or("unknown", |sysno| sysno.name());
+        warn!("Unimplemented syscall: {} ({})", syscall_num, name);
+    } else {
+        limit.suppressed += 1;
+    }
+    drop(limit);
+    if cfg!(feature = "enosys") {
+        -(LinuxError::ENOSYS.code() as isize)
+    } else {
+        sys_exit(LinuxError::ENOSYS as _)
     }
 }
@@ -1,6 +1,14 @@
+use crate::process::pid::{from_user, to_user};
 use crate::process::signal::send_signal_to_proc;
+use crate::process::{all_processes, current_process, get_process, AxProcessRef, Process};
+use crate::ptr::UserPtr;
+use crate::signal::action::SigAction;
+use crate::signal::info::SigInfo;
+use crate::signal::signal_no::{SignalNo, MAX_SIG_NUM};
 use crate::syscall_body;
 use crate::syscall_imp::{SigMaskFlag, SIGSET_SIZE_IN_BYTE};
+use alloc::vec;
+use alloc::vec::Vec;
 use axtask::{current, TaskExtRef};
 
 pub fn sys_sigprocmask(
@@ -20,45 +28,158 @@ pub fn sys_sigprocmask(
         }
 
         let task = current();
-        let proc = task.task_ext().get_proc().unwrap();
+        let mut sig_module = task.task_ext().signal();
+        let new_mask = UserPtr::from(new_mask).read_opt()?;
+        UserPtr::from(old_mask).write_opt(sig_module.sig_set.mask())?;
 
-        let mut sig_modules = proc.signal_module.lock();
-        let sig_module = sig_modules.get_mut(&task.id().as_u64()).unwrap();
-        if old_mask as usize != 0 {
-            unsafe {
-                *old_mask = sig_module.sig_set.mask;
+        if let Some(now_mask) = new_mask {
+            match flag {
+                SigMaskFlag::Block => sig_module.sig_set.block(now_mask),
+                SigMaskFlag::Unblock => sig_module.sig_set.unblock(now_mask),
+                SigMaskFlag::Setmask => sig_module.sig_set.set_mask(now_mask),
             }
+            // Signals which were blocked may be delivered now
+            task.task_ext().set_signal_pending();
         }
 
-        if new_mask as usize != 0 {
-            let now_mask = unsafe { *new_mask };
-            match flag {
-                SigMaskFlag::Block => {
-                    sig_module.sig_set.mask |= now_mask;
-                }
-                SigMaskFlag::Unblock => {
-                    sig_module.sig_set.mask &= !now_mask;
-                }
-                SigMaskFlag::Setmask => {
-                    sig_module.sig_set.mask = now_mask;
-                }
-            }
+        Ok(0)
+    })
+}
+
+/// Examine and change the action of `signum`.
+///
+/// The new action is checked before the old one is copied out, so a refused
+/// action leaves both the disposition and `oldact` untouched.
+pub(crate) fn sys_rt_sigaction(
+    signum: i32,
+    act: *const SigAction,
+    oldact: *mut SigAction,
+    sigsetsize: usize,
+) -> isize {
+    syscall_body!(sys_rt_sigaction, {
+        if sigsetsize != SIGSET_SIZE_IN_BYTE || !(1..=MAX_SIG_NUM as i32).contains(&signum) {
+            return Err(axerrno::LinuxError::EINVAL);
         }
+        let sig_num = signum as usize;
+        let action = UserPtr::from(act)
+            .read_opt()?
+            .map(|action: SigAction| action.check(sig_num))
+            .transpose()?;
 
+        let task = current();
+        let sig_module = task.task_ext().signal();
+        let mut sig_handler = sig_module.sig_handler.lock();
+        UserPtr::from(oldact).write_opt(*sig_handler.get_action(sig_num))?;
+        if let Some(action) = action {
+            sig_handler.set_action(sig_num, action);
+        }
         Ok(0)
     })
 }
 
+/// Whether `sender` may send `signum` to `target`: its credentials allow it,
+/// or it continues a process of its own session.
+fn may_kill(sender: &Process, target: &Process, signum: isize) -> bool {
+    sender.cred().may_signal(&target.cred())
+        || (signum == SignalNo::SIGCONT as isize && sender.sid() == target.sid())
+}
+
+/// Send `signum` to the process `pid`, to every process of the process group
+/// of the caller for 0, to every process the caller may signal but itself and
+/// init for -1, or to every process of the process group `-pid` otherwise.
+///
+/// Only the processes of the pid namespace of the caller are seen. The
+/// signal is sent if any of the targets could be signalled, and fails with
+/// `EPERM` if none of them could. A `signum` of 0 only checks that.
 pub(crate) fn sys_kill(pid: isize, signum: isize) -> isize {
     debug!("sys_kill <= {}, {}", pid, signum);
     syscall_body!(sys_kill, {
-        if pid > 0 && signum > 0 {
-            let _ = send_signal_to_proc(pid as u64, signum, None);
+        if !(0..=MAX_SIG_NUM as isize).contains(&signum) {
+            return Err(axerrno::LinuxError::EINVAL);
+        }
+        let sender = current_process().unwrap();
+        let targets: Vec<AxProcessRef> = if pid > 0 {
+            let target = from_user(pid as u64).and_then(get_process);
+            vec![target.ok_or(axerrno::LinuxError::ESRCH)?]
+        } else if pid == -1 {
+            all_processes()
+                .into_iter()
+                .filter(|proc| proc.pid != sender.pid && to_user(proc.pid) > 1)
+                .collect()
+        } else {
+            let pgid = if pid == 0 {
+                sender.pgid()
+            } else {
+                from_user(pid.unsigned_abs() as u64).ok_or(axerrno::LinuxError::ESRCH)?
+            };
+            all_processes()
+                .into_iter()
+                .filter(|proc| proc.pgid() == pgid && to_user(proc.pid) != 0)
+                .collect()
+        };
+        if targets.is_empty() {
+            return Err(axerrno::LinuxError::ESRCH);
+        }
+        let info = SigInfo {
+            si_signo: signum as i32,
+            si_code: SI_USER,
+            pid: sender.pid as i32,
+            uid: sender.cred().uid,
+            ..Default::default()
+        };
+        let mut permitted = false;
+        for target in targets {
+            if !may_kill(&sender, &target, signum) {
+                continue;
+            }
+            permitted = true;
+            if signum != 0 {
+                let _ = send_signal_to_proc(target.pid, signum, Some(info));
+            }
+        }
+        if permitted {
             Ok(0)
-        } else if pid == 0 {
-            Err(axerrno::LinuxError::ESRCH)
         } else {
-            Err(axerrno::LinuxError::EINVAL)
+            Err(axerrno::LinuxError::EPERM)
         }
     })
 }
+
+/// `si_code` of the signals sent by `kill`.
+const SI_USER: i32 = 0;
+/// `si_code` of the signals sent by `tkill`.
+const SI_TKILL: i32 = -6;
+
+/// Send `sig` with the information at `uinfo` to the process `pid`.
+///
+/// A real-time signal is queued even if it is already pending, up to
+/// `SIGQUEUE_MAX` of them, after which this fails with `EAGAIN`.
+pub(crate) fn sys_rt_sigqueueinfo(pid: i32, sig: i32, uinfo: *const SigInfo) -> isize {
+    syscall_body!(sys_rt_sigqueueinfo, {
+        if !(0..=MAX_SIG_NUM as i32).contains(&sig) {
+            return Err(axerrno::LinuxError::EINVAL);
+        }
+        let mut info = UserPtr::from(uinfo).read()?;
+        let curr = current();
+        let proc = curr.task_ext().get_proc().unwrap();
+        let target = if pid > 0 { from_user(pid as u64) } else { None };
+        // Only the kernel may pretend to be kill or the kernel itself
+        if (info.si_code >= SI_USER || info.si_code == SI_TKILL) && target != Some(proc.pid) {
+            return Err(axerrno::LinuxError::EPERM);
+        }
+        let Some(target) = target.filter(|&pid| get_process(pid).is_some()) else {
+            return Err(axerrno::LinuxError::ESRCH);
+        };
+        if sig == 0 {
+            return Ok(0);
+        }
+        info.si_signo = sig;
+        // The pid given is the one of the namespace of the caller
+        info.pid = from_user(info.pid as u64).unwrap_or(0) as i32;
+        send_signal_to_proc(target, sig as isize, Some(info)).map_err(|e| match e {
+            axerrno::AxError::NotFound => axerrno::LinuxError::ESRCH,
+            e => e.into(),
+        })?;
+        Ok(0)
+    })
+}
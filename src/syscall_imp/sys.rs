@@ -1,3 +1,20 @@
+use crate::mm::ram_usage;
+use crate::perf::{
+    PerfCounter, PerfEvent, PERF_FORMAT_TOTAL_TIME_ENABLED, PERF_FORMAT_TOTAL_TIME_RUNNING,
+    PERF_TYPE_HARDWARE,
+};
+use crate::process::loadavg::{loadavg, FSHIFT};
+use crate::process::timens::CLOCK_BOOTTIME;
+use crate::process::{all_processes, current_process};
+use crate::ptr::{UserPtr, UserSlice};
+use crate::random;
+use crate::syscall_body;
+use alloc::sync::Arc;
+use arceos_posix_api as api;
+use axerrno::LinuxError;
+use axtask::{current, TaskExtRef};
+
+#[derive(Clone, Copy)]
 pub(crate) struct Utsname {
     sysname: [u8; 65],
     nodename: [u8; 65],
@@ -7,40 +24,185 @@ pub(crate) struct Utsname {
     domainname: [u8; 65],
 }
 pub fn sys_uname(buf: *mut Utsname) -> i32 {
-    let utsname = Utsname {
-        sysname: {
-            let mut arr = [0u8; 65];
-            arr[..7].copy_from_slice(b"ArceOS\0");
-            arr
-        },
-        nodename: {
-            let mut arr = [0u8; 65];
-            arr[..7].copy_from_slice(b"ArceOS\0");
-            arr
-        },
-        release: {
-            let mut arr = [0u8; 65];
-            arr[..6].copy_from_slice(b"0.1.0\0");
-            arr
-        },
-        version: {
-            let mut arr = [0u8; 65];
-            arr[..6].copy_from_slice(b"0.1.0\0");
-            arr
-        },
-        machine: {
-            let mut arr = [0u8; 65];
-            arr[..8].copy_from_slice(b"riscv64\0");
-            arr
-        },
-        domainname: {
-            let mut arr = [0u8; 65];
-            arr[..1].copy_from_slice(b"\0");
-            arr
-        },
-    };
-    unsafe {
-        buf.write(utsname);
-    }
-    0
+    syscall_body!(sys_uname, {
+        let utsname = Utsname {
+            sysname: {
+                let mut arr = [0u8; 65];
+                arr[..7].copy_from_slice(b"ArceOS\0");
+                arr
+            },
+            nodename: {
+                let mut arr = [0u8; 65];
+                arr[..7].copy_from_slice(b"ArceOS\0");
+                arr
+            },
+            release: {
+                let mut arr = [0u8; 65];
+                arr[..6].copy_from_slice(b"0.1.0\0");
+                arr
+            },
+            version: {
+                let mut arr = [0u8; 65];
+                arr[..6].copy_from_slice(b"0.1.0\0");
+                arr
+            },
+            machine: {
+                let mut arr = [0u8; 65];
+                arr[..8].copy_from_slice(b"riscv64\0");
+                arr
+            },
+            domainname: {
+                let mut arr = [0u8; 65];
+                arr[..1].copy_from_slice(b"\0");
+                arr
+            },
+        };
+        UserPtr::from(buf).write(utsname)?;
+        Ok(0)
+    })
+}
+
+/// `struct sysinfo` of the Linux uapi, for 64-bit targets.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SysInfo {
+    uptime: i64,
+    loads: [u64; 3],
+    totalram: u64,
+    freeram: u64,
+    sharedram: u64,
+    bufferram: u64,
+    totalswap: u64,
+    freeswap: u64,
+    procs: u16,
+    pad: u16,
+    totalhigh: u64,
+    freehigh: u64,
+    mem_unit: u32,
+}
+
+/// The fractional bits of the load averages in `struct sysinfo`.
+const SI_LOAD_SHIFT: u32 = 16;
+
+/// Report the uptime, memory usage, process count and load averages.
+///
+/// Sizes are in bytes. There is no swap, no high memory, and no shared or
+/// buffer memory accounted separately.
+pub(crate) fn sys_sysinfo(info: *mut SysInfo) -> isize {
+    syscall_body!(sys_sysinfo, {
+        let info = UserPtr::from(info);
+        if info.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let uptime = current_process()
+            .unwrap()
+            .time_ns
+            .lock()
+            .apply(CLOCK_BOOTTIME, axhal::time::monotonic_time());
+        let ram = ram_usage();
+        let sysinfo = SysInfo {
+            uptime: uptime.as_secs() as i64,
+            loads: loadavg().map(|load| load << (SI_LOAD_SHIFT - FSHIFT)),
+            totalram: ram.total as u64,
+            freeram: ram.free as u64,
+            procs: all_processes().len().min(u16::MAX as usize) as u16,
+            mem_unit: 1,
+            ..Default::default()
+        };
+        info.write(sysinfo)?;
+        Ok(0)
+    })
+}
+
+/// The leading fields of `struct perf_event_attr` which are looked at.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    _sample_type: u64,
+    read_format: u64,
+    flags: u64,
+}
+
+/// `perf_event_attr.disabled`: the event starts disabled.
+const PERF_ATTR_DISABLED: u64 = 1 << 0;
+/// `perf_event_attr.inherit`: children count into the event too.
+const PERF_ATTR_INHERIT: u64 = 1 << 1;
+const PERF_FLAG_FD_CLOEXEC: u32 = 1 << 3;
+
+/// Open a counting hardware event for the calling thread, which counts while
+/// the thread runs in user space.
+///
+/// Only `PERF_TYPE_HARDWARE` cycle and instruction counters are supported,
+/// without sampling, inheritance or event groups.
+pub(crate) fn sys_perf_event_open(
+    attr: *const PerfEventAttr,
+    pid: i32,
+    cpu: i32,
+    group_fd: i32,
+    flags: u32,
+) -> isize {
+    syscall_body!(sys_perf_event_open, {
+        let attr = UserPtr::from(attr).read()?;
+        if (attr.size as usize) < core::mem::size_of::<PerfEventAttr>()
+            || flags & !PERF_FLAG_FD_CLOEXEC != 0
+            || group_fd != -1
+            || attr.sample_period != 0
+            || attr.flags & PERF_ATTR_INHERIT != 0
+            || attr.read_format & !(PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING)
+                != 0
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        if attr.type_ != PERF_TYPE_HARDWARE {
+            return Err(LinuxError::ENOENT);
+        }
+        // Only the calling thread can be measured, on whatever CPU it runs
+        let tid = current().task_ext().tid();
+        if !(pid == 0 || pid as u64 == tid) {
+            return Err(if current_process().unwrap().cred().is_privileged() {
+                LinuxError::EOPNOTSUPP
+            } else {
+                LinuxError::EACCES
+            });
+        }
+        if cpu != -1 {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+
+        let counter = PerfCounter::from_config(attr.config)?;
+        let event = Arc::new(PerfEvent::new(
+            counter,
+            attr.read_format,
+            attr.flags & PERF_ATTR_DISABLED != 0,
+        ));
+        current().task_ext().add_perf_event(&event);
+        api::add_file_like(event).map(|fd| fd as isize)
+    })
+}
+
+const GRND_NONBLOCK: u32 = 0x1;
+const GRND_RANDOM: u32 = 0x2;
+const GRND_INSECURE: u32 = 0x4;
+
+/// The most bytes returned by one `getrandom` call, as on Linux.
+const GETRANDOM_MAX: usize = (i32::MAX as usize) >> 6;
+
+/// Fill `buf` with up to `len` random bytes.
+///
+/// The generator is seeded at boot, so no flag ever makes the call block.
+pub(crate) fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> isize {
+    syscall_body!(sys_getrandom, {
+        if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+            || flags & (GRND_RANDOM | GRND_INSECURE) == GRND_RANDOM | GRND_INSECURE
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        let buf = UserSlice::new(buf, len.min(GETRANDOM_MAX)).as_mut_slice()?;
+        random::fill(buf);
+        Ok(buf.len())
+    })
 }
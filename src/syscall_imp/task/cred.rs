@@ -0,0 +1,191 @@
+//! The identities of the caller, as [`crate::process::cred::Credentials`]
+//! holds them.
+//!
+//! Root may take any identity. Other processes may only switch between their
+//! real, effective and saved IDs, as on Linux.
+use crate::process::cred::Credentials;
+use crate::process::current_process;
+use crate::ptr::UserPtr;
+use crate::syscall_body;
+use axerrno::{LinuxError, LinuxResult};
+
+/// An ID argument which leaves the ID as it is.
+const UNCHANGED: u32 = u32::MAX;
+
+pub(crate) fn sys_getuid() -> i32 {
+    current_process().map_or(0, |p| p.cred().uid) as i32
+}
+
+pub(crate) fn sys_geteuid() -> i32 {
+    current_process().map_or(0, |p| p.cred().euid) as i32
+}
+
+pub(crate) fn sys_getgid() -> i32 {
+    current_process().map_or(0, |p| p.cred().gid) as i32
+}
+
+pub(crate) fn sys_getegid() -> i32 {
+    current_process().map_or(0, |p| p.cred().egid) as i32
+}
+
+/// Change the credentials of the caller with `change`, which fails to leave
+/// them as they are.
+fn change_cred(change: impl FnOnce(&mut Credentials) -> LinuxResult) -> LinuxResult<isize> {
+    let proc = current_process().ok_or(LinuxError::ESRCH)?;
+    let mut cred = proc.cred.lock();
+    let mut new = *cred;
+    change(&mut new)?;
+    *cred = new;
+    Ok(0)
+}
+
+/// Set the real, effective and saved IDs of root, or only the effective one of
+/// another user, which must be its real or saved one.
+pub(crate) fn sys_setuid(uid: u32) -> isize {
+    syscall_body!(sys_setuid, {
+        change_cred(|cred| {
+            if cred.is_privileged() {
+                (cred.uid, cred.euid, cred.suid) = (uid, uid, uid);
+            } else if uid == cred.uid || uid == cred.suid {
+                cred.euid = uid;
+            } else {
+                return Err(LinuxError::EPERM);
+            }
+            Ok(())
+        })
+    })
+}
+
+pub(crate) fn sys_setgid(gid: u32) -> isize {
+    syscall_body!(sys_setgid, {
+        change_cred(|cred| {
+            if cred.is_privileged() {
+                (cred.gid, cred.egid, cred.sgid) = (gid, gid, gid);
+            } else if gid == cred.gid || gid == cred.sgid {
+                cred.egid = gid;
+            } else {
+                return Err(LinuxError::EPERM);
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Set the real and effective user IDs. The saved one follows the effective
+/// one when the real one is set, or the effective one set to another ID than
+/// the real one.
+pub(crate) fn sys_setreuid(ruid: u32, euid: u32) -> isize {
+    syscall_body!(sys_setreuid, {
+        change_cred(|cred| {
+            let old = *cred;
+            let privileged = old.is_privileged();
+            if ruid != UNCHANGED {
+                if !privileged && ruid != old.uid && ruid != old.euid {
+                    return Err(LinuxError::EPERM);
+                }
+                cred.uid = ruid;
+            }
+            if euid != UNCHANGED {
+                if !privileged && !old.holds_uid(euid) {
+                    return Err(LinuxError::EPERM);
+                }
+                cred.euid = euid;
+            }
+            if ruid != UNCHANGED || (euid != UNCHANGED && euid != old.uid) {
+                cred.suid = cred.euid;
+            }
+            Ok(())
+        })
+    })
+}
+
+pub(crate) fn sys_setregid(rgid: u32, egid: u32) -> isize {
+    syscall_body!(sys_setregid, {
+        change_cred(|cred| {
+            let old = *cred;
+            let privileged = old.is_privileged();
+            if rgid != UNCHANGED {
+                if !privileged && rgid != old.gid && rgid != old.egid {
+                    return Err(LinuxError::EPERM);
+                }
+                cred.gid = rgid;
+            }
+            if egid != UNCHANGED {
+                if !privileged && !old.holds_gid(egid) {
+                    return Err(LinuxError::EPERM);
+                }
+                cred.egid = egid;
+            }
+            if rgid != UNCHANGED || (egid != UNCHANGED && egid != old.gid) {
+                cred.sgid = cred.egid;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Set the real, effective and saved user IDs, each of which an unprivileged
+/// caller must already hold.
+pub(crate) fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> isize {
+    syscall_body!(sys_setresuid, {
+        change_cred(|cred| {
+            let old = *cred;
+            for (id, new) in [
+                (&mut cred.uid, ruid),
+                (&mut cred.euid, euid),
+                (&mut cred.suid, suid),
+            ] {
+                if new == UNCHANGED {
+                    continue;
+                }
+                if !old.is_privileged() && !old.holds_uid(new) {
+                    return Err(LinuxError::EPERM);
+                }
+                *id = new;
+            }
+            Ok(())
+        })
+    })
+}
+
+pub(crate) fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> isize {
+    syscall_body!(sys_setresgid, {
+        change_cred(|cred| {
+            let old = *cred;
+            for (id, new) in [
+                (&mut cred.gid, rgid),
+                (&mut cred.egid, egid),
+                (&mut cred.sgid, sgid),
+            ] {
+                if new == UNCHANGED {
+                    continue;
+                }
+                if !old.is_privileged() && !old.holds_gid(new) {
+                    return Err(LinuxError::EPERM);
+                }
+                *id = new;
+            }
+            Ok(())
+        })
+    })
+}
+
+pub(crate) fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> isize {
+    syscall_body!(sys_getresuid, {
+        let cred = current_process().ok_or(LinuxError::ESRCH)?.cred();
+        UserPtr::from(ruid).write(cred.uid)?;
+        UserPtr::from(euid).write(cred.euid)?;
+        UserPtr::from(suid).write(cred.suid)?;
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> isize {
+    syscall_body!(sys_getresgid, {
+        let cred = current_process().ok_or(LinuxError::ESRCH)?.cred();
+        UserPtr::from(rgid).write(cred.gid)?;
+        UserPtr::from(egid).write(cred.egid)?;
+        UserPtr::from(sgid).write(cred.sgid)?;
+        Ok(0)
+    })
+}
@@ -0,0 +1,265 @@
+use crate::futex::{
+    futex_requeue, futex_wait_multiple, futex_wake, FutexKey, FutexWaitItem, RobustListHead,
+    FUTEX_BITSET_MATCH_ANY,
+};
+use crate::process::pid::from_user;
+use crate::process::timens::CLOCK_BOOTTIME;
+use crate::process::{all_processes, current_process};
+use crate::ptr::{check_region, UserPtr, UserSlice};
+use crate::syscall_body;
+use alloc::vec::Vec;
+use arceos_posix_api::ctypes::{self, timespec};
+use axerrno::LinuxError;
+use axhal::paging::MappingFlags;
+use axtask::{current, TaskExtRef};
+use core::time::Duration;
+
+const FUTEX_WAIT: u32 = 0;
+const FUTEX_WAKE: u32 = 1;
+const FUTEX_REQUEUE: u32 = 3;
+const FUTEX_CMP_REQUEUE: u32 = 4;
+const FUTEX_WAIT_BITSET: u32 = 9;
+const FUTEX_WAKE_BITSET: u32 = 10;
+
+const FUTEX_PRIVATE_FLAG: u32 = 128;
+const FUTEX_CLOCK_REALTIME: u32 = 256;
+const FUTEX_CMD_MASK: u32 = !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+
+/// The maximum number of futexes accepted by `futex_waitv`.
+const FUTEX_WAITV_MAX: usize = 128;
+
+bitflags::bitflags! {
+    /// Per-entry flags of `struct futex_waitv`.
+    ///
+    /// See <https://github.com/torvalds/linux/blob/master/include/uapi/linux/futex.h>
+    #[derive(Debug, Clone, Copy)]
+    struct Futex2Flags: u32 {
+        const SIZE_U8 = 0x00;
+        const SIZE_U16 = 0x01;
+        const SIZE_U32 = 0x02;
+        const SIZE_U64 = 0x03;
+        const NUMA = 0x04;
+        const PRIVATE = 128;
+    }
+}
+
+const FUTEX2_SIZE_MASK: u32 = 0x03;
+
+/// `struct futex_waitv` in the Linux uapi.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FutexWaitv {
+    val: u64,
+    uaddr: u64,
+    flags: u32,
+    reserved: u32,
+}
+
+pub(crate) fn timespec_to_duration(ts: &timespec) -> Result<Duration, LinuxError> {
+    if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Convert an absolute deadline on `clock_id` into a relative timeout.
+///
+/// Monotonic deadlines are in the time namespace of the caller.
+pub(crate) fn deadline_to_timeout(
+    clock_id: u32,
+    deadline: Duration,
+) -> Result<Duration, LinuxError> {
+    let now = match clock_id {
+        ctypes::CLOCK_MONOTONIC | CLOCK_BOOTTIME => current_process()
+            .unwrap()
+            .time_ns
+            .lock()
+            .apply(clock_id, axhal::time::monotonic_time()),
+        ctypes::CLOCK_REALTIME => crate::clock::realtime(),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    Ok(deadline.saturating_sub(now))
+}
+
+pub(crate) fn sys_futex(
+    uaddr: usize,
+    op: u32,
+    val: u32,
+    timeout: *const timespec,
+    uaddr2: usize,
+    val3: u32,
+) -> isize {
+    debug!(
+        "sys_futex <= uaddr: {:#x}, op: {:#x}, val: {}",
+        uaddr, op, val
+    );
+    syscall_body!(sys_futex, {
+        if uaddr % 4 != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        check_region(uaddr, 4, MappingFlags::READ)?;
+        let key = FutexKey::current(uaddr);
+        match op & FUTEX_CMD_MASK {
+            cmd @ (FUTEX_WAIT | FUTEX_WAIT_BITSET) => {
+                let bitset = if cmd == FUTEX_WAIT {
+                    FUTEX_BITSET_MATCH_ANY
+                } else {
+                    val3
+                };
+                if bitset == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                let timeout = if let Some(ts) = UserPtr::from(timeout).read_opt()? {
+                    let ts = timespec_to_duration(&ts)?;
+                    // FUTEX_WAIT takes a relative timeout, FUTEX_WAIT_BITSET an absolute one
+                    if cmd == FUTEX_WAIT {
+                        Some(ts)
+                    } else if op & FUTEX_CLOCK_REALTIME != 0 {
+                        Some(deadline_to_timeout(ctypes::CLOCK_REALTIME, ts)?)
+                    } else {
+                        Some(deadline_to_timeout(ctypes::CLOCK_MONOTONIC, ts)?)
+                    }
+                } else {
+                    None
+                };
+                let item = FutexWaitItem {
+                    key,
+                    uaddr,
+                    expected: val as u64,
+                    is_u64: false,
+                    bitset,
+                };
+                futex_wait_multiple(&[item], timeout).map(|_| 0)
+            }
+            cmd @ (FUTEX_WAKE | FUTEX_WAKE_BITSET) => {
+                let bitset = if cmd == FUTEX_WAKE {
+                    FUTEX_BITSET_MATCH_ANY
+                } else {
+                    val3
+                };
+                if bitset == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                Ok(futex_wake(key, val as usize, bitset) as isize)
+            }
+            cmd @ (FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) => {
+                // For the requeue operations, the `timeout` argument carries `val2`.
+                let nr_requeue = timeout as usize;
+                let expected = (cmd == FUTEX_CMP_REQUEUE).then_some(val3);
+                let to = FutexKey::current(uaddr2);
+                futex_requeue(key, to, val as usize, nr_requeue, expected).map(|n| n as isize)
+            }
+            _ => {
+                warn!("Unsupported futex op: {:#x}", op);
+                Err(LinuxError::ENOSYS)
+            }
+        }
+    })
+}
+
+/// Wait on several futexes at once.
+///
+/// Returns the index of the futex which woke the thread up.
+pub(crate) fn sys_futex_waitv(
+    waiters: *const FutexWaitv,
+    nr_futexes: u32,
+    flags: u32,
+    timeout: *const timespec,
+    clock_id: u32,
+) -> isize {
+    debug!(
+        "sys_futex_waitv <= waiters: {:p}, nr_futexes: {}, flags: {:#x}",
+        waiters, nr_futexes, flags
+    );
+    syscall_body!(sys_futex_waitv, {
+        let nr_futexes = nr_futexes as usize;
+        if flags != 0 || nr_futexes == 0 || nr_futexes > FUTEX_WAITV_MAX || waiters.is_null() {
+            return Err(LinuxError::EINVAL);
+        }
+        if clock_id == CLOCK_BOOTTIME {
+            return Err(LinuxError::EINVAL);
+        }
+
+        let timeout = match UserPtr::from(timeout).read_opt()? {
+            Some(ts) => Some(deadline_to_timeout(clock_id, timespec_to_duration(&ts)?)?),
+            None => None,
+        };
+
+        let waiters = UserSlice::new(waiters, nr_futexes).as_slice()?;
+        let mut items = Vec::with_capacity(nr_futexes);
+        for waiter in waiters {
+            let Some(wflags) = Futex2Flags::from_bits(waiter.flags) else {
+                return Err(LinuxError::EINVAL);
+            };
+            if waiter.reserved != 0 || wflags.contains(Futex2Flags::NUMA) {
+                return Err(LinuxError::EINVAL);
+            }
+            let is_u64 = match waiter.flags & FUTEX2_SIZE_MASK {
+                s if s == Futex2Flags::SIZE_U32.bits() => false,
+                s if s == Futex2Flags::SIZE_U64.bits() => true,
+                // 8-bit and 16-bit futexes are not supported, just like Linux
+                _ => return Err(LinuxError::EINVAL),
+            };
+            let uaddr = waiter.uaddr as usize;
+            let align = if is_u64 { 8 } else { 4 };
+            if uaddr % align != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            check_region(uaddr, align, MappingFlags::READ)?;
+            if !is_u64 && waiter.val > u32::MAX as u64 {
+                return Err(LinuxError::EINVAL);
+            }
+            items.push(FutexWaitItem {
+                key: FutexKey::current(uaddr),
+                uaddr,
+                expected: waiter.val,
+                is_u64,
+                bitset: FUTEX_BITSET_MATCH_ANY,
+            });
+        }
+
+        futex_wait_multiple(&items, timeout).map(|index| index as isize)
+    })
+}
+
+/// Register the robust futex list of the calling thread, walked when it
+/// exits.
+pub(crate) fn sys_set_robust_list(head: *const RobustListHead, len: usize) -> isize {
+    syscall_body!(sys_set_robust_list, {
+        if len != core::mem::size_of::<RobustListHead>() {
+            return Err(LinuxError::EINVAL);
+        }
+        current().task_ext().set_robust_list(head as u64);
+        Ok(0)
+    })
+}
+
+/// Get the head of the robust futex list of the thread `tid`, 0 for the
+/// calling one.
+pub(crate) fn sys_get_robust_list(tid: i32, head_ptr: *mut usize, len_ptr: *mut usize) -> isize {
+    syscall_body!(sys_get_robust_list, {
+        let head = if tid == 0 {
+            current().task_ext().robust_list()
+        } else {
+            let tid = u64::try_from(tid)
+                .ok()
+                .and_then(from_user)
+                .ok_or(LinuxError::ESRCH)?;
+            let (proc, thread) = all_processes()
+                .into_iter()
+                .find_map(|proc| {
+                    let thread = proc.threads.lock().get(&tid).cloned()?;
+                    Some((proc, thread))
+                })
+                .ok_or(LinuxError::ESRCH)?;
+            let cred = current_process().unwrap().cred();
+            if !cred.is_privileged() && cred.uid != proc.cred().uid {
+                return Err(LinuxError::EPERM);
+            }
+            thread.task_ext().robust_list()
+        };
+        UserPtr::from(head_ptr).write(head as usize)?;
+        UserPtr::from(len_ptr).write(core::mem::size_of::<RobustListHead>())?;
+        Ok(0)
+    })
+}
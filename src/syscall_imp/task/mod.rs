@@ -1,7 +1,11 @@
+mod cred;
+mod futex;
 mod process;
 mod schedule;
 mod thread;
 
+pub(crate) use self::cred::*;
+pub(crate) use self::futex::*;
 pub(crate) use self::process::*;
 pub(crate) use self::schedule::*;
 pub(crate) use self::thread::*;
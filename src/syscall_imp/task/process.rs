@@ -1,14 +1,27 @@
+use crate::flag::{CloneFlags, WaitOptions, WaitStatus};
+use crate::fs::{resolve_path_at, AT_FDCWD};
 use crate::mm::load_elf_with_arg;
-use crate::process::wait_pid;
+use crate::process::events::{self, ProcessEvent};
+use crate::process::pid::{from_user, to_user};
+use crate::process::signal::wait_interruptible;
+use crate::process::{
+    all_processes, current_process, get_process, wait_child, ExecArgs, WaitResult, WaitTarget,
+};
+use crate::ptr::{check_region, read_cstr, UserPtr};
+use crate::signal::info::SigInfo;
 use crate::syscall_body;
-use crate::task::TaskExt;
-use crate::{flag::WaitStatus, task::write_trap_frame_to_kstack};
+use crate::task::{set_task_comm, write_trap_frame_to_kstack, TaskExt};
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use arceos_posix_api::char_ptr_to_str;
+use axerrno::{LinuxError, LinuxResult};
 use axhal::arch::UspaceContext;
+use axhal::paging::MappingFlags;
+use axsync::Mutex;
 use axtask::{current, TaskExtRef};
 use core::ffi::c_char;
+use core::mem::size_of;
+use core::sync::atomic::Ordering;
 
 pub(crate) fn sys_clone(
     flags: usize,
@@ -26,31 +39,248 @@ pub(crate) fn sys_clone(
 
         let curr_task = current();
 
-        if let Ok(new_task_id) = curr_task
+        match curr_task
             .task_ext()
             .get_proc()
             .unwrap()
             .clone_proc(flags, stack, ptid, tls, child_tid)
         {
-            Ok(new_task_id as isize)
-        } else {
-            Err(axerrno::LinuxError::ENOMEM)
+            Ok(new_task_id) => Ok(to_user(new_task_id) as isize),
+            Err(axerrno::AxError::PermissionDenied) => Err(axerrno::LinuxError::EPERM),
+            Err(axerrno::AxError::InvalidInput) => Err(axerrno::LinuxError::EINVAL),
+            Err(_) => Err(axerrno::LinuxError::ENOMEM),
         }
     })
 }
 
-pub(crate) fn sys_wait4(pid: i32, exit_code_ptr: *mut i32, _option: u32) -> usize {
-    syscall_body!(sys_wait4, {
-        loop {
-            match wait_pid(pid, exit_code_ptr, _option) {
-                Ok(child_pid) => return Ok(child_pid as usize),
-                Err(WaitStatus::NotExist) => return Err(axerrno::LinuxError::ECHILD),
-                Err(WaitStatus::Running) => {
-                    axtask::yield_now();
+/// Move the caller into new namespaces. `CLONE_NEWNS` and `CLONE_NEWTIME` are
+/// supported:
+///
+/// - `CLONE_NEWNS` gives the caller a copy of its mount namespace, where its
+///   mounts and unmounts are its own from then on.
+/// - `CLONE_NEWTIME` makes a namespace for the children of the caller, whose
+///   offsets can be set in `/proc/self/timens_offsets` until the first child
+///   is created.
+pub(crate) fn sys_unshare(flags: u32) -> isize {
+    syscall_body!(sys_unshare, {
+        let supported = CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWTIME;
+        let flags = CloneFlags::from_bits(flags)
+            .filter(|flags| supported.contains(*flags))
+            .ok_or(LinuxError::EINVAL)?;
+        let proc = current_process().unwrap();
+        if !flags.is_empty() && !proc.cred().is_privileged() {
+            return Err(LinuxError::EPERM);
+        }
+        if flags.contains(CloneFlags::CLONE_NEWNS) {
+            let mnt_ns = Arc::new(proc.mnt_ns.lock().fork());
+            *proc.mnt_ns.lock() = mnt_ns;
+        }
+        if flags.contains(CloneFlags::CLONE_NEWTIME) {
+            let time_ns = Arc::new(proc.time_ns_for_children.lock().fork());
+            *proc.time_ns_for_children.lock() = time_ns;
+        }
+        Ok(0)
+    })
+}
+
+/// Wait for a child matching `target` to change state as `options` asks,
+/// or `None` with `WNOHANG` if none has yet.
+fn wait_for_child(target: WaitTarget, options: WaitOptions) -> LinuxResult<Option<WaitResult>> {
+    let proc = current_process().unwrap();
+    loop {
+        // Read before looking, so that a change in between wakes us up
+        let seen = proc.child_events.load(Ordering::Acquire);
+        match wait_child(target, options) {
+            Ok(result) => return Ok(Some(result)),
+            Err(WaitStatus::NotExist) => return Err(LinuxError::ECHILD),
+            Err(WaitStatus::Running) => {
+                if options.contains(WaitOptions::WNOHANG) {
+                    return Ok(None);
                 }
-                _ => panic!("Unexpected wait status"),
+                wait_interruptible(&proc.child_wq, None, || {
+                    proc.child_events.load(Ordering::Acquire) != seen
+                })?;
+            }
+        }
+    }
+}
+
+/// The child a `pid` of wait4 selects: the child `pid`, any child for -1, a
+/// child in the process group of the caller for 0, or in the process group
+/// `-pid` otherwise.
+fn wait4_target(pid: i32) -> LinuxResult<WaitTarget> {
+    Ok(match pid {
+        -1 => WaitTarget::Any,
+        0 => WaitTarget::Pgid(current_process().unwrap().pgid()),
+        pid => {
+            let target = from_user(pid.unsigned_abs() as u64).ok_or(LinuxError::ECHILD)?;
+            if pid > 0 {
+                WaitTarget::Pid(target)
+            } else {
+                WaitTarget::Pgid(target)
+            }
+        }
+    })
+}
+
+pub(crate) fn sys_wait4(pid: i32, exit_code_ptr: *mut i32, options: u32) -> usize {
+    syscall_body!(sys_wait4, {
+        let options = WaitOptions::from_bits(options)
+            .filter(|options| !options.intersects(WaitOptions::WEXITED | WaitOptions::WNOWAIT))
+            .ok_or(LinuxError::EINVAL)?;
+        if !exit_code_ptr.is_null() {
+            check_region(exit_code_ptr as usize, 4, MappingFlags::WRITE)?;
+        }
+        let target = wait4_target(pid)?;
+        match wait_for_child(target, options | WaitOptions::WEXITED)? {
+            Some(result) => {
+                // The pointer was checked above
+                let _ = UserPtr::from(exit_code_ptr).write_opt(result.state.wait_status());
+                Ok(to_user(result.pid) as usize)
+            }
+            None => Ok(0),
+        }
+    })
+}
+
+/// `idtype` of waitid: any child
+const P_ALL: u32 = 0;
+/// `idtype` of waitid: the child `id`
+const P_PID: u32 = 1;
+/// `idtype` of waitid: a child in the process group `id`, or in the one of the
+/// caller for 0
+const P_PGID: u32 = 2;
+
+/// Wait for a child to exit, stop or continue as `options` asks, and describe
+/// the change in the `siginfo_t` at `infop`.
+///
+/// With `WNOHANG` and no change yet, `infop` is cleared instead.
+pub(crate) fn sys_waitid(idtype: u32, id: i32, infop: *mut SigInfo, options: u32) -> isize {
+    syscall_body!(sys_waitid, {
+        let options = WaitOptions::from_bits(options)
+            .filter(|options| {
+                options.intersects(
+                    WaitOptions::WEXITED | WaitOptions::WSTOPPED | WaitOptions::WCONTINUED,
+                )
+            })
+            .ok_or(LinuxError::EINVAL)?;
+        let target = match idtype {
+            P_ALL => WaitTarget::Any,
+            P_PID if id > 0 => WaitTarget::Pid(from_user(id as u64).ok_or(LinuxError::ECHILD)?),
+            P_PGID if id == 0 => WaitTarget::Pgid(current_process().unwrap().pgid()),
+            P_PGID if id > 0 => WaitTarget::Pgid(from_user(id as u64).ok_or(LinuxError::ECHILD)?),
+            _ => return Err(LinuxError::EINVAL),
+        };
+        if !infop.is_null() {
+            check_region(infop as usize, size_of::<SigInfo>(), MappingFlags::WRITE)?;
+        }
+        let info = match wait_for_child(target, options)? {
+            Some(result) => {
+                let mut info = result.siginfo();
+                info.pid = to_user(result.pid) as i32;
+                info
+            }
+            None => SigInfo {
+                si_code: 0,
+                ..Default::default()
+            },
+        };
+        // The pointer was checked above
+        let _ = UserPtr::from(infop).write_opt(info);
+        Ok(0)
+    })
+}
+
+/// Move the process `pid` into the process group `pgid`.
+///
+/// A `pid` of 0 is the caller and a `pgid` of 0 is the pid of the target. The
+/// target must be the caller or one of its children, and the group must
+/// already exist in the session of the caller unless it is created here.
+pub(crate) fn sys_setpgid(pid: i32, pgid: i32) -> isize {
+    syscall_body!(sys_setpgid, {
+        if pid < 0 || pgid < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let curr = current_process().unwrap();
+        let target = if pid == 0 || from_user(pid as u64) == Some(curr.pid) {
+            curr.clone()
+        } else {
+            from_user(pid as u64)
+                .and_then(get_process)
+                .filter(|proc| proc.ppid.load(Ordering::Relaxed) == curr.pid)
+                .ok_or(LinuxError::ESRCH)?
+        };
+        let pgid = if pgid == 0 {
+            target.pid
+        } else {
+            from_user(pgid as u64).ok_or(LinuxError::EPERM)?
+        };
+
+        // A session leader can't leave its group, and a child can't be moved
+        // once it is in another session
+        if target.sid() == target.pid || target.sid() != curr.sid() {
+            return Err(LinuxError::EPERM);
+        }
+        if pgid != target.pid {
+            let exists = all_processes()
+                .iter()
+                .any(|proc| proc.pgid() == pgid && proc.sid() == curr.sid());
+            if !exists {
+                return Err(LinuxError::EPERM);
             }
         }
+        target.pgid.store(pgid, Ordering::Relaxed);
+        Ok(0)
+    })
+}
+
+/// The process group of the process `pid`, or of the caller if it is 0.
+pub(crate) fn sys_getpgid(pid: i32) -> isize {
+    syscall_body!(sys_getpgid, {
+        let proc = if pid == 0 {
+            current_process()
+        } else {
+            from_user(pid as u64).and_then(get_process)
+        };
+        proc.map(|proc| to_user(proc.pgid()) as isize)
+            .ok_or(LinuxError::ESRCH)
+    })
+}
+
+/// The process group of the caller, the same as `getpgid(0)`. Shells like
+/// ash compare it with `tcgetpgrp` to tell whether they are in the foreground
+/// and may enable job control.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn sys_getpgrp() -> isize {
+    sys_getpgid(0)
+}
+
+/// Start a new session, with the caller as the leader of the session and of
+/// a new process group. The new session has no controlling terminal.
+pub(crate) fn sys_setsid() -> isize {
+    syscall_body!(sys_setsid, {
+        let curr = current_process().unwrap();
+        // A group leader can't start a session, which would leave the other
+        // members of its group in a different session
+        if curr.pgid() == curr.pid {
+            return Err(LinuxError::EPERM);
+        }
+        curr.sid.store(curr.pid, Ordering::Relaxed);
+        curr.pgid.store(curr.pid, Ordering::Relaxed);
+        Ok(to_user(curr.pid) as isize)
+    })
+}
+
+/// The session of the process `pid`, or of the caller if it is 0.
+pub(crate) fn sys_getsid(pid: i32) -> isize {
+    syscall_body!(sys_getsid, {
+        let proc = if pid == 0 {
+            current_process()
+        } else {
+            from_user(pid as u64).and_then(get_process)
+        };
+        proc.map(|proc| to_user(proc.sid()) as isize)
+            .ok_or(LinuxError::ESRCH)
     })
 }
 
@@ -67,29 +297,67 @@ pub(crate) fn sys_execve(
         return -1;
     }
 
-    let Ok(path) = char_ptr_to_str(file_name) else {
+    let Ok(path) = read_cstr(file_name) else {
         return -1;
     };
 
     // Copy the path, argv, and envp from user space to kernel space
-    let path = String::from(path);
-    let argv = unsafe { copy_from_ptr(argv) };
-    let envp = unsafe { copy_from_ptr(envp) };
+    let Ok(path) = resolve_path_at(AT_FDCWD, &path, true) else {
+        return -1;
+    };
+    let (Ok(argv), Ok(envp)) = (copy_from_ptr(argv), copy_from_ptr(envp)) else {
+        return -(LinuxError::EFAULT.code() as isize);
+    };
+    // A script runs its interpreter instead
+    let (exe, argv) = match resolve_interpreter(&path, argv) {
+        Ok(res) => res,
+        Err(e) => return -(e.code() as isize),
+    };
+
+    // The futexes the old program holds are released, the new one registers
+    // its own robust list
+    let robust_list = curr.task_ext().robust_list();
+    if robust_list != 0 {
+        let tid = crate::process::pid::to_user(curr.task_ext().tid());
+        crate::futex::exit_robust_list(robust_list as usize, tid as u32);
+        curr.task_ext().set_robust_list(0);
+    }
 
     let mut aspace = proc.aspace.lock();
 
     // Clear the address space
+    crate::mm::zero::release_all(&aspace);
     aspace.clear();
+    crate::mm::tlb::flush_all(&aspace);
+    proc.vm_areas.lock().clear();
+    crate::ipc::shm::detach_all(&proc.aspace);
+    crate::mm::forget_all_frames(crate::mm::aspace_key(&proc.aspace));
 
     // Load the ELF file
-    let Ok((entry_vaddr, ustack_top)) = load_elf_with_arg(&path, &mut aspace, &argv, &envp) else {
+    let Ok(layout) = load_elf_with_arg(
+        &exe,
+        &mut aspace,
+        &argv,
+        &envp,
+        proc.personality(),
+        &proc.cred(),
+    ) else {
         return -1;
     };
+    // The vDSO shows the offsets of the time namespace of the process
+    let key = crate::mm::aspace_key(&proc.aspace);
+    let time_ns = proc.time_ns.lock().clone();
+    if crate::mm::vdso::enter_timens(key, &mut aspace, &layout.areas, &time_ns).is_err() {
+        return -(LinuxError::ENOMEM.code() as isize);
+    }
+    proc.init_layout(&layout);
+    let argc = argv.len();
+    proc.set_exec_args(ExecArgs { path, argv, envp });
 
     // 可能造成了 UB
     // TODO: 不使用裸指针
     let task_ext = unsafe { &mut *(curr.task_ext_ptr() as *mut TaskExt) };
-    task_ext.uctx = UspaceContext::new(entry_vaddr.as_usize(), ustack_top, argv.len());
+    task_ext.uctx = UspaceContext::new(layout.entry.as_usize(), layout.ustack_pointer, argc);
 
     // Write the trap frame to the kernel stack
     let trap_frame = task_ext.uctx.get_inner();
@@ -97,6 +365,16 @@ pub(crate) fn sys_execve(
 
     drop(aspace);
 
+    // The handlers of the old program are gone. A table shared with another
+    // process through CLONE_SIGHAND is copied rather than changed
+    let mut sig_handler = curr.task_ext().signal().sig_handler.lock().clone();
+    sig_handler.reset_on_exec();
+    curr.task_ext()
+        .set_sig_handler(Arc::new(Mutex::new(sig_handler)));
+
+    set_task_comm(&curr, &proc.default_comm());
+    events::emit(ProcessEvent::Exec { pid: proc.pid });
+
     let kstack_top = curr.kernel_stack_top().unwrap();
     info!(
         "Enter user space: entry={:#x}, ustack={:#x}, kstack={:#x}",
@@ -116,23 +394,63 @@ pub(crate) fn sys_execve(
     }
 }
 
-/// Safety: ptr is a valid pointer to a null-terminated array of pointers to null-terminated strings
-unsafe fn copy_from_ptr(ptr: *const *const c_char) -> Vec<String> {
+/// How much of a script its `#!` line may take, as in Linux.
+const BINPRM_BUF_SIZE: usize = 256;
+/// How many scripts may be run by another script as their interpreter.
+const MAX_INTERP_DEPTH: usize = 4;
+
+/// The executable to load to run `path` with `argv`, and the arguments to
+/// give it.
+///
+/// A file starting with `#!interp [arg]` is run by `interp`, with the
+/// arguments `[interp, arg, path, argv[1..]]`, `arg` being everything after
+/// the interpreter on that line. The interpreter may be a script itself.
+fn resolve_interpreter(path: &str, argv: Vec<String>) -> LinuxResult<(String, Vec<String>)> {
+    let (mut exe, mut argv) = (String::from(path), argv);
+    for _ in 0..=MAX_INTERP_DEPTH {
+        // Only the start of the file is needed to tell a script
+        let mut buf = [0; BINPRM_BUF_SIZE];
+        let len = crate::fs::read_at(&exe, 0, &mut buf)?;
+        let Some(line) = buf[..len].strip_prefix(b"#!") else {
+            return Ok((exe, argv));
+        };
+        let line = match line.iter().position(|&b| b == b'\n') {
+            Some(end) => &line[..end],
+            None => line,
+        };
+        let line = core::str::from_utf8(line).map_err(|_| LinuxError::ENOEXEC)?;
+        let line = line.trim_matches([' ', '\t']);
+        let (interp, arg) = match line.split_once([' ', '\t']) {
+            Some((interp, arg)) => (interp, Some(arg.trim_start_matches([' ', '\t']))),
+            None => (line, None),
+        };
+        if interp.is_empty() {
+            return Err(LinuxError::ENOEXEC);
+        }
+        let mut args = Vec::with_capacity(argv.len() + 2);
+        args.push(String::from(interp));
+        args.extend(arg.map(String::from));
+        args.push(exe.clone());
+        args.extend(argv.into_iter().skip(1));
+        argv = args;
+        exe = resolve_path_at(AT_FDCWD, interp, true)?;
+    }
+    Err(LinuxError::ELOOP)
+}
+
+/// Copy the null-terminated array of strings at `ptr` in user space, which
+/// may be null for an empty one.
+fn copy_from_ptr(ptr: *const *const c_char) -> LinuxResult<Vec<String>> {
     let mut res = Vec::new();
-    let mut i = 0;
-    loop {
-        let p = unsafe { *ptr.add(i) };
+    if ptr.is_null() {
+        return Ok(res);
+    }
+    for i in 0.. {
+        let p = UserPtr::from(ptr.wrapping_add(i)).read()?;
         if p.is_null() {
             break;
         }
-        let Ok(s) = char_ptr_to_str(p) else {
-            return Vec::new();
-        };
-
-        let mut str = String::from(s);
-        str.push('\0');
-        res.push(str);
-        i += 1;
+        res.push(read_cstr(p)?);
     }
-    res
+    Ok(res)
 }
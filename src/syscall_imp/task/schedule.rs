@@ -1,12 +1,68 @@
+use super::{deadline_to_timeout, timespec_to_duration};
+use crate::process::signal::wait_interruptible;
+use crate::process::timens::CLOCK_BOOTTIME;
+use crate::ptr::UserPtr;
+use crate::syscall_body;
 use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+use axtask::WaitQueue;
+use core::time::Duration;
 
 pub(crate) fn sys_sched_yield() -> i32 {
     api::sys_sched_yield()
 }
 
+/// `flags` of clock_nanosleep: `req` is an absolute time on the clock
+const TIMER_ABSTIME: u32 = 1;
+
+/// Sleep for `dur`, and on a signal write the time left to `rem`.
+fn sleep(dur: Duration, rem: *mut api::ctypes::timespec) -> LinuxResult<isize> {
+    let deadline = axhal::time::monotonic_time() + dur;
+    // Nothing wakes the queue up but a signal
+    let wq = WaitQueue::new();
+    if let Err(err) = wait_interruptible(&wq, Some(dur), || false) {
+        let left = deadline.saturating_sub(axhal::time::monotonic_time());
+        UserPtr::from(rem).write_opt(api::ctypes::timespec {
+            tv_sec: left.as_secs() as _,
+            tv_nsec: left.subsec_nanos() as _,
+        })?;
+        return Err(err);
+    }
+    Ok(0)
+}
+
 pub(crate) fn sys_nanosleep(
     req: *const api::ctypes::timespec,
     rem: *mut api::ctypes::timespec,
 ) -> i32 {
-    unsafe { api::sys_nanosleep(req, rem) }
+    syscall_body!(sys_nanosleep, {
+        sleep(timespec_to_duration(&UserPtr::from(req).read()?)?, rem)
+    })
+}
+
+/// Sleep on `clock_id`, until the time `req` with `TIMER_ABSTIME`.
+///
+/// The monotonic clocks are those of the time namespace of the caller, and an
+/// absolute sleep interrupted by a signal leaves `rem` alone.
+pub(crate) fn sys_clock_nanosleep(
+    clock_id: u32,
+    flags: u32,
+    req: *const api::ctypes::timespec,
+    rem: *mut api::ctypes::timespec,
+) -> isize {
+    syscall_body!(sys_clock_nanosleep, {
+        if !matches!(
+            clock_id,
+            api::ctypes::CLOCK_REALTIME | api::ctypes::CLOCK_MONOTONIC | CLOCK_BOOTTIME
+        ) {
+            return Err(LinuxError::EINVAL);
+        }
+        let req = timespec_to_duration(&UserPtr::from(req).read()?)?;
+        if flags & TIMER_ABSTIME == 0 {
+            return sleep(req, rem);
+        }
+        let wq = WaitQueue::new();
+        wait_interruptible(&wq, Some(deadline_to_timeout(clock_id, req)?), || false)?;
+        Ok(0)
+    })
 }
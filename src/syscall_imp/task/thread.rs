@@ -1,5 +1,13 @@
+use crate::process::current_process;
+use crate::process::pid::to_user;
+use crate::ptr::{read_cstr, UserPtr, UserSlice};
+use crate::seccomp::{self, PR_GET_SECCOMP, PR_SET_SECCOMP};
+use crate::signal::signal_no::MAX_SIG_NUM;
+use crate::strace::{self, PR_GET_STRACE, PR_SET_STRACE, PR_SET_STRACE_OUTPUT};
+use crate::task::{set_task_comm, TASK_COMM_LEN};
 use crate::{signal::info, syscall_body};
 use alloc::sync::Arc;
+use axerrno::LinuxError;
 use axtask::{current, TaskExtRef};
 use core::sync::atomic::Ordering;
 use num_enum::TryFromPrimitive;
@@ -28,42 +36,30 @@ enum ArchPrctlCode {
 pub(crate) fn sys_getpid() -> i32 {
     let curr = current();
     let proc = curr.task_ext().get_proc();
-    let pid = proc.map(|p| p.pid);
+    let pid = proc.map(|p| to_user(p.pid));
     pid.unwrap_or(1) as i32
 }
 
+/// Get the thread ID of the calling thread.
+///
+/// For the main thread of a process, it is the same as the pid.
+pub(crate) fn sys_gettid() -> i32 {
+    to_user(current().task_ext().tid()) as i32
+}
+
 pub(crate) fn sys_getppid() -> i32 {
     let curr = current();
     let proc = curr.task_ext().get_proc();
-    let ppid = proc.map(|p| p.ppid.load(Ordering::Relaxed));
+    let ppid = proc.map(|p| to_user(p.ppid.load(Ordering::Relaxed)));
     ppid.unwrap_or(1) as i32
 }
 
 pub(crate) fn sys_exit(status: i32) -> ! {
-    let curr = current();
-    let clear_child_tid = curr.task_ext().clear_child_tid() as *mut i32;
-    if !clear_child_tid.is_null() {
-        // TODO: check whether the address is valid
-        unsafe {
-            // TODO: Encapsulate all operations that access user-mode memory into a unified function
-            *(clear_child_tid) = 0;
-        }
-        // TODO: wake up threads, which are blocked by futex, and waiting for the address pointed by clear_child_tid
-    }
-    match curr.task_ext().get_proc() {
-        Some(proc) => {
-            proc.exit_thread(curr.as_task_ref().clone(), status);
-        }
-        None => {
-            warn!("No process found for the current task");
-        }
-    }
-    axtask::exit(status);
+    crate::process::thread_exit(status)
 }
 
 pub(crate) fn sys_exit_group(status: i32) -> ! {
-    warn!("Temporarily replace sys_exit_group with sys_exit");
-    sys_exit(status);
+    crate::process::group_exit(status)
 }
 
 /// To set the clear_child_tid field in the task extended data.
@@ -73,13 +69,107 @@ pub(crate) fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     syscall_body!(sys_set_tid_address, {
         let curr = current();
         curr.task_ext().set_clear_child_tid(tid_ptd as _);
-        Ok(curr.id().as_u64() as isize)
+        Ok(to_user(curr.task_ext().tid()) as isize)
+    })
+}
+
+const PR_SET_PDEATHSIG: i32 = 1;
+const PR_GET_PDEATHSIG: i32 = 2;
+const PR_SET_NAME: i32 = 15;
+const PR_GET_NAME: i32 = 16;
+
+/// Operations on the calling thread or process.
+///
+/// Only the name of the thread, the signal sent when the parent exits, the
+/// syscall tracing of [`crate::strace`] and the syscall filtering of
+/// [`crate::seccomp`] are supported.
+pub(crate) fn sys_prctl(option: i32, arg2: usize, arg3: usize) -> isize {
+    syscall_body!(sys_prctl, {
+        let curr = current();
+        match option {
+            PR_SET_PDEATHSIG => {
+                if arg2 > MAX_SIG_NUM {
+                    return Err(LinuxError::EINVAL);
+                }
+                let proc = curr.task_ext().get_proc().unwrap();
+                proc.pdeath_signal.store(arg2 as u32, Ordering::Relaxed);
+            }
+            PR_GET_PDEATHSIG => {
+                if arg2 == 0 {
+                    return Err(LinuxError::EFAULT);
+                }
+                let proc = curr.task_ext().get_proc().unwrap();
+                let signal = proc.pdeath_signal.load(Ordering::Relaxed);
+                UserPtr::<i32>::from(arg2).write(signal as i32)?;
+            }
+            PR_SET_NAME => {
+                if arg2 == 0 {
+                    return Err(LinuxError::EFAULT);
+                }
+                // The name needs no NUL if it fills the whole buffer
+                let buf = UserSlice::new(arg2 as *const u8, TASK_COMM_LEN).as_slice()?;
+                let len = buf.iter().position(|&b| b == 0).unwrap_or(TASK_COMM_LEN);
+                let name = core::str::from_utf8(&buf[..len]).map_err(|_| LinuxError::EINVAL)?;
+                set_task_comm(&curr, name);
+            }
+            PR_GET_NAME => {
+                if arg2 == 0 {
+                    return Err(LinuxError::EFAULT);
+                }
+                let comm = curr.task_ext().comm();
+                let buf = UserSlice::new(arg2 as *mut u8, TASK_COMM_LEN).as_mut_slice()?;
+                buf.fill(0);
+                buf[..comm.len()].copy_from_slice(comm.as_bytes());
+            }
+            PR_SET_STRACE => {
+                let proc = curr.task_ext().get_proc().unwrap();
+                proc.strace.store(arg2 != 0, Ordering::Relaxed);
+            }
+            PR_GET_STRACE => {
+                if arg2 == 0 {
+                    return Err(LinuxError::EFAULT);
+                }
+                let proc = curr.task_ext().get_proc().unwrap();
+                let traced = proc.strace.load(Ordering::Relaxed);
+                UserPtr::<i32>::from(arg2).write(traced as i32)?;
+            }
+            PR_SET_STRACE_OUTPUT => {
+                let name = if arg2 == 0 {
+                    None
+                } else {
+                    Some(read_cstr(arg2 as _)?)
+                };
+                let proc = curr.task_ext().get_proc().unwrap();
+                *proc.strace_output.lock() = strace::Output::from_name(name.as_deref());
+            }
+            PR_GET_SECCOMP => return Ok(seccomp::get_mode() as isize),
+            PR_SET_SECCOMP => seccomp::set_mode(arg2, arg3)?,
+            _ => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
+
+/// The `personality` argument which only queries the current one.
+const PERSONALITY_QUERY: u32 = 0xffff_ffff;
+
+/// Set the execution domain of the calling process to `persona`, returning
+/// the previous one. Only [`crate::mm::aslr::ADDR_NO_RANDOMIZE`] has an effect, on the
+/// programs the process executes next.
+pub(crate) fn sys_personality(persona: u32) -> isize {
+    syscall_body!(sys_personality, {
+        let proc = current_process().unwrap();
+        let old = if persona == PERSONALITY_QUERY {
+            proc.personality()
+        } else {
+            proc.personality.swap(persona, Ordering::Relaxed)
+        };
+        Ok(old as isize)
     })
 }
 
 #[cfg(target_arch = "x86_64")]
 pub(crate) fn sys_arch_prctl(code: i32, addr: u64) -> isize {
-    use axerrno::LinuxError;
     syscall_body!(sys_arch_prctl, {
         match ArchPrctlCode::try_from(code) {
             // TODO: check the legality of the address
@@ -90,9 +180,8 @@ pub(crate) fn sys_arch_prctl(code: i32, addr: u64) -> isize {
                 Ok(0)
             }
             Ok(ArchPrctlCode::GetFs) => {
-                unsafe {
-                    *(addr as *mut u64) = axhal::arch::read_thread_pointer() as u64;
-                }
+                UserPtr::<u64>::from(addr as usize)
+                    .write(axhal::arch::read_thread_pointer() as u64)?;
                 Ok(0)
             }
             Ok(ArchPrctlCode::SetGs) => {
@@ -102,9 +191,8 @@ pub(crate) fn sys_arch_prctl(code: i32, addr: u64) -> isize {
                 Ok(0)
             }
             Ok(ArchPrctlCode::GetGs) => {
-                unsafe {
-                    *(addr as *mut u64) = x86::msr::rdmsr(x86::msr::IA32_KERNEL_GSBASE);
-                }
+                let gs = unsafe { x86::msr::rdmsr(x86::msr::IA32_KERNEL_GSBASE) };
+                UserPtr::<u64>::from(addr as usize).write(gs)?;
                 Ok(0)
             }
             _ => Err(LinuxError::ENOSYS),
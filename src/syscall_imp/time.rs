@@ -1,13 +1,339 @@
+use super::task::timespec_to_duration;
+use crate::clock;
+use crate::mm::USER_HZ;
+use crate::process::{current_process, get_process, pid};
+use crate::ptr::UserPtr;
+use crate::syscall_body;
+use alloc::vec;
 use alloc::vec::Vec;
 use arceos_posix_api as api;
-use axtask::{current, TaskExtRef, Tms};
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{current, AxTaskRef, TaskExtRef, Tms};
+use core::time::Duration;
+
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+const CLOCK_PROCESS_CPUTIME_ID: i32 = 2;
+const CLOCK_THREAD_CPUTIME_ID: i32 = 3;
+const CLOCK_MONOTONIC_RAW: i32 = 4;
+const CLOCK_REALTIME_COARSE: i32 = 5;
+const CLOCK_MONOTONIC_COARSE: i32 = 6;
+const CLOCK_BOOTTIME: i32 = 7;
+
+/// The CPU-time clocks of another process or thread, as handed out by
+/// `clock_getcpuclockid` and `pthread_getcpuclockid`, have negative ids: the
+/// complement of the pid or tid shifted left by 3, whether the clock is of a
+/// thread, and which time it counts.
+const CPUCLOCK_PERTHREAD: i32 = 4;
+const CPUCLOCK_CLOCK_MASK: i32 = 3;
+/// The user time only, while the others count the system time too
+const CPUCLOCK_VIRT: i32 = 1;
+const CPUCLOCK_SCHED: i32 = 2;
+
+/// The unit `Tms` counts the CPU time of a task in, the `AT_CLKTCK` tick,
+/// which is also the resolution of the CPU-time clocks.
+const TMS_UNIT: Duration = Duration::from_nanos(1_000_000_000 / USER_HZ as u64);
+
+/// The user and system time `task` has run for, as the scheduler accounts it.
+fn cpu_times(task: &AxTaskRef) -> (Duration, Duration) {
+    let tms: Tms = task.sys_times(&[]);
+    let time =
+        |count: isize| Duration::from_nanos(TMS_UNIT.as_nanos() as u64 * count.max(0) as u64);
+    (time(tms.tms_utime), time(tms.tms_stime))
+}
+
+/// The time of the CPU-time clock `clock_id`.
+///
+/// The time of a process is that of its live threads.
+fn cpu_clock(clock_id: i32) -> LinuxResult<Duration> {
+    let (id, per_thread, which) = match clock_id {
+        CLOCK_PROCESS_CPUTIME_ID => (0, false, CPUCLOCK_SCHED),
+        CLOCK_THREAD_CPUTIME_ID => (0, true, CPUCLOCK_SCHED),
+        id if id < 0 => (
+            !(id >> 3),
+            id & CPUCLOCK_PERTHREAD != 0,
+            id & CPUCLOCK_CLOCK_MASK,
+        ),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    if which == CPUCLOCK_CLOCK_MASK {
+        return Err(LinuxError::EINVAL);
+    }
+    let curr = current();
+    let proc = current_process().ok_or(LinuxError::EINVAL)?;
+    let global = |id: i32| pid::from_user(id as u64).ok_or(LinuxError::EINVAL);
+    let tasks: Vec<AxTaskRef> = if per_thread {
+        // Only the threads of the calling process can be looked at
+        let thread = if id == 0 {
+            curr.as_task_ref().clone()
+        } else {
+            let tid = global(id)?;
+            proc.threads
+                .lock()
+                .get(&tid)
+                .cloned()
+                .ok_or(LinuxError::EINVAL)?
+        };
+        vec![thread]
+    } else {
+        let proc = if id == 0 {
+            proc
+        } else {
+            get_process(global(id)?).ok_or(LinuxError::EINVAL)?
+        };
+        let threads = proc.threads.lock();
+        threads.values().cloned().collect()
+    };
+    let (user, system) = tasks.iter().map(cpu_times).fold(
+        (Duration::ZERO, Duration::ZERO),
+        |(user, system), (u, s)| (user + u, system + s),
+    );
+    Ok(if which == CPUCLOCK_VIRT {
+        user
+    } else {
+        user + system
+    })
+}
+
+/// The time of `clock_id`, as seen by the current process.
+///
+/// The monotonic time isn't adjusted, so `CLOCK_MONOTONIC_RAW` is
+/// `CLOCK_MONOTONIC`, and the coarse clocks are as fine as the others.
+fn clock_time(clock_id: i32) -> LinuxResult<Duration> {
+    match clock_id {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(clock::realtime()),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            let time = axhal::time::monotonic_time();
+            // Shift the clock by the offset of the time namespace of the caller
+            Ok(match current_process() {
+                Some(proc) => proc.time_ns.lock().apply(clock_id as u32, time),
+                None => time,
+            })
+        }
+        _ => cpu_clock(clock_id),
+    }
+}
+
+fn to_timespec(time: Duration) -> api::ctypes::timespec {
+    api::ctypes::timespec {
+        tv_sec: time.as_secs() as _,
+        tv_nsec: time.subsec_nanos() as _,
+    }
+}
 
 pub(crate) fn sys_clock_gettime(clock_id: i32, tp: *mut api::ctypes::timespec) -> i32 {
-    unsafe { api::sys_clock_gettime(clock_id, tp) }
+    syscall_body!(sys_clock_gettime, {
+        let tp = UserPtr::from(tp);
+        if tp.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        tp.write(to_timespec(clock_time(clock_id)?))?;
+        Ok(0)
+    })
+}
+
+/// Get the resolution of `clock_id`, if `res` isn't null.
+pub(crate) fn sys_clock_getres(clock_id: i32, res: *mut api::ctypes::timespec) -> i32 {
+    syscall_body!(sys_clock_getres, {
+        let resolution = match clock_id {
+            CLOCK_REALTIME
+            | CLOCK_MONOTONIC
+            | CLOCK_MONOTONIC_RAW
+            | CLOCK_REALTIME_COARSE
+            | CLOCK_MONOTONIC_COARSE
+            | CLOCK_BOOTTIME => Duration::from_nanos(1),
+            _ => {
+                cpu_clock(clock_id)?;
+                TMS_UNIT
+            }
+        };
+        UserPtr::from(res).write_opt(to_timespec(resolution))?;
+        Ok(0)
+    })
+}
+
+/// Set `clock_id` to `tp`. Only root can, and only `CLOCK_REALTIME` can be
+/// set, see [`crate::clock`].
+pub(crate) fn sys_clock_settime(clock_id: i32, tp: *const api::ctypes::timespec) -> i32 {
+    syscall_body!(sys_clock_settime, {
+        if clock_id != CLOCK_REALTIME {
+            return Err(LinuxError::EINVAL);
+        }
+        let time = timespec_to_duration(&UserPtr::from(tp).read()?)?;
+        check_settime()?;
+        clock::set_realtime(time);
+        Ok(0)
+    })
+}
+
+fn to_timeval(time: Duration) -> api::ctypes::timeval {
+    api::ctypes::timeval {
+        tv_sec: time.as_secs() as _,
+        tv_usec: time.subsec_micros() as _,
+    }
 }
 
 pub(crate) fn sys_get_time_of_day(tv: *mut api::ctypes::timeval) -> i32 {
-    unsafe { api::sys_get_time_of_day(tv) }
+    syscall_body!(sys_get_time_of_day, {
+        let tv = UserPtr::from(tv);
+        if tv.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        tv.write(to_timeval(clock::realtime()))?;
+        Ok(0)
+    })
+}
+
+/// Set `CLOCK_REALTIME` to `tv`, if it isn't null. The time zone `tz` is
+/// ignored, like the kernel of Linux mostly does.
+pub(crate) fn sys_settimeofday(tv: *const api::ctypes::timeval, tz: *const u8) -> i32 {
+    syscall_body!(sys_settimeofday, {
+        let time = match UserPtr::from(tv).read_opt()? {
+            Some(tv) if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) => {
+                return Err(LinuxError::EINVAL)
+            }
+            Some(tv) => Some(Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)),
+            None => None,
+        };
+        if time.is_some() || !tz.is_null() {
+            check_settime()?;
+        }
+        if let Some(time) = time {
+            clock::set_realtime(time);
+        }
+        Ok(0)
+    })
+}
+
+/// Only root can change the time.
+fn check_settime() -> LinuxResult {
+    if current_process().unwrap().cred().is_privileged() {
+        Ok(())
+    } else {
+        Err(LinuxError::EPERM)
+    }
+}
+
+/// `struct timex` of `adjtimex`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(dead_code)] // Most fields are only read by user space
+pub(crate) struct Timex {
+    modes: u32,
+    offset: i64,
+    freq: i64,
+    maxerror: i64,
+    esterror: i64,
+    status: i32,
+    constant: i64,
+    precision: i64,
+    tolerance: i64,
+    time: api::ctypes::timeval,
+    tick: i64,
+    ppsfreq: i64,
+    jitter: i64,
+    shift: i32,
+    stabil: i64,
+    jitcnt: i64,
+    calcnt: i64,
+    errcnt: i64,
+    stbcnt: i64,
+    tai: i32,
+    _reserved: [i32; 11],
+}
+
+const _: () = assert!(core::mem::size_of::<Timex>() == 208);
+
+const ADJ_OFFSET: u32 = 0x0001;
+const ADJ_SETOFFSET: u32 = 0x0100;
+const ADJ_NANO: u32 = 0x2000;
+/// The `adjtime` of old, whose offset is in microseconds
+const ADJ_OFFSET_SINGLESHOT: u32 = 0x8001;
+const ADJ_OFFSET_SS_READ: u32 = 0xa001;
+
+/// The offset is in nanoseconds rather than microseconds.
+const STA_NANO: i32 = 0x2000;
+/// The clock is synchronized
+const TIME_OK: i32 = 0;
+/// The largest offset `ADJ_OFFSET` slews in, in nanoseconds
+const MAXPHASE: i64 = 500_000_000;
+
+/// Tune `CLOCK_REALTIME` as `adjtimex` does, only slewing the time in and
+/// stepping it: the other parameters of the NTP clock model are ignored and
+/// read as 0. Only root can change anything.
+fn adjtimex(tx: &mut Timex) -> LinuxResult<i32> {
+    let modes = tx.modes;
+    if modes != 0 && modes != ADJ_OFFSET_SS_READ {
+        check_settime()?;
+    }
+    // Offsets in nanoseconds or microseconds
+    let unit = if modes & ADJ_NANO != 0 { 1 } else { 1000 };
+    if modes & ADJ_OFFSET_SINGLESHOT == ADJ_OFFSET_SINGLESHOT {
+        if modes != ADJ_OFFSET_SINGLESHOT && modes != ADJ_OFFSET_SS_READ {
+            return Err(LinuxError::EINVAL);
+        }
+        let delta = (modes == ADJ_OFFSET_SINGLESHOT).then(|| tx.offset.saturating_mul(1000));
+        tx.offset = clock::adjust(delta) / 1000;
+    } else {
+        if modes & ADJ_SETOFFSET != 0 {
+            let (sec, frac) = (tx.time.tv_sec as i64, tx.time.tv_usec as i64);
+            if !(0..1_000_000_000 / unit).contains(&frac) {
+                return Err(LinuxError::EINVAL);
+            }
+            clock::step_realtime(
+                sec.saturating_mul(1_000_000_000)
+                    .saturating_add(frac * unit),
+            );
+        }
+        if modes & ADJ_OFFSET != 0 {
+            let delta = tx.offset.saturating_mul(unit).clamp(-MAXPHASE, MAXPHASE);
+            clock::adjust(Some(delta));
+        }
+        tx.offset = clock::adjust(None) / unit;
+    }
+    let now = clock::realtime();
+    *tx = Timex {
+        modes: tx.modes,
+        offset: tx.offset,
+        status: if unit == 1 { STA_NANO } else { 0 },
+        precision: 1,
+        time: api::ctypes::timeval {
+            tv_sec: now.as_secs() as _,
+            tv_usec: (now.subsec_nanos() as i64 / unit) as _,
+        },
+        tick: 1_000_000 / USER_HZ as i64,
+        ..Default::default()
+    };
+    Ok(TIME_OK)
+}
+
+/// See [`adjtimex`].
+pub(crate) fn sys_adjtimex(buf: *mut Timex) -> i32 {
+    syscall_body!(sys_adjtimex, {
+        let buf = UserPtr::from(buf);
+        let mut tx = buf.read()?;
+        let state = adjtimex(&mut tx)?;
+        buf.write(tx)?;
+        Ok(state)
+    })
+}
+
+/// `adjtimex` of `clock_id`, which only `CLOCK_REALTIME` supports.
+pub(crate) fn sys_clock_adjtime(clock_id: i32, buf: *mut Timex) -> i32 {
+    syscall_body!(sys_clock_adjtime, {
+        match clock_id {
+            CLOCK_REALTIME => {}
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_BOOTTIME => {
+                return Err(LinuxError::EOPNOTSUPP)
+            }
+            _ => return Err(LinuxError::EINVAL),
+        }
+        let buf = UserPtr::from(buf);
+        let mut tx = buf.read()?;
+        let state = adjtimex(&mut tx)?;
+        buf.write(tx)?;
+        Ok(state)
+    })
 }
 
 pub(crate) fn sys_times(tms: *mut Tms) -> isize {
@@ -15,8 +341,8 @@ pub(crate) fn sys_times(tms: *mut Tms) -> isize {
     let proc = curr.task_ext().get_proc().unwrap();
     let children = proc.children.lock();
     let res = curr.sys_times(&children.iter().map(|x| x.main_thread()).collect::<Vec<_>>());
-    unsafe {
-        tms.write(res);
+    if UserPtr::from(tms).write(res).is_err() {
+        return -(LinuxError::EFAULT.code() as isize);
     }
     res.tms_utime
 }
@@ -0,0 +1,106 @@
+//! Kernel tunables, exposed as `/proc/sys/<name>`.
+//!
+//! Every tunable is a named integer. Subsystems read their limits from here
+//! instead of hard-coding them, so that they can be adjusted at runtime.
+use axerrno::{LinuxError, LinuxResult};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A single integer tunable.
+pub struct Sysctl {
+    /// The path relative to `/proc/sys`, e.g. `fs/pipe-max-size`
+    pub name: &'static str,
+    value: AtomicUsize,
+    min: usize,
+    max: usize,
+}
+
+impl Sysctl {
+    const fn new(name: &'static str, value: usize, min: usize, max: usize) -> Self {
+        Self {
+            name,
+            value: AtomicUsize::new(value),
+            min,
+            max,
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, value: usize) -> LinuxResult {
+        if value < self.min || value > self.max {
+            return Err(LinuxError::EINVAL);
+        }
+        self.value.store(value, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The maximum size in bytes of a single pipe buffer.
+pub static PIPE_MAX_SIZE: Sysctl = Sysctl::new("fs/pipe-max-size", 1 << 20, 4096, 1 << 31);
+/// The number of pipe pages a single user may own before new pipes are shrunk to one page.
+pub static PIPE_USER_PAGES_SOFT: Sysctl =
+    Sysctl::new("fs/pipe-user-pages-soft", 16384, 0, usize::MAX);
+/// The number of pipe pages a single user may own before pipe creation fails, 0 for no limit.
+pub static PIPE_USER_PAGES_HARD: Sysctl = Sysctl::new("fs/pipe-user-pages-hard", 0, 0, usize::MAX);
+
+/// The number of 512-byte blocks an unprivileged user may own, 0 for no limit.
+pub static QUOTA_MAX_BLOCKS: Sysctl = Sysctl::new("fs/quota-max-blocks", 0, 0, usize::MAX);
+/// Whether the owners and modes of files are persisted in the image.
+pub static META_PERSIST: Sysctl = Sysctl::new("fs/meta-persist", 1, 0, 1);
+
+/// Whether the kernel sticks to POSIX where it costs performance, see
+/// [`strict_posix`].
+pub static STRICT_POSIX: Sysctl = Sysctl::new("kernel/strict-posix", 1, 0, 1);
+
+/// The pids and tids are below this, and wrap around when they reach it.
+pub static PID_MAX: Sysctl = Sysctl::new("kernel/pid_max", 32768, 301, 1 << 22);
+/// Whether every testcase gets its pids in sequence from `kernel/pid-base`.
+pub static PID_SEQUENTIAL: Sysctl = Sysctl::new("kernel/pid-sequential", 0, 0, 1);
+/// The first pid of every testcase when `kernel/pid-sequential` is set.
+pub static PID_BASE: Sysctl = Sysctl::new("kernel/pid-base", 2, 2, 32767);
+
+/// Which parts of the layout of new address spaces are randomized, as in
+/// Linux, see [`crate::mm::aslr`].
+pub static RANDOMIZE_VA_SPACE: Sysctl = Sysctl::new("kernel/randomize_va_space", 0, 0, 2);
+
+/// Whether the console output is also drawn on the framebuffer.
+#[cfg(feature = "display")]
+pub static FBCON: Sysctl = Sysctl::new("kernel/fbcon", 0, 0, 1);
+
+static SYSCTLS: &[&Sysctl] = &[
+    &PIPE_MAX_SIZE,
+    &PIPE_USER_PAGES_SOFT,
+    &PIPE_USER_PAGES_HARD,
+    &QUOTA_MAX_BLOCKS,
+    &META_PERSIST,
+    &STRICT_POSIX,
+    &PID_MAX,
+    &PID_SEQUENTIAL,
+    &PID_BASE,
+    &RANDOMIZE_VA_SPACE,
+    #[cfg(feature = "display")]
+    &FBCON,
+];
+
+/// Whether the strict POSIX mode is on, the default. Clearing
+/// `kernel/strict-posix` selects the fast mode, for benchmarks, in which:
+///
+/// - `writev` writes its segments one at a time instead of all at once, so
+///   the writes of other processes, even with `O_APPEND`, may come in between;
+/// - real-time signals are no longer queued but merged like standard ones,
+///   keeping the information of the first one sent.
+pub fn strict_posix() -> bool {
+    STRICT_POSIX.get() != 0
+}
+
+/// Find a tunable by its name relative to `/proc/sys`.
+pub fn find(name: &str) -> Option<&'static Sysctl> {
+    SYSCTLS.iter().copied().find(|ctl| ctl.name == name)
+}
+
+/// Iterate over all the registered tunables.
+pub fn iter() -> impl Iterator<Item = &'static Sysctl> {
+    SYSCTLS.iter().copied()
+}
@@ -1,24 +1,55 @@
-use crate::process::{new_process, AxProcessRef, Process};
+use crate::mm::UserLayout;
+use crate::perf::PerfEvent;
+use crate::process::pid::{alloc_tid, PidNamespace};
+use crate::process::signal::SignalModule;
+use crate::process::{new_process, AxProcessRef, ExecArgs, Process};
+use crate::signal::SignalHandler;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use arceos_posix_api::FD_TABLE;
 use axfs::{CURRENT_DIR, CURRENT_DIR_PATH};
 use axhal::arch::{TrapFrame, UspaceContext};
 use axmm::AddrSpace;
 use axns::{AxNamespace, AxNamespaceIf};
-use axsync::Mutex;
-use axtask::{AxTaskRef, TaskExtRef, TaskInner};
-use core::sync::atomic::AtomicU64;
+use axsync::{Mutex, MutexGuard};
+use axtask::{AxTaskRef, TaskExtRef, TaskInner, WaitQueue};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
 
 /// Task extended data for the monolithic kernel.
 pub struct TaskExt {
     /// 所属进程
     pub proc: Weak<Process>,
+    /// The thread ID, which is also the pid for the main thread of a process
+    tid: u64,
+    /// The name of the thread, i.e. `comm` on Linux
+    comm: Mutex<String>,
     /// The clear thread tid field
     ///
     /// See <https://manpages.debian.org/unstable/manpages-dev/set_tid_address.2.en.html#clear_child_tid>
     ///
     /// When the thread exits, the kernel clears the word at this address if it is not NULL.
     clear_child_tid: AtomicU64,
+    /// The head of the robust futex list of the thread, 0 if it has none
+    robust_list: AtomicU64,
+    /// The signals of the thread: its mask, its pending signals and the
+    /// handlers it shares with the other threads of its process
+    signal: Mutex<SignalModule>,
+    /// Set by the senders of a signal, and for every thread when the process
+    /// exits or stops, so that returning to user space only looks at the
+    /// process and the signals, under their lock, when it is set
+    sig_pending: AtomicBool,
+    /// The address of the wait queue the thread sleeps on in an interruptible
+    /// wait, or 0, so that a signal can wake it up
+    interrupt_wq: Mutex<usize>,
+    /// The number, plus one, of the last syscall which failed with `EINTR`,
+    /// to make it again if the signal asks for a restart, or 0
+    interrupted_sysno: AtomicUsize,
+    /// The first argument of that syscall
+    interrupted_arg0: AtomicUsize,
+    /// The perf events counting the thread, dropped once closed
+    perf_events: Mutex<Vec<Weak<PerfEvent>>>,
     /// The user space context.
     pub uctx: UspaceContext,
     /// The resource namespace.
@@ -26,11 +57,20 @@ pub struct TaskExt {
 }
 
 impl TaskExt {
-    pub fn new(uctx: UspaceContext, proc: &AxProcessRef) -> Self {
+    pub fn new(tid: u64, comm: &str, uctx: UspaceContext, proc: &AxProcessRef) -> Self {
         let ext = Self {
             proc: Arc::downgrade(proc),
+            tid,
+            comm: Mutex::new(truncate_comm(comm)),
             uctx,
             clear_child_tid: AtomicU64::new(0),
+            robust_list: AtomicU64::new(0),
+            signal: Mutex::new(SignalModule::new(None)),
+            sig_pending: AtomicBool::new(false),
+            interrupt_wq: Mutex::new(0),
+            interrupted_sysno: AtomicUsize::new(0),
+            interrupted_arg0: AtomicUsize::new(0),
+            perf_events: Mutex::new(Vec::new()),
             ns: AxNamespace::new_thread_local(),
         };
         ext.init_ns_space();
@@ -41,6 +81,42 @@ impl TaskExt {
         self.proc.upgrade()
     }
 
+    /// The thread ID of the task.
+    pub fn tid(&self) -> u64 {
+        self.tid
+    }
+
+    /// The name of the thread.
+    pub fn comm(&self) -> String {
+        self.comm.lock().clone()
+    }
+
+    /// Rename the thread, truncating the name to `TASK_COMM_LEN - 1` bytes.
+    /// Only through [`set_task_comm`], which renames the axtask too.
+    fn set_comm(&self, comm: &str) {
+        *self.comm.lock() = truncate_comm(comm);
+    }
+
+    /// Count the thread with `event` from its next return to user space.
+    pub(crate) fn add_perf_event(&self, event: &Arc<PerfEvent>) {
+        self.perf_events.lock().push(Arc::downgrade(event));
+    }
+
+    /// Call `f` on the open perf events counting the thread.
+    pub(crate) fn for_each_perf_event(&self, f: impl Fn(&PerfEvent)) {
+        let mut events = self.perf_events.lock();
+        if events.is_empty() {
+            return;
+        }
+        events.retain(|event| match event.upgrade() {
+            Some(event) => {
+                f(&event);
+                true
+            }
+            None => false,
+        });
+    }
+
     /// This function is used to initialize the namespace space.
     /// It is called when the task is created.
     fn init_ns_space(&self) {
@@ -61,6 +137,106 @@ impl TaskExt {
             .store(clear_child_tid, core::sync::atomic::Ordering::Relaxed);
     }
 
+    /// The head of the robust futex list, 0 if the thread has none.
+    pub(crate) fn robust_list(&self) -> u64 {
+        self.robust_list.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_robust_list(&self, head: u64) {
+        self.robust_list
+            .store(head, core::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// The signals of the thread.
+    pub(crate) fn signal(&self) -> MutexGuard<'_, SignalModule> {
+        self.signal.lock()
+    }
+
+    /// Use the signal handlers `sig_handler`, shared with other threads,
+    /// instead of a table of its own. Done before the thread runs.
+    pub(crate) fn set_sig_handler(&self, sig_handler: Arc<Mutex<SignalHandler>>) {
+        self.signal.lock().sig_handler = sig_handler;
+    }
+
+    /// Whether a signal may be pending for the thread.
+    pub(crate) fn signal_pending(&self) -> bool {
+        self.sig_pending.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Note that a signal may be pending, after making it so.
+    pub(crate) fn set_signal_pending(&self) {
+        self.sig_pending
+            .store(true, core::sync::atomic::Ordering::Release);
+    }
+
+    /// Clear the flag before looking at the signals, and tell whether it was
+    /// set.
+    pub(crate) fn take_signal_pending(&self) -> bool {
+        self.sig_pending
+            .swap(false, core::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Clear the flag, when no signal turned out to be pending under the lock
+    /// of the signals.
+    pub(crate) fn clear_signal_pending(&self) {
+        self.sig_pending
+            .store(false, core::sync::atomic::Ordering::Release);
+    }
+
+    /// Note that the thread sleeps on `wq` until a signal arrives, or that it
+    /// doesn't anymore with `None`.
+    pub(crate) fn set_interrupt_wq(&self, wq: Option<&WaitQueue>) {
+        *self.interrupt_wq.lock() = wq.map_or(0, |wq| wq as *const WaitQueue as usize);
+    }
+
+    /// Wake the thread up if it sleeps in an interruptible wait. The other
+    /// sleepers of the queue go back to sleep.
+    pub(crate) fn interrupt(&self) {
+        let wq = self.interrupt_wq.lock();
+        if *wq != 0 {
+            // SAFETY: the sleeper unregisters the queue, under this lock,
+            // before it can go away
+            unsafe { &*(*wq as *const WaitQueue) }.notify_all(false);
+        }
+    }
+
+    // Only the thread itself records and takes the interrupted syscall
+    pub(crate) fn set_interrupted_syscall(&self, syscall_num: usize, arg0: usize) {
+        self.interrupted_arg0
+            .store(arg0, core::sync::atomic::Ordering::Relaxed);
+        self.interrupted_sysno
+            .store(syscall_num + 1, core::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub(crate) fn take_interrupted_syscall(&self) -> Option<(usize, usize)> {
+        let sysno = self
+            .interrupted_sysno
+            .swap(0, core::sync::atomic::Ordering::Relaxed);
+        let arg0 = self
+            .interrupted_arg0
+            .load(core::sync::atomic::Ordering::Relaxed);
+        sysno.checked_sub(1).map(|sysno| (sysno, arg0))
+    }
+
+    /// Close the files of the thread, unless its fd table is shared with a
+    /// thread which is still running.
+    pub(crate) fn close_files(&self) {
+        let table = FD_TABLE.deref_from(&self.ns).share();
+        // Ours, and the one just taken
+        if Arc::strong_count(&table) > 2 {
+            return;
+        }
+        let mut files = Vec::new();
+        {
+            let mut table = table.write();
+            for fd in 0..table.capacity() {
+                files.extend(table.remove(fd));
+            }
+        }
+        // Closing a file may wake up others, which is better done unlocked
+        drop(files);
+    }
+
     pub(crate) fn init_fs_shared(&self) {
         FD_TABLE.deref_from(&self.ns).init_shared(FD_TABLE.share());
     }
@@ -88,6 +264,58 @@ impl Drop for TaskExt {
     }
 }
 
+/// The size of the name buffer of a task on Linux, including the trailing NUL.
+pub const TASK_COMM_LEN: usize = 16;
+
+fn truncate_comm(comm: &str) -> String {
+    let mut end = comm.len().min(TASK_COMM_LEN - 1);
+    while !comm.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::from(&comm[..end])
+}
+
+/// The name of an axtask running a user thread: the thread name plus its tid.
+pub fn task_name(comm: &str, tid: u64) -> String {
+    format!("{}:{}", truncate_comm(comm), tid)
+}
+
+/// Rename the user thread `task`, in `comm` and in the name of its axtask.
+pub fn set_task_comm(task: &TaskInner, comm: &str) {
+    let ext = task.task_ext();
+    ext.set_comm(comm);
+    task.set_name(&task_name(comm, ext.tid()));
+}
+
+/// The name of a program as shown in `comm`: the basename of its path.
+pub fn exe_basename(path: &str) -> &str {
+    path.trim_end_matches('\0')
+        .rsplit('/')
+        .next()
+        .unwrap_or(path)
+}
+
+/// Prefix for the log lines of this crate, identifying the current user thread.
+///
+/// Printed as `[pid:tid] `, or nothing if the current task is not a user thread.
+pub struct LogPrefix;
+
+impl core::fmt::Display for LogPrefix {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Some(curr) = axtask::current_may_uninit() else {
+            return Ok(());
+        };
+        // Safety: We only check whether the task extended data is null and do not access it.
+        if unsafe { curr.task_ext_ptr() }.is_null() {
+            return Ok(());
+        }
+        match curr.task_ext().get_proc() {
+            Some(proc) => write!(f, "[{}:{}] ", proc.pid, curr.task_ext().tid()),
+            None => write!(f, "[?:{}] ", curr.task_ext().tid()),
+        }
+    }
+}
+
 struct AxNamespaceImpl;
 
 #[crate_interface::impl_interface]
@@ -105,7 +333,14 @@ impl AxNamespaceIf for AxNamespaceImpl {
 
 axtask::def_task_ext!(TaskExt);
 
-pub fn spawn_user_task(aspace: Arc<Mutex<AddrSpace>>, uctx: UspaceContext) -> AxTaskRef {
+pub fn spawn_user_task(
+    name: &str,
+    aspace: Arc<Mutex<AddrSpace>>,
+    uctx: UspaceContext,
+    layout: &UserLayout,
+) -> AxTaskRef {
+    let pid = alloc_tid(&PidNamespace::root()).expect("no free pid for the user task");
+    let comm = exe_basename(name);
     let mut task = TaskInner::new(
         || {
             let curr = axtask::current();
@@ -118,15 +353,26 @@ pub fn spawn_user_task(aspace: Arc<Mutex<AddrSpace>>, uctx: UspaceContext) -> Ax
             );
             unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
         },
-        "userboot".into(),
+        task_name(comm, pid),
         crate::config::KERNEL_STACK_SIZE,
     );
-    let pid = task.id().as_u64();
-    let proc = new_process(1, pid, aspace.clone());
+    let proc = new_process(1, pid, aspace.clone(), Default::default());
+    proc.init_layout(layout);
+    // As `load_user_app` put them on the stack
+    proc.set_exec_args(ExecArgs {
+        path: layout.exe.clone(),
+        argv: alloc::vec![String::from(name)],
+        envp: Vec::new(),
+    });
+    proc.strace.store(
+        crate::strace::traced_at_boot(name),
+        core::sync::atomic::Ordering::Relaxed,
+    );
+    *proc.strace_output.lock() = crate::strace::output_at_boot();
 
     task.ctx_mut()
         .set_page_table_root(aspace.lock().page_table_root());
-    task.init_task_ext(TaskExt::new(uctx, &proc));
+    task.init_task_ext(TaskExt::new(pid, comm, uctx, &proc));
     task.task_ext().init_ns();
 
     let task = axtask::spawn_task(task);
@@ -0,0 +1,245 @@
+//! Static kernel tracepoints.
+//!
+//! Hot paths of the kernel call [`record`] with an event and two arguments.
+//! When the event is enabled, a fixed-size [`TraceRecord`] is appended to the
+//! ring buffer of the current CPU, overwriting the oldest record once the ring
+//! is full. Disabled events cost a single atomic load.
+//!
+//! The events are controlled through `/proc/trace/enable` and the buffers are
+//! dumped through `/proc/trace/buffer` in the format produced by [`dump`].
+use alloc::vec::Vec;
+use axstd::os::arceos::modules::axconfig;
+use axsync::Mutex;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// The magic at the start of a dump: `"STRC"` in little endian.
+pub const TRACE_MAGIC: u32 = 0x4352_5453;
+/// The version of the dump format.
+pub const TRACE_VERSION: u32 = 1;
+/// The number of records kept per CPU.
+const RING_CAPACITY: usize = 4096;
+
+/// The tracepoints of the kernel.
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// `arg0` is the syscall number
+    SyscallEnter = 0,
+    /// `arg0` is the syscall number, `arg1` the return value
+    SyscallExit = 1,
+    /// `arg0` is the tid of the previous thread on the CPU, `arg1` the next one
+    ContextSwitch = 2,
+    /// `arg0` is the faulting address, `arg1` the access flags
+    PageFault = 3,
+    /// `arg0` is the signal number, `arg1` the address of the handler
+    SignalDeliver = 4,
+}
+
+impl TraceEvent {
+    pub const ALL: [TraceEvent; 5] = [
+        TraceEvent::SyscallEnter,
+        TraceEvent::SyscallExit,
+        TraceEvent::ContextSwitch,
+        TraceEvent::PageFault,
+        TraceEvent::SignalDeliver,
+    ];
+
+    /// The name of the event in `/proc/trace/enable`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceEvent::SyscallEnter => "sys_enter",
+            TraceEvent::SyscallExit => "sys_exit",
+            TraceEvent::ContextSwitch => "sched_switch",
+            TraceEvent::PageFault => "page_fault",
+            TraceEvent::SignalDeliver => "signal_deliver",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.name() == name)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u16
+    }
+}
+
+/// A single record, written to the dump as is in native endianness.
+#[allow(dead_code)] // The fields are only read by userspace through the dump
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceRecord {
+    /// Monotonic time in nanoseconds
+    pub timestamp: u64,
+    /// The tid of the current thread, 0 for kernel tasks
+    pub tid: u32,
+    pub cpu: u16,
+    pub event: u16,
+    pub arg0: u64,
+    pub arg1: u64,
+}
+
+struct TraceRing {
+    records: Vec<TraceRecord>,
+    /// The index the next record is written to
+    head: usize,
+    /// The number of records lost because the ring was full
+    dropped: u64,
+    /// The tid of the thread which recorded the last event on this CPU
+    last_tid: u32,
+}
+
+impl TraceRing {
+    const fn new() -> Self {
+        Self {
+            records: Vec::new(),
+            head: 0,
+            dropped: 0,
+            last_tid: 0,
+        }
+    }
+
+    fn push(&mut self, record: TraceRecord) {
+        if self.records.len() < RING_CAPACITY {
+            self.records.push(record);
+        } else {
+            self.records[self.head] = record;
+            self.dropped += 1;
+        }
+        self.head = (self.head + 1) % RING_CAPACITY;
+    }
+
+    /// The records from the oldest to the newest.
+    fn iter(&self) -> impl Iterator<Item = &TraceRecord> {
+        let (newer, older) = if self.records.len() < RING_CAPACITY {
+            (&self.records[..], &[][..])
+        } else {
+            self.records.split_at(self.head)
+        };
+        older.iter().chain(newer)
+    }
+
+    fn clear(&mut self) {
+        self.records.clear();
+        self.head = 0;
+        self.dropped = 0;
+    }
+}
+
+static ENABLED: AtomicU32 = AtomicU32::new(0);
+static RINGS: [Mutex<TraceRing>; axconfig::SMP] =
+    [const { Mutex::new(TraceRing::new()) }; axconfig::SMP];
+/// The total number of records lost, kept outside the rings for cheap reads.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Whether `event` is currently traced.
+#[inline]
+pub fn is_enabled(event: TraceEvent) -> bool {
+    ENABLED.load(Ordering::Relaxed) & event.bit() != 0
+}
+
+/// The events currently traced.
+pub fn enabled_events() -> impl Iterator<Item = TraceEvent> {
+    TraceEvent::ALL
+        .into_iter()
+        .filter(|&event| is_enabled(event))
+}
+
+/// Enable exactly the given events.
+pub fn set_enabled(events: &[TraceEvent]) {
+    let mask = events.iter().fold(0, |mask, event| mask | event.bit());
+    ENABLED.store(mask, Ordering::Relaxed);
+}
+
+fn current_tid() -> u32 {
+    match axtask::current_may_uninit() {
+        Some(task) if !unsafe { task.task_ext_ptr().is_null() } => {
+            use axtask::TaskExtRef;
+            task.task_ext().tid() as u32
+        }
+        _ => 0,
+    }
+}
+
+/// Hit the tracepoint `event`.
+///
+/// The scheduler has no tracepoints of its own, so a context switch is
+/// recorded whenever a CPU sees an event from a different thread than the
+/// previous one while `sched_switch` is enabled.
+#[inline]
+pub fn record(event: TraceEvent, arg0: u64, arg1: u64) {
+    let wanted = event.bit() | TraceEvent::ContextSwitch.bit();
+    if ENABLED.load(Ordering::Relaxed) & wanted == 0 {
+        return;
+    }
+    record_slow(event, arg0, arg1);
+}
+
+#[cold]
+fn record_slow(event: TraceEvent, arg0: u64, arg1: u64) {
+    let cpu = axhal::cpu::this_cpu_id();
+    let tid = current_tid();
+    let timestamp = axhal::time::monotonic_time_nanos();
+    let mut ring = RINGS[cpu % axconfig::SMP].lock();
+    let dropped = ring.dropped;
+
+    let prev_tid = core::mem::replace(&mut ring.last_tid, tid);
+    if prev_tid != tid && is_enabled(TraceEvent::ContextSwitch) {
+        ring.push(TraceRecord {
+            timestamp,
+            tid,
+            cpu: cpu as u16,
+            event: TraceEvent::ContextSwitch as u16,
+            arg0: prev_tid as u64,
+            arg1: tid as u64,
+        });
+    }
+    if event != TraceEvent::ContextSwitch && is_enabled(event) {
+        ring.push(TraceRecord {
+            timestamp,
+            tid,
+            cpu: cpu as u16,
+            event: event as u16,
+            arg0,
+            arg1,
+        });
+    }
+    DROPPED.fetch_add(ring.dropped - dropped, Ordering::Relaxed);
+}
+
+/// Dump the rings of all CPUs.
+///
+/// The dump starts with a header of four `u32`: [`TRACE_MAGIC`],
+/// [`TRACE_VERSION`], the size of a record and the number of records,
+/// followed by a `u64` with the number of dropped records. Then come the
+/// [`TraceRecord`]s, grouped by CPU and ordered by time within each CPU.
+pub fn dump() -> Vec<u8> {
+    let mut records = Vec::new();
+    for ring in RINGS.iter() {
+        records.extend(ring.lock().iter().copied());
+    }
+
+    let record_size = core::mem::size_of::<TraceRecord>();
+    let mut buf = Vec::with_capacity(24 + records.len() * record_size);
+    buf.extend_from_slice(&TRACE_MAGIC.to_ne_bytes());
+    buf.extend_from_slice(&TRACE_VERSION.to_ne_bytes());
+    buf.extend_from_slice(&(record_size as u32).to_ne_bytes());
+    buf.extend_from_slice(&(records.len() as u32).to_ne_bytes());
+    buf.extend_from_slice(&DROPPED.load(Ordering::Relaxed).to_ne_bytes());
+    for record in &records {
+        // SAFETY: `TraceRecord` is `repr(C)` without padding
+        let bytes = unsafe {
+            core::slice::from_raw_parts(record as *const TraceRecord as *const u8, record_size)
+        };
+        buf.extend_from_slice(bytes);
+    }
+    buf
+}
+
+/// Discard all the records.
+pub fn clear() {
+    for ring in RINGS.iter() {
+        ring.lock().clear();
+    }
+    DROPPED.store(0, Ordering::Relaxed);
+}
@@ -0,0 +1,187 @@
+//! The line discipline: turns the raw bytes received by a terminal into the
+//! input read by programs, as configured by termios.
+//!
+//! In canonical mode, input is edited a line at a time and only complete lines
+//! become readable. In raw mode, every byte is readable as soon as it arrives.
+use super::termios::{
+    cc, Termios, ECHOCTL, ECHOE, ECHOK, ECHOKE, ECHONL, ICRNL, IGNCR, INLCR, ISIG, NOFLSH,
+};
+use crate::signal::signal_no::SignalNo;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// The maximum length of a line in canonical mode, like Linux's `N_TTY_BUF_SIZE`.
+const MAX_CANON: usize = 4096;
+
+#[derive(Default)]
+pub struct LineDiscipline {
+    /// Complete lines, readable in canonical mode. An empty line is an end of
+    /// file, produced by `VEOF` at the start of a line.
+    lines: VecDeque<Vec<u8>>,
+    /// The line being edited in canonical mode
+    line: Vec<u8>,
+    /// Bytes readable in raw mode
+    raw: VecDeque<u8>,
+}
+
+/// Append the echo of `byte` to `echo`, showing control characters as `^X`
+/// if `ECHOCTL` is set.
+fn echo_byte(byte: u8, termios: &Termios, echo: &mut Vec<u8>) {
+    let is_ctl = byte < 0x20 && byte != b'\t' && byte != b'\n' || byte == 0x7f;
+    if is_ctl && termios.c_lflag & ECHOCTL != 0 {
+        echo.push(b'^');
+        echo.push(byte ^ 0x40);
+    } else {
+        echo.push(byte);
+    }
+}
+
+/// Erase the last echoed character on the screen.
+fn echo_erase(byte: u8, termios: &Termios, echo: &mut Vec<u8>) {
+    let is_ctl = byte < 0x20 && byte != b'\t' || byte == 0x7f;
+    let width = if is_ctl && termios.c_lflag & ECHOCTL != 0 {
+        2
+    } else {
+        1
+    };
+    for _ in 0..width {
+        echo.extend_from_slice(b"\x08 \x08");
+    }
+}
+
+impl LineDiscipline {
+    /// Process a received byte, appending what should be echoed to `echo`.
+    ///
+    /// Returns the signal to send to the foreground process group if the byte
+    /// is one of the `VINTR`, `VQUIT` and `VSUSP` characters and `ISIG` is set.
+    /// The byte is then consumed, and the pending input discarded unless
+    /// `NOFLSH` is set.
+    pub fn receive(
+        &mut self,
+        mut byte: u8,
+        termios: &Termios,
+        echo: &mut Vec<u8>,
+    ) -> Option<SignalNo> {
+        match byte {
+            b'\r' if termios.c_iflag & IGNCR != 0 => return None,
+            b'\r' if termios.c_iflag & ICRNL != 0 => byte = b'\n',
+            b'\n' if termios.c_iflag & INLCR != 0 => byte = b'\r',
+            _ => {}
+        }
+        let echo_on = termios.echo();
+        let c_cc = &termios.c_cc;
+        let is_special = |index: usize| c_cc[index] != 0 && c_cc[index] == byte;
+
+        if termios.c_lflag & ISIG != 0 {
+            let signal = [
+                (cc::VINTR, SignalNo::SIGINT),
+                (cc::VQUIT, SignalNo::SIGQUIT),
+                (cc::VSUSP, SignalNo::SIGTSTP),
+            ]
+            .into_iter()
+            .find(|&(index, _)| is_special(index))
+            .map(|(_, signal)| signal);
+            if let Some(signal) = signal {
+                if termios.c_lflag & NOFLSH == 0 {
+                    self.flush_input();
+                }
+                if echo_on {
+                    echo_byte(byte, termios, echo);
+                }
+                return Some(signal);
+            }
+        }
+
+        if !termios.is_canonical() {
+            self.raw.push_back(byte);
+            if echo_on {
+                echo_byte(byte, termios, echo);
+            }
+            return None;
+        }
+
+        if is_special(cc::VERASE) {
+            if let Some(erased) = self.line.pop() {
+                if echo_on && termios.c_lflag & ECHOE != 0 {
+                    echo_erase(erased, termios, echo);
+                }
+            }
+        } else if is_special(cc::VKILL) {
+            if echo_on && termios.c_lflag & ECHOKE != 0 {
+                for &erased in self.line.iter().rev() {
+                    echo_erase(erased, termios, echo);
+                }
+            } else if echo_on && termios.c_lflag & ECHOK != 0 {
+                echo.push(b'\n');
+            }
+            self.line.clear();
+        } else if is_special(cc::VEOF) {
+            // The line is passed on as it is, without the EOF character
+            self.lines.push_back(core::mem::take(&mut self.line));
+        } else if byte == b'\n' || is_special(cc::VEOL) || is_special(cc::VEOL2) {
+            self.line.push(byte);
+            self.lines.push_back(core::mem::take(&mut self.line));
+            if echo_on || (byte == b'\n' && termios.c_lflag & ECHONL != 0) {
+                echo_byte(byte, termios, echo);
+            }
+        } else if self.line.len() < MAX_CANON - 1 {
+            // One byte is always left for the end of the line
+            self.line.push(byte);
+            if echo_on {
+                echo_byte(byte, termios, echo);
+            }
+        }
+        None
+    }
+
+    /// Called when the terminal leaves canonical mode: the pending input,
+    /// including the line being edited, becomes readable byte by byte.
+    pub fn flush_to_raw(&mut self) {
+        for line in self.lines.drain(..) {
+            self.raw.extend(line);
+        }
+        self.raw.extend(self.line.drain(..));
+    }
+
+    /// Discard all the input, e.g. for `TCFLSH` or `TCSETSF`.
+    pub fn flush_input(&mut self) {
+        self.lines.clear();
+        self.line.clear();
+        self.raw.clear();
+    }
+
+    /// The number of bytes readable in raw mode.
+    pub fn raw_len(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Whether a read would return something right away.
+    pub fn is_readable(&self, canonical: bool) -> bool {
+        !self.raw.is_empty() || canonical && !self.lines.is_empty()
+    }
+
+    /// Read raw bytes first, then at most one line in canonical mode.
+    ///
+    /// Returns `None` if nothing is readable. `Some(0)` is an end of file.
+    pub fn read(&mut self, buf: &mut [u8], canonical: bool) -> Option<usize> {
+        if !self.raw.is_empty() {
+            let count = buf.len().min(self.raw.len());
+            for (dst, src) in buf.iter_mut().zip(self.raw.drain(..count)) {
+                *dst = src;
+            }
+            return Some(count);
+        }
+        if !canonical {
+            return None;
+        }
+        let line = self.lines.front_mut()?;
+        let count = buf.len().min(line.len());
+        buf[..count].copy_from_slice(&line[..count]);
+        if count == line.len() {
+            self.lines.pop_front();
+        } else {
+            line.drain(..count);
+        }
+        Some(count)
+    }
+}
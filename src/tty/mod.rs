@@ -0,0 +1,554 @@
+//! The terminal attached to the boot console.
+pub mod ldisc;
+pub mod termios;
+
+use self::ldisc::LineDiscipline;
+use self::termios::{baud_to_code, cc, Termios, Termios2, BOTHER, CBAUD, TOSTOP};
+use crate::console;
+use crate::fs::devfs::{self, makedev, CharDevice};
+use crate::process::signal::{is_ignored_or_blocked, send_signal_to_pgrp, wait_interruptible};
+use crate::process::{all_processes, current_process};
+use crate::ptr::UserPtr;
+use crate::signal::signal_no::SignalNo;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axsync::Mutex;
+use axtask::WaitQueue;
+use core::any::{Any, TypeId};
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use lazyinit::LazyInit;
+
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TCSETSW: usize = 0x5403;
+const TCSETSF: usize = 0x5404;
+const TCSBRK: usize = 0x5409;
+const TCXONC: usize = 0x540A;
+const TCFLSH: usize = 0x540B;
+const TIOCSCTTY: usize = 0x540E;
+const TIOCGPGRP: usize = 0x540F;
+const TIOCSPGRP: usize = 0x5410;
+const TIOCGWINSZ: usize = 0x5413;
+const TIOCSWINSZ: usize = 0x5414;
+const TCSBRKP: usize = 0x5425;
+const TIOCGSID: usize = 0x5429;
+const TCGETS2: usize = 0x802C_542A;
+const TCSETS2: usize = 0x402C_542B;
+const TCSETSW2: usize = 0x402C_542C;
+const TCSETSF2: usize = 0x402C_542D;
+
+// Arguments of TCFLSH
+const TCIFLUSH: usize = 0;
+const TCOFLUSH: usize = 1;
+const TCIOFLUSH: usize = 2;
+
+// Arguments of TCXONC
+const TCOOFF: usize = 0;
+const TCOON: usize = 1;
+const TCIOFF: usize = 2;
+const TCION: usize = 3;
+
+/// `struct winsize`, read and written by `TIOCGWINSZ`/`TIOCSWINSZ`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WinSize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+impl Default for WinSize {
+    /// The size of a serial console, which can't be queried.
+    fn default() -> Self {
+        Self {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
+
+/// The state of a terminal device.
+pub struct Tty {
+    termios: Termios,
+    /// The input speed, kept apart from `c_cflag` to support non-standard rates
+    ispeed: u32,
+    /// The output speed, kept apart from `c_cflag` to support non-standard rates
+    ospeed: u32,
+    ldisc: LineDiscipline,
+    winsize: WinSize,
+    /// The session the terminal controls, 0 if none
+    session: u64,
+    /// The foreground process group
+    pgrp: u64,
+}
+
+impl Tty {
+    fn new() -> Self {
+        let termios = Termios::default();
+        let speed = termios.baud().unwrap_or(38400);
+        Self {
+            termios,
+            ispeed: speed,
+            ospeed: speed,
+            ldisc: LineDiscipline::default(),
+            winsize: WinSize::default(),
+            session: 0,
+            pgrp: 0,
+        }
+    }
+
+    /// Make the terminal the controlling terminal of session `sid`, with the
+    /// group of the session leader in the foreground.
+    pub fn set_session(&mut self, sid: u64) {
+        self.session = sid;
+        self.pgrp = sid;
+    }
+
+    /// Move the bytes received by the console through the line discipline,
+    /// returning the signals they generate for the foreground process group.
+    fn receive(&mut self) -> Vec<SignalNo> {
+        let mut buf = [0; 64];
+        let mut echo = Vec::new();
+        let mut signals = Vec::new();
+        let mut received = false;
+        loop {
+            let count = axhal::console::read_bytes(&mut buf);
+            if count == 0 {
+                break;
+            }
+            received = true;
+            for &byte in &buf[..count] {
+                signals.extend(self.ldisc.receive(byte, &self.termios, &mut echo));
+            }
+        }
+        if !echo.is_empty() {
+            console::write(&echo);
+        }
+        if received {
+            wake_readers();
+        }
+        signals
+    }
+
+    /// The current line settings.
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    fn termios2(&self) -> Termios2 {
+        Termios2 {
+            termios: self.termios,
+            c_ispeed: self.ispeed,
+            c_ospeed: self.ospeed,
+        }
+    }
+
+    fn set_termios(&mut self, termios: Termios) {
+        if self.termios.is_canonical() && !termios.is_canonical() {
+            self.ldisc.flush_to_raw();
+        }
+        if let Some(baud) = termios.baud() {
+            self.ispeed = baud;
+            self.ospeed = baud;
+        }
+        self.termios = termios;
+        wake_readers();
+    }
+
+    fn set_termios2(&mut self, termios2: Termios2) {
+        let mut termios = termios2.termios;
+        if termios.c_cflag & CBAUD == BOTHER {
+            self.ispeed = termios2.c_ispeed;
+            self.ospeed = termios2.c_ospeed;
+        } else if let Some(baud) = termios.baud() {
+            self.ispeed = baud;
+            self.ospeed = baud;
+        }
+        termios.c_cflag = (termios.c_cflag & !CBAUD) | baud_to_code(self.ospeed);
+        if self.termios.is_canonical() && !termios.is_canonical() {
+            self.ldisc.flush_to_raw();
+        }
+        self.termios = termios;
+        wake_readers();
+    }
+}
+
+lazy_static! {
+    /// The terminal of the boot console, shared by stdin, stdout and stderr.
+    pub static ref CONSOLE_TTY: Mutex<Tty> = Mutex::new(Tty::new());
+}
+
+/// The types of the files of the console the boot task has as its stdin and
+/// stdout, which every process inherits.
+static CONSOLE_FILE_TYPES: LazyInit<[TypeId; 2]> = LazyInit::new();
+
+/// The type of the open file `fd`.
+fn file_type_id(fd: i32) -> Option<TypeId> {
+    let file = api::get_file_like(fd).ok()?.into_any();
+    Some(Any::type_id(&*file))
+}
+
+/// Whether `fd` is the console opened as stdin, stdout or stderr.
+///
+/// `/dev/console` and `/dev/tty` are devfs files, whose ioctls reach the
+/// terminal through their device.
+pub fn is_tty(fd: i32) -> bool {
+    file_type_id(fd).is_some_and(|ty| CONSOLE_FILE_TYPES.contains(&ty))
+}
+
+/// How often the console is polled for input when nobody is reading it.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Bumped whenever a read of the console may find more to return
+static INPUT_SEQ: AtomicU64 = AtomicU64::new(0);
+/// The readers of the console waiting for input
+static INPUT_WQ: WaitQueue = WaitQueue::new();
+
+/// Wake up the readers of the console, after input arrived or the line
+/// settings changed.
+fn wake_readers() {
+    INPUT_SEQ.fetch_add(1, Ordering::Release);
+    INPUT_WQ.notify_all(false);
+}
+
+/// Pass the pending console input to the line discipline, and send the
+/// signals generated by control characters to the foreground process group.
+fn poll_input() {
+    let mut tty = CONSOLE_TTY.lock();
+    let signals = tty.receive();
+    let pgrp = tty.pgrp;
+    drop(tty);
+    if pgrp == 0 {
+        return;
+    }
+    for signal in signals {
+        // The group may have exited meanwhile
+        let _ = send_signal_to_pgrp(pgrp, signal as isize);
+    }
+}
+
+const TTYAUX_MAJOR: u32 = 5;
+
+/// `/dev/console` and `/dev/tty`, other names for the console.
+///
+/// `/dev/tty` is the controlling terminal of the caller, which can only be
+/// the console: opening it fails with `ENXIO` outside the session of the
+/// console.
+struct ConsoleDevice {
+    controlling: bool,
+}
+
+impl CharDevice for ConsoleDevice {
+    fn open(&self) -> LinuxResult {
+        let session = CONSOLE_TTY.lock().session;
+        if self.controlling && current_process().unwrap().sid() != session {
+            return Err(LinuxError::ENXIO);
+        }
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        write(buf)
+    }
+
+    fn ioctl(&self, op: usize, argp: usize) -> LinuxResult<isize> {
+        tty_ioctl(op, argp as *mut c_void).unwrap_or(Err(LinuxError::ENOTTY))
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        poll_input();
+        let tty = CONSOLE_TTY.lock();
+        Ok(PollState {
+            readable: tty.ldisc.is_readable(tty.termios.is_canonical()),
+            writable: true,
+        })
+    }
+}
+
+/// Record the files of the console, spawn the task which polls it for input,
+/// so that `^C` and `^Z` are seen even while no process reads the terminal,
+/// and register `/dev/console` and `/dev/tty`.
+pub fn init() {
+    CONSOLE_FILE_TYPES.init_once([
+        file_type_id(0).expect("no stdin"),
+        file_type_id(1).expect("no stdout"),
+    ]);
+    axtask::spawn(|| loop {
+        poll_input();
+        axtask::sleep(INPUT_POLL_INTERVAL);
+    });
+    let tty = Arc::new(ConsoleDevice { controlling: true });
+    devfs::register_chrdev("tty", 0o666, makedev(TTYAUX_MAJOR, 0), tty).unwrap();
+    let console = Arc::new(ConsoleDevice { controlling: false });
+    devfs::register_chrdev("console", 0o600, makedev(TTYAUX_MAJOR, 1), console).unwrap();
+}
+
+/// Check that the calling process may use the terminal, i.e. it isn't in a
+/// background group of the session the terminal controls.
+///
+/// A background process has `signal` sent to its group and fails with
+/// `EINTR`. If it ignores or blocks the signal, a read fails with `EIO`
+/// instead, and any other operation goes ahead.
+fn job_control_check(signal: SignalNo) -> LinuxResult {
+    let (session, pgrp) = {
+        let tty = CONSOLE_TTY.lock();
+        (tty.session, tty.pgrp)
+    };
+    let proc = current_process().unwrap();
+    if session == 0 || proc.sid() != session || proc.pgid() == pgrp {
+        return Ok(());
+    }
+    if is_ignored_or_blocked(signal) {
+        return match signal {
+            SignalNo::SIGTTIN => Err(LinuxError::EIO),
+            _ => Ok(()),
+        };
+    }
+    let _ = send_signal_to_pgrp(proc.pgid(), signal as isize);
+    Err(LinuxError::EINTR)
+}
+
+/// Write to the console. With `TOSTOP` set, background processes are stopped
+/// by `SIGTTOU` instead.
+pub fn write(data: &[u8]) -> LinuxResult<usize> {
+    if CONSOLE_TTY.lock().termios.c_lflag & TOSTOP != 0 {
+        job_control_check(SignalNo::SIGTTOU)?;
+    }
+    Ok(console::write(data))
+}
+
+/// Read from the console through the line discipline.
+///
+/// In canonical mode this waits for a complete line. In raw mode it follows
+/// `VMIN` and `VTIME`: wait for `VMIN` bytes, or with `VMIN` of 0 for any
+/// byte at all, giving up after `VTIME` tenths of a second. With both set,
+/// `VTIME` bounds the gap between bytes once the first one has arrived.
+pub fn read(buf: &mut [u8]) -> LinuxResult<usize> {
+    job_control_check(SignalNo::SIGTTIN)?;
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    let mut deadline = None;
+    let mut last_len = 0;
+    loop {
+        let seq = INPUT_SEQ.load(Ordering::Acquire);
+        poll_input();
+        let mut tty = CONSOLE_TTY.lock();
+        let termios = tty.termios;
+        if termios.is_canonical() {
+            if let Some(count) = tty.ldisc.read(buf, true) {
+                return Ok(count);
+            }
+        } else {
+            let vmin = termios.c_cc[cc::VMIN] as usize;
+            let vtime = Duration::from_millis(termios.c_cc[cc::VTIME] as u64 * 100);
+            let len = tty.ldisc.raw_len();
+            let now = axhal::time::monotonic_time();
+            if vtime.is_zero() {
+                if len >= vmin.min(buf.len()).max(1) || vmin == 0 {
+                    return Ok(tty.ldisc.read(buf, false).unwrap_or(0));
+                }
+            } else {
+                if vmin == 0 {
+                    deadline.get_or_insert(now + vtime);
+                } else if len > last_len {
+                    // Every byte restarts the inter-byte timer
+                    deadline = Some(now + vtime);
+                }
+                let timed_out = deadline.is_some_and(|deadline| now >= deadline);
+                if len >= vmin.min(buf.len()).max(1) || timed_out {
+                    return Ok(tty.ldisc.read(buf, false).unwrap_or(0));
+                }
+            }
+            last_len = len;
+        }
+        drop(tty);
+        let timeout =
+            deadline.map(|deadline| deadline.saturating_sub(axhal::time::monotonic_time()));
+        wait_interruptible(&INPUT_WQ, timeout, || {
+            INPUT_SEQ.load(Ordering::Acquire) != seq
+        })?;
+    }
+}
+
+/// The session of the calling process.
+fn current_session() -> u64 {
+    current_process().unwrap().sid()
+}
+
+/// Handle the ioctls on the controlling terminal and its window size.
+fn tty_ctl_ioctl(op: usize, argp: *mut c_void) -> LinuxResult<isize> {
+    if argp.is_null() && op != TIOCSCTTY {
+        return Err(LinuxError::EFAULT);
+    }
+    let mut tty = CONSOLE_TTY.lock();
+    match op {
+        TIOCGWINSZ => UserPtr::from(argp as *mut WinSize).write(tty.winsize)?,
+        TIOCSWINSZ => tty.winsize = UserPtr::from(argp as *const WinSize).read()?,
+        TIOCGPGRP | TIOCGSID => {
+            let sid = current_session();
+            // Only the session the terminal controls may ask
+            if tty.session == 0 || tty.session != sid {
+                return Err(LinuxError::ENOTTY);
+            }
+            let id = if op == TIOCGPGRP {
+                tty.pgrp
+            } else {
+                tty.session
+            };
+            UserPtr::from(argp as *mut i32).write(id as i32)?;
+        }
+        TIOCSPGRP => {
+            let pgrp = UserPtr::from(argp as *const i32).read()?;
+            if pgrp < 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let sid = current_session();
+            if tty.session == 0 || tty.session != sid {
+                return Err(LinuxError::ENOTTY);
+            }
+            // The group must exist in the session of the terminal
+            let exists = all_processes()
+                .iter()
+                .any(|proc| proc.pgid() == pgrp as u64 && proc.sid() == sid);
+            if !exists {
+                return Err(LinuxError::EPERM);
+            }
+            tty.pgrp = pgrp as u64;
+        }
+        TIOCSCTTY => {
+            let proc = current_process().unwrap();
+            let sid = proc.sid();
+            // Only a session leader may acquire a controlling terminal, and
+            // only root may steal it from another session
+            if sid != proc.pid {
+                return Err(LinuxError::EPERM);
+            }
+            if tty.session != 0 && tty.session != sid && !proc.cred().is_privileged() {
+                return Err(LinuxError::EPERM);
+            }
+            tty.set_session(sid);
+        }
+        _ => unreachable!(),
+    }
+    Ok(0)
+}
+
+/// Whether `op` is a terminal ioctl, i.e. its type is `'T'`.
+pub fn is_tty_request(op: usize) -> bool {
+    (op >> 8) & 0xff == b'T' as usize
+}
+
+/// Handle a terminal ioctl on the console.
+///
+/// # Returns
+/// `None` if `op` isn't a terminal request.
+pub fn tty_ioctl(op: usize, argp: *mut c_void) -> Option<LinuxResult<isize>> {
+    let changes_settings = matches!(
+        op,
+        TCSETS | TCSETSW | TCSETSF | TCSETS2 | TCSETSW2 | TCSETSF2 | TIOCSPGRP
+    );
+    // Background processes may not change the settings
+    if changes_settings {
+        if let Err(e) = job_control_check(SignalNo::SIGTTOU) {
+            return Some(Err(e));
+        }
+    }
+    let res = match op {
+        TCGETS | TCGETS2 if argp.is_null() => Err(LinuxError::EFAULT),
+        TCGETS => {
+            let termios = CONSOLE_TTY.lock().termios();
+            UserPtr::from(argp as *mut Termios)
+                .write(termios)
+                .map(|_| 0)
+        }
+        TCGETS2 => {
+            let termios2 = CONSOLE_TTY.lock().termios2();
+            UserPtr::from(argp as *mut Termios2)
+                .write(termios2)
+                .map(|_| 0)
+        }
+        TCSETS | TCSETSW | TCSETSF | TCSETS2 | TCSETSW2 | TCSETSF2 if argp.is_null() => {
+            Err(LinuxError::EFAULT)
+        }
+        // TCSETSW and TCSETSF apply the settings once the output is drained
+        TCSETS | TCSETSW | TCSETSF => {
+            let termios = match UserPtr::from(argp as *const Termios).read() {
+                Ok(termios) => termios,
+                Err(e) => return Some(Err(e)),
+            };
+            if op != TCSETS {
+                console::flush();
+            }
+            let mut tty = CONSOLE_TTY.lock();
+            if op == TCSETSF {
+                tty.ldisc.flush_input();
+            }
+            tty.set_termios(termios);
+            Ok(0)
+        }
+        TCSETS2 | TCSETSW2 | TCSETSF2 => {
+            let termios2 = match UserPtr::from(argp as *const Termios2).read() {
+                Ok(termios2) => termios2,
+                Err(e) => return Some(Err(e)),
+            };
+            if op != TCSETS2 {
+                console::flush();
+            }
+            let mut tty = CONSOLE_TTY.lock();
+            if op == TCSETSF2 {
+                tty.ldisc.flush_input();
+            }
+            tty.set_termios2(termios2);
+            Ok(0)
+        }
+        // tcdrain() and tcsendbreak(): drain the output, the console has no
+        // line to send a break on.
+        TCSBRK | TCSBRKP => {
+            console::flush();
+            Ok(0)
+        }
+        TCFLSH => match argp as usize {
+            TCIFLUSH => {
+                CONSOLE_TTY.lock().ldisc.flush_input();
+                Ok(0)
+            }
+            TCOFLUSH => {
+                console::discard();
+                Ok(0)
+            }
+            TCIOFLUSH => {
+                CONSOLE_TTY.lock().ldisc.flush_input();
+                console::discard();
+                Ok(0)
+            }
+            _ => Err(LinuxError::EINVAL),
+        },
+        TIOCSCTTY | TIOCGPGRP | TIOCSPGRP | TIOCGWINSZ | TIOCSWINSZ | TIOCGSID => {
+            tty_ctl_ioctl(op, argp)
+        }
+        TCXONC => match argp as usize {
+            TCOOFF | TCOON => {
+                console::set_stopped(argp as usize == TCOOFF);
+                Ok(0)
+            }
+            TCIOFF | TCION => Ok(0),
+            _ => Err(LinuxError::EINVAL),
+        },
+        _ => return None,
+    };
+    Some(res)
+}
@@ -0,0 +1,192 @@
+//! Binary-compatible `struct termios` as used by the Linux `TCGETS` family of ioctls.
+//!
+//! See <https://github.com/torvalds/linux/blob/master/include/uapi/asm-generic/termbits.h>
+
+/// The number of control characters in the kernel `struct termios`.
+pub const NCCS: usize = 19;
+
+/// Indices into [`Termios::c_cc`].
+pub mod cc {
+    pub const VINTR: usize = 0;
+    pub const VQUIT: usize = 1;
+    pub const VERASE: usize = 2;
+    pub const VKILL: usize = 3;
+    pub const VEOF: usize = 4;
+    pub const VTIME: usize = 5;
+    pub const VMIN: usize = 6;
+    pub const VSWTC: usize = 7;
+    pub const VSTART: usize = 8;
+    pub const VSTOP: usize = 9;
+    pub const VSUSP: usize = 10;
+    pub const VEOL: usize = 11;
+    pub const VREPRINT: usize = 12;
+    pub const VDISCARD: usize = 13;
+    pub const VWERASE: usize = 14;
+    pub const VLNEXT: usize = 15;
+    pub const VEOL2: usize = 16;
+}
+
+// c_iflag bits
+pub const IGNBRK: u32 = 0o1;
+pub const BRKINT: u32 = 0o2;
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+pub const IXON: u32 = 0o2000;
+
+// c_oflag bits
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+
+// c_cflag bits
+pub const CBAUD: u32 = 0o10017;
+pub const BOTHER: u32 = 0o10000;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+pub const HUPCL: u32 = 0o2000;
+
+// c_lflag bits
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const TOSTOP: u32 = 0o400;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+pub const IEXTEN: u32 = 0o100000;
+
+/// The standard baud rates, indexed by their `Bxxx` code in `c_cflag`.
+const BAUD_TABLE: [(u32, u32); 31] = [
+    (0o0, 0),
+    (0o1, 50),
+    (0o2, 75),
+    (0o3, 110),
+    (0o4, 134),
+    (0o5, 150),
+    (0o6, 200),
+    (0o7, 300),
+    (0o10, 600),
+    (0o11, 1200),
+    (0o12, 1800),
+    (0o13, 2400),
+    (0o14, 4800),
+    (0o15, 9600),
+    (0o16, 19200),
+    (0o17, 38400),
+    (0o10001, 57600),
+    (0o10002, 115200),
+    (0o10003, 230400),
+    (0o10004, 460800),
+    (0o10005, 500000),
+    (0o10006, 576000),
+    (0o10007, 921600),
+    (0o10010, 1000000),
+    (0o10011, 1152000),
+    (0o10012, 1500000),
+    (0o10013, 2000000),
+    (0o10014, 2500000),
+    (0o10015, 3000000),
+    (0o10016, 3500000),
+    (0o10017, 4000000),
+];
+
+/// Convert a `Bxxx` code to a baud rate.
+pub fn code_to_baud(code: u32) -> Option<u32> {
+    BAUD_TABLE
+        .iter()
+        .find(|&&(c, _)| c == code)
+        .map(|&(_, baud)| baud)
+}
+
+/// Convert a baud rate to its `Bxxx` code, or `BOTHER` if it isn't a standard rate.
+pub fn baud_to_code(baud: u32) -> u32 {
+    BAUD_TABLE
+        .iter()
+        .find(|&&(_, b)| b == baud)
+        .map_or(BOTHER, |&(code, _)| code)
+}
+
+/// The kernel `struct termios`, read and written by `TCGETS`/`TCSETS*`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    /// Input modes
+    pub c_iflag: u32,
+    /// Output modes
+    pub c_oflag: u32,
+    /// Control modes, including the baud rate
+    pub c_cflag: u32,
+    /// Local modes
+    pub c_lflag: u32,
+    /// Line discipline
+    pub c_line: u8,
+    /// Special control characters
+    pub c_cc: [u8; NCCS],
+}
+
+/// The kernel `struct termios2`, which carries arbitrary baud rates.
+///
+/// Read and written by `TCGETS2`/`TCSETS2*`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios2 {
+    pub termios: Termios,
+    /// Input speed
+    pub c_ispeed: u32,
+    /// Output speed
+    pub c_ospeed: u32,
+}
+
+impl Default for Termios {
+    /// The settings of a freshly opened Linux console: canonical mode with echo.
+    fn default() -> Self {
+        let mut c_cc = [0; NCCS];
+        c_cc[cc::VINTR] = 0x03; // ^C
+        c_cc[cc::VQUIT] = 0x1c; // ^\
+        c_cc[cc::VERASE] = 0x7f; // DEL
+        c_cc[cc::VKILL] = 0x15; // ^U
+        c_cc[cc::VEOF] = 0x04; // ^D
+        c_cc[cc::VTIME] = 0;
+        c_cc[cc::VMIN] = 1;
+        c_cc[cc::VSTART] = 0x11; // ^Q
+        c_cc[cc::VSTOP] = 0x13; // ^S
+        c_cc[cc::VSUSP] = 0x1a; // ^Z
+        c_cc[cc::VREPRINT] = 0x12; // ^R
+        c_cc[cc::VDISCARD] = 0x0f; // ^O
+        c_cc[cc::VWERASE] = 0x17; // ^W
+        c_cc[cc::VLNEXT] = 0x16; // ^V
+        Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: 0o17 /* B38400 */ | CS8 | CREAD | HUPCL,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+}
+
+impl Termios {
+    /// The baud rate encoded in `c_cflag`, if it is a standard one.
+    pub fn baud(&self) -> Option<u32> {
+        code_to_baud(self.c_cflag & CBAUD)
+    }
+
+    /// Whether the terminal is in canonical (line-buffered) mode.
+    pub fn is_canonical(&self) -> bool {
+        self.c_lflag & ICANON != 0
+    }
+
+    /// Whether input characters are echoed back.
+    pub fn echo(&self) -> bool {
+        self.c_lflag & ECHO != 0
+    }
+
+    /// Whether the `VINTR`/`VQUIT`/`VSUSP` characters generate signals.
+    pub fn is_signal_enabled(&self) -> bool {
+        self.c_lflag & ISIG != 0
+    }
+}
@@ -0,0 +1,27 @@
+//! The structures and constants exchanged with user space.
+//!
+//! They are defined next to the syscalls using them, which keeps most of
+//! those modules private. This module gathers them in one place, so that
+//! components outside the kernel, like drivers or test fixtures, can build and
+//! read them without copying their layouts. Every structure is `#[repr(C)]`
+//! with the layout of the target architecture, and needs nothing beyond
+//! `core`.
+
+// Processes and threads
+pub use crate::flag::CloneFlags;
+
+// Signals
+pub use crate::signal::action::{SigAction, SigActionFlags, SIG_DFL, SIG_IGN};
+pub use crate::signal::info::SigInfo;
+pub use crate::signal::signal_no::{SignalNo, MAX_SIG_NUM};
+pub use crate::signal::SIGQUEUE_MAX;
+pub use crate::syscall_imp::{SigMaskFlag, SIGSET_SIZE_IN_BYTE};
+
+// Files
+pub use crate::syscall_imp::{DirEnt, FileType, Kstat, Statx, StatxTimestamp};
+
+// IPC
+pub use crate::ipc::mqueue::{MqAttr, MQ_PRIO_MAX};
+pub use crate::ipc::sem::{SemBuf, SemidDs};
+pub use crate::ipc::shm::ShmidDs;
+pub use crate::ipc::IpcPerm64;
@@ -0,0 +1,620 @@
+//! A subset of io_uring: rings of submissions and completions shared with
+//! user space, whose reads, writes and fsyncs a kernel worker carries out.
+//!
+//! Every ring has a worker task of its own, which takes the submissions one
+//! after the other and makes the same syscalls the process would, in its
+//! address space and with its fd table. The worker isn't one of the threads
+//! of the process: it gets no signals, and is stopped when the ring is closed
+//! or the process exits.
+//!
+//! Only the rings themselves are supported: no polled or fixed files and
+//! buffers, no linked submissions and no submission thread.
+use crate::config::KERNEL_STACK_SIZE;
+use crate::mm::Frame;
+use crate::process::current_process;
+use crate::process::pid::{alloc_tid, dealloc_tid};
+use crate::process::signal::wait_interruptible;
+use crate::syscall_imp::{
+    sys_fdatasync, sys_fsync, sys_pread64, sys_preadv, sys_pwrite64, sys_pwritev, sys_read,
+    sys_readv, sys_write, sys_writev,
+};
+use crate::task::{task_name, TaskExt};
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use arceos_posix_api::{self as api, ctypes};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::arch::{TrapFrame, UspaceContext};
+use axhal::time::monotonic_time;
+use axio::PollState;
+use axsync::Mutex;
+use axtask::{AxTaskRef, TaskExtRef, TaskInner, WaitQueue};
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::time::Duration;
+use memory_addr::PAGE_SIZE_4K;
+
+/// The `mmap` offsets of the parts of a ring.
+pub const IORING_OFF_SQ_RING: u64 = 0;
+pub const IORING_OFF_CQ_RING: u64 = 0x800_0000;
+pub const IORING_OFF_SQES: u64 = 0x1000_0000;
+
+pub const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+
+const IORING_SETUP_CQSIZE: u32 = 1 << 3;
+
+/// Both rings lie in the mapping at `IORING_OFF_SQ_RING`.
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+/// Completions which don't fit in the ring wait in the kernel.
+const IORING_FEAT_NODROP: u32 = 1 << 1;
+
+const IORING_MAX_ENTRIES: u32 = 32768;
+const IORING_MAX_CQ_ENTRIES: u32 = 2 * IORING_MAX_ENTRIES;
+
+const IORING_OP_NOP: u8 = 0;
+const IORING_OP_READV: u8 = 1;
+const IORING_OP_WRITEV: u8 = 2;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const IORING_FSYNC_DATASYNC: u32 = 1 << 0;
+
+/// The submissions run in order anyway.
+const IOSQE_IO_DRAIN: u8 = 1 << 1;
+
+/// Where the fields of the rings lie in the pages they share.
+const SQ_HEAD: usize = 0;
+const SQ_TAIL: usize = 4;
+const SQ_RING_MASK: usize = 8;
+const SQ_RING_ENTRIES: usize = 12;
+const SQ_FLAGS: usize = 16;
+const SQ_DROPPED: usize = 20;
+const CQ_HEAD: usize = 24;
+const CQ_TAIL: usize = 28;
+const CQ_RING_MASK: usize = 32;
+const CQ_RING_ENTRIES: usize = 36;
+const CQ_OVERFLOW: usize = 40;
+const CQ_FLAGS: usize = 44;
+/// The completion entries, followed by the indices of the submission ring
+const CQES: usize = 64;
+
+/// How long an exiting process waits for a worker busy with a syscall.
+const WORKER_EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+const WORKER_EXIT_POLL: Duration = Duration::from_millis(10);
+
+/// `struct io_sqring_offsets`
+#[allow(dead_code)] // The fields are only read by user space
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SqRingOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    pub resv1: u32,
+    pub user_addr: u64,
+}
+
+/// `struct io_cqring_offsets`
+#[allow(dead_code)] // The fields are only read by user space
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CqRingOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    pub resv1: u32,
+    pub user_addr: u64,
+}
+
+/// `struct io_uring_params`
+#[allow(dead_code)] // Most fields are only read by user space
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoUringParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    pub features: u32,
+    pub wq_fd: u32,
+    pub resv: [u32; 3],
+    pub sq_off: SqRingOffsets,
+    pub cq_off: CqRingOffsets,
+}
+
+/// `struct io_uring_sqe`
+#[allow(dead_code)] // The fields of the unsupported features are ignored
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    /// The flags of the operation, e.g. `fsync_flags`
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    __pad2: u64,
+}
+
+/// `struct io_uring_cqe`
+#[allow(dead_code)] // The fields are only read by user space, in the ring
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// Pages shared with user space, which it may be changing meanwhile.
+struct Region {
+    frames: Vec<Arc<Frame>>,
+}
+
+impl Region {
+    fn new(size: usize) -> LinuxResult<Self> {
+        let frames = (0..size.div_ceil(PAGE_SIZE_4K))
+            .map(|_| Frame::alloc())
+            .collect::<LinuxResult<_>>()?;
+        Ok(Self { frames })
+    }
+
+    /// The `T` at `offset`, which must not cross a page.
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        debug_assert!(offset % PAGE_SIZE_4K + core::mem::size_of::<T>() <= PAGE_SIZE_4K);
+        let page = self.frames[offset / PAGE_SIZE_4K].as_mut_slice();
+        page[offset % PAGE_SIZE_4K..].as_mut_ptr() as *mut T
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*self.ptr::<AtomicU32>(offset) }
+    }
+
+    fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(value) }
+    }
+}
+
+/// The state of a ring, shared by its fd and its worker.
+struct Ring {
+    /// The pid of the process the worker runs for
+    pid: u64,
+    sq_entries: u32,
+    cq_entries: u32,
+    /// Where the indices of the submission ring start in `rings`
+    sq_array: usize,
+    /// The heads, tails and entries of both rings
+    rings: Region,
+    /// The submission entries
+    sqes: Region,
+    /// The submissions taken off the ring, for the worker
+    queue: Mutex<VecDeque<Sqe>>,
+    /// The completions which don't fit in the ring yet
+    backlog: Mutex<VecDeque<Cqe>>,
+    /// Woken up when there is work for the worker, or when it is to stop
+    work_wq: WaitQueue,
+    /// Woken up when completions are posted
+    cq_wq: WaitQueue,
+    stopped: AtomicBool,
+    /// The worker, until it has stopped
+    worker: Mutex<Option<AxTaskRef>>,
+}
+
+/// The rings which are open, to stop their workers when their process exits.
+static RINGS: Mutex<Vec<Weak<Ring>>> = Mutex::new(Vec::new());
+
+impl Ring {
+    fn new(pid: u64, sq_entries: u32, cq_entries: u32) -> LinuxResult<Self> {
+        let sq_array = CQES + cq_entries as usize * core::mem::size_of::<Cqe>();
+        let ring = Self {
+            pid,
+            sq_entries,
+            cq_entries,
+            sq_array,
+            rings: Region::new(sq_array + sq_entries as usize * 4)?,
+            sqes: Region::new(sq_entries as usize * core::mem::size_of::<Sqe>())?,
+            queue: Mutex::new(VecDeque::new()),
+            backlog: Mutex::new(VecDeque::new()),
+            work_wq: WaitQueue::new(),
+            cq_wq: WaitQueue::new(),
+            stopped: AtomicBool::new(false),
+            worker: Mutex::new(None),
+        };
+        ring.rings.write(SQ_RING_MASK, sq_entries - 1);
+        ring.rings.write(SQ_RING_ENTRIES, sq_entries);
+        ring.rings.write(CQ_RING_MASK, cq_entries - 1);
+        ring.rings.write(CQ_RING_ENTRIES, cq_entries);
+        Ok(ring)
+    }
+
+    /// Queue up to `count` submissions for the worker, and return how many
+    /// were taken off the ring.
+    fn submit(&self, count: u32) -> u32 {
+        let head = self.rings.word(SQ_HEAD).load(Ordering::Relaxed);
+        let tail = self.rings.word(SQ_TAIL).load(Ordering::Acquire);
+        let count = count.min(tail.wrapping_sub(head));
+        let mut queue = self.queue.lock();
+        for i in 0..count {
+            let slot = head.wrapping_add(i) & (self.sq_entries - 1);
+            let index = self.rings.read::<u32>(self.sq_array + slot as usize * 4);
+            if index >= self.sq_entries {
+                self.rings.word(SQ_DROPPED).fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            queue.push_back(self.sqes.read(index as usize * core::mem::size_of::<Sqe>()));
+        }
+        self.rings
+            .word(SQ_HEAD)
+            .store(head.wrapping_add(count), Ordering::Release);
+        drop(queue);
+        self.work_wq.notify_one(false);
+        count
+    }
+
+    /// The number of completions in the ring.
+    fn ready(&self) -> u32 {
+        let head = self.rings.word(CQ_HEAD).load(Ordering::Acquire);
+        let tail = self.rings.word(CQ_TAIL).load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Move the completions waiting in the kernel into the ring, as far as
+    /// there is room for them.
+    fn flush(&self, backlog: &mut VecDeque<Cqe>) {
+        let head = self.rings.word(CQ_HEAD).load(Ordering::Acquire);
+        let mut tail = self.rings.word(CQ_TAIL).load(Ordering::Relaxed);
+        while tail.wrapping_sub(head) < self.cq_entries {
+            let Some(cqe) = backlog.pop_front() else {
+                break;
+            };
+            let slot = tail & (self.cq_entries - 1);
+            self.rings
+                .write(CQES + slot as usize * core::mem::size_of::<Cqe>(), cqe);
+            tail = tail.wrapping_add(1);
+        }
+        self.rings.word(CQ_TAIL).store(tail, Ordering::Release);
+    }
+
+    fn complete(&self, cqe: Cqe) {
+        let mut backlog = self.backlog.lock();
+        backlog.push_back(cqe);
+        self.flush(&mut backlog);
+        drop(backlog);
+        self.cq_wq.notify_all(false);
+    }
+
+    /// Wait until there are `count` completions in the ring, or a signal.
+    fn wait(&self, count: u32) -> LinuxResult {
+        let count = count.min(self.cq_entries);
+        self.flush(&mut self.backlog.lock());
+        wait_interruptible(&self.cq_wq, None, || {
+            self.ready() >= count || self.stopped.load(Ordering::Acquire)
+        })?;
+        Ok(())
+    }
+
+    /// The next submission for the worker, or `None` once it is to stop.
+    fn next_work(&self) -> Option<Sqe> {
+        loop {
+            self.work_wq.wait_until(|| {
+                self.stopped.load(Ordering::Acquire) || !self.queue.lock().is_empty()
+            });
+            if self.stopped.load(Ordering::Acquire) {
+                return None;
+            }
+            if let Some(sqe) = self.queue.lock().pop_front() {
+                return Some(sqe);
+            }
+        }
+    }
+
+    /// Tell the worker to stop, interrupting the syscall it may be blocked in
+    /// if the process is exiting.
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.work_wq.notify_all(false);
+        self.cq_wq.notify_all(false);
+        if let Some(worker) = self.worker.lock().as_ref() {
+            worker.task_ext().set_signal_pending();
+            worker.task_ext().interrupt();
+        }
+    }
+}
+
+/// Run `current` at the file offset of the fd if `off` is -1, and
+/// `positioned` at `off` otherwise. Like on Linux, an offset of 0 is the
+/// current one for the files which can't seek.
+fn at_offset(
+    off: u64,
+    current: impl FnOnce() -> isize,
+    positioned: impl FnOnce(i64) -> isize,
+) -> isize {
+    if off == u64::MAX {
+        return current();
+    }
+    let ret = positioned(off as i64);
+    if ret == -(LinuxError::ESPIPE.code() as isize) && off == 0 {
+        return current();
+    }
+    ret
+}
+
+/// Carry out a submission, and return the result of its completion.
+fn execute(sqe: &Sqe) -> i32 {
+    let einval = -(LinuxError::EINVAL.code() as isize);
+    if sqe.flags & !IOSQE_IO_DRAIN != 0 {
+        return einval as i32;
+    }
+    let (fd, len) = (sqe.fd, sqe.len as usize);
+    let rw = matches!(
+        sqe.opcode,
+        IORING_OP_READ | IORING_OP_WRITE | IORING_OP_READV | IORING_OP_WRITEV
+    );
+    if rw && sqe.op_flags != 0 {
+        return -(LinuxError::EOPNOTSUPP.code() as i32);
+    }
+    let ret = match sqe.opcode {
+        IORING_OP_NOP => 0,
+        IORING_OP_READ => {
+            let buf = sqe.addr as *mut c_void;
+            at_offset(
+                sqe.off,
+                || sys_read(fd, buf, len),
+                |off| sys_pread64(fd, buf, len, off),
+            )
+        }
+        IORING_OP_WRITE => {
+            let buf = sqe.addr as *const c_void;
+            at_offset(
+                sqe.off,
+                || sys_write(fd, buf, len),
+                |off| sys_pwrite64(fd, buf, len, off),
+            )
+        }
+        IORING_OP_READV => {
+            let iov = sqe.addr as *const ctypes::iovec;
+            at_offset(
+                sqe.off,
+                || sys_readv(fd, iov, len as i32),
+                |off| sys_preadv(fd, iov, len as i32, off),
+            )
+        }
+        IORING_OP_WRITEV => {
+            let iov = sqe.addr as *const ctypes::iovec;
+            at_offset(
+                sqe.off,
+                || sys_writev(fd, iov, len as i32),
+                |off| sys_pwritev(fd, iov, len as i32, off),
+            )
+        }
+        IORING_OP_FSYNC if sqe.op_flags & !IORING_FSYNC_DATASYNC != 0 => einval,
+        IORING_OP_FSYNC if sqe.op_flags & IORING_FSYNC_DATASYNC != 0 => sys_fdatasync(fd) as _,
+        IORING_OP_FSYNC => sys_fsync(fd) as _,
+        _ => einval,
+    };
+    ret as i32
+}
+
+/// Start the worker of `ring`, in the address space and with the fd table of
+/// the current process.
+fn spawn_worker(ring: &Arc<Ring>) -> LinuxResult {
+    const COMM: &str = "iou-wrk";
+    let proc = current_process().ok_or(LinuxError::ESRCH)?;
+    let tid = alloc_tid(&proc.pid_ns.lock()).ok_or(LinuxError::EAGAIN)?;
+    let aspace = proc.aspace.clone();
+    let page_table_root = aspace.lock().page_table_root();
+    let worker_ring = ring.clone();
+    let mut task = TaskInner::new(
+        move || {
+            // The address space stays alive as long as the worker runs in it
+            let _aspace = aspace;
+            while let Some(sqe) = worker_ring.next_work() {
+                let res = execute(&sqe);
+                worker_ring.complete(Cqe {
+                    user_data: sqe.user_data,
+                    res,
+                    flags: 0,
+                });
+            }
+            worker_ring.worker.lock().take();
+            dealloc_tid(tid);
+        },
+        task_name(COMM, tid),
+        KERNEL_STACK_SIZE,
+    );
+    task.ctx_mut().set_page_table_root(page_table_root);
+    let task_ext = TaskExt::new(tid, COMM, UspaceContext::from(&TrapFrame::default()), &proc);
+    task_ext.init_fs_shared();
+    task_ext.init_ns();
+    task.init_task_ext(task_ext);
+    *ring.worker.lock() = Some(axtask::spawn_task(task));
+    Ok(())
+}
+
+/// An open io_uring.
+pub struct IoUring {
+    ring: Arc<Ring>,
+}
+
+impl IoUring {
+    /// Set up a ring with room for `entries` submissions, as `params` asks,
+    /// and fill in `params` with where its parts lie.
+    pub fn new(entries: u32, params: &mut IoUringParams) -> LinuxResult<Arc<Self>> {
+        if !(1..=IORING_MAX_ENTRIES).contains(&entries)
+            || params.flags & !IORING_SETUP_CQSIZE != 0
+            || params.resv != [0; 3]
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        let sq_entries = entries.next_power_of_two();
+        let cq_entries = if params.flags & IORING_SETUP_CQSIZE != 0 {
+            if !(1..=IORING_MAX_CQ_ENTRIES).contains(&params.cq_entries) {
+                return Err(LinuxError::EINVAL);
+            }
+            let cq_entries = params.cq_entries.next_power_of_two();
+            if cq_entries < sq_entries {
+                return Err(LinuxError::EINVAL);
+            }
+            cq_entries
+        } else {
+            2 * sq_entries
+        };
+
+        let pid = current_process().ok_or(LinuxError::ESRCH)?.pid;
+        let ring = Arc::new(Ring::new(pid, sq_entries, cq_entries)?);
+        spawn_worker(&ring)?;
+        let mut rings = RINGS.lock();
+        rings.retain(|ring| ring.strong_count() > 0);
+        rings.push(Arc::downgrade(&ring));
+        drop(rings);
+
+        params.sq_entries = sq_entries;
+        params.cq_entries = cq_entries;
+        params.features = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_NODROP;
+        params.sq_off = SqRingOffsets {
+            head: SQ_HEAD as u32,
+            tail: SQ_TAIL as u32,
+            ring_mask: SQ_RING_MASK as u32,
+            ring_entries: SQ_RING_ENTRIES as u32,
+            flags: SQ_FLAGS as u32,
+            dropped: SQ_DROPPED as u32,
+            array: ring.sq_array as u32,
+            ..Default::default()
+        };
+        params.cq_off = CqRingOffsets {
+            head: CQ_HEAD as u32,
+            tail: CQ_TAIL as u32,
+            ring_mask: CQ_RING_MASK as u32,
+            ring_entries: CQ_RING_ENTRIES as u32,
+            overflow: CQ_OVERFLOW as u32,
+            cqes: CQES as u32,
+            flags: CQ_FLAGS as u32,
+            ..Default::default()
+        };
+        Ok(Arc::new(Self { ring }))
+    }
+
+    /// Submit up to `to_submit` entries, then wait for `min_complete`
+    /// completions with `IORING_ENTER_GETEVENTS`, and return how many entries
+    /// were submitted.
+    pub fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> LinuxResult<u32> {
+        if flags & !IORING_ENTER_GETEVENTS != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let submitted = self.ring.submit(to_submit);
+        if flags & IORING_ENTER_GETEVENTS != 0 {
+            // What was submitted is reported rather than an interruption
+            if let Err(e) = self.ring.wait(min_complete) {
+                if submitted == 0 {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(submitted)
+    }
+
+    /// The pages to map at the `mmap` offset `offset` of the ring.
+    pub fn frames(&self, offset: u64, len: usize) -> LinuxResult<Vec<Arc<Frame>>> {
+        let region = match offset {
+            IORING_OFF_SQ_RING | IORING_OFF_CQ_RING => &self.ring.rings,
+            IORING_OFF_SQES => &self.ring.sqes,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        region
+            .frames
+            .get(..len.div_ceil(PAGE_SIZE_4K))
+            .map(<[_]>::to_vec)
+            .ok_or(LinuxError::EINVAL)
+    }
+}
+
+impl Drop for IoUring {
+    fn drop(&mut self) {
+        self.ring.stop();
+    }
+}
+
+impl api::FileLike for IoUring {
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(ctypes::stat {
+            st_mode: 0o600,
+            st_nlink: 1,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    /// Readable while there are completions in the ring.
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: self.ring.ready() > 0,
+            writable: true,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// The io_uring opened as `fd`, if it is one.
+pub fn ring_from_fd(fd: i32) -> Option<Arc<IoUring>> {
+    api::get_file_like(fd)
+        .ok()?
+        .into_any()
+        .downcast::<IoUring>()
+        .ok()
+}
+
+/// Stop the workers of the rings of the process `pid`, which is exiting, and
+/// give them some time to leave its address space.
+pub fn exit(pid: u64) {
+    let rings: Vec<Arc<Ring>> = RINGS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|ring| ring.pid == pid)
+        .collect();
+    for ring in &rings {
+        ring.stop();
+    }
+    let deadline = monotonic_time() + WORKER_EXIT_TIMEOUT;
+    for ring in &rings {
+        while ring.worker.lock().is_some() && monotonic_time() < deadline {
+            axtask::sleep(WORKER_EXIT_POLL);
+        }
+    }
+}